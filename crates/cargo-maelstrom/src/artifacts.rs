@@ -1,6 +1,7 @@
 use crate::MainAppDeps;
 use anyhow::{bail, Result};
 use byteorder::{BigEndian, ReadBytesExt as _, WriteBytesExt as _};
+use globset::GlobSet;
 use maelstrom_base::Sha256Digest;
 use maelstrom_client::spec::{Layer, PrefixOptions};
 use maelstrom_util::elf::read_shared_libraries;
@@ -72,7 +73,13 @@ fn create_artifact_for_binary(binary_path: &Path, log: slog::Logger) -> Result<L
     })
 }
 
-fn create_artifact_for_binary_deps(binary_path: &Path, log: slog::Logger) -> Result<Layer> {
+/// Create a layer containing the shared libraries that the binary depends on, leaving out any that
+/// match `exclusions`.
+fn create_artifact_for_binary_deps(
+    binary_path: &Path,
+    exclusions: &GlobSet,
+    log: slog::Logger,
+) -> Result<Layer> {
     let fs = Fs::new();
 
     let paths = if let Some(paths) = check_for_cached_so_listing(&fs, binary_path)? {
@@ -88,6 +95,12 @@ fn create_artifact_for_binary_deps(binary_path: &Path, log: slog::Logger) -> Res
         paths
     };
 
+    let (excluded, paths): (Vec<_>, Vec<_>) =
+        paths.into_iter().partition(|p| exclusions.is_match(p));
+    if !excluded.is_empty() {
+        slog::debug!(log, "excluding shared libraries"; "excluded" => ?excluded);
+    }
+
     slog::debug!(log, "adding layer for binary deps"; "binary" => ?binary_path);
     Ok(Layer::Paths {
         paths: paths
@@ -110,13 +123,57 @@ pub struct GeneratedArtifacts {
 pub fn add_generated_artifacts(
     deps: &impl MainAppDeps,
     binary_path: &Path,
+    shared_library_exclusions: &GlobSet,
     log: slog::Logger,
 ) -> Result<GeneratedArtifacts> {
     let (binary_artifact, _) =
         deps.add_layer(create_artifact_for_binary(binary_path, log.clone())?)?;
-    let (deps_artifact, _) = deps.add_layer(create_artifact_for_binary_deps(binary_path, log)?)?;
+    let (deps_artifact, _) = deps.add_layer(create_artifact_for_binary_deps(
+        binary_path,
+        shared_library_exclusions,
+        log,
+    )?)?;
     Ok(GeneratedArtifacts {
         binary: binary_artifact,
         deps: deps_artifact,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globset::{Glob, GlobSetBuilder};
+
+    fn deps_layer_paths(exclusions: &GlobSet) -> Vec<String> {
+        // The listing of shared libraries is cached next to the binary, so use a copy.
+        let temp_dir = tempfile::tempdir().unwrap();
+        let binary = temp_dir.path().join("true");
+        Fs::new().copy("/bin/true", &binary).unwrap();
+        let Layer::Paths { paths, .. } = create_artifact_for_binary_deps(
+            &binary,
+            exclusions,
+            maelstrom_util::log::test_logger(),
+        )
+        .unwrap() else {
+            panic!("expected a paths layer");
+        };
+        paths.into_iter().map(String::from).collect()
+    }
+
+    fn is_libc(path: &str) -> bool {
+        path.rsplit('/').next().unwrap().starts_with("libc.so")
+    }
+
+    #[test]
+    fn excluded_shared_libraries_left_out_of_deps_layer() {
+        assert!(deps_layer_paths(&GlobSet::empty())
+            .iter()
+            .any(|p| is_libc(p)));
+
+        let mut exclusions = GlobSetBuilder::new();
+        exclusions.add(Glob::new("*/libc.so*").unwrap());
+        let paths = deps_layer_paths(&exclusions.build().unwrap());
+        assert!(!paths.is_empty());
+        assert!(!paths.iter().any(|p| is_libc(p)), "{paths:?}");
+    }
+}
@@ -5,8 +5,10 @@ use cargo_metadata::{
 use maelstrom_macro::Config;
 use maelstrom_util::process::ExitCode;
 use regex::Regex;
+use serde::Deserialize;
 use std::os::unix::process::ExitStatusExt as _;
 use std::{
+    collections::HashMap,
     ffi::OsString,
     fmt,
     io::{BufReader, Read as _},
@@ -142,6 +144,63 @@ pub fn get_cases_from_binary(binary: &Path, filter: &Option<String>) -> Result<V
         .collect())
 }
 
+/// Where a test case is defined in the source.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TestCaseLocation {
+    pub path: String,
+    pub line: u32,
+}
+
+impl fmt::Display for TestCaseLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.path, self.line)
+    }
+}
+
+#[derive(Deserialize)]
+struct ListedTestCase {
+    #[serde(rename = "type")]
+    type_: String,
+    name: Option<String>,
+    source_path: Option<String>,
+    start_line: Option<u32>,
+}
+
+/// Parse the output of `--list --format json`. Lines that aren't tests, or that don't have a
+/// location, are skipped.
+fn parse_case_locations(output: &str) -> HashMap<String, TestCaseLocation> {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<ListedTestCase>(line).ok())
+        .filter(|listed| listed.type_ == "test")
+        .filter_map(|listed| {
+            Some((
+                listed.name?,
+                TestCaseLocation {
+                    path: listed.source_path?,
+                    line: listed.start_line?,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Ask the test binary where each of its test cases is defined. The JSON list format is unstable
+/// in libtest, so we have to opt into it. If the binary doesn't support it, or doesn't include
+/// locations, we return what we could find, which may be nothing.
+pub fn get_case_locations_from_binary(binary: &Path) -> Result<HashMap<String, TestCaseLocation>> {
+    let output = Command::new(binary)
+        .args(["--list", "--format", "json", "-Zunstable-options"])
+        .env("RUSTC_BOOTSTRAP", "1")
+        .output()?;
+    if !output.status.success() {
+        return Ok(HashMap::new());
+    }
+    Ok(parse_case_locations(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
 #[derive(Config, Debug, Default)]
 pub struct FeatureSelectionOptions {
     /// Comma-separated list of features to activate.
@@ -255,6 +314,59 @@ impl ManifestOptions {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_case_locations_with_locations() {
+        let output = r#"{ "type": "suite", "event": "discovery" }
+{ "type": "test", "event": "discovered", "name": "tests::a", "ignore": false, "ignore_message": "", "source_path": "src/lib.rs", "start_line": 10, "start_col": 8, "end_line": 10, "end_col": 9 }
+{ "type": "test", "event": "discovered", "name": "tests::b", "ignore": true, "ignore_message": "", "source_path": "src/b.rs", "start_line": 3, "start_col": 8, "end_line": 3, "end_col": 9 }
+{ "type": "suite", "event": "completed", "tests": 2, "benchmarks": 0, "total": 2, "ignored": 1 }
+"#;
+        assert_eq!(
+            parse_case_locations(output),
+            HashMap::from([
+                (
+                    "tests::a".into(),
+                    TestCaseLocation {
+                        path: "src/lib.rs".into(),
+                        line: 10
+                    }
+                ),
+                (
+                    "tests::b".into(),
+                    TestCaseLocation {
+                        path: "src/b.rs".into(),
+                        line: 3
+                    }
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn get_case_locations_from_test_binary() {
+        // This test is itself run by a libtest binary, which can report its own locations.
+        let locations = get_case_locations_from_binary(Path::new("/proc/self/exe")).unwrap();
+        let location = &locations["cargo::tests::get_case_locations_from_test_binary"];
+        assert!(location.path.ends_with("cargo.rs"), "{location}");
+        assert_ne!(location.line, 0);
+    }
+
+    #[test]
+    fn get_case_locations_from_other_binary() {
+        assert_eq!(
+            get_case_locations_from_binary(Path::new("/bin/false")).unwrap(),
+            HashMap::new()
+        );
+    }
+
+    #[test]
+    fn parse_case_locations_without_locations() {
+        let output = r#"{ "type": "test", "event": "discovered", "name": "tests::a" }
+tests::b: test
+"#;
+        assert_eq!(parse_case_locations(output), HashMap::new());
+    }
+
     #[test]
     fn feature_selection_options_iter_default() {
         assert_eq!(
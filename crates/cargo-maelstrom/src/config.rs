@@ -1,11 +1,20 @@
 use crate::cargo::{CompilationOptions, FeatureSelectionOptions, ManifestOptions};
+use crate::test_listing::TestListingFormat;
+use bytesize::ByteSize;
 use derive_more::From;
 use maelstrom_macro::Config;
-use maelstrom_util::config::common::{BrokerAddr, CacheSize, InlineLimit, LogLevel, Slots};
+use maelstrom_util::config::common::{
+    BrokerAddr, CacheRoot, CacheSize, DispatcherThreads, HashingThreads, InlineLimit, LogLevel,
+    Slots, StringError,
+};
 use serde::Deserialize;
 use std::{
+    collections::BTreeMap,
     fmt::{self, Debug, Formatter},
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
     result,
+    str::FromStr,
 };
 
 #[derive(Clone, Deserialize, From)]
@@ -24,6 +33,233 @@ impl Debug for Quiet {
     }
 }
 
+/// Captured output larger than this is written to a file instead of being printed.
+#[derive(Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct LargeOutputThreshold(#[serde(with = "bytesize_serde")] ByteSize);
+
+impl LargeOutputThreshold {
+    pub fn as_bytes(self) -> u64 {
+        self.0 .0
+    }
+
+    pub fn from_bytes(bytes: u64) -> Self {
+        Self(ByteSize(bytes))
+    }
+}
+
+impl Debug for LargeOutputThreshold {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for LargeOutputThreshold {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        Ok(Self(
+            <ByteSize as FromStr>::from_str(s).map_err(StringError)?,
+        ))
+    }
+}
+
+/// How a test that exited with a particular code is categorized.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExitCodeSeverity {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl FromStr for ExitCodeSeverity {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "pass" => Ok(Self::Pass),
+            "fail" => Ok(Self::Fail),
+            "skip" => Ok(Self::Skip),
+            _ => Err(StringError(format!(
+                "unknown severity {s:?}, expected \"pass\", \"fail\", or \"skip\""
+            ))),
+        }
+    }
+}
+
+/// Overrides for how tests that exit with particular codes are categorized, written like
+/// "77=skip,3=pass". Codes that aren't listed pass if they are zero and fail otherwise.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub struct ExitCodeSeverities(BTreeMap<u8, ExitCodeSeverity>);
+
+impl ExitCodeSeverities {
+    pub fn get(&self, code: u8) -> ExitCodeSeverity {
+        match self.0.get(&code) {
+            Some(severity) => *severity,
+            None if code == 0 => ExitCodeSeverity::Pass,
+            None => ExitCodeSeverity::Fail,
+        }
+    }
+}
+
+impl FromStr for ExitCodeSeverities {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let mut severities = BTreeMap::new();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let Some((code, severity)) = entry.split_once('=') else {
+                return Err(StringError(format!(
+                    "expected CODE=SEVERITY, got {entry:?}"
+                )));
+            };
+            let code = code
+                .trim()
+                .parse()
+                .map_err(|err| StringError(format!("invalid exit code {code:?}: {err}")))?;
+            severities.insert(code, severity.trim().parse()?);
+        }
+        Ok(Self(severities))
+    }
+}
+
+impl TryFrom<String> for ExitCodeSeverities {
+    type Error = StringError;
+    fn try_from(s: String) -> result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// How captured output that isn't valid UTF-8 is printed.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub enum OutputEncoding {
+    /// Invalid sequences are replaced with U+FFFD.
+    #[default]
+    Lossy,
+    /// Output that isn't valid UTF-8 is printed as a hex dump.
+    Hex,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl FromStr for OutputEncoding {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "lossy" => Ok(Self::Lossy),
+            "hex" => Ok(Self::Hex),
+            "utf-16le" => Ok(Self::Utf16Le),
+            "utf-16be" => Ok(Self::Utf16Be),
+            _ => Err(StringError(format!(
+                "unknown output encoding {s:?}, expected \"lossy\", \"hex\", \"utf-16le\", \
+                or \"utf-16be\""
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for OutputEncoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Lossy => "lossy",
+            Self::Hex => "hex",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+        })
+    }
+}
+
+impl TryFrom<String> for OutputEncoding {
+    type Error = StringError;
+    fn try_from(s: String) -> result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// The order the tests in each test binary are enqueued in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub enum TestOrder {
+    /// The order the test binary lists them in.
+    #[default]
+    Listing,
+    /// The tests that took longest the last time they were run go first. Tests that haven't been
+    /// run before go before all of them.
+    SlowestFirst,
+}
+
+impl FromStr for TestOrder {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "listing" => Ok(Self::Listing),
+            "slowest-first" => Ok(Self::SlowestFirst),
+            _ => Err(StringError(format!(
+                "unknown test order {s:?}, expected \"listing\" or \"slowest-first\""
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for TestOrder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Listing => "listing",
+            Self::SlowestFirst => "slowest-first",
+        })
+    }
+}
+
+impl TryFrom<String> for TestOrder {
+    type Error = StringError;
+    fn try_from(s: String) -> result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// The seed used to shuffle the order tests are enqueued in. This is either a number or "random",
+/// in which case a seed is picked when the run starts.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub enum ShuffleSeed {
+    Random,
+    Seed(u64),
+}
+
+impl ShuffleSeed {
+    /// The seed to use, picking one if it is [`Self::Random`].
+    pub fn resolve(self) -> u64 {
+        match self {
+            Self::Random => rand::random(),
+            Self::Seed(seed) => seed,
+        }
+    }
+}
+
+impl FromStr for ShuffleSeed {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        if s == "random" {
+            return Ok(Self::Random);
+        }
+        s.parse().map(Self::Seed).map_err(|err| {
+            StringError(format!(
+                "invalid shuffle seed {s:?}, expected a number or \"random\": {err}"
+            ))
+        })
+    }
+}
+
+impl TryFrom<String> for ShuffleSeed {
+    type Error = StringError;
+    fn try_from(s: String) -> result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 #[derive(Config, Debug)]
 pub struct Config {
     /// Socket address of broker. If not provided, all tests will be run locally.
@@ -39,10 +275,94 @@ pub struct Config {
     #[config(short = 'l', value_name = "LEVEL", default = r#""info""#)]
     pub log_level: LogLevel,
 
+    /// The directory to use for the cache. This is where the local worker keeps its artifacts,
+    /// where layers are built before they are uploaded, and where the test listing is kept.
+    #[config(
+        option,
+        value_name = "PATH",
+        default = r#""maelstrom/ in the target directory""#
+    )]
+    pub cache_dir: Option<CacheRoot>,
+
     /// Don't output information about the tests being run.
     #[config(flag, short = 'q')]
     pub quiet: Quiet,
 
+    /// Print where each test is defined after its result. This relies on the test binary reporting
+    /// test locations, which currently needs an unstable libtest feature. When a location isn't
+    /// available, it is left out.
+    #[config(flag)]
+    pub include_test_locations: bool,
+
+    /// Print each test's result in the "test <name> ... ok <time>" format that libtest uses with
+    /// its --report-time option, instead of the usual format.
+    #[config(flag)]
+    pub report_time: bool,
+
+    /// For each test that fails, also print a GitHub Actions "::error" workflow command, so that
+    /// the failure is shown as an annotation. The annotation points at where the test is defined
+    /// when that is known.
+    #[config(flag)]
+    pub github_annotations: bool,
+
+    /// In the summary printed at the end, list the tests that didn't pass under a heading for
+    /// their package.
+    #[config(flag)]
+    pub summary_by_package: bool,
+
+    /// Write counts of the tests' results, how long the run took, and the exit code as a JSON
+    /// object to this file at the end of the run. If the path is "-", it is written to stdout.
+    #[config(option, value_name = "PATH", default = r#""not written""#)]
+    pub json_summary: Option<PathBuf>,
+
+    /// Write the results of the tests to this file as a JUnit XML report at the end of the run.
+    #[config(option, value_name = "PATH", default = r#""not written""#)]
+    pub junit: Option<PathBuf>,
+
+    /// A directory to write each test's captured stdout and stderr to, in files named after the
+    /// test case under a subdirectory for its package and test binary. Files are only written for
+    /// tests that produced output.
+    #[config(option, value_name = "PATH", default = r#""output isn't saved""#)]
+    pub test_output_dir: Option<PathBuf>,
+
+    /// Captured stdout or stderr larger than this is written to a temporary file, and the file's
+    /// path is printed instead of the output. SI and binary suffixes are supported.
+    #[config(
+        option,
+        value_name = "BYTES",
+        default = r#""output is always printed""#
+    )]
+    pub large_output_threshold: Option<LargeOutputThreshold>,
+
+    /// Only print the captured output of this many failed tests. Any further failed tests are
+    /// still reported and counted, just without their output.
+    #[config(option, value_name = "COUNT", default = r#""no limit""#)]
+    pub failure_output_limit: Option<u32>,
+
+    /// Report a test as lost, and cancel it, if it hasn't finished this many seconds after it
+    /// started running, instead of waiting for it forever.
+    #[config(option, value_name = "SECONDS", default = r#""wait forever""#)]
+    pub lost_test_deadline: Option<u32>,
+
+    /// How to categorize tests that exit with particular codes, as a comma-separated list of
+    /// CODE=SEVERITY entries, where SEVERITY is "pass", "fail", or "skip". For example, "77=skip"
+    /// reports tests that exit with 77 as ignored, like autotools does.
+    #[config(
+        option,
+        value_name = "CODE=SEVERITY,...",
+        default = r#""0 passes, anything else fails""#
+    )]
+    pub exit_code_severities: Option<ExitCodeSeverities>,
+
+    /// How to print captured output that isn't valid UTF-8. One of "lossy", "hex", "utf-16le", or
+    /// "utf-16be".
+    #[config(value_name = "ENCODING", default = "OutputEncoding::default()")]
+    pub output_encoding: OutputEncoding,
+
+    /// The encoding to use for the test listing cached between runs. Either "toml" or "cbor".
+    #[config(value_name = "FORMAT", default = "TestListingFormat::default()")]
+    pub test_listing_format: TestListingFormat,
+
     /// Override timeout value for all tests specified (O indicates no timeout).
     #[config(
         option,
@@ -53,6 +373,16 @@ pub struct Config {
     )]
     pub timeout: Option<u32>,
 
+    /// Timeout value for tests which don't specify one in their metadata (0 indicates no
+    /// timeout). Unlike `timeout`, this doesn't replace timeouts given in the test metadata.
+    #[config(option, value_name = "SECONDS", default = r#""no timeout""#)]
+    pub test_timeout_default: Option<u32>,
+
+    /// How many more times to run a test that fails, unless its `retry` directive field allows
+    /// retries itself. Tests that pass on a later attempt are listed in the summary.
+    #[config(value_name = "N", default = "0")]
+    pub retries: u32,
+
     /// The target amount of disk space to use for the cache. This bound won't be followed
     /// strictly, so it's best to be conservative. SI and binary suffixes are supported.
     #[config(
@@ -71,6 +401,57 @@ pub struct Config {
     #[config(short = 'S', value_name = "N", default = "Slots::default()")]
     pub slots: Slots,
 
+    /// The maximum number of artifacts to hash at once.
+    #[config(
+        value_name = "N",
+        default = "HashingThreads::default()",
+        next_help_heading = "Client Options"
+    )]
+    pub hashing_threads: HashingThreads,
+
+    /// The number of worker threads the client uses to send requests to the client process.
+    #[config(value_name = "N", default = "DispatcherThreads::default()")]
+    pub dispatcher_threads: DispatcherThreads,
+
+    /// Put the layers of each test's job in a canonical order before submitting it, moving layers
+    /// around only when they don't share any paths. Tests whose layers differ only in order then
+    /// share cached file systems on the workers.
+    #[config(flag)]
+    pub canonicalize_layer_order: bool,
+
+    /// How many more times to try submitting a job to the client after the client returns an I/O
+    /// or transport error, waiting twice as long before each attempt, starting at 100 milliseconds
+    /// and going up to 5 seconds.
+    #[config(value_name = "N", default = "2")]
+    pub job_submission_retries: u32,
+
+    /// After enqueuing this many tests in a row from one test binary, hand control back to the
+    /// main loop before enqueuing more. By default, all of a binary's tests are enqueued at once.
+    #[config(option, value_name = "N", default = r#""no batching""#)]
+    pub enqueue_batch_size: Option<NonZeroU32>,
+
+    /// Before enqueuing any tests, build every test binary and add the layers of every test that
+    /// will be run, so that the tests aren't held up by layers being uploaded once they start
+    /// being enqueued.
+    #[config(flag)]
+    pub affinity_cache_warm: bool,
+
+    /// Enqueue each test binary's tests in an order shuffled with this seed, which is either a
+    /// number or "random". The seed is printed at the end of the run so the order can be repeated.
+    #[config(option, value_name = "SEED", default = r#""don't shuffle""#)]
+    pub shuffle: Option<ShuffleSeed>,
+
+    /// How many test binaries to list the tests of at once. When this is more than one, each test
+    /// binary is listed as soon as it's built and a listing thread is free.
+    #[config(value_name = "N", default = "NonZeroUsize::MIN")]
+    pub list_threads: NonZeroUsize,
+
+    /// The order to enqueue each test binary's tests in. Either "listing", the order the test
+    /// binary lists them in, or "slowest-first", which uses how long each test took the last time
+    /// it was run. When combined with `shuffle`, tests that took equally long are shuffled.
+    #[config(value_name = "ORDER", default = "TestOrder::default()")]
+    pub order: TestOrder,
+
     #[config(flatten, next_help_heading = "Feature Selection Config Options")]
     pub cargo_feature_selection_options: FeatureSelectionOptions,
 
@@ -0,0 +1,305 @@
+//! Checks that the machine is set up to run tests with Maelstrom.
+//!
+//! Each check reports whether it passed, and if it didn't, a hint about how to fix the problem.
+//! The paths the checks look at are taken from [`DoctorPaths`] so that they can be pointed at a
+//! controlled environment.
+
+use maelstrom_util::config::common::BrokerAddr;
+use std::{
+    fmt, fs, io,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// How long to wait when trying to connect to the broker.
+const BROKER_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the checks look for things.
+#[derive(Clone, Debug)]
+pub struct DoctorPaths {
+    /// Usually `/proc/sys`.
+    pub proc_sys: PathBuf,
+    /// Usually `/sys/fs/cgroup`.
+    pub cgroup: PathBuf,
+    /// The directory `cargo-maelstrom` stores its files in.
+    pub cache_dir: PathBuf,
+}
+
+impl DoctorPaths {
+    pub fn new(cache_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            proc_sys: "/proc/sys".into(),
+            cgroup: "/sys/fs/cgroup".into(),
+            cache_dir: cache_dir.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CheckOutcome {
+    Pass,
+    Fail { problem: String, hint: String },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, outcome: CheckOutcome) -> Self {
+        Self { name, outcome }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.outcome == CheckOutcome::Pass
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            CheckOutcome::Pass => write!(f, "[PASS] {}", self.name),
+            CheckOutcome::Fail { problem, hint } => {
+                writeln!(f, "[FAIL] {}: {problem}", self.name)?;
+                write!(f, "       hint: {hint}")
+            }
+        }
+    }
+}
+
+fn fail(problem: impl Into<String>, hint: impl Into<String>) -> CheckOutcome {
+    CheckOutcome::Fail {
+        problem: problem.into(),
+        hint: hint.into(),
+    }
+}
+
+/// Read a sysctl-style file containing a single integer. Returns `None` if the file doesn't exist.
+fn read_sysctl(path: &Path) -> io::Result<Option<i64>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents.trim().parse().map(Some).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected contents in {}", path.display()),
+            )
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Jobs are run in user namespaces created by an unprivileged user. Some kernels only allow this
+/// if a sysctl is set.
+pub fn check_user_namespaces(paths: &DoctorPaths) -> CheckResult {
+    const NAME: &str = "unprivileged user namespaces";
+    let sysctl = |name: &str| read_sysctl(&paths.proc_sys.join(name));
+    let outcome = (|| -> io::Result<CheckOutcome> {
+        if sysctl("user/max_user_namespaces")? == Some(0) {
+            return Ok(fail(
+                "user.max_user_namespaces is 0",
+                "run `sysctl -w user.max_user_namespaces=15000`",
+            ));
+        }
+        if sysctl("kernel/unprivileged_userns_clone")? == Some(0) {
+            return Ok(fail(
+                "kernel.unprivileged_userns_clone is 0",
+                "run `sysctl -w kernel.unprivileged_userns_clone=1`",
+            ));
+        }
+        if sysctl("kernel/apparmor_restrict_unprivileged_userns")?.unwrap_or(0) != 0 {
+            return Ok(fail(
+                "AppArmor restricts unprivileged user namespaces",
+                "run `sysctl -w kernel.apparmor_restrict_unprivileged_userns=0`",
+            ));
+        }
+        Ok(CheckOutcome::Pass)
+    })()
+    .unwrap_or_else(|err| fail(err.to_string(), "make sure /proc is mounted and readable"));
+    CheckResult::new(NAME, outcome)
+}
+
+/// The local worker uses cgroup v2 to limit the resources used by jobs.
+pub fn check_cgroup_v2(paths: &DoctorPaths) -> CheckResult {
+    const NAME: &str = "cgroup v2";
+    let outcome = if paths.cgroup.join("cgroup.controllers").is_file() {
+        CheckOutcome::Pass
+    } else {
+        fail(
+            format!("cgroup v2 is not mounted at {}", paths.cgroup.display()),
+            "boot with `systemd.unified_cgroup_hierarchy=1`, or mount it with \
+                `mount -t cgroup2 none /sys/fs/cgroup`",
+        )
+    };
+    CheckResult::new(NAME, outcome)
+}
+
+/// `cargo-maelstrom` needs to be able to write to its cache directory.
+pub fn check_cache_dir(paths: &DoctorPaths) -> CheckResult {
+    const NAME: &str = "writable cache directory";
+    let dir = &paths.cache_dir;
+    let outcome = (|| -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let probe = dir.join(".maelstrom-doctor");
+        fs::write(&probe, b"")?;
+        fs::remove_file(&probe)
+    })()
+    .map_or_else(
+        |err| {
+            fail(
+                format!("can't write to {}: {err}", dir.display()),
+                "check the permissions of the directory, or use a different target directory",
+            )
+        },
+        |()| CheckOutcome::Pass,
+    );
+    CheckResult::new(NAME, outcome)
+}
+
+/// If a broker is configured, we need to be able to connect to it. Without a broker, tests are run
+/// on the local worker, so there's nothing to check.
+pub fn check_broker(broker: Option<BrokerAddr>) -> CheckResult {
+    const NAME: &str = "broker reachable";
+    let outcome = match broker {
+        None => CheckOutcome::Pass,
+        Some(broker) => match TcpStream::connect_timeout(broker.inner(), BROKER_CONNECT_TIMEOUT) {
+            Ok(_) => CheckOutcome::Pass,
+            Err(err) => fail(
+                format!("can't connect to {}: {err}", broker.inner()),
+                "make sure the broker is running and the `broker` configuration value is correct",
+            ),
+        },
+    };
+    CheckResult::new(NAME, outcome)
+}
+
+/// Run all of the checks, in the order they should be reported.
+pub fn run_checks(paths: &DoctorPaths, broker: Option<BrokerAddr>) -> Vec<CheckResult> {
+    vec![
+        check_user_namespaces(paths),
+        check_cgroup_v2(paths),
+        check_cache_dir(paths),
+        check_broker(broker),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+    use tempfile::TempDir;
+
+    /// A fake `/proc/sys` and `/sys/fs/cgroup` in which every check passes.
+    fn healthy_environment() -> (TempDir, DoctorPaths) {
+        let root = tempfile::tempdir().unwrap();
+        let paths = DoctorPaths {
+            proc_sys: root.path().join("proc/sys"),
+            cgroup: root.path().join("sys/fs/cgroup"),
+            cache_dir: root.path().join("target/maelstrom"),
+        };
+        fs::create_dir_all(paths.proc_sys.join("user")).unwrap();
+        fs::create_dir_all(paths.proc_sys.join("kernel")).unwrap();
+        fs::write(paths.proc_sys.join("user/max_user_namespaces"), "15000\n").unwrap();
+        fs::create_dir_all(&paths.cgroup).unwrap();
+        fs::write(paths.cgroup.join("cgroup.controllers"), "cpu memory pids\n").unwrap();
+        (root, paths)
+    }
+
+    fn assert_fails(result: &CheckResult, problem_contains: &str) {
+        let CheckOutcome::Fail { problem, .. } = &result.outcome else {
+            panic!("expected {} to fail", result.name);
+        };
+        assert!(problem.contains(problem_contains), "{problem}");
+    }
+
+    #[test]
+    fn all_pass_in_healthy_environment() {
+        let (_root, paths) = healthy_environment();
+        let results = run_checks(&paths, None);
+        assert_eq!(results.len(), 4);
+        assert!(results.iter().all(CheckResult::passed), "{results:#?}");
+        assert!(paths.cache_dir.is_dir());
+    }
+
+    #[test]
+    fn user_namespaces_disabled_by_max_user_namespaces() {
+        let (_root, paths) = healthy_environment();
+        fs::write(paths.proc_sys.join("user/max_user_namespaces"), "0\n").unwrap();
+        assert_fails(
+            &check_user_namespaces(&paths),
+            "user.max_user_namespaces is 0",
+        );
+    }
+
+    #[test]
+    fn user_namespaces_disabled_by_unprivileged_userns_clone() {
+        let (_root, paths) = healthy_environment();
+        fs::write(paths.proc_sys.join("kernel/unprivileged_userns_clone"), "0").unwrap();
+        assert_fails(
+            &check_user_namespaces(&paths),
+            "kernel.unprivileged_userns_clone is 0",
+        );
+    }
+
+    #[test]
+    fn user_namespaces_restricted_by_apparmor() {
+        let (_root, paths) = healthy_environment();
+        fs::write(
+            paths
+                .proc_sys
+                .join("kernel/apparmor_restrict_unprivileged_userns"),
+            "1",
+        )
+        .unwrap();
+        assert_fails(&check_user_namespaces(&paths), "AppArmor");
+    }
+
+    #[test]
+    fn cgroup_v2_not_mounted() {
+        let (_root, paths) = healthy_environment();
+        fs::remove_file(paths.cgroup.join("cgroup.controllers")).unwrap();
+        assert_fails(&check_cgroup_v2(&paths), "cgroup v2 is not mounted");
+    }
+
+    #[test]
+    fn cache_dir_not_writable() {
+        let (root, mut paths) = healthy_environment();
+        // A directory can't be created underneath a regular file, regardless of who we're running
+        // as.
+        let file = root.path().join("file");
+        fs::write(&file, "").unwrap();
+        paths.cache_dir = file.join("maelstrom");
+        assert_fails(&check_cache_dir(&paths), "can't write to");
+    }
+
+    #[test]
+    fn broker_reachable() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = BrokerAddr::new(listener.local_addr().unwrap());
+        assert!(check_broker(Some(addr)).passed());
+    }
+
+    #[test]
+    fn broker_unreachable() {
+        // Bind a port and then release it so that nothing is listening on it.
+        let addr = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let result = check_broker(Some(BrokerAddr::new(addr)));
+        assert_fails(&result, "can't connect to");
+    }
+
+    #[test]
+    fn failure_display_includes_hint() {
+        let result = CheckResult::new("broker reachable", fail("it's down", "start it"));
+        assert_eq!(
+            result.to_string(),
+            "[FAIL] broker reachable: it's down\n       hint: start it"
+        );
+        let result = CheckResult::new("cgroup v2", CheckOutcome::Pass);
+        assert_eq!(result.to_string(), "[PASS] cgroup v2");
+    }
+}
@@ -1,6 +1,7 @@
 pub mod artifacts;
 pub mod cargo;
 pub mod config;
+pub mod doctor;
 pub mod metadata;
 pub mod pattern;
 pub mod progress;
@@ -10,50 +11,75 @@ pub mod visitor;
 #[cfg(test)]
 mod tests;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context as _, Result};
 use artifacts::GeneratedArtifacts;
-use cargo::{CompilationOptions, FeatureSelectionOptions, ManifestOptions};
+use bytesize::ByteSize;
+use cargo::{CompilationOptions, FeatureSelectionOptions, ManifestOptions, TestCaseLocation};
 use cargo_metadata::{Artifact as CargoArtifact, Package as CargoPackage, PackageId};
-use config::Quiet;
+use config::{ExitCodeSeverities, LargeOutputThreshold, OutputEncoding, Quiet, TestOrder};
+use globset::GlobSet;
 use indicatif::TermLike;
 use maelstrom_base::{
-    stats::JobStateCounts, ArtifactType, ClientJobId, JobOutcomeResult, JobSpec, NonEmpty,
-    Sha256Digest, Timeout,
+    stats::JobStateCounts, ArtifactType, ClientJobId, JobCompleted, JobEffects, JobError,
+    JobOutcome, JobOutcomeResult, JobOutputResult, JobSpec, JobStatus, NonEmpty, Sha256Digest,
+    Timeout, Utf8PathBuf, WorkerCapabilitiesMap,
 };
 use maelstrom_client::{
-    spec::{ImageConfig, Layer},
+    is_transient_error,
+    spec::{split_image_reference, ImageConfig, Layer},
     ArtifactUploadProgress, Client, ClientBgProcess,
 };
 use maelstrom_util::{
-    config::common::{BrokerAddr, CacheSize, InlineLimit, LogLevel, Slots},
+    config::common::{
+        BrokerAddr, CacheSize, DispatcherThreads, HashingThreads, InlineLimit, LogLevel, Slots,
+    },
+    fs::Fs,
     process::ExitCode,
     template::TemplateVars,
 };
-use metadata::{AllMetadata, TestMetadata};
+use metadata::{AllMetadata, GlobalJob, HostEnvironment, TestMetadata};
 use progress::{
     MultipleProgressBars, NoBar, ProgressDriver, ProgressIndicator, QuietNoBar, QuietProgressBar,
     TestListingProgress, TestListingProgressNoSpinner,
 };
+use rand::{rngs::StdRng, seq::SliceRandom as _, SeedableRng as _};
 use slog::Drain as _;
 use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::{
-    collections::{BTreeMap, HashSet},
+    cmp,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     io,
+    num::{NonZeroU32, NonZeroUsize},
     path::{Path, PathBuf},
     str,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc, Mutex,
+        mpsc, Arc, Condvar, Mutex,
     },
+    thread,
+    time::{Duration, Instant},
 };
-use test_listing::{load_test_listing, write_test_listing, TestListing, LAST_TEST_LISTING_NAME};
-use visitor::{JobStatusTracker, JobStatusVisitor};
+use test_listing::{load_test_listing, write_test_listing, TestListing, TestListingFormat};
+use visitor::{CaseName, JobStatusTracker, JobStatusVisitor};
 
 #[derive(Debug)]
 pub enum ListAction {
     ListTests,
     ListBinaries,
     ListPackages,
+    /// Print the job spec each test would be run with, as a line of JSON, instead of running it.
+    DumpSpecs,
+}
+
+/// Which of the workspace's packages tests are run from, before any filters are applied. This is
+/// decided the same way `cargo test` decides which packages to test.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum PackageScope {
+    /// Every package in the workspace.
+    #[default]
+    Workspace,
+    /// Only the packages with the given names.
+    Packages(Vec<String>),
 }
 
 /// Returns `true` if the given `CargoPackage` matches the given pattern
@@ -71,16 +97,47 @@ fn filter_case(
     package_name: &str,
     artifact: &CargoArtifact,
     case: &str,
+    previously_failed: bool,
     p: &pattern::Pattern,
 ) -> bool {
     let c = pattern::Context {
         package: package_name.into(),
         artifact: Some(pattern::Artifact::from_target(&artifact.target)),
-        case: Some(pattern::Case { name: case.into() }),
+        case: Some(pattern::Case {
+            name: case.into(),
+            previously_failed,
+        }),
     };
     pattern::interpret_pattern(p, &c).expect("case is provided")
 }
 
+/// Put `cases` in an order determined only by `seed` and the names of the cases.
+fn shuffle_cases(cases: &mut [String], seed: u64) {
+    cases.sort();
+    cases.shuffle(&mut StdRng::seed_from_u64(seed));
+}
+
+/// Put `cases` in order of how long they took last time, as given by `last_duration`, slowest first.
+/// Cases without a duration go first. The sort is stable, so cases that took equally long keep
+/// their order.
+fn order_slowest_first(cases: &mut [String], last_duration: impl Fn(&str) -> Option<Duration>) {
+    cases.sort_by_cached_key(|c| cmp::Reverse(last_duration(c).unwrap_or(Duration::MAX)));
+}
+
+/// The name a case is displayed with: its package, then its artifact if that is named differently
+/// than the package, then the case itself.
+fn format_case_str(package_name: &str, artifact_name: &str, case: &str) -> String {
+    let mut s = package_name.to_string();
+    s += " ";
+
+    if artifact_name != package_name {
+        s += artifact_name;
+        s += " ";
+    }
+    s += case;
+    s
+}
+
 fn do_template_replacement(
     test_metadata: &mut AllMetadata,
     compilation_options: &CompilationOptions,
@@ -102,6 +159,52 @@ fn do_template_replacement(
     Ok(())
 }
 
+/// The exact test case names read from a `--tests-from` file. When one is given, it is used
+/// instead of the include and exclude filters. It remembers which names it has matched so the
+/// rest can be reported as unknown at the end.
+struct CaseList {
+    names: HashSet<String>,
+    matched: Mutex<HashSet<String>>,
+}
+
+impl CaseList {
+    /// Read the case names from `path`, one per line. Blank lines are ignored.
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = Fs::new().read_to_string(path)?;
+        Ok(Self {
+            names: contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(ToOwned::to_owned)
+                .collect(),
+            matched: Default::default(),
+        })
+    }
+
+    /// Returns `true` if `case` is one of the listed names
+    fn select(&self, case: &str) -> bool {
+        let selected = self.names.contains(case);
+        if selected {
+            self.matched.lock().unwrap().insert(case.into());
+        }
+        selected
+    }
+
+    /// The listed names which didn't match any test case, in sorted order
+    fn unknown(&self) -> Vec<&str> {
+        let matched = self.matched.lock().unwrap();
+        let mut unknown = Vec::from_iter(
+            self.names
+                .iter()
+                .filter(|name| !matched.contains(*name))
+                .map(String::as_str),
+        );
+        unknown.sort();
+        unknown
+    }
+}
+
 /// A collection of objects that are used while enqueuing jobs. This is useful as a separate object
 /// since it can contain things which live longer than the scoped threads and thus can be shared
 /// among them.
@@ -110,13 +213,39 @@ fn do_template_replacement(
 struct JobQueuingState {
     packages: BTreeMap<PackageId, CargoPackage>,
     filter: pattern::Pattern,
+    case_list: Option<CaseList>,
     stderr_color: bool,
     tracker: Arc<JobStatusTracker>,
     jobs_queued: AtomicU64,
     test_metadata: AllMetadata,
-    expected_job_count: u64,
+    shared_library_exclusions: GlobSet,
+    /// The number of jobs we expect to run, according to the test listing. This is recomputed as
+    /// each artifact is listed, since the cached listing may be out of date.
+    expected_job_count: AtomicU64,
     test_listing: Mutex<TestListing>,
+    /// The container images that have been looked up so far, by name and tag.
+    container_images: Mutex<HashMap<(String, String), ImageConfig>>,
     list_action: Option<ListAction>,
+    include_test_locations: bool,
+    report_time: bool,
+    github_annotations: bool,
+    summary_by_package: bool,
+    json_summary: Option<PathBuf>,
+    junit: Option<PathBuf>,
+    started: Instant,
+    test_output_dir: Option<PathBuf>,
+    large_output_threshold: Option<LargeOutputThreshold>,
+    lost_test_deadline: Option<Duration>,
+    exit_code_severities: ExitCodeSeverities,
+    output_encoding: OutputEncoding,
+    retries: u32,
+    job_submission_retries: u32,
+    enqueue_batch_size: Option<NonZeroU32>,
+    affinity_cache_warm: bool,
+    shuffle_seed: Option<u64>,
+    list_threads: NonZeroUsize,
+    order: TestOrder,
+    host: HostEnvironment,
     feature_selection_options: FeatureSelectionOptions,
     compilation_options: CompilationOptions,
     manifest_options: ManifestOptions,
@@ -127,41 +256,462 @@ impl JobQueuingState {
     fn new(
         packages: BTreeMap<PackageId, CargoPackage>,
         filter: pattern::Pattern,
-        stderr_color: bool,
+        case_list: Option<CaseList>,
         mut test_metadata: AllMetadata,
         test_listing: TestListing,
         list_action: Option<ListAction>,
         target_directory: impl AsRef<Path>,
-        feature_selection_options: FeatureSelectionOptions,
-        compilation_options: CompilationOptions,
-        manifest_options: ManifestOptions,
+        options: MainAppOptions,
     ) -> Result<Self> {
-        let expected_job_count = test_listing.expected_job_count(&filter);
+        let MainAppOptions {
+            stderr_color,
+            test_listing_format: _,
+            include_test_locations,
+            report_time,
+            github_annotations,
+            summary_by_package,
+            json_summary,
+            junit,
+            test_output_dir,
+            large_output_threshold,
+            failure_output_limit,
+            lost_test_deadline,
+            exit_code_severities,
+            output_encoding,
+            retries,
+            job_submission_retries,
+            enqueue_batch_size,
+            affinity_cache_warm,
+            shuffle_seed,
+            list_threads,
+            order,
+            feature_selection_options,
+            compilation_options,
+            manifest_options,
+        } = options;
+        let expected_job_count = expected_job_count(&test_listing, &filter, case_list.as_ref());
         do_template_replacement(
             &mut test_metadata,
             &compilation_options,
             target_directory.as_ref(),
         )?;
+        let shared_library_exclusions = test_metadata.shared_library_exclusions()?;
 
         Ok(Self {
             packages,
             filter,
+            case_list,
             stderr_color,
-            tracker: Arc::new(JobStatusTracker::default()),
+            tracker: Arc::new(JobStatusTracker::new(failure_output_limit, junit.is_some())),
             jobs_queued: AtomicU64::new(0),
             test_metadata,
-            expected_job_count,
+            shared_library_exclusions,
+            expected_job_count: AtomicU64::new(expected_job_count),
             test_listing: Mutex::new(test_listing),
+            container_images: Mutex::default(),
             list_action,
+            include_test_locations,
+            report_time,
+            github_annotations,
+            summary_by_package,
+            json_summary,
+            junit,
+            started: Instant::now(),
+            test_output_dir,
+            large_output_threshold,
+            lost_test_deadline,
+            exit_code_severities: exit_code_severities.unwrap_or_default(),
+            output_encoding,
+            retries,
+            job_submission_retries,
+            enqueue_batch_size,
+            affinity_cache_warm,
+            shuffle_seed,
+            list_threads,
+            order,
+            host: HostEnvironment::current(),
             feature_selection_options,
             compilation_options,
             manifest_options,
         })
     }
+
+    /// Look up a container image, only asking the client for it the first time it is used.
+    fn get_container_image(
+        &self,
+        deps: &impl MainAppDeps,
+        ind: &impl ProgressIndicator,
+        log: &slog::Logger,
+        image: &str,
+    ) -> Result<ImageConfig> {
+        let (name, version) = split_image_reference(image);
+        let key = (name.to_owned(), version.to_owned());
+        if let Some(image) = self.container_images.lock().unwrap().get(&key) {
+            return Ok(image.clone());
+        }
+        ind.update_enqueue_status(format!("downloading image {image}"));
+        slog::debug!(
+            log, "getting container image";
+            "image" => &name,
+            "version" => &version,
+        );
+        let image = deps.get_container_image(name, version)?;
+        self.container_images
+            .lock()
+            .unwrap()
+            .insert(key, image.clone());
+        Ok(image)
+    }
+}
+
+/// Build the spec for a job that runs `program` with everything else coming from the test
+/// metadata.
+fn job_spec(
+    program: Utf8PathBuf,
+    arguments: Vec<String>,
+    layers: NonEmpty<(Sha256Digest, ArtifactType)>,
+    timeout: Option<Timeout>,
+    test_metadata: TestMetadata,
+) -> JobSpec {
+    JobSpec {
+        program,
+        arguments,
+        environment: test_metadata.environment(),
+        layers,
+        devices: test_metadata.devices,
+        mounts: test_metadata.mounts(),
+        enable_loopback: test_metadata.enable_loopback,
+        enable_writable_file_system: test_metadata.enable_writable_file_system,
+        working_directory: test_metadata.working_directory,
+        user: test_metadata.user,
+        group: test_metadata.group,
+        timeout,
+        enable_init_process: test_metadata.enable_init_process,
+        cpus: test_metadata.cpus,
+        memory: test_metadata.memory,
+        stream_output: false,
+        priority: 0,
+    }
 }
 
 type StringIter = <Vec<String> as IntoIterator>::IntoIter;
 
+struct PendingJobsInner<ProgressIndicatorT> {
+    next_id: u64,
+    /// Jobs which have been submitted but haven't finished yet. Once a job starts running, we
+    /// also have when that happened and the job's id in the client.
+    #[allow(clippy::type_complexity)]
+    outstanding: HashMap<
+        u64,
+        (
+            Option<(Instant, ClientJobId)>,
+            JobSpec,
+            JobStatusVisitor<ProgressIndicatorT>,
+        ),
+    >,
+    /// Jobs which failed in a way that their retry policy says they should be run again. They are
+    /// resubmitted once all of the outstanding jobs have finished.
+    retries: Vec<(JobSpec, JobStatusVisitor<ProgressIndicatorT>)>,
+    /// Once we're done queuing jobs, the number of outstanding jobs is reported here every time it
+    /// changes.
+    drain_progress: Option<ProgressIndicatorT>,
+    /// Whether [`PendingJobs::watch_for_lost_jobs`] should keep going.
+    watching: bool,
+}
+
+impl<ProgressIndicatorT: ProgressIndicator> PendingJobsInner<ProgressIndicatorT> {
+    fn report_outstanding(&self) {
+        if let Some(ind) = &self.drain_progress {
+            ind.update_outstanding_jobs(self.outstanding.len() as u64);
+        }
+    }
+}
+
+/// Keeps track of the jobs that we are still waiting on.
+struct PendingJobs<ProgressIndicatorT> {
+    inner: Mutex<PendingJobsInner<ProgressIndicatorT>>,
+    condvar: Condvar,
+}
+
+impl<ProgressIndicatorT> Default for PendingJobs<ProgressIndicatorT> {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(PendingJobsInner {
+                next_id: 0,
+                outstanding: HashMap::new(),
+                retries: vec![],
+                drain_progress: None,
+                watching: false,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+}
+
+impl<ProgressIndicatorT: ProgressIndicator> PendingJobs<ProgressIndicatorT> {
+    /// Start tracking a job which is about to be submitted. The returned id is used to refer to
+    /// it later.
+    fn add(&self, spec: JobSpec, visitor: JobStatusVisitor<ProgressIndicatorT>) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.outstanding.insert(id, (None, spec, visitor));
+        inner.report_outstanding();
+        id
+    }
+
+    /// Stop tracking a job which couldn't be submitted after all.
+    fn remove(&self, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.outstanding.remove(&id);
+        inner.report_outstanding();
+    }
+
+    /// Record that a job has started running, as the job with `cjid` in the client.
+    fn job_running(&self, id: u64, cjid: ClientJobId) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some((running, _, _)) = inner.outstanding.get_mut(&id) {
+            *running = Some((Instant::now(), cjid));
+            self.condvar.notify_all();
+        }
+    }
+
+    /// Report the result of a job, or the error that kept us from getting it. If the job's retry
+    /// policy says it should be run again, it is put on the retry list.
+    fn job_finished(&self, id: u64, result: Result<(ClientJobId, JobOutcomeResult)>) {
+        let mut inner = self.inner.lock().unwrap();
+        // If the job isn't there, it was already reported as lost.
+        if let Some((_, spec, visitor)) = inner.outstanding.remove(&id) {
+            match result {
+                Ok((cjid, result)) => {
+                    if let Some(visitor) = visitor.job_finished(cjid, result) {
+                        inner.retries.push((spec, visitor));
+                    }
+                }
+                Err(err) => visitor.job_errored(&err),
+            }
+            inner.report_outstanding();
+            self.condvar.notify_all();
+        }
+    }
+
+    /// Called once all jobs have been queued. From now on, `ind` is told how many jobs we're still
+    /// waiting on whenever that changes.
+    fn start_draining(&self, ind: ProgressIndicatorT) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.drain_progress = Some(ind);
+        inner.report_outstanding();
+    }
+
+    /// Allow [`Self::watch_for_lost_jobs`] to run. This must be called before it is.
+    fn start_watching(&self) {
+        self.inner.lock().unwrap().watching = true;
+    }
+
+    /// Make [`Self::watch_for_lost_jobs`] return.
+    fn stop_watching(&self) {
+        self.inner.lock().unwrap().watching = false;
+        self.condvar.notify_all();
+    }
+
+    /// Until [`Self::stop_watching`] is called, report jobs which haven't finished `lost_deadline`
+    /// after they started running as lost. Each lost job is passed to `cancel` so the client stops
+    /// waiting for it too. Time a job spends waiting to start running doesn't count.
+    fn watch_for_lost_jobs(&self, lost_deadline: Duration, mut cancel: impl FnMut(ClientJobId)) {
+        let mut inner = self.inner.lock().unwrap();
+        while inner.watching {
+            let now = Instant::now();
+            let lost = Vec::from_iter(
+                inner
+                    .outstanding
+                    .iter()
+                    .filter(|(_, (running, _, _))| {
+                        running.is_some_and(|(started, _)| now - started >= lost_deadline)
+                    })
+                    .map(|(id, _)| *id),
+            );
+            if !lost.is_empty() {
+                let mut lost_cjids = vec![];
+                for id in lost {
+                    let (running, _, visitor) = inner.outstanding.remove(&id).unwrap();
+                    visitor.job_lost(lost_deadline);
+                    lost_cjids.push(running.unwrap().1);
+                }
+                inner.report_outstanding();
+                drop(inner);
+                for cjid in lost_cjids {
+                    cancel(cjid);
+                }
+                inner = self.inner.lock().unwrap();
+                continue;
+            }
+            let next_deadline = inner
+                .outstanding
+                .values()
+                .filter_map(|(running, _, _)| running.map(|(started, _)| started + lost_deadline))
+                .min();
+            inner = match next_deadline {
+                Some(next_deadline) => {
+                    self.condvar
+                        .wait_timeout(inner, next_deadline - now)
+                        .unwrap()
+                        .0
+                }
+                None => self.condvar.wait(inner).unwrap(),
+            };
+        }
+    }
+
+    /// Wait for all of the outstanding jobs to finish or be reported as lost, then return the jobs
+    /// that need to be retried.
+    fn wait(&self) -> Vec<(JobSpec, JobStatusVisitor<ProgressIndicatorT>)> {
+        let mut inner = self
+            .condvar
+            .wait_while(self.inner.lock().unwrap(), |inner| {
+                !inner.outstanding.is_empty()
+            })
+            .unwrap();
+        std::mem::take(&mut inner.retries)
+    }
+}
+
+/// How long to wait before the first retry of a job submission that failed. The wait doubles for
+/// each later retry, up to [`MAX_SUBMISSION_RETRY_BACKOFF`].
+const SUBMISSION_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest we wait before retrying a job submission.
+const MAX_SUBMISSION_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Submit a job to the client. It is tracked in `pending_jobs` until it finishes. If the job needs
+/// to be retried, it is put on the retry list in `pending_jobs` when it finishes.
+///
+/// This is separate from the job's own retry policy: if the client returns an error when the job is
+/// submitted, the job never ran. If the error is transient, as decided by [`is_transient_error`],
+/// submitting it is tried again up to `submission_retries` more times before giving up. Other
+/// errors won't go away by trying again, so they are returned right away.
+fn submit_job<ProgressIndicatorT: ProgressIndicator>(
+    deps: &impl MainAppDeps,
+    spec: JobSpec,
+    visitor: JobStatusVisitor<ProgressIndicatorT>,
+    pending_jobs: &Arc<PendingJobs<ProgressIndicatorT>>,
+    submission_retries: u32,
+    log: &slog::Logger,
+) -> Result<()> {
+    let id = pending_jobs.add(spec.clone(), visitor);
+    let mut backoff = SUBMISSION_RETRY_BACKOFF;
+    let mut retries = 0;
+    loop {
+        let running_pending_jobs = pending_jobs.clone();
+        let handler_pending_jobs = pending_jobs.clone();
+        let res = deps.add_job(
+            spec.clone(),
+            move |cjid| running_pending_jobs.job_running(id, cjid),
+            move |result| handler_pending_jobs.job_finished(id, result),
+        );
+        match res {
+            Ok(()) => return Ok(()),
+            Err(err) if retries < submission_retries && is_transient_error(&err) => {
+                retries += 1;
+                slog::warn!(
+                    log, "error submitting job, retrying";
+                    "error" => %err,
+                    "retry" => retries,
+                    "backoff" => ?backoff,
+                );
+                std::thread::sleep(backoff);
+                backoff = std::cmp::min(backoff * 2, MAX_SUBMISSION_RETRY_BACKOFF);
+            }
+            Err(err) => {
+                pending_jobs.remove(id);
+                return Err(err);
+            }
+        }
+    }
+}
+
+/// Run a setup or teardown job and wait for it to finish. An error is returned if the job doesn't
+/// exit successfully.
+///
+/// The job's spec is built from the test metadata like a test's is, from the directives that
+/// apply to every test. The `timeout` and `test-timeout-default` configuration values only apply
+/// to tests, so only a timeout from the directives is used.
+fn run_global_job(
+    deps: &impl MainAppDeps,
+    queuing_state: &JobQueuingState,
+    ind: &impl ProgressIndicator,
+    log: &slog::Logger,
+    name: &str,
+    job: &GlobalJob,
+) -> Result<()> {
+    slog::debug!(log, "running global job"; "name" => name, "job" => ?job);
+    let test_metadata = queuing_state
+        .test_metadata
+        .get_metadata_for_global_job_with_env(job, |image| {
+            queuing_state.get_container_image(deps, ind, log, image)
+        })
+        .with_context(|| format!("getting test metadata for {name} job"))?;
+    let layers = test_metadata
+        .layers
+        .iter()
+        .map(|layer| deps.add_layer(layer.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let layers = NonEmpty::try_from(layers)
+        .map_err(|_| anyhow!("{name} job must have at least one layer"))?;
+    let timeout = test_metadata.timeout(None, None);
+    let spec = job_spec(
+        job.program.clone(),
+        job.arguments.clone(),
+        layers,
+        timeout,
+        test_metadata,
+    );
+    spec.validate()
+        .with_context(|| format!("invalid test metadata for {name} job"))?;
+
+    let outcome = Arc::new(Mutex::new(None));
+    let outcome_clone = outcome.clone();
+    deps.add_job(
+        spec,
+        |_| {},
+        move |result| *outcome_clone.lock().unwrap() = Some(result.map(|(_, result)| result)),
+    )?;
+    deps.wait_for_outstanding_jobs()?;
+
+    let outcome = outcome.lock().unwrap().take();
+    let (details, stderr) = match outcome {
+        None => (String::from("job never finished"), None),
+        Some(Ok(Ok(JobOutcome::Completed(JobCompleted {
+            status: JobStatus::Exited(0),
+            ..
+        })))) => {
+            ind.println(format!("{name} job succeeded"));
+            return Ok(());
+        }
+        Some(Ok(Ok(JobOutcome::Completed(JobCompleted { status, effects })))) => {
+            let details = match status {
+                JobStatus::Exited(code) => format!("exited with code {code}"),
+                JobStatus::Signaled(signo) => format!("killed by signal {signo}"),
+            };
+            (details, Some(effects.stderr))
+        }
+        Some(Ok(Ok(JobOutcome::TimedOut(JobEffects { stderr, .. })))) => {
+            ("timed out".into(), Some(stderr))
+        }
+        Some(Ok(Err(JobError::Execution(err)))) => (format!("execution error: {err}"), None),
+        Some(Ok(Err(JobError::System(err)))) => (format!("system error: {err}"), None),
+        Some(Err(err)) => (format!("client error: {err:#}"), None),
+    };
+    let stderr = match &stderr {
+        Some(JobOutputResult::Inline(bytes)) => &bytes[..],
+        Some(JobOutputResult::Truncated { first, .. }) => &first[..],
+        Some(JobOutputResult::None) | None => &[],
+    };
+    if !stderr.is_empty() {
+        ind.eprintln(String::from_utf8_lossy(stderr));
+    }
+    Err(anyhow!("{name} job failed: {details}"))
+}
+
 /// Enqueues test cases as jobs in the given client from the given `CargoArtifact`
 ///
 /// This object is like an iterator, it maintains a position in the test listing and enqueues the
@@ -179,15 +729,32 @@ struct ArtifactQueuing<'a, ProgressIndicatorT, MainAppDepsT> {
     binary: PathBuf,
     generated_artifacts: Option<GeneratedArtifacts>,
     ignored_cases: HashSet<String>,
+    locations: HashMap<String, TestCaseLocation>,
     package_name: String,
     cases: StringIter,
+    /// The layers of the cases whose layers were added before any jobs were enqueued.
+    warmed_layers: HashMap<String, NonEmpty<(Sha256Digest, ArtifactType)>>,
     timeout_override: Option<Option<Timeout>>,
+    timeout_default: Option<Timeout>,
+    pending_jobs: Arc<PendingJobs<ProgressIndicatorT>>,
 }
 
 #[derive(Default)]
 struct TestListingResult {
     cases: Vec<String>,
     ignored_cases: HashSet<String>,
+    locations: HashMap<String, TestCaseLocation>,
+}
+
+fn expected_job_count(
+    test_listing: &TestListing,
+    filter: &pattern::Pattern,
+    case_list: Option<&CaseList>,
+) -> u64 {
+    match case_list {
+        Some(case_list) => test_listing.expected_job_count_for_cases(&case_list.names),
+        None => test_listing.expected_job_count(filter),
+    }
 }
 
 fn list_test_cases(
@@ -210,23 +777,56 @@ fn list_test_cases(
     slog::debug!(log, "listing tests"; "binary" => ?artifact.executable);
     let mut cases = deps.get_cases_from_binary(&binary, &None)?;
 
+    let listed_ignored_cases: Vec<_> = cases
+        .iter()
+        .filter(|c| ignored_cases.contains(*c))
+        .cloned()
+        .collect();
     let mut listing = queuing_state.test_listing.lock().unwrap();
-    listing.add_cases(package_name, artifact, &cases[..]);
+    listing.add_cases(
+        package_name,
+        artifact,
+        &cases[..],
+        &listed_ignored_cases[..],
+    );
+
+    match &queuing_state.case_list {
+        Some(case_list) => cases.retain(|c| case_list.select(c)),
+        None => cases.retain(|c| {
+            let previously_failed = listing.previously_failed(package_name, artifact, c);
+            filter_case(
+                package_name,
+                artifact,
+                c,
+                previously_failed,
+                &queuing_state.filter,
+            )
+        }),
+    }
+
+    // Locations are printed with results, and used to point GitHub annotations at the test.
+    let locations = if queuing_state.include_test_locations || queuing_state.github_annotations {
+        slog::debug!(log, "listing test locations"; "binary" => ?artifact.executable);
+        deps.get_case_locations_from_binary(&binary)?
+    } else {
+        HashMap::new()
+    };
 
-    cases.retain(|c| filter_case(package_name, artifact, c, &queuing_state.filter));
     Ok(TestListingResult {
         cases,
         ignored_cases,
+        locations,
     })
 }
 
 fn generate_artifacts(
     deps: &impl MainAppDeps,
+    queuing_state: &JobQueuingState,
     artifact: &CargoArtifact,
     log: slog::Logger,
 ) -> Result<GeneratedArtifacts> {
     let binary = PathBuf::from(artifact.executable.clone().unwrap());
-    artifacts::add_generated_artifacts(deps, &binary, log)
+    artifacts::add_generated_artifacts(deps, &binary, &queuing_state.shared_library_exclusions, log)
 }
 
 impl<'a, ProgressIndicatorT, MainAppDepsT> ArtifactQueuing<'a, ProgressIndicatorT, MainAppDepsT>
@@ -242,14 +842,24 @@ where
         width: usize,
         ind: ProgressIndicatorT,
         artifact: CargoArtifact,
-        package_name: String,
         timeout_override: Option<Option<Timeout>>,
+        timeout_default: Option<Timeout>,
+        pending_jobs: Arc<PendingJobs<ProgressIndicatorT>>,
     ) -> Result<Self> {
         let binary = PathBuf::from(artifact.executable.clone().unwrap());
+        let package_name = queuing_state
+            .packages
+            .get(&artifact.package_id)
+            .expect("artifact for unknown package")
+            .name
+            .clone();
 
-        let running_tests = queuing_state.list_action.is_none();
+        let running_tests = matches!(
+            queuing_state.list_action,
+            None | Some(ListAction::DumpSpecs)
+        );
 
-        let listing = list_test_cases(
+        let mut listing = list_test_cases(
             deps,
             log.clone(),
             queuing_state,
@@ -257,6 +867,29 @@ where
             &artifact,
             &package_name,
         )?;
+        if queuing_state.list_action.is_none() {
+            // Now that we know which of the artifact's cases are ignored, the expected count may
+            // have gone down.
+            let expected = expected_job_count(
+                &queuing_state.test_listing.lock().unwrap(),
+                &queuing_state.filter,
+                queuing_state.case_list.as_ref(),
+            );
+            queuing_state
+                .expected_job_count
+                .store(expected, Ordering::Release);
+            let queued = queuing_state.jobs_queued.load(Ordering::Acquire);
+            ind.update_length(std::cmp::max(expected, queued));
+        }
+        if let Some(seed) = queuing_state.shuffle_seed {
+            shuffle_cases(&mut listing.cases, seed);
+        }
+        if queuing_state.order == TestOrder::SlowestFirst {
+            let test_listing = queuing_state.test_listing.lock().unwrap();
+            order_slowest_first(&mut listing.cases, |c| {
+                test_listing.last_duration(&package_name, &artifact, c)
+            });
+        }
 
         ind.update_enqueue_status(format!("generating artifacts for {package_name}"));
         slog::debug!(
@@ -265,7 +898,7 @@ where
             "package_name" => &package_name,
             "artifact" => ?artifact);
         let generated_artifacts = running_tests
-            .then(|| generate_artifacts(deps, &artifact, log.clone()))
+            .then(|| generate_artifacts(deps, queuing_state, &artifact, log.clone()))
             .transpose()?;
 
         Ok(Self {
@@ -278,9 +911,13 @@ where
             binary,
             generated_artifacts,
             ignored_cases: listing.ignored_cases,
+            locations: listing.locations,
             package_name,
             cases: listing.cases.into_iter(),
+            warmed_layers: HashMap::new(),
             timeout_override,
+            timeout_default,
+            pending_jobs,
         })
     }
 
@@ -306,98 +943,185 @@ where
     }
 
     fn format_case_str(&self, case: &str) -> String {
-        let mut s = self.package_name.to_string();
-        s += " ";
-
-        let artifact_name = &self.artifact.target.name;
-        if artifact_name != &self.package_name {
-            s += artifact_name;
-            s += " ";
-        }
-        s += case;
-        s
+        format_case_str(&self.package_name, &self.artifact.target.name, case)
     }
 
-    fn queue_job_from_case(&mut self, case: &str) -> Result<EnqueueResult> {
-        let case_str = self.format_case_str(case);
-        self.ind
-            .update_enqueue_status(format!("processing {case_str}"));
-        slog::debug!(self.log, "enqueuing test case"; "case" => &case_str);
-
-        if self.queuing_state.list_action.is_some() {
-            self.ind.println(case_str);
-            return Ok(EnqueueResult::Listed);
-        }
-
+    fn get_test_metadata(&self, case: &str) -> Result<TestMetadata> {
         let image_lookup = |image: &str| {
-            self.ind
-                .update_enqueue_status(format!("downloading image {image}"));
-            let (image, version) = image.split_once(':').unwrap_or((image, "latest"));
-            slog::debug!(
-                self.log, "getting container image";
-                "image" => &image,
-                "version" => &version,
-            );
-            self.deps.get_container_image(image, version)
+            self.queuing_state
+                .get_container_image(self.deps, &self.ind, &self.log, image)
         };
 
+        let previously_failed = self
+            .queuing_state
+            .test_listing
+            .lock()
+            .unwrap()
+            .previously_failed(&self.package_name, &self.artifact, case);
         let filter_context = pattern::Context {
             package: self.package_name.clone(),
             artifact: Some(pattern::Artifact::from_target(&self.artifact.target)),
-            case: Some(pattern::Case { name: case.into() }),
+            case: Some(pattern::Case {
+                name: case.into(),
+                previously_failed,
+            }),
         };
 
-        let test_metadata = self
-            .queuing_state
+        self.queuing_state
             .test_metadata
-            .get_metadata_for_test_with_env(&filter_context, image_lookup)?;
-        self.ind
-            .update_enqueue_status(format!("calculating layers for {case_str}"));
-        slog::debug!(&self.log, "calculating job layers"; "case" => &case_str);
-        let layers = self.calculate_job_layers(&test_metadata)?;
+            .get_metadata_for_test_with_env(&filter_context, image_lookup)
+    }
 
-        // N.B. Must do this before we enqueue the job, but after we know we can't fail
+    /// Add the layers of every case that isn't ignored, and remember them so they can be used when
+    /// the case's job is enqueued.
+    fn warm_layers(&mut self) -> Result<()> {
+        let cases: Vec<_> = self
+            .cases
+            .as_slice()
+            .iter()
+            .filter(|case| !self.ignored_cases.contains(*case))
+            .cloned()
+            .collect();
+        for case in cases {
+            let case_str = self.format_case_str(&case);
+            self.ind
+                .update_enqueue_status(format!("adding layers for {case_str}"));
+            slog::debug!(&self.log, "warming job layers"; "case" => &case_str);
+            let test_metadata = self.get_test_metadata(&case)?;
+            if test_metadata
+                .skip_if
+                .reason(&self.queuing_state.host)
+                .is_some()
+            {
+                continue;
+            }
+            let layers = self.calculate_job_layers(&test_metadata)?;
+            self.warmed_layers.insert(case, layers);
+        }
+        Ok(())
+    }
+
+    fn new_visitor(
+        &self,
+        case: &str,
+        test_metadata: &TestMetadata,
+    ) -> JobStatusVisitor<ProgressIndicatorT> {
+        JobStatusVisitor::new(
+            self.queuing_state.tracker.clone(),
+            CaseName::new(&self.package_name, &self.artifact.target.name, case),
+            self.locations.get(case).cloned(),
+            self.queuing_state.include_test_locations,
+            self.width,
+            self.ind.clone(),
+            test_metadata.retry.clone(),
+            self.queuing_state.retries,
+            test_metadata.allow_failure,
+            self.queuing_state.test_output_dir.clone(),
+            self.queuing_state.large_output_threshold,
+            self.queuing_state.report_time,
+            self.queuing_state.github_annotations,
+            self.queuing_state.exit_code_severities.clone(),
+            self.queuing_state.output_encoding,
+        )
+    }
+
+    /// Count a test that is about to be run or skipped as a job, making the progress bar longer if
+    /// needed.
+    fn count_job(&self) {
         let count = self
             .queuing_state
             .jobs_queued
             .fetch_add(1, Ordering::AcqRel);
         self.ind.update_length(std::cmp::max(
-            self.queuing_state.expected_job_count,
+            self.queuing_state
+                .expected_job_count
+                .load(Ordering::Acquire),
             count + 1,
         ));
+    }
 
-        let visitor = JobStatusVisitor::new(
-            self.queuing_state.tracker.clone(),
-            case_str.clone(),
-            self.width,
-            self.ind.clone(),
-        );
+    fn queue_job_from_case(&mut self, case: &str) -> Result<EnqueueResult> {
+        let case_str = self.format_case_str(case);
+        self.ind
+            .update_enqueue_status(format!("processing {case_str}"));
+        slog::debug!(self.log, "enqueuing test case"; "case" => &case_str);
+
+        if !matches!(
+            self.queuing_state.list_action,
+            None | Some(ListAction::DumpSpecs)
+        ) {
+            self.ind.println(case_str);
+            return Ok(EnqueueResult::Listed);
+        }
+
+        let test_metadata = self.get_test_metadata(case)?;
 
-        if self.ignored_cases.contains(case) {
-            visitor.job_ignored();
-            return Ok(EnqueueResult::Ignored);
+        // Tests that aren't going to be run don't need their layers built and uploaded.
+        if self.queuing_state.list_action.is_none() {
+            // Ignored cases aren't counted as jobs, so they don't make the progress bar any longer.
+            if self.ignored_cases.contains(case) {
+                self.new_visitor(case, &test_metadata).job_ignored();
+                return Ok(EnqueueResult::Ignored);
+            }
+            if let Some(reason) = test_metadata.skip_if.reason(&self.queuing_state.host) {
+                self.count_job();
+                self.new_visitor(case, &test_metadata).job_skipped(reason);
+                return Ok(EnqueueResult::Ignored);
+            }
         }
 
+        let layers = match self.warmed_layers.remove(case) {
+            Some(layers) => layers,
+            None => {
+                self.ind
+                    .update_enqueue_status(format!("calculating layers for {case_str}"));
+                slog::debug!(&self.log, "calculating job layers"; "case" => &case_str);
+                self.calculate_job_layers(&test_metadata)?
+            }
+        };
+
+        let binary_name = self.binary.file_name().unwrap().to_str().unwrap();
+        let binary_path = format!("/{binary_name}");
+        let mut arguments = vec!["--exact".into(), "--nocapture".into(), case.into()];
+
+        // If a runner is configured, it becomes the program and the test binary gets passed to it
+        // as its first argument after any of the runner's own arguments.
+        let program = match test_metadata.runner.split_first() {
+            Some((runner, runner_arguments)) => {
+                arguments = runner_arguments
+                    .iter()
+                    .cloned()
+                    .chain([binary_path])
+                    .chain(arguments)
+                    .collect();
+                runner.clone()
+            }
+            None => binary_path,
+        };
+        let timeout = test_metadata.timeout(self.timeout_override, self.timeout_default);
+        let visitor = self.new_visitor(case, &test_metadata);
+        let spec = job_spec(program.into(), arguments, layers, timeout, test_metadata);
+        spec.validate()
+            .with_context(|| format!("invalid test metadata for {case_str}"))?;
+
+        if let Some(ListAction::DumpSpecs) = self.queuing_state.list_action {
+            self.ind.println(serde_json::to_string(&spec)?);
+            return Ok(EnqueueResult::Listed);
+        }
+
+        // N.B. Must do this before we enqueue the job, but after we know we can't fail
+        self.count_job();
+
         self.ind
             .update_enqueue_status(format!("submitting job for {case_str}"));
         slog::debug!(&self.log, "submitting job"; "case" => &case_str);
-        let binary_name = self.binary.file_name().unwrap().to_str().unwrap();
-        self.deps.add_job(
-            JobSpec {
-                program: format!("/{binary_name}").into(),
-                arguments: vec!["--exact".into(), "--nocapture".into(), case.into()],
-                environment: test_metadata.environment(),
-                layers,
-                devices: test_metadata.devices,
-                mounts: test_metadata.mounts,
-                enable_loopback: test_metadata.enable_loopback,
-                enable_writable_file_system: test_metadata.enable_writable_file_system,
-                working_directory: test_metadata.working_directory,
-                user: test_metadata.user,
-                group: test_metadata.group,
-                timeout: self.timeout_override.unwrap_or(test_metadata.timeout),
-            },
-            move |cjid, result| visitor.job_finished(cjid, result),
+        submit_job(
+            self.deps,
+            spec,
+            visitor,
+            &self.pending_jobs,
+            self.queuing_state.job_submission_retries,
+            &self.log,
         )?;
 
         Ok(EnqueueResult::Enqueued {
@@ -418,6 +1142,16 @@ where
     }
 }
 
+/// Where [`JobQueuing`] gets the test binaries to enqueue tests from.
+enum ArtifactSource<'a, ProgressIndicatorT, MainAppDepsT: MainAppDeps> {
+    /// Artifacts are taken from cargo and listed one at a time, when they're needed.
+    Serial(MainAppDepsT::CargoTestArtifactStream),
+    /// A pool of threads takes artifacts from cargo as they're built and lists them. Each thread
+    /// takes the next artifact as soon as it's done with its last one, so a binary that is slow to
+    /// list only holds up its own thread. Artifacts come out in the order their listing finished.
+    Pool(mpsc::Receiver<Result<ArtifactQueuing<'a, ProgressIndicatorT, MainAppDepsT>>>),
+}
+
 /// Enqueues tests as jobs using the given deps.
 ///
 /// This object is like an iterator, it maintains a position in the test listing and enqueues the
@@ -430,9 +1164,22 @@ struct JobQueuing<'a, ProgressIndicatorT, MainAppDepsT: MainAppDeps> {
     ind: ProgressIndicatorT,
     wait_handle: Option<MainAppDepsT::CargoWaitHandle>,
     package_match: bool,
-    artifacts: Option<MainAppDepsT::CargoTestArtifactStream>,
+    artifacts: Option<ArtifactSource<'a, ProgressIndicatorT, MainAppDepsT>>,
     artifact_queuing: Option<ArtifactQueuing<'a, ProgressIndicatorT, MainAppDepsT>>,
+    /// Artifacts that the listing pool finished with while we were enqueuing from another one,
+    /// waiting to be queued from.
+    listed_artifacts: VecDeque<Result<ArtifactQueuing<'a, ProgressIndicatorT, MainAppDepsT>>>,
+    /// Artifacts whose layers were added up front, waiting to have their jobs enqueued.
+    warmed_artifacts: VecDeque<ArtifactQueuing<'a, ProgressIndicatorT, MainAppDepsT>>,
+    /// Whether we still need to add every test's layers before enqueuing the first job.
+    warm_pending: bool,
+    /// How many jobs have been enqueued from the current artifact since the last batch ended.
+    enqueued_in_batch: u32,
+    /// Whether a batch just ended, so the next call should yield.
+    yield_pending: bool,
     timeout_override: Option<Option<Timeout>>,
+    timeout_default: Option<Timeout>,
+    pending_jobs: Arc<PendingJobs<ProgressIndicatorT>>,
 }
 
 impl<'a, ProgressIndicatorT: ProgressIndicator, MainAppDepsT>
@@ -441,14 +1188,22 @@ where
     ProgressIndicatorT: ProgressIndicator,
     MainAppDepsT: MainAppDeps,
 {
-    fn new(
+    /// If more than one test binary can be listed at once, the listing pool's threads are spawned
+    /// in `scope`.
+    #[allow(clippy::too_many_arguments)]
+    fn new<'scope, 'env>(
         log: slog::Logger,
         queuing_state: &'a JobQueuingState,
         deps: &'a MainAppDepsT,
         width: usize,
         ind: ProgressIndicatorT,
         timeout_override: Option<Option<Timeout>>,
-    ) -> Result<Self> {
+        timeout_default: Option<Timeout>,
+        scope: &'scope thread::Scope<'scope, 'env>,
+    ) -> Result<Self>
+    where
+        'a: 'scope,
+    {
         let package_names: Vec<_> = queuing_state
             .packages
             .values()
@@ -458,7 +1213,7 @@ where
         let building_tests = !package_names.is_empty()
             && matches!(
                 queuing_state.list_action,
-                None | Some(ListAction::ListTests)
+                None | Some(ListAction::ListTests | ListAction::DumpSpecs)
             );
 
         let (wait_handle, artifacts) = building_tests
@@ -474,6 +1229,50 @@ where
             .transpose()?
             .unzip();
 
+        let pending_jobs = Arc::<PendingJobs<ProgressIndicatorT>>::default();
+        let list_threads = queuing_state.list_threads.get();
+        let artifacts = artifacts.map(|artifacts| {
+            if list_threads == 1 {
+                return ArtifactSource::Serial(artifacts);
+            }
+            let artifacts = Arc::new(Mutex::new(artifacts));
+            let (sender, receiver) = mpsc::channel();
+            for _ in 0..list_threads {
+                let artifacts = artifacts.clone();
+                let sender = sender.clone();
+                let log = log.clone();
+                let ind = ind.clone();
+                let pending_jobs = pending_jobs.clone();
+                scope.spawn(move || loop {
+                    // Only one thread waits on cargo at a time. The rest are listing, or waiting
+                    // for their turn to take the next artifact.
+                    let Some(artifact) = artifacts.lock().unwrap().next() else {
+                        break;
+                    };
+                    slog::debug!(log, "got artifact"; "artifact" => ?artifact);
+                    let res = artifact.and_then(|artifact| {
+                        ArtifactQueuing::new(
+                            log.clone(),
+                            queuing_state,
+                            deps,
+                            width,
+                            ind.clone(),
+                            artifact,
+                            timeout_override,
+                            timeout_default,
+                            pending_jobs.clone(),
+                        )
+                    });
+                    // Stop after an error, or once we've been dropped.
+                    let failed = res.is_err();
+                    if sender.send(res).is_err() || failed {
+                        break;
+                    }
+                });
+            }
+            ArtifactSource::Pool(receiver)
+        });
+
         Ok(Self {
             log,
             queuing_state,
@@ -483,41 +1282,96 @@ where
             package_match: false,
             artifacts,
             artifact_queuing: None,
+            listed_artifacts: VecDeque::new(),
+            warmed_artifacts: VecDeque::new(),
+            warm_pending: queuing_state.affinity_cache_warm && queuing_state.list_action.is_none(),
+            enqueued_in_batch: 0,
+            yield_pending: false,
             wait_handle,
             timeout_override,
+            timeout_default,
+            pending_jobs,
         })
     }
 
-    fn start_queuing_from_artifact(&mut self) -> Result<bool> {
+    /// Get the next artifact from cargo and list its tests. If more than one test binary can be
+    /// listed at once, this is the next artifact the listing pool finishes with.
+    fn queuing_from_next_artifact(
+        &mut self,
+    ) -> Result<Option<ArtifactQueuing<'a, ProgressIndicatorT, MainAppDepsT>>> {
+        if let Some(artifact_queuing) = self.listed_artifacts.pop_front() {
+            return artifact_queuing.map(Some);
+        }
+
         self.ind.update_enqueue_status("building artifacts...");
 
-        slog::debug!(self.log, "getting artifact from cargo");
-        let Some(ref mut artifacts) = self.artifacts else {
-            return Ok(false);
-        };
-        let Some(artifact) = artifacts.next() else {
-            return Ok(false);
-        };
-        let artifact = artifact?;
+        slog::debug!(self.log, "getting artifacts from cargo");
+        match &mut self.artifacts {
+            None => Ok(None),
+            Some(ArtifactSource::Serial(artifacts)) => {
+                let Some(artifact) = artifacts.next().transpose()? else {
+                    return Ok(None);
+                };
+                slog::debug!(self.log, "got artifact"; "artifact" => ?artifact);
+                ArtifactQueuing::new(
+                    self.log.clone(),
+                    self.queuing_state,
+                    self.deps,
+                    self.width,
+                    self.ind.clone(),
+                    artifact,
+                    self.timeout_override,
+                    self.timeout_default,
+                    self.pending_jobs.clone(),
+                )
+                .map(Some)
+            }
+            // The pool's threads all exit once cargo is done, which closes the channel.
+            Some(ArtifactSource::Pool(listed)) => listed.recv().ok().transpose(),
+        }
+    }
 
-        slog::debug!(self.log, "got artifact"; "artifact" => ?artifact);
-        let package_name = &self
-            .queuing_state
-            .packages
-            .get(&artifact.package_id)
-            .expect("artifact for unknown package")
-            .name;
+    /// Build every test binary and add the layers of every test that will be run, before any jobs
+    /// are enqueued. This way, the jobs don't have to wait on layers being uploaded once they start
+    /// being enqueued.
+    fn warm_cache(&mut self) -> Result<()> {
+        slog::debug!(self.log, "adding layers before enqueuing jobs");
+        while let Some(mut artifact_queuing) = self.queuing_from_next_artifact()? {
+            artifact_queuing.warm_layers()?;
+            self.warmed_artifacts.push_back(artifact_queuing);
+        }
+        Ok(())
+    }
 
-        self.artifact_queuing = Some(ArtifactQueuing::new(
-            self.log.clone(),
-            self.queuing_state,
-            self.deps,
-            self.width,
-            self.ind.clone(),
-            artifact,
-            package_name.into(),
-            self.timeout_override,
-        )?);
+    /// At the end of a batch, if another test binary is already waiting to have its tests
+    /// enqueued, put the current one behind it. This way, every binary that's ready has some of its
+    /// tests in front of the scheduler early, instead of waiting for one large binary to be done.
+    fn rotate_artifacts(&mut self) {
+        if let Some(ArtifactSource::Pool(listed)) = &self.artifacts {
+            self.listed_artifacts.extend(listed.try_iter());
+        }
+        let current = self.artifact_queuing.take().unwrap();
+        if !self.warmed_artifacts.is_empty() {
+            self.warmed_artifacts.push_back(current);
+        } else if !self.listed_artifacts.is_empty() {
+            self.listed_artifacts.push_back(Ok(current));
+        } else {
+            self.artifact_queuing = Some(current);
+            return;
+        }
+        slog::debug!(self.log, "moving on to the next test binary after a batch");
+    }
+
+    fn start_queuing_from_artifact(&mut self) -> Result<bool> {
+        let artifact_queuing = match self.warmed_artifacts.pop_front() {
+            Some(artifact_queuing) => artifact_queuing,
+            None => match self.queuing_from_next_artifact()? {
+                Some(artifact_queuing) => artifact_queuing,
+                None => return Ok(false),
+            },
+        };
+        self.artifact_queuing = Some(artifact_queuing);
+        self.enqueued_in_batch = 0;
 
         Ok(true)
     }
@@ -537,8 +1391,16 @@ where
     /// Returns an `EnqueueResult` describing what happened. Meant to be called it returns
     /// `EnqueueResult::Done`
     fn enqueue_one(&mut self) -> Result<EnqueueResult> {
+        if std::mem::take(&mut self.yield_pending) {
+            return Ok(EnqueueResult::Yielded);
+        }
+
         slog::debug!(self.log, "enqueuing a job");
 
+        if std::mem::take(&mut self.warm_pending) {
+            self.warm_cache()?;
+        }
+
         if self.artifact_queuing.is_none() && !self.start_queuing_from_artifact()? {
             self.finish()?;
             return Ok(EnqueueResult::Done);
@@ -551,6 +1413,18 @@ where
             return self.enqueue_one();
         }
 
+        if let (EnqueueResult::Enqueued { .. }, Some(batch_size)) =
+            (&res, self.queuing_state.enqueue_batch_size)
+        {
+            self.enqueued_in_batch += 1;
+            if self.enqueued_in_batch == batch_size.get() {
+                slog::debug!(self.log, "yielding after a batch"; "batch_size" => batch_size.get());
+                self.enqueued_in_batch = 0;
+                self.yield_pending = true;
+                self.rotate_artifacts();
+            }
+        }
+
         Ok(res)
     }
 }
@@ -572,18 +1446,26 @@ pub trait MainAppDeps: Sync {
 
     fn get_job_state_counts(&self) -> Result<JobStateCounts>;
 
+    fn get_worker_capabilities(&self) -> Result<WorkerCapabilitiesMap>;
+
     fn get_container_image(&self, name: &str, tag: &str) -> Result<ImageConfig>;
 
+    /// Add a job. `running` is called with the job's id in the client once it starts running, and
+    /// `handler` is called with its result.
     fn add_job(
         &self,
         spec: JobSpec,
-        handler: impl FnOnce(ClientJobId, JobOutcomeResult) + Send + Sync + 'static,
+        running: impl FnOnce(ClientJobId) + Send + Sync + 'static,
+        handler: impl FnOnce(Result<(ClientJobId, JobOutcomeResult)>) + Send + Sync + 'static,
     ) -> Result<()>;
 
+    /// Cancel a job that has started running. Its handler won't be called.
+    fn cancel_job(&self, cjid: ClientJobId) -> Result<()>;
+
     fn wait_for_outstanding_jobs(&self) -> Result<()>;
 
     type CargoWaitHandle: Wait;
-    type CargoTestArtifactStream: Iterator<Item = Result<CargoArtifact>>;
+    type CargoTestArtifactStream: Iterator<Item = Result<CargoArtifact>> + Send;
 
     fn run_cargo_test(
         &self,
@@ -595,10 +1477,108 @@ pub trait MainAppDeps: Sync {
     ) -> Result<(Self::CargoWaitHandle, Self::CargoTestArtifactStream)>;
 
     fn get_cases_from_binary(&self, binary: &Path, filter: &Option<String>) -> Result<Vec<String>>;
+
+    fn get_case_locations_from_binary(
+        &self,
+        binary: &Path,
+    ) -> Result<HashMap<String, TestCaseLocation>>;
+}
+
+/// Print the capabilities of every worker, in worker-id order, for `--list-capabilities`.
+pub fn list_capabilities(deps: &impl MainAppDeps, mut out: impl io::Write) -> Result<()> {
+    let capabilities = deps.get_worker_capabilities()?;
+    if capabilities.is_empty() {
+        writeln!(out, "no workers connected")?;
+    }
+    for (id, capabilities) in capabilities.into_iter().collect::<BTreeMap<_, _>>() {
+        let devices = Vec::from_iter(capabilities.devices.iter().map(|device| device.name()));
+        writeln!(out, "worker {id}:")?;
+        writeln!(out, "    devices: {}", list_or_none(&devices))?;
+        writeln!(
+            out,
+            "    overlayfs: {}",
+            if capabilities.overlayfs { "yes" } else { "no" }
+        )?;
+        writeln!(
+            out,
+            "    cgroup controllers: {}",
+            list_or_none(&capabilities.cgroup_controllers)
+        )?;
+        writeln!(out, "    cpus: {}", capabilities.cpus)?;
+        writeln!(
+            out,
+            "    memory: {}",
+            ByteSize(capabilities.memory).to_string_as(true)
+        )?;
+    }
+    Ok(())
+}
+
+/// Print, for every case in the cached test listing, whether it would be selected and which of the
+/// include and exclude patterns decided it, for `--explain-selection`. Nothing is built, so only
+/// the cases found by an earlier run are explained.
+pub fn explain_selection(
+    cache_directory: &impl AsRef<Path>,
+    include_filter: &[String],
+    exclude_filter: &[String],
+    package_scope: &PackageScope,
+    workspace_packages: &[&CargoPackage],
+    mut out: impl io::Write,
+) -> Result<()> {
+    let filter = pattern::compile_filter_terms(include_filter, exclude_filter)?;
+    let mut test_listing = load_test_listing(cache_directory.as_ref())?.unwrap_or_default();
+    test_listing.retain_packages(workspace_packages);
+    for (package_name, artifact, case, selection) in test_listing.explain_selection(&filter) {
+        if let PackageScope::Packages(packages) = package_scope {
+            if !packages.iter().any(|p| p == package_name) {
+                continue;
+            }
+        }
+        writeln!(
+            out,
+            "{}: {selection}",
+            format_case_str(package_name, &artifact.name, case)
+        )?;
+    }
+    Ok(())
+}
+
+fn list_or_none(items: &[impl AsRef<str>]) -> String {
+    if items.is_empty() {
+        "none".into()
+    } else {
+        Vec::from_iter(items.iter().map(AsRef::as_ref)).join(", ")
+    }
+}
+
+/// Remembers the result of adding each distinct layer, so that a layer shared by many tests is
+/// only hashed and sent to the client once per run.
+#[derive(Default)]
+struct LayerCache {
+    layers: Mutex<HashMap<Layer, (Sha256Digest, ArtifactType)>>,
+}
+
+impl LayerCache {
+    /// Return the cached result for `layer`, calling `add` to compute it if we haven't seen the
+    /// layer before. Errors aren't cached.
+    fn get_or_add(
+        &self,
+        layer: Layer,
+        add: impl FnOnce(Layer) -> Result<(Sha256Digest, ArtifactType)>,
+    ) -> Result<(Sha256Digest, ArtifactType)> {
+        if let Some(res) = self.layers.lock().unwrap().get(&layer) {
+            return Ok(res.clone());
+        }
+        // Don't hold the lock while adding the layer, since it can take a while.
+        let res = add(layer.clone())?;
+        self.layers.lock().unwrap().insert(layer, res.clone());
+        Ok(res)
+    }
 }
 
 pub struct DefaultMainAppDeps {
     client: Client,
+    layer_cache: LayerCache,
 }
 
 impl DefaultMainAppDeps {
@@ -611,6 +1591,9 @@ impl DefaultMainAppDeps {
         cache_size: CacheSize,
         inline_limit: InlineLimit,
         slots: Slots,
+        hashing_threads: HashingThreads,
+        dispatcher_threads: DispatcherThreads,
+        canonicalize_layer_order: bool,
         log: slog::Logger,
     ) -> Result<Self> {
         slog::debug!(
@@ -619,6 +1602,9 @@ impl DefaultMainAppDeps {
             "cache_size" => ?cache_size,
             "inline_limit" => ?inline_limit,
             "slots" => ?slots,
+            "hashing_threads" => ?hashing_threads,
+            "dispatcher_threads" => ?dispatcher_threads,
+            "canonicalize_layer_order" => canonicalize_layer_order,
         );
         let client = Client::new(
             bg_proc,
@@ -628,15 +1614,25 @@ impl DefaultMainAppDeps {
             cache_size,
             inline_limit,
             slots,
+            hashing_threads,
+            dispatcher_threads,
+            None, // local_worker_idle_timeout
+            canonicalize_layer_order,
+            None, // keep_alive_interval
+            None, // request_timeout
             log,
         )?;
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            layer_cache: LayerCache::default(),
+        })
     }
 }
 
 impl MainAppDeps for DefaultMainAppDeps {
     fn add_layer(&self, layer: Layer) -> Result<(Sha256Digest, ArtifactType)> {
-        self.client.add_layer(layer)
+        self.layer_cache
+            .get_or_add(layer, |layer| self.client.add_layer(layer))
     }
 
     fn get_artifact_upload_progress(&self) -> Result<Vec<ArtifactUploadProgress>> {
@@ -647,6 +1643,10 @@ impl MainAppDeps for DefaultMainAppDeps {
         self.client.get_job_state_counts()
     }
 
+    fn get_worker_capabilities(&self) -> Result<WorkerCapabilitiesMap> {
+        self.client.get_worker_capabilities()
+    }
+
     fn get_container_image(&self, name: &str, tag: &str) -> Result<ImageConfig> {
         let image = self.client.get_container_image(name, tag)?;
         Ok(ImageConfig {
@@ -659,9 +1659,15 @@ impl MainAppDeps for DefaultMainAppDeps {
     fn add_job(
         &self,
         spec: JobSpec,
-        handler: impl FnOnce(ClientJobId, JobOutcomeResult) + Send + Sync + 'static,
+        running: impl FnOnce(ClientJobId) + Send + Sync + 'static,
+        handler: impl FnOnce(Result<(ClientJobId, JobOutcomeResult)>) + Send + Sync + 'static,
     ) -> Result<()> {
-        self.client.add_job(spec, handler)
+        self.client
+            .add_job_with_running_handler(spec, running, handler)
+    }
+
+    fn cancel_job(&self, cjid: ClientJobId) -> Result<()> {
+        self.client.cancel_job(cjid)
     }
 
     fn wait_for_outstanding_jobs(&self) -> Result<()> {
@@ -691,6 +1697,97 @@ impl MainAppDeps for DefaultMainAppDeps {
     fn get_cases_from_binary(&self, binary: &Path, filter: &Option<String>) -> Result<Vec<String>> {
         cargo::get_cases_from_binary(binary, filter)
     }
+
+    fn get_case_locations_from_binary(
+        &self,
+        binary: &Path,
+    ) -> Result<HashMap<String, TestCaseLocation>> {
+        cargo::get_case_locations_from_binary(binary)
+    }
+}
+
+/// The options that change what a run does or how its results are reported. The defaults are
+/// the same as the configuration values' defaults.
+pub struct MainAppOptions {
+    /// Should terminal color codes be written to `stderr` or not.
+    pub stderr_color: bool,
+    /// The encoding to use when writing out the test listing.
+    pub test_listing_format: TestListingFormat,
+    /// Should each test's result be followed by where it is defined.
+    pub include_test_locations: bool,
+    /// Should each test's result be printed in libtest's `--report-time` format.
+    pub report_time: bool,
+    /// Should each failure also be printed as a GitHub Actions annotation.
+    pub github_annotations: bool,
+    /// Should the tests listed in the summary be grouped by package.
+    pub summary_by_package: bool,
+    /// If some, a JSON summary of the results is written here, or to stdout if the path is "-".
+    pub json_summary: Option<PathBuf>,
+    /// If some, a JUnit XML report of the results is written here.
+    pub junit: Option<PathBuf>,
+    /// If some, each test's captured stdout and stderr are written here.
+    pub test_output_dir: Option<PathBuf>,
+    /// If some, captured output larger than this is written to a file.
+    pub large_output_threshold: Option<LargeOutputThreshold>,
+    /// If some, only this many failed tests have their output printed.
+    pub failure_output_limit: Option<u32>,
+    /// If some, tests that haven't finished this long after they started running are reported as
+    /// lost and canceled.
+    pub lost_test_deadline: Option<Duration>,
+    /// If some, overrides how tests that exit with particular codes are categorized.
+    pub exit_code_severities: Option<ExitCodeSeverities>,
+    /// How captured output that isn't valid UTF-8 is printed.
+    pub output_encoding: OutputEncoding,
+    /// How many more times to run a failed test whose retry policy doesn't say otherwise.
+    pub retries: u32,
+    /// How many more times to try submitting a job if the client returns an error.
+    pub job_submission_retries: u32,
+    /// If some, `EnqueueResult::Yielded` is returned after this many jobs have been enqueued in a
+    /// row from one test binary, and the next batch is taken from another test binary that is
+    /// ready.
+    pub enqueue_batch_size: Option<NonZeroU32>,
+    /// Should every test's layers be added before any jobs are enqueued.
+    pub affinity_cache_warm: bool,
+    /// If some, each test binary's tests are enqueued in an order shuffled with this seed.
+    pub shuffle_seed: Option<u64>,
+    /// How many test binaries to list the tests of at once.
+    pub list_threads: NonZeroUsize,
+    /// The order each test binary's tests are enqueued in.
+    pub order: TestOrder,
+    pub feature_selection_options: FeatureSelectionOptions,
+    pub compilation_options: CompilationOptions,
+    pub manifest_options: ManifestOptions,
+}
+
+impl Default for MainAppOptions {
+    fn default() -> Self {
+        Self {
+            stderr_color: false,
+            test_listing_format: Default::default(),
+            include_test_locations: false,
+            report_time: false,
+            github_annotations: false,
+            summary_by_package: false,
+            json_summary: None,
+            junit: None,
+            test_output_dir: None,
+            large_output_threshold: None,
+            failure_output_limit: None,
+            lost_test_deadline: None,
+            exit_code_severities: None,
+            output_encoding: Default::default(),
+            retries: 0,
+            job_submission_retries: 0,
+            enqueue_batch_size: None,
+            affinity_cache_warm: false,
+            shuffle_seed: None,
+            list_threads: NonZeroUsize::MIN,
+            order: Default::default(),
+            feature_selection_options: Default::default(),
+            compilation_options: Default::default(),
+            manifest_options: Default::default(),
+        }
+    }
 }
 
 /// A collection of objects that are used to run the MainApp. This is useful as a separate object
@@ -699,6 +1796,7 @@ pub struct MainAppState<MainAppDepsT> {
     deps: MainAppDepsT,
     queuing_state: JobQueuingState,
     cache_dir: PathBuf,
+    test_listing_format: TestListingFormat,
     logging_output: LoggingOutput,
     log: slog::Logger,
 }
@@ -710,10 +1808,13 @@ impl<MainAppDepsT> MainAppState<MainAppDepsT> {
     /// `cargo`: the command to run when invoking cargo
     /// `include_filter`: tests which match any of the patterns in this filter are run
     /// `exclude_filter`: tests which match any of the patterns in this filter are not run
+    /// `tests_from`: if some, a file of exact test case names, one per line, to run instead of
+    /// using the filters
+    /// `package_scope`: which of the workspace's packages tests are run from
     /// `list_action`: if some, tests aren't run, instead tests or other things are listed
-    /// `stderr_color`: should terminal color codes be written to `stderr` or not
     /// `workspace_root`: the path to the root of the workspace
     /// `workspace_packages`: a listing of the packages in the workspace
+    /// `options`: everything else that changes what the run does or how it's reported
     /// `broker_addr`: the network address of the broker which we connect to
     /// `client_driver`: an object which drives the background work of the `Client`
     #[allow(clippy::too_many_arguments)]
@@ -721,32 +1822,49 @@ impl<MainAppDepsT> MainAppState<MainAppDepsT> {
         deps: MainAppDepsT,
         include_filter: Vec<String>,
         exclude_filter: Vec<String>,
+        tests_from: Option<&Path>,
+        package_scope: PackageScope,
         list_action: Option<ListAction>,
-        stderr_color: bool,
         workspace_root: &impl AsRef<Path>,
         workspace_packages: &[&CargoPackage],
         cache_directory: &impl AsRef<Path>,
         target_directory: &impl AsRef<Path>,
-        feature_selection_options: FeatureSelectionOptions,
-        compilation_options: CompilationOptions,
-        manifest_options: ManifestOptions,
+        options: MainAppOptions,
         logging_output: LoggingOutput,
         log: slog::Logger,
     ) -> Result<Self> {
+        let test_listing_format = options.test_listing_format;
         slog::debug!(
             log, "creating app state";
             "include_filter" => ?include_filter,
             "exclude_filter" => ?exclude_filter,
+            "tests_from" => ?tests_from,
+            "package_scope" => ?package_scope,
             "list_action" => ?list_action,
         );
 
         let test_metadata = AllMetadata::load(log.clone(), workspace_root)?;
-        let mut test_listing =
-            load_test_listing(&cache_directory.as_ref().join(LAST_TEST_LISTING_NAME))?
-                .unwrap_or_default();
+        let mut test_listing = load_test_listing(cache_directory.as_ref())?.unwrap_or_default();
         test_listing.retain_packages(workspace_packages);
 
-        let filter = pattern::compile_filter(&include_filter, &exclude_filter)?;
+        let case_list = tests_from.map(CaseList::from_file).transpose()?;
+        let filter = if case_list.is_some() {
+            pattern::compile_filter(&["all".into()], &[])?
+        } else {
+            pattern::compile_filter(&include_filter, &exclude_filter)?
+        };
+        let filter = match package_scope {
+            PackageScope::Workspace => filter,
+            PackageScope::Packages(packages) => {
+                if let Some(unknown) = packages
+                    .iter()
+                    .find(|&name| !workspace_packages.iter().any(|p| &p.name == name))
+                {
+                    return Err(anyhow!("package `{unknown}` not found in workspace"));
+                }
+                pattern::restrict_to_packages(filter, &packages)
+            }
+        };
         let selected_packages: BTreeMap<_, _> = workspace_packages
             .iter()
             .filter(|p| filter_package(p, &filter))
@@ -763,16 +1881,15 @@ impl<MainAppDepsT> MainAppState<MainAppDepsT> {
             queuing_state: JobQueuingState::new(
                 selected_packages,
                 filter,
-                stderr_color,
+                case_list,
                 test_metadata,
                 test_listing,
                 list_action,
                 target_directory,
-                feature_selection_options,
-                compilation_options,
-                manifest_options,
+                options,
             )?,
             cache_dir: cache_directory.as_ref().to_owned(),
+            test_listing_format,
             logging_output,
             log,
         })
@@ -791,6 +1908,10 @@ pub enum EnqueueResult {
     Done,
     /// No job was enqueued, we listed the test case instead
     Listed,
+    /// No job was enqueued, we have enqueued a whole batch of jobs from the current test binary and
+    /// are handing control back before enqueuing more. The next batch comes from the next test
+    /// binary whose tests have already been listed, if there is one
+    Yielded,
 }
 
 impl EnqueueResult {
@@ -805,6 +1926,19 @@ impl EnqueueResult {
     }
 }
 
+/// The result of running a [`MainApp`] to completion with [`MainApp::run_to_completion`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CompletedRun {
+    /// The `ExitCode` returned by [`MainApp::finish`]
+    pub exit_code: ExitCode,
+    /// The number of tests enqueued as jobs
+    pub enqueued: u64,
+    /// The number of tests ignored instead of being enqueued
+    pub ignored: u64,
+    /// The number of tests listed instead of being enqueued
+    pub listed: u64,
+}
+
 /// This is the public API for the MainApp
 ///
 /// N.B. This API is a trait only for type-erasure purposes
@@ -818,6 +1952,46 @@ pub trait MainApp {
 
     /// Waits for all outstanding jobs to finish, displays a summary, and obtains an `ExitCode`
     fn finish(&mut self) -> Result<ExitCode>;
+
+    /// Enqueue every test, drain, and finish, stopping at the first error. This is what `main`
+    /// does, but it's here so other programs don't have to repeat it.
+    fn run_to_completion(&mut self) -> Result<CompletedRun> {
+        let (mut enqueued, mut ignored, mut listed) = (0, 0, 0);
+        loop {
+            match self.enqueue_one()? {
+                EnqueueResult::Enqueued { .. } => enqueued += 1,
+                EnqueueResult::Ignored => ignored += 1,
+                EnqueueResult::Listed => listed += 1,
+                EnqueueResult::Yielded => {}
+                EnqueueResult::Done => break,
+            }
+        }
+        self.drain()?;
+        let exit_code = self.finish()?;
+        Ok(CompletedRun {
+            exit_code,
+            enqueued,
+            ignored,
+            listed,
+        })
+    }
+}
+
+/// Write the JSON summary of the results to `path`, or to `term` if `path` is "-".
+fn write_json_summary(
+    path: &Path,
+    tracker: &JobStatusTracker,
+    duration: Duration,
+    term: &impl TermLike,
+) -> Result<()> {
+    let json = serde_json::to_string(&tracker.json_summary(duration))?;
+    if path == Path::new("-") {
+        term.write_line(&json)?;
+        term.flush()?;
+    } else {
+        Fs::new().write(path, json + "\n")?;
+    }
+    Ok(())
 }
 
 struct MainAppImpl<'state, TermT, ProgressIndicatorT, ProgressDriverT, MainAppDepsT: MainAppDeps> {
@@ -826,6 +2000,7 @@ struct MainAppImpl<'state, TermT, ProgressIndicatorT, ProgressDriverT, MainAppDe
     prog_driver: ProgressDriverT,
     prog: ProgressIndicatorT,
     term: TermT,
+    setup_done: bool,
 }
 
 impl<'state, TermT, ProgressIndicatorT, ProgressDriverT, MainAppDepsT: MainAppDeps>
@@ -844,6 +2019,26 @@ impl<'state, TermT, ProgressIndicatorT, ProgressDriverT, MainAppDepsT: MainAppDe
             prog_driver,
             prog,
             term,
+            setup_done: false,
+        }
+    }
+
+    /// Run the setup or teardown job from the test metadata, if there is one. Nothing is run when
+    /// we are only listing things.
+    fn run_global_job(&self, name: &str, job: &Option<GlobalJob>) -> Result<()>
+    where
+        ProgressIndicatorT: ProgressIndicator,
+    {
+        match job {
+            Some(job) if self.state.queuing_state.list_action.is_none() => run_global_job(
+                &self.state.deps,
+                &self.state.queuing_state,
+                &self.prog,
+                &self.queuing.log,
+                name,
+                job,
+            ),
+            _ => Ok(()),
         }
     }
 }
@@ -857,6 +2052,10 @@ where
     MainAppDepsT: MainAppDeps,
 {
     fn enqueue_one(&mut self) -> Result<EnqueueResult> {
+        if !self.setup_done {
+            self.run_global_job("setup", &self.state.queuing_state.test_metadata.setup)?;
+            self.setup_done = true;
+        }
         self.queuing.enqueue_one()
     }
 
@@ -864,29 +2063,116 @@ where
         slog::debug!(self.queuing.log, "draining");
         self.prog
             .update_length(self.state.queuing_state.jobs_queued.load(Ordering::Acquire));
+        self.queuing.pending_jobs.start_draining(self.prog.clone());
         self.prog.done_queuing_jobs();
         self.prog_driver.stop()?;
         Ok(())
     }
 
     fn finish(&mut self) -> Result<ExitCode> {
-        slog::debug!(self.queuing.log, "waiting for outstanding jobs");
-        self.state.deps.wait_for_outstanding_jobs()?;
+        loop {
+            slog::debug!(self.queuing.log, "waiting for outstanding jobs");
+            let pending_jobs = &self.queuing.pending_jobs;
+            let deps = &self.state.deps;
+            let log = &self.queuing.log;
+            // The client knows when jobs are done, and tells us if it goes away while we're
+            // waiting. Jobs that get lost are canceled, so the client stops waiting for them too.
+            pending_jobs.start_watching();
+            std::thread::scope(|scope| {
+                if let Some(lost_deadline) = self.state.queuing_state.lost_test_deadline {
+                    scope.spawn(move || {
+                        pending_jobs.watch_for_lost_jobs(lost_deadline, |cjid| {
+                            if let Err(err) = deps.cancel_job(cjid) {
+                                slog::warn!(log, "error canceling lost job"; "error" => %err);
+                            }
+                        })
+                    });
+                }
+                let res = deps.wait_for_outstanding_jobs();
+                pending_jobs.stop_watching();
+                res
+            })?;
+            // The client may have told us that all jobs are done before the last handlers ran.
+            let retries = pending_jobs.wait();
+            if retries.is_empty() {
+                break;
+            }
+            slog::debug!(self.queuing.log, "retrying jobs"; "count" => retries.len());
+            // Each retry is another job, so the bar grows instead of moving backwards.
+            let count = retries.len() as u64;
+            let queued = self
+                .state
+                .queuing_state
+                .jobs_queued
+                .fetch_add(count, Ordering::AcqRel);
+            self.prog.update_length(queued + count);
+            for (spec, visitor) in retries {
+                submit_job(
+                    &self.state.deps,
+                    spec,
+                    visitor,
+                    &self.queuing.pending_jobs,
+                    self.state.queuing_state.job_submission_retries,
+                    &self.queuing.log,
+                )?;
+            }
+        }
+
+        // Even if the teardown job fails, we still want to print the summary of the tests.
+        let teardown_result =
+            self.run_global_job("teardown", &self.state.queuing_state.test_metadata.teardown);
+
+        if let Some(case_list) = &self.state.queuing_state.case_list {
+            for name in case_list.unknown() {
+                self.prog.println(format!("unknown test case: {name}"));
+            }
+        }
         self.prog.finished()?;
 
         if self.state.queuing_state.list_action.is_none() {
             let width = self.term.width() as usize;
-            self.state
-                .queuing_state
-                .tracker
-                .print_summary(width, self.term.clone())?;
+            self.state.queuing_state.tracker.print_summary(
+                width,
+                self.state.queuing_state.summary_by_package,
+                self.term.clone(),
+            )?;
+            if let Some(seed) = self.state.queuing_state.shuffle_seed {
+                self.term
+                    .write_line(&format!("Tests were shuffled with seed {seed}"))?;
+            }
+            if let Some(path) = &self.state.queuing_state.json_summary {
+                write_json_summary(
+                    path,
+                    &self.state.queuing_state.tracker,
+                    self.state.queuing_state.started.elapsed(),
+                    &self.term,
+                )?;
+            }
+            if let Some(path) = &self.state.queuing_state.junit {
+                self.state.queuing_state.tracker.write_junit_xml(path)?;
+            }
         }
 
+        let mut test_listing = self.state.queuing_state.test_listing.lock().unwrap();
+        let failed = self.state.queuing_state.tracker.failed_by_case();
+        test_listing.record_results(|package_name, artifact, case| {
+            failed
+                .get(&format_case_str(package_name, &artifact.name, case))
+                .copied()
+        });
+        let durations = self.state.queuing_state.tracker.duration_by_case();
+        test_listing.record_durations(|package_name, artifact, case| {
+            durations
+                .get(&format_case_str(package_name, &artifact.name, case))
+                .copied()
+        });
         write_test_listing(
-            &self.state.cache_dir.join(LAST_TEST_LISTING_NAME),
-            &self.state.queuing_state.test_listing.lock().unwrap(),
+            &self.state.cache_dir,
+            &test_listing,
+            self.state.test_listing_format,
         )?;
 
+        teardown_result?;
         Ok(self.state.queuing_state.tracker.exit_code())
     }
 }
@@ -980,12 +2266,14 @@ impl Logger {
     }
 }
 
-fn new_helper<'state, 'scope, ProgressIndicatorT, TermT, MainAppDepsT>(
+fn new_helper<'state, 'scope, 'env, ProgressIndicatorT, TermT, MainAppDepsT>(
     state: &'state MainAppState<MainAppDepsT>,
     prog_factory: impl FnOnce(TermT) -> ProgressIndicatorT,
     term: TermT,
     mut prog_driver: impl ProgressDriver<'scope> + 'scope,
+    scope: &'scope thread::Scope<'scope, 'env>,
     timeout_override: Option<Option<Timeout>>,
+    timeout_default: Option<Timeout>,
 ) -> Result<Box<dyn MainApp + 'scope>>
 where
     ProgressIndicatorT: ProgressIndicator,
@@ -994,10 +2282,23 @@ where
     'state: 'scope,
 {
     let width = term.width() as usize;
+    // Print the seed before anything runs, so that it isn't lost if the run hangs or is
+    // interrupted. It's printed again with the summary.
+    if let (Some(seed), None) = (
+        state.queuing_state.shuffle_seed,
+        &state.queuing_state.list_action,
+    ) {
+        term.write_line(&format!("Shuffling tests with seed {seed}"))?;
+    }
     let prog = prog_factory(term.clone());
 
     prog_driver.drive(&state.deps, prog.clone());
-    prog.update_length(state.queuing_state.expected_job_count);
+    prog.update_length(
+        state
+            .queuing_state
+            .expected_job_count
+            .load(Ordering::Acquire),
+    );
 
     state
         .logging_output
@@ -1008,7 +2309,7 @@ where
         Some(ListAction::ListPackages) => list_packages(&prog, &state.queuing_state.packages),
 
         Some(ListAction::ListBinaries) => list_binaries(&prog, &state.queuing_state.packages),
-        _ => {}
+        Some(ListAction::ListTests | ListAction::DumpSpecs) | None => {}
     }
 
     let queuing = JobQueuing::new(
@@ -1018,6 +2319,8 @@ where
         width,
         prog.clone(),
         timeout_override,
+        timeout_default,
+        scope,
     )?;
     Ok(Box::new(MainAppImpl::new(
         state,
@@ -1035,13 +2338,17 @@ where
 /// `quiet`: indicates whether quiet mode should be used or not
 /// `term`: represents the terminal
 /// `driver`: drives the background work needed for updating the progress bars
-pub fn main_app_new<'state, 'scope, TermT, MainAppDepsT>(
+/// `scope`: the scope that threads used to list test binaries are spawned in
+#[allow(clippy::too_many_arguments)]
+pub fn main_app_new<'state, 'scope, 'env, TermT, MainAppDepsT>(
     state: &'state MainAppState<MainAppDepsT>,
     stdout_tty: bool,
     quiet: Quiet,
     term: TermT,
     driver: impl ProgressDriver<'scope> + 'scope,
+    scope: &'scope thread::Scope<'scope, 'env>,
     timeout_override: Option<Option<Timeout>>,
+    timeout_default: Option<Timeout>,
 ) -> Result<Box<dyn MainApp + 'scope>>
 where
     TermT: TermLike + Clone + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
@@ -1055,7 +2362,9 @@ where
                 TestListingProgress::new,
                 term,
                 driver,
+                scope,
                 timeout_override,
+                timeout_default,
             )?)
         } else {
             Ok(new_helper(
@@ -1063,7 +2372,9 @@ where
                 TestListingProgressNoSpinner::new,
                 term,
                 driver,
+                scope,
                 timeout_override,
+                timeout_default,
             )?)
         };
     }
@@ -1074,28 +2385,36 @@ where
             QuietProgressBar::new,
             term,
             driver,
+            scope,
             timeout_override,
+            timeout_default,
         )?),
         (true, false) => Ok(new_helper(
             state,
             MultipleProgressBars::new,
             term,
             driver,
+            scope,
             timeout_override,
+            timeout_default,
         )?),
         (false, true) => Ok(new_helper(
             state,
             QuietNoBar::new,
             term,
             driver,
+            scope,
             timeout_override,
+            timeout_default,
         )?),
         (false, false) => Ok(new_helper(
             state,
             NoBar::new,
             term,
             driver,
+            scope,
             timeout_override,
+            timeout_default,
         )?),
     }
 }
@@ -1,16 +1,27 @@
 use anyhow::{bail, Context as _, Result};
 use cargo_maelstrom::{
-    cargo::CargoBuildError, config::Config, main_app_new,
-    metadata::maybe_write_default_test_metadata, progress::DefaultProgressDriver,
-    DefaultMainAppDeps, ListAction, Logger, LoggingOutput, MainAppState,
+    cargo::CargoBuildError,
+    config::{Config, ShuffleSeed},
+    doctor::{self, DoctorPaths},
+    explain_selection, list_capabilities, main_app_new,
+    metadata::maybe_write_default_test_metadata,
+    progress::DefaultProgressDriver,
+    DefaultMainAppDeps, ListAction, Logger, LoggingOutput, MainAppOptions, MainAppState,
+    PackageScope,
 };
-use cargo_metadata::Metadata as CargoMetadata;
-use clap::{command, Args};
+use cargo_metadata::{workspace_default_members_is_missing, Metadata as CargoMetadata};
+use clap::{command, Args, Subcommand};
 use console::Term;
 use maelstrom_base::Timeout;
 use maelstrom_client::ClientBgProcess;
-use maelstrom_util::{fs::Fs, process::ExitCode};
-use std::{env, io::IsTerminal as _, process};
+use maelstrom_util::{config::common::CacheRoot, fs::Fs, process::ExitCode};
+use std::{
+    env,
+    io::{self, IsTerminal as _},
+    path::PathBuf,
+    process,
+    time::Duration,
+};
 
 #[derive(Args)]
 #[command(next_help_heading = "Test Selection Options")]
@@ -32,11 +43,46 @@ struct ExtraCommandLineOptions {
     )]
     exclude: Vec<String>,
 
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["include", "exclude"],
+        help = "Only include the tests whose exact case names are listed, one per line, in the \
+            given file. Names that don't match any test are reported."
+    )]
+    tests_from: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "package",
+        help = "Run tests from every package in the workspace. This is the default when run \
+            from the root of a virtual workspace."
+    )]
+    workspace: bool,
+
+    #[arg(
+        long,
+        short = 'p',
+        value_name = "PACKAGE",
+        help = "Only run tests from the given package. Can be specified multiple times. \
+            Defaults to the package in the current directory."
+    )]
+    package: Vec<String>,
+
     #[command(flatten)]
     list: ListOptions,
 
     #[command(flatten)]
     test_metadata: TestMetadataOptions,
+
+    #[command(subcommand)]
+    command: Option<ExtraCommand>,
+}
+
+#[derive(Subcommand)]
+enum ExtraCommand {
+    /// Check that this machine is set up to run tests with Maelstrom, then exit.
+    Doctor,
 }
 
 #[derive(Args)]
@@ -64,6 +110,28 @@ struct ListOptions {
             have been run."
     )]
     packages: bool,
+
+    #[arg(
+        long = "dump-specs",
+        help = "Instead of running tests, print the job spec each test would have been run \
+            with, as one line of JSON per test. May require building test binaries."
+    )]
+    dump_specs: bool,
+
+    #[arg(
+        long = "list-capabilities",
+        help = "Instead of running tests, print the capabilities of the connected workers: \
+            the devices they provide, whether they support overlayfs, and their cgroup \
+            controllers."
+    )]
+    capabilities: bool,
+
+    #[arg(
+        long = "explain-selection",
+        help = "Instead of running tests, print each test in the cached test listing, whether it \
+            would be run, and which include or exclude filter decided that. Nothing is built."
+    )]
+    explain_selection: bool,
 }
 
 #[derive(Args)]
@@ -108,11 +176,13 @@ pub fn main() -> Result<ExitCode> {
         extra_options.list.tests,
         extra_options.list.binaries,
         extra_options.list.packages,
+        extra_options.list.dump_specs,
     ) {
-        (true, _, _) => Some(ListAction::ListTests),
-        (_, true, _) => Some(ListAction::ListBinaries),
-        (_, _, true) => Some(ListAction::ListPackages),
-        (_, _, _) => None,
+        (true, _, _, _) => Some(ListAction::ListTests),
+        (_, true, _, _) => Some(ListAction::ListBinaries),
+        (_, _, true, _) => Some(ListAction::ListPackages),
+        (_, _, _, true) => Some(ListAction::DumpSpecs),
+        (_, _, _, _) => None,
     };
 
     let output = process::Command::new("cargo")
@@ -131,13 +201,59 @@ pub fn main() -> Result<ExitCode> {
     let cargo_metadata: CargoMetadata =
         serde_json::from_slice(&output.stdout).context("parsing cargo metadata")?;
 
+    let package_scope = if extra_options.workspace {
+        PackageScope::Workspace
+    } else if !extra_options.package.is_empty() {
+        PackageScope::Packages(extra_options.package)
+    } else if let Some(package) = cargo_metadata.root_package() {
+        PackageScope::Packages(vec![package.name.clone()])
+    } else if workspace_default_members_is_missing(&cargo_metadata.workspace_default_members) {
+        PackageScope::Workspace
+    } else {
+        PackageScope::Packages(
+            cargo_metadata
+                .workspace_default_packages()
+                .into_iter()
+                .map(|p| p.name.clone())
+                .collect(),
+        )
+    };
+
+    let target_dir = &cargo_metadata.target_directory;
+    let cache_dir = config
+        .cache_dir
+        .map(CacheRoot::into_inner)
+        .unwrap_or_else(|| target_dir.join("maelstrom").into_std_path_buf());
+
+    if let Some(ExtraCommand::Doctor) = extra_options.command {
+        let results = doctor::run_checks(&DoctorPaths::new(&cache_dir), config.broker);
+        for result in &results {
+            println!("{result}");
+        }
+        return Ok(if results.iter().all(doctor::CheckResult::passed) {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        });
+    }
+
     if extra_options.test_metadata.init {
         maybe_write_default_test_metadata(&cargo_metadata.workspace_root)?;
         return Ok(ExitCode::SUCCESS);
     }
 
-    let target_dir = &cargo_metadata.target_directory;
-    let cache_dir = target_dir.join("maelstrom");
+    if extra_options.list.explain_selection {
+        explain_selection(
+            &cache_dir,
+            &extra_options.include,
+            &extra_options.exclude,
+            &package_scope,
+            &cargo_metadata.workspace_packages(),
+            io::stdout().lock(),
+        )?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
     fs.create_dir_all(&cache_dir)?;
 
     let deps = DefaultMainAppDeps::new(
@@ -148,22 +264,56 @@ pub fn main() -> Result<ExitCode> {
         config.cache_size,
         config.inline_limit,
         config.slots,
+        config.hashing_threads,
+        config.dispatcher_threads,
+        config.canonicalize_layer_order,
         log.clone(),
     )?;
 
+    if extra_options.list.capabilities {
+        list_capabilities(&deps, io::stdout().lock())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
     let state = MainAppState::new(
         deps,
         extra_options.include,
         extra_options.exclude,
+        extra_options.tests_from.as_deref(),
+        package_scope,
         list_action,
-        std::io::stderr().is_terminal(),
         &cargo_metadata.workspace_root,
         &cargo_metadata.workspace_packages(),
         &cache_dir,
         target_dir,
-        config.cargo_feature_selection_options,
-        config.cargo_compilation_options,
-        config.cargo_manifest_options,
+        MainAppOptions {
+            stderr_color: std::io::stderr().is_terminal(),
+            test_listing_format: config.test_listing_format,
+            include_test_locations: config.include_test_locations,
+            report_time: config.report_time,
+            github_annotations: config.github_annotations,
+            summary_by_package: config.summary_by_package,
+            json_summary: config.json_summary,
+            junit: config.junit,
+            test_output_dir: config.test_output_dir,
+            large_output_threshold: config.large_output_threshold,
+            failure_output_limit: config.failure_output_limit,
+            lost_test_deadline: config
+                .lost_test_deadline
+                .map(|secs| Duration::from_secs(secs.into())),
+            exit_code_severities: config.exit_code_severities,
+            output_encoding: config.output_encoding,
+            retries: config.retries,
+            job_submission_retries: config.job_submission_retries,
+            enqueue_batch_size: config.enqueue_batch_size,
+            affinity_cache_warm: config.affinity_cache_warm,
+            shuffle_seed: config.shuffle.map(ShuffleSeed::resolve),
+            list_threads: config.list_threads,
+            order: config.order,
+            feature_selection_options: config.cargo_feature_selection_options,
+            compilation_options: config.cargo_compilation_options,
+            manifest_options: config.cargo_manifest_options,
+        },
         logging_output,
         log,
     )?;
@@ -176,11 +326,11 @@ pub fn main() -> Result<ExitCode> {
             config.quiet,
             Term::buffered_stdout(),
             DefaultProgressDriver::new(scope),
+            scope,
             config.timeout.map(Timeout::new),
+            config.test_timeout_default.and_then(Timeout::new),
         )?;
-        while !app.enqueue_one()?.is_done() {}
-        app.drain()?;
-        app.finish()
+        Ok(app.run_to_completion()?.exit_code)
     });
     drop(state);
     maybe_print_build_error(res)
@@ -4,13 +4,14 @@ use crate::pattern;
 use anyhow::{Context as _, Error, Result};
 use directive::TestDirective;
 use enumset::enum_set;
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use maelstrom_base::{
-    EnumSet, GroupId, JobDevice, JobMount, JobMountFsType, Timeout, UserId, Utf8PathBuf,
+    EnumSet, GroupId, JobDevice, JobMount, JobMountFsType, JobStatus, Timeout, UserId, Utf8PathBuf,
 };
 use maelstrom_client::spec::{self, substitute, ImageConfig, ImageOption, Layer, PossiblyImage};
 use maelstrom_util::{fs::Fs, template::TemplateVars};
-use serde::Deserialize;
-use std::{collections::BTreeMap, path::Path, str};
+use serde::{de, Deserialize, Deserializer};
+use std::{cmp::Ordering, collections::BTreeMap, fmt, path::Path, str};
 
 /// This file is what we write out for the user when `--init` is provided. It should contain the
 /// same data as `AllMetadata::default()` but it contains nice formatting, comments, and examples.
@@ -39,6 +40,14 @@ pub fn maybe_write_default_test_metadata(workspace_root: &impl AsRef<Path>) -> R
 #[serde(deny_unknown_fields)]
 pub struct AllMetadata {
     directives: Vec<TestDirective>,
+    /// Run once before any tests are run. If it fails, no tests are run.
+    pub setup: Option<GlobalJob>,
+    /// Run once after all of the tests have finished.
+    pub teardown: Option<GlobalJob>,
+    /// Glob patterns for shared libraries to leave out of the layer containing a test binary's
+    /// shared library dependencies.
+    #[serde(default)]
+    exclude_shared_libraries: Vec<String>,
 }
 
 impl Default for AllMetadata {
@@ -52,10 +61,18 @@ impl Default for AllMetadata {
             include_shared_libraries: Some(true),
             enable_loopback: Some(false),
             enable_writable_file_system: Some(false),
+            enable_init_process: None,
+            scratch_dir: None,
+            cpus: None,
+            memory: None,
             working_directory: Some(PossiblyImage::Explicit(Utf8PathBuf::from("/"))),
             user: Some(UserId::from(0)),
             group: Some(GroupId::from(0)),
             timeout: None,
+            retry: None,
+            allow_failure: None,
+            skip_if: None,
+            runner: None,
             // Create directories and files for mounting special file-systems and device files
             layers: Some(PossiblyImage::Explicit(vec![Layer::Stubs {
                 stubs: vec![
@@ -99,6 +116,9 @@ impl Default for AllMetadata {
         };
         Self {
             directives: vec![single_directive],
+            setup: None,
+            teardown: None,
+            exclude_shared_libraries: vec![],
         }
     }
 }
@@ -112,18 +132,182 @@ fn all_metadata_default_matches_default_file() {
     assert_eq!(parsed_default_file, AllMetadata::default());
 }
 
+/// When to run a test again after it fails.
+///
+/// Only failures with one of the given exit codes or signals are retried. This is meant for
+/// failures that are known to come from flaky infrastructure, so that real test failures aren't
+/// hidden by retrying them.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// How many more times the test may be run after the first attempt.
+    pub max_retries: u32,
+    #[serde(default)]
+    pub exit_codes: Vec<u8>,
+    #[serde(default)]
+    pub signals: Vec<u8>,
+}
+
+impl RetryPolicy {
+    /// Return whether a test that ended with `status` should be retried, if it has any retries
+    /// left.
+    pub fn matches(&self, status: &JobStatus) -> bool {
+        match status {
+            JobStatus::Exited(0) => false,
+            JobStatus::Exited(code) => self.exit_codes.contains(code),
+            JobStatus::Signaled(signo) => self.signals.contains(signo),
+        }
+    }
+}
+
+/// A kernel version like "5.10" or "6.1.12". Any suffix after the numeric components, like the
+/// "-generic" in "5.15.0-91-generic", is ignored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KernelVersion(Vec<u32>);
+
+impl KernelVersion {
+    fn parse(s: &str) -> Option<Self> {
+        let numeric = s
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()
+            .unwrap_or_default();
+        let components = numeric
+            .split('.')
+            .filter(|c| !c.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+        (!components.is_empty()).then_some(Self(components))
+    }
+}
+
+impl Ord for KernelVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl PartialOrd for KernelVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let components: Vec<_> = self.0.iter().map(u32::to_string).collect();
+        f.write_str(&components.join("."))
+    }
+}
+
+impl<'de> Deserialize<'de> for KernelVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Self::parse(&s).ok_or_else(|| de::Error::custom(format!("invalid kernel version {s:?}")))
+    }
+}
+
+/// The parts of the machine `cargo-maelstrom` is running on that a [`SkipCondition`] can check.
+#[derive(Clone, Debug)]
+pub struct HostEnvironment {
+    pub arch: String,
+    pub kernel_version: Option<KernelVersion>,
+}
+
+impl HostEnvironment {
+    pub fn current() -> Self {
+        let kernel_version = Fs::new()
+            .read_to_string_if_exists("/proc/sys/kernel/osrelease")
+            .ok()
+            .flatten()
+            .and_then(|release| KernelVersion::parse(release.trim()));
+        Self {
+            arch: std::env::consts::ARCH.into(),
+            kernel_version,
+        }
+    }
+}
+
+/// Conditions under which a test is skipped instead of being run. The test is skipped if any of
+/// the conditions hold.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SkipCondition {
+    /// Skip on any of these architectures, as named by `std::env::consts::ARCH`.
+    #[serde(default)]
+    pub arch: Vec<String>,
+    /// Skip if the kernel is older than this version.
+    pub kernel_older_than: Option<KernelVersion>,
+    /// Skip if any of these paths, usually devices, don't exist.
+    #[serde(default)]
+    pub missing_paths: Vec<Utf8PathBuf>,
+}
+
+impl SkipCondition {
+    /// Return why the test should be skipped on `host`, or `None` if it should be run.
+    pub fn reason(&self, host: &HostEnvironment) -> Option<String> {
+        if self.arch.contains(&host.arch) {
+            return Some(format!("skipped on {}", host.arch));
+        }
+        if let (Some(minimum), Some(version)) = (&self.kernel_older_than, &host.kernel_version) {
+            if version < minimum {
+                return Some(format!("skipped on kernel {version}, older than {minimum}"));
+            }
+        }
+        let fs = Fs::new();
+        self.missing_paths
+            .iter()
+            .find(|path| !fs.exists(path))
+            .map(|path| format!("skipped because {path} doesn't exist"))
+    }
+}
+
+/// A job that isn't for any particular test. These are used for the setup and teardown jobs, which
+/// are run before and after all of the tests.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GlobalJob {
+    pub program: Utf8PathBuf,
+    #[serde(default)]
+    pub arguments: Vec<String>,
+    #[serde(default)]
+    layers: Vec<Layer>,
+    #[serde(default)]
+    environment: BTreeMap<String, String>,
+}
+
+impl GlobalJob {
+    /// The job's layers and environment are added to the metadata from the directives as if they
+    /// came from one last directive.
+    fn directive(&self) -> TestDirective {
+        TestDirective {
+            added_layers: self.layers.clone(),
+            added_environment: self.environment.clone(),
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct TestMetadata {
     include_shared_libraries: Option<bool>,
     pub enable_loopback: bool,
     pub enable_writable_file_system: bool,
+    pub enable_init_process: bool,
+    scratch_dir: Option<Utf8PathBuf>,
+    pub cpus: u32,
+    pub memory: u64,
     pub working_directory: Utf8PathBuf,
     pub user: UserId,
     pub group: GroupId,
-    pub timeout: Option<Timeout>,
+    timeout: Option<Option<Timeout>>,
+    pub retry: RetryPolicy,
+    pub allow_failure: bool,
+    pub skip_if: SkipCondition,
+    pub runner: Vec<String>,
     pub layers: Vec<Layer>,
     environment: BTreeMap<String, String>,
-    pub mounts: Vec<JobMount>,
+    mounts: Vec<JobMount>,
     pub devices: EnumSet<JobDevice>,
 }
 
@@ -133,10 +317,18 @@ impl Default for TestMetadata {
             include_shared_libraries: Default::default(),
             enable_loopback: Default::default(),
             enable_writable_file_system: Default::default(),
+            enable_init_process: Default::default(),
+            scratch_dir: None,
+            cpus: Default::default(),
+            memory: Default::default(),
             working_directory: Utf8PathBuf::from("/"),
             user: UserId::from(0),
             group: GroupId::from(0),
             timeout: None,
+            retry: Default::default(),
+            allow_failure: Default::default(),
+            skip_if: Default::default(),
+            runner: Default::default(),
             layers: Default::default(),
             environment: Default::default(),
             mounts: Default::default(),
@@ -158,6 +350,19 @@ impl TestMetadata {
         }
     }
 
+    /// Return the timeout to use for the test.
+    ///
+    /// A timeout override given on the command line replaces whatever else was specified. Failing
+    /// that, we use the timeout from the directives, which may explicitly be no timeout at all.
+    /// Only if no directive set a timeout do we fall back to the default timeout.
+    pub fn timeout(
+        &self,
+        timeout_override: Option<Option<Timeout>>,
+        timeout_default: Option<Timeout>,
+    ) -> Option<Timeout> {
+        timeout_override.unwrap_or(self.timeout.unwrap_or(timeout_default))
+    }
+
     pub fn environment(&self) -> Vec<String> {
         self.environment
             .iter()
@@ -165,6 +370,17 @@ impl TestMetadata {
             .collect()
     }
 
+    /// The job's mounts. A scratch directory is just a tmpfs mounted after the others. Each job
+    /// gets its own mount namespace, so each gets its own tmpfs, which is gone once the job is.
+    pub fn mounts(&self) -> Vec<JobMount> {
+        let mut mounts = self.mounts.clone();
+        mounts.extend(self.scratch_dir.iter().map(|mount_point| JobMount {
+            fs_type: JobMountFsType::Tmp,
+            mount_point: mount_point.clone(),
+        }));
+        mounts
+    }
+
     fn try_fold(
         mut self,
         &TestDirective {
@@ -173,9 +389,17 @@ impl TestMetadata {
             include_shared_libraries,
             enable_loopback,
             enable_writable_file_system,
+            enable_init_process,
+            ref scratch_dir,
+            cpus,
+            memory,
             user,
             group,
             timeout,
+            ref retry,
+            allow_failure,
+            ref skip_if,
+            ref runner,
             ref layers,
             ref added_layers,
             ref mounts,
@@ -195,9 +419,19 @@ impl TestMetadata {
         self.enable_loopback = enable_loopback.unwrap_or(self.enable_loopback);
         self.enable_writable_file_system =
             enable_writable_file_system.unwrap_or(self.enable_writable_file_system);
+        self.enable_init_process = enable_init_process.unwrap_or(self.enable_init_process);
+        self.scratch_dir = scratch_dir.clone().or(self.scratch_dir);
+        self.cpus = cpus.unwrap_or(self.cpus);
+        self.memory = memory.unwrap_or(self.memory);
         self.user = user.unwrap_or(self.user);
         self.group = group.unwrap_or(self.group);
-        self.timeout = timeout.unwrap_or(self.timeout);
+        self.timeout = timeout.or(self.timeout);
+        self.retry = retry.as_ref().map_or(self.retry, RetryPolicy::clone);
+        self.allow_failure = allow_failure.unwrap_or(self.allow_failure);
+        self.skip_if = skip_if.as_ref().map_or(self.skip_if, SkipCondition::clone);
+        self.runner = runner
+            .as_ref()
+            .map_or(self.runner, |runner| runner.to_vec());
 
         match layers {
             Some(PossiblyImage::Explicit(layers)) => {
@@ -276,9 +510,26 @@ impl AllMetadata {
                 }
             }
         }
+        for job in self.setup.iter_mut().chain(&mut self.teardown) {
+            for layer in &mut job.layers {
+                layer.replace_template_vars(vars)?;
+            }
+        }
         Ok(())
     }
 
+    /// Compile the patterns for shared libraries that shouldn't be included in a test binary's
+    /// layer of shared library dependencies.
+    pub fn shared_library_exclusions(&self) -> Result<GlobSet> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &self.exclude_shared_libraries {
+            builder.add(Glob::new(pattern).with_context(|| {
+                format!("parsing exclude_shared_libraries pattern {pattern:?}")
+            })?);
+        }
+        Ok(builder.build()?)
+    }
+
     fn get_metadata_for_test(
         &self,
         context: &pattern::Context,
@@ -307,6 +558,34 @@ impl AllMetadata {
         self.get_metadata_for_test(context, spec::std_env_lookup, image_lookup)
     }
 
+    /// A setup or teardown job isn't for any test, so only the directives without a filter apply
+    /// to it. Its own layers and environment are added last.
+    fn get_metadata_for_global_job(
+        &self,
+        job: &GlobalJob,
+        env_lookup: impl Fn(&str) -> Result<Option<String>>,
+        mut image_lookup: impl FnMut(&str) -> Result<ImageConfig>,
+    ) -> Result<TestMetadata> {
+        let mut metadata = TestMetadata::default();
+        for directive in self
+            .directives
+            .iter()
+            .filter(|directive| directive.filter.is_none())
+            .chain([&job.directive()])
+        {
+            metadata = metadata.try_fold(directive, &env_lookup, &mut image_lookup)?;
+        }
+        Ok(metadata)
+    }
+
+    pub fn get_metadata_for_global_job_with_env(
+        &self,
+        job: &GlobalJob,
+        image_lookup: impl FnMut(&str) -> Result<ImageConfig>,
+    ) -> Result<TestMetadata> {
+        self.get_metadata_for_global_job(job, spec::std_env_lookup, image_lookup)
+    }
+
     fn from_str(contents: &str) -> Result<Self> {
         Ok(toml::from_str(contents)?)
     }
@@ -338,7 +617,10 @@ mod test {
                 name: package.into(),
                 kind: pattern::ArtifactKind::Library,
             }),
-            case: Some(pattern::Case { name: test.into() }),
+            case: Some(pattern::Case {
+                name: test.into(),
+                previously_failed: false,
+            }),
         }
     }
 
@@ -353,9 +635,14 @@ mod test {
     #[test]
     fn default() {
         assert_eq!(
-            AllMetadata { directives: vec![] }
-                .get_metadata_for_test(&test_ctx("mod", "foo"), empty_env, no_containers)
-                .unwrap(),
+            AllMetadata {
+                directives: vec![],
+                setup: None,
+                teardown: None,
+                exclude_shared_libraries: vec![],
+            }
+            .get_metadata_for_test(&test_ctx("mod", "foo"), empty_env, no_containers)
+            .unwrap(),
             TestMetadata::default(),
         );
     }
@@ -482,6 +769,139 @@ mod test {
         );
     }
 
+    #[test]
+    fn enable_init_process() {
+        let all = AllMetadata::from_str(
+            r#"
+            [[directives]]
+            filter = "package.equals(package1)"
+            enable_init_process = true
+
+            [[directives]]
+            filter = "package.equals(package1) && name.equals(test1)"
+            enable_init_process = false
+            "#,
+        )
+        .unwrap();
+        assert!(
+            !all.get_metadata_for_test(&test_ctx("package1", "test1"), empty_env, no_containers)
+                .unwrap()
+                .enable_init_process
+        );
+        assert!(
+            all.get_metadata_for_test(&test_ctx("package1", "test2"), empty_env, no_containers)
+                .unwrap()
+                .enable_init_process
+        );
+        assert!(
+            !all.get_metadata_for_test(&test_ctx("package2", "test1"), empty_env, no_containers)
+                .unwrap()
+                .enable_init_process
+        );
+    }
+
+    #[test]
+    fn scratch_dir() {
+        let all = AllMetadata::from_str(
+            r#"
+            [[directives]]
+            filter = "package.equals(package1)"
+            scratch_dir = "/tmp/scratch1"
+
+            [[directives]]
+            filter = "package.equals(package1) && name.equals(test1)"
+            scratch_dir = "/tmp/scratch2"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            all.get_metadata_for_test(&test_ctx("package1", "test1"), empty_env, no_containers)
+                .unwrap()
+                .scratch_dir,
+            Some(utf8_path_buf!("/tmp/scratch2"))
+        );
+        assert_eq!(
+            all.get_metadata_for_test(&test_ctx("package1", "test2"), empty_env, no_containers)
+                .unwrap()
+                .scratch_dir,
+            Some(utf8_path_buf!("/tmp/scratch1"))
+        );
+        assert_eq!(
+            all.get_metadata_for_test(&test_ctx("package2", "test1"), empty_env, no_containers)
+                .unwrap()
+                .scratch_dir,
+            None
+        );
+
+        // The scratch directory is added to the job's mounts as a tmpfs.
+        assert_eq!(
+            all.get_metadata_for_test(&test_ctx("package1", "test1"), empty_env, no_containers)
+                .unwrap()
+                .mounts(),
+            vec![JobMount {
+                fs_type: JobMountFsType::Tmp,
+                mount_point: utf8_path_buf!("/tmp/scratch2"),
+            }]
+        );
+    }
+
+    #[test]
+    fn resource_requests() {
+        let all = AllMetadata::from_str(
+            r#"
+            [[directives]]
+            filter = "package.equals(package1)"
+            cpus = 2
+            memory = 1000
+
+            [[directives]]
+            filter = "package.equals(package1) && name.equals(test1)"
+            cpus = 4
+            "#,
+        )
+        .unwrap();
+        let resources = |package, test| {
+            let metadata = all
+                .get_metadata_for_test(&test_ctx(package, test), empty_env, no_containers)
+                .unwrap();
+            (metadata.cpus, metadata.memory)
+        };
+        assert_eq!(resources("package1", "test1"), (4, 1000));
+        assert_eq!(resources("package1", "test2"), (2, 1000));
+        assert_eq!(resources("package2", "test1"), (0, 0));
+    }
+
+    #[test]
+    fn allow_failure() {
+        let all = AllMetadata::from_str(
+            r#"
+            [[directives]]
+            filter = "package.equals(package1)"
+            allow_failure = true
+
+            [[directives]]
+            filter = "package.equals(package1) && name.equals(test1)"
+            allow_failure = false
+            "#,
+        )
+        .unwrap();
+        assert!(
+            !all.get_metadata_for_test(&test_ctx("package1", "test1"), empty_env, no_containers)
+                .unwrap()
+                .allow_failure
+        );
+        assert!(
+            all.get_metadata_for_test(&test_ctx("package1", "test2"), empty_env, no_containers)
+                .unwrap()
+                .allow_failure
+        );
+        assert!(
+            !all.get_metadata_for_test(&test_ctx("package2", "test1"), empty_env, no_containers)
+                .unwrap()
+                .allow_failure
+        );
+    }
+
     #[test]
     fn working_directory() {
         let image_lookup = |name: &_| match name {
@@ -618,23 +1038,374 @@ mod test {
         assert_eq!(
             all.get_metadata_for_test(&test_ctx("package1", "test1"), empty_env, no_containers)
                 .unwrap()
-                .timeout,
+                .timeout(None, None),
             None,
         );
         assert_eq!(
             all.get_metadata_for_test(&test_ctx("package1", "test2"), empty_env, no_containers)
                 .unwrap()
-                .timeout,
+                .timeout(None, None),
             Timeout::new(100),
         );
         assert_eq!(
             all.get_metadata_for_test(&test_ctx("package2", "test1"), empty_env, no_containers)
                 .unwrap()
-                .timeout,
+                .timeout(None, None),
             None,
         );
     }
 
+    #[test]
+    fn timeout_precedence() {
+        let all = AllMetadata::from_str(
+            r#"
+            [[directives]]
+            filter = "package.equals(package1)"
+            timeout = 100
+
+            [[directives]]
+            filter = "package.equals(package1) && name.equals(test1)"
+            timeout = 0
+            "#,
+        )
+        .unwrap();
+        let timeout = |package, test, timeout_override, timeout_default| {
+            all.get_metadata_for_test(&test_ctx(package, test), empty_env, no_containers)
+                .unwrap()
+                .timeout(timeout_override, timeout_default)
+        };
+
+        // The default only applies when no directive sets a timeout.
+        assert_eq!(timeout("package1", "test1", None, Timeout::new(5)), None);
+        assert_eq!(
+            timeout("package1", "test2", None, Timeout::new(5)),
+            Timeout::new(100)
+        );
+        assert_eq!(
+            timeout("package2", "test1", None, Timeout::new(5)),
+            Timeout::new(5)
+        );
+
+        // The override replaces everything, including the default.
+        for (package, test) in [
+            ("package1", "test1"),
+            ("package1", "test2"),
+            ("package2", "test1"),
+        ] {
+            assert_eq!(
+                timeout(package, test, Some(Timeout::new(7)), Timeout::new(5)),
+                Timeout::new(7)
+            );
+            assert_eq!(timeout(package, test, Some(None), Timeout::new(5)), None);
+        }
+    }
+
+    #[test]
+    fn skip_if() {
+        let all = AllMetadata::from_str(
+            r#"
+            [[directives]]
+            filter = "package.equals(package1)"
+            skip_if = { arch = ["riscv64"], kernel_older_than = "5.10" }
+
+            [[directives]]
+            filter = "package.equals(package1) && name.equals(test1)"
+            skip_if = { missing_paths = ["/dev/does-not-exist"] }
+            "#,
+        )
+        .unwrap();
+        let skip_reason = |package, test, arch: &str, kernel_version| {
+            let host = HostEnvironment {
+                arch: arch.into(),
+                kernel_version: KernelVersion::parse(kernel_version),
+            };
+            all.get_metadata_for_test(&test_ctx(package, test), empty_env, no_containers)
+                .unwrap()
+                .skip_if
+                .reason(&host)
+        };
+
+        assert_eq!(
+            skip_reason("package1", "test2", "riscv64", "6.1.0"),
+            Some("skipped on riscv64".into())
+        );
+        assert_eq!(
+            skip_reason("package1", "test2", "x86_64", "5.4.0-150-generic"),
+            Some("skipped on kernel 5.4.0, older than 5.10".into())
+        );
+        assert_eq!(skip_reason("package1", "test2", "x86_64", "5.10.3"), None);
+        assert_eq!(
+            skip_reason("package1", "test1", "riscv64", "5.4.0"),
+            Some("skipped because /dev/does-not-exist doesn't exist".into())
+        );
+        assert_eq!(skip_reason("package2", "test1", "riscv64", "5.4.0"), None);
+    }
+
+    #[test]
+    fn skip_if_invalid_kernel_version() {
+        assert_toml_error(
+            AllMetadata::from_str(
+                r#"
+                [[directives]]
+                skip_if = { kernel_older_than = "new" }
+                "#,
+            )
+            .unwrap_err(),
+            "invalid kernel version \"new\"",
+        );
+    }
+
+    #[test]
+    fn retry() {
+        let all = AllMetadata::from_str(
+            r#"
+            [[directives]]
+            filter = "package.equals(package1)"
+            retry = { max_retries = 2, exit_codes = [3], signals = [9] }
+
+            [[directives]]
+            filter = "package.equals(package1) && name.equals(test1)"
+            retry = { max_retries = 1 }
+            "#,
+        )
+        .unwrap();
+        let retry = |package, test| {
+            all.get_metadata_for_test(&test_ctx(package, test), empty_env, no_containers)
+                .unwrap()
+                .retry
+        };
+        assert_eq!(
+            retry("package1", "test1"),
+            RetryPolicy {
+                max_retries: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            retry("package1", "test2"),
+            RetryPolicy {
+                max_retries: 2,
+                exit_codes: vec![3],
+                signals: vec![9],
+            }
+        );
+        assert_eq!(retry("package2", "test1"), RetryPolicy::default());
+    }
+
+    #[test]
+    fn retry_unknown_field() {
+        assert_toml_error(
+            AllMetadata::from_str(
+                r#"
+                [[directives]]
+                retry = { max_retries = 2, exit_code = [3] }
+                "#,
+            )
+            .unwrap_err(),
+            "unknown field `exit_code`",
+        );
+    }
+
+    #[test]
+    fn retry_policy_matches() {
+        let policy = RetryPolicy {
+            max_retries: 1,
+            exit_codes: vec![3],
+            signals: vec![9],
+        };
+        assert!(policy.matches(&JobStatus::Exited(3)));
+        assert!(policy.matches(&JobStatus::Signaled(9)));
+        assert!(!policy.matches(&JobStatus::Exited(1)));
+        assert!(!policy.matches(&JobStatus::Exited(9)));
+        assert!(!policy.matches(&JobStatus::Signaled(3)));
+        assert!(!policy.matches(&JobStatus::Signaled(6)));
+    }
+
+    #[test]
+    fn setup_and_teardown() {
+        let all = AllMetadata::from_str(
+            r#"
+            [[directives]]
+            include_shared_libraries = true
+
+            [setup]
+            program = "/bin/setup"
+            arguments = ["--start"]
+            layers = [{ tar = "setup.tar" }]
+            environment = { FOO = "bar" }
+
+            [teardown]
+            program = "/bin/teardown"
+            layers = [{ tar = "teardown.tar" }]
+            "#,
+        )
+        .unwrap();
+        let setup = all.setup.as_ref().unwrap();
+        assert_eq!(setup.program, "/bin/setup");
+        assert_eq!(setup.arguments, string_vec!["--start"]);
+        let metadata = all
+            .get_metadata_for_global_job(setup, empty_env, no_containers)
+            .unwrap();
+        assert_eq!(metadata.layers, vec![tar_layer!("setup.tar")]);
+        assert_eq!(metadata.environment(), string_vec!["FOO=bar"]);
+        let teardown = all.teardown.as_ref().unwrap();
+        assert_eq!(teardown.program, "/bin/teardown");
+        assert_eq!(teardown.arguments, Vec::<String>::new());
+        let metadata = all
+            .get_metadata_for_global_job(teardown, empty_env, no_containers)
+            .unwrap();
+        assert_eq!(metadata.layers, vec![tar_layer!("teardown.tar")]);
+        assert_eq!(metadata.environment(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn setup_gets_metadata_from_unfiltered_directives() {
+        let image_lookup = |name: &_| match name {
+            "rust" => Ok(ImageConfig {
+                layers: path_buf_vec!["foo.tar"],
+                working_directory: Some(utf8_path_buf!("/foo")),
+                environment: Some(string_vec!["PATH=/usr/bin"]),
+            }),
+            _ => panic!(),
+        };
+        let all = AllMetadata::from_str(
+            r#"
+            [[directives]]
+            image.name = "rust"
+            image.use = ["layers", "working_directory", "environment"]
+            user = 1000
+            timeout = 60
+            added_devices = ["fuse"]
+
+            [[directives]]
+            filter = "package.equals(package1)"
+            user = 2000
+            added_layers = [{ tar = "package1.tar" }]
+
+            [setup]
+            program = "/bin/setup"
+            layers = [{ tar = "setup.tar" }]
+            environment = { FOO = "bar" }
+            "#,
+        )
+        .unwrap();
+        let metadata = all
+            .get_metadata_for_global_job(all.setup.as_ref().unwrap(), empty_env, image_lookup)
+            .unwrap();
+        assert_eq!(
+            metadata.layers,
+            vec![tar_layer!("foo.tar"), tar_layer!("setup.tar")]
+        );
+        assert_eq!(metadata.working_directory, utf8_path_buf!("/foo"));
+        assert_eq!(
+            metadata.environment(),
+            string_vec!["FOO=bar", "PATH=/usr/bin"]
+        );
+        assert_eq!(metadata.user, UserId::from(1000));
+        assert_eq!(metadata.timeout(None, None), Timeout::new(60));
+        assert_eq!(metadata.devices, enum_set!(JobDevice::Fuse));
+    }
+
+    #[test]
+    fn exclude_shared_libraries() {
+        let all = AllMetadata::from_str(
+            r#"
+            exclude_shared_libraries = ["*/libssl.so*", "/usr/lib/libfoo.so"]
+
+            [[directives]]
+            include_shared_libraries = true
+            "#,
+        )
+        .unwrap();
+        let exclusions = all.shared_library_exclusions().unwrap();
+        assert!(exclusions.is_match("/lib/x86_64-linux-gnu/libssl.so.3"));
+        assert!(exclusions.is_match("/usr/lib/libfoo.so"));
+        assert!(!exclusions.is_match("/usr/lib/libfoo.so.1"));
+        assert!(!exclusions.is_match("/lib/x86_64-linux-gnu/libc.so.6"));
+
+        assert!(AllMetadata::default()
+            .shared_library_exclusions()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn exclude_shared_libraries_bad_pattern() {
+        let all = AllMetadata::from_str(
+            r#"
+            exclude_shared_libraries = ["lib[.so"]
+            directives = []
+            "#,
+        )
+        .unwrap();
+        let err = all.shared_library_exclusions().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"parsing exclude_shared_libraries pattern "lib[.so""#
+        );
+    }
+
+    #[test]
+    fn setup_missing_program() {
+        assert_toml_error(
+            AllMetadata::from_str(
+                r#"
+                directives = []
+
+                [setup]
+                layers = [{ tar = "setup.tar" }]
+                "#,
+            )
+            .unwrap_err(),
+            "missing field `program`",
+        );
+    }
+
+    #[test]
+    fn runner() {
+        let all = AllMetadata::from_str(
+            r#"
+            [[directives]]
+            filter = "test"
+            runner = ["/usr/bin/wasmtime", "run"]
+
+            [[directives]]
+            filter = "test.equals(package1) && name.equals(test1)"
+            runner = []
+            "#,
+        )
+        .unwrap();
+        let integration_test_ctx = |name: &str| pattern::Context {
+            package: "package1".into(),
+            artifact: Some(pattern::Artifact {
+                name: "package1".into(),
+                kind: pattern::ArtifactKind::Test,
+            }),
+            case: Some(pattern::Case {
+                name: name.into(),
+                previously_failed: false,
+            }),
+        };
+        assert_eq!(
+            all.get_metadata_for_test(&integration_test_ctx("test1"), empty_env, no_containers)
+                .unwrap()
+                .runner,
+            Vec::<String>::new(),
+        );
+        assert_eq!(
+            all.get_metadata_for_test(&integration_test_ctx("test2"), empty_env, no_containers)
+                .unwrap()
+                .runner,
+            string_vec!["/usr/bin/wasmtime", "run"],
+        );
+        assert_eq!(
+            all.get_metadata_for_test(&test_ctx("package1", "test2"), empty_env, no_containers)
+                .unwrap()
+                .runner,
+            Vec::<String>::new(),
+        );
+    }
+
     #[test]
     fn layers() {
         let image_lookup = |name: &_| match name {
@@ -1150,7 +1921,8 @@ mod test {
                 "#,
             )
             .unwrap_err(),
-            "unknown field `not_a_field`, expected `directives`",
+            "unknown field `not_a_field`, expected one of `directives`, `setup`, `teardown`, \
+            `exclude_shared_libraries`",
         );
     }
 }
@@ -1,3 +1,4 @@
+use super::{RetryPolicy, SkipCondition};
 use crate::pattern;
 use anyhow::Result;
 use maelstrom_base::{
@@ -15,9 +16,17 @@ pub struct TestDirective {
     pub include_shared_libraries: Option<bool>,
     pub enable_loopback: Option<bool>,
     pub enable_writable_file_system: Option<bool>,
+    pub enable_init_process: Option<bool>,
+    pub scratch_dir: Option<Utf8PathBuf>,
+    pub cpus: Option<u32>,
+    pub memory: Option<u64>,
     pub user: Option<UserId>,
     pub group: Option<GroupId>,
     pub timeout: Option<Option<Timeout>>,
+    pub retry: Option<RetryPolicy>,
+    pub allow_failure: Option<bool>,
+    pub skip_if: Option<SkipCondition>,
+    pub runner: Option<Vec<String>>,
     pub layers: Option<PossiblyImage<Vec<Layer>>>,
     pub added_layers: Vec<Layer>,
     pub mounts: Option<Vec<JobMount>>,
@@ -36,9 +45,17 @@ enum DirectiveField {
     IncludeSharedLibraries,
     EnableLoopback,
     EnableWritableFileSystem,
+    EnableInitProcess,
+    ScratchDir,
+    Cpus,
+    Memory,
     User,
     Group,
     Timeout,
+    Retry,
+    AllowFailure,
+    SkipIf,
+    Runner,
     Mounts,
     AddedMounts,
     Devices,
@@ -68,9 +85,17 @@ impl<'de> de::Visitor<'de> for DirectiveVisitor {
         let mut include_shared_libraries = None;
         let mut enable_loopback = None;
         let mut enable_writable_file_system = None;
+        let mut enable_init_process = None;
+        let mut scratch_dir = None;
+        let mut cpus = None;
+        let mut memory = None;
         let mut user = None;
         let mut group = None;
         let mut timeout = None;
+        let mut retry = None;
+        let mut allow_failure = None;
+        let mut skip_if = None;
+        let mut runner = None;
         let mut mounts = None;
         let mut added_mounts = None;
         let mut devices = None;
@@ -99,6 +124,18 @@ impl<'de> de::Visitor<'de> for DirectiveVisitor {
                 DirectiveField::EnableWritableFileSystem => {
                     enable_writable_file_system = Some(map.next_value()?);
                 }
+                DirectiveField::EnableInitProcess => {
+                    enable_init_process = Some(map.next_value()?);
+                }
+                DirectiveField::ScratchDir => {
+                    scratch_dir = Some(map.next_value()?);
+                }
+                DirectiveField::Cpus => {
+                    cpus = Some(map.next_value()?);
+                }
+                DirectiveField::Memory => {
+                    memory = Some(map.next_value()?);
+                }
                 DirectiveField::User => {
                     user = Some(map.next_value()?);
                 }
@@ -108,6 +145,18 @@ impl<'de> de::Visitor<'de> for DirectiveVisitor {
                 DirectiveField::Timeout => {
                     timeout = Some(Timeout::new(map.next_value()?));
                 }
+                DirectiveField::Retry => {
+                    retry = Some(map.next_value()?);
+                }
+                DirectiveField::AllowFailure => {
+                    allow_failure = Some(map.next_value()?);
+                }
+                DirectiveField::SkipIf => {
+                    skip_if = Some(map.next_value()?);
+                }
+                DirectiveField::Runner => {
+                    runner = Some(map.next_value()?);
+                }
                 DirectiveField::Mounts => {
                     incompatible(
                         &added_mounts,
@@ -209,9 +258,17 @@ impl<'de> de::Visitor<'de> for DirectiveVisitor {
             include_shared_libraries,
             enable_loopback,
             enable_writable_file_system,
+            enable_init_process,
+            scratch_dir,
+            cpus,
+            memory,
             user,
             group,
             timeout,
+            retry,
+            allow_failure,
+            skip_if,
+            runner,
             layers,
             added_layers: added_layers.unwrap_or_default(),
             mounts,
@@ -240,8 +297,8 @@ mod test {
     use super::*;
     use anyhow::Error;
     use maelstrom_base::{enum_set, JobMountFsType};
-    use maelstrom_client::spec::SymlinkSpec;
-    use maelstrom_test::{glob_layer, paths_layer, string, tar_layer, utf8_path_buf};
+    use maelstrom_client::spec::{PrefixOptions, SymlinkSpec};
+    use maelstrom_test::{glob_layer, paths_layer, string, string_vec, tar_layer, utf8_path_buf};
     use toml::de::Error as TomlError;
 
     fn parse_test_directive(file: &str) -> Result<TestDirective> {
@@ -295,9 +352,14 @@ mod test {
                 include_shared_libraries = true
                 enable_loopback = false
                 enable_writable_file_system = true
+                enable_init_process = true
+                scratch_dir = "/tmp/test"
+                cpus = 2
+                memory = 1073741824
                 user = 101
                 group = 202
                 timeout = 1
+                allow_failure = true
                 "#
             )
             .unwrap(),
@@ -310,9 +372,14 @@ mod test {
                 include_shared_libraries: Some(true),
                 enable_loopback: Some(false),
                 enable_writable_file_system: Some(true),
+                enable_init_process: Some(true),
+                scratch_dir: Some(Utf8PathBuf::from("/tmp/test")),
+                cpus: Some(2),
+                memory: Some(1073741824),
                 user: Some(UserId::from(101)),
                 group: Some(GroupId::from(202)),
                 timeout: Some(Timeout::new(1)),
+                allow_failure: Some(true),
                 ..Default::default()
             }
         );
@@ -340,6 +407,24 @@ mod test {
         );
     }
 
+    #[test]
+    fn runner() {
+        assert_eq!(
+            parse_test_directive(
+                r#"
+                filter = "test"
+                runner = ["/usr/bin/wasmtime", "run"]
+                "#
+            )
+            .unwrap(),
+            TestDirective {
+                filter: Some("test".parse().unwrap()),
+                runner: Some(string_vec!["/usr/bin/wasmtime", "run"]),
+                ..Default::default()
+            }
+        );
+    }
+
     #[test]
     fn mounts() {
         assert_eq!(
@@ -694,6 +779,24 @@ mod test {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            parse_test_directive(
+                r#"
+                layers = [{ glob = "foo*.bin", respect_gitignore = true }]
+                "#
+            )
+            .unwrap(),
+            TestDirective {
+                layers: Some(PossiblyImage::Explicit(vec![Layer::Glob {
+                    glob: "foo*.bin".into(),
+                    prefix_options: PrefixOptions {
+                        respect_gitignore: true,
+                        ..Default::default()
+                    },
+                }])),
+                ..Default::default()
+            }
+        );
     }
 
     #[test]
@@ -798,6 +901,36 @@ mod test {
         );
     }
 
+    #[test]
+    fn layers_command() {
+        assert_eq!(
+            parse_test_directive(
+                r#"
+                layers = [
+                    { command = "npm", args = ["install"], output = "node_modules" },
+                    { command = "<build_dir>/gen-assets", output = "<build_dir>/assets" },
+                ]
+                "#
+            )
+            .unwrap(),
+            TestDirective {
+                layers: Some(PossiblyImage::Explicit(vec![
+                    Layer::Command {
+                        program: "npm".into(),
+                        args: vec!["install".into()],
+                        output: "node_modules".into(),
+                    },
+                    Layer::Command {
+                        program: "<build_dir>/gen-assets".into(),
+                        args: vec![],
+                        output: "<build_dir>/assets".into(),
+                    },
+                ])),
+                ..Default::default()
+            }
+        );
+    }
+
     #[test]
     fn image_with_layers() {
         assert_eq!(
@@ -55,6 +55,19 @@ impl Artifact {
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Case {
     pub name: String,
+    /// Whether the test failed the last time it was run.
+    pub previously_failed: bool,
+}
+
+impl Case {
+    /// The module path of the test, which is everything in its name before the last `::`. Tests
+    /// at the root of their crate have an empty module path.
+    pub fn module(&self) -> &str {
+        self.name
+            .rsplit_once("::")
+            .map(|(module, _)| module)
+            .unwrap_or("")
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -107,11 +120,13 @@ pub fn interpret_simple_selector(s: &SimpleSelector, c: &Context) -> Option<bool
         All | Any | True => true,
         None | False => false,
         Library => matches!(c.artifact()?.kind, ArtifactKind::Library),
+        PreviouslyFailed => c.case()?.previously_failed,
         Compound(Binary) => matches!(c.artifact()?.kind, ArtifactKind::Binary),
         Compound(Benchmark) => matches!(c.artifact()?.kind, ArtifactKind::Benchmark),
         Compound(Test) => matches!(c.artifact()?.kind, ArtifactKind::Test),
         Compound(Example) => matches!(c.artifact()?.kind, ArtifactKind::Example),
         Compound(Name) => unreachable!("should be parser error"),
+        Compound(Module) => unreachable!("should be parser error"),
         Compound(Package) => unreachable!("should be parser error"),
     })
 }
@@ -132,6 +147,7 @@ pub fn interpret_compound_selector(s: &CompoundSelector, c: &Context) -> Option<
     use CompoundSelectorName::*;
     Some(match s.name {
         Name => interpret_matcher(&c.case()?.name, &s.matcher),
+        Module => interpret_matcher(c.case()?.module(), &s.matcher),
         Package => interpret_matcher(&c.package, &s.matcher),
         Binary => {
             matches!(&c.artifact()?.kind, ArtifactKind::Binary)
@@ -199,6 +215,56 @@ pub fn interpret_pattern(s: &Pattern, c: &Context) -> Option<bool> {
     interpret_or_expression(&s.0, c)
 }
 
+/// Return the text of the first of `terms` which matches `c`, or `Some(None)` if none of them do.
+/// Like [`interpret_pattern`], this returns `None` if it can't be decided from `c`.
+pub fn interpret_first_match<'a>(
+    terms: &'a [(String, Pattern)],
+    c: &Context,
+) -> Option<Option<&'a str>> {
+    for (text, pattern) in terms {
+        if interpret_pattern(pattern, c)? {
+            return Some(Some(text));
+        }
+    }
+    Some(None)
+}
+
+/// Whether a test is selected by a filter, and which of the filter's patterns decided it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Selection<'a> {
+    /// The test matched this include pattern, and none of the exclude patterns.
+    Included(&'a str),
+    /// The test matched an include pattern, but was then excluded by an exclude pattern.
+    Excluded { include: &'a str, exclude: &'a str },
+    /// The test didn't match any of the include patterns.
+    NotIncluded,
+}
+
+impl fmt::Display for Selection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Included(include) => write!(f, "selected by include `{include}`"),
+            Self::Excluded { include, exclude } => {
+                write!(f, "excluded by `{exclude}` (included by `{include}`)")
+            }
+            Self::NotIncluded => write!(f, "not selected by any include"),
+        }
+    }
+}
+
+/// Explain whether `filter` selects the test described by `c`, which must include the case. When
+/// more than one include or exclude pattern matches, the first one given is the one reported.
+pub fn explain_filter<'a>(filter: &'a FilterTerms, c: &Context) -> Selection<'a> {
+    let first_match = |terms| interpret_first_match(terms, c).expect("case is provided");
+    match first_match(&filter.include) {
+        None => Selection::NotIncluded,
+        Some(include) => match first_match(&filter.exclude) {
+            None => Selection::Included(include),
+            Some(exclude) => Selection::Excluded { include, exclude },
+        },
+    }
+}
+
 #[test]
 fn simple_expression_simple_selector() {
     use ArtifactKind::*;
@@ -349,6 +415,7 @@ fn test_compound_sel_case(
         }),
         case: Some(Case {
             name: case_name.into(),
+            previously_failed: false,
         }),
     };
     let actual = interpret_simple_expression(&parse_str!(SimpleExpression, s).unwrap(), &c);
@@ -378,6 +445,51 @@ fn simple_expression_compound_selector_name() {
     }
 }
 
+#[test]
+fn simple_expression_compound_selector_module() {
+    use ArtifactKind::*;
+
+    let p = "module.starts_with(net::)";
+    test_compound_sel_case(p, Some(Library), "", "", "net::tcp::connects", Some(true));
+    test_compound_sel_case(p, Some(Library), "", "", "net::connects", Some(false));
+    test_compound_sel_case(p, Some(Library), "", "", "fs::net::reads", Some(false));
+
+    let p = "module.equals(net)";
+    test_compound_sel_case(p, Some(Library), "", "", "net::connects", Some(true));
+    test_compound_sel_case(p, Some(Library), "", "", "net::tcp::connects", Some(false));
+    test_compound_sel_case(p, Some(Library), "", "", "net", Some(false));
+
+    let p = "module.equals()";
+    test_compound_sel_case(p, Some(Library), "", "", "connects", Some(true));
+    test_compound_sel_case(p, Some(Library), "", "", "net::connects", Some(false));
+    test_compound_sel(p, Some(Library), "", None);
+}
+
+#[test]
+fn simple_expression_simple_selector_previously_failed() {
+    let test_it = |previously_failed, case: Option<&str>, expected| {
+        let c = Context {
+            package: "foo".into(),
+            artifact: Some(Artifact {
+                kind: ArtifactKind::Library,
+                name: "foo".into(),
+            }),
+            case: case.map(|name| Case {
+                name: name.into(),
+                previously_failed,
+            }),
+        };
+        let actual = interpret_simple_expression(
+            &parse_str!(SimpleExpression, "previously_failed").unwrap(),
+            &c,
+        );
+        assert_eq!(actual, expected);
+    };
+    test_it(true, Some("foo_test"), Some(true));
+    test_it(false, Some("foo_test"), Some(false));
+    test_it(false, None, None);
+}
+
 #[test]
 fn simple_expression_compound_selector_binary() {
     use ArtifactKind::*;
@@ -429,6 +541,7 @@ fn and_or_not_diff_expressions() {
             }),
             case: Some(Case {
                 name: "foo_test".into(),
+                previously_failed: false,
             }),
         };
         let actual = interpret_pattern(&parse_str!(Pattern, s).unwrap(), &c);
@@ -491,3 +604,62 @@ fn and_or_not_diff_maybe_expressions() {
     test_it("package.equals(foo) - name.equals(bar_test)", None);
     test_it("package.equals(foo) - name.equals(foo_test)", None);
 }
+
+#[test]
+fn explain_filter_overlapping_patterns() {
+    fn test_it(package: &str, case: &str, expected: Selection) {
+        let filter = compile_filter_terms(
+            &[
+                "package.equals(foo)".into(),
+                "name.starts_with(test_)".into(),
+            ],
+            &[
+                "name.contains(slow)".into(),
+                "name.equals(test_slow_b)".into(),
+            ],
+        )
+        .unwrap();
+        let c = Context {
+            package: package.into(),
+            artifact: Some(Artifact {
+                kind: ArtifactKind::Library,
+                name: package.into(),
+            }),
+            case: Some(Case {
+                name: case.into(),
+                previously_failed: false,
+            }),
+        };
+        assert_eq!(explain_filter(&filter, &c), expected);
+    }
+
+    // Both includes match, so the first one is credited.
+    test_it("foo", "test_a", Selection::Included("package.equals(foo)"));
+    test_it(
+        "bar",
+        "test_a",
+        Selection::Included("name.starts_with(test_)"),
+    );
+    test_it("bar", "check_a", Selection::NotIncluded);
+
+    // Both excludes match, so the first one is credited.
+    test_it(
+        "foo",
+        "test_slow_b",
+        Selection::Excluded {
+            include: "package.equals(foo)",
+            exclude: "name.contains(slow)",
+        },
+    );
+    test_it(
+        "bar",
+        "test_slow_a",
+        Selection::Excluded {
+            include: "name.starts_with(test_)",
+            exclude: "name.contains(slow)",
+        },
+    );
+
+    // An exclude alone doesn't select anything.
+    test_it("bar", "check_slow", Selection::NotIncluded);
+}
@@ -1,5 +1,9 @@
 pub mod interpreter;
 pub mod parser;
 
-pub use interpreter::{interpret_pattern, Artifact, ArtifactKind, Case, Context};
-pub use parser::{compile_filter, Pattern};
+pub use interpreter::{
+    explain_filter, interpret_pattern, Artifact, ArtifactKind, Case, Context, Selection,
+};
+pub use parser::{
+    compile_filter, compile_filter_terms, restrict_to_packages, FilterTerms, Pattern,
+};
@@ -204,6 +204,7 @@ impl Matcher {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum CompoundSelectorName {
     Name,
+    Module,
     Binary,
     Benchmark,
     Example,
@@ -215,6 +216,7 @@ impl CompoundSelectorName {
     pub fn parser<InputT: Stream<Token = char>>() -> impl Parser<InputT, Output = Self> {
         choice((
             attempt(prefix("name", 1)).map(|_| Self::Name),
+            attempt(prefix("module", 1)).map(|_| Self::Module),
             attempt(prefix("package", 1)).map(|_| Self::Package),
             Self::parser_for_simple_selector(),
         ))
@@ -255,6 +257,7 @@ pub enum SimpleSelectorName {
     None,
     False,
     Library,
+    PreviouslyFailed,
     #[from]
     Compound(CompoundSelectorName),
 }
@@ -268,6 +271,7 @@ impl SimpleSelectorName {
             attempt(prefix("none", 1)).map(|_| Self::None),
             attempt(prefix("false", 1)).map(|_| Self::False),
             attempt(prefix("library", 1)).map(|_| Self::Library),
+            attempt(prefix("previously_failed", 2)).map(|_| Self::PreviouslyFailed),
             CompoundSelectorName::parser_for_simple_selector().map(Self::Compound),
         ))
     }
@@ -467,6 +471,48 @@ pub fn compile_filter(include_filter: &[String], exclude_filter: &[String]) -> R
     Ok(AndExpression::Diff(include.into(), Box::new(exclude.into())).into())
 }
 
+/// The include and exclude patterns of a filter, each compiled on its own and kept along with the
+/// text it came from. Unlike the [`Pattern`] returned by [`compile_filter`], this lets us tell
+/// which of the patterns decided whether a test is selected.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FilterTerms {
+    pub include: Vec<(String, Pattern)>,
+    pub exclude: Vec<(String, Pattern)>,
+}
+
+pub fn compile_filter_terms(
+    include_filter: &[String],
+    exclude_filter: &[String],
+) -> Result<FilterTerms> {
+    let compile = |filters: &[String]| {
+        filters
+            .iter()
+            .map(|f| Ok((f.clone(), Pattern::from_str(f)?)))
+            .collect::<Result<Vec<_>>>()
+    };
+    Ok(FilterTerms {
+        include: compile(include_filter)?,
+        exclude: compile(exclude_filter)?,
+    })
+}
+
+/// Restrict `pattern` so that it only matches tests in one of the given packages.
+pub fn restrict_to_packages(pattern: Pattern, packages: &[String]) -> Pattern {
+    let packages = packages
+        .iter()
+        .fold(SimpleSelectorName::False.into(), |e, package| {
+            OrExpression::Or(
+                CompoundSelector {
+                    name: CompoundSelectorName::Package,
+                    matcher: Matcher::Equals(MatcherParameter(package.clone())),
+                }
+                .into(),
+                Box::new(e),
+            )
+        });
+    AndExpression::And(packages.into(), Box::new(pattern.0.into())).into()
+}
+
 #[test]
 fn simple_expr() {
     use CompoundSelectorName::*;
@@ -487,6 +533,8 @@ fn simple_expr() {
     test_it("false()", False);
     test_it("library", Library);
     test_it("library()", Library);
+    test_it("previously_failed", PreviouslyFailed);
+    test_it("previously_failed()", PreviouslyFailed);
 
     test_it("binary", Binary);
     test_it("binary()", Binary);
@@ -502,6 +550,8 @@ fn simple_expr() {
     }
     test_it_err("name");
     test_it_err("name()");
+    test_it_err("module");
+    test_it_err("module()");
     test_it_err("package");
     test_it_err("package()");
 }
@@ -524,6 +574,7 @@ fn simple_expr_prefix() {
     test_it("none", 1, None);
     test_it("false", 1, False);
     test_it("library", 1, Library);
+    test_it("previously_failed", 2, PreviouslyFailed);
 
     test_it("binary", 2, Binary);
     test_it("benchmark", 2, Benchmark);
@@ -559,6 +610,12 @@ fn simple_expr_compound() {
         Example,
         Contains("s(oi)l".into()),
     );
+    test_it(
+        "module.starts_with(net::)",
+        Module,
+        StartsWith("net::".into()),
+    );
+    test_it("m.eq(net)", Module, Equals("net".into()));
 }
 
 #[test]
@@ -59,6 +59,10 @@ pub trait ProgressIndicator: Clone + Send + Sync + UnwindSafe + RefUnwindSafe +
     /// Called when all jobs are running
     fn done_queuing_jobs(&self) {}
 
+    /// Called after all jobs have been queued, and then again every time a job finishes, with the
+    /// number of jobs we are still waiting on
+    fn update_outstanding_jobs(&self, _outstanding: u64) {}
+
     /// Called when all jobs are done
     fn finished(&self) -> Result<()> {
         Ok(())
@@ -13,6 +13,7 @@ struct State {
     done_queuing_jobs: bool,
     length: u64,
     finished: u64,
+    outstanding: u64,
 }
 
 #[derive(Clone)]
@@ -117,10 +118,29 @@ impl ProgressIndicator for MultipleProgressBars {
         let mut state = self.state.lock().unwrap();
         state.done_queuing_jobs = true;
 
-        self.enqueue_spinner.finish_and_clear();
+        // If we're still waiting on jobs, the spinner is kept around to show how many.
+        if state.outstanding == 0 {
+            self.enqueue_spinner.finish_and_clear();
+        }
+    }
+
+    fn update_outstanding_jobs(&self, outstanding: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.outstanding = outstanding;
+
+        // Finishing the spinner forces it to be redrawn with the new message, and we don't need it
+        // to spin anymore anyway.
+        if state.done_queuing_jobs && outstanding == 0 {
+            self.enqueue_spinner.finish_and_clear();
+        } else {
+            let jobs = if outstanding == 1 { "job" } else { "jobs" };
+            self.enqueue_spinner
+                .finish_with_message(format!("waiting for {outstanding} {jobs}"));
+        }
     }
 
     fn finished(&self) -> Result<()> {
+        self.enqueue_spinner.finish_and_clear();
         for bar in self.bars.values() {
             bar.finish_and_clear();
         }
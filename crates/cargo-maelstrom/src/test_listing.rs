@@ -4,18 +4,24 @@ use cargo_metadata::{Artifact as CargoArtifact, Package as CargoPackage, Target
 use maelstrom_util::fs::Fs;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use serde_with::{serde_as, FromInto};
+use serde_with::{serde_as, DurationSecondsWithFrac, FromInto};
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::path::Path;
+use std::{path::Path, str, time::Duration};
+use strum::{Display, EnumString};
 
 pub use crate::pattern::ArtifactKind;
 
-#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[derive(
+    Default, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize_repr, Deserialize_repr,
+)]
 #[repr(u32)]
 pub enum TestListingVersion {
     V0 = 0,
-    #[default]
     V1 = 1,
+    V2 = 2,
+    V3 = 3,
+    #[default]
+    V4 = 4,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -33,9 +39,18 @@ impl ArtifactKey {
     }
 }
 
+#[serde_as]
 #[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct ArtifactCases {
     pub cases: Vec<String>,
+    /// Added in [`TestListingVersion::V2`].
+    pub ignored_cases: Vec<String>,
+    /// The cases which failed the last time they were run. Added in [`TestListingVersion::V3`].
+    pub failed_cases: Vec<String>,
+    /// How long each case took the last time it ran to completion. Added in
+    /// [`TestListingVersion::V4`].
+    #[serde_as(as = "BTreeMap<_, DurationSecondsWithFrac<f64>>")]
+    pub durations: BTreeMap<String, Duration>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,48 +95,173 @@ pub struct TestListing {
     pub packages: BTreeMap<String, Package>,
 }
 
-fn filter_case(
+fn case_context(
     package: &str,
     artifact: &ArtifactKey,
+    cases: &ArtifactCases,
     case: &str,
-    filter: &pattern::Pattern,
-) -> bool {
-    let c = pattern::Context {
+) -> pattern::Context {
+    pattern::Context {
         package: package.into(),
         artifact: Some(pattern::Artifact {
             name: artifact.name.clone(),
             kind: artifact.kind,
         }),
-        case: Some(pattern::Case { name: case.into() }),
-    };
+        case: Some(pattern::Case {
+            name: case.into(),
+            previously_failed: cases.failed_cases.iter().any(|c| c == case),
+        }),
+    }
+}
+
+fn filter_case(
+    package: &str,
+    artifact: &ArtifactKey,
+    cases: &ArtifactCases,
+    case: &str,
+    filter: &pattern::Pattern,
+) -> bool {
+    let c = case_context(package, artifact, cases, case);
     pattern::interpret_pattern(filter, &c).expect("case is provided")
 }
 
 impl TestListing {
-    pub fn add_cases(&mut self, package_name: &str, artifact: &CargoArtifact, cases: &[String]) {
+    /// Record the cases the artifact currently has. This replaces whatever was previously listed
+    /// for the artifact, so cases which have since been removed from it are dropped. Whether the
+    /// remaining cases failed last time is kept.
+    pub fn add_cases(
+        &mut self,
+        package_name: &str,
+        artifact: &CargoArtifact,
+        cases: &[String],
+        ignored_cases: &[String],
+    ) {
         let artifact_key = ArtifactKey::from_target(&artifact.target);
         let package = self.packages.entry(package_name.into()).or_default();
+        let previous = package.artifacts.remove(&artifact_key).unwrap_or_default();
+        let mut failed_cases = previous.failed_cases;
+        failed_cases.retain(|c| cases.contains(c));
+        let mut durations = previous.durations;
+        durations.retain(|c, _| cases.contains(c));
         package.artifacts.insert(
             artifact_key,
             ArtifactCases {
                 cases: cases.to_vec(),
+                ignored_cases: ignored_cases.to_vec(),
+                failed_cases,
+                durations,
             },
         );
     }
 
+    /// Whether the given case failed the last time it was run. Cases that have never been run
+    /// haven't failed.
+    pub fn previously_failed(
+        &self,
+        package_name: &str,
+        artifact: &CargoArtifact,
+        case: &str,
+    ) -> bool {
+        self.packages
+            .get(package_name)
+            .and_then(|p| p.artifacts.get(&ArtifactKey::from_target(&artifact.target)))
+            .is_some_and(|a| a.failed_cases.iter().any(|c| c == case))
+    }
+
+    /// How long the given case took the last time it ran to completion, if it has.
+    pub fn last_duration(
+        &self,
+        package_name: &str,
+        artifact: &CargoArtifact,
+        case: &str,
+    ) -> Option<Duration> {
+        self.packages
+            .get(package_name)
+            .and_then(|p| p.artifacts.get(&ArtifactKey::from_target(&artifact.target)))
+            .and_then(|a| a.durations.get(case))
+            .copied()
+    }
+
+    /// Record how long cases took in the latest run. `duration` is called for every listed case
+    /// with its package, artifact, and name, and returns how long it took, or `None` if it didn't
+    /// run to completion. Cases that didn't run to completion keep their previous duration, if they
+    /// had one.
+    pub fn record_durations(
+        &mut self,
+        duration: impl Fn(&str, &ArtifactKey, &str) -> Option<Duration>,
+    ) {
+        for (package_name, package) in &mut self.packages {
+            for (key, artifact) in &mut package.artifacts {
+                for case in &artifact.cases {
+                    if let Some(duration) = duration(package_name, key, case) {
+                        artifact.durations.insert(case.clone(), duration);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record which cases failed in the latest run. `failed` is called for every listed case with
+    /// its package, artifact, and name, and returns whether it failed, or `None` if it wasn't run.
+    /// Cases that weren't run keep their previous result.
+    pub fn record_results(&mut self, failed: impl Fn(&str, &ArtifactKey, &str) -> Option<bool>) {
+        for (package_name, package) in &mut self.packages {
+            for (key, artifact) in &mut package.artifacts {
+                for case in &artifact.cases {
+                    match failed(package_name, key, case) {
+                        Some(true) if !artifact.failed_cases.contains(case) => {
+                            artifact.failed_cases.push(case.clone());
+                        }
+                        Some(false) => artifact.failed_cases.retain(|c| c != case),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
     pub fn remove_package(&mut self, package: &str) {
         self.packages.remove(package);
     }
 
+    /// Count the cases selected by `filter` that will be run. Ignored cases aren't run, so they
+    /// aren't counted.
     pub fn expected_job_count(&self, filter: &pattern::Pattern) -> u64 {
         self.packages
             .iter()
             .flat_map(|(p, a)| {
                 a.artifacts
                     .iter()
-                    .flat_map(move |(a, c)| c.cases.iter().map(move |c| (p, a, c)))
+                    .flat_map(move |(a, cases)| cases.cases.iter().map(move |c| (p, a, cases, c)))
             })
-            .filter(|(p, a, c)| filter_case(p, a, c, filter))
+            .filter(|(_, _, cases, c)| !cases.ignored_cases.contains(c))
+            .filter(|(p, a, cases, c)| filter_case(p, a, cases, c, filter))
+            .count() as u64
+    }
+
+    /// Explain, for every listed case, whether `filter` selects it. Each case is returned with its
+    /// package and artifact.
+    pub fn explain_selection<'a>(
+        &'a self,
+        filter: &'a pattern::FilterTerms,
+    ) -> impl Iterator<Item = (&'a str, &'a ArtifactKey, &'a str, pattern::Selection<'a>)> {
+        self.packages.iter().flat_map(move |(p, a)| {
+            a.artifacts.iter().flat_map(move |(a, cases)| {
+                cases.cases.iter().map(move |c| {
+                    let selection = pattern::explain_filter(filter, &case_context(p, a, cases, c));
+                    (p.as_str(), a, c.as_str(), selection)
+                })
+            })
+        })
+    }
+
+    /// Like [`Self::expected_job_count`], but counts the cases whose names are in `cases`
+    pub fn expected_job_count_for_cases(&self, cases: &HashSet<String>) -> u64 {
+        self.packages
+            .values()
+            .flat_map(|a| a.artifacts.values())
+            .flat_map(|a| a.cases.iter().filter(|c| !a.ignored_cases.contains(c)))
+            .filter(|c| cases.contains(*c))
             .count() as u64
     }
 
@@ -148,31 +288,149 @@ impl TestListing {
     }
 }
 
-pub const LAST_TEST_LISTING_NAME: &str = "test-listing.toml";
+/// The encoding used when writing the test listing. Whichever one is used, it is detected
+/// automatically when the listing is loaded.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Display, EnumString, Eq, PartialEq, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum TestListingFormat {
+    #[default]
+    Toml,
+    Cbor,
+}
 
-pub fn load_test_listing(path: &Path) -> Result<Option<TestListing>> {
+impl TestListingFormat {
+    /// The name of the file in the cache directory the listing is kept in when written with this
+    /// format.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Toml => "test-listing.toml",
+            Self::Cbor => "test-listing.cbor",
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            Self::Toml => Self::Cbor,
+            Self::Cbor => Self::Toml,
+        }
+    }
+}
+
+/// CBOR listings start with the "self-described CBOR" tag. This can't be the start of a valid TOML
+/// file since it isn't valid UTF-8. The contents are checked for it rather than trusting the file
+/// name, so a CBOR listing written to `test-listing.toml` by an older version still loads.
+const CBOR_MAGIC: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+/// The oldest version of the listing we know how to migrate. Listings older than this are
+/// discarded.
+const OLDEST_MIGRATABLE_VERSION: TestListingVersion = TestListingVersion::V1;
+
+/// Add a field with the given name and empty value to every artifact in the listing.
+fn add_empty_field(table: &mut toml::Table, name: &str, empty: toml::Value) -> Result<()> {
+    for (_, package) in table.iter_mut() {
+        let artifacts = package
+            .get_mut("artifacts")
+            .and_then(toml::Value::as_array_mut)
+            .ok_or(anyhow!("package missing artifacts"))?;
+        for artifact in artifacts {
+            artifact
+                .as_table_mut()
+                .ok_or(anyhow!("malformed artifact"))?
+                .entry(name)
+                .or_insert_with(|| empty.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Upgrade a listing of the given version by one version, returning the new version.
+fn migrate_listing_once(
+    table: &mut toml::Table,
+    version: TestListingVersion,
+) -> Result<TestListingVersion> {
+    match version {
+        TestListingVersion::V0 => Err(anyhow!("can't migrate test listing version 0")),
+        TestListingVersion::V1 => {
+            add_empty_field(table, "ignored_cases", toml::Value::Array(vec![]))?;
+            Ok(TestListingVersion::V2)
+        }
+        TestListingVersion::V2 => {
+            add_empty_field(table, "failed_cases", toml::Value::Array(vec![]))?;
+            Ok(TestListingVersion::V3)
+        }
+        TestListingVersion::V3 => {
+            add_empty_field(table, "durations", toml::Value::Table(toml::Table::new()))?;
+            Ok(TestListingVersion::V4)
+        }
+        TestListingVersion::V4 => Ok(TestListingVersion::V4),
+    }
+}
+
+/// Upgrade a listing (without its version field) to the current version.
+fn migrate_listing(table: &mut toml::Table, mut version: TestListingVersion) -> Result<()> {
+    while version != TestListingVersion::default() {
+        version = migrate_listing_once(table, version)?;
+    }
+    Ok(())
+}
+
+/// Load the test listing from the given cache directory, in whichever format it was written.
+pub fn load_test_listing(cache_dir: &Path) -> Result<Option<TestListing>> {
     let fs = Fs::new();
-    if let Some(contents) = fs.read_to_string_if_exists(path)? {
-        let mut table: toml::Table = toml::from_str(&contents)?;
+    let mut contents = None;
+    for format in [TestListingFormat::Cbor, TestListingFormat::Toml] {
+        contents = fs.read_if_exists(cache_dir.join(format.file_name()))?;
+        if contents.is_some() {
+            break;
+        }
+    }
+    if let Some(contents) = contents {
+        let mut table: toml::Table = match contents.strip_prefix(&CBOR_MAGIC) {
+            Some(cbor) => ciborium::from_reader(cbor)?,
+            None => toml::from_str(str::from_utf8(&contents)?)?,
+        };
         let version: TestListingVersion = table
             .remove("version")
             .ok_or(anyhow!("missing version"))?
             .try_into()?;
-        if version != TestListingVersion::default() {
-            Ok(None)
-        } else {
-            Ok(toml::from_str(&contents)?)
+        if version < OLDEST_MIGRATABLE_VERSION {
+            return Ok(None);
         }
+        migrate_listing(&mut table, version)?;
+        table.insert(
+            "version".into(),
+            toml::Value::try_from(TestListingVersion::default())?,
+        );
+        Ok(Some(table.try_into()?))
     } else {
         Ok(None)
     }
 }
 
-pub fn write_test_listing(path: &Path, job_listing: &TestListing) -> Result<()> {
+/// Write the test listing to the given cache directory, removing any listing left there in the
+/// other format.
+pub fn write_test_listing(
+    cache_dir: &Path,
+    job_listing: &TestListing,
+    format: TestListingFormat,
+) -> Result<()> {
     let fs = Fs::new();
-    if let Some(parent) = path.parent() {
-        fs.create_dir_all(parent)?;
+    fs.create_dir_all(cache_dir)?;
+    let contents = match format {
+        TestListingFormat::Toml => toml::to_string_pretty(job_listing)?.into_bytes(),
+        TestListingFormat::Cbor => {
+            let mut contents = CBOR_MAGIC.to_vec();
+            ciborium::into_writer(job_listing, &mut contents)?;
+            contents
+        }
+    };
+    fs.write(cache_dir.join(format.file_name()), contents)?;
+    let other_path = cache_dir.join(format.other().file_name());
+    if fs.exists(&other_path) {
+        fs.remove_file(other_path)?;
     }
-    fs.write(path, toml::to_string_pretty(job_listing)?)?;
     Ok(())
 }
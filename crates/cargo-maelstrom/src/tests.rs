@@ -1,34 +1,47 @@
 use crate::{
-    cargo::{CompilationOptions, FeatureSelectionOptions, ManifestOptions},
-    config::Quiet,
-    main_app_new,
+    cargo::{CompilationOptions, FeatureSelectionOptions, ManifestOptions, TestCaseLocation},
+    config::{ExitCodeSeverities, LargeOutputThreshold, OutputEncoding, Quiet, TestOrder},
+    explain_selection, list_capabilities, main_app_new,
+    metadata::RetryPolicy,
+    new_helper,
     progress::{ProgressDriver, ProgressIndicator},
+    submit_job,
     test_listing::{
-        load_test_listing, ArtifactCases, ArtifactKey, ArtifactKind, Package, TestListing,
-        LAST_TEST_LISTING_NAME,
+        load_test_listing, write_test_listing, ArtifactCases, ArtifactKey, ArtifactKind, Package,
+        TestListing, TestListingFormat, TestListingVersion,
     },
-    EnqueueResult, ListAction, LoggingOutput, MainAppDeps, MainAppState, Wait,
+    visitor::{CaseFailure, CaseName, JobStatusTracker, JobStatusVisitor, JsonSummary},
+    write_json_summary, CompletedRun, EnqueueResult, LayerCache, ListAction, LoggingOutput,
+    MainAppDeps, MainAppOptions, MainAppState, PackageScope, PendingJobs, Wait,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use cargo_metadata::{Artifact as CargoArtifact, Package as CargoPackage};
 use indicatif::InMemoryTerm;
 use maelstrom_base::{
+    enum_set, nonempty,
     stats::{JobState, JobStateCounts},
-    ArtifactType, ClientJobId, JobCompleted, JobEffects, JobOutcome, JobOutcomeResult,
-    JobOutputResult, JobSpec, JobStatus, Sha256Digest,
+    ArtifactType, ClientJobId, JobCompleted, JobDevice, JobEffects, JobOutcome, JobOutcomeResult,
+    JobOutputResult, JobSpec, JobStatus, Sha256Digest, Timeout, WorkerCapabilities,
+    WorkerCapabilitiesMap, WorkerId,
 };
 use maelstrom_client::{
     spec::{ImageConfig, Layer},
     ArtifactUploadProgress,
 };
 use maelstrom_test::digest;
-use maelstrom_util::{fs::Fs, log::test_logger};
-use std::collections::HashSet;
+use maelstrom_util::{fs::Fs, log::test_logger, process::ExitCode};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::{
     cell::RefCell,
+    io,
+    num::{NonZeroU32, NonZeroUsize},
     path::{Path, PathBuf},
     rc::Rc,
-    sync::atomic::{AtomicU32, Ordering},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
 };
 use tempfile::{tempdir, TempDir};
 
@@ -36,8 +49,12 @@ use tempfile::{tempdir, TempDir};
 struct FakeTestCase {
     name: String,
     ignored: bool,
+    location: Option<TestCaseLocation>,
     desired_state: JobState,
     outcome: JobOutcome,
+    /// Outcomes of the first attempts at running the test. Once these are used up, `outcome` is
+    /// used for every later attempt.
+    earlier_outcomes: Vec<JobOutcome>,
 }
 
 impl Default for FakeTestCase {
@@ -45,6 +62,7 @@ impl Default for FakeTestCase {
         Self {
             name: "".into(),
             ignored: false,
+            location: None,
             desired_state: JobState::Complete,
             outcome: JobOutcome::Completed(JobCompleted {
                 status: JobStatus::Exited(0),
@@ -52,8 +70,10 @@ impl Default for FakeTestCase {
                     stdout: JobOutputResult::None,
                     stderr: JobOutputResult::Inline(Box::new(*b"this output should be ignored")),
                     duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
                 },
             }),
+            earlier_outcomes: vec![],
         }
     }
 }
@@ -69,6 +89,17 @@ struct FakeTests {
     test_binaries: Vec<FakeTestBinary>,
 }
 
+impl FakeTestCase {
+    /// How long the test's last attempt takes.
+    fn duration(&self) -> Duration {
+        match &self.outcome {
+            JobOutcome::Completed(JobCompleted { effects, .. }) | JobOutcome::TimedOut(effects) => {
+                effects.duration
+            }
+        }
+    }
+}
+
 impl FakeTests {
     fn create_binaries(&self, fs: &Fs, bin_path: &Path) {
         for bin in &self.test_binaries {
@@ -96,6 +127,19 @@ impl FakeTests {
                                 },
                                 ArtifactCases {
                                     cases: b.tests.iter().map(|t| t.name.clone()).collect(),
+                                    ignored_cases: b
+                                        .tests
+                                        .iter()
+                                        .filter(|t| t.ignored)
+                                        .map(|t| t.name.clone())
+                                        .collect(),
+                                    failed_cases: vec![],
+                                    durations: b
+                                        .tests
+                                        .iter()
+                                        .filter(|t| !t.ignored)
+                                        .map(|t| (t.name.clone(), t.duration()))
+                                        .collect(),
                                 },
                             )]
                             .into_iter()
@@ -190,16 +234,62 @@ impl FakeTests {
             .collect()
     }
 
-    fn find_outcome(&self, spec: JobSpec) -> Option<JobOutcome> {
-        let binary_name = spec.program.file_name().unwrap();
+    fn locations(&self, binary: &Path) -> HashMap<String, TestCaseLocation> {
+        let binary_name = binary.file_name().unwrap().to_str().unwrap();
         let binary = self.find_binary(&binary_name);
+        binary
+            .tests
+            .iter()
+            .filter_map(|t| Some((t.name.to_owned(), t.location.clone()?)))
+            .collect()
+    }
+
+    /// Find the test case a job is for. Jobs that aren't for a test binary, like setup and
+    /// teardown jobs, don't have one.
+    fn find_case_for_job(&self, spec: &JobSpec) -> Option<&FakeTestCase> {
+        let binary_name = spec.program.file_name().unwrap();
+        let binary = self.test_binaries.iter().find(|b| b.name == binary_name)?;
         let case_name = spec
             .arguments
             .iter()
             .find(|a| !a.starts_with("--"))
             .unwrap();
-        let case = binary.tests.iter().find(|c| &c.name == case_name).unwrap();
-        (case.desired_state == JobState::Complete).then(|| case.outcome.clone())
+        Some(binary.tests.iter().find(|c| &c.name == case_name).unwrap())
+    }
+
+    /// How far a job gets. Jobs that aren't for a test binary always complete.
+    fn desired_state(&self, spec: &JobSpec) -> JobState {
+        self.find_case_for_job(spec)
+            .map_or(JobState::Complete, |case| case.desired_state)
+    }
+
+    fn find_outcome(&self, spec: JobSpec, attempt: usize) -> Option<JobOutcome> {
+        let Some(case) = self.find_case_for_job(&spec) else {
+            // Jobs that aren't for a test binary, like setup and teardown jobs, exit with the code
+            // given as their first argument.
+            let binary_name = spec.program.file_name().unwrap();
+            let code = spec
+                .arguments
+                .first()
+                .map_or(0, |code| code.parse().unwrap());
+            return Some(JobOutcome::Completed(JobCompleted {
+                status: JobStatus::Exited(code),
+                effects: JobEffects {
+                    stdout: JobOutputResult::None,
+                    stderr: JobOutputResult::Inline(
+                        format!("{binary_name} output").into_bytes().into(),
+                    ),
+                    duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
+                },
+            }));
+        };
+        (case.desired_state == JobState::Complete).then(|| {
+            case.earlier_outcomes
+                .get(attempt)
+                .unwrap_or(&case.outcome)
+                .clone()
+        })
     }
 
     fn find_binary(&self, binary_name: &str) -> &FakeTestBinary {
@@ -253,6 +343,30 @@ struct TestMainAppDeps {
     bin_path: PathBuf,
     target_dir: PathBuf,
     next_job_id: AtomicU32,
+    attempts: Mutex<HashMap<(String, Vec<String>), usize>>,
+    worker_capabilities: WorkerCapabilitiesMap,
+    /// How many more calls to `add_job` should fail before jobs are accepted.
+    submission_failures: AtomicU32,
+    /// Whether the failures from `add_job` are I/O errors, which are worth retrying.
+    submission_failures_transient: AtomicBool,
+    /// The calls to `add_layer` and `add_job` that succeeded, in order.
+    calls: Mutex<Vec<&'static str>>,
+    /// The layers passed to `add_layer`, in order.
+    added_layers: Mutex<Vec<Layer>>,
+    /// The specs passed to successful calls to `add_job`, in order.
+    added_jobs: Mutex<Vec<JobSpec>>,
+    /// The name and tag of each call to `get_container_image`, in order.
+    container_image_lookups: Mutex<Vec<(String, String)>>,
+    /// How long listing a binary's ignored tests takes.
+    list_delay: Duration,
+    /// Binaries whose ignored tests aren't listed until `calls` has this many of the given call, or
+    /// until ten seconds have passed.
+    listing_gates: HashMap<String, (&'static str, usize)>,
+    /// How many binaries are having their tests listed right now, and the most there have been.
+    listing: Mutex<(usize, usize)>,
+    /// The jobs that were added but haven't completed or been canceled.
+    outstanding: Mutex<HashSet<ClientJobId>>,
+    outstanding_condvar: Condvar,
 }
 
 impl TestMainAppDeps {
@@ -262,12 +376,27 @@ impl TestMainAppDeps {
             bin_path,
             target_dir,
             next_job_id: AtomicU32::new(1),
+            attempts: Default::default(),
+            worker_capabilities: Default::default(),
+            submission_failures: AtomicU32::new(0),
+            submission_failures_transient: AtomicBool::new(true),
+            calls: Default::default(),
+            added_layers: Default::default(),
+            added_jobs: Default::default(),
+            container_image_lookups: Default::default(),
+            list_delay: Duration::ZERO,
+            listing_gates: Default::default(),
+            listing: Default::default(),
+            outstanding: Default::default(),
+            outstanding_condvar: Condvar::new(),
         }
     }
 }
 
 impl MainAppDeps for TestMainAppDeps {
-    fn add_layer(&self, _layer: Layer) -> Result<(Sha256Digest, ArtifactType)> {
+    fn add_layer(&self, layer: Layer) -> Result<(Sha256Digest, ArtifactType)> {
+        self.calls.lock().unwrap().push("add_layer");
+        self.added_layers.lock().unwrap().push(layer);
         Ok((digest!(42), ArtifactType::Manifest))
     }
 
@@ -279,23 +408,78 @@ impl MainAppDeps for TestMainAppDeps {
         todo!()
     }
 
-    fn get_container_image(&self, _name: &str, _tag: &str) -> Result<ImageConfig> {
-        todo!()
+    fn get_worker_capabilities(&self) -> Result<WorkerCapabilitiesMap> {
+        Ok(self.worker_capabilities.clone())
+    }
+
+    fn get_container_image(&self, name: &str, tag: &str) -> Result<ImageConfig> {
+        self.container_image_lookups
+            .lock()
+            .unwrap()
+            .push((name.into(), tag.into()));
+        Ok(ImageConfig {
+            working_directory: Some(format!("/{name}/{tag}").into()),
+            ..Default::default()
+        })
     }
 
     fn add_job(
         &self,
         spec: JobSpec,
-        handler: impl FnOnce(ClientJobId, JobOutcomeResult) + Send + Sync + 'static,
+        running: impl FnOnce(ClientJobId) + Send + Sync + 'static,
+        handler: impl FnOnce(Result<(ClientJobId, JobOutcomeResult)>) + Send + Sync + 'static,
     ) -> Result<()> {
+        if self
+            .submission_failures
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            if self.submission_failures_transient.load(Ordering::Acquire) {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "client process went away",
+                )
+                .into());
+            }
+            return Err(anyhow!("client process shut down"));
+        }
+        self.calls.lock().unwrap().push("add_job");
+        self.added_jobs.lock().unwrap().push(spec.clone());
         let cjid = ClientJobId::from_u32(self.next_job_id.fetch_add(1, Ordering::AcqRel));
-        if let Some(outcome) = self.tests.find_outcome(spec) {
-            handler(cjid, Ok(outcome));
+        let attempt = {
+            let mut attempts = self.attempts.lock().unwrap();
+            let key = (spec.program.to_string(), spec.arguments.clone());
+            let attempt = attempts.entry(key).or_default();
+            *attempt += 1;
+            *attempt - 1
+        };
+        let state = self.tests.desired_state(&spec);
+        if state >= JobState::Running {
+            running(cjid);
+        }
+        match self.tests.find_outcome(spec, attempt) {
+            Some(outcome) => handler(Ok((cjid, Ok(outcome)))),
+            None => {
+                self.outstanding.lock().unwrap().insert(cjid);
+            }
         }
         Ok(())
     }
 
+    fn cancel_job(&self, cjid: ClientJobId) -> Result<()> {
+        self.calls.lock().unwrap().push("cancel_job");
+        self.outstanding.lock().unwrap().remove(&cjid);
+        self.outstanding_condvar.notify_all();
+        Ok(())
+    }
+
     fn wait_for_outstanding_jobs(&self) -> Result<()> {
+        let _outstanding = self
+            .outstanding_condvar
+            .wait_while(self.outstanding.lock().unwrap(), |outstanding| {
+                !outstanding.is_empty()
+            })
+            .unwrap();
         Ok(())
     }
 
@@ -323,11 +507,43 @@ impl MainAppDeps for TestMainAppDeps {
 
     fn get_cases_from_binary(&self, binary: &Path, filter: &Option<String>) -> Result<Vec<String>> {
         match filter.as_ref().map(|s| s.as_str()) {
-            Some("--ignored") => Ok(self.tests.ignored_cases(binary)),
+            Some("--ignored") => {
+                {
+                    let mut listing = self.listing.lock().unwrap();
+                    listing.0 += 1;
+                    listing.1 = std::cmp::max(listing.0, listing.1);
+                }
+                let binary_name = binary.file_name().unwrap().to_str().unwrap();
+                if let Some(&(call, count)) = self.listing_gates.get(binary_name) {
+                    let start = Instant::now();
+                    while self
+                        .calls
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter(|c| **c == call)
+                        .count()
+                        < count
+                        && start.elapsed() < Duration::from_secs(10)
+                    {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                std::thread::sleep(self.list_delay);
+                self.listing.lock().unwrap().0 -= 1;
+                Ok(self.tests.ignored_cases(binary))
+            }
             None => Ok(self.tests.cases(binary)),
             o => panic!("unsupported filter {o:?}"),
         }
     }
+
+    fn get_case_locations_from_binary(
+        &self,
+        binary: &Path,
+    ) -> Result<HashMap<String, TestCaseLocation>> {
+        Ok(self.tests.locations(binary))
+    }
 }
 
 fn counts_from_states(states: &[JobState]) -> JobStateCounts {
@@ -338,28 +554,49 @@ fn counts_from_states(states: &[JobState]) -> JobStateCounts {
     counts
 }
 
-#[allow(clippy::too_many_arguments)]
-fn run_app(
-    temp_dir: &TempDir,
-    term: InMemoryTerm,
-    fake_tests: FakeTests,
-    workspace_root: &Path,
+/// How a test wants the app to be set up and run. Tests set the fields they exercise and leave the
+/// rest at their defaults.
+struct RunAppOptions<'a> {
     stdout_tty: bool,
     quiet: Quiet,
     include_filter: Vec<String>,
     exclude_filter: Vec<String>,
+    tests_from: Option<&'a Path>,
+    package_scope: PackageScope,
     list: Option<ListAction>,
+    /// Where the test listing is kept. Defaults to `maelstrom` in the target directory.
+    cache_dir: Option<&'a Path>,
+    main_app_options: MainAppOptions,
     finish: bool,
-) -> String {
+}
+
+impl Default for RunAppOptions<'_> {
+    fn default() -> Self {
+        Self {
+            stdout_tty: false,
+            quiet: Quiet::from(false),
+            include_filter: vec!["all".into()],
+            exclude_filter: vec![],
+            tests_from: None,
+            package_scope: PackageScope::Workspace,
+            list: None,
+            cache_dir: None,
+            main_app_options: MainAppOptions::default(),
+            finish: true,
+        }
+    }
+}
+
+/// Create the binaries for `fake_tests` and a `MainAppState` that runs them. `configure_deps` can
+/// change the fake deps before they're handed to the state.
+fn test_main_app_state(
+    temp_dir: &TempDir,
+    fake_tests: FakeTests,
+    workspace_root: &Path,
+    options: RunAppOptions,
+    configure_deps: impl FnOnce(&mut TestMainAppDeps),
+) -> Result<MainAppState<TestMainAppDeps>> {
     let fs = Fs::new();
-    let log = test_logger();
-    slog::info!(
-        log, "doing test";
-        "quiet" => ?quiet,
-        "include_filter" => ?include_filter,
-        "exclude_filter" => ?exclude_filter,
-        "list" => ?list
-    );
     let packages = fake_tests.packages();
 
     let bin_path = temp_dir.path().join("bin");
@@ -367,59 +604,92 @@ fn run_app(
     fake_tests.create_binaries(&fs, &bin_path);
 
     let target_directory = workspace_root.join("target");
-    let deps = TestMainAppDeps::new(fake_tests.clone(), bin_path, target_directory.clone());
+    let cache_dir = options
+        .cache_dir
+        .map(Path::to_owned)
+        .unwrap_or_else(|| target_directory.join("maelstrom"));
+    let mut deps = TestMainAppDeps::new(fake_tests, bin_path, target_directory.clone());
+    configure_deps(&mut deps);
 
-    let state = MainAppState::new(
+    MainAppState::new(
         deps,
-        include_filter,
-        exclude_filter,
-        list,
-        false, // stderr_color
+        options.include_filter,
+        options.exclude_filter,
+        options.tests_from,
+        options.package_scope,
+        options.list,
         &workspace_root,
         &Vec::from_iter(packages.iter()),
-        &target_directory.join("maelstrom"),
+        &cache_dir,
         &target_directory,
-        FeatureSelectionOptions::default(),
-        CompilationOptions::default(),
-        ManifestOptions::default(),
+        options.main_app_options,
         LoggingOutput::default(),
-        log.clone(),
-    )
-    .unwrap();
-    let prog_driver = TestProgressDriver::default();
-    let mut app = main_app_new(
-        &state,
-        stdout_tty,
-        quiet,
-        term.clone(),
-        prog_driver.clone(),
-        None,
+        test_logger(),
     )
-    .unwrap();
+}
 
-    let mut running = vec![];
-    loop {
-        let res = app.enqueue_one().unwrap();
-        let (package_name, case) = match res {
-            EnqueueResult::Done => break,
-            EnqueueResult::Ignored | EnqueueResult::Listed => continue,
-            EnqueueResult::Enqueued { package_name, case } => (package_name, case),
-        };
-        let test = fake_tests.find_case(&package_name, &case);
-        running.push(test.desired_state);
+fn run_app(
+    temp_dir: &TempDir,
+    term: InMemoryTerm,
+    fake_tests: FakeTests,
+    workspace_root: &Path,
+    options: RunAppOptions,
+) -> Result<String> {
+    let log = test_logger();
+    slog::info!(
+        log, "doing test";
+        "quiet" => ?options.quiet,
+        "include_filter" => ?options.include_filter,
+        "exclude_filter" => ?options.exclude_filter,
+        "list" => ?options.list
+    );
+    let stdout_tty = options.stdout_tty;
+    let quiet = options.quiet.clone();
+    let finish = options.finish;
+    let state = test_main_app_state(
+        temp_dir,
+        fake_tests.clone(),
+        workspace_root,
+        options,
+        |_| {},
+    )?;
+    std::thread::scope(|scope| {
+        let prog_driver = TestProgressDriver::default();
+        let mut app = main_app_new(
+            &state,
+            stdout_tty,
+            quiet,
+            term.clone(),
+            prog_driver.clone(),
+            scope,
+            None,
+            None,
+        )?;
 
-        prog_driver.update(counts_from_states(&running)).unwrap();
-    }
+        let mut running = vec![];
+        loop {
+            let res = app.enqueue_one()?;
+            let (package_name, case) = match res {
+                EnqueueResult::Done => break,
+                EnqueueResult::Ignored | EnqueueResult::Listed | EnqueueResult::Yielded => continue,
+                EnqueueResult::Enqueued { package_name, case } => (package_name, case),
+            };
+            let test = fake_tests.find_case(&package_name, &case);
+            running.push(test.desired_state);
 
-    app.drain().unwrap();
+            prog_driver.update(counts_from_states(&running)).unwrap();
+        }
 
-    if finish {
-        app.finish().unwrap();
-    }
+        app.drain()?;
+
+        if finish {
+            app.finish()?;
+        }
 
-    slog::info!(log, "test complete");
+        slog::info!(log, "test complete");
 
-    term.contents()
+        Ok(term.contents())
+    })
 }
 
 fn run_or_list_all_tests_sync(
@@ -438,13 +708,15 @@ fn run_or_list_all_tests_sync(
         term.clone(),
         fake_tests,
         &workspace,
-        false, // stdout_tty
-        quiet,
-        include_filter,
-        exclude_filter,
-        list,
-        true, // finish
+        RunAppOptions {
+            quiet,
+            include_filter,
+            exclude_filter,
+            list,
+            ..Default::default()
+        },
     )
+    .unwrap()
 }
 
 fn run_all_tests_sync(
@@ -940,7 +1212,7 @@ fn two_tests_all_tests_sync_quiet() {
     );
 }
 
-fn run_failed_tests(fake_tests: FakeTests) -> String {
+fn run_failed_tests(fake_tests: FakeTests, failure_output_limit: Option<u32>) -> String {
     let tmp_dir = tempdir().unwrap();
 
     let term = InMemoryTerm::new(50, 50);
@@ -949,13 +1221,15 @@ fn run_failed_tests(fake_tests: FakeTests) -> String {
         term.clone(),
         fake_tests,
         &tmp_dir.path().join("workspace"),
-        false, // stdout_tty
-        Quiet::from(false),
-        vec!["all".into()],
-        vec![],
-        None,
-        true, // finish
-    );
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                failure_output_limit,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
 
     term.contents()
 }
@@ -968,6 +1242,7 @@ fn failed_tests() {
             stdout: JobOutputResult::None,
             stderr: JobOutputResult::Inline(Box::new(*b"error output")),
             duration: std::time::Duration::from_secs(1),
+            resource_usage: Default::default(),
         },
     });
     let fake_tests = FakeTests {
@@ -991,7 +1266,7 @@ fn failed_tests() {
         ],
     };
     assert_eq!(
-        run_failed_tests(fake_tests),
+        run_failed_tests(fake_tests, None),
         "\
         bar test_it..........................FAIL   1.000s\n\
         stderr: error output\n\
@@ -1007,172 +1282,732 @@ fn failed_tests() {
     );
 }
 
-fn run_in_progress_test(fake_tests: FakeTests, quiet: Quiet, expected_output: &str) {
-    let tmp_dir = tempdir().unwrap();
-
-    let term = InMemoryTerm::new(50, 50);
-    let term_clone = term.clone();
-    let contents = run_app(
-        &tmp_dir,
-        term_clone,
-        fake_tests,
-        &tmp_dir.path().join("workspace"),
-        true, // stdout_tty
-        quiet,
-        vec!["all".into()],
-        vec![],
-        None,
-        false, // finish
-    );
-    assert_eq!(contents, expected_output);
-}
-
 #[test]
-fn waiting_for_artifacts() {
+fn failure_output_limit() {
+    let failed_outcome = JobOutcome::Completed(JobCompleted {
+        status: JobStatus::Exited(1),
+        effects: JobEffects {
+            stdout: JobOutputResult::None,
+            stderr: JobOutputResult::Inline(Box::new(*b"error output")),
+            duration: std::time::Duration::from_secs(1),
+            resource_usage: Default::default(),
+        },
+    });
     let fake_tests = FakeTests {
-        test_binaries: vec![
-            FakeTestBinary {
-                name: "foo".into(),
-                tests: vec![FakeTestCase {
-                    name: "test_it".into(),
-                    desired_state: JobState::WaitingForArtifacts,
-                    ..Default::default()
-                }],
-            },
-            FakeTestBinary {
-                name: "bar".into(),
+        test_binaries: ["bar", "baz", "foo"]
+            .into_iter()
+            .map(|name| FakeTestBinary {
+                name: name.into(),
                 tests: vec![FakeTestCase {
                     name: "test_it".into(),
-                    desired_state: JobState::WaitingForArtifacts,
+                    outcome: failed_outcome.clone(),
                     ..Default::default()
                 }],
-            },
-        ],
+            })
+            .collect(),
     };
-    run_in_progress_test(
-        fake_tests,
-        false.into(),
+    assert_eq!(
+        run_failed_tests(fake_tests, Some(2)),
         "\
-        ######################## 2/2 waiting for artifacts\n\
-        ------------------------ 0/2 pending\n\
-        ------------------------ 0/2 running\n\
-        ------------------------ 0/2 complete\
-        ",
+        bar test_it..........................FAIL   1.000s\n\
+        stderr: error output\n\
+        baz test_it..........................FAIL   1.000s\n\
+        stderr: error output\n\
+        foo test_it..........................FAIL   1.000s\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         0\n\
+        Failed Tests    :         3\n\
+        \x20\x20\x20\x20bar test_it: failure\n\
+        \x20\x20\x20\x20baz test_it: failure\n\
+        \x20\x20\x20\x20foo test_it: failure\n\
+        1 failed test had output omitted\
+        "
     );
 }
 
 #[test]
-fn pending() {
+fn retried_tests() {
+    let outcome = |status| {
+        JobOutcome::Completed(JobCompleted {
+            status,
+            effects: JobEffects {
+                stdout: JobOutputResult::None,
+                stderr: JobOutputResult::Inline(Box::new(*b"error output")),
+                duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
+            },
+        })
+    };
     let fake_tests = FakeTests {
         test_binaries: vec![
-            FakeTestBinary {
-                name: "foo".into(),
-                tests: vec![FakeTestCase {
-                    name: "test_it".into(),
-                    desired_state: JobState::Pending,
-                    ..Default::default()
-                }],
-            },
             FakeTestBinary {
                 name: "bar".into(),
                 tests: vec![FakeTestCase {
                     name: "test_it".into(),
-                    desired_state: JobState::Pending,
+                    earlier_outcomes: vec![outcome(JobStatus::Exited(3))],
+                    outcome: outcome(JobStatus::Exited(0)),
                     ..Default::default()
                 }],
             },
-        ],
-    };
-    run_in_progress_test(
-        fake_tests,
-        false.into(),
-        "\
-        ######################## 2/2 waiting for artifacts\n\
-        ######################## 2/2 pending\n\
-        ------------------------ 0/2 running\n\
-        ------------------------ 0/2 complete\
-        ",
-    );
-}
-
-#[test]
-fn running() {
-    let fake_tests = FakeTests {
-        test_binaries: vec![
             FakeTestBinary {
-                name: "foo".into(),
+                name: "baz".into(),
                 tests: vec![FakeTestCase {
                     name: "test_it".into(),
-                    desired_state: JobState::Running,
+                    earlier_outcomes: vec![outcome(JobStatus::Signaled(9))],
+                    outcome: outcome(JobStatus::Exited(3)),
                     ..Default::default()
                 }],
             },
             FakeTestBinary {
-                name: "bar".into(),
+                name: "foo".into(),
                 tests: vec![FakeTestCase {
                     name: "test_it".into(),
-                    desired_state: JobState::Running,
+                    outcome: outcome(JobStatus::Exited(1)),
                     ..Default::default()
                 }],
             },
         ],
     };
-    run_in_progress_test(
+
+    let tmp_dir = tempdir().unwrap();
+    let workspace = tmp_dir.path().join("workspace");
+    let fs = Fs::new();
+    fs.create_dir_all(&workspace).unwrap();
+    fs.write(
+        workspace.join("maelstrom-test.toml"),
+        r#"
+        [[directives]]
+        retry = { max_retries = 2, exit_codes = [3], signals = [9] }
+        "#,
+    )
+    .unwrap();
+
+    let term = InMemoryTerm::new(50, 50);
+    run_app(
+        &tmp_dir,
+        term.clone(),
         fake_tests,
-        false.into(),
+        &workspace,
+        RunAppOptions::default(),
+    )
+    .unwrap();
+
+    // "bar" passes on its retry, "baz" runs out of retries, and "foo" isn't retried because it
+    // failed with an exit code that isn't in the policy.
+    assert_eq!(
+        term.contents(),
         "\
-        ######################## 2/2 waiting for artifacts\n\
-        ######################## 2/2 pending\n\
-        ######################## 2/2 running\n\
-        ------------------------ 0/2 complete\
-        ",
+        bar test_it.........................RETRY   1.000s\n\
+        exited with code 3, retrying (1 of 2)\n\
+        baz test_it.........................RETRY   1.000s\n\
+        killed by signal 9, retrying (1 of 2)\n\
+        foo test_it..........................FAIL   1.000s\n\
+        stderr: error output\n\
+        bar test_it............................OK   1.000s\n\
+        baz test_it.........................RETRY   1.000s\n\
+        exited with code 3, retrying (2 of 2)\n\
+        baz test_it..........................FAIL   1.000s\n\
+        stderr: error output\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         1\n\
+        Failed Tests    :         2\n\
+        \x20\x20\x20\x20foo test_it: failure\n\
+        \x20\x20\x20\x20baz test_it: failure\n\
+        Passed On Retry :         1\n\
+        \x20\x20\x20\x20bar test_it: passed on retry\
+        "
     );
 }
 
 #[test]
-fn complete() {
+fn retries_option_retries_any_failure() {
+    let outcome = |status| {
+        JobOutcome::Completed(JobCompleted {
+            status,
+            effects: JobEffects {
+                stdout: JobOutputResult::None,
+                stderr: JobOutputResult::None,
+                duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
+            },
+        })
+    };
     let fake_tests = FakeTests {
         test_binaries: vec![
             FakeTestBinary {
-                name: "foo".into(),
+                name: "bar".into(),
                 tests: vec![FakeTestCase {
                     name: "test_it".into(),
-                    desired_state: JobState::Complete,
+                    earlier_outcomes: vec![outcome(JobStatus::Exited(1))],
+                    outcome: outcome(JobStatus::Exited(0)),
                     ..Default::default()
                 }],
             },
             FakeTestBinary {
-                name: "bar".into(),
+                name: "baz".into(),
                 tests: vec![FakeTestCase {
                     name: "test_it".into(),
-                    desired_state: JobState::Running,
+                    earlier_outcomes: vec![outcome(JobStatus::Signaled(9))],
+                    outcome: outcome(JobStatus::Exited(2)),
+                    ..Default::default()
+                }],
+            },
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    outcome: outcome(JobStatus::Exited(0)),
                     ..Default::default()
                 }],
             },
         ],
     };
-    run_in_progress_test(
+    let tmp_dir = tempdir().unwrap();
+    let state = test_main_app_state(
+        &tmp_dir,
         fake_tests,
-        false.into(),
-        "\
-        foo test_it............................OK   1.000s\n\
-        ######################## 2/2 waiting for artifacts\n\
-        ######################## 2/2 pending\n\
-        ######################## 2/2 running\n\
-        #############----------- 1/2 complete\
-        ",
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                retries: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        |_| {},
+    )
+    .unwrap();
+    let term = InMemoryTerm::new(50, 50);
+    std::thread::scope(|scope| {
+        let mut app = main_app_new(
+            &state,
+            false, // stdout_tty
+            Quiet::from(false),
+            term.clone(),
+            TestProgressDriver::default(),
+            scope,
+            None,
+            None,
+        )
+        .unwrap();
+        while !matches!(app.enqueue_one().unwrap(), EnqueueResult::Done) {}
+        app.drain().unwrap();
+        app.finish().unwrap();
+        drop(app);
+
+        // "bar" passes on its retry and "baz" runs out of retries. Each retry counts as another job.
+        assert_eq!(
+            term.contents(),
+            "\
+            bar test_it.........................RETRY   1.000s\n\
+            exited with code 1, retrying (1 of 1)\n\
+            baz test_it.........................RETRY   1.000s\n\
+            killed by signal 9, retrying (1 of 1)\n\
+            foo test_it............................OK   1.000s\n\
+            bar test_it............................OK   1.000s\n\
+            baz test_it..........................FAIL   1.000s\n\
+            \n\
+            ================== Test Summary ==================\n\
+            Successful Tests:         2\n\
+            Failed Tests    :         1\n\
+            \x20\x20\x20\x20baz test_it: failure\n\
+            Passed On Retry :         1\n\
+            \x20\x20\x20\x20bar test_it: passed on retry\
+            "
+        );
+        assert_eq!(state.queuing_state.jobs_queued.load(Ordering::Acquire), 5);
+    })
+}
+
+#[test]
+fn skip_if_current_arch() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![
+                FakeTestCase {
+                    name: "test_portable".into(),
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_other_arch".into(),
+                    ..Default::default()
+                },
+            ],
+        }],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let workspace = tmp_dir.path().join("workspace");
+    let fs = Fs::new();
+    fs.create_dir_all(&workspace).unwrap();
+    let arch = std::env::consts::ARCH;
+    fs.write(
+        workspace.join("maelstrom-test.toml"),
+        format!(
+            r#"
+            [[directives]]
+            filter = "name.equals(test_other_arch)"
+            skip_if = {{ arch = ["{arch}"] }}
+            "#
+        ),
+    )
+    .unwrap();
+
+    let term = InMemoryTerm::new(50, 50);
+    run_app(
+        &tmp_dir,
+        term.clone(),
+        fake_tests,
+        &workspace,
+        RunAppOptions::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        term.contents(),
+        format!(
+            "\
+            foo test_portable......................OK   1.000s\n\
+            foo test_other_arch...............IGNORED\n\
+            skipped on {arch}\n\
+            \n\
+            ================== Test Summary ==================\n\
+            Successful Tests:         1\n\
+            Failed Tests    :         0\n\
+            Ignored Tests   :         1\n\
+            \x20\x20\x20\x20foo test_other_arch: ignored\
+            "
+        )
     );
 }
 
 #[test]
-fn complete_quiet() {
+fn skip_if_checked_before_adding_layers() {
+    for affinity_cache_warm in [false, true] {
+        let fake_tests = FakeTests {
+            test_binaries: vec![FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![
+                    FakeTestCase {
+                        name: "test_portable".into(),
+                        ..Default::default()
+                    },
+                    FakeTestCase {
+                        name: "test_other_arch".into(),
+                        ..Default::default()
+                    },
+                ],
+            }],
+        };
+
+        let tmp_dir = tempdir().unwrap();
+        let workspace = tmp_dir.path().join("workspace");
+        let fs = Fs::new();
+        fs.create_dir_all(&workspace).unwrap();
+        let arch = std::env::consts::ARCH;
+        fs.write(
+            workspace.join("maelstrom-test.toml"),
+            format!(
+                r#"
+                [[directives]]
+                filter = "name.equals(test_other_arch)"
+                skip_if = {{ arch = ["{arch}"] }}
+                added_layers = [{{ stubs = ["/skipped/"] }}]
+                "#
+            ),
+        )
+        .unwrap();
+
+        let state = test_main_app_state(
+            &tmp_dir,
+            fake_tests,
+            &workspace,
+            RunAppOptions {
+                main_app_options: MainAppOptions {
+                    affinity_cache_warm,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            |_| {},
+        )
+        .unwrap();
+        std::thread::scope(|scope| {
+            let mut app = main_app_new(
+                &state,
+                false, // stdout_tty
+                Quiet::from(true),
+                InMemoryTerm::new(50, 50),
+                TestProgressDriver::default(),
+                scope,
+                None,
+                None,
+            )
+            .unwrap();
+            assert_eq!(app.run_to_completion().unwrap().ignored, 1);
+        });
+
+        // The skipped test's layer was never added.
+        let skipped_layer = Layer::Stubs {
+            stubs: vec!["/skipped/".into()],
+        };
+        assert!(!state
+            .deps
+            .added_layers
+            .lock()
+            .unwrap()
+            .contains(&skipped_layer));
+    }
+}
+
+#[test]
+fn container_images_are_looked_up_once_per_run() {
+    let case = |name: &str| FakeTestCase {
+        name: name.into(),
+        ..Default::default()
+    };
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![case("test_a"), case("test_b")],
+            },
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![case("test_a"), case("test_b"), case("test_c")],
+            },
+        ],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let workspace_root = tmp_dir.path().join("workspace");
+    let fs = Fs::new();
+    fs.create_dir_all(&workspace_root).unwrap();
+    fs.write(
+        workspace_root.join("maelstrom-test.toml"),
+        r#"
+        [[directives]]
+        image.name = "alpine"
+        image.use = ["working_directory"]
+
+        [[directives]]
+        filter = "package.equals(bar)"
+        image.name = "alpine:latest"
+        image.use = ["working_directory"]
+
+        [[directives]]
+        filter = "package.equals(foo) && name.equals(test_c)"
+        image.name = "alpine:3.19"
+        image.use = ["working_directory"]
+        "#,
+    )
+    .unwrap();
+
+    let state = test_main_app_state(
+        &tmp_dir,
+        fake_tests,
+        &workspace_root,
+        RunAppOptions::default(),
+        |_| {},
+    )
+    .unwrap();
+    std::thread::scope(|scope| {
+        let mut app = main_app_new(
+            &state,
+            false, // stdout_tty
+            Quiet::from(true),
+            InMemoryTerm::new(50, 50),
+            TestProgressDriver::default(),
+            scope,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(app.run_to_completion().unwrap().enqueued, 5);
+        drop(app);
+
+        // Each image is only looked up once, and "alpine" and "alpine:latest" are the same image.
+        assert_eq!(
+            *state.deps.container_image_lookups.lock().unwrap(),
+            vec![
+                ("alpine".to_owned(), "latest".to_owned()),
+                ("alpine".to_owned(), "3.19".to_owned()),
+            ]
+        );
+    })
+}
+
+#[test]
+fn dump_specs_prints_job_specs_as_json_lines() {
+    let tmp_dir = tempdir().unwrap();
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![
+                FakeTestCase {
+                    name: "test_a".into(),
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_b".into(),
+                    ..Default::default()
+                },
+            ],
+        }],
+    };
+    let workspace = tmp_dir.path().join("workspace");
+    let term = InMemoryTerm::new(50, 1000);
+    let output = run_app(
+        &tmp_dir,
+        term,
+        fake_tests,
+        &workspace,
+        RunAppOptions {
+            list: Some(ListAction::DumpSpecs),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let specs = Vec::from_iter(
+        output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()),
+    );
+    assert_eq!(specs.len(), 2);
+    for (spec, case) in specs.iter().zip(["test_a", "test_b"]) {
+        assert_eq!(spec["program"], "/foo");
+        assert_eq!(
+            spec["arguments"],
+            serde_json::json!(["--exact", "--nocapture", case])
+        );
+        assert_eq!(spec["layers"][0][0], digest!(42).to_string());
+    }
+}
+
+fn test_locations_fake_tests() -> FakeTests {
+    FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![
+                    FakeTestCase {
+                        name: "test_it".into(),
+                        location: Some(TestCaseLocation {
+                            path: "bar/src/lib.rs".into(),
+                            line: 12,
+                        }),
+                        ..Default::default()
+                    },
+                    FakeTestCase {
+                        name: "test_ignored".into(),
+                        ignored: true,
+                        location: Some(TestCaseLocation {
+                            path: "bar/src/lib.rs".into(),
+                            line: 20,
+                        }),
+                        ..Default::default()
+                    },
+                ],
+            },
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    ..Default::default()
+                }],
+            },
+        ],
+    }
+}
+
+fn run_with_test_locations(include_test_locations: bool) -> String {
+    let tmp_dir = tempdir().unwrap();
+
+    let term = InMemoryTerm::new(50, 50);
+    run_app(
+        &tmp_dir,
+        term.clone(),
+        test_locations_fake_tests(),
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                include_test_locations,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    term.contents()
+}
+
+#[test]
+fn test_locations_included_when_available() {
+    assert_eq!(
+        run_with_test_locations(true),
+        "\
+        bar test_it............................OK   1.000s\n\
+        \x20\x20\x20\x20--> bar/src/lib.rs:12\n\
+        bar test_ignored..................IGNORED\n\
+        \x20\x20\x20\x20--> bar/src/lib.rs:20\n\
+        foo test_it............................OK   1.000s\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         2\n\
+        Failed Tests    :         0\n\
+        Ignored Tests   :         1\n\
+        \x20\x20\x20\x20bar test_ignored: ignored\
+        "
+    );
+}
+
+#[test]
+fn test_locations_omitted_when_not_requested() {
+    assert_eq!(
+        run_with_test_locations(false),
+        "\
+        bar test_it............................OK   1.000s\n\
+        bar test_ignored..................IGNORED\n\
+        foo test_it............................OK   1.000s\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         2\n\
+        Failed Tests    :         0\n\
+        Ignored Tests   :         1\n\
+        \x20\x20\x20\x20bar test_ignored: ignored\
+        "
+    );
+}
+
+#[test]
+fn github_annotations_for_failures() {
+    let outcome = |status| {
+        JobOutcome::Completed(JobCompleted {
+            status,
+            effects: JobEffects {
+                stdout: JobOutputResult::None,
+                stderr: JobOutputResult::None,
+                duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
+            },
+        })
+    };
     let fake_tests = FakeTests {
         test_binaries: vec![
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![
+                    FakeTestCase {
+                        name: "tests::it_fails".into(),
+                        outcome: outcome(JobStatus::Exited(1)),
+                        location: Some(TestCaseLocation {
+                            path: "bar/src/lib.rs".into(),
+                            line: 12,
+                        }),
+                        ..Default::default()
+                    },
+                    FakeTestCase {
+                        name: "tests::it_passes".into(),
+                        location: Some(TestCaseLocation {
+                            path: "bar/src/lib.rs".into(),
+                            line: 20,
+                        }),
+                        ..Default::default()
+                    },
+                ],
+            },
             FakeTestBinary {
                 name: "foo".into(),
                 tests: vec![FakeTestCase {
                     name: "test_it".into(),
-                    desired_state: JobState::Complete,
+                    outcome: outcome(JobStatus::Signaled(9)),
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+    let tmp_dir = tempdir().unwrap();
+    let state = test_main_app_state(
+        &tmp_dir,
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                github_annotations: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        |_| {},
+    )
+    .unwrap();
+    let term = InMemoryTerm::new(50, 200);
+    std::thread::scope(|scope| {
+        let mut app = main_app_new(
+            &state,
+            false, // stdout_tty
+            Quiet::from(false),
+            term.clone(),
+            TestProgressDriver::default(),
+            scope,
+            None,
+            None,
+        )
+        .unwrap();
+        app.run_to_completion().unwrap();
+        drop(app);
+
+        // Only failures are annotated. They point at where the test is defined when we know that, even
+        // though locations weren't asked to be printed, and the property values have their ":"s
+        // escaped.
+        let contents = term.contents();
+        assert!(!contents.contains("-->"), "{contents}");
+        assert_eq!(
+            Vec::from_iter(contents.lines().filter(|line| line.starts_with("::"))),
+            vec![
+                "::error file=bar/src/lib.rs,line=12,title=bar tests%3A%3Ait_fails\
+                    ::bar tests::it_fails exited with code 1",
+                "::error title=foo test_it::foo test_it killed by signal 9",
+            ]
+        );
+    })
+}
+
+fn run_in_progress_test(fake_tests: FakeTests, quiet: Quiet, expected_output: &str) {
+    let tmp_dir = tempdir().unwrap();
+
+    let term = InMemoryTerm::new(50, 50);
+    let term_clone = term.clone();
+    let contents = run_app(
+        &tmp_dir,
+        term_clone,
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            stdout_tty: true,
+            quiet,
+            finish: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(contents, expected_output);
+}
+
+#[test]
+fn waiting_for_artifacts() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    desired_state: JobState::WaitingForArtifacts,
                     ..Default::default()
                 }],
             },
@@ -1180,154 +2015,2879 @@ fn complete_quiet() {
                 name: "bar".into(),
                 tests: vec![FakeTestCase {
                     name: "test_it".into(),
-                    desired_state: JobState::Running,
+                    desired_state: JobState::WaitingForArtifacts,
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+    run_in_progress_test(
+        fake_tests,
+        false.into(),
+        "\
+        \x20 waiting for 2 jobs\n\
+        ######################## 2/2 waiting for artifacts\n\
+        ------------------------ 0/2 pending\n\
+        ------------------------ 0/2 running\n\
+        ------------------------ 0/2 complete\
+        ",
+    );
+}
+
+#[test]
+fn pending() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    desired_state: JobState::Pending,
+                    ..Default::default()
+                }],
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    desired_state: JobState::Pending,
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+    run_in_progress_test(
+        fake_tests,
+        false.into(),
+        "\
+        \x20 waiting for 2 jobs\n\
+        ######################## 2/2 waiting for artifacts\n\
+        ######################## 2/2 pending\n\
+        ------------------------ 0/2 running\n\
+        ------------------------ 0/2 complete\
+        ",
+    );
+}
+
+#[test]
+fn running() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    desired_state: JobState::Running,
+                    ..Default::default()
+                }],
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    desired_state: JobState::Running,
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+    run_in_progress_test(
+        fake_tests,
+        false.into(),
+        "\
+        \x20 waiting for 2 jobs\n\
+        ######################## 2/2 waiting for artifacts\n\
+        ######################## 2/2 pending\n\
+        ######################## 2/2 running\n\
+        ------------------------ 0/2 complete\
+        ",
+    );
+}
+
+#[test]
+fn complete() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    desired_state: JobState::Complete,
+                    ..Default::default()
+                }],
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    desired_state: JobState::Running,
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+    run_in_progress_test(
+        fake_tests,
+        false.into(),
+        "\
+        foo test_it............................OK   1.000s\n\
+        \x20 waiting for 1 job\n\
+        ######################## 2/2 waiting for artifacts\n\
+        ######################## 2/2 pending\n\
+        ######################## 2/2 running\n\
+        #############----------- 1/2 complete\
+        ",
+    );
+}
+
+#[test]
+fn complete_quiet() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    desired_state: JobState::Complete,
+                    ..Default::default()
+                }],
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    desired_state: JobState::Running,
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+    run_in_progress_test(
+        fake_tests,
+        true.into(),
+        "#####################-------------------- 1/2 jobs",
+    );
+}
+
+#[test]
+fn expected_count_updates_packages() {
+    let tmp_dir = tempdir().unwrap();
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    ..Default::default()
+                }],
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+    run_all_tests_sync(
+        &tmp_dir,
+        fake_tests.clone(),
+        false.into(),
+        vec!["all".into()],
+        vec![],
+    );
+
+    let path = tmp_dir.path().join("workspace/target/maelstrom");
+    let listing: TestListing = load_test_listing(&path).unwrap().unwrap();
+    assert_eq!(listing, fake_tests.listing());
+
+    // remove bar
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![FakeTestCase {
+                name: "test_it".into(),
+                ..Default::default()
+            }],
+        }],
+    };
+
+    run_all_tests_sync(
+        &tmp_dir,
+        fake_tests.clone(),
+        false.into(),
+        vec!["all".into()],
+        vec![],
+    );
+
+    // new listing should match
+    let listing: TestListing = load_test_listing(&path).unwrap().unwrap();
+    assert_eq!(listing, fake_tests.listing());
+}
+
+#[test]
+fn previously_failed_selects_last_failures() {
+    let tmp_dir = tempdir().unwrap();
+    let outcome = |status| {
+        JobOutcome::Completed(JobCompleted {
+            status,
+            effects: JobEffects {
+                stdout: JobOutputResult::None,
+                stderr: JobOutputResult::None,
+                duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
+            },
+        })
+    };
+    let fake_tests = |test_b_status| FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![
+                FakeTestCase {
+                    name: "test_a".into(),
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_b".into(),
+                    outcome: outcome(test_b_status),
+                    ..Default::default()
+                },
+            ],
+        }],
+    };
+    let path = tmp_dir.path().join("workspace/target/maelstrom");
+    let failed_cases = || {
+        let listing = load_test_listing(&path).unwrap().unwrap();
+        listing.packages["foo"].artifacts[&ArtifactKey {
+            name: "foo".into(),
+            kind: ArtifactKind::Library,
+        }]
+            .failed_cases
+            .clone()
+    };
+
+    // Without any history, nothing has previously failed.
+    assert_eq!(
+        run_all_tests_sync(
+            &tmp_dir,
+            fake_tests(JobStatus::Exited(1)),
+            false.into(),
+            vec!["previously_failed".into()],
+            vec![],
+        ),
+        "\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         0\n\
+        Failed Tests    :         0\
+        "
+    );
+
+    run_all_tests_sync(
+        &tmp_dir,
+        fake_tests(JobStatus::Exited(1)),
+        false.into(),
+        vec!["all".into()],
+        vec![],
+    );
+    assert_eq!(failed_cases(), vec!["test_b".to_owned()]);
+
+    assert_eq!(
+        run_all_tests_sync(
+            &tmp_dir,
+            fake_tests(JobStatus::Exited(0)),
+            false.into(),
+            vec!["previously_failed".into()],
+            vec![],
+        ),
+        "\
+        foo test_b.............................OK   1.000s\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         1\n\
+        Failed Tests    :         0\
+        "
+    );
+    assert_eq!(failed_cases(), Vec::<String>::new());
+}
+
+#[test]
+fn explain_selection_uses_cached_listing() {
+    let tmp_dir = tempdir().unwrap();
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    ..Default::default()
+                }],
+            },
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![
+                    FakeTestCase {
+                        name: "test_it".into(),
+                        ..Default::default()
+                    },
+                    FakeTestCase {
+                        name: "test_slow".into(),
+                        ..Default::default()
+                    },
+                ],
+            },
+        ],
+    };
+    let packages = fake_tests.packages();
+    let cache_dir = tmp_dir.path().join("workspace/target/maelstrom");
+    let explain = |package_scope| {
+        let mut out = vec![];
+        explain_selection(
+            &cache_dir,
+            &["package.equals(foo)".into(), "name.equals(test_it)".into()],
+            &["name.contains(slow)".into()],
+            &package_scope,
+            &Vec::from_iter(packages.iter()),
+            &mut out,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    // Nothing has been listed yet, so there is nothing to explain.
+    assert_eq!(explain(PackageScope::Workspace), "");
+
+    run_all_tests_sync(
+        &tmp_dir,
+        fake_tests,
+        false.into(),
+        vec!["all".into()],
+        vec![],
+    );
+    assert_eq!(
+        explain(PackageScope::Workspace),
+        "\
+        bar test_it: selected by include `name.equals(test_it)`\n\
+        foo test_it: selected by include `package.equals(foo)`\n\
+        foo test_slow: excluded by `name.contains(slow)` (included by `package.equals(foo)`)\n\
+        "
+    );
+    assert_eq!(
+        explain(PackageScope::Packages(vec!["bar".into()])),
+        "bar test_it: selected by include `name.equals(test_it)`\n"
+    );
+}
+
+#[test]
+fn expected_count_updates_cases() {
+    let tmp_dir = tempdir().unwrap();
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![FakeTestCase {
+                name: "test_it".into(),
+                ..Default::default()
+            }],
+        }],
+    };
+    run_all_tests_sync(
+        &tmp_dir,
+        fake_tests.clone(),
+        false.into(),
+        vec!["all".into()],
+        vec![],
+    );
+
+    let path = tmp_dir.path().join("workspace/target/maelstrom");
+    let listing: TestListing = load_test_listing(&path).unwrap().unwrap();
+    assert_eq!(listing, fake_tests.listing());
+
+    // remove the test
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![],
+        }],
+    };
+
+    run_all_tests_sync(
+        &tmp_dir,
+        fake_tests.clone(),
+        false.into(),
+        vec!["all".into()],
+        vec![],
+    );
+
+    // new listing should match
+    let listing: TestListing = load_test_listing(&path).unwrap().unwrap();
+    assert_eq!(listing, fake_tests.listing());
+}
+
+#[test]
+fn expected_count_prunes_removed_case() {
+    let tmp_dir = tempdir().unwrap();
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![
+                    FakeTestCase {
+                        name: "test_it".into(),
+                        ..Default::default()
+                    },
+                    FakeTestCase {
+                        name: "test_it2".into(),
+                        ..Default::default()
+                    },
+                ],
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+    run_all_tests_sync(
+        &tmp_dir,
+        fake_tests.clone(),
+        false.into(),
+        vec!["all".into()],
+        vec![],
+    );
+
+    let path = tmp_dir.path().join("workspace/target/maelstrom");
+    let listing: TestListing = load_test_listing(&path).unwrap().unwrap();
+    assert_eq!(listing, fake_tests.listing());
+
+    // remove one of foo's tests, leaving the other and bar's alone
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    ..Default::default()
+                }],
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_it".into(),
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+
+    run_all_tests_sync(
+        &tmp_dir,
+        fake_tests.clone(),
+        false.into(),
+        vec!["all".into()],
+        vec![],
+    );
+
+    // the stale case should be gone
+    let listing: TestListing = load_test_listing(&path).unwrap().unwrap();
+    assert_eq!(listing, fake_tests.listing());
+}
+
+#[test]
+fn load_test_listing_migrates_v1() {
+    let tmp_dir = tempdir().unwrap();
+    let path = tmp_dir.path();
+    Fs::new()
+        .write(
+            path.join("test-listing.toml"),
+            "\
+            version = 1\n\
+            \n\
+            [[foo.artifacts]]\n\
+            name = \"foo\"\n\
+            kind = \"Library\"\n\
+            cases = [\"test_it\", \"test_it2\"]\n\
+            \n\
+            [[foo.artifacts]]\n\
+            name = \"foo\"\n\
+            kind = \"Binary\"\n\
+            cases = []\n\
+            ",
+        )
+        .unwrap();
+
+    let listing = load_test_listing(&path).unwrap().unwrap();
+    assert_eq!(
+        listing,
+        TestListing {
+            version: TestListingVersion::V4,
+            packages: [(
+                "foo".into(),
+                Package {
+                    artifacts: [
+                        (
+                            ArtifactKey {
+                                name: "foo".into(),
+                                kind: ArtifactKind::Library,
+                            },
+                            ArtifactCases {
+                                cases: vec!["test_it".into(), "test_it2".into()],
+                                ignored_cases: vec![],
+                                failed_cases: vec![],
+                                durations: BTreeMap::new(),
+                            },
+                        ),
+                        (
+                            ArtifactKey {
+                                name: "foo".into(),
+                                kind: ArtifactKind::Binary,
+                            },
+                            ArtifactCases::default(),
+                        ),
+                    ]
+                    .into_iter()
+                    .collect(),
+                },
+            )]
+            .into_iter()
+            .collect(),
+        }
+    );
+}
+
+#[test]
+fn load_test_listing_discards_v0() {
+    let tmp_dir = tempdir().unwrap();
+    let path = tmp_dir.path();
+    Fs::new()
+        .write(path.join("test-listing.toml"), "version = 0\n")
+        .unwrap();
+    assert_eq!(load_test_listing(path).unwrap(), None);
+}
+
+#[test]
+fn filtering_none_does_not_build() {
+    let tmp_dir = tempdir().unwrap();
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![FakeTestCase {
+                name: "test_it".into(),
+                ..Default::default()
+            }],
+        }],
+    };
+    run_all_tests_sync(
+        &tmp_dir,
+        fake_tests.clone(),
+        false.into(),
+        vec!["none".into()],
+        vec![],
+    );
+
+    let fs = Fs::new();
+    let cache_dir = tmp_dir.path().join("workspace/target/maelstrom");
+    let mut entries: Vec<_> = fs
+        .read_dir(cache_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, vec!["test-listing.toml".to_owned(),]);
+}
+
+#[test]
+fn cache_dir_override() {
+    let tmp_dir = tempdir().unwrap();
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![FakeTestCase {
+                name: "test_it".into(),
+                ..Default::default()
+            }],
+        }],
+    };
+    let workspace = tmp_dir.path().join("workspace");
+    let cache_dir = tmp_dir.path().join("fast-disk/cache");
+    run_app(
+        &tmp_dir,
+        InMemoryTerm::new(50, 50),
+        fake_tests.clone(),
+        &workspace,
+        RunAppOptions {
+            cache_dir: Some(&cache_dir),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let listing: TestListing = load_test_listing(&cache_dir).unwrap().unwrap();
+    assert_eq!(listing, fake_tests.listing());
+    assert!(!workspace.join("target/maelstrom").exists());
+}
+
+#[test]
+fn layer_cache_adds_each_layer_once() {
+    let cache = LayerCache::default();
+    let added = RefCell::new(vec![]);
+    let add = |layer: Layer| {
+        added.borrow_mut().push(layer.clone());
+        let digest = match layer {
+            Layer::Tar { .. } => digest!(1),
+            _ => digest!(2),
+        };
+        Ok((digest, ArtifactType::Tar))
+    };
+    let tar = Layer::Tar {
+        path: "foo.tar".into(),
+    };
+    let stubs = Layer::Stubs {
+        stubs: vec!["/dev/null".into()],
+    };
+
+    for _ in 0..10 {
+        assert_eq!(
+            cache.get_or_add(tar.clone(), add).unwrap(),
+            (digest!(1), ArtifactType::Tar)
+        );
+        assert_eq!(
+            cache.get_or_add(stubs.clone(), add).unwrap(),
+            (digest!(2), ArtifactType::Tar)
+        );
+    }
+    assert_eq!(added.into_inner(), vec![tar, stubs]);
+}
+
+#[test]
+fn layer_cache_does_not_cache_errors() {
+    let cache = LayerCache::default();
+    let tar = Layer::Tar {
+        path: "foo.tar".into(),
+    };
+    cache
+        .get_or_add(tar.clone(), |_| Err(anyhow::anyhow!("failed")))
+        .unwrap_err();
+    assert_eq!(
+        cache
+            .get_or_add(tar, |_| Ok((digest!(1), ArtifactType::Tar)))
+            .unwrap(),
+        (digest!(1), ArtifactType::Tar)
+    );
+}
+
+#[test]
+fn test_listing_round_trips() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![
+                    FakeTestCase {
+                        name: "test_it".into(),
+                        ..Default::default()
+                    },
+                    FakeTestCase {
+                        name: "test_it2".into(),
+                        ignored: true,
+                        ..Default::default()
+                    },
+                ],
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![],
+            },
+        ],
+    };
+    let listing = fake_tests.listing();
+
+    let tmp_dir = tempdir().unwrap();
+    let path = tmp_dir.path();
+    let fs = Fs::new();
+    let file_names = || {
+        let mut entries: Vec<_> = fs
+            .read_dir(path)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        entries.sort();
+        entries
+    };
+    for format in [TestListingFormat::Toml, TestListingFormat::Cbor] {
+        write_test_listing(path, &listing, format).unwrap();
+        assert_eq!(load_test_listing(path).unwrap().unwrap(), listing);
+
+        // Writing in one format removes the listing in the other.
+        assert_eq!(file_names(), vec![format.file_name().to_owned()]);
+    }
+
+    // The CBOR encoding is detected by its magic header, so one written under the TOML file name
+    // by an older version still loads.
+    fs.rename(
+        path.join("test-listing.cbor"),
+        path.join("test-listing.toml"),
+    )
+    .unwrap();
+    let contents = fs
+        .read_if_exists(path.join("test-listing.toml"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(contents[..3], [0xd9, 0xd9, 0xf7]);
+    assert_eq!(load_test_listing(path).unwrap().unwrap(), listing);
+}
+
+#[test]
+fn test_output_saved_to_dir() {
+    let tmp_dir = tempdir().unwrap();
+    let outcome = |stdout: &[u8], stderr: &[u8]| {
+        let output = |bytes: &[u8]| {
+            if bytes.is_empty() {
+                JobOutputResult::None
+            } else {
+                JobOutputResult::Inline(bytes.into())
+            }
+        };
+        JobOutcome::Completed(JobCompleted {
+            status: JobStatus::Exited(0),
+            effects: JobEffects {
+                stdout: output(stdout),
+                stderr: output(stderr),
+                duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
+            },
+        })
+    };
+    let mut fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![
+                FakeTestCase {
+                    name: "test_both".into(),
+                    outcome: outcome(b"out\n", b"err\n"),
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_stdout".into(),
+                    outcome: outcome(b"only out\n", b""),
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_quiet".into(),
+                    outcome: outcome(b"", b""),
+                    ..Default::default()
+                },
+            ],
+        }],
+    };
+    // The same case name in another package doesn't overwrite the first one's output.
+    fake_tests.test_binaries.push(FakeTestBinary {
+        name: "bar".into(),
+        tests: vec![FakeTestCase {
+            name: "test_both".into(),
+            outcome: outcome(b"bar out\n", b""),
+            ..Default::default()
+        }],
+    });
+    let output_dir = tmp_dir.path().join("output");
+    let fs = Fs::new();
+
+    // A file left over from an earlier run should be removed if the test no longer has output.
+    fs.create_dir_all(output_dir.join("foo/foo")).unwrap();
+    fs.write(output_dir.join("foo/foo/test_quiet.stdout"), "stale")
+        .unwrap();
+
+    run_app(
+        &tmp_dir,
+        InMemoryTerm::new(50, 50),
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                test_output_dir: Some(output_dir.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let files = |dir: &str| {
+        let mut files = Vec::from_iter(
+            fs.read_dir(output_dir.join(dir))
+                .unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap()),
+        );
+        files.sort();
+        files
+    };
+    assert_eq!(
+        files("foo/foo"),
+        ["test_both.stderr", "test_both.stdout", "test_stdout.stdout"]
+    );
+    assert_eq!(files("bar/bar"), ["test_both.stdout"]);
+    let contents = |name| fs.read_to_string(output_dir.join(name)).unwrap();
+    assert_eq!(contents("foo/foo/test_both.stdout"), "out\n");
+    assert_eq!(contents("foo/foo/test_both.stderr"), "err\n");
+    assert_eq!(contents("foo/foo/test_stdout.stdout"), "only out\n");
+    assert_eq!(contents("bar/bar/test_both.stdout"), "bar out\n");
+}
+
+#[test]
+fn large_output_written_to_file() {
+    let large_output = "large output\n".repeat(100);
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![
+                FakeTestCase {
+                    name: "test_large".into(),
+                    outcome: JobOutcome::Completed(JobCompleted {
+                        status: JobStatus::Exited(1),
+                        effects: JobEffects {
+                            stdout: JobOutputResult::None,
+                            stderr: JobOutputResult::Inline(large_output.as_bytes().into()),
+                            duration: std::time::Duration::from_secs(1),
+                            resource_usage: Default::default(),
+                        },
+                    }),
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_small".into(),
+                    outcome: JobOutcome::Completed(JobCompleted {
+                        status: JobStatus::Exited(1),
+                        effects: JobEffects {
+                            stdout: JobOutputResult::None,
+                            stderr: JobOutputResult::Inline(Box::new(*b"small output")),
+                            duration: std::time::Duration::from_secs(1),
+                            resource_usage: Default::default(),
+                        },
+                    }),
+                    ..Default::default()
+                },
+            ],
+        }],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let contents = run_app(
+        &tmp_dir,
+        InMemoryTerm::new(50, 500),
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                large_output_threshold: Some(LargeOutputThreshold::from_bytes(100)),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let lines = Vec::from_iter(contents.lines());
+    assert!(lines.contains(&"stderr: small output"), "{contents}");
+    let (size, path) = lines
+        .iter()
+        .find_map(|line| {
+            line.strip_prefix("stderr: job ")?
+                .split_once(": stderr is ")?
+                .1
+                .split_once(" bytes, written to ")
+        })
+        .unwrap_or_else(|| panic!("no path printed: {contents}"));
+    assert_eq!(size, large_output.len().to_string());
+    assert!(!contents.contains("large output"), "{contents}");
+
+    let fs = Fs::new();
+    assert_eq!(fs.read_to_string(path).unwrap(), large_output);
+    fs.remove_file(path).unwrap();
+}
+
+#[test]
+fn non_utf8_output_hex_dumped() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![
+                FakeTestCase {
+                    name: "test_binary".into(),
+                    outcome: JobOutcome::Completed(JobCompleted {
+                        status: JobStatus::Exited(1),
+                        effects: JobEffects {
+                            stdout: JobOutputResult::None,
+                            stderr: JobOutputResult::Inline(Box::new(*b"bad \xff\xfe byte")),
+                            duration: std::time::Duration::from_secs(1),
+                            resource_usage: Default::default(),
+                        },
+                    }),
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_text".into(),
+                    outcome: JobOutcome::Completed(JobCompleted {
+                        status: JobStatus::Exited(1),
+                        effects: JobEffects {
+                            stdout: JobOutputResult::None,
+                            stderr: JobOutputResult::Inline(Box::new(*b"plain text")),
+                            duration: std::time::Duration::from_secs(1),
+                            resource_usage: Default::default(),
+                        },
+                    }),
+                    ..Default::default()
+                },
+            ],
+        }],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let contents = run_app(
+        &tmp_dir,
+        InMemoryTerm::new(50, 500),
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                output_encoding: OutputEncoding::Hex,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let lines = Vec::from_iter(contents.lines());
+    assert!(lines.contains(&"stderr: plain text"), "{contents}");
+    assert!(
+        lines.contains(
+            &"stderr: 00000000  62 61 64 20 ff fe 20 62 79 74 65                 |bad .. byte|"
+        ),
+        "{contents}"
+    );
+}
+
+#[test]
+fn output_encoding_parse() {
+    assert_eq!(
+        "hex".parse::<OutputEncoding>().unwrap(),
+        OutputEncoding::Hex
+    );
+    assert_eq!(
+        "utf-16le".parse::<OutputEncoding>().unwrap(),
+        OutputEncoding::Utf16Le
+    );
+    assert_eq!(
+        "latin1".parse::<OutputEncoding>().unwrap_err().to_string(),
+        "unknown output encoding \"latin1\", expected \"lossy\", \"hex\", \"utf-16le\", or \
+        \"utf-16be\""
+    );
+}
+
+fn run_with_setup_and_teardown(setup_code: u8, teardown_code: u8) -> (Result<String>, String) {
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![FakeTestCase {
+                name: "test_it".into(),
+                ..Default::default()
+            }],
+        }],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let workspace = tmp_dir.path().join("workspace");
+    let fs = Fs::new();
+    fs.create_dir_all(&workspace).unwrap();
+    fs.write(
+        workspace.join("maelstrom-test.toml"),
+        format!(
+            r#"
+            directives = []
+
+            [setup]
+            program = "/setup"
+            arguments = ["{setup_code}"]
+            layers = [{{ tar = "setup.tar" }}]
+
+            [teardown]
+            program = "/teardown"
+            arguments = ["{teardown_code}"]
+            layers = [{{ tar = "teardown.tar" }}]
+            "#
+        ),
+    )
+    .unwrap();
+
+    let term = InMemoryTerm::new(50, 50);
+    let res = run_app(
+        &tmp_dir,
+        term.clone(),
+        fake_tests,
+        &workspace,
+        RunAppOptions::default(),
+    );
+    (res, term.contents())
+}
+
+#[test]
+fn setup_and_teardown_jobs() {
+    let (res, contents) = run_with_setup_and_teardown(0, 0);
+    res.unwrap();
+    assert_eq!(
+        contents,
+        "\
+        setup job succeeded\n\
+        foo test_it............................OK   1.000s\n\
+        teardown job succeeded\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         1\n\
+        Failed Tests    :         0\
+        "
+    );
+}
+
+#[test]
+fn setup_job_gets_metadata_from_directives() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![FakeTestCase {
+                name: "test_it".into(),
+                ..Default::default()
+            }],
+        }],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let workspace = tmp_dir.path().join("workspace");
+    let fs = Fs::new();
+    fs.create_dir_all(&workspace).unwrap();
+    fs.write(
+        workspace.join("maelstrom-test.toml"),
+        r#"
+        [[directives]]
+        working_directory = "/work"
+        user = 1000
+        timeout = 60
+
+        [[directives]]
+        filter = "package.equals(foo)"
+        user = 2000
+
+        [setup]
+        program = "/setup"
+        arguments = ["0"]
+        layers = [{ tar = "setup.tar" }]
+        "#,
+    )
+    .unwrap();
+
+    let state = test_main_app_state(
+        &tmp_dir,
+        fake_tests,
+        &workspace,
+        RunAppOptions::default(),
+        |_| {},
+    )
+    .unwrap();
+    std::thread::scope(|scope| {
+        let mut app = main_app_new(
+            &state,
+            false, // stdout_tty
+            Quiet::from(true),
+            InMemoryTerm::new(50, 50),
+            TestProgressDriver::default(),
+            scope,
+            None,
+            None,
+        )
+        .unwrap();
+        app.run_to_completion().unwrap();
+    });
+
+    // Only the directive without a filter applies to the setup job.
+    let added_jobs = state.deps.added_jobs.lock().unwrap();
+    let setup = &added_jobs[0];
+    assert_eq!(setup.program, "/setup");
+    assert_eq!(setup.working_directory, "/work");
+    assert_eq!(setup.user, 1000.into());
+    assert_eq!(setup.timeout, Timeout::new(60));
+    let test = &added_jobs[1];
+    assert_eq!(test.user, 2000.into());
+}
+
+#[test]
+fn failed_setup_job_prevents_tests() {
+    let (res, contents) = run_with_setup_and_teardown(1, 0);
+    assert_eq!(
+        res.unwrap_err().to_string(),
+        "setup job failed: exited with code 1"
+    );
+    assert_eq!(contents, "stderr: setup output");
+}
+
+#[test]
+fn failed_teardown_job_still_prints_summary() {
+    let (res, contents) = run_with_setup_and_teardown(0, 2);
+    assert_eq!(
+        res.unwrap_err().to_string(),
+        "teardown job failed: exited with code 2"
+    );
+    assert_eq!(
+        contents,
+        "\
+        setup job succeeded\n\
+        foo test_it............................OK   1.000s\n\
+        stderr: teardown output\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         1\n\
+        Failed Tests    :         0\
+        "
+    );
+}
+
+#[test]
+fn report_time_format() {
+    let outcome = |status| {
+        JobOutcome::Completed(JobCompleted {
+            status,
+            effects: JobEffects {
+                stdout: JobOutputResult::None,
+                stderr: JobOutputResult::Inline(Box::new(*b"error output")),
+                duration: std::time::Duration::from_millis(1234),
+                resource_usage: Default::default(),
+            },
+        })
+    };
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![
+                FakeTestCase {
+                    name: "test_fails".into(),
+                    outcome: outcome(JobStatus::Exited(1)),
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_ignored".into(),
+                    ignored: true,
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_passes".into(),
+                    outcome: outcome(JobStatus::Exited(0)),
+                    ..Default::default()
+                },
+            ],
+        }],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let term = InMemoryTerm::new(50, 50);
+    run_app(
+        &tmp_dir,
+        term.clone(),
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                report_time: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        term.contents(),
+        "\
+        test foo test_fails ... FAILED <1.234s>\n\
+        stderr: error output\n\
+        test foo test_ignored ... ignored\n\
+        test foo test_passes ... ok <1.234s>\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         1\n\
+        Failed Tests    :         1\n\
+        \x20\x20\x20\x20foo test_fails: failure\n\
+        Ignored Tests   :         1\n\
+        \x20\x20\x20\x20foo test_ignored: ignored\
+        "
+    );
+}
+
+#[test]
+fn report_time_format_for_retried_test() {
+    let outcome = |status| {
+        JobOutcome::Completed(JobCompleted {
+            status,
+            effects: JobEffects {
+                stdout: JobOutputResult::None,
+                stderr: JobOutputResult::None,
+                duration: std::time::Duration::from_millis(1234),
+                resource_usage: Default::default(),
+            },
+        })
+    };
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![FakeTestCase {
+                name: "test_flaky".into(),
+                earlier_outcomes: vec![outcome(JobStatus::Exited(1))],
+                outcome: outcome(JobStatus::Exited(0)),
+                ..Default::default()
+            }],
+        }],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let term = InMemoryTerm::new(50, 50);
+    run_app(
+        &tmp_dir,
+        term.clone(),
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                report_time: true,
+                retries: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // The attempt that is retried isn't reported as a failure.
+    let contents = term.contents();
+    assert_eq!(
+        Vec::from_iter(contents.lines().take(3)),
+        vec![
+            "test foo test_flaky ... retrying <1.234s>",
+            "exited with code 1, retrying (1 of 1)",
+            "test foo test_flaky ... ok <1.234s>",
+        ],
+        "{contents}"
+    );
+}
+
+#[test]
+fn lost_tests_reported_after_deadline() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![
+                FakeTestCase {
+                    name: "test_lost".into(),
+                    desired_state: JobState::Running,
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_passes".into(),
+                    ..Default::default()
+                },
+            ],
+        }],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let term = InMemoryTerm::new(50, 50);
+    run_app(
+        &tmp_dir,
+        term.clone(),
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                lost_test_deadline: Some(Duration::from_millis(10)),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        term.contents(),
+        "\
+        foo test_passes........................OK   1.000s\n\
+        foo test_lost........................LOST\n\
+        no result after 0.010s\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         1\n\
+        Failed Tests    :         0\n\
+        Lost Tests      :         1\n\
+        \x20\x20\x20\x20foo test_lost: lost\
+        "
+    );
+}
+
+#[test]
+fn exit_code_severities() {
+    let exited = |code| {
+        JobOutcome::Completed(JobCompleted {
+            status: JobStatus::Exited(code),
+            effects: JobEffects {
+                stdout: JobOutputResult::None,
+                stderr: JobOutputResult::None,
+                duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
+            },
+        })
+    };
+    let fake_tests = FakeTests {
+        test_binaries: vec![FakeTestBinary {
+            name: "foo".into(),
+            tests: vec![
+                FakeTestCase {
+                    name: "test_skipped".into(),
+                    outcome: exited(77),
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_passes".into(),
+                    outcome: exited(3),
+                    ..Default::default()
+                },
+                FakeTestCase {
+                    name: "test_fails".into(),
+                    outcome: exited(1),
+                    ..Default::default()
+                },
+            ],
+        }],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let term = InMemoryTerm::new(50, 50);
+    run_app(
+        &tmp_dir,
+        term.clone(),
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                exit_code_severities: Some("77=skip, 3=pass".parse().unwrap()),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        term.contents(),
+        "\
+        foo test_skipped..................IGNORED   1.000s\n\
+        exited with code 77\n\
+        foo test_passes........................OK   1.000s\n\
+        foo test_fails.......................FAIL   1.000s\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         1\n\
+        Failed Tests    :         1\n\
+        \x20\x20\x20\x20foo test_fails: failure\n\
+        Ignored Tests   :         1\n\
+        \x20\x20\x20\x20foo test_skipped: ignored\
+        "
+    );
+}
+
+#[test]
+fn exit_code_severities_parse_errors() {
+    assert_eq!(
+        "77".parse::<ExitCodeSeverities>().unwrap_err().to_string(),
+        "expected CODE=SEVERITY, got \"77\""
+    );
+    assert_eq!(
+        "256=skip"
+            .parse::<ExitCodeSeverities>()
+            .unwrap_err()
+            .to_string(),
+        "invalid exit code \"256\": number too large to fit in target type"
+    );
+    assert_eq!(
+        "77=maybe"
+            .parse::<ExitCodeSeverities>()
+            .unwrap_err()
+            .to_string(),
+        "unknown severity \"maybe\", expected \"pass\", \"fail\", or \"skip\""
+    );
+}
+
+#[test]
+fn tests_from_file() {
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![
+                    FakeTestCase {
+                        name: "test_a".into(),
+                        ..Default::default()
+                    },
+                    FakeTestCase {
+                        name: "test_b".into(),
+                        ..Default::default()
+                    },
+                ],
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_c".into(),
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    let tests_from = tmp_dir.path().join("tests.txt");
+    Fs::new()
+        .write(&tests_from, "test_b\n\ntest_c\ntest_missing\n")
+        .unwrap();
+    let term = InMemoryTerm::new(50, 50);
+    run_app(
+        &tmp_dir,
+        term.clone(),
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            tests_from: Some(&tests_from),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        term.contents(),
+        "\
+        foo test_b.............................OK   1.000s\n\
+        bar test_c.............................OK   1.000s\n\
+        unknown test case: test_missing\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         2\n\
+        Failed Tests    :         0\
+        "
+    );
+}
+
+fn run_with_package_scope(package_scope: PackageScope) -> Result<String> {
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_a".into(),
+                    ..Default::default()
+                }],
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: vec![FakeTestCase {
+                    name: "test_b".into(),
+                    ..Default::default()
+                }],
+            },
+        ],
+    };
+
+    let tmp_dir = tempdir().unwrap();
+    run_app(
+        &tmp_dir,
+        InMemoryTerm::new(50, 50),
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            package_scope,
+            ..Default::default()
+        },
+    )
+}
+
+#[test]
+fn package_scope_current_package() {
+    assert_eq!(
+        run_with_package_scope(PackageScope::Packages(vec!["bar".into()])).unwrap(),
+        "\
+        bar test_b.............................OK   1.000s\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         1\n\
+        Failed Tests    :         0\
+        "
+    );
+}
+
+#[test]
+fn package_scope_workspace() {
+    assert_eq!(
+        run_with_package_scope(PackageScope::Workspace).unwrap(),
+        "\
+        foo test_a.............................OK   1.000s\n\
+        bar test_b.............................OK   1.000s\n\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         2\n\
+        Failed Tests    :         0\
+        "
+    );
+}
+
+#[test]
+fn package_scope_unknown_package() {
+    assert_eq!(
+        run_with_package_scope(PackageScope::Packages(vec!["baz".into()]))
+            .unwrap_err()
+            .to_string(),
+        "package `baz` not found in workspace"
+    );
+}
+
+#[derive(Clone, Default)]
+struct OutstandingJobsRecorder {
+    counts: Arc<Mutex<Vec<u64>>>,
+}
+
+impl ProgressIndicator for OutstandingJobsRecorder {
+    fn println(&self, _msg: String) {}
+
+    fn update_outstanding_jobs(&self, outstanding: u64) {
+        self.counts.lock().unwrap().push(outstanding);
+    }
+}
+
+#[test]
+fn outstanding_jobs_reported_while_draining() {
+    let ind = OutstandingJobsRecorder::default();
+    let tracker = Arc::new(JobStatusTracker::default());
+    let pending_jobs = PendingJobs::default();
+    let ids = Vec::from_iter((0..4).map(|i| {
+        let visitor = JobStatusVisitor::new(
+            tracker.clone(),
+            CaseName::new("foo", "foo", format!("test_{i}")),
+            None,  // location
+            false, // print_location
+            50,    // width
+            ind.clone(),
+            RetryPolicy::default(),
+            0,     // failure_retries
+            false, // allow_failure
+            None,  // output_dir
+            None,  // large_output_threshold
+            false, // report_time
+            false, // github_annotations
+            ExitCodeSeverities::default(),
+            OutputEncoding::default(),
+        );
+        pending_jobs.add(
+            JobSpec::new("foo", nonempty![(digest!(1), ArtifactType::Tar)]),
+            visitor,
+        )
+    }));
+    let outcome = FakeTestCase::default().outcome;
+
+    // Jobs that finish while we're still queuing aren't reported.
+    pending_jobs.job_finished(ids[0], Ok((ClientJobId::from_u32(0), Ok(outcome.clone()))));
+    assert_eq!(*ind.counts.lock().unwrap(), Vec::<u64>::new());
+
+    pending_jobs.start_draining(ind.clone());
+    for (cjid, id) in ids.into_iter().enumerate().skip(1) {
+        pending_jobs.job_finished(
+            id,
+            Ok((ClientJobId::from_u32(cjid as u32), Ok(outcome.clone()))),
+        );
+    }
+    assert_eq!(*ind.counts.lock().unwrap(), vec![3, 2, 1, 0]);
+    assert!(pending_jobs.wait().is_empty());
+}
+
+fn pending_job_visitor(
+    tracker: &Arc<JobStatusTracker>,
+    ind: &OutstandingJobsRecorder,
+) -> JobStatusVisitor<OutstandingJobsRecorder> {
+    JobStatusVisitor::new(
+        tracker.clone(),
+        CaseName::new("foo", "foo", "test_1"),
+        None,  // location
+        false, // print_location
+        50,    // width
+        ind.clone(),
+        RetryPolicy::default(),
+        0,     // failure_retries
+        false, // allow_failure
+        None,  // output_dir
+        None,  // large_output_threshold
+        false, // report_time
+        false, // github_annotations
+        ExitCodeSeverities::default(),
+        OutputEncoding::default(),
+    )
+}
+
+#[test]
+fn job_result_error_reported_as_failure() {
+    let ind = OutstandingJobsRecorder::default();
+    let tracker = Arc::new(JobStatusTracker::default());
+    let pending_jobs = PendingJobs::default();
+    let id = pending_jobs.add(
+        JobSpec::new("foo", nonempty![(digest!(1), ArtifactType::Tar)]),
+        pending_job_visitor(&tracker, &ind),
+    );
+
+    pending_jobs.job_finished(id, Err(anyhow!("client process went away")));
+    assert!(pending_jobs.wait().is_empty());
+    assert_eq!(tracker.exit_code(), ExitCode::FAILURE);
+}
+
+#[test]
+fn lost_deadline_starts_when_job_starts_running() {
+    let ind = OutstandingJobsRecorder::default();
+    let tracker = Arc::new(JobStatusTracker::default());
+    let pending_jobs = PendingJobs::default();
+    let id = pending_jobs.add(
+        JobSpec::new("foo", nonempty![(digest!(1), ArtifactType::Tar)]),
+        pending_job_visitor(&tracker, &ind),
+    );
+
+    let (send, recv) = std::sync::mpsc::channel();
+    pending_jobs.start_watching();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            pending_jobs
+                .watch_for_lost_jobs(Duration::from_millis(10), |cjid| send.send(cjid).unwrap())
+        });
+
+        // The job hasn't started running, so it can wait for as long as it needs to.
+        assert_eq!(
+            recv.recv_timeout(Duration::from_millis(100)),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+        );
+
+        // Once it is running, it is lost and canceled after the deadline.
+        pending_jobs.job_running(id, ClientJobId::from_u32(7));
+        assert_eq!(recv.recv().unwrap(), ClientJobId::from_u32(7));
+        pending_jobs.stop_watching();
+    });
+
+    assert!(pending_jobs.wait().is_empty());
+    assert_eq!(tracker.exit_code(), ExitCode::FAILURE);
+}
+
+#[test]
+fn job_submission_retried_after_error() {
+    let log = test_logger();
+    let spec = JobSpec {
+        program: "/setup".into(),
+        arguments: vec![],
+        environment: vec![],
+        layers: nonempty![(digest!(42), ArtifactType::Manifest)],
+        devices: Default::default(),
+        mounts: vec![],
+        enable_loopback: false,
+        enable_writable_file_system: false,
+        working_directory: "/".into(),
+        user: 0.into(),
+        group: 0.into(),
+        timeout: None,
+        enable_init_process: false,
+        cpus: 0,
+        memory: 0,
+        stream_output: false,
+        priority: 0,
+    };
+    let submit = |deps: &TestMainAppDeps, tracker: &Arc<JobStatusTracker>, retries| {
+        let visitor = JobStatusVisitor::new(
+            tracker.clone(),
+            CaseName::new("foo", "foo", "test_it"),
+            None,  // location
+            false, // print_location
+            50,    // width
+            OutstandingJobsRecorder::default(),
+            RetryPolicy::default(),
+            0,     // failure_retries
+            false, // allow_failure
+            None,  // output_dir
+            None,  // large_output_threshold
+            false, // report_time
+            false, // github_annotations
+            ExitCodeSeverities::default(),
+            OutputEncoding::default(),
+        );
+        let pending_jobs = Arc::new(PendingJobs::default());
+        let res = submit_job(deps, spec.clone(), visitor, &pending_jobs, retries, &log);
+        assert!(pending_jobs.wait().is_empty());
+        res
+    };
+    let new_deps = |submission_failures, transient| {
+        let deps = TestMainAppDeps::new(
+            FakeTests {
+                test_binaries: vec![],
+            },
+            PathBuf::new(),
+            PathBuf::new(),
+        );
+        deps.submission_failures
+            .store(submission_failures, Ordering::Release);
+        deps.submission_failures_transient
+            .store(transient, Ordering::Release);
+        deps
+    };
+
+    // The first submission fails, but the retry gets the job enqueued and run.
+    let deps = new_deps(1, true);
+    let tracker = Arc::new(JobStatusTracker::default());
+    submit(&deps, &tracker, 1).unwrap();
+    assert_eq!(deps.next_job_id.load(Ordering::Acquire), 2);
+    let summary = tracker.json_summary(Duration::ZERO);
+    assert_eq!((summary.total, summary.passed), (1, 1));
+
+    // Once the retries run out, the error is returned and the job isn't waited for.
+    let deps = new_deps(2, true);
+    let tracker = Arc::new(JobStatusTracker::default());
+    assert_eq!(
+        submit(&deps, &tracker, 1).unwrap_err().to_string(),
+        "client process went away"
+    );
+    assert_eq!(tracker.json_summary(Duration::ZERO).total, 0);
+
+    // Errors that aren't transient aren't retried at all.
+    let deps = new_deps(1, false);
+    let tracker = Arc::new(JobStatusTracker::default());
+    assert_eq!(
+        submit(&deps, &tracker, 1).unwrap_err().to_string(),
+        "client process shut down"
+    );
+    assert_eq!(deps.submission_failures.load(Ordering::Acquire), 0);
+    assert_eq!(deps.next_job_id.load(Ordering::Acquire), 1);
+}
+
+#[test]
+fn allowed_failure_does_not_change_exit_code() {
+    let tracker = Arc::new(JobStatusTracker::default());
+    let failed = JobOutcome::Completed(JobCompleted {
+        status: JobStatus::Exited(1),
+        effects: JobEffects {
+            stdout: JobOutputResult::None,
+            stderr: JobOutputResult::None,
+            duration: std::time::Duration::from_secs(1),
+            resource_usage: Default::default(),
+        },
+    });
+    for (cjid, (case, allow_failure, outcome)) in [
+        ("test_passes", false, FakeTestCase::default().outcome),
+        ("test_known_broken", true, failed),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let visitor = JobStatusVisitor::new(
+            tracker.clone(),
+            CaseName::new("foo", "foo", case),
+            None,  // location
+            false, // print_location
+            50,    // width
+            OutstandingJobsRecorder::default(),
+            RetryPolicy::default(),
+            0, // failure_retries
+            allow_failure,
+            None,  // output_dir
+            None,  // large_output_threshold
+            false, // report_time
+            false, // github_annotations
+            ExitCodeSeverities::default(),
+            OutputEncoding::default(),
+        );
+        assert!(visitor
+            .job_finished(ClientJobId::from_u32(cjid as u32), Ok(outcome))
+            .is_none());
+    }
+    assert_eq!(tracker.exit_code(), ExitCode::SUCCESS);
+
+    let term = InMemoryTerm::new(50, 50);
+    tracker.print_summary(50, false, term.clone()).unwrap();
+    assert_eq!(
+        term.contents(),
+        "\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         1\n\
+        Failed Tests    :         0\n\
+        Allowed Failures:         1\n\
+        \x20\x20\x20\x20foo test_known_broken: failure allowed\
+        "
+    );
+}
+
+#[test]
+fn summary_grouped_by_package() {
+    let tracker = JobStatusTracker::default();
+    tracker.job_exited(
+        CaseName::new("foo", "foo", "test_a"),
+        ExitCode::FAILURE,
+        None,
+    );
+    tracker.job_exited(
+        CaseName::new("bar", "bar", "test_a"),
+        ExitCode::SUCCESS,
+        None,
+    );
+    tracker.job_exited(
+        CaseName::new("bar", "bar", "test_b"),
+        ExitCode::FAILURE,
+        None,
+    );
+    tracker.job_ignored(CaseName::new("foo", "foo", "test_ignored"));
+    tracker.job_exited(
+        CaseName::new("foo", "baz", "test_c"),
+        ExitCode::FAILURE,
+        None,
+    );
+
+    let term = InMemoryTerm::new(50, 50);
+    tracker.print_summary(50, true, term.clone()).unwrap();
+    assert_eq!(
+        term.contents(),
+        "\
+        \n\
+        ================== Test Summary ==================\n\
+        Successful Tests:         1\n\
+        Failed Tests    :         3\n\
+        \x20\x20\x20\x20bar:\n\
+        \x20\x20\x20\x20\x20\x20\x20\x20test_b: failure\n\
+        \x20\x20\x20\x20foo:\n\
+        \x20\x20\x20\x20\x20\x20\x20\x20test_a    : failure\n\
+        \x20\x20\x20\x20\x20\x20\x20\x20baz test_c: failure\n\
+        Ignored Tests   :         1\n\
+        \x20\x20\x20\x20foo:\n\
+        \x20\x20\x20\x20\x20\x20\x20\x20test_ignored: ignored\
+        "
+    );
+}
+
+#[test]
+fn json_summary_counts() {
+    let tracker = Arc::new(JobStatusTracker::default());
+    let exited = |code| {
+        JobOutcome::Completed(JobCompleted {
+            status: JobStatus::Exited(code),
+            effects: JobEffects {
+                stdout: JobOutputResult::None,
+                stderr: JobOutputResult::None,
+                duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
+            },
+        })
+    };
+    let new_visitor = |case: &str, retry_policy, allow_failure| {
+        JobStatusVisitor::new(
+            tracker.clone(),
+            CaseName::new("foo", "foo", case),
+            None,  // location
+            false, // print_location
+            50,    // width
+            OutstandingJobsRecorder::default(),
+            retry_policy,
+            0, // failure_retries
+            allow_failure,
+            None,  // output_dir
+            None,  // large_output_threshold
+            false, // report_time
+            false, // github_annotations
+            ExitCodeSeverities::default(),
+            OutputEncoding::default(),
+        )
+    };
+    let retry_once = RetryPolicy {
+        max_retries: 1,
+        exit_codes: vec![1],
+        signals: vec![],
+    };
+
+    for (case, allow_failure, code) in [
+        ("test_a", false, 0),
+        ("test_b", false, 0),
+        ("test_c", false, 2),
+        ("test_d", true, 1),
+    ] {
+        let visitor = new_visitor(case, RetryPolicy::default(), allow_failure);
+        assert!(visitor
+            .job_finished(ClientJobId::from_u32(1), Ok(exited(code)))
+            .is_none());
+    }
+    let visitor = new_visitor("test_flaky", retry_once, false);
+    let visitor = visitor
+        .job_finished(ClientJobId::from_u32(2), Ok(exited(1)))
+        .unwrap();
+    assert!(visitor
+        .job_finished(ClientJobId::from_u32(3), Ok(exited(0)))
+        .is_none());
+    new_visitor("test_ignored", RetryPolicy::default(), false).job_ignored();
+    tracker.job_lost(
+        CaseName::new("foo", "foo", "test_lost"),
+        CaseFailure::new("no result after 1.000s"),
+    );
+
+    let expected = JsonSummary {
+        total: 7,
+        passed: 3,
+        failed: 2,
+        ignored: 1,
+        allowed_failures: 1,
+        flaky: 1,
+        duration_secs: 1.5,
+        exit_code: 2,
+    };
+
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("summary.json");
+    let term = InMemoryTerm::new(50, 500);
+    write_json_summary(&path, &tracker, Duration::from_millis(1500), &term).unwrap();
+    assert_eq!(term.contents(), "");
+    let summary: JsonSummary =
+        serde_json::from_str(&Fs::new().read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(summary, expected);
+
+    write_json_summary(Path::new("-"), &tracker, Duration::from_millis(1500), &term).unwrap();
+    let summary: JsonSummary = serde_json::from_str(&term.contents()).unwrap();
+    assert_eq!(summary, expected);
+}
+
+#[test]
+fn junit_xml_report() {
+    let tracker = JobStatusTracker::new(None, true);
+    let millis = |millis| Some(Duration::from_millis(millis));
+    tracker.job_exited(
+        CaseName::new("foo", "foo", "test_a"),
+        ExitCode::SUCCESS,
+        millis(1500),
+    );
+    tracker.job_failed(
+        CaseName::new("foo", "baz", "test_b"),
+        ExitCode::FAILURE,
+        millis(250),
+        CaseFailure {
+            reason: "exited with code 1".into(),
+            stdout: "assertion <failed>\n".into(),
+            stderr: "".into(),
+        },
+    );
+    tracker.job_ignored(CaseName::new("foo", "foo", "test_<ignored>"));
+    tracker.job_flaky(
+        CaseName::new("bar", "bar", "test_c"),
+        Duration::from_secs(2),
+        CaseFailure::new("killed by signal 9"),
+    );
+    tracker.job_failure_allowed(
+        CaseName::new("bar", "bar", "test_d"),
+        None,
+        CaseFailure {
+            reason: "exited with code 2".into(),
+            stdout: "".into(),
+            stderr: "known & broken\n".into(),
+        },
+    );
+    tracker.job_lost(
+        CaseName::new("bar", "bar", "test_e"),
+        CaseFailure::new("no result after 10.000s"),
+    );
+
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("junit.xml");
+    tracker.write_junit_xml(&path).unwrap();
+    assert_eq!(
+        Fs::new().read_to_string(&path).unwrap(),
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<testsuites name=\"cargo-maelstrom\" tests=\"6\" failures=\"2\" skipped=\"2\" time=\"3.750\">\n",
+            "  <testsuite name=\"bar\" tests=\"3\" failures=\"1\" skipped=\"1\" time=\"2.000\">\n",
+            "    <testcase name=\"test_c\" classname=\"bar\" time=\"2.000\">\n",
+            "      <flakyFailure message=\"killed by signal 9\"/>\n",
+            "    </testcase>\n",
+            "    <testcase name=\"test_d\" classname=\"bar\">\n",
+            "      <skipped message=\"failure allowed: exited with code 2\"/>\n",
+            "      <system-err>known &amp; broken\n</system-err>\n",
+            "    </testcase>\n",
+            "    <testcase name=\"test_e\" classname=\"bar\">\n",
+            "      <failure message=\"lost: no result after 10.000s\"/>\n",
+            "    </testcase>\n",
+            "  </testsuite>\n",
+            "  <testsuite name=\"foo\" tests=\"3\" failures=\"1\" skipped=\"1\" time=\"1.750\">\n",
+            "    <testcase name=\"test_a\" classname=\"foo\" time=\"1.500\"/>\n",
+            "    <testcase name=\"test_b\" classname=\"baz\" time=\"0.250\">\n",
+            "      <failure message=\"exited with code 1\"/>\n",
+            "      <system-out>assertion &lt;failed&gt;\n</system-out>\n",
+            "    </testcase>\n",
+            "    <testcase name=\"test_&lt;ignored&gt;\" classname=\"foo\">\n",
+            "      <skipped/>\n",
+            "    </testcase>\n",
+            "  </testsuite>\n",
+            "</testsuites>\n",
+        )
+    );
+}
+
+#[test]
+fn junit_xml_report_keeps_output_of_failed_jobs() {
+    let tracker = Arc::new(JobStatusTracker::new(None, true));
+    let visitor = JobStatusVisitor::new(
+        tracker.clone(),
+        CaseName::new("foo", "foo", "test_a"),
+        None,  // location
+        false, // print_location
+        50,    // width
+        OutstandingJobsRecorder::default(),
+        RetryPolicy::default(),
+        0,     // failure_retries
+        false, // allow_failure
+        None,  // output_dir
+        None,  // large_output_threshold
+        false, // report_time
+        false, // github_annotations
+        ExitCodeSeverities::default(),
+        OutputEncoding::default(),
+    );
+    let outcome = JobOutcome::Completed(JobCompleted {
+        status: JobStatus::Exited(1),
+        effects: JobEffects {
+            stdout: JobOutputResult::Inline(Box::new(*b"out\n")),
+            stderr: JobOutputResult::Truncated {
+                first: Box::new(*b"err"),
+                truncated: 10,
+            },
+            duration: Duration::from_secs(1),
+            resource_usage: Default::default(),
+        },
+    });
+    assert!(visitor
+        .job_finished(ClientJobId::from_u32(1), Ok(outcome))
+        .is_none());
+
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("junit.xml");
+    tracker.write_junit_xml(&path).unwrap();
+    let xml = Fs::new().read_to_string(&path).unwrap();
+    assert!(
+        xml.contains(concat!(
+            "      <failure message=\"exited with code 1\"/>\n",
+            "      <system-out>out\n</system-out>\n",
+            "      <system-err>err\n[output truncated, 10 bytes lost]</system-err>\n",
+        )),
+        "{xml}"
+    );
+}
+
+#[test]
+fn cases_enqueued_in_batches() {
+    let tmp_dir = tempdir().unwrap();
+    let case = |name: &str| FakeTestCase {
+        name: name.into(),
+        ..Default::default()
+    };
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: ["test_a", "test_b", "test_c", "test_d", "test_e"]
+                    .into_iter()
+                    .map(case)
+                    .collect(),
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: ["test_a", "test_b"].into_iter().map(case).collect(),
+            },
+        ],
+    };
+    let state = test_main_app_state(
+        &tmp_dir,
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                enqueue_batch_size: NonZeroU32::new(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        |_| {},
+    )
+    .unwrap();
+    std::thread::scope(|scope| {
+        let mut app = main_app_new(
+            &state,
+            false, // stdout_tty
+            Quiet::from(true),
+            InMemoryTerm::new(50, 50),
+            TestProgressDriver::default(),
+            scope,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let mut results = vec![];
+        loop {
+            match app.enqueue_one().unwrap() {
+                EnqueueResult::Done => break,
+                EnqueueResult::Enqueued { case, .. } => results.push(case),
+                EnqueueResult::Yielded => results.push("yield".into()),
+                EnqueueResult::Ignored | EnqueueResult::Listed => unreachable!(),
+            }
+        }
+        app.drain().unwrap();
+        app.finish().unwrap();
+
+        // The batch starts over with each test binary.
+        assert_eq!(
+            results,
+            vec![
+                "test_a", "test_b", "yield", "test_c", "test_d", "yield", "test_e", "test_a",
+                "test_b", "yield",
+            ]
+        );
+    })
+}
+
+#[test]
+fn batches_alternate_between_listed_binaries() {
+    let tmp_dir = tempdir().unwrap();
+    let case = |name: &str| FakeTestCase {
+        name: name.into(),
+        ..Default::default()
+    };
+    let fake_tests = FakeTests {
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: ["test_a", "test_b", "test_c", "test_d", "test_e"]
+                    .into_iter()
+                    .map(case)
+                    .collect(),
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: ["test_a", "test_b"].into_iter().map(case).collect(),
+            },
+        ],
+    };
+    let state = test_main_app_state(
+        &tmp_dir,
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                enqueue_batch_size: NonZeroU32::new(2),
+                list_threads: NonZeroUsize::new(2).unwrap(),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        |deps| {
+            // Make sure foo is the first binary to be listed.
+            deps.listing_gates.insert("bar".into(), ("add_layer", 2));
+        },
+    )
+    .unwrap();
+    std::thread::scope(|scope| {
+        let mut app = main_app_new(
+            &state,
+            false, // stdout_tty
+            Quiet::from(true),
+            InMemoryTerm::new(50, 50),
+            TestProgressDriver::default(),
+            scope,
+            None,
+            None,
+        )
+        .unwrap();
+
+        // Wait until both binaries have been listed and had their layers added, so that both are
+        // waiting to be queued from by the time the first batch is done.
+        while state.deps.calls.lock().unwrap().len() < 4 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+
+        let mut results = vec![];
+        loop {
+            match app.enqueue_one().unwrap() {
+                EnqueueResult::Done => break,
+                EnqueueResult::Enqueued { package_name, case } => {
+                    results.push(format!("{package_name} {case}"))
+                }
+                EnqueueResult::Yielded => results.push("yield".into()),
+                EnqueueResult::Ignored | EnqueueResult::Listed => unreachable!(),
+            }
+        }
+        app.drain().unwrap();
+        app.finish().unwrap();
+
+        // Both binaries are listed together, so once a batch from one is done, the next batch comes
+        // from the other.
+        assert_eq!(
+            results,
+            vec![
+                "foo test_a",
+                "foo test_b",
+                "yield",
+                "bar test_a",
+                "bar test_b",
+                "yield",
+                "foo test_c",
+                "foo test_d",
+                "yield",
+                "foo test_e",
+            ]
+        );
+    })
+}
+
+#[test]
+fn list_threads_lists_binaries_in_parallel() {
+    let run = |list_threads| {
+        let tmp_dir = tempdir().unwrap();
+        let case = |name: &str| FakeTestCase {
+            name: name.into(),
+            ..Default::default()
+        };
+        let fake_tests = FakeTests {
+            test_binaries: vec![
+                FakeTestBinary {
+                    name: "bar".into(),
+                    tests: vec![case("test_a"), case("test_b")],
+                },
+                FakeTestBinary {
+                    name: "baz".into(),
+                    tests: vec![case("test_a")],
+                },
+                FakeTestBinary {
+                    name: "foo".into(),
+                    tests: vec![case("test_a"), case("test_b"), case("test_c")],
+                },
+            ],
+        };
+        let workspace_root = tmp_dir.path().join("workspace");
+        let target_directory = workspace_root.join("target");
+        let state = test_main_app_state(
+            &tmp_dir,
+            fake_tests,
+            &workspace_root,
+            RunAppOptions {
+                main_app_options: MainAppOptions {
+                    list_threads: NonZeroUsize::new(list_threads).unwrap(),
                     ..Default::default()
-                }],
+                },
+                ..Default::default()
             },
-        ],
+            |deps| {
+                deps.list_delay = Duration::from_millis(200);
+            },
+        )
+        .unwrap();
+        std::thread::scope(|scope| {
+            let mut app = main_app_new(
+                &state,
+                false, // stdout_tty
+                Quiet::from(true),
+                InMemoryTerm::new(50, 50),
+                TestProgressDriver::default(),
+                scope,
+                None,
+                None,
+            )
+            .unwrap();
+
+            let mut enqueued = vec![];
+            loop {
+                match app.enqueue_one().unwrap() {
+                    EnqueueResult::Done => break,
+                    EnqueueResult::Enqueued { package_name, case } => {
+                        enqueued.push(format!("{package_name} {case}"))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            app.drain().unwrap();
+            app.finish().unwrap();
+            drop(app);
+
+            let listing = Fs::new()
+                .read_to_string(
+                    target_directory
+                        .join("maelstrom")
+                        .join(TestListingFormat::Toml.file_name()),
+                )
+                .unwrap();
+            let max_listing = state.deps.listing.lock().unwrap().1;
+            (enqueued, listing, max_listing)
+        })
     };
-    run_in_progress_test(
-        fake_tests,
-        true.into(),
-        "#####################-------------------- 1/2 jobs",
-    );
+
+    let (mut serial_enqueued, serial_listing, serial_max_listing) = run(1);
+    assert_eq!(serial_max_listing, 1);
+
+    // The binaries are enqueued from in the order their listing finished, so only compare which
+    // tests were enqueued. The listing that's written out is the same.
+    let (mut enqueued, listing, max_listing) = run(3);
+    assert!(max_listing > 1);
+    enqueued.sort();
+    serial_enqueued.sort();
+    assert_eq!(enqueued, serial_enqueued);
+    assert_eq!(listing, serial_listing);
 }
 
 #[test]
-fn expected_count_updates_packages() {
+fn slow_listing_does_not_hold_up_other_binaries() {
     let tmp_dir = tempdir().unwrap();
+    let case = |name: &str| FakeTestCase {
+        name: name.into(),
+        ..Default::default()
+    };
     let fake_tests = FakeTests {
         test_binaries: vec![
             FakeTestBinary {
-                name: "foo".into(),
-                tests: vec![FakeTestCase {
-                    name: "test_it".into(),
-                    ..Default::default()
-                }],
+                name: "bar".into(),
+                tests: vec![case("test_a"), case("test_b")],
             },
             FakeTestBinary {
-                name: "bar".into(),
-                tests: vec![FakeTestCase {
-                    name: "test_it".into(),
-                    ..Default::default()
-                }],
+                name: "baz".into(),
+                tests: vec![case("test_a")],
+            },
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![case("test_a"), case("test_b"), case("test_c")],
             },
         ],
     };
-    run_all_tests_sync(
+    let state = test_main_app_state(
         &tmp_dir,
-        fake_tests.clone(),
-        false.into(),
-        vec!["all".into()],
-        vec![],
-    );
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                list_threads: NonZeroUsize::new(2).unwrap(),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        |deps| {
+            // Listing bar doesn't finish until the other binaries' tests have been enqueued.
+            deps.listing_gates.insert("bar".into(), ("add_job", 4));
+        },
+    )
+    .unwrap();
+    std::thread::scope(|scope| {
+        let mut app = main_app_new(
+            &state,
+            false, // stdout_tty
+            Quiet::from(true),
+            InMemoryTerm::new(50, 50),
+            TestProgressDriver::default(),
+            scope,
+            None,
+            None,
+        )
+        .unwrap();
 
-    let path = tmp_dir
-        .path()
-        .join("workspace/target/maelstrom")
-        .join(LAST_TEST_LISTING_NAME);
-    let listing: TestListing = load_test_listing(&path).unwrap().unwrap();
-    assert_eq!(listing, fake_tests.listing());
+        let mut enqueued = vec![];
+        loop {
+            match app.enqueue_one().unwrap() {
+                EnqueueResult::Done => break,
+                EnqueueResult::Enqueued { package_name, case } => {
+                    enqueued.push(format!("{package_name} {case}"))
+                }
+                _ => unreachable!(),
+            }
+        }
+        app.drain().unwrap();
+        app.finish().unwrap();
 
-    // remove bar
-    let fake_tests = FakeTests {
-        test_binaries: vec![FakeTestBinary {
-            name: "foo".into(),
-            tests: vec![FakeTestCase {
-                name: "test_it".into(),
-                ..Default::default()
+        // Cargo built bar first, but while it was being listed, baz and foo were listed by the
+        // other thread, and their tests were enqueued first.
+        assert_eq!(
+            enqueued,
+            vec![
+                "baz test_a",
+                "foo test_a",
+                "foo test_b",
+                "foo test_c",
+                "bar test_a",
+                "bar test_b",
+            ]
+        );
+    })
+}
+
+#[test]
+fn shuffle_seed_determines_order() {
+    let enqueue_order = |shuffle_seed| {
+        let tmp_dir = tempdir().unwrap();
+        let fake_tests = FakeTests {
+            test_binaries: vec![FakeTestBinary {
+                name: "foo".into(),
+                tests: (1..=8)
+                    .map(|i| FakeTestCase {
+                        name: format!("test_{i}"),
+                        ..Default::default()
+                    })
+                    .collect(),
             }],
-        }],
+        };
+        let state = test_main_app_state(
+            &tmp_dir,
+            fake_tests,
+            &tmp_dir.path().join("workspace"),
+            RunAppOptions {
+                main_app_options: MainAppOptions {
+                    shuffle_seed,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            |_| {},
+        )
+        .unwrap();
+        let term = InMemoryTerm::new(50, 50);
+        std::thread::scope(|scope| {
+            let mut app = main_app_new(
+                &state,
+                false, // stdout_tty
+                Quiet::from(true),
+                term.clone(),
+                TestProgressDriver::default(),
+                scope,
+                None,
+                None,
+            )
+            .unwrap();
+
+            let mut cases = vec![];
+            loop {
+                match app.enqueue_one().unwrap() {
+                    EnqueueResult::Done => break,
+                    EnqueueResult::Enqueued { case, .. } => cases.push(case),
+                    _ => unreachable!(),
+                }
+            }
+            app.drain().unwrap();
+            app.finish().unwrap();
+            drop(app);
+            (cases, term.contents())
+        })
     };
 
-    run_all_tests_sync(
-        &tmp_dir,
-        fake_tests.clone(),
-        false.into(),
-        vec!["all".into()],
-        vec![],
+    let (unshuffled, contents) = enqueue_order(None);
+    assert_eq!(
+        unshuffled,
+        Vec::from_iter((1..=8).map(|i| format!("test_{i}")))
     );
+    assert!(!contents.contains("shuffled"));
 
-    // new listing should match
-    let listing: TestListing = load_test_listing(&path).unwrap().unwrap();
-    assert_eq!(listing, fake_tests.listing());
+    // The seed is printed so that the order can be repeated, both when the run starts and with the
+    // summary.
+    let (shuffled, contents) = enqueue_order(Some(1));
+    assert!(contents.starts_with("Shuffling tests with seed 1\n"));
+    assert!(contents.ends_with("Tests were shuffled with seed 1"));
+    assert_eq!(enqueue_order(Some(1)).0, shuffled);
+    assert_ne!(shuffled, unshuffled);
+
+    let mut sorted = shuffled.clone();
+    sorted.sort();
+    assert_eq!(sorted, unshuffled);
+
+    assert_ne!(enqueue_order(Some(2)).0, shuffled);
 }
 
 #[test]
-fn expected_count_updates_cases() {
+fn slowest_first_uses_last_durations() {
     let tmp_dir = tempdir().unwrap();
-    let fake_tests = FakeTests {
+    let fake_tests = |names: &[(&str, u64)]| FakeTests {
         test_binaries: vec![FakeTestBinary {
             name: "foo".into(),
-            tests: vec![FakeTestCase {
-                name: "test_it".into(),
-                ..Default::default()
-            }],
+            tests: names
+                .iter()
+                .map(|(name, secs)| FakeTestCase {
+                    name: name.to_string(),
+                    outcome: JobOutcome::Completed(JobCompleted {
+                        status: JobStatus::Exited(0),
+                        effects: JobEffects {
+                            stdout: JobOutputResult::None,
+                            stderr: JobOutputResult::None,
+                            duration: Duration::from_secs(*secs),
+                            resource_usage: Default::default(),
+                        },
+                    }),
+                    ..Default::default()
+                })
+                .collect(),
         }],
     };
-    run_all_tests_sync(
-        &tmp_dir,
-        fake_tests.clone(),
-        false.into(),
-        vec!["all".into()],
-        vec![],
+    let enqueue_order = |fake_tests: FakeTests, order| {
+        let state = test_main_app_state(
+            &tmp_dir,
+            fake_tests,
+            &tmp_dir.path().join("workspace"),
+            RunAppOptions {
+                main_app_options: MainAppOptions {
+                    order,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            |_| {},
+        )
+        .unwrap();
+        std::thread::scope(|scope| {
+            let mut app = main_app_new(
+                &state,
+                false, // stdout_tty
+                Quiet::from(true),
+                InMemoryTerm::new(50, 50),
+                TestProgressDriver::default(),
+                scope,
+                None,
+                None,
+            )
+            .unwrap();
+
+            let mut cases = vec![];
+            loop {
+                match app.enqueue_one().unwrap() {
+                    EnqueueResult::Done => break,
+                    EnqueueResult::Enqueued { case, .. } => cases.push(case),
+                    _ => unreachable!(),
+                }
+            }
+            app.drain().unwrap();
+            app.finish().unwrap();
+            cases
+        })
+    };
+
+    // Without any history, the listing order is used either way.
+    assert_eq!(
+        enqueue_order(
+            fake_tests(&[("test_a", 1), ("test_b", 3), ("test_c", 2)]),
+            TestOrder::SlowestFirst
+        ),
+        vec!["test_a", "test_b", "test_c"]
     );
 
-    let path = tmp_dir
-        .path()
-        .join("workspace/target/maelstrom")
-        .join(LAST_TEST_LISTING_NAME);
-    let listing: TestListing = load_test_listing(&path).unwrap().unwrap();
-    assert_eq!(listing, fake_tests.listing());
+    // Tests that haven't been run before go first.
+    assert_eq!(
+        enqueue_order(
+            fake_tests(&[("test_a", 1), ("test_b", 3), ("test_c", 2), ("test_d", 1)]),
+            TestOrder::SlowestFirst
+        ),
+        vec!["test_d", "test_b", "test_c", "test_a"]
+    );
 
-    // remove the test
-    let fake_tests = FakeTests {
+    // The durations from the latest run are used.
+    assert_eq!(
+        enqueue_order(
+            fake_tests(&[("test_a", 5), ("test_b", 3), ("test_c", 2), ("test_d", 1)]),
+            TestOrder::SlowestFirst
+        ),
+        vec!["test_b", "test_c", "test_a", "test_d"]
+    );
+    assert_eq!(
+        enqueue_order(
+            fake_tests(&[("test_a", 5), ("test_b", 3), ("test_c", 2), ("test_d", 1)]),
+            TestOrder::SlowestFirst
+        ),
+        vec!["test_a", "test_b", "test_c", "test_d"]
+    );
+    assert_eq!(
+        enqueue_order(
+            fake_tests(&[("test_d", 1), ("test_c", 2), ("test_b", 3), ("test_a", 5)]),
+            TestOrder::Listing
+        ),
+        vec!["test_d", "test_c", "test_b", "test_a"]
+    );
+}
+
+#[derive(Clone, Default)]
+struct LengthRecorder {
+    lengths: Arc<Mutex<Vec<u64>>>,
+}
+
+impl ProgressIndicator for LengthRecorder {
+    fn println(&self, _msg: String) {}
+
+    fn update_length(&self, new_length: u64) {
+        self.lengths.lock().unwrap().push(new_length);
+    }
+}
+
+#[test]
+fn ignored_tests_not_counted_in_expected_job_count() {
+    let tmp_dir = tempdir().unwrap();
+    let fake_tests = |ignored: &[&str]| FakeTests {
         test_binaries: vec![FakeTestBinary {
             name: "foo".into(),
-            tests: vec![],
+            tests: ["test_a", "test_b", "test_c"]
+                .into_iter()
+                .map(|name| FakeTestCase {
+                    name: name.into(),
+                    ignored: ignored.contains(&name),
+                    ..Default::default()
+                })
+                .collect(),
         }],
     };
+    let lengths = |fake_tests: FakeTests| {
+        let state = test_main_app_state(
+            &tmp_dir,
+            fake_tests,
+            &tmp_dir.path().join("workspace"),
+            RunAppOptions::default(),
+            |_| {},
+        )
+        .unwrap();
+        let ind = LengthRecorder::default();
+        std::thread::scope(|scope| {
+            let mut app = new_helper(
+                &state,
+                |_| ind.clone(),
+                InMemoryTerm::new(50, 50),
+                TestProgressDriver::default(),
+                scope,
+                None,
+                None,
+            )
+            .unwrap();
+            while !matches!(app.enqueue_one().unwrap(), EnqueueResult::Done) {}
+            app.drain().unwrap();
+            app.finish().unwrap();
+            let lengths = ind.lengths.lock().unwrap().clone();
+            lengths
+        })
+    };
 
-    run_all_tests_sync(
-        &tmp_dir,
-        fake_tests.clone(),
-        false.into(),
-        vec!["all".into()],
-        vec![],
-    );
+    // Without a cached listing, the length grows as jobs are enqueued.
+    assert_eq!(lengths(fake_tests(&["test_b"])), vec![0, 2, 2, 2, 2]);
 
-    // new listing should match
-    let listing: TestListing = load_test_listing(&path).unwrap().unwrap();
-    assert_eq!(listing, fake_tests.listing());
+    // The cached listing knows test_b is ignored, so it isn't expected.
+    assert_eq!(lengths(fake_tests(&["test_b"])), vec![2, 2, 2, 2, 2]);
+
+    // The cached listing is out of date, so the length goes down once the binary has been listed.
+    assert_eq!(lengths(fake_tests(&["test_b", "test_c"])), vec![2, 1, 1, 1]);
 }
 
 #[test]
-fn filtering_none_does_not_build() {
+fn run_to_completion_counts_results() {
+    for (list, expected) in [
+        (None, (ExitCode::SUCCESS, 2, 1, 0)),
+        (Some(ListAction::ListTests), (ExitCode::SUCCESS, 0, 0, 3)),
+    ] {
+        let tmp_dir = tempdir().unwrap();
+        let fake_tests = FakeTests {
+            test_binaries: vec![FakeTestBinary {
+                name: "foo".into(),
+                tests: vec![
+                    FakeTestCase {
+                        name: "test_a".into(),
+                        ..Default::default()
+                    },
+                    FakeTestCase {
+                        name: "test_b".into(),
+                        ignored: true,
+                        ..Default::default()
+                    },
+                    FakeTestCase {
+                        name: "test_c".into(),
+                        ..Default::default()
+                    },
+                ],
+            }],
+        };
+        let state = test_main_app_state(
+            &tmp_dir,
+            fake_tests,
+            &tmp_dir.path().join("workspace"),
+            RunAppOptions {
+                list,
+                ..Default::default()
+            },
+            |_| {},
+        )
+        .unwrap();
+        std::thread::scope(|scope| {
+            let mut app = main_app_new(
+                &state,
+                false, // stdout_tty
+                Quiet::from(true),
+                InMemoryTerm::new(50, 50),
+                TestProgressDriver::default(),
+                scope,
+                None,
+                None,
+            )
+            .unwrap();
+
+            let (exit_code, enqueued, ignored, listed) = expected;
+            assert_eq!(
+                app.run_to_completion().unwrap(),
+                CompletedRun {
+                    exit_code,
+                    enqueued,
+                    ignored,
+                    listed,
+                }
+            );
+        })
+    }
+}
+
+#[test]
+fn affinity_cache_warm_adds_layers_before_jobs() {
     let tmp_dir = tempdir().unwrap();
+    let case = |name: &str| FakeTestCase {
+        name: name.into(),
+        ..Default::default()
+    };
     let fake_tests = FakeTests {
-        test_binaries: vec![FakeTestBinary {
-            name: "foo".into(),
-            tests: vec![FakeTestCase {
-                name: "test_it".into(),
-                ..Default::default()
-            }],
-        }],
+        test_binaries: vec![
+            FakeTestBinary {
+                name: "foo".into(),
+                tests: ["test_a", "test_b"].into_iter().map(case).collect(),
+            },
+            FakeTestBinary {
+                name: "bar".into(),
+                tests: ["test_a", "test_b"].into_iter().map(case).collect(),
+            },
+        ],
     };
-    run_all_tests_sync(
+    let state = test_main_app_state(
         &tmp_dir,
-        fake_tests.clone(),
-        false.into(),
-        vec!["none".into()],
-        vec![],
+        fake_tests,
+        &tmp_dir.path().join("workspace"),
+        RunAppOptions {
+            main_app_options: MainAppOptions {
+                affinity_cache_warm: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        |_| {},
+    )
+    .unwrap();
+    std::thread::scope(|scope| {
+        let mut app = main_app_new(
+            &state,
+            false, // stdout_tty
+            Quiet::from(true),
+            InMemoryTerm::new(50, 50),
+            TestProgressDriver::default(),
+            scope,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let mut enqueued = vec![];
+        loop {
+            match app.enqueue_one().unwrap() {
+                EnqueueResult::Done => break,
+                EnqueueResult::Enqueued { package_name, case } => {
+                    enqueued.push(format!("{package_name} {case}"))
+                }
+                EnqueueResult::Ignored | EnqueueResult::Listed | EnqueueResult::Yielded => {
+                    unreachable!()
+                }
+            }
+        }
+        app.drain().unwrap();
+        app.finish().unwrap();
+        drop(app);
+
+        assert_eq!(
+            enqueued,
+            vec!["foo test_a", "foo test_b", "bar test_a", "bar test_b"]
+        );
+
+        // Each binary adds a layer for itself and one for its shared libraries, and each case adds a
+        // layer for its stubs. All of them are added up front, and none are added again once jobs
+        // start being enqueued.
+        let calls = state.deps.calls.lock().unwrap();
+        assert_eq!(*calls, [vec!["add_layer"; 8], vec!["add_job"; 4]].concat());
+    })
+}
+
+#[test]
+fn list_capabilities_from_mock_workers() {
+    let mut deps = TestMainAppDeps::new(
+        FakeTests {
+            test_binaries: vec![],
+        },
+        PathBuf::new(),
+        PathBuf::new(),
+    );
+    deps.worker_capabilities = HashMap::from([
+        (
+            WorkerId::from(2),
+            WorkerCapabilities {
+                devices: enum_set! {JobDevice::Null},
+                overlayfs: false,
+                cgroup_controllers: vec![],
+                cpus: 0,
+                memory: 0,
+            },
+        ),
+        (
+            WorkerId::from(1),
+            WorkerCapabilities {
+                devices: enum_set! {JobDevice::Fuse | JobDevice::Null | JobDevice::Zero},
+                overlayfs: true,
+                cgroup_controllers: vec!["cpu".into(), "memory".into(), "pids".into()],
+                cpus: 8,
+                memory: 16 * 1024 * 1024 * 1024,
+            },
+        ),
+    ]);
+    let mut out = vec![];
+    list_capabilities(&deps, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\
+        worker 1:\n\
+        \x20   devices: fuse, null, zero\n\
+        \x20   overlayfs: yes\n\
+        \x20   cgroup controllers: cpu, memory, pids\n\
+        \x20   cpus: 8\n\
+        \x20   memory: 16.0 GiB\n\
+        worker 2:\n\
+        \x20   devices: null\n\
+        \x20   overlayfs: no\n\
+        \x20   cgroup controllers: none\n\
+        \x20   cpus: 0\n\
+        \x20   memory: 0 B\n\
+        "
     );
+}
 
-    let fs = Fs::new();
-    let cache_dir = tmp_dir.path().join("workspace/target/maelstrom");
-    let mut entries: Vec<_> = fs
-        .read_dir(cache_dir)
-        .unwrap()
-        .map(|e| e.unwrap().file_name().into_string().unwrap())
-        .collect();
-    entries.sort();
-    assert_eq!(entries, vec![LAST_TEST_LISTING_NAME.to_owned(),]);
+#[test]
+fn list_capabilities_with_no_workers() {
+    let deps = TestMainAppDeps::new(
+        FakeTests {
+            test_binaries: vec![],
+        },
+        PathBuf::new(),
+        PathBuf::new(),
+    );
+    let mut out = vec![];
+    list_capabilities(&deps, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "no workers connected\n");
 }
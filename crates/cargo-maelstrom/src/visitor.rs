@@ -1,5 +1,10 @@
-use crate::ProgressIndicator;
-use anyhow::Result;
+use crate::{
+    cargo::TestCaseLocation,
+    config::{ExitCodeSeverities, ExitCodeSeverity, LargeOutputThreshold, OutputEncoding},
+    metadata::RetryPolicy,
+    ProgressIndicator,
+};
+use anyhow::{Error, Result};
 use colored::{ColoredString, Colorize as _};
 use indicatif::TermLike;
 use maelstrom_base::{
@@ -7,34 +12,257 @@ use maelstrom_base::{
     JobStatus,
 };
 use maelstrom_util::process::{ExitCode, ExitCodeAccumulator};
-use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 use unicode_truncate::UnicodeTruncateStr as _;
 use unicode_width::UnicodeWidthStr as _;
 
 enum CaseResult {
     Ignored,
+    Lost,
+    AllowedFailure,
+    /// The test passed, but only after being retried.
+    Flaky,
     Ran(ExitCode),
 }
 
+/// A test case, identified by its package, the test binary (artifact) it's in, and its name within
+/// that binary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaseName {
+    pub package: String,
+    pub artifact: String,
+    pub case: String,
+}
+
+impl CaseName {
+    pub fn new(
+        package: impl Into<String>,
+        artifact: impl Into<String>,
+        case: impl Into<String>,
+    ) -> Self {
+        Self {
+            package: package.into(),
+            artifact: artifact.into(),
+            case: case.into(),
+        }
+    }
+
+    /// The case string without the package name in front. The binary's name is only included if
+    /// it differs from the package's.
+    fn without_package(&self) -> String {
+        if self.artifact == self.package {
+            self.case.clone()
+        } else {
+            format!("{} {}", self.artifact, self.case)
+        }
+    }
+}
+
+/// Displays the case string, as made by [`crate::format_case_str`].
+impl fmt::Display for CaseName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&crate::format_case_str(
+            &self.package,
+            &self.artifact,
+            &self.case,
+        ))
+    }
+}
+
+/// Why a test didn't pass, along with its captured output if the tracker keeps it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CaseFailure {
+    pub reason: String,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CaseFailure {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            ..Default::default()
+        }
+    }
+}
+
+struct CaseStatus {
+    name: CaseName,
+    result: CaseResult,
+    /// How long the test's last attempt ran, if it ran to completion or timed out.
+    duration: Option<Duration>,
+    /// For tests that failed, were lost, or were allowed to fail, why. For flaky tests, why the
+    /// last failed attempt failed.
+    failure: Option<CaseFailure>,
+}
+
+/// Escape `s` so that it can be used as XML text or in a quoted attribute value.
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write a line for each of `cases`, marked with `label`. If `group_by_package` is true, the cases
+/// are listed under a heading for their package, without the package name in front of each case.
+fn write_cases<'a>(
+    term: &impl TermLike,
+    cases: impl Iterator<Item = &'a CaseStatus>,
+    label: ColoredString,
+    group_by_package: bool,
+) -> Result<()> {
+    if !group_by_package {
+        let cases = Vec::from_iter(cases.map(|c| c.name.to_string()));
+        let width = cases.iter().map(|c| c.width()).max().unwrap_or(0);
+        for case in cases {
+            term.write_line(&format!("    {case:<width$}: {label}"))?;
+        }
+        return Ok(());
+    }
+
+    let mut packages: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for CaseStatus { name, .. } in cases {
+        packages
+            .entry(&name.package)
+            .or_default()
+            .push(name.without_package());
+    }
+    for (package, cases) in packages {
+        term.write_line(&format!("    {}:", package.bold()))?;
+        let width = cases.iter().map(|c| c.width()).max().unwrap_or(0);
+        for case in cases {
+            term.write_line(&format!("        {case:<width$}: {label}"))?;
+        }
+    }
+    Ok(())
+}
+
 #[derive(Default)]
 pub struct JobStatusTracker {
-    statuses: Mutex<Vec<(String, CaseResult)>>,
+    statuses: Mutex<Vec<CaseStatus>>,
     exit_code: ExitCodeAccumulator,
+    failure_output_limit: Option<u32>,
+    /// How many failed tests have had their output considered for printing so far.
+    failures_with_output: AtomicU32,
+    /// Whether to keep the captured output of tests that don't pass, for the JUnit XML report.
+    keep_failure_output: bool,
 }
 
 impl JobStatusTracker {
-    pub fn job_exited(&self, case: String, exit_code: ExitCode) {
-        let mut statuses = self.statuses.lock().unwrap();
-        statuses.push((case, CaseResult::Ran(exit_code)));
+    /// If `failure_output_limit` is some, only that many failed tests get their output printed.
+    /// If `keep_failure_output` is true, the output of tests that don't pass is kept in their
+    /// [`CaseFailure`]s.
+    pub fn new(failure_output_limit: Option<u32>, keep_failure_output: bool) -> Self {
+        Self {
+            failure_output_limit,
+            keep_failure_output,
+            ..Default::default()
+        }
+    }
+
+    /// Called when a test fails, before its output is printed. Returns false if the
+    /// `failure_output_limit` has already been reached, in which case the output shouldn't be
+    /// printed.
+    fn failure_output_allowed(&self) -> bool {
+        let earlier = self.failures_with_output.fetch_add(1, Ordering::AcqRel);
+        !matches!(self.failure_output_limit, Some(limit) if earlier >= limit)
+    }
+
+    /// How many failed tests didn't have their output printed because of the
+    /// `failure_output_limit`.
+    fn failure_outputs_omitted(&self) -> u32 {
+        self.failure_output_limit.map_or(0, |limit| {
+            self.failures_with_output
+                .load(Ordering::Acquire)
+                .saturating_sub(limit)
+        })
+    }
+
+    fn push(
+        &self,
+        name: CaseName,
+        result: CaseResult,
+        duration: Option<Duration>,
+        failure: Option<CaseFailure>,
+    ) {
+        self.statuses.lock().unwrap().push(CaseStatus {
+            name,
+            result,
+            duration,
+            failure,
+        });
+    }
+
+    pub fn job_exited(&self, name: CaseName, exit_code: ExitCode, duration: Option<Duration>) {
+        self.push(name, CaseResult::Ran(exit_code), duration, None);
+        self.exit_code.add(exit_code);
+    }
+
+    /// Record a test that exited with a failing `exit_code`, because of `failure`.
+    pub fn job_failed(
+        &self,
+        name: CaseName,
+        exit_code: ExitCode,
+        duration: Option<Duration>,
+        failure: CaseFailure,
+    ) {
+        self.push(name, CaseResult::Ran(exit_code), duration, Some(failure));
         self.exit_code.add(exit_code);
     }
 
-    pub fn job_ignored(&self, case: String) {
-        let mut statuses = self.statuses.lock().unwrap();
-        statuses.push((case, CaseResult::Ignored));
+    pub fn job_ignored(&self, name: CaseName) {
+        self.push(name, CaseResult::Ignored, None, None);
+    }
+
+    /// Record a failure of a test that is allowed to fail. It doesn't affect the exit code.
+    pub fn job_failure_allowed(
+        &self,
+        name: CaseName,
+        duration: Option<Duration>,
+        failure: CaseFailure,
+    ) {
+        self.push(name, CaseResult::AllowedFailure, duration, Some(failure));
     }
 
-    pub fn print_summary(&self, width: usize, term: impl TermLike) -> Result<()> {
+    /// Record a test that passed after failing at least once and being retried. `failure` is why
+    /// the last failed attempt failed.
+    pub fn job_flaky(&self, name: CaseName, duration: Duration, failure: CaseFailure) {
+        self.push(name, CaseResult::Flaky, Some(duration), Some(failure));
+    }
+
+    pub fn job_lost(&self, name: CaseName, failure: CaseFailure) {
+        self.push(name, CaseResult::Lost, None, Some(failure));
+        self.exit_code.add(ExitCode::FAILURE);
+    }
+
+    /// Print counts of the results of all of the tests, followed by the tests that didn't pass. If
+    /// `group_by_package` is true, those tests are listed under a heading for their package.
+    pub fn print_summary(
+        &self,
+        width: usize,
+        group_by_package: bool,
+        term: impl TermLike,
+    ) -> Result<()> {
         term.write_line("")?;
 
         let heading = " Test Summary ";
@@ -47,22 +275,46 @@ impl JobStatusTracker {
         let success = "Successful Tests";
         let failure = "Failed Tests";
         let ignore = "Ignored Tests";
+        let lost = "Lost Tests";
+        let allowed = "Allowed Failures";
+        let flaky = "Passed On Retry";
         let mut column1_width = std::cmp::max(success.width(), failure.width());
         let max_digits = 9;
         let statuses = self.statuses.lock().unwrap();
         let failed = statuses
             .iter()
-            .filter(|(_, res)| matches!(res, CaseResult::Ran(e) if e != &ExitCode::SUCCESS));
+            .filter(|c| matches!(&c.result, CaseResult::Ran(e) if e != &ExitCode::SUCCESS));
         let ignored = statuses
             .iter()
-            .filter(|(_, res)| matches!(res, CaseResult::Ignored));
+            .filter(|c| matches!(c.result, CaseResult::Ignored));
+        let lost_cases = statuses
+            .iter()
+            .filter(|c| matches!(c.result, CaseResult::Lost));
+        let allowed_failures = statuses
+            .iter()
+            .filter(|c| matches!(c.result, CaseResult::AllowedFailure));
         let num_failed = failed.clone().count();
         let num_ignored = ignored.clone().count();
-        let num_succeeded = statuses.len() - num_failed - num_ignored;
+        let num_lost = lost_cases.clone().count();
+        let flaky_cases = statuses
+            .iter()
+            .filter(|c| matches!(c.result, CaseResult::Flaky));
+        let num_allowed = allowed_failures.clone().count();
+        let num_flaky = flaky_cases.clone().count();
+        let num_succeeded = statuses.len() - num_failed - num_ignored - num_lost - num_allowed;
 
         if num_ignored > 0 {
             column1_width = std::cmp::max(column1_width, ignore.width());
         }
+        if num_lost > 0 {
+            column1_width = std::cmp::max(column1_width, lost.width());
+        }
+        if num_allowed > 0 {
+            column1_width = std::cmp::max(column1_width, allowed.width());
+        }
+        if num_flaky > 0 {
+            column1_width = std::cmp::max(column1_width, flaky.width());
+        }
 
         term.write_line(&format!(
             "{:<column1_width$}: {num_succeeded:>max_digits$}",
@@ -72,9 +324,24 @@ impl JobStatusTracker {
             "{:<column1_width$}: {num_failed:>max_digits$}",
             failure.red(),
         ))?;
-        let failed_width = failed.clone().map(|(n, _)| n.width()).max().unwrap_or(0);
-        for (failed, _) in failed {
-            term.write_line(&format!("    {failed:<failed_width$}: {}", "failure".red()))?;
+        write_cases(&term, failed, "failure".red(), group_by_package)?;
+        let omitted = self.failure_outputs_omitted();
+        if omitted > 0 {
+            let tests = if omitted == 1 { "test" } else { "tests" };
+            term.write_line(&format!("{omitted} failed {tests} had output omitted"))?;
+        }
+
+        if num_flaky > 0 {
+            term.write_line(&format!(
+                "{:<column1_width$}: {num_flaky:>max_digits$}",
+                flaky.yellow(),
+            ))?;
+            write_cases(
+                &term,
+                flaky_cases,
+                "passed on retry".yellow(),
+                group_by_package,
+            )?;
         }
 
         if num_ignored > 0 {
@@ -82,13 +349,28 @@ impl JobStatusTracker {
                 "{:<column1_width$}: {num_ignored:>max_digits$}",
                 ignore.yellow(),
             ))?;
-            let failed_width = ignored.clone().map(|(n, _)| n.width()).max().unwrap_or(0);
-            for (ignored, _) in ignored {
-                term.write_line(&format!(
-                    "    {ignored:<failed_width$}: {}",
-                    "ignored".yellow()
-                ))?;
-            }
+            write_cases(&term, ignored, "ignored".yellow(), group_by_package)?;
+        }
+
+        if num_lost > 0 {
+            term.write_line(&format!(
+                "{:<column1_width$}: {num_lost:>max_digits$}",
+                lost.red(),
+            ))?;
+            write_cases(&term, lost_cases, "lost".red(), group_by_package)?;
+        }
+
+        if num_allowed > 0 {
+            term.write_line(&format!(
+                "{:<column1_width$}: {num_allowed:>max_digits$}",
+                allowed.yellow(),
+            ))?;
+            write_cases(
+                &term,
+                allowed_failures,
+                "failure allowed".yellow(),
+                group_by_package,
+            )?;
         }
 
         term.flush()?;
@@ -98,51 +380,468 @@ impl JobStatusTracker {
     pub fn exit_code(&self) -> ExitCode {
         self.exit_code.get()
     }
+
+    /// Whether each test that was run failed, keyed by the test's case string. Lost tests count
+    /// as failed. Tests that were ignored or skipped aren't included.
+    pub fn failed_by_case(&self) -> HashMap<String, bool> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|c| {
+                let failed = match &c.result {
+                    CaseResult::Ignored => return None,
+                    CaseResult::Lost => true,
+                    CaseResult::AllowedFailure | CaseResult::Flaky => false,
+                    CaseResult::Ran(exit_code) => exit_code != &ExitCode::SUCCESS,
+                };
+                Some((c.name.to_string(), failed))
+            })
+            .collect()
+    }
+
+    /// How long each test that ran to completion or timed out took, keyed by the test's case
+    /// string. If a test was retried, this is how long its last attempt took.
+    pub fn duration_by_case(&self) -> HashMap<String, Duration> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|c| Some((c.name.to_string(), c.duration?)))
+            .collect()
+    }
+
+    /// Counts of the results of all of the tests, along with how long the run took and the exit
+    /// code. Flaky tests are counted as passed as well as flaky, and lost tests as failed.
+    pub fn json_summary(&self, duration: Duration) -> JsonSummary {
+        let statuses = self.statuses.lock().unwrap();
+        let count =
+            |pred: fn(&CaseResult) -> bool| statuses.iter().filter(|c| pred(&c.result)).count();
+        JsonSummary {
+            total: statuses.len(),
+            passed: count(|r| matches!(r, CaseResult::Flaky | CaseResult::Ran(ExitCode::SUCCESS))),
+            failed: count(|r| {
+                matches!(r, CaseResult::Lost)
+                    || matches!(r, CaseResult::Ran(e) if e != &ExitCode::SUCCESS)
+            }),
+            ignored: count(|r| matches!(r, CaseResult::Ignored)),
+            allowed_failures: count(|r| matches!(r, CaseResult::AllowedFailure)),
+            flaky: count(|r| matches!(r, CaseResult::Flaky)),
+            duration_secs: duration.as_secs_f64(),
+            exit_code: self.exit_code().into(),
+        }
+    }
+
+    /// Write the results of all of the tests to `path` as a JUnit XML report. There is a
+    /// `<testsuite>` for each package, and each test's binary is used as its class name.
+    ///
+    /// Failed and lost tests get a `<failure>` element with why they failed. Ignored tests get a
+    /// `<skipped>` element, as do tests that were allowed to fail, with why they failed. Flaky
+    /// tests pass, with a `<flakyFailure>` element saying why the last failed attempt failed.
+    /// Captured output, if it was kept, goes in `<system-out>` and `<system-err>` elements.
+    pub fn write_junit_xml(&self, path: &Path) -> Result<()> {
+        let statuses = self.statuses.lock().unwrap();
+        let mut packages: BTreeMap<&str, Vec<&CaseStatus>> = BTreeMap::new();
+        for status in statuses.iter() {
+            packages
+                .entry(&status.name.package)
+                .or_default()
+                .push(status);
+        }
+        let is_failure = |c: &CaseStatus| {
+            matches!(c.result, CaseResult::Lost)
+                || matches!(&c.result, CaseResult::Ran(e) if e != &ExitCode::SUCCESS)
+        };
+        let is_skipped =
+            |c: &CaseStatus| matches!(c.result, CaseResult::Ignored | CaseResult::AllowedFailure);
+        let total_time = |cases: &mut dyn Iterator<Item = &CaseStatus>| {
+            cases
+                .filter_map(|c| c.duration)
+                .sum::<Duration>()
+                .as_secs_f64()
+        };
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml += &format!(
+            "<testsuites name=\"cargo-maelstrom\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" \
+            time=\"{:.3}\">\n",
+            statuses.len(),
+            statuses.iter().filter(|c| is_failure(c)).count(),
+            statuses.iter().filter(|c| is_skipped(c)).count(),
+            total_time(&mut statuses.iter()),
+        );
+        for (package, cases) in packages {
+            xml += &format!(
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" \
+                time=\"{:.3}\">\n",
+                xml_escape(package),
+                cases.len(),
+                cases.iter().filter(|c| is_failure(c)).count(),
+                cases.iter().filter(|c| is_skipped(c)).count(),
+                total_time(&mut cases.iter().copied()),
+            );
+            for case in cases {
+                xml += &format!(
+                    "    <testcase name=\"{}\" classname=\"{}\"",
+                    xml_escape(&case.name.case),
+                    xml_escape(&case.name.artifact),
+                );
+                if let Some(duration) = case.duration {
+                    xml += &format!(" time=\"{:.3}\"", duration.as_secs_f64());
+                }
+                let reason = case.failure.as_ref().map(|f| xml_escape(&f.reason));
+                let mut children = vec![];
+                match (&case.result, reason) {
+                    (CaseResult::Ignored, _) => children.push("<skipped/>".into()),
+                    (CaseResult::AllowedFailure, Some(reason)) => {
+                        children.push(format!("<skipped message=\"failure allowed: {reason}\"/>"))
+                    }
+                    (CaseResult::Flaky, Some(reason)) => {
+                        children.push(format!("<flakyFailure message=\"{reason}\"/>"))
+                    }
+                    (CaseResult::Lost, Some(reason)) => {
+                        children.push(format!("<failure message=\"lost: {reason}\"/>"))
+                    }
+                    (CaseResult::Lost, None) => children.push("<failure message=\"lost\"/>".into()),
+                    (CaseResult::Ran(e), reason) if e != &ExitCode::SUCCESS => {
+                        let reason = reason.unwrap_or_else(|| "failure".into());
+                        children.push(format!("<failure message=\"{reason}\"/>"))
+                    }
+                    _ => {}
+                }
+                if let Some(failure) = &case.failure {
+                    if !failure.stdout.is_empty() {
+                        children.push(format!(
+                            "<system-out>{}</system-out>",
+                            xml_escape(&failure.stdout)
+                        ));
+                    }
+                    if !failure.stderr.is_empty() {
+                        children.push(format!(
+                            "<system-err>{}</system-err>",
+                            xml_escape(&failure.stderr)
+                        ));
+                    }
+                }
+                if children.is_empty() {
+                    xml += "/>\n";
+                } else {
+                    xml += ">\n";
+                    for child in children {
+                        xml += &format!("      {child}\n");
+                    }
+                    xml += "    </testcase>\n";
+                }
+            }
+            xml += "  </testsuite>\n";
+        }
+        xml += "</testsuites>\n";
+        fs::write(path, xml)?;
+        Ok(())
+    }
+}
+
+/// The machine-readable summary written at the end of a run when requested.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct JsonSummary {
+    pub total: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub ignored: usize,
+    pub allowed_failures: usize,
+    pub flaky: usize,
+    pub duration_secs: f64,
+    pub exit_code: u8,
 }
 
 pub struct JobStatusVisitor<ProgressIndicatorT> {
     tracker: Arc<JobStatusTracker>,
+    name: CaseName,
+    /// The case string for `name`, which is what we print.
     case: String,
+    /// Where the test is defined, if we know.
+    location: Option<TestCaseLocation>,
+    /// Whether to print `location` after the test's result. It's used for GitHub annotations
+    /// either way.
+    print_location: bool,
     width: usize,
     ind: ProgressIndicatorT,
+    retry_policy: RetryPolicy,
+    failure_retries: u32,
+    allow_failure: bool,
+    retries: u32,
+    output_dir: Option<PathBuf>,
+    large_output_threshold: Option<LargeOutputThreshold>,
+    report_time: bool,
+    github_annotations: bool,
+    exit_code_severities: ExitCodeSeverities,
+    output_encoding: OutputEncoding,
+    /// Why the last attempt that was retried failed.
+    last_retry_reason: Option<String>,
 }
 
 impl<ProgressIndicatorT> JobStatusVisitor<ProgressIndicatorT> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         tracker: Arc<JobStatusTracker>,
-        case: String,
+        name: CaseName,
+        location: Option<TestCaseLocation>,
+        print_location: bool,
         width: usize,
         ind: ProgressIndicatorT,
+        retry_policy: RetryPolicy,
+        failure_retries: u32,
+        allow_failure: bool,
+        output_dir: Option<PathBuf>,
+        large_output_threshold: Option<LargeOutputThreshold>,
+        report_time: bool,
+        github_annotations: bool,
+        exit_code_severities: ExitCodeSeverities,
+        output_encoding: OutputEncoding,
     ) -> Self {
         Self {
             tracker,
-            case,
+            case: name.to_string(),
+            name,
+            location,
+            print_location,
             width,
             ind,
+            retry_policy,
+            failure_retries,
+            allow_failure,
+            retries: 0,
+            output_dir,
+            large_output_threshold,
+            report_time,
+            github_annotations,
+            exit_code_severities,
+            output_encoding,
+            last_retry_reason: None,
         }
     }
 }
 
-fn format_test_output(res: &JobOutputResult, name: &str, cjid: ClientJobId) -> Vec<String> {
-    let mut test_output_lines = vec![];
-    match res {
-        JobOutputResult::None => {}
-        JobOutputResult::Inline(bytes) => {
-            test_output_lines.push(String::from_utf8_lossy(bytes).into());
+/// Escape `s` for use in a GitHub Actions workflow command. Property values, like the file name,
+/// also need `:` and `,` escaped, since those separate properties.
+fn github_escape(s: &str, property: bool) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '%' => escaped += "%25",
+            '\r' => escaped += "%0D",
+            '\n' => escaped += "%0A",
+            ':' if property => escaped += "%3A",
+            ',' if property => escaped += "%2C",
+            c => escaped.push(c),
         }
-        JobOutputResult::Truncated { first, truncated } => {
-            test_output_lines.push(String::from_utf8_lossy(first).into());
+    }
+    escaped
+}
+
+/// Write output that is too large to print to a temporary file, returning the file's path. The file
+/// is kept around after we exit so that it can be looked at.
+fn write_large_output(bytes: &[u8], name: &str, cjid: ClientJobId) -> io::Result<PathBuf> {
+    let mut file = tempfile::Builder::new()
+        .prefix(&format!("maelstrom-job-{cjid}-"))
+        .suffix(&format!(".{name}"))
+        .tempfile()?;
+    file.write_all(bytes)?;
+    Ok(file.into_temp_path().keep()?)
+}
+
+/// Decode captured output for printing. Output that is valid UTF-8 is always printed as-is, except
+/// when it has been explicitly declared to be UTF-16.
+fn decode_test_output(bytes: &[u8], encoding: OutputEncoding) -> String {
+    let from_utf16 = |to_u16: fn([u8; 2]) -> u16| {
+        let chunks = bytes.chunks_exact(2);
+        let odd_byte = !chunks.remainder().is_empty();
+        let mut decoded: String = char::decode_utf16(chunks.map(|pair| to_u16([pair[0], pair[1]])))
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        if odd_byte {
+            decoded.push(char::REPLACEMENT_CHARACTER);
+        }
+        decoded
+    };
+    match encoding {
+        OutputEncoding::Lossy => String::from_utf8_lossy(bytes).into(),
+        OutputEncoding::Hex => match std::str::from_utf8(bytes) {
+            Ok(s) => s.into(),
+            Err(_) => hex_dump(bytes),
+        },
+        OutputEncoding::Utf16Le => from_utf16(u16::from_le_bytes),
+        OutputEncoding::Utf16Be => from_utf16(u16::from_be_bytes),
+    }
+}
+
+/// Format bytes like `hexdump -C`: an offset, 16 bytes in hex, then the printable ASCII characters.
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut lines = vec![];
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<_> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        lines.push(format!("{:08x}  {:<47}  |{ascii}|", i * 16, hex.join(" ")));
+    }
+    lines.join("\n")
+}
+
+fn format_test_output(
+    res: &JobOutputResult,
+    name: &str,
+    cjid: ClientJobId,
+    large_output_threshold: Option<LargeOutputThreshold>,
+    output_encoding: OutputEncoding,
+) -> Vec<String> {
+    let mut test_output_lines = vec![];
+    let (bytes, truncated) = match res {
+        JobOutputResult::None => return test_output_lines,
+        JobOutputResult::Inline(bytes) => (bytes, None),
+        JobOutputResult::Truncated { first, truncated } => (first, Some(truncated)),
+    };
+    let too_large =
+        large_output_threshold.is_some_and(|threshold| bytes.len() as u64 > threshold.as_bytes());
+    match too_large.then(|| write_large_output(bytes, name, cjid)) {
+        Some(Ok(path)) => {
             test_output_lines.push(format!(
-                "job {cjid}: {name} truncated, {truncated} bytes lost"
+                "job {cjid}: {name} is {} bytes, written to {}",
+                bytes.len(),
+                path.display()
             ));
         }
+        Some(Err(err)) => {
+            test_output_lines.push(format!("job {cjid}: error writing {name} to a file: {err}"));
+            test_output_lines.push(decode_test_output(bytes, output_encoding));
+        }
+        None => {
+            test_output_lines.push(decode_test_output(bytes, output_encoding));
+        }
+    }
+    if let Some(truncated) = truncated {
+        test_output_lines.push(format!(
+            "job {cjid}: {name} truncated, {truncated} bytes lost"
+        ));
     }
     test_output_lines
 }
 
+/// Write captured output to `path`. If there wasn't any output, make sure there isn't a file left
+/// over from an earlier run instead.
+fn save_test_output(res: &JobOutputResult, path: &Path) -> io::Result<()> {
+    let bytes = match res {
+        JobOutputResult::None => &[][..],
+        JobOutputResult::Inline(bytes) => bytes,
+        JobOutputResult::Truncated { first, .. } => first,
+    };
+    if bytes.is_empty() {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    } else {
+        fs::write(path, bytes)
+    }
+}
+
+/// Decode captured output to keep in a [`CaseFailure`], noting if it was truncated.
+fn case_failure_output(res: &JobOutputResult, encoding: OutputEncoding) -> String {
+    match res {
+        JobOutputResult::None => String::new(),
+        JobOutputResult::Inline(bytes) => decode_test_output(bytes, encoding),
+        JobOutputResult::Truncated { first, truncated } => {
+            let mut output = decode_test_output(first, encoding);
+            output += &format!("\n[output truncated, {truncated} bytes lost]");
+            output
+        }
+    }
+}
+
+/// How one attempt at running a test turned out, as shown in its result line.
+#[derive(Clone, Copy)]
+enum JobResult {
+    Ok,
+    Fail,
+    Timeout,
+    Error,
+    Lost,
+    Ignored,
+    /// The attempt failed, but the test is going to be run again.
+    Retry,
+}
+
+impl JobResult {
+    fn label(self) -> ColoredString {
+        match self {
+            Self::Ok => "OK".green(),
+            Self::Fail => "FAIL".red(),
+            Self::Timeout => "TIMEOUT".red(),
+            Self::Error => "ERR".yellow(),
+            Self::Lost => "LOST".red(),
+            Self::Ignored => "IGNORED".yellow(),
+            Self::Retry => "RETRY".yellow(),
+        }
+    }
+
+    /// The label in libtest's format. libtest doesn't retry tests, so an attempt that is going to
+    /// be retried gets its own label instead of being reported as a failure.
+    fn libtest_label(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Ignored => "ignored",
+            Self::Retry => "retrying",
+            Self::Fail | Self::Timeout | Self::Error | Self::Lost => "FAILED",
+        }
+    }
+}
+
 impl<ProgressIndicatorT: ProgressIndicator> JobStatusVisitor<ProgressIndicatorT> {
-    fn print_job_result(&self, result_str: ColoredString, duration_str: String) {
-        if self.width > 10 {
+    /// If we were given an output directory, write the test's stdout and stderr to it. They go in
+    /// a subdirectory for the package and test binary, since the same case name can be in more
+    /// than one of them.
+    fn save_output(&self, stdout: &JobOutputResult, stderr: &JobOutputResult) {
+        let Some(output_dir) = &self.output_dir else {
+            return;
+        };
+        let dir = output_dir
+            .join(&self.name.package)
+            .join(&self.name.artifact);
+        let res = fs::create_dir_all(&dir).and_then(|()| {
+            save_test_output(stdout, &dir.join(format!("{}.stdout", self.name.case)))?;
+            save_test_output(stderr, &dir.join(format!("{}.stderr", self.name.case)))
+        });
+        if let Err(err) = res {
+            self.ind.eprintln(format!(
+                "error saving output of {} to {}: {err}",
+                self.case,
+                output_dir.display()
+            ));
+        }
+    }
+
+    /// Print a result line in the format libtest uses when given `--report-time`, like
+    /// "test foo ... ok <0.001s>".
+    fn print_libtest_job_result(&self, result: JobResult, duration_str: &str) {
+        let mut line = format!("test {} ... {}", self.case, result.libtest_label());
+        if !duration_str.is_empty() {
+            line += &format!(" <{duration_str}>");
+        }
+        self.ind.println(line);
+    }
+
+    fn print_job_result(&self, result: JobResult, duration_str: String) {
+        let result_str = result.label();
+        if self.report_time {
+            self.print_libtest_job_result(result, &duration_str);
+        } else if self.width > 10 {
             let case_width = self.case.width();
             let trailer_str = format!("{result_str} {duration_str:>8}");
             let trailer_width = result_str.width() + 1 + std::cmp::max(duration_str.width(), 8);
@@ -169,10 +868,106 @@ impl<ProgressIndicatorT: ProgressIndicator> JobStatusVisitor<ProgressIndicatorT>
             self.ind
                 .println(format!("{case} {result_str}", case = self.case));
         }
+        if let Some(location) = self.location.as_ref().filter(|_| self.print_location) {
+            self.ind.println(format!("    --> {location}"));
+        }
+    }
+
+    /// Consult the retry policy to see if a job that ended with `status` should be run again. If
+    /// so, report the failed attempt and return true.
+    ///
+    /// If the retry policy doesn't allow any retries, any failure is retried up to
+    /// `failure_retries` times instead.
+    fn retry(&mut self, status: &JobStatus, effects: &JobEffects) -> bool {
+        let (max_retries, matches) = if self.retry_policy.max_retries > 0 {
+            (
+                self.retry_policy.max_retries,
+                self.retry_policy.matches(status),
+            )
+        } else {
+            let failed = match status {
+                JobStatus::Exited(code) => {
+                    self.exit_code_severities.get(*code) == ExitCodeSeverity::Fail
+                }
+                JobStatus::Signaled(_) => true,
+            };
+            (self.failure_retries, failed)
+        };
+        if self.retries >= max_retries || !matches {
+            return false;
+        }
+        self.retries += 1;
+        let reason = match status {
+            JobStatus::Exited(code) => format!("exited with code {code}"),
+            JobStatus::Signaled(signo) => format!("killed by signal {signo}"),
+        };
+        self.last_retry_reason = Some(reason.clone());
+        self.print_job_result(
+            JobResult::Retry,
+            format!("{:.3}s", effects.duration.as_secs_f64()),
+        );
+        self.ind.println(format!(
+            "{reason}, retrying ({} of {max_retries})",
+            self.retries
+        ));
+        // The retry is counted as another job, so the attempt that failed is finished.
+        self.ind.job_finished();
+        true
     }
 
-    pub fn job_finished(&self, cjid: ClientJobId, result: JobOutcomeResult) {
-        let result_str: ColoredString;
+    /// Print a GitHub Actions annotation for a test that failed because of `reason`, if they were
+    /// asked for. The annotation points at where the test is defined if we know that.
+    fn annotate_failure(&self, reason: &str) {
+        if !self.github_annotations {
+            return;
+        }
+        let mut properties = vec![];
+        if let Some(location) = &self.location {
+            properties.push(format!("file={}", github_escape(&location.path, true)));
+            properties.push(format!("line={}", location.line));
+        }
+        properties.push(format!("title={}", github_escape(&self.case, true)));
+        self.ind.println(format!(
+            "::error {}::{}",
+            properties.join(","),
+            github_escape(&format!("{} {reason}", self.case), false)
+        ));
+    }
+
+    /// Record that the job failed with `exit_code` because of `reason`, unless the test is allowed
+    /// to fail. `output` is the job's stdout and stderr, if it ran.
+    fn job_failed(
+        &self,
+        exit_code: ExitCode,
+        duration: Option<Duration>,
+        reason: &str,
+        output: Option<(&JobOutputResult, &JobOutputResult)>,
+    ) {
+        let mut failure = CaseFailure::new(reason);
+        if let Some((stdout, stderr)) = output.filter(|_| self.tracker.keep_failure_output) {
+            failure.stdout = case_failure_output(stdout, self.output_encoding);
+            failure.stderr = case_failure_output(stderr, self.output_encoding);
+        }
+        if self.allow_failure {
+            self.tracker
+                .job_failure_allowed(self.name.clone(), duration, failure);
+        } else {
+            self.annotate_failure(reason);
+            self.tracker
+                .job_failed(self.name.clone(), exit_code, duration, failure);
+        }
+    }
+
+    /// Report the result of a job. If the job's retry policy says it should be run again, nothing
+    /// is recorded and the visitor is returned so it can be used for the next attempt.
+    pub fn job_finished(mut self, cjid: ClientJobId, result: JobOutcomeResult) -> Option<Self> {
+        if let Ok(JobOutcome::Completed(JobCompleted { status, effects })) = &result {
+            if self.retry(status, effects) {
+                return Some(self);
+            }
+        }
+
+        let job_result: JobResult;
         let mut result_details: Option<String> = None;
         let mut test_output_stderr: Vec<String> = vec![];
         let mut test_output_stdout: Vec<String> = vec![];
@@ -189,52 +984,133 @@ impl<ProgressIndicatorT: ProgressIndicator> JobStatusVisitor<ProgressIndicatorT>
                     },
             })) => {
                 duration_str = format!("{:.3}s", duration.as_secs_f64());
+                self.save_output(&stdout, &stderr);
                 let mut job_failed = true;
                 match status {
-                    JobStatus::Exited(code) => {
-                        result_str = if code == 0 {
+                    JobStatus::Exited(code) => match self.exit_code_severities.get(code) {
+                        ExitCodeSeverity::Pass => {
                             job_failed = false;
-                            "OK".green()
-                        } else {
-                            "FAIL".red()
-                        };
-                        self.tracker
-                            .job_exited(self.case.clone(), ExitCode::from(code));
-                    }
+                            job_result = JobResult::Ok;
+                            if let Some(reason) = &self.last_retry_reason {
+                                self.tracker.job_flaky(
+                                    self.name.clone(),
+                                    duration,
+                                    CaseFailure::new(reason),
+                                );
+                            } else {
+                                self.tracker.job_exited(
+                                    self.name.clone(),
+                                    ExitCode::SUCCESS,
+                                    Some(duration),
+                                );
+                            }
+                        }
+                        ExitCodeSeverity::Fail => {
+                            job_result = JobResult::Fail;
+                            if code == 0 {
+                                result_details = Some("exited with code 0".into());
+                            }
+                            let exit_code = if code == 0 {
+                                ExitCode::FAILURE
+                            } else {
+                                ExitCode::from(code)
+                            };
+                            self.job_failed(
+                                exit_code,
+                                Some(duration),
+                                &format!("exited with code {code}"),
+                                Some((&stdout, &stderr)),
+                            );
+                        }
+                        ExitCodeSeverity::Skip => {
+                            job_failed = false;
+                            job_result = JobResult::Ignored;
+                            result_details = Some(format!("exited with code {code}"));
+                            self.tracker.job_ignored(self.name.clone());
+                        }
+                    },
                     JobStatus::Signaled(signo) => {
-                        result_str = "FAIL".red();
+                        job_result = JobResult::Fail;
                         result_details = Some(format!("killed by signal {signo}"));
-                        self.tracker
-                            .job_exited(self.case.clone(), ExitCode::FAILURE);
+                        self.job_failed(
+                            ExitCode::FAILURE,
+                            Some(duration),
+                            &format!("killed by signal {signo}"),
+                            Some((&stdout, &stderr)),
+                        );
                     }
                 };
-                if job_failed {
-                    test_output_stdout.extend(format_test_output(&stdout, "stdout", cjid));
-                    test_output_stderr.extend(format_test_output(&stderr, "stderr", cjid));
+                if job_failed && self.tracker.failure_output_allowed() {
+                    test_output_stdout.extend(format_test_output(
+                        &stdout,
+                        "stdout",
+                        cjid,
+                        self.large_output_threshold,
+                        self.output_encoding,
+                    ));
+                    test_output_stderr.extend(format_test_output(
+                        &stderr,
+                        "stderr",
+                        cjid,
+                        self.large_output_threshold,
+                        self.output_encoding,
+                    ));
                 }
             }
-            Ok(JobOutcome::TimedOut(JobEffects { stdout, stderr, .. })) => {
-                result_str = "TIMEOUT".red();
+            Ok(JobOutcome::TimedOut(JobEffects {
+                stdout,
+                stderr,
+                duration,
+                ..
+            })) => {
+                self.save_output(&stdout, &stderr);
+                job_result = JobResult::Timeout;
                 result_details = Some("timed out".into());
-                self.tracker
-                    .job_exited(self.case.clone(), ExitCode::FAILURE);
-                test_output_stdout.extend(format_test_output(&stdout, "stdout", cjid));
-                test_output_stderr.extend(format_test_output(&stderr, "stderr", cjid));
+                self.job_failed(
+                    ExitCode::FAILURE,
+                    Some(duration),
+                    "timed out",
+                    Some((&stdout, &stderr)),
+                );
+                if self.tracker.failure_output_allowed() {
+                    test_output_stdout.extend(format_test_output(
+                        &stdout,
+                        "stdout",
+                        cjid,
+                        self.large_output_threshold,
+                        self.output_encoding,
+                    ));
+                    test_output_stderr.extend(format_test_output(
+                        &stderr,
+                        "stderr",
+                        cjid,
+                        self.large_output_threshold,
+                        self.output_encoding,
+                    ));
+                }
             }
             Err(JobError::Execution(err)) => {
-                result_str = "ERR".yellow();
+                job_result = JobResult::Error;
                 result_details = Some(format!("execution error: {err}"));
-                self.tracker
-                    .job_exited(self.case.clone(), ExitCode::FAILURE);
+                self.job_failed(
+                    ExitCode::FAILURE,
+                    None,
+                    &format!("execution error: {err}"),
+                    None,
+                );
             }
             Err(JobError::System(err)) => {
-                result_str = "ERR".yellow();
+                job_result = JobResult::Error;
                 result_details = Some(format!("system error: {err}"));
-                self.tracker
-                    .job_exited(self.case.clone(), ExitCode::FAILURE);
+                self.job_failed(
+                    ExitCode::FAILURE,
+                    None,
+                    &format!("system error: {err}"),
+                    None,
+                );
             }
         }
-        self.print_job_result(result_str, duration_str);
+        self.print_job_result(job_result, duration_str);
 
         if let Some(details_str) = result_details {
             self.ind.println(details_str);
@@ -246,11 +1122,41 @@ impl<ProgressIndicatorT: ProgressIndicator> JobStatusVisitor<ProgressIndicatorT>
             self.ind.eprintln(line);
         }
         self.ind.job_finished();
+        None
+    }
+
+    /// Report that we couldn't get the job's result because of an error talking to the client.
+    pub fn job_errored(self, err: &Error) {
+        let reason = format!("client error: {err:#}");
+        self.job_failed(ExitCode::FAILURE, None, &reason, None);
+        self.print_job_result(JobResult::Error, "".into());
+        self.ind.println(reason);
+        self.ind.job_finished();
     }
 
+    /// Report that we gave up waiting for the job to finish after `deadline`.
+    pub fn job_lost(self, deadline: Duration) {
+        let reason = format!("no result after {:.3}s", deadline.as_secs_f64());
+        self.annotate_failure(&reason);
+        self.print_job_result(JobResult::Lost, "".into());
+        self.ind.println(reason.clone());
+        self.tracker
+            .job_lost(self.name.clone(), CaseFailure::new(reason));
+        self.ind.job_finished();
+    }
+
+    /// Report that the job wasn't run because the test is ignored. Ignored tests aren't counted in
+    /// the progress bar's length, so this doesn't advance it.
     pub fn job_ignored(&self) {
-        self.print_job_result("IGNORED".yellow(), "".into());
-        self.tracker.job_ignored(self.case.clone());
+        self.print_job_result(JobResult::Ignored, "".into());
+        self.tracker.job_ignored(self.name.clone());
+    }
+
+    /// Report that the job wasn't run because of the test's `skip_if` conditions.
+    pub fn job_skipped(&self, reason: String) {
+        self.print_job_result(JobResult::Ignored, "".into());
+        self.ind.println(reason);
+        self.tracker.job_ignored(self.name.clone());
         self.ind.job_finished();
     }
 }
@@ -12,8 +12,12 @@ use enumset::EnumSetType;
 pub use enumset::{enum_set, EnumSet};
 use hex::{self, FromHexError};
 pub use nonempty::{nonempty, NonEmpty};
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 use std::{
+    collections::{HashMap, HashSet},
     error::Error,
     fmt::{self, Debug, Formatter},
     hash::Hash,
@@ -105,6 +109,21 @@ impl From<JobDeviceListDeserialize> for JobDevice {
     }
 }
 
+impl JobDevice {
+    /// The name of the device's file in `/dev`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobDevice::Full => "full",
+            JobDevice::Fuse => "fuse",
+            JobDevice::Null => "null",
+            JobDevice::Random => "random",
+            JobDevice::Tty => "tty",
+            JobDevice::Urandom => "urandom",
+            JobDevice::Zero => "zero",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, PartialOrd, Ord, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum JobMountFsType {
@@ -187,6 +206,19 @@ pub struct JobSpec {
     pub user: UserId,
     pub group: GroupId,
     pub timeout: Option<Timeout>,
+    pub enable_init_process: bool,
+    /// The number of CPU cores the job asks for. The broker only sends the job to a worker that
+    /// has at least this many cores not already asked for by the other jobs it was sent. Zero
+    /// means the job doesn't ask for any.
+    pub cpus: u32,
+    /// The number of bytes of memory the job asks for, treated the same way as `cpus`.
+    pub memory: u64,
+    /// Send the job's stdout and stderr to the client in chunks as they're produced, instead of
+    /// in the job's result. The output in the result is then always [`JobOutputResult::None`].
+    pub stream_output: bool,
+    /// Jobs waiting for a worker are sent to one in order of priority, highest first, and then
+    /// in the order they became ready.
+    pub priority: i8,
 }
 
 impl JobSpec {
@@ -207,6 +239,11 @@ impl JobSpec {
             user: UserId::from(0),
             group: GroupId::from(0),
             timeout: None,
+            enable_init_process: false,
+            cpus: 0,
+            memory: 0,
+            stream_output: false,
+            priority: 0,
         }
     }
 
@@ -267,8 +304,101 @@ impl JobSpec {
         self.timeout = timeout.into();
         self
     }
+
+    pub fn enable_init_process(mut self, enable_init_process: bool) -> Self {
+        self.enable_init_process = enable_init_process;
+        self
+    }
+
+    pub fn cpus(mut self, cpus: u32) -> Self {
+        self.cpus = cpus;
+        self
+    }
+
+    pub fn memory(mut self, memory: u64) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    pub fn stream_output(mut self, stream_output: bool) -> Self {
+        self.stream_output = stream_output;
+        self
+    }
+
+    pub fn priority(mut self, priority: i8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Check the constraints on the spec's fields that the worker would otherwise only discover
+    /// when trying to run the job. The first violation found is returned.
+    pub fn validate(&self) -> Result<(), JobSpecError> {
+        if self.program.as_str().is_empty() {
+            return Err(JobSpecError::EmptyProgram);
+        }
+        if !self.working_directory.is_absolute() {
+            return Err(JobSpecError::RelativeWorkingDirectory(
+                self.working_directory.clone(),
+            ));
+        }
+        let mut seen_mount_points = HashSet::new();
+        for mount_point in self.mounts.iter().map(|mount| &mount.mount_point) {
+            if mount_point.as_str().is_empty() {
+                return Err(JobSpecError::EmptyMountPoint);
+            }
+            if !mount_point.is_absolute() {
+                return Err(JobSpecError::RelativeMountPoint(mount_point.clone()));
+            }
+            // Devices are set up before the mounts, so a mount over /dev or / would hide them.
+            if !self.devices.is_empty() && Utf8Path::new("/dev").starts_with(mount_point) {
+                return Err(JobSpecError::MountHidesDevices(mount_point.clone()));
+            }
+            // The kernel would happily stack a second mount on top of the first, hiding it.
+            if !seen_mount_points.insert(mount_point) {
+                return Err(JobSpecError::DuplicateMountPoint(mount_point.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A constraint on a [`JobSpec`]'s fields that was violated. Returned by [`JobSpec::validate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JobSpecError {
+    EmptyProgram,
+    RelativeWorkingDirectory(Utf8PathBuf),
+    EmptyMountPoint,
+    RelativeMountPoint(Utf8PathBuf),
+    MountHidesDevices(Utf8PathBuf),
+    DuplicateMountPoint(Utf8PathBuf),
 }
 
+impl fmt::Display for JobSpecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyProgram => write!(f, "program must not be empty"),
+            Self::RelativeWorkingDirectory(path) => {
+                write!(f, "working directory {path:?} must be an absolute path")
+            }
+            Self::EmptyMountPoint => write!(f, "mount point must not be empty"),
+            Self::RelativeMountPoint(path) => {
+                write!(f, "mount point {path:?} must be an absolute path")
+            }
+            Self::MountHidesDevices(path) => {
+                write!(
+                    f,
+                    "mount point {path:?} would hide the job's devices in /dev"
+                )
+            }
+            Self::DuplicateMountPoint(path) => {
+                write!(f, "mount point {path:?} is used by more than one mount")
+            }
+        }
+    }
+}
+
+impl Error for JobSpecError {}
+
 /// How a job's process terminated. A process can either exit of its own accord or be killed by a
 /// signal.
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize)]
@@ -316,6 +446,18 @@ impl Debug for JobOutputResult {
     }
 }
 
+/// Which of a job's outputs a chunk of streamed output came from. See [`JobSpec::stream_output`].
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub enum JobOutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// The most bytes of a job's streamed output that may be on their way from the worker to the
+/// client at once. Once this many bytes have been sent without the client acknowledging them, the
+/// worker stops reading the job's output, which in turn blocks the job when its pipes fill up.
+pub const JOB_OUTPUT_WINDOW: u32 = 1024 * 1024;
+
 /// The output and duration of a job that ran for some amount of time. This is generated regardless
 /// of how the job terminated. From our point of view, it doesn't matter. We ran the job until it
 /// was terminated, and gathered its output.
@@ -324,6 +466,22 @@ pub struct JobEffects {
     pub stdout: JobOutputResult,
     pub stderr: JobOutputResult,
     pub duration: Duration,
+    pub resource_usage: JobResourceUsage,
+}
+
+/// The resources used by a job, as reported by the kernel when the job's process is reaped. This
+/// covers the job's process and all of the descendants it (or its init process) waited for.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct JobResourceUsage {
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in the kernel.
+    pub system_time: Duration,
+    /// How many times the job gave up the CPU before its time slice was up, usually to wait for
+    /// I/O.
+    pub voluntary_context_switches: u64,
+    /// How many times the job was preempted.
+    pub involuntary_context_switches: u64,
 }
 
 /// The outcome of a completed job. That is, a job that ran to completion, instead of timing out,
@@ -391,8 +549,37 @@ pub type JobOutcomeResult = JobResult<JobOutcome, String>;
 )]
 pub struct WorkerId(u32);
 
+impl WorkerId {
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+/// What a worker is able to provide to the jobs it runs. A worker detects these when it starts and
+/// reports them to the broker in its [`proto::Hello`].
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct WorkerCapabilities {
+    /// The devices that exist on the worker and can be bind-mounted into a job's `/dev`.
+    pub devices: EnumSet<JobDevice>,
+    /// Whether the worker's kernel supports overlayfs.
+    pub overlayfs: bool,
+    /// The cgroup controllers available in the worker's cgroup.
+    pub cgroup_controllers: Vec<String>,
+    /// The number of CPU cores on the worker, which bounds the cores its jobs may ask for.
+    pub cpus: u32,
+    /// The number of bytes of memory on the worker, which bounds the memory its jobs may ask for.
+    pub memory: u64,
+}
+
+/// The capabilities of every worker connected to the broker.
+pub type WorkerCapabilitiesMap = HashMap<WorkerId, WorkerCapabilities>;
+
 /// A SHA-256 digest.
-#[derive(Clone, Constructor, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+///
+/// In human-readable serialization formats, like JSON and TOML, a digest is represented as the same
+/// 64-character hex string that [`Display`](fmt::Display) produces. In compact formats, like
+/// bincode, it is represented as its 32 bytes.
+#[derive(Clone, Constructor, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Sha256Digest([u8; 32]);
 
 impl Sha256Digest {
@@ -471,6 +658,42 @@ impl fmt::Display for Sha256Digest {
     }
 }
 
+impl Serialize for Sha256Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Sha256Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            struct HexVisitor;
+
+            impl<'de> Visitor<'de> for HexVisitor {
+                type Value = Sha256Digest;
+
+                fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                    f.write_str("a SHA-256 digest as a 64-character hex string")
+                }
+
+                fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                    value.parse().map_err(|err| {
+                        E::custom(format_args!("invalid SHA-256 digest {value:?}: {err}"))
+                    })
+                }
+            }
+
+            deserializer.deserialize_str(HexVisitor)
+        } else {
+            <[u8; 32]>::deserialize(deserializer).map(Self)
+        }
+    }
+}
+
 impl Debug for Sha256Digest {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         if f.alternate() {
@@ -645,6 +868,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn serde_json_round_trip_as_hex() {
+        let s = "101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f";
+        let d = s.parse::<Sha256Digest>().unwrap();
+        let json = serde_json::to_string(&d).unwrap();
+        assert_eq!(json, format!("\"{s}\""));
+        assert_eq!(serde_json::from_str::<Sha256Digest>(&json).unwrap(), d);
+    }
+
+    #[test]
+    fn serde_json_bad_hex() {
+        let err = serde_json::from_str::<Sha256Digest>("\"1011\"").unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("invalid SHA-256 digest \"1011\": Invalid string length"),
+            "{err}"
+        );
+        serde_json::from_str::<Sha256Digest>(&format!("{:?}", [0u8; 32])).unwrap_err();
+    }
+
+    #[test]
+    fn bincode_round_trip_as_bytes() {
+        let d = "101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f"
+            .parse::<Sha256Digest>()
+            .unwrap();
+        let bytes = proto::serialize(&d).unwrap();
+        assert_eq!(bytes, Vec::from_iter(16..48));
+        assert_eq!(proto::deserialize::<Sha256Digest>(&bytes).unwrap(), d);
+    }
+
     #[test]
     fn debug() {
         let d = "101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f"
@@ -698,4 +951,109 @@ mod tests {
 )"
         );
     }
+
+    fn valid_spec() -> JobSpec {
+        JobSpec::new(
+            "/bin/true",
+            nonempty![(Sha256Digest::from(1u64), ArtifactType::Tar)],
+        )
+    }
+
+    #[test]
+    fn job_spec_validate_ok() {
+        let spec = valid_spec()
+            .working_directory("/tmp")
+            .devices([JobDevice::Null])
+            .mounts([JobMount {
+                fs_type: JobMountFsType::Proc,
+                mount_point: "/proc".into(),
+            }]);
+        assert_eq!(spec.validate(), Ok(()));
+    }
+
+    #[test]
+    fn job_spec_validate_empty_program() {
+        let mut spec = valid_spec();
+        spec.program = "".into();
+        assert_eq!(spec.validate(), Err(JobSpecError::EmptyProgram));
+        assert_eq!(
+            spec.validate().unwrap_err().to_string(),
+            "program must not be empty"
+        );
+    }
+
+    #[test]
+    fn job_spec_validate_relative_working_directory() {
+        let spec = valid_spec().working_directory("tmp");
+        assert_eq!(
+            spec.validate(),
+            Err(JobSpecError::RelativeWorkingDirectory("tmp".into()))
+        );
+        assert_eq!(
+            spec.validate().unwrap_err().to_string(),
+            r#"working directory "tmp" must be an absolute path"#
+        );
+    }
+
+    #[test]
+    fn job_spec_validate_empty_mount_point() {
+        let spec = valid_spec().mounts([JobMount {
+            fs_type: JobMountFsType::Tmp,
+            mount_point: "".into(),
+        }]);
+        assert_eq!(spec.validate(), Err(JobSpecError::EmptyMountPoint));
+    }
+
+    #[test]
+    fn job_spec_validate_relative_mount_point() {
+        let spec = valid_spec().mounts([JobMount {
+            fs_type: JobMountFsType::Tmp,
+            mount_point: "tmp".into(),
+        }]);
+        assert_eq!(
+            spec.validate(),
+            Err(JobSpecError::RelativeMountPoint("tmp".into()))
+        );
+    }
+
+    #[test]
+    fn job_spec_validate_mount_hides_devices() {
+        let mount = |mount_point: &str| JobMount {
+            fs_type: JobMountFsType::Tmp,
+            mount_point: mount_point.into(),
+        };
+        let spec = valid_spec().mounts([mount("/dev")]);
+        assert_eq!(spec.validate(), Ok(()));
+        let spec = spec.devices([JobDevice::Null]);
+        assert_eq!(
+            spec.validate(),
+            Err(JobSpecError::MountHidesDevices("/dev".into()))
+        );
+        let spec = spec.mounts([mount("/")]);
+        assert_eq!(
+            spec.validate(),
+            Err(JobSpecError::MountHidesDevices("/".into()))
+        );
+        let spec = spec.mounts([mount("/dev/shm")]);
+        assert_eq!(spec.validate(), Ok(()));
+    }
+
+    #[test]
+    fn job_spec_validate_duplicate_mount_point() {
+        let mount = |mount_point: &str| JobMount {
+            fs_type: JobMountFsType::Tmp,
+            mount_point: mount_point.into(),
+        };
+        let spec = valid_spec().mounts([mount("/tmp"), mount("/tmp/")]);
+        assert_eq!(
+            spec.validate(),
+            Err(JobSpecError::DuplicateMountPoint("/tmp/".into()))
+        );
+        assert_eq!(
+            spec.validate().unwrap_err().to_string(),
+            r#"mount point "/tmp/" is used by more than one mount"#
+        );
+        let spec = spec.mounts([mount("/tmp"), mount("/tmp/foo")]);
+        assert_eq!(spec.validate(), Ok(()));
+    }
 }
@@ -1,8 +1,9 @@
 //! Messages sent between various binaries.
 
 use crate::{
-    stats::{BrokerStatistics, JobStateCounts},
-    ClientJobId, JobId, JobOutcomeResult, JobSpec, Sha256Digest,
+    stats::{BrokerStatistics, CachedArtifact, JobStateCounts, WorkerDistribution},
+    ClientJobId, JobId, JobOutcomeResult, JobOutputStream, JobSpec, Sha256Digest,
+    WorkerCapabilities, WorkerCapabilitiesMap,
 };
 use bincode::Options;
 use serde::{Deserialize, Serialize};
@@ -12,7 +13,10 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Hello {
     Client,
-    Worker { slots: u32 },
+    Worker {
+        slots: u32,
+        capabilities: WorkerCapabilities,
+    },
     ArtifactPusher,
     ArtifactFetcher,
 }
@@ -21,24 +25,48 @@ pub enum Hello {
 /// a [`Hello`] and determined the type of its interlocutor.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum BrokerToWorker {
-    EnqueueJob(JobId, JobSpec),
+    EnqueueJob(JobId, Box<JobSpec>),
     CancelJob(JobId),
+    /// The client has consumed this many more bytes of the job's streamed output, so the worker
+    /// may send that many more. See [`crate::JOB_OUTPUT_WINDOW`].
+    JobOutputAck(JobId, u32),
 }
 
 /// Message sent from a worker to the broker. These are responses to previous
 /// [`BrokerToWorker::EnqueueJob`] messages. After sending the initial [`Hello`], a worker will
 /// send a stream of these messages.
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize)]
-pub struct WorkerToBroker(pub JobId, pub JobOutcomeResult);
+pub enum WorkerToBroker {
+    JobResponse(JobId, JobOutcomeResult),
+    /// The job has all of its artifacts and has been started. This is sent before any of the job's
+    /// output.
+    JobRunning(JobId),
+    /// A chunk of output from a job with [`JobSpec::stream_output`] set. All of a job's output is
+    /// sent before its [`WorkerToBroker::JobResponse`].
+    JobOutput(JobId, JobOutputStream, Vec<u8>),
+}
 
 /// Message sent from the broker to a client. The broker won't send a message until it has recevied
 /// a [`Hello`] and determined the type of its interlocutor.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum BrokerToClient {
     JobResponse(ClientJobId, JobOutcomeResult),
+    /// The job has started running on a worker, forwarded from that worker.
+    JobRunning(ClientJobId),
+    /// A chunk of output from a job with [`JobSpec::stream_output`] set, forwarded from the worker
+    /// running it. All of a job's output is sent before its [`BrokerToClient::JobResponse`].
+    JobOutput(ClientJobId, JobOutputStream, Vec<u8>),
     TransferArtifact(Sha256Digest),
     StatisticsResponse(BrokerStatistics),
     JobStateCountsResponse(JobStateCounts),
+    WorkerDistributionResponse(WorkerDistribution),
+    WorkerCapabilitiesResponse(WorkerCapabilitiesMap),
+    /// The artifacts in the broker's cache, sorted by digest.
+    ArtifactsResponse(Vec<CachedArtifact>),
+    /// The client has sent more requests than the broker allows it in a given interval. The broker
+    /// holds on to the extra requests and handles them later. If the client keeps sending requests
+    /// until too many are held, further job requests are rejected.
+    TooManyRequests,
 }
 
 /// Message sent from a client to the broker. After sending the initial [`Hello`], a client will
@@ -49,6 +77,18 @@ pub enum ClientToBroker {
     JobRequest(ClientJobId, JobSpec),
     StatisticsRequest,
     JobStateCountsRequest,
+    WorkerDistributionRequest,
+    WorkerCapabilitiesRequest,
+    ArtifactsRequest,
+    /// Cancel all of the client's outstanding jobs, as if it had disconnected, but leave it
+    /// connected. No [`BrokerToClient::JobResponse`]s are sent for the canceled jobs.
+    CancelAllJobs,
+    /// Cancel one of the client's jobs, wherever it is. No [`BrokerToClient::JobResponse`] is sent
+    /// for it. This does nothing if the job has already completed.
+    CancelJob(ClientJobId),
+    /// The client has consumed this many more bytes of the job's streamed output. The broker
+    /// forwards this to the worker running the job. See [`crate::JOB_OUTPUT_WINDOW`].
+    JobOutputAck(ClientJobId, u32),
 }
 
 /// Message sent from the broker to an artifact fetcher. This will be in response to an
@@ -2,7 +2,7 @@
 
 use crate::{
     ring_buffer::{RingBuffer, RingBufferIter},
-    ClientId, WorkerId,
+    ClientId, Sha256Digest, WorkerId,
 };
 use enum_map::EnumMap;
 use serde::{Deserialize, Serialize};
@@ -67,6 +67,10 @@ fn job_state_iter() {
 /// For a single client, counts of jobs in various states
 pub type JobStateCounts = EnumMap<JobState, u64>;
 
+/// For a single client, how many of its jobs have been completed by each worker. Workers that
+/// haven't completed any of the client's jobs aren't included.
+pub type WorkerDistribution = HashMap<WorkerId, u64>;
+
 /// Single point-in-time snapshot
 /// TODO: This should contain a timestamp
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -134,6 +138,20 @@ impl JobStatisticsTimeSeries {
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct WorkerStatistics {
     pub slots: usize,
+    /// The number of jobs sent to the worker that it hasn't finished yet. This can be up to twice
+    /// `slots`.
+    pub pending: usize,
+}
+
+/// An artifact in the broker's cache.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedArtifact {
+    pub digest: Sha256Digest,
+    /// The number of bytes the artifact takes up in the cache.
+    pub size: u64,
+    /// The number of outstanding uses of the artifact, by jobs waiting to run or by workers
+    /// fetching it. The broker may remove an artifact from its cache once this reaches zero.
+    pub refcount: u32,
 }
 
 /// Useful information for a client to display about the broker's state.
@@ -91,6 +91,11 @@ pub struct Config {
     )]
     pub cache_size: CacheSize,
 
+    /// The maximum number of requests per second to handle from each client. Requests beyond this
+    /// are held and handled later. Once two seconds' worth are held, new jobs are rejected.
+    #[config(option, value_name = "REQUESTS", default = r#""no limit""#)]
+    pub client_request_limit: Option<u32>,
+
     /// Minimum log level to output.
     #[config(short = 'l', value_name = "LEVEL", default = r#""info""#)]
     pub log_level: LogLevel,
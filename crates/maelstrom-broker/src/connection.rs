@@ -129,7 +129,10 @@ async fn unassigned_connection_main(
             .await;
             debug!(log, "client disconnected");
         }
-        Ok(Hello::Worker { slots }) => {
+        Ok(Hello::Worker {
+            slots,
+            capabilities,
+        }) => {
             let (read_stream, write_stream) = socket.into_split();
             let read_stream = BufReader::new(read_stream);
             let id: WorkerId = id_vendor.vend();
@@ -143,6 +146,14 @@ async fn unassigned_connection_main(
                 |id, sender| SchedulerMessage::WorkerConnected(id, slots as usize, sender),
                 SchedulerMessage::WorkerDisconnected,
                 |scheduler_sender| async move {
+                    // This has to come before anything the worker sends us, and after the
+                    // "connected" message that connection_main sent.
+                    if scheduler_sender
+                        .send(SchedulerMessage::GotWorkerCapabilities(id, capabilities))
+                        .is_err()
+                    {
+                        return;
+                    }
                     let _ = net::async_socket_reader(read_stream, scheduler_sender, move |msg| {
                         debug!(log_clone, "received worker message"; "msg" => ?msg);
                         SchedulerMessage::FromWorker(id, msg)
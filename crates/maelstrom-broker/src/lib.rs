@@ -24,6 +24,7 @@ use std::{
 use tokio::{
     net::TcpListener,
     signal::unix::{self, SignalKind},
+    sync::oneshot,
     task::JoinSet,
 };
 
@@ -56,16 +57,33 @@ async fn stats_heartbeat(sender: SchedulerSender) {
     }
 }
 
+fn spawn_signal_handlers(join_set: &mut JoinSet<()>, log: &Logger) {
+    join_set.spawn(signal_handler(
+        SignalKind::interrupt(),
+        log.clone(),
+        "SIGINT",
+    ));
+    join_set.spawn(signal_handler(
+        SignalKind::terminate(),
+        log.clone(),
+        "SIGTERM",
+    ));
+}
+
 /// The main function for the broker. It will return when a signal is received, or when the broker
-/// or http listener socket returns an error at accept time.
+/// or http listener socket returns an error at accept time. Before returning, it waits for the jobs
+/// already queued or sent to workers to complete, unless a second signal is received.
 async fn main_inner_inner(
     listener: TcpListener,
     http_listener: TcpListener,
     cache_root: CacheRoot,
     cache_size: CacheSize,
+    client_request_limit: Option<u32>,
     log: Logger,
 ) {
-    let scheduler_task = SchedulerTask::new(cache_root, cache_size, log.clone());
+    let scheduler_task =
+        SchedulerTask::new(cache_root, cache_size, client_request_limit, log.clone());
+    let scheduler_sender = scheduler_task.scheduler_sender().clone();
     let id_vendor = Arc::new(IdVendor {
         id: AtomicU32::new(0),
     });
@@ -87,18 +105,22 @@ async fn main_inner_inner(
     ));
     join_set.spawn(stats_heartbeat(scheduler_task.scheduler_sender().clone()));
     join_set.spawn(scheduler_task.run());
-    join_set.spawn(signal_handler(
-        SignalKind::interrupt(),
-        log.clone(),
-        "SIGINT",
-    ));
-    join_set.spawn(signal_handler(
-        SignalKind::terminate(),
-        log.clone(),
-        "SIGTERM",
-    ));
+    spawn_signal_handlers(&mut join_set, &log);
 
     join_set.join_next().await;
+
+    info!(log, "draining outstanding jobs");
+    let (shutdown_sender, drained) = oneshot::channel();
+    if scheduler_sender
+        .send(SchedulerMessage::Shutdown(shutdown_sender))
+        .is_ok()
+    {
+        spawn_signal_handlers(&mut join_set, &log);
+        tokio::select! {
+            _ = drained => {}
+            _ = join_set.join_next() => {}
+        }
+    }
 }
 
 pub fn main(config: Config, log: Logger) -> Result<()> {
@@ -134,6 +156,7 @@ async fn main_inner(config: Config, log: Logger) -> Result<()> {
         http_listener,
         config.cache_root,
         config.cache_size,
+        config.client_request_limit,
         log.clone(),
     )
     .await;
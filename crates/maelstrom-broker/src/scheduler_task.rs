@@ -2,7 +2,10 @@ mod cache;
 mod scheduler;
 
 use cache::{Cache, GetArtifactForWorkerError, StdCacheFs};
-use maelstrom_base::proto::{BrokerToClient, BrokerToWorker};
+use maelstrom_base::{
+    proto::{BrokerToClient, BrokerToWorker},
+    stats::BROKER_STATISTICS_INTERVAL,
+};
 use maelstrom_util::{
     config::common::{CacheRoot, CacheSize},
     sync,
@@ -13,7 +16,7 @@ use std::{
     path::{Path, PathBuf},
     sync::mpsc as std_mpsc,
 };
-use tokio::sync::mpsc as tokio_mpsc;
+use tokio::sync::{mpsc as tokio_mpsc, oneshot};
 
 #[derive(Debug)]
 pub struct PassThroughDeps;
@@ -25,6 +28,7 @@ impl SchedulerDeps for PassThroughDeps {
     type WorkerSender = tokio_mpsc::UnboundedSender<BrokerToWorker>;
     type WorkerArtifactFetcherSender =
         std_mpsc::Sender<Result<(PathBuf, u64), GetArtifactForWorkerError>>;
+    type ShutdownSender = oneshot::Sender<()>;
 
     fn send_message_to_client(&mut self, sender: &mut Self::ClientSender, message: BrokerToClient) {
         sender.send(message).ok();
@@ -41,6 +45,10 @@ impl SchedulerDeps for PassThroughDeps {
     ) {
         sender.send(message).ok();
     }
+
+    fn send_drained(&mut self, sender: Self::ShutdownSender) {
+        sender.send(()).ok();
+    }
 }
 
 /// The production scheduler message type. Some [Message] arms contain a
@@ -59,12 +67,23 @@ pub struct SchedulerTask {
 }
 
 impl SchedulerTask {
-    pub fn new(cache_root: CacheRoot, cache_size: CacheSize, log: Logger) -> Self {
+    /// `client_request_limit` is the number of requests per second each client is allowed.
+    pub fn new(
+        cache_root: CacheRoot,
+        cache_size: CacheSize,
+        client_request_limit: Option<u32>,
+        log: Logger,
+    ) -> Self {
         let (sender, receiver) = tokio_mpsc::unbounded_channel();
         let cache = Cache::new(StdCacheFs::new(), cache_root, cache_size, log);
         let cache_tmp_path = cache.tmp_path();
+        // The scheduler counts requests between statistics heartbeats, so scale the limit to
+        // that interval. Never round down to zero though, since then nothing would get through.
+        let client_request_limit = client_request_limit.map(|limit| {
+            ((limit as f64 * BROKER_STATISTICS_INTERVAL.as_secs_f64()).ceil() as u32).max(1)
+        });
         SchedulerTask {
-            scheduler: Scheduler::new(cache),
+            scheduler: Scheduler::new(cache, client_request_limit),
             sender,
             receiver,
             cache_tmp_path,
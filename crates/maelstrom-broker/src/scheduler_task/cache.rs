@@ -6,7 +6,7 @@
 
 use anyhow::{anyhow, bail, Result};
 use bytesize::ByteSize;
-use maelstrom_base::{ClientId, JobId, Sha256Digest};
+use maelstrom_base::{stats::CachedArtifact, ClientId, JobId, Sha256Digest};
 use maelstrom_util::{
     config::common::{CacheRoot, CacheSize},
     heap::{Heap, HeapDeps, HeapIndex},
@@ -441,6 +441,20 @@ impl<FsT: CacheFs> Cache<FsT> {
         })
     }
 
+    /// Notify the cache that a job was canceled while waiting for artifacts. The job won't be
+    /// returned by `got_artifact`.
+    ///
+    /// As with `client_disconnected`, this does nothing about the refcounts the job holds.
+    pub fn job_canceled(&mut self, jid: JobId) {
+        self.entries.retain(|_, e| {
+            let CacheEntry::Waiting(jids, _) = e else {
+                return true;
+            };
+            jids.retain(|waiting| *waiting != jid);
+            !jids.is_empty()
+        })
+    }
+
     /// Get an artifact for a worker.
     ///
     /// On success, this will give the caller a refcount as well as the path to the artifact and
@@ -463,6 +477,29 @@ impl<FsT: CacheFs> Cache<FsT> {
         Ok((self.cache_path(digest), bytes_used))
     }
 
+    /// List the artifacts in the cache, sorted by digest, along with their sizes and refcounts.
+    /// Artifacts that are still being fetched aren't included, since we don't know their sizes
+    /// yet.
+    pub fn artifacts(&self) -> Vec<CachedArtifact> {
+        let mut artifacts = Vec::from_iter(self.entries.iter().filter_map(|(digest, entry)| {
+            let (size, refcount) = match entry {
+                CacheEntry::Waiting(..) => return None,
+                CacheEntry::InUse {
+                    bytes_used,
+                    refcount,
+                } => (*bytes_used, refcount.get()),
+                CacheEntry::InHeap { bytes_used, .. } => (*bytes_used, 0),
+            };
+            Some(CachedArtifact {
+                digest: digest.clone(),
+                size,
+                refcount,
+            })
+        }));
+        artifacts.sort_by(|lhs, rhs| lhs.digest.cmp(&rhs.digest));
+        artifacts
+    }
+
     /// Return a [`PathBuf`] that contains the temporary directory for the cache. This is where
     /// inbound artifacts should go before [`Self::got_artifact`] is called.
     pub fn tmp_path(&self) -> PathBuf {
@@ -776,6 +813,24 @@ mod tests {
         assert_eq!(fixture.cache.bytes_used, 1003);
     }
 
+    #[test]
+    fn new_with_artifacts_from_previous_cache_needs_no_get() {
+        let fs = TestCacheFs {
+            directories: HashMap::from([(
+                path_buf!("/z/sha256"),
+                vec![long_path!("/z/sha256", 1, "bin")],
+            )]),
+            files: HashMap::from([(long_path!("/z/sha256", 1, "bin"), vec![0; 1000])]),
+            ..Default::default()
+        };
+        let mut fixture = Fixture::new_and_clear_fs_operations(fs, 2000);
+        fixture.get_artifact(jid!(1), digest!(1), GetArtifact::Success, vec![]);
+        fixture.get_artifact_for_worker(digest!(1), Ok((long_path!("/z/sha256", 1, "bin"), 1000)));
+        fixture.decrement_refcount(digest!(1), vec![]);
+        fixture.decrement_refcount(digest!(1), vec![]);
+        fixture.get_artifact(jid!(2), digest!(1), GetArtifact::Success, vec![]);
+    }
+
     #[test]
     fn get_artifact_once() {
         let mut fixture = Fixture::new_and_clear_fs_operations(TestCacheFs::default(), 1000);
@@ -976,6 +1031,42 @@ mod tests {
         fixture.decrement_refcount(digest!(1), vec![Remove(long_path!("/z/sha256", 1, "bin"))]);
     }
 
+    #[test]
+    fn artifacts_refcounts_return_to_zero() {
+        let mut fixture = Fixture::new_and_clear_fs_operations(TestCacheFs::default(), 100);
+        fixture.get_artifact_ign(jid!(1, 1001), digest!(1));
+        fixture.get_artifact_ign(jid!(1, 1002), digest!(1));
+        fixture.get_artifact_ign(jid!(1, 1002), digest!(2));
+        assert_eq!(fixture.cache.artifacts(), vec![]);
+
+        fixture.got_artifact_ign(digest!(1), 10, short_path!("/z/tmp", 1, "bin"));
+        fixture.got_artifact_ign(digest!(2), 20, short_path!("/z/tmp", 2, "bin"));
+        fixture.get_artifact_for_worker(digest!(1), Ok((long_path!("/z/sha256", 1, "bin"), 10)));
+        let artifact = |digest, size, refcount| CachedArtifact {
+            digest,
+            size,
+            refcount,
+        };
+        assert_eq!(
+            fixture.cache.artifacts(),
+            vec![artifact(digest!(1), 10, 3), artifact(digest!(2), 20, 1)]
+        );
+
+        // The jobs complete and the worker finishes fetching the artifact.
+        fixture.decrement_refcount_ign(digest!(1));
+        fixture.decrement_refcount_ign(digest!(1));
+        fixture.decrement_refcount_ign(digest!(2));
+        assert_eq!(
+            fixture.cache.artifacts(),
+            vec![artifact(digest!(1), 10, 1), artifact(digest!(2), 20, 0)]
+        );
+        fixture.decrement_refcount_ign(digest!(1));
+        assert_eq!(
+            fixture.cache.artifacts(),
+            vec![artifact(digest!(1), 10, 0), artifact(digest!(2), 20, 0)]
+        );
+    }
+
     #[test]
     fn got_artifact_with_waiter_pushes_out_old() {
         let fs = TestCacheFs {
@@ -1158,6 +1249,45 @@ mod tests {
         fixture.decrement_refcount(digest!(1), vec![Remove(long_path!("/z/sha256", 1, "bin"))]);
     }
 
+    #[test]
+    fn job_canceled_one_of_two_jids() {
+        let mut fixture = Fixture::new(TestCacheFs::default(), 0);
+        fixture.get_artifact_ign(jid!(1, 1001), digest!(1));
+        fixture.get_artifact_ign(jid!(1, 1002), digest!(1));
+        fixture.cache.job_canceled(jid!(1, 1001));
+        fixture.got_artifact(
+            digest!(1),
+            1,
+            short_path!("/z/tmp", 1, "bin"),
+            vec![jid!(1, 1002)],
+            vec![Rename(
+                short_path!("/z/tmp", 1, "bin"),
+                long_path!("/z/sha256", 1, "bin"),
+            )],
+        );
+        fixture.decrement_refcount(digest!(1), vec![Remove(long_path!("/z/sha256", 1, "bin"))]);
+    }
+
+    #[test]
+    fn job_canceled_only_jid() {
+        let mut fixture = Fixture::new(TestCacheFs::default(), 0);
+        fixture.get_artifact_ign(jid!(1, 1001), digest!(1));
+        fixture.cache.job_canceled(jid!(1, 1001));
+        fixture.got_artifact(
+            digest!(1),
+            1,
+            short_path!("/z/tmp", 1, "bin"),
+            vec![],
+            vec![
+                Rename(
+                    short_path!("/z/tmp", 1, "bin"),
+                    long_path!("/z/sha256", 1, "bin"),
+                ),
+                Remove(long_path!("/z/sha256", 1, "bin")),
+            ],
+        );
+    }
+
     #[test]
     fn get_artifact_for_worker_no_entry() {
         let mut fixture = Fixture::new(TestCacheFs::default(), 0);
@@ -7,10 +7,11 @@ use maelstrom_base::{
     manifest::ManifestEntryData,
     proto::{BrokerToClient, BrokerToWorker, ClientToBroker, WorkerToBroker},
     stats::{
-        BrokerStatistics, JobState, JobStateCounts, JobStatisticsSample, JobStatisticsTimeSeries,
-        WorkerStatistics,
+        BrokerStatistics, CachedArtifact, JobState, JobStateCounts, JobStatisticsSample,
+        JobStatisticsTimeSeries, WorkerDistribution, WorkerStatistics,
     },
-    ArtifactType, ClientId, ClientJobId, JobId, JobOutcomeResult, JobSpec, Sha256Digest, WorkerId,
+    ArtifactType, ClientId, ClientJobId, JobError, JobId, JobOutcomeResult, JobOutputStream,
+    JobSpec, Sha256Digest, WorkerCapabilities, WorkerId,
 };
 use maelstrom_util::{
     ext::{BoolExt as _, OptionExt as _},
@@ -21,9 +22,20 @@ use std::{
     collections::{HashMap, HashSet, VecDeque},
     fmt::{self, Debug, Formatter},
     io,
+    ops::{AddAssign, SubAssign},
     path::{Path, PathBuf},
 };
 
+/// How many intervals' worth of requests we hold for a client that is over its request limit. With
+/// the broker's 500ms statistics interval, this is two seconds' worth. Once this many are held, new
+/// requests aren't held anymore: see [`Scheduler::receive_client_message_while_full`].
+const MAX_HELD_INTERVALS: u32 = 4;
+
+/// A worker that was recently sent a job's layers is only preferred for the job over the
+/// least-subscribed worker if the fraction of its slots that are subscribed is higher by at most
+/// one over this.
+const LAYER_AFFINITY_LOAD_BAND: usize = 4;
+
 /*              _     _ _
  *  _ __  _   _| |__ | (_) ___
  * | '_ \| | | | '_ \| | |/ __|
@@ -39,6 +51,7 @@ pub trait SchedulerDeps {
     type ClientSender;
     type WorkerSender;
     type WorkerArtifactFetcherSender;
+    type ShutdownSender;
     fn send_message_to_client(&mut self, sender: &mut Self::ClientSender, message: BrokerToClient);
     fn send_message_to_worker(&mut self, sender: &mut Self::WorkerSender, message: BrokerToWorker);
     fn send_message_to_worker_artifact_fetcher(
@@ -46,6 +59,7 @@ pub trait SchedulerDeps {
         sender: &mut Self::WorkerArtifactFetcherSender,
         message: Result<(PathBuf, u64), GetArtifactForWorkerError>,
     );
+    fn send_drained(&mut self, sender: Self::ShutdownSender);
 }
 
 /// The required interface for the cache that is provided to the [`Scheduler`]. This mirrors the API
@@ -75,11 +89,17 @@ pub trait SchedulerCache {
     /// See [`super::cache::Cache::client_disconnected`].
     fn client_disconnected(&mut self, cid: ClientId);
 
+    /// See [`super::cache::Cache::job_canceled`].
+    fn job_canceled(&mut self, jid: JobId);
+
     /// See [`super::cache::Cache::get_artifact_for_worker`].
     fn get_artifact_for_worker(
         &mut self,
         digest: &Sha256Digest,
     ) -> Result<(PathBuf, u64), GetArtifactForWorkerError>;
+
+    /// See [`super::cache::Cache::artifacts`].
+    fn artifacts(&self) -> Vec<CachedArtifact>;
 }
 
 impl<FsT: CacheFs> SchedulerCache for Cache<FsT> {
@@ -106,12 +126,20 @@ impl<FsT: CacheFs> SchedulerCache for Cache<FsT> {
         self.client_disconnected(cid)
     }
 
+    fn job_canceled(&mut self, jid: JobId) {
+        self.job_canceled(jid)
+    }
+
     fn get_artifact_for_worker(
         &mut self,
         digest: &Sha256Digest,
     ) -> Result<(PathBuf, u64), GetArtifactForWorkerError> {
         self.get_artifact_for_worker(digest)
     }
+
+    fn artifacts(&self) -> Vec<CachedArtifact> {
+        self.artifacts()
+    }
 }
 
 /// The incoming messages, or events, for [`Scheduler`].
@@ -131,6 +159,10 @@ pub enum Message<DepsT: SchedulerDeps> {
     /// on the given sender.
     WorkerConnected(WorkerId, usize, DepsT::WorkerSender),
 
+    /// The given worker reported its capabilities. This is sent right after
+    /// [`Message::WorkerConnected`].
+    GotWorkerCapabilities(WorkerId, WorkerCapabilities),
+
     /// The given worker disconnected.
     WorkerDisconnected(WorkerId),
 
@@ -150,8 +182,14 @@ pub enum Message<DepsT: SchedulerDeps> {
     /// the artifact from being removed while being transferred.
     DecrementRefcount(Sha256Digest),
 
-    /// The stats heartbeat task has decided it's time to take another statistics sample.
+    /// The stats heartbeat task has decided it's time to take another statistics sample. This also
+    /// starts a new interval for rate-limiting client requests.
     StatisticsHeartbeat,
+
+    /// The broker is shutting down. Stop accepting new jobs, but keep running the ones that are
+    /// queued or have been sent to workers. Once there are none left, notify the given sender.
+    /// Jobs still waiting for artifacts at that point are dropped.
+    Shutdown(DepsT::ShutdownSender),
 }
 
 impl<DepsT: SchedulerDeps> Debug for Message<DepsT> {
@@ -171,6 +209,11 @@ impl<DepsT: SchedulerDeps> Debug for Message<DepsT> {
                 .field(wid)
                 .field(slots)
                 .finish(),
+            Message::GotWorkerCapabilities(wid, capabilities) => f
+                .debug_tuple("GotWorkerCapabilities")
+                .field(wid)
+                .field(capabilities)
+                .finish(),
             Message::WorkerDisconnected(wid) => {
                 f.debug_tuple("WorkerDisconnected").field(wid).finish()
             }
@@ -190,6 +233,7 @@ impl<DepsT: SchedulerDeps> Debug for Message<DepsT> {
                 f.debug_tuple("DecrementRefcount").field(digest).finish()
             }
             Message::StatisticsHeartbeat => f.debug_tuple("StatisticsHeartbeat").finish(),
+            Message::Shutdown(_sender) => f.debug_tuple("Shutdown").finish(),
         }
     }
 }
@@ -197,14 +241,23 @@ impl<DepsT: SchedulerDeps> Debug for Message<DepsT> {
 impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
     /// Create a new scheduler with the given [`SchedulerCache`]. Note that [`SchedulerDeps`] are
     /// passed in to `Self::receive_message`.
-    pub fn new(cache: CacheT) -> Self {
+    ///
+    /// If `client_request_limit` is given, each client may only have that many of its requests
+    /// handled between each [`Message::StatisticsHeartbeat`]. Requests beyond that are held until
+    /// a later heartbeat, and the client is sent a [`BrokerToClient::TooManyRequests`]. At most
+    /// [`MAX_HELD_INTERVALS`] heartbeats' worth of requests are held for each client.
+    pub fn new(cache: CacheT, client_request_limit: Option<u32>) -> Self {
         Scheduler {
             cache,
+            client_request_limit,
             clients: HashMap::default(),
             workers: WorkerMap(HashMap::default()),
             queued_requests: VecDeque::default(),
             worker_heap: Heap::default(),
+            recent_layer_workers: RecentLayerWorkers::default(),
             job_statistics: JobStatisticsTimeSeries::default(),
+            draining: false,
+            shutdown_sender: None,
         }
     }
 
@@ -214,22 +267,23 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
         match msg {
             Message::ClientConnected(id, sender) => self.receive_client_connected(id, sender),
             Message::ClientDisconnected(id) => self.receive_client_disconnected(deps, id),
-            Message::FromClient(cid, ClientToBroker::JobRequest(cjid, spec)) => {
-                self.receive_client_job_request(deps, cid, cjid, spec)
-            }
-            Message::FromClient(cid, ClientToBroker::StatisticsRequest) => {
-                self.receive_client_statistics_request(deps, cid)
-            }
-            Message::FromClient(cid, ClientToBroker::JobStateCountsRequest) => {
-                self.receive_client_job_state_counts(deps, cid)
-            }
+            Message::FromClient(cid, msg) => self.receive_client_message(deps, cid, msg),
             Message::WorkerConnected(id, slots, sender) => {
                 self.receive_worker_connected(deps, id, slots, sender)
             }
+            Message::GotWorkerCapabilities(id, capabilities) => {
+                self.receive_worker_capabilities(deps, id, capabilities)
+            }
             Message::WorkerDisconnected(id) => self.receive_worker_disconnected(deps, id),
-            Message::FromWorker(wid, WorkerToBroker(jid, result)) => {
+            Message::FromWorker(wid, WorkerToBroker::JobResponse(jid, result)) => {
                 self.receive_worker_response(deps, wid, jid, result)
             }
+            Message::FromWorker(wid, WorkerToBroker::JobRunning(jid)) => {
+                self.receive_worker_job_running(deps, wid, jid)
+            }
+            Message::FromWorker(wid, WorkerToBroker::JobOutput(jid, stream, output)) => {
+                self.receive_worker_output(deps, wid, jid, stream, output)
+            }
             Message::GotArtifact(digest, size, path) => {
                 self.receive_got_artifact(deps, digest, size, path)
             }
@@ -237,8 +291,10 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
                 self.receive_get_artifact_for_worker(deps, digest, sender)
             }
             Message::DecrementRefcount(digest) => self.receive_decrement_refcount(digest),
-            Message::StatisticsHeartbeat => self.receive_statistics_heartbeat(),
+            Message::StatisticsHeartbeat => self.receive_statistics_heartbeat(deps),
+            Message::Shutdown(sender) => self.receive_shutdown(sender),
         }
+        self.possibly_send_drained(deps);
     }
 }
 
@@ -293,6 +349,9 @@ struct Client<DepsT: SchedulerDeps> {
     sender: DepsT::ClientSender,
     jobs: HashMap<ClientJobId, Job>,
     num_completed_jobs: u64,
+    worker_distribution: WorkerDistribution,
+    requests_this_interval: u32,
+    throttled_requests: VecDeque<ClientToBroker>,
 }
 
 impl<DepsT: SchedulerDeps> Client<DepsT> {
@@ -301,15 +360,102 @@ impl<DepsT: SchedulerDeps> Client<DepsT> {
             sender,
             jobs: HashMap::default(),
             num_completed_jobs: 0,
+            worker_distribution: WorkerDistribution::default(),
+            requests_this_interval: 0,
+            throttled_requests: VecDeque::default(),
+        }
+    }
+}
+
+/// The CPU cores and memory a job asks for, or that a worker has.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Resources {
+    cpus: u64,
+    memory: u64,
+}
+
+impl Resources {
+    fn requested_by(spec: &JobSpec) -> Self {
+        Resources {
+            cpus: spec.cpus.into(),
+            memory: spec.memory,
+        }
+    }
+
+    fn is_none(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn available_on(capabilities: &WorkerCapabilities) -> Self {
+        Resources {
+            cpus: capabilities.cpus.into(),
+            memory: capabilities.memory,
+        }
+    }
+
+    /// Whether `self` is at least `other` in every resource.
+    fn covers(&self, other: Resources) -> bool {
+        self.cpus >= other.cpus && self.memory >= other.memory
+    }
+}
+
+impl AddAssign for Resources {
+    fn add_assign(&mut self, other: Self) {
+        self.cpus += other.cpus;
+        self.memory += other.memory;
+    }
+}
+
+impl SubAssign for Resources {
+    fn sub_assign(&mut self, other: Self) {
+        self.cpus -= other.cpus;
+        self.memory -= other.memory;
+    }
+}
+
+/// The layers of the jobs most recently sent to a worker. The worker will have fetched them to run
+/// the jobs, so it probably still has them in its cache. Only the last [`Self::CAPACITY`] are
+/// remembered.
+#[derive(Default)]
+struct RecentLayers {
+    digests: HashSet<Sha256Digest>,
+    order: VecDeque<Sha256Digest>,
+}
+
+impl RecentLayers {
+    const CAPACITY: usize = 1000;
+
+    /// Remember the layer, returning the one that was forgotten to make room for it, if any.
+    fn insert(&mut self, digest: &Sha256Digest) -> Option<Sha256Digest> {
+        if self.digests.insert(digest.clone()) {
+            self.order.push_back(digest.clone());
+            if self.order.len() > Self::CAPACITY {
+                let oldest = self.order.pop_front().unwrap();
+                self.digests.remove(&oldest).assert_is_true();
+                return Some(oldest);
+            }
         }
+        None
+    }
+
+    fn contains(&self, digest: &Sha256Digest) -> bool {
+        self.digests.contains(digest)
     }
 }
 
 struct Worker<DepsT: SchedulerDeps> {
     slots: usize,
-    pending: HashSet<JobId>,
+    /// The jobs sent to the worker that it hasn't responded to yet, and the resources each asked
+    /// for.
+    pending: HashMap<JobId, Resources>,
+    /// The sum of the resources asked for by the pending jobs.
+    used: Resources,
     heap_index: HeapIndex,
     sender: DepsT::WorkerSender,
+    /// Until the worker reports them, we assume it has no resources to give to jobs that ask for
+    /// them, but we don't know that it couldn't ever run them.
+    capabilities: Option<WorkerCapabilities>,
+    recent_layers: RecentLayers,
 }
 
 impl<DepsT: SchedulerDeps> Worker<DepsT> {
@@ -317,23 +463,130 @@ impl<DepsT: SchedulerDeps> Worker<DepsT> {
         Worker {
             slots,
             sender,
-            pending: HashSet::default(),
+            pending: HashMap::default(),
+            used: Resources::default(),
             heap_index: HeapIndex::default(),
+            capabilities: None,
+            recent_layers: RecentLayers::default(),
         }
     }
+
+    fn is_full(&self) -> bool {
+        self.pending.len() == 2 * self.slots
+    }
+
+    /// Whether the worker could start another job right away.
+    fn has_free_slot(&self) -> bool {
+        self.pending.len() < self.slots
+    }
+
+    /// Whether the worker is loaded about as lightly as `least_loaded`, so that sending it a job
+    /// because it has the job's layers doesn't unbalance the workers much. If `least_loaded` could
+    /// start the job right away, the worker must be able to as well. Beyond that, the fraction of
+    /// its slots that are subscribed may be higher by at most one over
+    /// [`LAYER_AFFINITY_LOAD_BAND`].
+    fn is_in_load_band_of(&self, least_loaded: &Self) -> bool {
+        if least_loaded.has_free_slot() && !self.has_free_slot() {
+            return false;
+        }
+        let band = LAYER_AFFINITY_LOAD_BAND;
+        self.pending.len() * band * least_loaded.slots
+            <= (least_loaded.pending.len() * band + least_loaded.slots) * self.slots
+    }
+
+    /// How many of the job's layers the worker was recently sent jobs for.
+    fn recent_layer_count(&self, spec: &JobSpec) -> usize {
+        spec.layers
+            .iter()
+            .filter(|(digest, _)| self.recent_layers.contains(digest))
+            .count()
+    }
+
+    /// The resources the worker has, or none if it hasn't told us yet.
+    fn resources(&self) -> Resources {
+        self.capabilities
+            .as_ref()
+            .map(Resources::available_on)
+            .unwrap_or_default()
+    }
+
+    /// Whether the resources the worker has, less those asked for by its pending jobs, cover
+    /// `requested`.
+    fn has_free(&self, requested: Resources) -> bool {
+        let mut wanted = self.used;
+        wanted += requested;
+        self.resources().covers(wanted)
+    }
+
+    /// Whether the worker could run a job asking for `requested` once its other jobs were done.
+    /// If it hasn't told us what it has yet, we assume it could.
+    fn could_fit(&self, requested: Resources) -> bool {
+        self.capabilities.is_none() || self.resources().covers(requested)
+    }
+
+    fn add_pending(&mut self, jid: JobId, requested: Resources) {
+        self.pending.insert(jid, requested).assert_is_none();
+        self.used += requested;
+    }
+
+    /// Remove the job from the worker's pending jobs, returning whether it was there.
+    fn remove_pending(&mut self, jid: JobId) -> bool {
+        let Some(requested) = self.pending.remove(&jid) else {
+            return false;
+        };
+        self.used -= requested;
+        true
+    }
 }
 
 struct WorkerMap<DepsT: SchedulerDeps>(HashMap<WorkerId, Worker<DepsT>>);
 
-impl<DepsT: SchedulerDeps> HeapDeps for WorkerMap<DepsT> {
-    type Element = WorkerId;
+/// For each layer, the workers that have it in their [`RecentLayers`]. This lets us find the
+/// workers a job has an affinity for without looking at every worker.
+#[derive(Default)]
+struct RecentLayerWorkers(HashMap<Sha256Digest, HashSet<WorkerId>>);
 
-    fn is_element_less_than(&self, lhs_id: &WorkerId, rhs_id: &WorkerId) -> bool {
+impl RecentLayerWorkers {
+    fn insert(&mut self, digest: &Sha256Digest, wid: WorkerId) {
+        self.0.entry(digest.clone()).or_default().insert(wid);
+    }
+
+    fn remove(&mut self, digest: &Sha256Digest, wid: WorkerId) {
+        let workers = self.0.get_mut(digest).unwrap();
+        workers.remove(&wid).assert_is_true();
+        if workers.is_empty() {
+            self.0.remove(digest);
+        }
+    }
+
+    /// The workers that have any of the job's layers, each only once.
+    fn for_job(&self, spec: &JobSpec) -> HashSet<WorkerId> {
+        spec.layers
+            .iter()
+            .filter_map(|(digest, _)| self.0.get(digest))
+            .flatten()
+            .copied()
+            .collect()
+    }
+}
+
+impl<DepsT: SchedulerDeps> WorkerMap<DepsT> {
+    /// Order workers by the fraction of their slots that are subscribed, breaking ties by worker
+    /// id.
+    fn compare_load(&self, lhs_id: &WorkerId, rhs_id: &WorkerId) -> std::cmp::Ordering {
         let lhs_worker = self.0.get(lhs_id).unwrap();
         let rhs_worker = self.0.get(rhs_id).unwrap();
         let lhs = (lhs_worker.pending.len() * rhs_worker.slots, *lhs_id);
         let rhs = (rhs_worker.pending.len() * lhs_worker.slots, *rhs_id);
-        lhs.cmp(&rhs) == std::cmp::Ordering::Less
+        lhs.cmp(&rhs)
+    }
+}
+
+impl<DepsT: SchedulerDeps> HeapDeps for WorkerMap<DepsT> {
+    type Element = WorkerId;
+
+    fn is_element_less_than(&self, lhs_id: &WorkerId, rhs_id: &WorkerId) -> bool {
+        self.compare_load(lhs_id, rhs_id) == std::cmp::Ordering::Less
     }
 
     fn update_index(&mut self, elem: &WorkerId, idx: HeapIndex) {
@@ -343,24 +596,154 @@ impl<DepsT: SchedulerDeps> HeapDeps for WorkerMap<DepsT> {
 
 pub struct Scheduler<CacheT, DepsT: SchedulerDeps> {
     cache: CacheT,
+    client_request_limit: Option<u32>,
     clients: HashMap<ClientId, Client<DepsT>>,
     workers: WorkerMap<DepsT>,
+    /// Jobs that are ready to be sent to a worker, ordered by priority, highest first. Jobs with
+    /// the same priority are in the order they became ready, except that those taken back from a
+    /// disconnected worker go first.
     queued_requests: VecDeque<JobId>,
     worker_heap: Heap<WorkerMap<DepsT>>,
+    recent_layer_workers: RecentLayerWorkers,
     job_statistics: JobStatisticsTimeSeries,
+    /// Set once we've been told to shut down. New jobs are rejected from then on.
+    draining: bool,
+    /// Where to send notice that we've drained, if we're draining and haven't done so yet.
+    shutdown_sender: Option<DepsT::ShutdownSender>,
 }
 
 impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
-    fn possibly_start_jobs(&mut self, deps: &mut DepsT) {
-        while !self.queued_requests.is_empty() && !self.workers.0.is_empty() {
-            let wid = self.worker_heap.peek().unwrap();
-            let worker = self.workers.0.get_mut(wid).unwrap();
+    /// Pick a worker that isn't full and has enough free resources for the job. A job that asks
+    /// for resources can't go to a worker in `reserved`.
+    ///
+    /// We start with the least-subscribed such worker. For a job that doesn't ask for any
+    /// resources, that's just the top of the heap. Then, among the workers that were recently sent
+    /// jobs with some of this job's layers, and that are in the load band of that worker (see
+    /// [`Worker::is_in_load_band_of`]), we pick the one with the most of the layers, since it
+    /// probably won't have to fetch them again. If there are none, we stick with the
+    /// least-subscribed worker.
+    fn worker_for_job(
+        &self,
+        spec: &JobSpec,
+        requested: Resources,
+        reserved: &HashSet<WorkerId>,
+    ) -> Option<WorkerId> {
+        let can_take = |wid: &WorkerId, worker: &Worker<DepsT>| {
+            !worker.is_full()
+                && (requested.is_none() || (!reserved.contains(wid) && worker.has_free(requested)))
+        };
+        let least_loaded = if requested.is_none() {
+            let top = *self.worker_heap.peek()?;
+            if self.workers.0[&top].is_full() {
+                return None;
+            }
+            top
+        } else {
+            self.workers
+                .0
+                .iter()
+                .filter(|(wid, worker)| can_take(wid, worker))
+                .map(|(wid, _)| *wid)
+                .min_by(|lhs, rhs| self.workers.compare_load(lhs, rhs))?
+        };
+        let least_loaded_worker = &self.workers.0[&least_loaded];
+        let with_layers = self
+            .recent_layer_workers
+            .for_job(spec)
+            .into_iter()
+            .filter(|wid| {
+                let worker = &self.workers.0[wid];
+                can_take(wid, worker) && worker.is_in_load_band_of(least_loaded_worker)
+            })
+            .min_by(|lhs, rhs| {
+                let lhs_count = self.workers.0[lhs].recent_layer_count(spec);
+                let rhs_count = self.workers.0[rhs].recent_layer_count(spec);
+                rhs_count
+                    .cmp(&lhs_count)
+                    .then_with(|| self.workers.compare_load(lhs, rhs))
+            });
+        Some(with_layers.unwrap_or(least_loaded))
+    }
+
+    fn job_priority(&self, jid: JobId) -> i8 {
+        self.clients[&jid.cid].jobs[&jid.cjid].spec.priority
+    }
+
+    /// Add a job that has become ready to the queue, behind all jobs with the same or higher
+    /// priority.
+    fn enqueue_job(&mut self, jid: JobId) {
+        let priority = self.job_priority(jid);
+        let index = self
+            .queued_requests
+            .partition_point(|queued| self.job_priority(*queued) >= priority);
+        self.queued_requests.insert(index, jid);
+    }
+
+    /// Put a job back in the queue, ahead of all jobs with the same or lower priority.
+    fn requeue_job(&mut self, jid: JobId) {
+        let priority = self.job_priority(jid);
+        let index = self
+            .queued_requests
+            .partition_point(|queued| self.job_priority(*queued) > priority);
+        self.queued_requests.insert(index, jid);
+    }
+
+    /// Pick a worker to hold for a job that asks for more resources than any worker has free. Of
+    /// the workers that aren't already held for another job and that could run the job once their
+    /// other jobs are done, we pick the least-subscribed one.
+    fn worker_to_reserve(
+        &self,
+        requested: Resources,
+        reserved: &HashSet<WorkerId>,
+    ) -> Option<WorkerId> {
+        self.workers
+            .0
+            .iter()
+            .filter(|(wid, worker)| !reserved.contains(wid) && worker.could_fit(requested))
+            .map(|(wid, _)| *wid)
+            .min_by(|lhs, rhs| self.workers.compare_load(lhs, rhs))
+    }
+
+    /// Fail a queued job that asks for more resources than any worker has in total.
+    fn reject_unfittable_job(&mut self, deps: &mut DepsT, index: usize, requested: Resources) {
+        let jid = self.queued_requests.remove(index).unwrap();
+        let client = self.clients.get_mut(&jid.cid).unwrap();
+        let job = client.jobs.remove(&jid.cjid).unwrap();
+        for artifact in job.acquired_artifacts {
+            self.cache.decrement_refcount(artifact);
+        }
+        client.num_completed_jobs += 1;
+        deps.send_message_to_client(
+            &mut client.sender,
+            BrokerToClient::JobResponse(
+                jid.cjid,
+                Err(JobError::Execution(format!(
+                    "job asks for {} CPUs and {} bytes of memory, \
+                    but no connected worker has that many",
+                    requested.cpus, requested.memory
+                ))),
+            ),
+        );
+    }
 
-            if worker.pending.len() == 2 * worker.slots {
+    /// Send queued jobs to workers, in queue order.
+    ///
+    /// A job that asks for more resources than any worker has free stays in the queue, and has a
+    /// worker held for it, so that jobs behind it can't keep taking the resources it's waiting
+    /// for. Jobs behind it can still go to other workers, and jobs that don't ask for resources
+    /// can go to any worker. A job that asks for more resources
+    /// than any worker has at all is failed, since it would otherwise wait forever. We only know
+    /// that once every connected worker has told us what it has.
+    fn possibly_start_jobs(&mut self, deps: &mut DepsT) {
+        let mut reserved = HashSet::new();
+        let mut index = 0;
+        while index < self.queued_requests.len() && !self.workers.0.is_empty() {
+            let top = self.worker_heap.peek().unwrap();
+            if self.workers.0.get(top).unwrap().is_full() {
                 break;
             }
 
-            let jid = self.queued_requests.pop_front().unwrap();
+            let jid = self.queued_requests[index];
             let spec = &self
                 .clients
                 .get(&jid.cid)
@@ -369,12 +752,41 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
                 .get(&jid.cjid)
                 .unwrap()
                 .spec;
+            let requested = Resources::requested_by(spec);
+            let Some(wid) = self.worker_for_job(spec, requested, &reserved) else {
+                if !requested.is_none() {
+                    if self
+                        .workers
+                        .0
+                        .values()
+                        .all(|worker| !worker.could_fit(requested))
+                    {
+                        self.reject_unfittable_job(deps, index, requested);
+                        continue;
+                    }
+                    if let Some(wid) = self.worker_to_reserve(requested, &reserved) {
+                        reserved.insert(wid);
+                    }
+                }
+                index += 1;
+                continue;
+            };
+            let spec = spec.clone();
+            self.queued_requests.remove(index).unwrap();
+
+            let worker = self.workers.0.get_mut(&wid).unwrap();
+            for (digest, _) in &spec.layers {
+                if let Some(forgotten) = worker.recent_layers.insert(digest) {
+                    self.recent_layer_workers.remove(&forgotten, wid);
+                }
+                self.recent_layer_workers.insert(digest, wid);
+            }
             deps.send_message_to_worker(
                 &mut worker.sender,
-                BrokerToWorker::EnqueueJob(jid, spec.clone()),
+                BrokerToWorker::EnqueueJob(jid, Box::new(spec)),
             );
 
-            worker.pending.insert(jid).assert_is_true();
+            worker.add_pending(jid, requested);
             let heap_index = worker.heap_index;
             self.worker_heap.sift_down(&mut self.workers, heap_index);
         }
@@ -387,10 +799,17 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
     }
 
     fn receive_client_disconnected(&mut self, deps: &mut DepsT, id: ClientId) {
+        self.cancel_client_jobs(deps, id);
+        self.clients.remove(&id).unwrap();
+    }
+
+    /// Cancel all of the client's jobs, wherever they are: waiting for artifacts, queued, or
+    /// pending on a worker. Any responses for them that workers send later will be ignored.
+    fn cancel_client_jobs(&mut self, deps: &mut DepsT, id: ClientId) {
         self.cache.client_disconnected(id);
 
-        let client = self.clients.remove(&id).unwrap();
-        for job in client.jobs.into_values() {
+        let client = self.clients.get_mut(&id).unwrap();
+        for job in std::mem::take(&mut client.jobs).into_values() {
             for artifact in job.acquired_artifacts {
                 self.cache.decrement_refcount(artifact);
             }
@@ -398,12 +817,13 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
 
         self.queued_requests.retain(|JobId { cid, .. }| *cid != id);
         for worker in self.workers.0.values_mut() {
-            worker.pending.retain(|jid| {
+            worker.pending.retain(|jid, requested| {
                 jid.cid != id || {
                     deps.send_message_to_worker(
                         &mut worker.sender,
                         BrokerToWorker::CancelJob(*jid),
                     );
+                    worker.used -= *requested;
                     false
                 }
             });
@@ -412,6 +832,179 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
         self.possibly_start_jobs(deps);
     }
 
+    /// Cancel one of a client's jobs, wherever it is. If the job has already completed, there is
+    /// nothing to do. As with [`Self::cancel_client_jobs`], a response for the job that the worker
+    /// sends later will be ignored.
+    fn cancel_client_job(&mut self, deps: &mut DepsT, jid: JobId) {
+        let client = self.clients.get_mut(&jid.cid).unwrap();
+        let Some(job) = client.jobs.remove(&jid.cjid) else {
+            return;
+        };
+        if !job.missing_artifacts.is_empty() {
+            self.cache.job_canceled(jid);
+        }
+        for artifact in job.acquired_artifacts {
+            self.cache.decrement_refcount(artifact);
+        }
+
+        self.queued_requests.retain(|queued| *queued != jid);
+        if let Some(worker) = self
+            .workers
+            .0
+            .values_mut()
+            .find(|worker| worker.pending.contains_key(&jid))
+        {
+            worker.remove_pending(jid).assert_is_true();
+            deps.send_message_to_worker(&mut worker.sender, BrokerToWorker::CancelJob(jid));
+            let heap_index = worker.heap_index;
+            self.worker_heap.sift_up(&mut self.workers, heap_index);
+        }
+        self.possibly_start_jobs(deps);
+    }
+
+    /// Forward a client's acknowledgement of some of a job's streamed output to the worker running
+    /// the job. If the job isn't running anywhere anymore, the ack is no longer needed.
+    fn receive_client_job_output_ack(&mut self, deps: &mut DepsT, jid: JobId, bytes: u32) {
+        if let Some(worker) = self
+            .workers
+            .0
+            .values_mut()
+            .find(|worker| worker.pending.contains_key(&jid))
+        {
+            deps.send_message_to_worker(
+                &mut worker.sender,
+                BrokerToWorker::JobOutputAck(jid, bytes),
+            );
+        }
+    }
+
+    fn receive_client_message(&mut self, deps: &mut DepsT, cid: ClientId, msg: ClientToBroker) {
+        // Acks aren't requests, and holding them back would stall the jobs they're for, so they
+        // aren't subject to the request limit.
+        if matches!(msg, ClientToBroker::JobOutputAck(..)) {
+            self.handle_client_message(deps, cid, msg);
+            return;
+        }
+        let client = self.clients.get_mut(&cid).unwrap();
+        if let Some(limit) = self.client_request_limit {
+            if client.requests_this_interval >= limit || !client.throttled_requests.is_empty() {
+                // Only tell the client the first time, not for every request it sends while it's
+                // being throttled.
+                if client.throttled_requests.is_empty() {
+                    deps.send_message_to_client(
+                        &mut client.sender,
+                        BrokerToClient::TooManyRequests,
+                    );
+                }
+                let max_held = limit.saturating_mul(MAX_HELD_INTERVALS) as usize;
+                if client.throttled_requests.len() >= max_held {
+                    self.receive_client_message_while_full(deps, cid, msg);
+                } else {
+                    client.throttled_requests.push_back(msg);
+                }
+                return;
+            }
+        }
+        client.requests_this_interval += 1;
+        self.handle_client_message(deps, cid, msg);
+    }
+
+    /// Handle a request from a client that already has as many requests held as we allow. Holding
+    /// this one too would let a client make us use an unbounded amount of memory.
+    ///
+    /// Job requests are rejected. Cancellations only take work away, so they are applied right
+    /// away: if the job being canceled is still held, its request is just dropped. Statistics
+    /// requests are cheap and don't add any work, so they are answered right away too.
+    fn receive_client_message_while_full(
+        &mut self,
+        deps: &mut DepsT,
+        cid: ClientId,
+        msg: ClientToBroker,
+    ) {
+        let client = self.clients.get_mut(&cid).unwrap();
+        match msg {
+            ClientToBroker::JobRequest(cjid, _) => {
+                deps.send_message_to_client(
+                    &mut client.sender,
+                    BrokerToClient::JobResponse(
+                        cjid,
+                        Err(JobError::System(
+                            "broker is holding too many requests from this client".into(),
+                        )),
+                    ),
+                );
+            }
+            ClientToBroker::CancelJob(cjid) => {
+                let held = client.throttled_requests.len();
+                client.throttled_requests.retain(|request| {
+                    !matches!(request, ClientToBroker::JobRequest(held_cjid, _) if *held_cjid == cjid)
+                });
+                if client.throttled_requests.len() == held {
+                    self.handle_client_message(deps, cid, msg);
+                }
+            }
+            ClientToBroker::CancelAllJobs => {
+                client.throttled_requests.retain(|request| {
+                    !matches!(
+                        request,
+                        ClientToBroker::JobRequest(..) | ClientToBroker::CancelJob(_)
+                    )
+                });
+                self.handle_client_message(deps, cid, msg);
+            }
+            msg => self.handle_client_message(deps, cid, msg),
+        }
+    }
+
+    fn handle_client_message(&mut self, deps: &mut DepsT, cid: ClientId, msg: ClientToBroker) {
+        match msg {
+            ClientToBroker::JobRequest(cjid, spec) => {
+                self.receive_client_job_request(deps, cid, cjid, spec)
+            }
+            ClientToBroker::StatisticsRequest => self.receive_client_statistics_request(deps, cid),
+            ClientToBroker::JobStateCountsRequest => {
+                self.receive_client_job_state_counts(deps, cid)
+            }
+            ClientToBroker::WorkerDistributionRequest => {
+                self.receive_client_worker_distribution_request(deps, cid)
+            }
+            ClientToBroker::WorkerCapabilitiesRequest => {
+                self.receive_client_worker_capabilities_request(deps, cid)
+            }
+            ClientToBroker::ArtifactsRequest => self.receive_client_artifacts_request(deps, cid),
+            ClientToBroker::CancelAllJobs => self.cancel_client_jobs(deps, cid),
+            ClientToBroker::CancelJob(cjid) => self.cancel_client_job(deps, JobId { cid, cjid }),
+            ClientToBroker::JobOutputAck(cjid, bytes) => {
+                self.receive_client_job_output_ack(deps, JobId { cid, cjid }, bytes)
+            }
+        }
+    }
+
+    /// Start a new rate-limiting interval for every client, handling as many of their held
+    /// requests as the limit allows.
+    fn release_throttled_requests(&mut self, deps: &mut DepsT) {
+        // We sort the clients to keep our tests deterministic.
+        let mut cids: Vec<_> = self.clients.keys().copied().collect();
+        cids.sort();
+        for cid in cids {
+            self.clients.get_mut(&cid).unwrap().requests_this_interval = 0;
+            loop {
+                let client = self.clients.get_mut(&cid).unwrap();
+                if self
+                    .client_request_limit
+                    .is_some_and(|limit| client.requests_this_interval >= limit)
+                {
+                    break;
+                }
+                let Some(msg) = client.throttled_requests.pop_front() else {
+                    break;
+                };
+                client.requests_this_interval += 1;
+                self.handle_client_message(deps, cid, msg);
+            }
+        }
+    }
+
     fn ensure_artifact_for_job(
         &mut self,
         deps: &mut DepsT,
@@ -460,6 +1053,16 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
     ) {
         let jid = JobId { cid, cjid };
         let client = self.clients.get_mut(&cid).unwrap();
+        if self.draining {
+            deps.send_message_to_client(
+                &mut client.sender,
+                BrokerToClient::JobResponse(
+                    cjid,
+                    Err(JobError::System("broker is shutting down".into())),
+                ),
+            );
+            return;
+        }
         let layers = spec.layers.clone();
         client.jobs.insert(cjid, Job::new(spec)).assert_is_none();
 
@@ -472,7 +1075,7 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
         let job = client.jobs.get(&jid.cjid).unwrap();
         let have_all_artifacts = job.missing_artifacts.is_empty();
         if have_all_artifacts {
-            self.queued_requests.push_back(jid);
+            self.enqueue_job(jid);
             self.possibly_start_jobs(deps);
         }
     }
@@ -481,7 +1084,13 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
         let worker_iter = self.workers.0.iter();
         let resp = BrokerToClient::StatisticsResponse(BrokerStatistics {
             worker_statistics: worker_iter
-                .map(|(id, w)| (*id, WorkerStatistics { slots: w.slots }))
+                .map(|(id, w)| {
+                    let stats = WorkerStatistics {
+                        slots: w.slots,
+                        pending: w.pending.len(),
+                    };
+                    (*id, stats)
+                })
                 .collect(),
             job_statistics: self.job_statistics.clone(),
         });
@@ -494,6 +1103,28 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
         deps.send_message_to_client(&mut self.clients.get_mut(&cid).unwrap().sender, resp);
     }
 
+    fn receive_client_worker_distribution_request(&mut self, deps: &mut DepsT, cid: ClientId) {
+        let client = self.clients.get_mut(&cid).unwrap();
+        let resp = BrokerToClient::WorkerDistributionResponse(client.worker_distribution.clone());
+        deps.send_message_to_client(&mut client.sender, resp);
+    }
+
+    fn receive_client_worker_capabilities_request(&mut self, deps: &mut DepsT, cid: ClientId) {
+        let resp = BrokerToClient::WorkerCapabilitiesResponse(
+            self.workers
+                .0
+                .iter()
+                .map(|(id, w)| (*id, w.capabilities.clone().unwrap_or_default()))
+                .collect(),
+        );
+        deps.send_message_to_client(&mut self.clients.get_mut(&cid).unwrap().sender, resp);
+    }
+
+    fn receive_client_artifacts_request(&mut self, deps: &mut DepsT, cid: ClientId) {
+        let resp = BrokerToClient::ArtifactsResponse(self.cache.artifacts());
+        deps.send_message_to_client(&mut self.clients.get_mut(&cid).unwrap().sender, resp);
+    }
+
     fn receive_worker_connected(
         &mut self,
         deps: &mut DepsT,
@@ -509,16 +1140,32 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
         self.possibly_start_jobs(deps);
     }
 
+    fn receive_worker_capabilities(
+        &mut self,
+        deps: &mut DepsT,
+        id: WorkerId,
+        capabilities: WorkerCapabilities,
+    ) {
+        self.workers.0.get_mut(&id).unwrap().capabilities = Some(capabilities);
+
+        // Jobs that ask for resources may have been waiting for a worker with enough of them, or
+        // for this worker to report before we could tell that none has enough.
+        self.possibly_start_jobs(deps);
+    }
+
     fn receive_worker_disconnected(&mut self, deps: &mut DepsT, id: WorkerId) {
         let mut worker = self.workers.0.remove(&id).unwrap();
         self.worker_heap
             .remove(&mut self.workers, worker.heap_index);
+        for digest in &worker.recent_layers.order {
+            self.recent_layer_workers.remove(digest, id);
+        }
 
         // We sort the requests to keep our tests deterministic.
-        let mut vec: Vec<_> = worker.pending.drain().collect();
+        let mut vec: Vec<_> = worker.pending.drain().map(|(jid, _)| jid).collect();
         vec.sort();
         for jid in vec.into_iter().rev() {
-            self.queued_requests.push_front(jid);
+            self.requeue_job(jid);
         }
 
         self.possibly_start_jobs(deps);
@@ -533,10 +1180,10 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
     ) {
         let worker = self.workers.0.get_mut(&wid).unwrap();
 
-        if !worker.pending.remove(&jid) {
-            // This indicates that the client isn't around anymore. Just ignore this response from
-            // the worker. When the client disconnected, we canceled all of the outstanding
-            // requests and updated our version of the worker's pending requests.
+        if !worker.remove_pending(jid) {
+            // This indicates that the job was canceled, because its client disconnected or asked
+            // for all of its jobs to be canceled. Just ignore this response from the worker. When
+            // we canceled the job, we updated our version of the worker's pending requests.
             return;
         }
 
@@ -550,30 +1197,48 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
             self.cache.decrement_refcount(artifact);
         }
         client.num_completed_jobs += 1;
+        *client.worker_distribution.entry(wid).or_default() += 1;
 
-        if let Some(jid) = self.queued_requests.pop_front() {
-            let spec = &self
-                .clients
-                .get(&jid.cid)
-                .unwrap()
-                .jobs
-                .get(&jid.cjid)
-                .unwrap()
-                .spec;
-            // If there are any queued_requests, we can just pop one off of the front of
-            // the queue and not have to update the worker's used slot count or position in the
-            // workers list.
-            deps.send_message_to_worker(
-                &mut worker.sender,
-                BrokerToWorker::EnqueueJob(jid, spec.clone()),
-            );
-            worker.pending.insert(jid);
-        } else {
-            // Since there are no queued_requests, we're going to have to update the
-            // worker's position in the workers list.
-            let heap_index = worker.heap_index;
-            self.worker_heap.sift_up(&mut self.workers, heap_index);
+        // The worker now has a free slot, and possibly the resources a queued job was waiting
+        // for. Since it was the job's slot and resources that were freed, the next queued job
+        // usually goes back to this worker.
+        let heap_index = worker.heap_index;
+        self.worker_heap.sift_up(&mut self.workers, heap_index);
+        self.possibly_start_jobs(deps);
+    }
+
+    fn receive_worker_job_running(&mut self, deps: &mut DepsT, wid: WorkerId, jid: JobId) {
+        let worker = self.workers.0.get(&wid).unwrap();
+
+        if !worker.pending.contains_key(&jid) {
+            // As with responses, this indicates that the job was canceled.
+            return;
+        }
+
+        let client = self.clients.get_mut(&jid.cid).unwrap();
+        deps.send_message_to_client(&mut client.sender, BrokerToClient::JobRunning(jid.cjid));
+    }
+
+    fn receive_worker_output(
+        &mut self,
+        deps: &mut DepsT,
+        wid: WorkerId,
+        jid: JobId,
+        stream: JobOutputStream,
+        output: Vec<u8>,
+    ) {
+        let worker = self.workers.0.get(&wid).unwrap();
+
+        if !worker.pending.contains_key(&jid) {
+            // As with responses, this indicates that the job was canceled.
+            return;
         }
+
+        let client = self.clients.get_mut(&jid.cid).unwrap();
+        deps.send_message_to_client(
+            &mut client.sender,
+            BrokerToClient::JobOutput(jid.cjid, stream, output),
+        );
     }
 
     fn ensure_manifest_artifacts_for_job(
@@ -614,7 +1279,7 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
             let client = self.clients.get_mut(&jid.cid).unwrap();
             let job = client.jobs.get_mut(&jid.cjid).unwrap();
             if job.missing_artifacts.is_empty() {
-                self.queued_requests.push_back(jid);
+                self.enqueue_job(jid);
             }
         }
         self.possibly_start_jobs(deps);
@@ -656,7 +1321,7 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
             .workers
             .0
             .values()
-            .flat_map(|w| w.pending.iter())
+            .flat_map(|w| w.pending.keys())
             .filter(|jid| jid.cid == cid)
             .count() as u64;
 
@@ -665,15 +1330,36 @@ impl<CacheT: SchedulerCache, DepsT: SchedulerDeps> Scheduler<CacheT, DepsT> {
         counts
     }
 
-    fn receive_statistics_heartbeat(&mut self) {
-        let sample = JobStatisticsSample {
-            client_to_stats: self
-                .clients
-                .keys()
-                .map(|&cid| (cid, self.sample_job_statistics_for_client(cid)))
+    fn receive_shutdown(&mut self, sender: DepsT::ShutdownSender) {
+        self.draining = true;
+        self.shutdown_sender = Some(sender);
+    }
+
+    /// If we're draining, and there are no more jobs queued or pending on workers, tell whoever
+    /// asked us to shut down.
+    fn possibly_send_drained(&mut self, deps: &mut DepsT) {
+        if self.shutdown_sender.is_some()
+            && self.queued_requests.is_empty()
+            && self
+                .workers
+                .0
+                .values()
+                .all(|worker| worker.pending.is_empty())
+        {
+            deps.send_drained(self.shutdown_sender.take().unwrap());
+        }
+    }
+
+    fn receive_statistics_heartbeat(&mut self, deps: &mut DepsT) {
+        let sample = JobStatisticsSample {
+            client_to_stats: self
+                .clients
+                .keys()
+                .map(|&cid| (cid, self.sample_job_statistics_for_client(cid)))
                 .collect(),
         };
         self.job_statistics.insert(sample);
+        self.release_throttled_requests(deps);
     }
 }
 
@@ -691,8 +1377,10 @@ mod tests {
     use enum_map::enum_map;
     use itertools::Itertools;
     use maelstrom_base::{
+        enum_set,
         manifest::{ManifestEntry, ManifestEntryMetadata, Mode, UnixTimestamp},
         proto::BrokerToWorker::{self, *},
+        JobDevice,
     };
     use maelstrom_test::*;
     use maelstrom_util::manifest::ManifestWriter;
@@ -708,7 +1396,9 @@ mod tests {
         CacheGotArtifact(Sha256Digest, u64, PathBuf),
         CacheDecrementRefcount(Sha256Digest),
         CacheClientDisconnected(ClientId),
+        CacheJobCanceled(JobId),
         CacheGetArtifactForWorker(Sha256Digest),
+        Drained(u32),
     }
 
     use TestMessage::*;
@@ -716,6 +1406,7 @@ mod tests {
     struct TestClientSender(ClientId);
     struct TestWorkerSender(WorkerId);
     struct TestWorkerArtifactFetcherSender(u32);
+    struct TestShutdownSender(u32);
 
     #[derive(Default)]
     struct TestState {
@@ -726,6 +1417,7 @@ mod tests {
         get_artifact_for_worker_returns:
             HashMap<Sha256Digest, Vec<Result<(PathBuf, u64), GetArtifactForWorkerError>>>,
         read_manifest_returns: HashMap<Sha256Digest, Vec<ManifestEntry>>,
+        artifacts_returns: Vec<CachedArtifact>,
     }
 
     impl SchedulerCache for Rc<RefCell<TestState>> {
@@ -777,6 +1469,9 @@ mod tests {
                 .messages
                 .push(CacheClientDisconnected(cid));
         }
+        fn job_canceled(&mut self, jid: JobId) {
+            self.borrow_mut().messages.push(CacheJobCanceled(jid));
+        }
         fn get_artifact_for_worker(
             &mut self,
             digest: &Sha256Digest,
@@ -790,12 +1485,16 @@ mod tests {
                 .unwrap()
                 .remove(0)
         }
+        fn artifacts(&self) -> Vec<CachedArtifact> {
+            self.borrow().artifacts_returns.clone()
+        }
     }
 
     impl SchedulerDeps for Rc<RefCell<TestState>> {
         type ClientSender = TestClientSender;
         type WorkerSender = TestWorkerSender;
         type WorkerArtifactFetcherSender = TestWorkerArtifactFetcherSender;
+        type ShutdownSender = TestShutdownSender;
 
         fn send_message_to_client(
             &mut self,
@@ -822,6 +1521,10 @@ mod tests {
                 .messages
                 .push(ToWorkerArtifactFetcher(sender.0, message));
         }
+
+        fn send_drained(&mut self, sender: TestShutdownSender) {
+            self.borrow_mut().messages.push(Drained(sender.0));
+        }
     }
 
     struct Fixture {
@@ -834,7 +1537,7 @@ mod tests {
             let test_state = Rc::new(RefCell::new(TestState::default()));
             Fixture {
                 test_state: test_state.clone(),
-                scheduler: Scheduler::new(test_state),
+                scheduler: Scheduler::new(test_state, None),
             }
         }
     }
@@ -898,6 +1601,10 @@ mod tests {
         [$n:expr] => { TestWorkerArtifactFetcherSender($n) };
     }
 
+    macro_rules! shutdown_sender {
+        [$n:expr] => { TestShutdownSender($n) };
+    }
+
     macro_rules! script_test {
         ($test_name:ident, $($in_msg:expr => { $($out_msg:expr),* $(,)? });+ $(;)?) => {
             script_test!($test_name, Fixture::default(), $($in_msg => { $($out_msg,)* };)+);
@@ -959,7 +1666,10 @@ mod tests {
         // The response will be ignored unless we use a valid ClientId.
         fixture.receive_message(ClientConnected(cid![1], client_sender![1]));
 
-        fixture.receive_message(FromWorker(wid![1], WorkerToBroker(jid![1], outcome![1])));
+        fixture.receive_message(FromWorker(
+            wid![1],
+            WorkerToBroker::JobResponse(jid![1], outcome![1]),
+        ));
     }
 
     #[test]
@@ -980,7 +1690,7 @@ mod tests {
     script_test! {
         response_from_known_worker_for_unknown_job_ignored,
         WorkerConnected(wid![1], 2, worker_sender![1]) => {};
-        FromWorker(wid![1], WorkerToBroker(jid![1], outcome![1])) => {};
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1], outcome![1])) => {};
     }
 
     script_test! {
@@ -994,18 +1704,74 @@ mod tests {
         WorkerConnected(wid![1], 2, worker_sender![1]) => {};
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid![1, 1], digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1], Box::new(spec![1, Tar]))),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1], outcome![1])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
+            CacheDecrementRefcount(digest![1]),
+        };
+    }
+
+    script_test! {
+        output_from_worker_forwarded_to_client,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1], Box::new(spec![1, Tar]))),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobOutput(jid![1], JobOutputStream::Stdout, b"a".to_vec())) => {
+            ToClient(cid![1], BrokerToClient::JobOutput(cjid![1], JobOutputStream::Stdout, b"a".to_vec())),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobOutput(jid![1], JobOutputStream::Stderr, b"b".to_vec())) => {
+            ToClient(cid![1], BrokerToClient::JobOutput(cjid![1], JobOutputStream::Stderr, b"b".to_vec())),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1], outcome![1])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
+            CacheDecrementRefcount(digest![1]),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobOutput(jid![1], JobOutputStream::Stdout, b"c".to_vec())) => {};
+    }
+
+    script_test! {
+        output_ack_from_client_forwarded_to_worker,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1], Box::new(spec![1, Tar]))),
         };
-        FromWorker(wid![1], WorkerToBroker(jid![1], outcome![1])) => {
+        FromClient(cid![1], ClientToBroker::JobOutputAck(cjid![1], 100)) => {
+            ToWorker(wid![1], JobOutputAck(jid![1], 100)),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1], outcome![1])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
             CacheDecrementRefcount(digest![1]),
         };
+        // Acks that trail the job's response are dropped.
+        FromClient(cid![1], ClientToBroker::JobOutputAck(cjid![1], 100)) => {};
+    }
+
+    script_test! {
+        output_from_worker_for_disconnected_client_ignored,
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        FromWorker(wid![1], WorkerToBroker::JobOutput(jid![1], JobOutputStream::Stdout, b"a".to_vec())) => {};
     }
 
     script_test! {
         response_from_worker_for_disconnected_client_ignored,
         WorkerConnected(wid![1], 2, worker_sender![1]) => {};
-        FromWorker(wid![1], WorkerToBroker(jid![1], outcome![1])) => {};
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1], outcome![1])) => {};
     }
 
     script_test! {
@@ -1032,70 +1798,70 @@ mod tests {
         // 0/2 0/2 0/3
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid![1, 1], digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
 
         // 1/2 0/2 0/3
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
             CacheGetArtifact(jid![1, 2], digest![2]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 2], spec![2, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
         };
 
         // 1/2 1/2 0/3
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
             CacheGetArtifact(jid![1, 3], digest![3]),
-            ToWorker(wid![3], EnqueueJob(jid![1, 3], spec![3, Tar])),
+            ToWorker(wid![3], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar]))),
         };
 
         // 1/2 1/2 1/3
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
             CacheGetArtifact(jid![1, 4], digest![4]),
-            ToWorker(wid![3], EnqueueJob(jid![1, 4], spec![4, Tar])),
+            ToWorker(wid![3], EnqueueJob(jid![1, 4], Box::new(spec![4, Tar]))),
         };
 
         // 1/2 1/2 2/3
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![5], spec![5, Tar])) => {
             CacheGetArtifact(jid![1, 5], digest![5]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 5], spec![5, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 5], Box::new(spec![5, Tar]))),
         };
 
         // 2/2 1/2 2/3
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![6], spec![6, Tar])) => {
             CacheGetArtifact(jid![1, 6], digest![6]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 6], spec![6, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 6], Box::new(spec![6, Tar]))),
         };
 
         // 2/2 2/2 2/3
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![7], spec![7, Tar])) => {
             CacheGetArtifact(jid![1, 7], digest![7]),
-            ToWorker(wid![3], EnqueueJob(jid![1, 7], spec![7, Tar])),
+            ToWorker(wid![3], EnqueueJob(jid![1, 7], Box::new(spec![7, Tar]))),
         };
 
-        FromWorker(wid![1], WorkerToBroker(jid![1, 1], outcome![1])) => {
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
             CacheDecrementRefcount(digest![1]),
         };
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![8], spec![8, Tar])) => {
             CacheGetArtifact(jid![1, 8], digest![8]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 8], spec![8, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 8], Box::new(spec![8, Tar]))),
         };
 
-        FromWorker(wid![2], WorkerToBroker(jid![1, 2], outcome![2])) => {
+        FromWorker(wid![2], WorkerToBroker::JobResponse(jid![1, 2], outcome![2])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![2])),
             CacheDecrementRefcount(digest![2]),
         };
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![9], spec![9, Tar])) => {
             CacheGetArtifact(jid![1, 9], digest![9]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 9], spec![9, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 9], Box::new(spec![9, Tar]))),
         };
 
-        FromWorker(wid![3], WorkerToBroker(jid![1, 3], outcome![3])) => {
+        FromWorker(wid![3], WorkerToBroker::JobResponse(jid![1, 3], outcome![3])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![3], outcome![3])),
             CacheDecrementRefcount(digest![3]),
         };
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![10], spec![10, Tar])) => {
             CacheGetArtifact(jid![1, 10], digest![10]),
-            ToWorker(wid![3], EnqueueJob(jid![1, 10], spec![10, Tar])),
+            ToWorker(wid![3], EnqueueJob(jid![1, 10], Box::new(spec![10, Tar]))),
         };
     }
 
@@ -1118,25 +1884,25 @@ mod tests {
         // 0/1 0/1
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid![1, 1], digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
 
         // 1/1 0/1
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
             CacheGetArtifact(jid![1, 2], digest![2]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 2], spec![2, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
         };
 
         // 1/1 1/1
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
             CacheGetArtifact(jid![1, 3], digest![3]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 3], spec![3, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar]))),
         };
 
         // 2/1 1/1
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
             CacheGetArtifact(jid![1, 4], digest![4]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 4], spec![4, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 4], Box::new(spec![4, Tar]))),
         };
 
         // 2/1 2/1
@@ -1148,17 +1914,17 @@ mod tests {
         };
 
         // 2/2 1/2
-        FromWorker(wid![2], WorkerToBroker(jid![1, 2], outcome![2])) => {
+        FromWorker(wid![2], WorkerToBroker::JobResponse(jid![1, 2], outcome![2])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![2])),
             CacheDecrementRefcount(digest![2]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 5], spec![5, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 5], Box::new(spec![5, Tar]))),
         };
 
         // 1/2 2/2
-        FromWorker(wid![1], WorkerToBroker(jid![1, 1], outcome![1])) => {
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
             CacheDecrementRefcount(digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 6], spec![6, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 6], Box::new(spec![6, Tar]))),
         };
     }
 
@@ -1196,15 +1962,15 @@ mod tests {
         };
 
         WorkerConnected(wid![1], 2, worker_sender![1]) => {
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![2, Tar])),
-            ToWorker(wid![1], EnqueueJob(jid![1, 3], spec![3, Tar])),
-            ToWorker(wid![1], EnqueueJob(jid![1, 4], spec![4, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
+            ToWorker(wid![1], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar]))),
+            ToWorker(wid![1], EnqueueJob(jid![1, 4], Box::new(spec![4, Tar]))),
         };
 
         WorkerConnected(wid![2], 2, worker_sender![2]) => {
-            ToWorker(wid![2], EnqueueJob(jid![1, 5], spec![5, Tar])),
-            ToWorker(wid![2], EnqueueJob(jid![1, 6], spec![6, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 5], Box::new(spec![5, Tar]))),
+            ToWorker(wid![2], EnqueueJob(jid![1, 6], Box::new(spec![6, Tar]))),
         };
     }
 
@@ -1226,37 +1992,37 @@ mod tests {
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid![1, 1], digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
             CacheGetArtifact(jid![1, 2], digest![2]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 2], spec![2, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
         };
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
             CacheGetArtifact(jid![1, 3], digest![3]),
-            ToWorker(wid![3], EnqueueJob(jid![1, 3], spec![3, Tar])),
+            ToWorker(wid![3], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar]))),
         };
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
             CacheGetArtifact(jid![1, 4], digest![4]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 4], spec![4, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 4], Box::new(spec![4, Tar]))),
         };
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![5], spec![5, Tar])) => {
             CacheGetArtifact(jid![1, 5], digest![5]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 5], spec![5, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 5], Box::new(spec![5, Tar]))),
         };
 
         WorkerDisconnected(wid![1]) => {
-            ToWorker(wid![3], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![3], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
 
-        FromWorker(wid![2], WorkerToBroker(jid![1, 2], outcome![2])) => {
+        FromWorker(wid![2], WorkerToBroker::JobResponse(jid![1, 2], outcome![2])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![2])),
             CacheDecrementRefcount(digest![2]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 4], spec![4, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 4], Box::new(spec![4, Tar]))),
         };
     }
 
@@ -1275,12 +2041,12 @@ mod tests {
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid![1, 1], digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
             CacheGetArtifact(jid![1, 2], digest![2]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![2, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
         };
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
@@ -1290,24 +2056,24 @@ mod tests {
             CacheGetArtifact(jid![1, 4], digest![4]),
         };
 
-        FromWorker(wid![1], WorkerToBroker(jid![1, 1], outcome![1])) => {
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
             CacheDecrementRefcount(digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 3], spec![3, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar]))),
         };
 
         WorkerConnected(wid![2], 1, worker_sender![2]) => {
-            ToWorker(wid![2], EnqueueJob(jid![1, 4], spec![4, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 4], Box::new(spec![4, Tar]))),
         };
 
         WorkerDisconnected(wid![1]) => {
-            ToWorker(wid![2], EnqueueJob(jid![1, 2], spec![2, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
         };
 
-        FromWorker(wid![2], WorkerToBroker(jid![1, 2], outcome![2])) => {
+        FromWorker(wid![2], WorkerToBroker::JobResponse(jid![1, 2], outcome![2])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![2])),
             CacheDecrementRefcount(digest![2]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 3], spec![3, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar]))),
         };
     }
 
@@ -1326,26 +2092,120 @@ mod tests {
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid![1, 1], digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
+
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
+            CacheGetArtifact(jid![1, 2], digest![2]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
+        };
+
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
+            CacheGetArtifact(jid![1, 3], digest![3]),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
+            CacheGetArtifact(jid![1, 4], digest![4]),
+        };
+
+        WorkerDisconnected(wid![1]) => {};
+
+        WorkerConnected(wid![2], 1, worker_sender![2]) => {
+            ToWorker(wid![2], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
         };
+    }
+
+    script_test! {
+        higher_priority_request_goes_ahead_of_queued_requests,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![2]), vec![GetArtifact::Success]),
+                ((jid![1, 3], digest![3]), vec![GetArtifact::Success]),
+                ((jid![1, 4], digest![4]), vec![GetArtifact::Success]),
+                ((jid![1, 5], digest![5]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
+        ClientConnected(cid![1], client_sender![1]) => {};
 
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
             CacheGetArtifact(jid![1, 2], digest![2]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![2, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
         };
 
+        // The worker is full, so these are queued.
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
             CacheGetArtifact(jid![1, 3], digest![3]),
         };
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
             CacheGetArtifact(jid![1, 4], digest![4]),
         };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![5], spec![5, Tar].priority(1))) => {
+            CacheGetArtifact(jid![1, 5], digest![5]),
+        };
+
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
+            CacheDecrementRefcount(digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 5], Box::new(spec![5, Tar].priority(1)))),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 2], outcome![2])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![2])),
+            CacheDecrementRefcount(digest![2]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar]))),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 5], outcome![5])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![5], outcome![5])),
+            CacheDecrementRefcount(digest![5]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 4], Box::new(spec![4, Tar]))),
+        };
+    }
+
+    script_test! {
+        requests_outstanding_on_disconnected_worker_keep_priority_order,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![2]), vec![GetArtifact::Success]),
+                ((jid![1, 3], digest![3]), vec![GetArtifact::Success]),
+                ((jid![1, 4], digest![4]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
+        ClientConnected(cid![1], client_sender![1]) => {};
+
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar].priority(2))) => {
+            CacheGetArtifact(jid![1, 2], digest![2]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar].priority(2)))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar].priority(1))) => {
+            CacheGetArtifact(jid![1, 3], digest![3]),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
+            CacheGetArtifact(jid![1, 4], digest![4]),
+        };
 
+        // The outstanding jobs go back in the queue: job 2 ahead of everything, and job 1 ahead of
+        // the other job with its priority, but behind job 3.
         WorkerDisconnected(wid![1]) => {};
 
         WorkerConnected(wid![2], 1, worker_sender![2]) => {
-            ToWorker(wid![2], EnqueueJob(jid![1, 1], spec![1, Tar])),
-            ToWorker(wid![2], EnqueueJob(jid![1, 2], spec![2, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar].priority(2)))),
+            ToWorker(wid![2], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar].priority(1)))),
+        };
+        FromWorker(wid![2], WorkerToBroker::JobResponse(jid![1, 2], outcome![2])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![2])),
+            CacheDecrementRefcount(digest![2]),
+            ToWorker(wid![2], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
     }
 
@@ -1363,20 +2223,20 @@ mod tests {
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid![1, 1], digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
             CacheGetArtifact(jid![1, 2], digest![2]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![2, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
         };
 
-        FromWorker(wid![1], WorkerToBroker(jid![1, 1], outcome![1])) => {
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
             CacheDecrementRefcount(digest![1]),
         };
 
-        FromWorker(wid![1], WorkerToBroker(jid![1, 2], outcome![1])) => {
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 2], outcome![1])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![1])),
             CacheDecrementRefcount(digest![2]),
         };
@@ -1397,7 +2257,7 @@ mod tests {
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid!(1, 1), digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
 
         ClientDisconnected(cid![1]) => {
@@ -1423,12 +2283,12 @@ mod tests {
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid!(1, 1), digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
 
         FromClient(cid![2], ClientToBroker::JobRequest(cjid![1], spec![2, Tar])) => {
             CacheGetArtifact(jid!(2, 1), digest![2]),
-            ToWorker(wid![2], EnqueueJob(jid![2, 1], spec![2, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![2, 1], Box::new(spec![2, Tar]))),
         };
 
         ClientDisconnected(cid![2]) => {
@@ -1439,7 +2299,7 @@ mod tests {
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![3, Tar])) => {
             CacheGetArtifact(jid!(1, 2), digest![3]),
-            ToWorker(wid![2], EnqueueJob(jid![1, 2], spec![3, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![3, Tar]))),
         };
     }
 
@@ -1458,12 +2318,12 @@ mod tests {
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid!(1, 1), digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
             CacheGetArtifact(jid!(1, 2), digest![2]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![2, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
         };
 
         ClientConnected(cid![2], client_sender![2]) => {};
@@ -1479,10 +2339,10 @@ mod tests {
             CacheClientDisconnected(cid![2]),
         };
 
-        FromWorker(wid![1], WorkerToBroker(jid![1, 1], outcome![1])) => {
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
             CacheDecrementRefcount(digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 3], spec![3, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar]))),
         };
     }
 
@@ -1505,22 +2365,22 @@ mod tests {
 
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid!(1, 1), digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
 
         FromClient(cid![2], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid!(2, 1), digest![1]),
-            ToWorker(wid![2], EnqueueJob(jid![2, 1], spec![1, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![2, 1], Box::new(spec![1, Tar]))),
         };
 
         FromClient(cid![2], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
             CacheGetArtifact(jid!(2, 2), digest![2]),
-            ToWorker(wid![1], EnqueueJob(jid![2, 2], spec![2, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![2, 2], Box::new(spec![2, Tar]))),
         };
 
         FromClient(cid![2], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
             CacheGetArtifact(jid!(2, 3), digest![3]),
-            ToWorker(wid![2], EnqueueJob(jid![2, 3], spec![3, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![2, 3], Box::new(spec![3, Tar]))),
         };
 
         FromClient(cid![2], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
@@ -1540,127 +2400,321 @@ mod tests {
             ToWorker(wid![1], CancelJob(jid![2, 2])),
             ToWorker(wid![2], CancelJob(jid![2, 3])),
 
-            ToWorker(wid![2], EnqueueJob(jid![1, 2], spec![2, Tar])),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
 
             CacheClientDisconnected(cid![2]),
         };
     }
 
     script_test! {
-        request_with_layers,
+        cancel_all_jobs,
         {
             Fixture::new([
-                ((jid![1, 2], digest![42]), vec![GetArtifact::Success]),
-                ((jid![1, 2], digest![43]), vec![GetArtifact::Wait]),
-                ((jid![1, 2], digest![44]), vec![GetArtifact::Get]),
+                ((jid!(1, 1), digest![1]), vec![GetArtifact::Success]),
+                ((jid!(1, 2), digest![2]), vec![GetArtifact::Success]),
+                ((jid!(1, 3), digest![3]), vec![GetArtifact::Success]),
+                ((jid!(1, 4), digest![4]), vec![GetArtifact::Get]),
+                ((jid!(1, 5), digest![5]), vec![GetArtifact::Success]),
             ], [], [], [])
         },
         WorkerConnected(wid![1], 1, worker_sender![1]) => {};
         ClientConnected(cid![1], client_sender![1]) => {};
 
-        FromClient(
-            cid![1],
-            ClientToBroker::JobRequest(cjid![2], spec![1, [(42, Tar), (43, Tar), (44, Tar)]])
-        ) => {
-            CacheGetArtifact(jid![1, 2], digest![42]),
-            CacheGetArtifact(jid![1, 2], digest![43]),
-            CacheGetArtifact(jid![1, 2], digest![44]),
-            ToClient(cid![1], BrokerToClient::TransferArtifact(digest![44])),
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid!(1, 1), digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
+            CacheGetArtifact(jid!(1, 2), digest![2]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
+            CacheGetArtifact(jid!(1, 3), digest![3]),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
+            CacheGetArtifact(jid!(1, 4), digest![4]),
+            ToClient(cid![1], BrokerToClient::TransferArtifact(digest![4])),
         };
 
-        ClientDisconnected(cid![1]) => {
+        FromClient(cid![1], ClientToBroker::CancelAllJobs) => {
             CacheClientDisconnected(cid![1]),
-            CacheDecrementRefcount(digest![42]),
-        }
+            CacheDecrementRefcount(digest![1]),
+            CacheDecrementRefcount(digest![2]),
+            CacheDecrementRefcount(digest![3]),
+            ToWorker(wid![1], CancelJob(jid![1, 1])),
+            ToWorker(wid![1], CancelJob(jid![1, 2])),
+        };
+
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {};
+
+        // The client is still connected and can submit more jobs.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![5], spec![5, Tar])) => {
+            CacheGetArtifact(jid!(1, 5), digest![5]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 5], Box::new(spec![5, Tar]))),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 5], outcome![5])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![5], outcome![5])),
+            CacheDecrementRefcount(digest![5]),
+        };
     }
 
     script_test! {
-        request_with_layers_2,
+        job_running_forwarded_to_client,
         {
             Fixture::new([
-                ((jid![1, 2], digest![42]), vec![GetArtifact::Success]),
-                ((jid![1, 2], digest![43]), vec![GetArtifact::Success]),
-                ((jid![1, 2], digest![44]), vec![GetArtifact::Success]),
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
             ], [], [], [])
         },
-        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
         ClientConnected(cid![1], client_sender![1]) => {};
-
-        FromClient(
-            cid![1],
-            ClientToBroker::JobRequest(cjid![2], spec![1, [(42, Tar), (43, Tar), (44, Tar)]])
-        ) => {
-            CacheGetArtifact(jid![1, 2], digest![42]),
-            CacheGetArtifact(jid![1, 2], digest![43]),
-            CacheGetArtifact(jid![1, 2], digest![44]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![1, [(42, Tar), (43, Tar), (44, Tar)]])),
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1], Box::new(spec![1, Tar]))),
         };
-
-        FromWorker(wid![1], WorkerToBroker(jid![1, 2], outcome![1])) => {
-            ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![1])),
-            CacheDecrementRefcount(digest![42]),
-            CacheDecrementRefcount(digest![43]),
-            CacheDecrementRefcount(digest![44]),
+        FromWorker(wid![1], WorkerToBroker::JobRunning(jid![1])) => {
+            ToClient(cid![1], BrokerToClient::JobRunning(cjid![1])),
         };
+        FromClient(cid![1], ClientToBroker::CancelJob(cjid![1])) => {
+            CacheDecrementRefcount(digest![1]),
+            ToWorker(wid![1], CancelJob(jid![1, 1])),
+        };
+        // Nothing is forwarded for a canceled job.
+        FromWorker(wid![1], WorkerToBroker::JobRunning(jid![1])) => {};
     }
 
     script_test! {
-        request_with_layers_3,
+        cancel_job,
         {
             Fixture::new([
-                ((jid![1, 2], digest![42]), vec![GetArtifact::Success]),
-                ((jid![1, 2], digest![43]), vec![GetArtifact::Wait]),
-                ((jid![1, 2], digest![44]), vec![GetArtifact::Get]),
-            ], [
-                (digest![43], vec![vec![jid![1, 2]]]),
-                (digest![44], vec![vec![jid![1, 2]]]),
-            ], [], [])
+                ((jid!(1, 1), digest![1]), vec![GetArtifact::Success]),
+                ((jid!(1, 2), digest![2]), vec![GetArtifact::Success]),
+                ((jid!(1, 3), digest![3]), vec![GetArtifact::Success]),
+                ((jid!(1, 4), digest![4]), vec![GetArtifact::Get]),
+            ], [], [], [])
         },
         WorkerConnected(wid![1], 1, worker_sender![1]) => {};
         ClientConnected(cid![1], client_sender![1]) => {};
 
-        FromClient(
-            cid![1],
-            ClientToBroker::JobRequest(cjid![2], spec![1, [(42, Tar), (43, Tar), (44, Tar)]])
-        ) => {
-            CacheGetArtifact(jid![1, 2], digest![42]),
-            CacheGetArtifact(jid![1, 2], digest![43]),
-            CacheGetArtifact(jid![1, 2], digest![44]),
-            ToClient(cid![1], BrokerToClient::TransferArtifact(digest![44])),
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid!(1, 1), digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
+            CacheGetArtifact(jid!(1, 2), digest![2]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
+            CacheGetArtifact(jid!(1, 3), digest![3]),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
+            CacheGetArtifact(jid!(1, 4), digest![4]),
+            ToClient(cid![1], BrokerToClient::TransferArtifact(digest![4])),
         };
 
-        GotArtifact(digest![43], 100, "/z/tmp/foo".into()) => {
-            CacheGotArtifact(digest![43], 100, "/z/tmp/foo".into()),
+        // Canceling a job that is waiting for artifacts tells the cache.
+        FromClient(cid![1], ClientToBroker::CancelJob(cjid![4])) => {
+            CacheJobCanceled(jid![1, 4]),
         };
-        GotArtifact(digest![44], 100, "/z/tmp/bar".into()) => {
-            CacheGotArtifact(digest![44], 100, "/z/tmp/bar".into()),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![1, [(42, Tar), (43, Tar), (44, Tar)]])),
+
+        // Canceling a queued job just takes it out of the queue.
+        FromClient(cid![1], ClientToBroker::CancelJob(cjid![3])) => {
+            CacheDecrementRefcount(digest![3]),
         };
 
-        ClientDisconnected(cid![1]) => {
-            ToWorker(wid![1], CancelJob(jid![1, 2])),
-            CacheClientDisconnected(cid![1]),
-            CacheDecrementRefcount(digest![42]),
-            CacheDecrementRefcount(digest![43]),
-            CacheDecrementRefcount(digest![44]),
-        }
+        // Canceling a job on a worker frees up room for others.
+        FromClient(cid![1], ClientToBroker::CancelJob(cjid![1])) => {
+            CacheDecrementRefcount(digest![1]),
+            ToWorker(wid![1], CancelJob(jid![1, 1])),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {};
+
+        // The other jobs are unaffected.
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 2], outcome![2])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![2])),
+            CacheDecrementRefcount(digest![2]),
+        };
+
+        // Canceling a job that has already completed does nothing.
+        FromClient(cid![1], ClientToBroker::CancelJob(cjid![2])) => {};
     }
 
     script_test! {
-        request_with_duplicate_layers_in_cache,
+        shutdown_with_nothing_outstanding,
+        ClientConnected(cid![1], client_sender![1]) => {};
+        Shutdown(shutdown_sender![1]) => {
+            Drained(1),
+        };
+    }
+
+    script_test! {
+        shutdown_drains_outstanding_jobs,
         {
             Fixture::new([
-                ((jid![1, 2], digest![42]), vec![GetArtifact::Success]),
+                ((jid!(1, 1), digest![1]), vec![GetArtifact::Success]),
+                ((jid!(1, 2), digest![2]), vec![GetArtifact::Success]),
+                ((jid!(1, 3), digest![3]), vec![GetArtifact::Success]),
             ], [], [], [])
         },
         WorkerConnected(wid![1], 1, worker_sender![1]) => {};
         ClientConnected(cid![1], client_sender![1]) => {};
 
-        FromClient(
-            cid![1], ClientToBroker::JobRequest(cjid![2], spec![1, [(42, Tar), (42, Tar)]])
-        ) => {
-            CacheGetArtifact(jid![1, 2], digest![42]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![1, [(42, Tar), (42, Tar)]])),
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid!(1, 1), digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
+            CacheGetArtifact(jid!(1, 2), digest![2]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
+            CacheGetArtifact(jid!(1, 3), digest![3]),
+        };
+
+        Shutdown(shutdown_sender![1]) => {};
+
+        // New jobs are rejected while draining.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(
+                cjid![4],
+                Err(JobError::System(string!("broker is shutting down"))),
+            )),
+        };
+
+        // Outstanding jobs, including queued ones, are still run.
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
+            CacheDecrementRefcount(digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar]))),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 2], outcome![2])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![2])),
+            CacheDecrementRefcount(digest![2]),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 3], outcome![3])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![3], outcome![3])),
+            CacheDecrementRefcount(digest![3]),
+            Drained(1),
+        };
+
+        // We only say we've drained once.
+        StatisticsHeartbeat => {};
+    }
+
+    script_test! {
+        request_with_layers,
+        {
+            Fixture::new([
+                ((jid![1, 2], digest![42]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![43]), vec![GetArtifact::Wait]),
+                ((jid![1, 2], digest![44]), vec![GetArtifact::Get]),
+            ], [], [], [])
+        },
+        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
+        ClientConnected(cid![1], client_sender![1]) => {};
+
+        FromClient(
+            cid![1],
+            ClientToBroker::JobRequest(cjid![2], spec![1, [(42, Tar), (43, Tar), (44, Tar)]])
+        ) => {
+            CacheGetArtifact(jid![1, 2], digest![42]),
+            CacheGetArtifact(jid![1, 2], digest![43]),
+            CacheGetArtifact(jid![1, 2], digest![44]),
+            ToClient(cid![1], BrokerToClient::TransferArtifact(digest![44])),
+        };
+
+        ClientDisconnected(cid![1]) => {
+            CacheClientDisconnected(cid![1]),
+            CacheDecrementRefcount(digest![42]),
+        }
+    }
+
+    script_test! {
+        request_with_layers_2,
+        {
+            Fixture::new([
+                ((jid![1, 2], digest![42]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![43]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![44]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
+        ClientConnected(cid![1], client_sender![1]) => {};
+
+        FromClient(
+            cid![1],
+            ClientToBroker::JobRequest(cjid![2], spec![1, [(42, Tar), (43, Tar), (44, Tar)]])
+        ) => {
+            CacheGetArtifact(jid![1, 2], digest![42]),
+            CacheGetArtifact(jid![1, 2], digest![43]),
+            CacheGetArtifact(jid![1, 2], digest![44]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![1, [(42, Tar), (43, Tar), (44, Tar)]]))),
+        };
+
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 2], outcome![1])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![1])),
+            CacheDecrementRefcount(digest![42]),
+            CacheDecrementRefcount(digest![43]),
+            CacheDecrementRefcount(digest![44]),
+        };
+    }
+
+    script_test! {
+        request_with_layers_3,
+        {
+            Fixture::new([
+                ((jid![1, 2], digest![42]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![43]), vec![GetArtifact::Wait]),
+                ((jid![1, 2], digest![44]), vec![GetArtifact::Get]),
+            ], [
+                (digest![43], vec![vec![jid![1, 2]]]),
+                (digest![44], vec![vec![jid![1, 2]]]),
+            ], [], [])
+        },
+        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
+        ClientConnected(cid![1], client_sender![1]) => {};
+
+        FromClient(
+            cid![1],
+            ClientToBroker::JobRequest(cjid![2], spec![1, [(42, Tar), (43, Tar), (44, Tar)]])
+        ) => {
+            CacheGetArtifact(jid![1, 2], digest![42]),
+            CacheGetArtifact(jid![1, 2], digest![43]),
+            CacheGetArtifact(jid![1, 2], digest![44]),
+            ToClient(cid![1], BrokerToClient::TransferArtifact(digest![44])),
+        };
+
+        GotArtifact(digest![43], 100, "/z/tmp/foo".into()) => {
+            CacheGotArtifact(digest![43], 100, "/z/tmp/foo".into()),
+        };
+        GotArtifact(digest![44], 100, "/z/tmp/bar".into()) => {
+            CacheGotArtifact(digest![44], 100, "/z/tmp/bar".into()),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![1, [(42, Tar), (43, Tar), (44, Tar)]]))),
+        };
+
+        ClientDisconnected(cid![1]) => {
+            ToWorker(wid![1], CancelJob(jid![1, 2])),
+            CacheClientDisconnected(cid![1]),
+            CacheDecrementRefcount(digest![42]),
+            CacheDecrementRefcount(digest![43]),
+            CacheDecrementRefcount(digest![44]),
+        }
+    }
+
+    script_test! {
+        request_with_duplicate_layers_in_cache,
+        {
+            Fixture::new([
+                ((jid![1, 2], digest![42]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
+        ClientConnected(cid![1], client_sender![1]) => {};
+
+        FromClient(
+            cid![1], ClientToBroker::JobRequest(cjid![2], spec![1, [(42, Tar), (42, Tar)]])
+        ) => {
+            CacheGetArtifact(jid![1, 2], digest![42]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![1, [(42, Tar), (42, Tar)]]))),
         };
 
         ClientDisconnected(cid![1]) => {
@@ -1691,7 +2745,7 @@ mod tests {
 
         GotArtifact(digest![42], 100, "/z/tmp/bar".into()) => {
             CacheGotArtifact(digest![42], 100, "/z/tmp/bar".into()),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![1, [(42, Tar), (42, Tar)]])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![1, [(42, Tar), (42, Tar)]]))),
         };
 
         ClientDisconnected(cid![1]) => {
@@ -1738,7 +2792,7 @@ mod tests {
 
         GotArtifact(digest![43], 100, "/z/tmp/bar".into()) => {
             CacheGotArtifact(digest![43], 100, "/z/tmp/bar".into()),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![1, [(42, Manifest)]])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![1, [(42, Manifest)]]))),
         };
 
         ClientDisconnected(cid![1]) => {
@@ -1797,7 +2851,7 @@ mod tests {
 
         GotArtifact(digest![43], 100, "/z/tmp/bar".into()) => {
             CacheGotArtifact(digest![43], 100, "/z/tmp/bar".into()),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![1, [(42, Manifest)]])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![1, [(42, Manifest)]]))),
         };
 
         ClientDisconnected(cid![1]) => {
@@ -1840,7 +2894,7 @@ mod tests {
 
         GotArtifact(digest![43], 100, "/z/tmp/bar".into()) => {
             CacheGotArtifact(digest![43], 100, "/z/tmp/bar".into()),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![1, [(42, Manifest)]])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![1, [(42, Manifest)]]))),
         };
 
         ClientDisconnected(cid![1]) => {
@@ -1883,7 +2937,7 @@ mod tests {
 
         GotArtifact(digest![43], 100, "/z/tmp/bar".into()) => {
             CacheGotArtifact(digest![43], 100, "/z/tmp/bar".into()),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![1, [(42, Manifest)]])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![1, [(42, Manifest)]]))),
         };
 
         ClientDisconnected(cid![1]) => {
@@ -1921,7 +2975,7 @@ mod tests {
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![1, [(42, Manifest)]])) => {
             CacheGetArtifact(jid![1, 2], digest![42]),
             CacheGetArtifact(jid![1, 2], digest![43]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![1, [(42, Manifest)]])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![1, [(42, Manifest)]]))),
         };
 
         ClientDisconnected(cid![1]) => {
@@ -1970,7 +3024,7 @@ mod tests {
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![1, [(42, Manifest)]])) => {
             CacheGetArtifact(jid![1, 2], digest![42]),
             CacheGetArtifact(jid![1, 2], digest![43]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 2], spec![1, [(42, Manifest)]])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![1, [(42, Manifest)]]))),
         };
 
         ClientDisconnected(cid![1]) => {
@@ -2036,7 +3090,7 @@ mod tests {
         FromClient(cid![1], ClientToBroker::StatisticsRequest) => {
             ToClient(cid![1], BrokerToClient::StatisticsResponse(BrokerStatistics {
                 worker_statistics: hashmap! {
-                    wid![1] => WorkerStatistics { slots: 2 }
+                    wid![1] => WorkerStatistics { slots: 2, pending: 0 }
                 },
                 job_statistics: [JobStatisticsSample {
                     client_to_stats: hashmap! {
@@ -2092,13 +3146,13 @@ mod tests {
         WorkerConnected(wid![1], 2, worker_sender![1]) => {};
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid![1, 1], digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
         StatisticsHeartbeat => {};
         FromClient(cid![1], ClientToBroker::StatisticsRequest) => {
             ToClient(cid![1], BrokerToClient::StatisticsResponse(BrokerStatistics {
                 worker_statistics: hashmap! {
-                    wid![1] => WorkerStatistics { slots: 2 }
+                    wid![1] => WorkerStatistics { slots: 2, pending: 1 }
                 },
                 job_statistics: [JobStatisticsSample {
                     client_to_stats: hashmap! {
@@ -2125,9 +3179,9 @@ mod tests {
         WorkerConnected(wid![1], 2, worker_sender![1]) => {};
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid![1, 1], digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
-        FromWorker(wid![1], WorkerToBroker(jid![1, 1], outcome![1])) => {
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
             ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
             CacheDecrementRefcount(digest![1]),
         };
@@ -2135,7 +3189,7 @@ mod tests {
         FromClient(cid![1], ClientToBroker::StatisticsRequest) => {
             ToClient(cid![1], BrokerToClient::StatisticsResponse(BrokerStatistics {
                 worker_statistics: hashmap! {
-                    wid![1] => WorkerStatistics { slots: 2 }
+                    wid![1] => WorkerStatistics { slots: 2, pending: 0 }
                 },
                 job_statistics: [JobStatisticsSample {
                     client_to_stats: hashmap! {
@@ -2151,6 +3205,41 @@ mod tests {
         }
     }
 
+    script_test! {
+        worker_statistics_include_pending_jobs,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![2]), vec![GetArtifact::Success]),
+                ((jid![1, 3], digest![3]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
+        WorkerConnected(wid![2], 3, worker_sender![2]) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
+            CacheGetArtifact(jid![1, 2], digest![2]),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {
+            CacheGetArtifact(jid![1, 3], digest![3]),
+            ToWorker(wid![2], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::StatisticsRequest) => {
+            ToClient(cid![1], BrokerToClient::StatisticsResponse(BrokerStatistics {
+                worker_statistics: hashmap! {
+                    wid![1] => WorkerStatistics { slots: 1, pending: 1 },
+                    wid![2] => WorkerStatistics { slots: 3, pending: 2 },
+                },
+                job_statistics: JobStatisticsTimeSeries::default(),
+            }))
+        };
+    }
+
     script_test! {
         job_state_counts,
         {
@@ -2162,7 +3251,7 @@ mod tests {
         WorkerConnected(wid![1], 2, worker_sender![1]) => {};
         FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
             CacheGetArtifact(jid![1, 1], digest![1]),
-            ToWorker(wid![1], EnqueueJob(jid![1, 1], spec![1, Tar])),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
         };
         FromClient(cid![1], ClientToBroker::JobStateCountsRequest) => {
             ToClient(cid![1], BrokerToClient::JobStateCountsResponse(
@@ -2175,4 +3264,615 @@ mod tests {
             ))
         }
     }
+
+    #[test]
+    fn worker_distribution() {
+        const NUM_JOBS: u32 = 5;
+        let mut fixture = Fixture::new(
+            [
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 3], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 4], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 5], digest![1]), vec![GetArtifact::Success]),
+                ((jid![2, 1], digest![1]), vec![GetArtifact::Success]),
+            ],
+            [],
+            [],
+            [],
+        );
+        fixture.receive_message(ClientConnected(cid![1], client_sender![1]));
+        fixture.receive_message(ClientConnected(cid![2], client_sender![2]));
+        fixture.receive_message(WorkerConnected(wid![1], 1, worker_sender![1]));
+        fixture.receive_message(WorkerConnected(wid![2], 2, worker_sender![2]));
+        for n in 1..=NUM_JOBS {
+            fixture.receive_message(FromClient(
+                cid![1],
+                ClientToBroker::JobRequest(cjid![n], spec![1, Tar]),
+            ));
+        }
+        fixture.receive_message(FromClient(
+            cid![2],
+            ClientToBroker::JobRequest(cjid![1], spec![1, Tar]),
+        ));
+
+        // Answer every job that was sent to a worker. Each answer may cause the worker to be sent
+        // another queued job, so keep going until there aren't any more.
+        loop {
+            let enqueued = Vec::from_iter(
+                fixture
+                    .test_state
+                    .borrow_mut()
+                    .messages
+                    .drain(..)
+                    .filter_map(|msg| match msg {
+                        ToWorker(wid, EnqueueJob(jid, _)) => Some((wid, jid)),
+                        _ => None,
+                    }),
+            );
+            if enqueued.is_empty() {
+                break;
+            }
+            for (wid, jid) in enqueued {
+                fixture.receive_message(FromWorker(
+                    wid,
+                    WorkerToBroker::JobResponse(jid, outcome![1]),
+                ));
+            }
+        }
+
+        fixture.receive_message(FromClient(
+            cid![1],
+            ClientToBroker::WorkerDistributionRequest,
+        ));
+        let messages = std::mem::take(&mut fixture.test_state.borrow_mut().messages);
+        let [ToClient(cid, BrokerToClient::WorkerDistributionResponse(distribution))] =
+            &messages[..]
+        else {
+            panic!("unexpected messages: {messages:#?}");
+        };
+        assert_eq!(*cid, cid![1]);
+        assert_eq!(distribution.len(), 2, "{distribution:?}");
+        assert_eq!(distribution.values().sum::<u64>(), u64::from(NUM_JOBS));
+
+        // The other client's job isn't counted for this client, and vice versa.
+        fixture.receive_message(FromClient(
+            cid![2],
+            ClientToBroker::WorkerDistributionRequest,
+        ));
+        let messages = std::mem::take(&mut fixture.test_state.borrow_mut().messages);
+        let [ToClient(_, BrokerToClient::WorkerDistributionResponse(distribution))] = &messages[..]
+        else {
+            panic!("unexpected messages: {messages:#?}");
+        };
+        assert_eq!(distribution.values().sum::<u64>(), 1);
+    }
+
+    script_test! {
+        worker_distribution_with_no_completed_jobs,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
+            CacheDecrementRefcount(digest![1]),
+        };
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {
+                wid![1] => 1,
+            }))
+        }
+    }
+
+    script_test! {
+        worker_capabilities,
+        {
+            Fixture::new([], [], [], [])
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::WorkerCapabilitiesRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerCapabilitiesResponse(hashmap! {}))
+        };
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        GotWorkerCapabilities(wid![1], WorkerCapabilities {
+            devices: enum_set! {JobDevice::Null | JobDevice::Zero},
+            overlayfs: true,
+            cgroup_controllers: vec!["cpu".into(), "memory".into()],
+            cpus: 4,
+            memory: 1 << 30,
+        }) => {};
+        WorkerConnected(wid![2], 2, worker_sender![2]) => {};
+        FromClient(cid![1], ClientToBroker::WorkerCapabilitiesRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerCapabilitiesResponse(hashmap! {
+                wid![1] => WorkerCapabilities {
+                    devices: enum_set! {JobDevice::Null | JobDevice::Zero},
+                    overlayfs: true,
+                    cgroup_controllers: vec!["cpu".into(), "memory".into()],
+                    cpus: 4,
+                    memory: 1 << 30,
+                },
+                wid![2] => WorkerCapabilities::default(),
+            }))
+        };
+        WorkerDisconnected(wid![1]) => {};
+        FromClient(cid![1], ClientToBroker::WorkerCapabilitiesRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerCapabilitiesResponse(hashmap! {
+                wid![2] => WorkerCapabilities::default(),
+            }))
+        }
+    }
+
+    script_test! {
+        artifacts,
+        {
+            let fixture = Fixture::default();
+            fixture.test_state.borrow_mut().artifacts_returns = vec![CachedArtifact {
+                digest: digest![1],
+                size: 10,
+                refcount: 2,
+            }];
+            fixture
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::ArtifactsRequest) => {
+            ToClient(cid![1], BrokerToClient::ArtifactsResponse(vec![CachedArtifact {
+                digest: digest![1],
+                size: 10,
+                refcount: 2,
+            }]))
+        }
+    }
+
+    script_test! {
+        large_job_waits_for_worker_with_enough_free_resources,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![2]), vec![GetArtifact::Success]),
+                ((jid![1, 3], digest![3]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        GotWorkerCapabilities(wid![1], WorkerCapabilities {
+            cpus: 4,
+            memory: 1000,
+            ..Default::default()
+        }) => {};
+        WorkerConnected(wid![2], 2, worker_sender![2]) => {};
+        GotWorkerCapabilities(wid![2], WorkerCapabilities {
+            cpus: 1,
+            memory: 1000,
+            ..Default::default()
+        }) => {};
+        // Worker 3 hasn't told us what it has yet.
+        WorkerConnected(wid![3], 2, worker_sender![3]) => {};
+
+        // Only worker 1 has 3 CPUs.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar].cpus(3))) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar].cpus(3)))),
+        };
+
+        // Worker 1 only has 1 CPU free, and worker 2 doesn't have enough memory. Worker 3 might,
+        // so the job waits for it.
+        FromClient(cid![1], ClientToBroker::JobRequest(
+            cjid![2],
+            spec![2, Tar].cpus(1).memory(2000),
+        )) => {
+            CacheGetArtifact(jid![1, 2], digest![2]),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(
+            cjid![3],
+            spec![3, Tar].cpus(2).memory(500),
+        )) => {
+            CacheGetArtifact(jid![1, 3], digest![3]),
+        };
+
+        // Once the first job is done, worker 1 has enough free for the third job, but nobody has
+        // enough memory for the second.
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
+            CacheDecrementRefcount(digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar].cpus(2).memory(500)))),
+        };
+
+        // Worker 3 has enough memory for the second job.
+        GotWorkerCapabilities(wid![3], WorkerCapabilities {
+            cpus: 2,
+            memory: 4000,
+            ..Default::default()
+        }) => {
+            ToWorker(wid![3], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar].cpus(1).memory(2000)))),
+        };
+    }
+
+    script_test! {
+        jobs_prefer_worker_recently_sent_their_layers,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![2]), vec![GetArtifact::Success]),
+                ((jid![1, 3], digest![2]), vec![GetArtifact::Success]),
+                ((jid![1, 4], digest![1]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        WorkerConnected(wid![2], 2, worker_sender![2]) => {};
+        ClientConnected(cid![1], client_sender![1]) => {};
+
+        // 0/2 0/2
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
+
+        // 1/2 0/2
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
+            CacheGetArtifact(jid![1, 2], digest![2]),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
+        };
+
+        // 1/2 1/2: the load is the same, so worker 2 wins because it has the layer.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![2, Tar])) => {
+            CacheGetArtifact(jid![1, 3], digest![2]),
+            ToWorker(wid![2], EnqueueJob(jid![1, 3], Box::new(spec![2, Tar]))),
+        };
+
+        // 1/2 2/2: worker 1 has the layer and a free slot.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 4], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 4], Box::new(spec![1, Tar]))),
+        };
+    }
+
+    script_test! {
+        worker_with_layers_not_preferred_when_much_more_loaded,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![2]), vec![GetArtifact::Success]),
+                ((jid![1, 3], digest![2]), vec![GetArtifact::Success]),
+                ((jid![1, 4], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 5], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 6], digest![1]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        WorkerConnected(wid![2], 2, worker_sender![2]) => {};
+        ClientConnected(cid![1], client_sender![1]) => {};
+
+        // 0/2 0/2
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
+
+        // 1/2 0/2
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
+            CacheGetArtifact(jid![1, 2], digest![2]),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
+        };
+
+        // 1/2 1/2
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![2, Tar])) => {
+            CacheGetArtifact(jid![1, 3], digest![2]),
+            ToWorker(wid![2], EnqueueJob(jid![1, 3], Box::new(spec![2, Tar]))),
+        };
+
+        // 1/2 2/2
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 4], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 4], Box::new(spec![1, Tar]))),
+        };
+
+        // 2/2 2/2: neither has a free slot and the load is the same, so worker 1 wins because it
+        // has the layer.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![5], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 5], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 5], Box::new(spec![1, Tar]))),
+        };
+
+        // 3/2 2/2: worker 1 has the layer, but it's too much more loaded than worker 2.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![6], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 6], digest![1]),
+            ToWorker(wid![2], EnqueueJob(jid![1, 6], Box::new(spec![1, Tar]))),
+        };
+    }
+
+    script_test! {
+        worker_with_free_slot_preferred_over_worker_with_layers,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 3], digest![1]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
+        WorkerConnected(wid![2], 1, worker_sender![2]) => {};
+        ClientConnected(cid![1], client_sender![1]) => {};
+
+        // 0/1 0/1
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar]))),
+        };
+
+        // 1/1 0/1: worker 1 has the layer, but worker 2 can start the job now.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 2], digest![1]),
+            ToWorker(wid![2], EnqueueJob(jid![1, 2], Box::new(spec![1, Tar]))),
+        };
+
+        // 1/1 1/1: neither has a free slot, so the existing balance decides.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 3], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 3], Box::new(spec![1, Tar]))),
+        };
+    }
+
+    script_test! {
+        jobs_without_resource_requests_pass_waiting_jobs,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![2]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar].cpus(1))) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {
+            CacheGetArtifact(jid![1, 2], digest![2]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar]))),
+        };
+        GotWorkerCapabilities(wid![1], WorkerCapabilities {
+            cpus: 1,
+            ..Default::default()
+        }) => {
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar].cpus(1)))),
+        };
+    }
+
+    script_test! {
+        job_no_worker_could_fit_rejected,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![2]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+
+        // We don't know what the worker has yet, so the job waits.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar].cpus(8))) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+        };
+        GotWorkerCapabilities(wid![1], WorkerCapabilities {
+            cpus: 4,
+            memory: 1000,
+            ..Default::default()
+        }) => {
+            CacheDecrementRefcount(digest![1]),
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], Err(JobError::Execution(
+                string!("job asks for 8 CPUs and 0 bytes of memory, but no connected worker has that many"),
+            )))),
+        };
+
+        FromClient(cid![1], ClientToBroker::JobRequest(
+            cjid![2],
+            spec![2, Tar].cpus(1).memory(2000),
+        )) => {
+            CacheGetArtifact(jid![1, 2], digest![2]),
+            CacheDecrementRefcount(digest![2]),
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], Err(JobError::Execution(
+                string!("job asks for 1 CPUs and 2000 bytes of memory, but no connected worker has that many"),
+            )))),
+        };
+    }
+
+    script_test! {
+        blocked_job_holds_worker_for_itself,
+        {
+            Fixture::new([
+                ((jid![1, 1], digest![1]), vec![GetArtifact::Success]),
+                ((jid![1, 2], digest![2]), vec![GetArtifact::Success]),
+                ((jid![1, 3], digest![3]), vec![GetArtifact::Success]),
+                ((jid![1, 4], digest![4]), vec![GetArtifact::Success]),
+            ], [], [], [])
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], 4, worker_sender![1]) => {};
+        GotWorkerCapabilities(wid![1], WorkerCapabilities {
+            cpus: 2,
+            ..Default::default()
+        }) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar].cpus(1))) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 1], Box::new(spec![1, Tar].cpus(1)))),
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar].cpus(2))) => {
+            CacheGetArtifact(jid![1, 2], digest![2]),
+        };
+
+        // There's a CPU free, but it's being held for the second job.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar].cpus(1))) => {
+            CacheGetArtifact(jid![1, 3], digest![3]),
+        };
+
+        // Jobs that don't ask for resources don't take anything from the second job.
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {
+            CacheGetArtifact(jid![1, 4], digest![4]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 4], Box::new(spec![4, Tar]))),
+        };
+
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 1], outcome![1])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![1], outcome![1])),
+            CacheDecrementRefcount(digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 2], Box::new(spec![2, Tar].cpus(2)))),
+        };
+        FromWorker(wid![1], WorkerToBroker::JobResponse(jid![1, 2], outcome![2])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(cjid![2], outcome![2])),
+            CacheDecrementRefcount(digest![2]),
+            ToWorker(wid![1], EnqueueJob(jid![1, 3], Box::new(spec![3, Tar].cpus(1)))),
+        };
+    }
+
+    impl Fixture {
+        fn with_client_request_limit(limit: u32) -> Self {
+            let mut fixture = Self::default();
+            fixture.scheduler = Scheduler::new(fixture.test_state.clone(), Some(limit));
+            fixture
+        }
+    }
+
+    script_test! {
+        client_requests_throttled_over_limit,
+        Fixture::with_client_request_limit(2),
+        ClientConnected(cid![1], client_sender![1]) => {};
+        ClientConnected(cid![2], client_sender![2]) => {};
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::TooManyRequests)
+        };
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {};
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {};
+        FromClient(cid![2], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![2], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+        FromClient(cid![2], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![2], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+        StatisticsHeartbeat => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {})),
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {})),
+        };
+        // The last held request must wait for the next interval, even though the limit hasn't
+        // been reached by any new request.
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {};
+        StatisticsHeartbeat => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {})),
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {})),
+        };
+    }
+
+    script_test! {
+        output_acks_not_throttled,
+        {
+            let fixture = Fixture::with_client_request_limit(1);
+            fixture.test_state.borrow_mut().get_artifact_returns.insert(
+                (jid![1, 1], digest![1]), vec![GetArtifact::Success]);
+            fixture
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+            ToWorker(wid![1], EnqueueJob(jid![1], Box::new(spec![1, Tar]))),
+        };
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::TooManyRequests)
+        };
+        FromClient(cid![1], ClientToBroker::JobOutputAck(cjid![1], 100)) => {
+            ToWorker(wid![1], JobOutputAck(jid![1], 100)),
+        };
+    }
+
+    script_test! {
+        client_requests_rejected_when_too_many_held,
+        {
+            let fixture = Fixture::with_client_request_limit(1);
+            let mut test_state = fixture.test_state.borrow_mut();
+            for n in [1, 3, 4] {
+                test_state.get_artifact_returns.insert(
+                    (jid![1, n], digest!(n)), vec![GetArtifact::Wait]);
+            }
+            drop(test_state);
+            fixture
+        },
+        ClientConnected(cid![1], client_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            ToClient(cid![1], BrokerToClient::TooManyRequests)
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![5], spec![5, Tar])) => {
+            ToClient(cid![1], BrokerToClient::JobResponse(
+                cjid![5],
+                Err(JobError::System(
+                    "broker is holding too many requests from this client".into()
+                )),
+            ))
+        };
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+        FromClient(cid![1], ClientToBroker::CancelJob(cjid![2])) => {};
+        StatisticsHeartbeat => {
+            CacheGetArtifact(jid![1, 1], digest![1]),
+        };
+        StatisticsHeartbeat => {
+            CacheGetArtifact(jid![1, 3], digest![3]),
+        };
+        StatisticsHeartbeat => {
+            CacheGetArtifact(jid![1, 4], digest![4]),
+        };
+    }
+
+    script_test! {
+        held_job_requests_dropped_when_all_canceled,
+        Fixture::with_client_request_limit(1),
+        ClientConnected(cid![1], client_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![1], spec![1, Tar])) => {
+            ToClient(cid![1], BrokerToClient::TooManyRequests)
+        };
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![2], spec![2, Tar])) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![3], spec![3, Tar])) => {};
+        FromClient(cid![1], ClientToBroker::JobRequest(cjid![4], spec![4, Tar])) => {};
+        FromClient(cid![1], ClientToBroker::CancelAllJobs) => {
+            CacheClientDisconnected(cid![1]),
+        };
+        StatisticsHeartbeat => {};
+    }
+
+    script_test! {
+        client_requests_not_throttled_without_limit,
+        ClientConnected(cid![1], client_sender![1]) => {};
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+        FromClient(cid![1], ClientToBroker::WorkerDistributionRequest) => {
+            ToClient(cid![1], BrokerToClient::WorkerDistributionResponse(hashmap! {}))
+        };
+    }
 }
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 use std::process::Command;
 use std::str::from_utf8;
 
-const INTO_RESULT: [&str; 7] = [
+const INTO_RESULT: [&str; 10] = [
     "AddArtifactResponse",
     "AddJobRequest",
     "AddLayerRequest",
@@ -10,16 +10,20 @@ const INTO_RESULT: [&str; 7] = [
     "GetArtifactUploadProgressResponse",
     "GetContainerImageResponse",
     "GetJobStateCountsResponse",
+    "GetWorkerCapabilitiesResponse",
+    "GetWorkerDistributionResponse",
+    "ListArtifactsResponse",
 ];
 
-const ENUM_PROTO: [(&str, &str); 4] = [
+const ENUM_PROTO: [(&str, &str); 5] = [
     ("JobDevice", "maelstrom_base::JobDevice"),
     ("JobMountFsType", "maelstrom_base::JobMountFsType"),
     ("ArtifactType", "maelstrom_base::ArtifactType"),
+    ("JobOutputStream", "maelstrom_base::JobOutputStream"),
     ("JobCompleted.status", "maelstrom_base::JobStatus"),
 ];
 
-const MSG_PROTO: [(&str, &str, &str); 7] = [
+const MSG_PROTO: [(&str, &str, &str); 10] = [
     ("JobMount", "maelstrom_base::JobMount", ""),
     ("JobSpec", "maelstrom_base::JobSpec", ""),
     ("ContainerImage", "maelstrom_container::ContainerImage", ""),
@@ -31,13 +35,26 @@ const MSG_PROTO: [(&str, &str, &str); 7] = [
     ("OciConfig", "maelstrom_container::Config", ""),
     ("OciRootFs", "maelstrom_container::RootFs", ""),
     ("JobEffects", "maelstrom_base::JobEffects", "option_all"),
+    ("JobResourceUsage", "maelstrom_base::JobResourceUsage", ""),
+    (
+        "WorkerCapabilities",
+        "maelstrom_base::WorkerCapabilities",
+        "",
+    ),
+    (
+        "CachedArtifact",
+        "maelstrom_base::stats::CachedArtifact",
+        "",
+    ),
 ];
 
-const FIELD_ATTR: [(&str, &str); 4] = [
+const FIELD_ATTR: [(&str, &str); 6] = [
     ("ContainerImage.config", "option"),
     ("OciImageConfiguration.architecture", "option"),
     ("OciImageConfiguration.os", "option"),
     ("OciImageConfiguration.rootfs", "option"),
+    ("JobResourceUsage.user_time", "option"),
+    ("JobResourceUsage.system_time", "option"),
 ];
 
 fn test_for_protoc() -> Option<PathBuf> {
@@ -13,6 +13,7 @@ extern crate self as maelstrom_client;
 use anyhow::{anyhow, Result};
 use maelstrom_macro::{IntoProtoBuf, TryFromProtoBuf};
 pub use proto_buf_conv::{IntoProtoBuf, TryFromProtoBuf};
+use std::collections::HashMap;
 
 pub const MANIFEST_DIR: &str = "manifests";
 pub const STUB_MANIFEST_DIR: &str = "manifests/stubs";
@@ -64,6 +65,14 @@ impl<V> IntoResult for Vec<V> {
     }
 }
 
+impl<K, V> IntoResult for HashMap<K, V> {
+    type Output = HashMap<K, V>;
+
+    fn into_result(self) -> Result<Self::Output> {
+        Ok(self)
+    }
+}
+
 #[derive(IntoProtoBuf, TryFromProtoBuf)]
 #[proto(other_type = "proto::ArtifactUploadProgress")]
 pub struct ArtifactUploadProgress {
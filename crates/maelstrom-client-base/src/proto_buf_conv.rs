@@ -68,6 +68,38 @@ impl TryFromProtoBuf for u8 {
     }
 }
 
+impl IntoProtoBuf for i8 {
+    type ProtoBufType = i32;
+
+    fn into_proto_buf(self) -> i32 {
+        self.into()
+    }
+}
+
+impl TryFromProtoBuf for i8 {
+    type ProtoBufType = i32;
+
+    fn try_from_proto_buf(v: i32) -> Result<Self> {
+        Ok(v.try_into()?)
+    }
+}
+
+impl IntoProtoBuf for u32 {
+    type ProtoBufType = u32;
+
+    fn into_proto_buf(self) -> u32 {
+        self
+    }
+}
+
+impl TryFromProtoBuf for u32 {
+    type ProtoBufType = u32;
+
+    fn try_from_proto_buf(v: u32) -> Result<Self> {
+        Ok(v)
+    }
+}
+
 impl IntoProtoBuf for u64 {
     type ProtoBufType = u64;
 
@@ -386,6 +418,22 @@ impl TryFromProtoBuf for maelstrom_base::ClientJobId {
     }
 }
 
+impl IntoProtoBuf for maelstrom_base::WorkerId {
+    type ProtoBufType = u32;
+
+    fn into_proto_buf(self) -> u32 {
+        self.as_u32()
+    }
+}
+
+impl TryFromProtoBuf for maelstrom_base::WorkerId {
+    type ProtoBufType = u32;
+
+    fn try_from_proto_buf(v: u32) -> Result<Self> {
+        Ok(Self::from(v))
+    }
+}
+
 //      _       _
 //     | | ___ | |__ __/\__
 //  _  | |/ _ \| '_ \\    /
@@ -625,6 +673,23 @@ impl TryFromProtoBuf for maelstrom_util::config::common::Slots {
     }
 }
 
+impl IntoProtoBuf for maelstrom_util::config::common::HashingThreads {
+    type ProtoBufType = u32;
+
+    fn into_proto_buf(self) -> u32 {
+        self.into_inner().into()
+    }
+}
+
+impl TryFromProtoBuf for maelstrom_util::config::common::HashingThreads {
+    type ProtoBufType = u32;
+
+    fn try_from_proto_buf(v: u32) -> Result<Self> {
+        Self::try_from(u16::try_from(v)?)
+            .map_err(|s| anyhow!("error deserializing hashing threads: {s}"))
+    }
+}
+
 //                       _     _
 //  _ __ ___   __ _  ___| |___| |_ _ __ ___  _ __ ___
 // | '_ ` _ \ / _` |/ _ \ / __| __| '__/ _ \| '_ ` _ \ _____
@@ -63,6 +63,14 @@ pub struct PrefixOptions {
     pub canonicalize: bool,
     #[serde(default)]
     pub follow_symlinks: bool,
+    /// Skip files ignored by `.gitignore` files in the project directory and its subdirectories.
+    /// This only affects [`Layer::Glob`].
+    #[serde(default)]
+    pub respect_gitignore: bool,
+    /// Keep the modification times of the files put in the layer. Otherwise, they are all given
+    /// the same fixed time, so that the layer's digest only depends on the files' contents.
+    #[serde(default)]
+    pub preserve_mtimes: bool,
 }
 
 #[derive(
@@ -78,6 +86,7 @@ pub struct PrefixOptions {
     Serialize,
 )]
 #[proto(other_type = "proto::SymlinkSpec")]
+#[serde(deny_unknown_fields)]
 pub struct SymlinkSpec {
     pub link: Utf8PathBuf,
     pub target: Utf8PathBuf,
@@ -112,6 +121,16 @@ pub enum Layer {
     Stubs { stubs: Vec<String> },
     #[proto(other_type = proto::SymlinksLayer)]
     Symlinks { symlinks: Vec<SymlinkSpec> },
+    /// Run `program` with `args` on the host in the project directory, then use the contents of the
+    /// `output` directory it leaves behind as the layer. The program is not sandboxed.
+    #[proto(other_type = proto::CommandLayer)]
+    Command {
+        #[serde(rename = "command")]
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+        output: Utf8PathBuf,
+    },
 }
 
 impl Layer {
@@ -135,6 +154,17 @@ impl Layer {
                     *target = replace_template_vars(target.as_str(), vars)?.into();
                 }
             }
+            Self::Command {
+                program,
+                args,
+                output,
+            } => {
+                *program = replace_template_vars(program, vars)?;
+                for arg in args {
+                    *arg = replace_template_vars(arg, vars)?;
+                }
+                *output = replace_template_vars(output.as_str(), vars)?.into();
+            }
         }
         Ok(())
     }
@@ -157,15 +187,39 @@ pub enum ImageUse {
 /// A struct used for deserializing "image" statements in JSON, TOML, or other similar formats.
 /// This allows the user to specify an image name and the parts of the image they want to use.
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Image {
     pub name: String,
     #[serde(rename = "use")]
     pub use_: EnumSet<ImageUse>,
 }
 
+/// Split an image reference from a job specification into the name and tag to pass to
+/// `get_container_image`. If no tag is given, `latest` is used. An image pinned by digest, like
+/// `name@sha256:<hex>`, is passed with the digest in place of the tag. References to local OCI
+/// image layouts, which start with `oci:`, are passed through as the name, since the path may
+/// itself contain colons.
+pub fn split_image_reference(image: &str) -> (&str, &str) {
+    if image.starts_with(maelstrom_container::OCI_LAYOUT_PREFIX) {
+        (image, "latest")
+    } else if let Some((name, digest)) = image.split_once('@') {
+        // The digest takes precedence over any tag that is also given.
+        let name = split_image_tag(name).map_or(name, |(name, _)| name);
+        (name, digest)
+    } else {
+        split_image_tag(image).unwrap_or((image, "latest"))
+    }
+}
+
+/// Split the tag off of an image name, if it has one. A registry's host may be followed by a port,
+/// like `localhost:5000/rust`, so a colon only starts a tag if there's no `/` after it.
+fn split_image_tag(name: &str) -> Option<(&str, &str)> {
+    name.rsplit_once(':').filter(|(_, tag)| !tag.contains('/'))
+}
+
 /// A simple wrapper struct for the config of a local OCI image. This is used for dependency
 /// injection for the other functions in this module.
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct ImageConfig {
     /// Local `PathBuf`s pointing to the various layer artifacts.
     pub layers: Vec<PathBuf>,
@@ -282,6 +336,40 @@ mod test {
     use maelstrom_test::{path_buf_vec, string, string_vec, tar_layer};
     use std::{ffi::OsStr, os::unix::ffi::OsStrExt as _};
 
+    fn command_layer() -> Layer {
+        Layer::Command {
+            program: string!("<build_dir>/npm"),
+            args: string_vec!["install", "--prefix", "<build_dir>/assets"],
+            output: "<build_dir>/assets/node_modules".into(),
+        }
+    }
+
+    #[test]
+    fn command_layer_proto_round_trip() {
+        let layer = command_layer();
+        assert_eq!(
+            Layer::try_from_proto_buf(layer.clone().into_proto_buf()).unwrap(),
+            layer
+        );
+    }
+
+    #[test]
+    fn command_layer_replace_template_vars() {
+        let mut layer = command_layer();
+        let vars = TemplateVars::new()
+            .with_var("build_dir", "/tmp/build")
+            .unwrap();
+        layer.replace_template_vars(&vars).unwrap();
+        assert_eq!(
+            layer,
+            Layer::Command {
+                program: string!("/tmp/build/npm"),
+                args: string_vec!["install", "--prefix", "/tmp/build/assets"],
+                output: "/tmp/build/assets/node_modules".into(),
+            }
+        );
+    }
+
     #[test]
     fn std_env_lookup_good() {
         let var = "AN_ENVIRONMENT_VARIABLE_1";
@@ -290,6 +378,60 @@ mod test {
         assert_eq!(std_env_lookup(var).unwrap(), Some(val.to_string()));
     }
 
+    #[test]
+    fn split_image_reference_with_tag() {
+        assert_eq!(split_image_reference("rust:1.77"), ("rust", "1.77"));
+    }
+
+    #[test]
+    fn split_image_reference_without_tag() {
+        assert_eq!(split_image_reference("rust"), ("rust", "latest"));
+    }
+
+    #[test]
+    fn split_image_reference_digest() {
+        assert_eq!(
+            split_image_reference("rust@sha256:abcdef"),
+            ("rust", "sha256:abcdef")
+        );
+    }
+
+    #[test]
+    fn split_image_reference_tag_and_digest() {
+        assert_eq!(
+            split_image_reference("rust:1.77@sha256:abcdef"),
+            ("rust", "sha256:abcdef")
+        );
+    }
+
+    #[test]
+    fn split_image_reference_registry_with_port() {
+        assert_eq!(
+            split_image_reference("localhost:5000/rust"),
+            ("localhost:5000/rust", "latest")
+        );
+        assert_eq!(
+            split_image_reference("localhost:5000/rust:1.77"),
+            ("localhost:5000/rust", "1.77")
+        );
+        assert_eq!(
+            split_image_reference("localhost:5000/rust@sha256:abcdef"),
+            ("localhost:5000/rust", "sha256:abcdef")
+        );
+        assert_eq!(
+            split_image_reference("localhost:5000/rust:1.77@sha256:abcdef"),
+            ("localhost:5000/rust", "sha256:abcdef")
+        );
+    }
+
+    #[test]
+    fn split_image_reference_oci_layout() {
+        assert_eq!(
+            split_image_reference("oci:./images/rust"),
+            ("oci:./images/rust", "latest")
+        );
+    }
+
     #[test]
     fn std_env_lookup_missing() {
         let var = "AN_ENVIRONMENT_VARIABLE_TO_DELETE";
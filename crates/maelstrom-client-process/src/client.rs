@@ -1,9 +1,12 @@
 mod layer_builder;
+mod layer_order;
+mod local_worker;
 mod state_machine;
 
 use crate::{
     artifact_pusher::{self, ArtifactUploadTracker},
     digest_repo::DigestRepository,
+    hasher::ArtifactHasher,
     router,
 };
 use anyhow::{Context as _, Result};
@@ -11,8 +14,9 @@ use async_trait::async_trait;
 use layer_builder::LayerBuilder;
 use maelstrom_base::{
     proto::{Hello, WorkerToBroker},
-    stats::JobStateCounts,
-    ArtifactType, ClientJobId, JobOutcomeResult, JobSpec, Sha256Digest,
+    stats::{CachedArtifact, JobStateCounts, WorkerDistribution},
+    ArtifactType, ClientJobId, JobOutcomeResult, JobSpec, Sha256Digest, Utf8PathBuf,
+    WorkerCapabilitiesMap,
 };
 use maelstrom_client_base::{
     spec::Layer, ArtifactUploadProgress, STUB_MANIFEST_DIR, SYMLINK_MANIFEST_DIR,
@@ -20,17 +24,21 @@ use maelstrom_client_base::{
 use maelstrom_container::{ContainerImage, ContainerImageDepot, NullProgressTracker};
 use maelstrom_util::{
     async_fs,
-    config::common::{BrokerAddr, CacheRoot, CacheSize, InlineLimit, LogLevel, Slots},
+    config::common::{
+        BrokerAddr, CacheRoot, CacheSize, HashingThreads, InlineLimit, LogLevel, Slots,
+    },
     ext::BoolExt,
     log::LoggerFactory,
-    net, sync,
+    net,
 };
 use slog::{debug, Logger};
 use state_machine::StateMachine;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::Infallible,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 use tokio::{
     net::TcpStream,
@@ -45,8 +53,10 @@ pub struct Client {
 struct ClientState {
     local_broker_sender: router::Sender,
     layer_builder: LayerBuilder,
+    hasher: ArtifactHasher,
     upload_tracker: ArtifactUploadTracker,
     container_image_depot: ContainerImageDepot,
+    canonicalize_layer_order: bool,
     log: Logger,
     locked: Mutex<ClientStateLocked>,
 }
@@ -55,6 +65,8 @@ struct ClientStateLocked {
     digest_repo: DigestRepository,
     processed_artifact_paths: HashSet<PathBuf>,
     cached_layers: HashMap<Layer, (Sha256Digest, ArtifactType)>,
+    /// The paths in each of the layers we've built, for the layers where they are known.
+    layer_paths: HashMap<Sha256Digest, BTreeSet<Utf8PathBuf>>,
 }
 
 #[async_trait]
@@ -71,23 +83,50 @@ impl ClientState {
         let fs = async_fs::Fs::new();
         let path = fs.canonicalize(path).await?;
 
-        let mut locked = self.locked.lock().await;
-        let digest = if let Some(digest) = locked.digest_repo.get(&path).await? {
-            digest
-        } else {
-            let (mtime, digest) = crate::calculate_digest(&path).await?;
-            locked
-                .digest_repo
-                .add(path.clone(), mtime, digest.clone())
-                .await?;
-            digest
+        // Don't hold the lock while hashing, so that other artifacts can be hashed at the same
+        // time.
+        let cached = self.locked.lock().await.digest_repo.get(&path).await?;
+        let digest = match cached {
+            Some(digest) => digest,
+            None => {
+                let (mtime, digest) = self.hasher.hash(path.clone()).await?;
+                self.locked
+                    .lock()
+                    .await
+                    .digest_repo
+                    .add(path.clone(), mtime, digest.clone())
+                    .await?;
+                digest
+            }
         };
+        let mut locked = self.locked.lock().await;
+        self.send_artifact(&mut locked, path, &digest)?;
+        Ok(digest)
+    }
+
+    /// Add an artifact whose digest we already know, like a manifest that was hashed as it was
+    /// built.
+    async fn add_artifact_with_digest(&self, path: &Path, digest: &Sha256Digest) -> Result<()> {
+        debug!(self.log, "add_artifact_with_digest"; "path" => ?path, "digest" => %digest);
+
+        let fs = async_fs::Fs::new();
+        let path = fs.canonicalize(path).await?;
+        let mut locked = self.locked.lock().await;
+        self.send_artifact(&mut locked, path, digest)
+    }
+
+    fn send_artifact(
+        &self,
+        locked: &mut ClientStateLocked,
+        path: PathBuf,
+        digest: &Sha256Digest,
+    ) -> Result<()> {
         if !locked.processed_artifact_paths.contains(&path) {
             locked.processed_artifact_paths.insert(path.clone());
             self.local_broker_sender
                 .send(router::Message::AddArtifact(path, digest.clone()))?;
         }
-        Ok(digest)
+        Ok(())
     }
 
     async fn add_layer(&self, layer: Layer) -> Result<(Sha256Digest, ArtifactType)> {
@@ -97,16 +136,21 @@ impl ClientState {
             return Ok(l.clone());
         }
 
-        let (artifact_path, artifact_type) =
-            self.layer_builder.build_layer(layer.clone(), self).await?;
-        let artifact_digest = self.add_artifact(&artifact_path).await?;
-        let res = (artifact_digest, artifact_type);
+        let built = self.layer_builder.build_layer(layer.clone(), self).await?;
+        let artifact_digest = match built.digest {
+            Some(digest) => {
+                self.add_artifact_with_digest(&built.path, &digest).await?;
+                digest
+            }
+            None => self.add_artifact(&built.path).await?,
+        };
+        let res = (artifact_digest, built.artifact_type);
 
-        self.locked
-            .lock()
-            .await
-            .cached_layers
-            .insert(layer, res.clone());
+        let mut locked = self.locked.lock().await;
+        if let Some(paths) = built.paths {
+            locked.layer_paths.insert(res.0.clone(), paths);
+        }
+        locked.cached_layers.insert(layer, res.clone());
         Ok(res)
     }
 }
@@ -128,6 +172,9 @@ impl Client {
         cache_size: CacheSize,
         inline_limit: InlineLimit,
         slots: Slots,
+        hashing_threads: HashingThreads,
+        local_worker_idle_timeout: Option<Duration>,
+        canonicalize_layer_order: bool,
     ) -> Result<()> {
         async fn file_logger(
             log_level: LogLevel,
@@ -152,6 +199,9 @@ impl Client {
             cache_size: CacheSize,
             inline_limit: InlineLimit,
             slots: Slots,
+            hashing_threads: HashingThreads,
+            local_worker_idle_timeout: Option<Duration>,
+            canonicalize_layer_order: bool,
         ) -> Result<(ClientState, JoinSet<Result<()>>)> {
             let fs = async_fs::Fs::new();
 
@@ -175,6 +225,9 @@ impl Client {
                 "cache_size" => ?cache_size,
                 "inline_limit" => ?inline_limit,
                 "slots" => ?slots,
+                "hashing_threads" => ?hashing_threads,
+                "local_worker_idle_timeout" => ?local_worker_idle_timeout,
+                "canonicalize_layer_order" => canonicalize_layer_order,
             );
 
             // Ensure all of the appropriate subdirectories have been created in the cache
@@ -269,6 +322,7 @@ impl Client {
                 &mut join_set,
                 standalone,
                 slots,
+                maelstrom_worker::capabilities::detect_capabilities(Path::new("/")),
                 local_broker_receiver,
                 broker_sender,
                 artifact_pusher_sender,
@@ -283,25 +337,6 @@ impl Client {
                 let cache_root = cache_root.join("artifacts");
                 let blob_cache_dir = cache_root.join("blob/sha256");
 
-                // Create the local_worker's cache. This is the same cache as the "real" worker
-                // uses.
-                let local_worker_cache = maelstrom_worker::cache::Cache::new(
-                    maelstrom_worker::cache::StdFs,
-                    CacheRoot::from(cache_root),
-                    cache_size,
-                    log.clone(),
-                );
-
-                // Create the local_worker's deps. This the same adapter as the "real" worker uses.
-                let local_worker_dispatcher_adapter = maelstrom_worker::DispatcherAdapter::new(
-                    local_worker_sender,
-                    inline_limit,
-                    log.clone(),
-                    mount_dir,
-                    tmpfs_dir,
-                    blob_cache_dir,
-                )?;
-
                 // Create an ArtifactFetcher for the local_worker that just forwards requests to
                 // the router.
                 struct ArtifactFetcher(router::Sender);
@@ -312,7 +347,6 @@ impl Client {
                             .ok();
                     }
                 }
-                let local_worker_artifact_fetcher = ArtifactFetcher(local_broker_sender.clone());
 
                 // Create a BrokerSender for the local_worker that just forwards messages to
                 // the router.
@@ -322,34 +356,70 @@ impl Client {
                         self.0.send(router::Message::LocalWorker(msg)).ok();
                     }
                 }
-                let worker_broker_sender = BrokerSender(local_broker_sender.clone());
-
-                // Create the actual local_worker.
-                let mut worker_dispatcher = maelstrom_worker::dispatcher::Dispatcher::new(
-                    local_worker_dispatcher_adapter,
-                    local_worker_artifact_fetcher,
-                    worker_broker_sender,
-                    local_worker_cache,
-                    slots,
-                );
+
+                // Create the actual local_worker. If it is shut down after being idle, this is
+                // also used to start a new one.
+                let local_broker_sender = local_broker_sender.clone();
+                let log_clone = log.clone();
+                let new_worker_dispatcher = move || -> Result<_> {
+                    // Create the local_worker's cache. This is the same cache as the "real" worker
+                    // uses.
+                    let local_worker_cache = maelstrom_worker::cache::Cache::new(
+                        maelstrom_worker::cache::StdFs,
+                        CacheRoot::from(cache_root.clone()),
+                        cache_size,
+                        log_clone.clone(),
+                    );
+
+                    // Create the local_worker's deps. This the same adapter as the "real" worker
+                    // uses.
+                    let local_worker_dispatcher_adapter = maelstrom_worker::DispatcherAdapter::new(
+                        local_worker_sender.clone(),
+                        inline_limit,
+                        log_clone.clone(),
+                        mount_dir.clone(),
+                        tmpfs_dir.clone(),
+                        blob_cache_dir.clone(),
+                        None, // environment_allowlist
+                        None, // cgroup_parent
+                        None, // job_memory_limit
+                        None, // output_buffer_limit
+                    )?;
+
+                    Ok(maelstrom_worker::dispatcher::Dispatcher::new(
+                        local_worker_dispatcher_adapter,
+                        ArtifactFetcher(local_broker_sender.clone()),
+                        BrokerSender(local_broker_sender.clone()),
+                        local_worker_cache,
+                        slots,
+                    ))
+                };
+                let worker_dispatcher = new_worker_dispatcher()?;
 
                 // Spawn a task for the local_worker.
-                join_set.spawn(sync::channel_reader(local_worker_receiver, move |msg| {
-                    worker_dispatcher.receive_message(msg)
-                }));
+                join_set.spawn(local_worker::run(
+                    local_worker_receiver,
+                    worker_dispatcher,
+                    new_worker_dispatcher,
+                    local_worker_idle_timeout,
+                    log.clone(),
+                ));
             }
 
             Ok((
                 ClientState {
                     local_broker_sender,
                     layer_builder: LayerBuilder::new(cache_dir, project_dir),
+                    hasher: ArtifactHasher::new(hashing_threads),
                     upload_tracker,
                     container_image_depot,
+                    canonicalize_layer_order,
                     log,
                     locked: Mutex::new(ClientStateLocked {
                         digest_repo,
                         processed_artifact_paths: HashSet::default(),
                         cached_layers: HashMap::new(),
+                        layer_paths: HashMap::new(),
                     }),
                 },
                 join_set,
@@ -367,6 +437,9 @@ impl Client {
             cache_size,
             inline_limit,
             slots,
+            hashing_threads,
+            local_worker_idle_timeout,
+            canonicalize_layer_order,
         )
         .await;
         match result {
@@ -412,14 +485,58 @@ impl Client {
             .await
     }
 
-    pub async fn run_job(&self, spec: JobSpec) -> Result<(ClientJobId, JobOutcomeResult)> {
+    /// Run a job and wait for its result. If the job is canceled before it completes, `None` is
+    /// returned.
+    pub async fn run_job(
+        &self,
+        mut spec: JobSpec,
+        running_handler: Option<router::JobRunningHandler>,
+        output_handler: Option<router::JobOutputHandler>,
+    ) -> Result<Option<(ClientJobId, JobOutcomeResult)>> {
         let (state, watcher) = self.state_machine.active_with_watcher()?;
         let (sender, receiver) = tokio::sync::oneshot::channel();
+        if state.canonicalize_layer_order {
+            let locked = state.locked.lock().await;
+            spec.layers =
+                layer_order::canonicalize(spec.layers, |digest| locked.layer_paths.get(digest));
+        }
         debug!(state.log, "run_job"; "spec" => ?spec);
+        state.local_broker_sender.send(router::Message::RunJob(
+            spec,
+            router::JobHandle::new(sender, running_handler, output_handler),
+        ))?;
+        // The router drops the sender if the job is canceled.
+        Ok(watcher
+            .wait(async { Ok::<_, Infallible>(receiver.await) })
+            .await?
+            .ok())
+    }
+
+    pub async fn cancel_all_jobs(&self) -> Result<()> {
+        let state = self.state_machine.active()?;
+        debug!(state.log, "cancel_all_jobs");
         state
             .local_broker_sender
-            .send(router::Message::RunJob(spec, sender))?;
-        watcher.wait(receiver).await
+            .send(router::Message::CancelAllJobs)?;
+        Ok(())
+    }
+
+    pub async fn cancel_job(&self, cjid: ClientJobId) -> Result<()> {
+        let state = self.state_machine.active()?;
+        debug!(state.log, "cancel_job"; "cjid" => ?cjid);
+        state
+            .local_broker_sender
+            .send(router::Message::CancelJob(cjid))?;
+        Ok(())
+    }
+
+    /// Let the job send `bytes` more of its streamed output, because that many have been consumed.
+    pub fn ack_job_output(&self, cjid: ClientJobId, bytes: u32) -> Result<()> {
+        let state = self.state_machine.active()?;
+        state
+            .local_broker_sender
+            .send(router::Message::AckJobOutput(cjid, bytes))?;
+        Ok(())
     }
 
     pub async fn wait_for_outstanding_jobs(&self) -> Result<()> {
@@ -441,6 +558,33 @@ impl Client {
         watcher.wait(receiver).await
     }
 
+    pub async fn get_worker_distribution(&self) -> Result<WorkerDistribution> {
+        let (state, watcher) = self.state_machine.active_with_watcher()?;
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        state
+            .local_broker_sender
+            .send(router::Message::GetWorkerDistribution(sender))?;
+        watcher.wait(receiver).await
+    }
+
+    pub async fn get_worker_capabilities(&self) -> Result<WorkerCapabilitiesMap> {
+        let (state, watcher) = self.state_machine.active_with_watcher()?;
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        state
+            .local_broker_sender
+            .send(router::Message::GetWorkerCapabilities(sender))?;
+        watcher.wait(receiver).await
+    }
+
+    pub async fn list_artifacts(&self) -> Result<Vec<CachedArtifact>> {
+        let (state, watcher) = self.state_machine.active_with_watcher()?;
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        state
+            .local_broker_sender
+            .send(router::Message::GetArtifacts(sender))?;
+        watcher.wait(receiver).await
+    }
+
     pub async fn get_artifact_upload_progress(&self) -> Result<Vec<ArtifactUploadProgress>> {
         Ok(self
             .state_machine
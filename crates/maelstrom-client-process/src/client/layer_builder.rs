@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context as _, Result};
 use futures::StreamExt as _;
 use itertools::Itertools as _;
 use maelstrom_base::{
@@ -11,18 +11,32 @@ use maelstrom_client_base::{
 };
 use maelstrom_util::{
     async_fs,
+    io::Sha256Stream,
     manifest::{AsyncManifestWriter, DataUpload, ManifestBuilder},
 };
 use sha2::{Digest as _, Sha256};
+use std::collections::BTreeSet;
 use std::fmt;
 use std::path::{Path, PathBuf};
 use std::pin::pin;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _, BufWriter};
+use tokio::process::Command;
 
 /// Having some deterministic time-stamp for files we create in manifests is useful for testing and
-/// potentially caching.
+/// caching. Unless asked to preserve them, files from the project are given this time-stamp too, so
+/// that a layer's digest doesn't change just because its files were touched.
 /// I picked this time arbitrarily 2024-1-11 11:11:11
 const ARBITRARY_TIME: UnixTimestamp = UnixTimestamp(1705000271);
 
+/// How long a [`Layer::Command`] program may run before it is killed.
+const HOST_COMMAND_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How much of the end of a [`Layer::Command`] program's standard error is kept for error messages.
+const HOST_COMMAND_STDERR_TAIL: usize = 4096;
+
 #[derive(Default)]
 struct PathHasher {
     hasher: Sha256,
@@ -78,6 +92,51 @@ fn expand_braces(expr: &str) -> Result<Vec<String>> {
     }
 }
 
+/// A manifest being written to a temporary file. Writes are buffered, and the contents are hashed
+/// as they are written, so the finished manifest never has to be read back to compute its digest.
+struct ManifestFile {
+    tmp_path: PathBuf,
+    writer: Sha256Stream<BufWriter<async_fs::File>>,
+}
+
+impl ManifestFile {
+    async fn new(dir: &Path) -> Result<Self> {
+        // Each manifest gets its own temporary file so that layers can be built concurrently.
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let fs = async_fs::Fs::new();
+        let tmp_path = dir.join(format!(
+            ".temp.{}.{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs.create_file(&tmp_path).await?;
+        Ok(Self {
+            tmp_path,
+            writer: Sha256Stream::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Flush the manifest and move it to `path`, returning its digest.
+    async fn finish(mut self, path: &Path) -> Result<Sha256Digest> {
+        self.writer.flush().await?;
+        let (_, digest) = self.writer.finalize();
+        async_fs::Fs::new().rename(&self.tmp_path, path).await?;
+        Ok(digest)
+    }
+}
+
+/// The result of building a layer.
+#[derive(Debug)]
+pub struct BuiltLayer {
+    pub path: PathBuf,
+    pub artifact_type: ArtifactType,
+    /// The digest of the artifact, if it was computed while building it.
+    pub digest: Option<Sha256Digest>,
+    /// The paths of the entries in the layer, if they are known. They aren't for tar layers,
+    /// which aren't read by the client.
+    pub paths: Option<BTreeSet<Utf8PathBuf>>,
+}
+
 pub struct LayerBuilder {
     cache_dir: PathBuf,
     project_dir: PathBuf,
@@ -114,14 +173,20 @@ impl LayerBuilder {
         mut paths: impl futures::stream::Stream<Item = Result<impl AsRef<Path>>>,
         prefix_options: PrefixOptions,
         data_upload: impl DataUpload,
-    ) -> Result<PathBuf> {
-        let fs = async_fs::Fs::new();
+    ) -> Result<(PathBuf, Sha256Digest, BTreeSet<Utf8PathBuf>)> {
         let project_dir = self.project_dir.clone();
-        let tmp_file_path = self.build_manifest_path(&".temp");
-        let manifest_file = fs.create_file(&tmp_file_path).await?;
+        let mut manifest_file = ManifestFile::new(&self.cache_dir.join(MANIFEST_DIR)).await?;
         let follow_symlinks = prefix_options.follow_symlinks;
-        let mut builder = ManifestBuilder::new(manifest_file, follow_symlinks, data_upload).await?;
+        let mtime = (!prefix_options.preserve_mtimes).then_some(ARBITRARY_TIME);
+        let mut builder = ManifestBuilder::new(
+            &mut manifest_file.writer,
+            follow_symlinks,
+            mtime,
+            data_upload,
+        )
+        .await?;
         let mut path_hasher = PathHasher::new();
+        let mut entry_paths = BTreeSet::new();
         let mut pinned_paths = pin!(paths);
         while let Some(maybe_path) = pinned_paths.next().await {
             let mut path = maybe_path?.as_ref().to_owned();
@@ -138,23 +203,56 @@ impl LayerBuilder {
                 utf8_path
             };
             let dest = calculate_manifest_entry_path(entry_path, &project_dir, &prefix_options)?;
+            entry_paths.insert(dest.clone());
             builder.add_file(utf8_path, dest).await?;
         }
         drop(builder);
 
         let manifest_path = self.build_manifest_path(&path_hasher.finish());
-        fs.rename(tmp_file_path, &manifest_path).await?;
-        Ok(manifest_path)
+        let digest = manifest_file.finish(&manifest_path).await?;
+        Ok((manifest_path, digest, entry_paths))
     }
 
-    async fn build_stub_manifest(&self, stubs: Vec<String>) -> Result<PathBuf> {
-        let fs = async_fs::Fs::new();
-        let tmp_file_path = self.build_manifest_path(&".temp");
-        let mut writer = AsyncManifestWriter::new(fs.create_file(&tmp_file_path).await?).await?;
+    /// Find the paths in the project directory that match `glob`, leaving out those ignored by
+    /// `.gitignore` files. Only `.gitignore` files in the project directory and its subdirectories
+    /// are used, whether or not the project directory is in a git repository.
+    async fn gitignore_glob_walk(&self, glob: globset::GlobSet) -> Result<Vec<PathBuf>> {
+        let project_dir = self.project_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut paths = vec![];
+            let walker = ignore::WalkBuilder::new(&project_dir)
+                .standard_filters(false)
+                .git_ignore(true)
+                .require_git(false)
+                .sort_by_file_name(|a, b| a.cmp(b))
+                .build();
+            for entry in walker {
+                let entry = entry?;
+                if entry.depth() == 0 {
+                    continue;
+                }
+                let path = entry.path().strip_prefix(&project_dir).unwrap();
+                if glob.is_match(path) {
+                    paths.push(path.to_owned());
+                }
+            }
+            Ok(paths)
+        })
+        .await?
+    }
+
+    async fn build_stub_manifest(
+        &self,
+        stubs: Vec<String>,
+    ) -> Result<(PathBuf, Sha256Digest, BTreeSet<Utf8PathBuf>)> {
+        let mut manifest_file = ManifestFile::new(&self.cache_dir.join(STUB_MANIFEST_DIR)).await?;
+        let mut writer = AsyncManifestWriter::new(&mut manifest_file.writer).await?;
         let mut path_hasher = PathHasher::new();
+        let mut entry_paths = BTreeSet::new();
         for maybe_stub in stubs.iter().map(|s| expand_braces(s)).flatten_ok() {
             let stub = Utf8PathBuf::from(maybe_stub?);
             path_hasher.hash_path(&stub);
+            entry_paths.insert(stub.clone());
             let is_dir = stub.as_str().ends_with('/');
             let data = if is_dir {
                 ManifestEntryData::Directory { opaque: false }
@@ -175,18 +273,23 @@ impl LayerBuilder {
         }
 
         let manifest_path = self.build_stub_manifest_path(&path_hasher.finish());
-        fs.rename(tmp_file_path, &manifest_path).await?;
-        Ok(manifest_path)
+        let digest = manifest_file.finish(&manifest_path).await?;
+        Ok((manifest_path, digest, entry_paths))
     }
 
-    async fn build_symlink_manifest(&self, symlinks: Vec<SymlinkSpec>) -> Result<PathBuf> {
-        let fs = async_fs::Fs::new();
-        let tmp_file_path = self.build_manifest_path(&".temp");
-        let mut writer = AsyncManifestWriter::new(fs.create_file(&tmp_file_path).await?).await?;
+    async fn build_symlink_manifest(
+        &self,
+        symlinks: Vec<SymlinkSpec>,
+    ) -> Result<(PathBuf, Sha256Digest, BTreeSet<Utf8PathBuf>)> {
+        let mut manifest_file =
+            ManifestFile::new(&self.cache_dir.join(SYMLINK_MANIFEST_DIR)).await?;
+        let mut writer = AsyncManifestWriter::new(&mut manifest_file.writer).await?;
         let mut path_hasher = PathHasher::new();
+        let mut entry_paths = BTreeSet::new();
         for SymlinkSpec { link, target } in symlinks {
             path_hasher.hash_path(&link);
             path_hasher.hash_path(&target);
+            entry_paths.insert(link.clone());
             let data = ManifestEntryData::Symlink(target.into_string().into_bytes());
             let metadata = ManifestEntryMetadata {
                 size: 0,
@@ -202,29 +305,109 @@ impl LayerBuilder {
         }
 
         let manifest_path = self.build_symlink_manifest_path(&path_hasher.finish());
-        fs.rename(tmp_file_path, &manifest_path).await?;
-        Ok(manifest_path)
+        let digest = manifest_file.finish(&manifest_path).await?;
+        Ok((manifest_path, digest, entry_paths))
+    }
+
+    /// Run `program` on the host in the project directory and build a manifest from everything in
+    /// `output` once it has finished. Like with [`Layer::Glob`], paths in `output` are relative to
+    /// the project directory.
+    ///
+    /// The program is not sandboxed: it runs as the client's user, with the client's view of the
+    /// file system and network. Its standard output is discarded, and only the last
+    /// [`HOST_COMMAND_STDERR_TAIL`] bytes of its standard error are kept to report failures. If it
+    /// hasn't finished after `timeout`, it is killed and building the layer fails.
+    async fn build_host_command_manifest(
+        &self,
+        program: String,
+        args: Vec<String>,
+        output: Utf8PathBuf,
+        timeout: Duration,
+        data_upload: impl DataUpload,
+    ) -> Result<(PathBuf, Sha256Digest, BTreeSet<Utf8PathBuf>)> {
+        let project_dir = self.project_dir.clone();
+        let mut child = Command::new(&program)
+            .args(args)
+            .current_dir(&project_dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("running layer command `{program}`"))?;
+        let mut stderr = child.stderr.take().unwrap();
+        let run = async {
+            let mut tail = Vec::new();
+            let mut chunk = [0; 4096];
+            loop {
+                let n = stderr.read(&mut chunk).await?;
+                if n == 0 {
+                    break;
+                }
+                tail.extend_from_slice(&chunk[..n]);
+                let excess = tail.len().saturating_sub(HOST_COMMAND_STDERR_TAIL);
+                tail.drain(..excess);
+            }
+            anyhow::Ok((child.wait().await?, tail))
+        };
+        let (status, stderr_tail) = tokio::time::timeout(timeout, run)
+            .await
+            .map_err(|_| anyhow!("layer command `{program}` timed out after {timeout:?}"))?
+            .with_context(|| format!("running layer command `{program}`"))?;
+        if !status.success() {
+            return Err(anyhow!(
+                "layer command `{program}` failed with {status}: {}",
+                String::from_utf8_lossy(&stderr_tail).trim_end()
+            ));
+        }
+
+        let fs = async_fs::Fs::new();
+        let output_dir = project_dir.join(&output);
+        if !fs.exists(&output_dir).await {
+            return Err(anyhow!(
+                "layer command `{program}` didn't create its output directory `{output}`"
+            ));
+        }
+        let mut glob_builder = globset::GlobSet::builder();
+        glob_builder.add(globset::Glob::new("**")?);
+        let glob = glob_builder.build()?;
+        self.build_manifest(
+            fs.glob_walk(&output_dir, &glob).as_stream().map(|p| {
+                p.map(|p| match p.strip_prefix(&project_dir) {
+                    Ok(relative) => relative.to_owned(),
+                    Err(_) => p,
+                })
+            }),
+            PrefixOptions::default(),
+            data_upload,
+        )
+        .await
     }
 
     pub async fn build_layer(
         &self,
         layer: Layer,
         data_upload: impl DataUpload,
-    ) -> Result<(PathBuf, ArtifactType)> {
-        Ok(match layer {
-            Layer::Tar { path } => (path.into_std_path_buf(), ArtifactType::Tar),
+    ) -> Result<BuiltLayer> {
+        let (path, digest, paths) = match layer {
+            Layer::Tar { path } => {
+                return Ok(BuiltLayer {
+                    path: path.into_std_path_buf(),
+                    artifact_type: ArtifactType::Tar,
+                    digest: None,
+                    paths: None,
+                })
+            }
             Layer::Paths {
                 paths,
                 prefix_options,
             } => {
-                let manifest_path = self
-                    .build_manifest(
-                        futures::stream::iter(paths.iter().map(Ok)),
-                        prefix_options,
-                        data_upload,
-                    )
-                    .await?;
-                (manifest_path, ArtifactType::Manifest)
+                self.build_manifest(
+                    futures::stream::iter(paths.iter().map(Ok)),
+                    prefix_options,
+                    data_upload,
+                )
+                .await?
             }
             Layer::Glob {
                 glob,
@@ -232,27 +415,50 @@ impl LayerBuilder {
             } => {
                 let mut glob_builder = globset::GlobSet::builder();
                 glob_builder.add(globset::Glob::new(&glob)?);
-                let fs = async_fs::Fs::new();
-                let project_dir = self.project_dir.clone();
-                let manifest_path = self
-                    .build_manifest(
-                        fs.glob_walk(&self.project_dir, &glob_builder.build()?)
+                let glob = glob_builder.build()?;
+                if prefix_options.respect_gitignore {
+                    let paths = self.gitignore_glob_walk(glob).await?;
+                    self.build_manifest(
+                        futures::stream::iter(paths.into_iter().map(Ok)),
+                        prefix_options,
+                        data_upload,
+                    )
+                    .await?
+                } else {
+                    let fs = async_fs::Fs::new();
+                    let project_dir = self.project_dir.clone();
+                    self.build_manifest(
+                        fs.glob_walk(&self.project_dir, &glob)
                             .as_stream()
                             .map(|p| p.map(|p| p.strip_prefix(&project_dir).unwrap().to_owned())),
                         prefix_options,
                         data_upload,
                     )
-                    .await?;
-                (manifest_path, ArtifactType::Manifest)
-            }
-            Layer::Stubs { stubs } => {
-                let manifest_path = self.build_stub_manifest(stubs).await?;
-                (manifest_path, ArtifactType::Manifest)
+                    .await?
+                }
             }
-            Layer::Symlinks { symlinks } => {
-                let manifest_path = self.build_symlink_manifest(symlinks).await?;
-                (manifest_path, ArtifactType::Manifest)
+            Layer::Stubs { stubs } => self.build_stub_manifest(stubs).await?,
+            Layer::Symlinks { symlinks } => self.build_symlink_manifest(symlinks).await?,
+            Layer::Command {
+                program,
+                args,
+                output,
+            } => {
+                self.build_host_command_manifest(
+                    program,
+                    args,
+                    output,
+                    HOST_COMMAND_TIMEOUT,
+                    data_upload,
+                )
+                .await?
             }
+        };
+        Ok(BuiltLayer {
+            path,
+            artifact_type: ArtifactType::Manifest,
+            digest: Some(digest),
+            paths: Some(paths),
         })
     }
 }
@@ -265,6 +471,7 @@ mod tests {
     use maelstrom_util::manifest::AsyncManifestReader;
     use maplit::hashmap;
     use std::collections::HashMap;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
     use tempfile::tempdir;
 
     fn hash_data(data: &[u8]) -> Sha256Digest {
@@ -405,18 +612,22 @@ mod tests {
         async fn build_layer(&self, layer: Layer) -> PathBuf {
             let is_tar = matches!(layer, Layer::Tar { .. });
 
-            let (artifact_path, artifact_type) = self
+            let built = self
                 .builder
                 .build_layer(layer, &self.uploader)
                 .await
                 .unwrap();
             if is_tar {
-                assert_eq!(artifact_type, ArtifactType::Tar);
+                assert_eq!(built.artifact_type, ArtifactType::Tar);
+                assert_eq!(built.digest, None);
             } else {
-                assert_eq!(artifact_type, ArtifactType::Manifest);
+                assert_eq!(built.artifact_type, ArtifactType::Manifest);
+                // The digest computed while writing must match the contents of the file.
+                let (_, digest) = crate::hasher::calculate_digest(&built.path).await.unwrap();
+                assert_eq!(built.digest, Some(digest));
             }
 
-            artifact_path
+            built.path
         }
     }
 
@@ -703,6 +914,99 @@ mod tests {
         verify_empty_manifest(&manifest).await;
     }
 
+    async fn glob_gitignore_test(
+        respect_gitignore: bool,
+        input_files: HashMap<&str, &str>,
+        expected: Vec<&str>,
+    ) {
+        let fix = Fixture::new().await;
+        for (path, contents) in input_files {
+            let artifact = fix.artifact_dir.join(path);
+            fix.fs
+                .create_dir_all(artifact.parent().unwrap())
+                .await
+                .unwrap();
+            fix.fs.write(artifact, contents.as_bytes()).await.unwrap();
+        }
+
+        let manifest = fix
+            .build_layer(Layer::Glob {
+                glob: "**/*.txt".into(),
+                prefix_options: PrefixOptions {
+                    respect_gitignore,
+                    ..Default::default()
+                },
+            })
+            .await;
+        verify_manifest(
+            &manifest,
+            expected
+                .into_iter()
+                .map(|path| {
+                    ExpectedManifestEntry::new(
+                        path,
+                        0o100644,
+                        ManifestEntryData::File(Some(hash_data(b"hello world"))),
+                    )
+                })
+                .collect(),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn glob_respect_gitignore_nested() {
+        glob_gitignore_test(
+            true,
+            hashmap! {
+                ".gitignore" => "target/\nignored*.txt\n",
+                "kept.txt" => "hello world",
+                "ignored.txt" => "hello world",
+                "target/out.txt" => "hello world",
+                "sub/.gitignore" => "local.txt\n",
+                "sub/local.txt" => "hello world",
+                "sub/kept.txt" => "hello world",
+                "sub/ignored-too.txt" => "hello world",
+                "local.txt" => "hello world",
+            },
+            vec!["kept.txt", "local.txt", "sub/kept.txt"],
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn glob_respect_gitignore_negated() {
+        glob_gitignore_test(
+            true,
+            hashmap! {
+                ".gitignore" => "*.txt\n!kept*.txt\n",
+                "kept.txt" => "hello world",
+                "ignored.txt" => "hello world",
+                "sub/.gitignore" => "!sub-kept.txt\nkept-but-not-here.txt\n",
+                "sub/sub-kept.txt" => "hello world",
+                "sub/kept-but-not-here.txt" => "hello world",
+                "sub/kept.txt" => "hello world",
+                "sub/ignored.txt" => "hello world",
+            },
+            vec!["kept.txt", "sub/sub-kept.txt", "sub/kept.txt"],
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn glob_without_respect_gitignore() {
+        glob_gitignore_test(
+            false,
+            hashmap! {
+                ".gitignore" => "ignored.txt\n",
+                "kept.txt" => "hello world",
+                "ignored.txt" => "hello world",
+            },
+            vec!["kept.txt", "ignored.txt"],
+        )
+        .await;
+    }
+
     async fn stubs_test(path: &str, expected: Vec<ExpectedManifestEntry>) {
         let fix = Fixture::new().await;
         let manifest = fix
@@ -771,6 +1075,101 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn command_test() {
+        let fix = Fixture::new().await;
+        let manifest = fix
+            .build_layer(Layer::Command {
+                program: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    "mkdir -p out/sub && echo hello > out/sub/file".into(),
+                ],
+                output: "out".into(),
+            })
+            .await;
+        verify_manifest(
+            &manifest,
+            vec![
+                ExpectedManifestEntry::new(
+                    "out/sub",
+                    0o40755,
+                    ManifestEntryData::Directory { opaque: false },
+                ),
+                ExpectedManifestEntry::new(
+                    "out/sub/file",
+                    0o100644,
+                    ManifestEntryData::File(Some(hash_data(b"hello\n"))),
+                ),
+            ],
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn command_failure_test() {
+        let fix = Fixture::new().await;
+        let err = fix
+            .builder
+            .build_layer(
+                Layer::Command {
+                    program: "sh".into(),
+                    args: vec!["-c".into(), "echo oops >&2; exit 3".into()],
+                    output: "out".into(),
+                },
+                &fix.uploader,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "layer command `sh` failed with exit status: 3: oops"
+        );
+    }
+
+    #[tokio::test]
+    async fn command_failure_keeps_stderr_tail_test() {
+        let fix = Fixture::new().await;
+        let err = fix
+            .builder
+            .build_layer(
+                Layer::Command {
+                    program: "sh".into(),
+                    args: vec![
+                        "-c".into(),
+                        "head -c 100000 /dev/zero | tr '\\0' a >&2; echo tail >&2; exit 1".into(),
+                    ],
+                    output: "out".into(),
+                },
+                &fix.uploader,
+            )
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.ends_with("aaaatail"), "{err}");
+        assert!(err.len() < HOST_COMMAND_STDERR_TAIL + 100, "{}", err.len());
+    }
+
+    #[tokio::test]
+    async fn command_timeout_test() {
+        let fix = Fixture::new().await;
+        let err = fix
+            .builder
+            .build_host_command_manifest(
+                "sleep".into(),
+                vec!["60".into()],
+                "out".into(),
+                Duration::from_millis(100),
+                &fix.uploader,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "layer command `sleep` timed out after 100ms"
+        );
+    }
+
     #[tokio::test]
     async fn symlink_test() {
         let fix = Fixture::new().await;
@@ -792,4 +1191,185 @@ mod tests {
         )
         .await;
     }
+
+    /// Write the same files into the project directory, with the given modification time, and
+    /// build a glob layer from them. Return the layer's digest and the mtimes in its manifest.
+    async fn build_layer_with_mtime(
+        fix: &Fixture,
+        mtime: SystemTime,
+        prefix_options: PrefixOptions,
+    ) -> (Sha256Digest, Vec<UnixTimestamp>) {
+        let dir = fix.artifact_dir.join("dir");
+        fix.fs.create_dir_all(dir.join("b")).await.unwrap();
+        for (path, contents) in [("a.txt", "a"), ("b/c.txt", "c")] {
+            let path = dir.join(path);
+            fix.fs.write(&path, contents).await.unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        }
+
+        let built = fix
+            .builder
+            .build_layer(
+                Layer::Glob {
+                    glob: "dir/**".into(),
+                    prefix_options,
+                },
+                &fix.uploader,
+            )
+            .await
+            .unwrap();
+        let mut entry_iter = AsyncManifestReader::new(fix.fs.open_file(&built.path).await.unwrap())
+            .await
+            .unwrap();
+        let mut mtimes = vec![];
+        while let Some(entry) = entry_iter.next().await.unwrap() {
+            if matches!(entry.data, ManifestEntryData::File(_)) {
+                mtimes.push(entry.metadata.mtime);
+            }
+        }
+        (built.digest.unwrap(), mtimes)
+    }
+
+    #[tokio::test]
+    async fn same_files_with_different_mtimes_have_same_digest() {
+        let fix = Fixture::new().await;
+        let epoch = UNIX_EPOCH;
+        let (digest1, mtimes1) =
+            build_layer_with_mtime(&fix, epoch + Duration::from_secs(1000), Default::default())
+                .await;
+        let (digest2, mtimes2) =
+            build_layer_with_mtime(&fix, epoch + Duration::from_secs(2000), Default::default())
+                .await;
+        assert_eq!(digest1, digest2);
+        assert_eq!(mtimes1, vec![ARBITRARY_TIME; 2]);
+        assert_eq!(mtimes2, vec![ARBITRARY_TIME; 2]);
+    }
+
+    #[tokio::test]
+    async fn preserve_mtimes() {
+        let fix = Fixture::new().await;
+        let epoch = UNIX_EPOCH;
+        let prefix_options = PrefixOptions {
+            preserve_mtimes: true,
+            ..Default::default()
+        };
+        let (digest1, mtimes1) = build_layer_with_mtime(
+            &fix,
+            epoch + Duration::from_secs(1000),
+            prefix_options.clone(),
+        )
+        .await;
+        let (digest2, mtimes2) =
+            build_layer_with_mtime(&fix, epoch + Duration::from_secs(2000), prefix_options).await;
+        assert_ne!(digest1, digest2);
+        assert_eq!(mtimes1, vec![UnixTimestamp(1000); 2]);
+        assert_eq!(mtimes2, vec![UnixTimestamp(2000); 2]);
+    }
+
+    /// Hashes the file without reading it all into memory, like the real client does.
+    struct StreamingUploader;
+
+    #[async_trait]
+    impl DataUpload for &StreamingUploader {
+        async fn upload(&mut self, path: &Path) -> Result<Sha256Digest> {
+            Ok(crate::hasher::calculate_digest(path).await?.1)
+        }
+    }
+
+    fn proc_status_kb(field: &str) -> u64 {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let line = status
+            .lines()
+            .find_map(|line| line.strip_prefix(field))
+            .unwrap();
+        line.trim_start_matches(':')
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .unwrap()
+    }
+
+    const SMALL_FILES: usize = 1000;
+
+    /// Build a paths layer in a new directory named `dir_name` holding a sparse file named "big"
+    /// of size `file_size`, along with `SMALL_FILES` small files. Return the layer, along with how
+    /// much the peak resident set size grew while building it, if that can be measured.
+    async fn build_big_file_layer(
+        fix: &Fixture,
+        dir_name: &str,
+        file_size: u64,
+    ) -> (BuiltLayer, Option<u64>) {
+        let dir = fix.artifact_dir.join(dir_name);
+        fix.fs.create_dir_all(&dir).await.unwrap();
+        // A sparse file doesn't take up any disk space, but reads back as all zeros.
+        let big_file = dir.join("big");
+        std::fs::File::create(&big_file)
+            .unwrap()
+            .set_len(file_size)
+            .unwrap();
+        let mut paths = vec![Utf8PathBuf::try_from(big_file).unwrap()];
+        for i in 0..SMALL_FILES {
+            let path = dir.join(format!("small-{i}"));
+            fix.fs.write(&path, i.to_string()).await.unwrap();
+            paths.push(path.try_into().unwrap());
+        }
+
+        // Writing 5 to clear_refs resets the peak resident set size. If we can't do that, we can
+        // still check that the layer is built correctly.
+        let can_measure = std::fs::write("/proc/self/clear_refs", "5").is_ok();
+        let rss_before = proc_status_kb("VmRSS");
+        let built = fix
+            .builder
+            .build_layer(
+                Layer::Paths {
+                    paths,
+                    prefix_options: Default::default(),
+                },
+                &StreamingUploader,
+            )
+            .await
+            .unwrap();
+        let growth = can_measure.then(|| proc_status_kb("VmHWM").saturating_sub(rss_before));
+        (built, growth)
+    }
+
+    #[tokio::test]
+    async fn large_paths_layer_uses_bounded_memory() {
+        const FILE_SIZE: u64 = 256 << 20;
+
+        let fix = Fixture::new().await;
+        // The peak resident set size is for the whole process, so it depends on the allocator and
+        // whatever else is running. Compare against building the same layer with a small file.
+        let (_, baseline_growth) = build_big_file_layer(&fix, "baseline", 1 << 20).await;
+        let (built, growth) = build_big_file_layer(&fix, "large", FILE_SIZE).await;
+        if let (Some(baseline_growth), Some(growth)) = (baseline_growth, growth) {
+            // If the file were read into memory, the resident set would grow by its whole size.
+            let extra_growth = growth.saturating_sub(baseline_growth);
+            assert!(
+                extra_growth < (FILE_SIZE >> 10) / 2,
+                "resident set grew by {growth} kB while building the layer, compared to \
+                {baseline_growth} kB with a small file"
+            );
+        }
+
+        let (_, digest) = crate::hasher::calculate_digest(&built.path).await.unwrap();
+        assert_eq!(built.digest, Some(digest));
+        let mut reader = AsyncManifestReader::new(fix.fs.open_file(&built.path).await.unwrap())
+            .await
+            .unwrap();
+        let mut entries = 0;
+        while let Some(entry) = reader.next().await.unwrap() {
+            if entry.path.file_name() == Some("big") {
+                assert_eq!(entry.metadata.size, FILE_SIZE);
+            }
+            entries += 1;
+        }
+        assert_eq!(entries, SMALL_FILES + 1);
+    }
 }
@@ -0,0 +1,158 @@
+//! Put a job's layers in a canonical order, so that jobs whose layers differ only in the order of
+//! layers that don't affect each other end up with the same layers.
+
+use maelstrom_base::{ArtifactType, NonEmpty, Sha256Digest, Utf8Path, Utf8PathBuf};
+use std::collections::BTreeSet;
+
+/// Make a layer's paths absolute, and drop any `.` components and trailing slashes, so that paths
+/// from different layers can be compared directly. Relative paths are relative to the root.
+fn normalize(paths: &BTreeSet<Utf8PathBuf>) -> BTreeSet<Utf8PathBuf> {
+    BTreeSet::from_iter(
+        paths
+            .iter()
+            .map(|path| Utf8Path::new("/").join(path).components().collect()),
+    )
+}
+
+/// Return whether two layers with the given normalized paths could affect each other if their
+/// order was swapped. This is the case if one layer has a path that is the same as, or an ancestor
+/// of, a path in the other layer. A layer whose paths aren't known could affect any other layer.
+fn overlaps(a: Option<&BTreeSet<Utf8PathBuf>>, b: Option<&BTreeSet<Utf8PathBuf>>) -> bool {
+    fn has_ancestor_in(paths: &BTreeSet<Utf8PathBuf>, others: &BTreeSet<Utf8PathBuf>) -> bool {
+        paths
+            .iter()
+            .any(|path| path.ancestors().any(|ancestor| others.contains(ancestor)))
+    }
+    match (a, b) {
+        (Some(a), Some(b)) => has_ancestor_in(a, b) || has_ancestor_in(b, a),
+        _ => true,
+    }
+}
+
+/// Reorder `layers` so that layers that don't overlap are sorted by digest, while any two layers
+/// that do overlap stay in the order they were given in. `paths` returns the paths a layer contains,
+/// if they are known. Relative paths are taken to be relative to the root.
+///
+/// This is a stable topological sort: a layer is only placed once every earlier layer it overlaps
+/// has been placed, and of the layers that could be placed next, the one with the smallest digest
+/// is chosen.
+pub fn canonicalize<'a>(
+    layers: NonEmpty<(Sha256Digest, ArtifactType)>,
+    paths: impl Fn(&Sha256Digest) -> Option<&'a BTreeSet<Utf8PathBuf>>,
+) -> NonEmpty<(Sha256Digest, ArtifactType)> {
+    let layers = Vec::from(layers);
+    let layer_paths = Vec::from_iter(
+        layers
+            .iter()
+            .map(|(digest, _)| paths(digest).map(normalize)),
+    );
+
+    // For each layer, the number of earlier layers it overlaps that haven't been placed yet.
+    let mut blockers = vec![0usize; layers.len()];
+    for later in 0..layers.len() {
+        for earlier in 0..later {
+            if overlaps(layer_paths[earlier].as_ref(), layer_paths[later].as_ref()) {
+                blockers[later] += 1;
+            }
+        }
+    }
+
+    let mut placed = vec![false; layers.len()];
+    let mut order = Vec::with_capacity(layers.len());
+    while order.len() < layers.len() {
+        let next = (0..layers.len())
+            .filter(|&i| !placed[i] && blockers[i] == 0)
+            .min_by_key(|&i| &layers[i])
+            .unwrap();
+        placed[next] = true;
+        order.push(next);
+        for later in next + 1..layers.len() {
+            if overlaps(layer_paths[next].as_ref(), layer_paths[later].as_ref()) {
+                blockers[later] -= 1;
+            }
+        }
+    }
+
+    NonEmpty::from_vec(Vec::from_iter(order.into_iter().map(|i| layers[i].clone()))).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maelstrom_test::digest;
+    use std::collections::HashMap;
+
+    fn layer_paths(layers: &[(u64, &[&str])]) -> HashMap<Sha256Digest, BTreeSet<Utf8PathBuf>> {
+        HashMap::from_iter(layers.iter().map(|(d, paths)| {
+            (
+                digest!(*d),
+                BTreeSet::from_iter(paths.iter().map(Utf8PathBuf::from)),
+            )
+        }))
+    }
+
+    fn manifests(digests: &[u64]) -> NonEmpty<(Sha256Digest, ArtifactType)> {
+        NonEmpty::from_vec(Vec::from_iter(
+            digests
+                .iter()
+                .map(|d| (digest!(*d), ArtifactType::Manifest)),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn independent_layers_in_any_order_are_the_same() {
+        let paths = layer_paths(&[(1, &["/usr/bin/a"]), (2, &["lib/b.so"]), (3, &["/etc/c"])]);
+        let canonical = |digests| canonicalize(manifests(digests), |d| paths.get(d));
+        assert_eq!(canonical(&[3, 1, 2]), manifests(&[1, 2, 3]));
+        assert_eq!(canonical(&[2, 3, 1]), manifests(&[1, 2, 3]));
+        assert_eq!(canonical(&[1, 2, 3]), manifests(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn overlapping_layers_keep_their_order() {
+        let paths = layer_paths(&[
+            (1, &["/etc/config"]),
+            (2, &["/etc/config"]),
+            (3, &["/usr/bin/a"]),
+            (4, &["/usr"]),
+        ]);
+        let canonical = |digests| canonicalize(manifests(digests), |d| paths.get(d));
+
+        // 2 has to stay before 1 and 3 has to stay before 4, but otherwise layers are sorted.
+        assert_eq!(canonical(&[2, 3, 1, 4]), manifests(&[2, 1, 3, 4]));
+        assert_eq!(canonical(&[3, 2, 4, 1]), manifests(&[2, 1, 3, 4]));
+        assert_eq!(canonical(&[4, 1, 3, 2]), manifests(&[1, 2, 4, 3]));
+    }
+
+    #[test]
+    fn relative_paths_overlap_absolute_paths() {
+        let paths = layer_paths(&[
+            (1, &["/usr/lib/a.so"]),
+            (2, &["usr/"]),
+            (3, &["./etc/config"]),
+            (4, &["/etc"]),
+        ]);
+        let canonical = |digests| canonicalize(manifests(digests), |d| paths.get(d));
+
+        // A relative path can be the ancestor of an absolute one, and the other way around.
+        assert_eq!(canonical(&[2, 1, 4, 3]), manifests(&[2, 1, 4, 3]));
+        assert_eq!(canonical(&[1, 2, 3, 4]), manifests(&[1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn layers_with_unknown_paths_stay_in_place() {
+        let paths = layer_paths(&[(1, &["/a"]), (2, &["/b"]), (4, &["/d"])]);
+        let layers = |digests: &[u64]| {
+            NonEmpty::from_vec(Vec::from_iter(digests.iter().map(|d| match d {
+                3 => (digest!(3), ArtifactType::Tar),
+                _ => (digest!(*d), ArtifactType::Manifest),
+            })))
+            .unwrap()
+        };
+        assert_eq!(
+            canonicalize(layers(&[4, 2, 3, 1]), |d| paths.get(d)),
+            layers(&[2, 4, 3, 1])
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! Drive the local worker used in standalone mode, shutting it down when it has been idle.
+
+use anyhow::Result;
+use maelstrom_worker::dispatcher::{self, ArtifactFetcher, BrokerSender, Cache, Deps, Dispatcher};
+use slog::{debug, Logger};
+use std::time::Duration;
+use tokio::{sync::mpsc::UnboundedReceiver, time};
+
+/// The parts of the local worker's [`Dispatcher`] that [`run`] needs. This lets us test [`run`]
+/// without a real dispatcher.
+pub trait LocalWorker {
+    type Message;
+    fn receive_message(&mut self, msg: Self::Message);
+    fn is_idle(&self) -> bool;
+}
+
+impl<DepsT, ArtifactFetcherT, BrokerSenderT, CacheT> LocalWorker
+    for Dispatcher<DepsT, ArtifactFetcherT, BrokerSenderT, CacheT>
+where
+    DepsT: Deps,
+    ArtifactFetcherT: ArtifactFetcher,
+    BrokerSenderT: BrokerSender,
+    CacheT: Cache,
+{
+    type Message = dispatcher::Message;
+
+    fn receive_message(&mut self, msg: Self::Message) {
+        self.receive_message(msg)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.is_idle()
+    }
+}
+
+/// Hand the messages read from `receiver` to `worker` until the channel is closed.
+///
+/// If `idle_timeout` is given, and the worker has been idle for that long, it is dropped, which
+/// releases its cache and anything else it holds. A new worker is made with `new_worker` when the
+/// next message arrives.
+pub async fn run<WorkerT: LocalWorker>(
+    mut receiver: UnboundedReceiver<WorkerT::Message>,
+    worker: WorkerT,
+    mut new_worker: impl FnMut() -> Result<WorkerT>,
+    idle_timeout: Option<Duration>,
+    log: Logger,
+) -> Result<()> {
+    let mut worker = Some(worker);
+    loop {
+        let msg = match (&worker, idle_timeout) {
+            (Some(current), Some(idle_timeout)) if current.is_idle() => {
+                match time::timeout(idle_timeout, receiver.recv()).await {
+                    Ok(msg) => msg,
+                    Err(_) => {
+                        debug!(log, "shutting down idle local worker"; "idle_timeout" => ?idle_timeout);
+                        worker = None;
+                        continue;
+                    }
+                }
+            }
+            _ => receiver.recv().await,
+        };
+        let Some(msg) = msg else {
+            break;
+        };
+        if worker.is_none() {
+            debug!(log, "restarting local worker");
+            worker = Some(new_worker()?);
+        }
+        worker.as_mut().unwrap().receive_message(msg);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::{sync::mpsc, task, time::sleep};
+
+    #[derive(Debug)]
+    enum Message {
+        StartJob,
+        FinishJob,
+    }
+
+    /// What has happened to each of the workers made so far.
+    #[derive(Debug, Default, PartialEq)]
+    struct WorkerEvents {
+        received: Vec<usize>,
+        dropped: bool,
+    }
+
+    struct FakeWorker {
+        events: Arc<Mutex<Vec<WorkerEvents>>>,
+        index: usize,
+        running: usize,
+    }
+
+    impl FakeWorker {
+        fn new(events: &Arc<Mutex<Vec<WorkerEvents>>>) -> Self {
+            let mut locked = events.lock().unwrap();
+            locked.push(WorkerEvents::default());
+            Self {
+                events: events.clone(),
+                index: locked.len() - 1,
+                running: 0,
+            }
+        }
+    }
+
+    impl LocalWorker for FakeWorker {
+        type Message = Message;
+
+        fn receive_message(&mut self, msg: Message) {
+            match msg {
+                Message::StartJob => self.running += 1,
+                Message::FinishJob => self.running -= 1,
+            }
+            self.events.lock().unwrap()[self.index]
+                .received
+                .push(self.running);
+        }
+
+        fn is_idle(&self) -> bool {
+            self.running == 0
+        }
+    }
+
+    impl Drop for FakeWorker {
+        fn drop(&mut self) {
+            self.events.lock().unwrap()[self.index].dropped = true;
+        }
+    }
+
+    fn worker_events(received: &[usize], dropped: bool) -> WorkerEvents {
+        WorkerEvents {
+            received: received.to_vec(),
+            dropped,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_worker_is_dropped_and_replaced_on_next_message() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let worker = FakeWorker::new(&events);
+        let events_clone = events.clone();
+        let task = task::spawn(run(
+            receiver,
+            worker,
+            move || Ok(FakeWorker::new(&events_clone)),
+            Some(Duration::from_secs(60)),
+            slog::Logger::root(slog::Discard, slog::o!()),
+        ));
+
+        // A worker with a job running isn't idle, no matter how long the job takes.
+        sender.send(Message::StartJob).unwrap();
+        sleep(Duration::from_secs(120)).await;
+        assert_eq!(*events.lock().unwrap(), vec![worker_events(&[1], false)]);
+
+        sender.send(Message::FinishJob).unwrap();
+        sleep(Duration::from_secs(59)).await;
+        assert_eq!(*events.lock().unwrap(), vec![worker_events(&[1, 0], false)]);
+
+        sleep(Duration::from_secs(2)).await;
+        assert_eq!(*events.lock().unwrap(), vec![worker_events(&[1, 0], true)]);
+
+        sender.send(Message::StartJob).unwrap();
+        sleep(Duration::from_secs(1)).await;
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![worker_events(&[1, 0], true), worker_events(&[1], false)]
+        );
+
+        drop(sender);
+        task.await.unwrap().unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![worker_events(&[1, 0], true), worker_events(&[1], true)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn worker_is_kept_without_idle_timeout() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let worker = FakeWorker::new(&events);
+        let task = task::spawn(run(
+            receiver,
+            worker,
+            || panic!("no new worker should be made"),
+            None,
+            slog::Logger::root(slog::Discard, slog::o!()),
+        ));
+
+        sender.send(Message::StartJob).unwrap();
+        sender.send(Message::FinishJob).unwrap();
+        sleep(Duration::from_secs(3600)).await;
+        sender.send(Message::StartJob).unwrap();
+        drop(sender);
+        task.await.unwrap().unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![worker_events(&[1, 0, 1], true)]
+        );
+    }
+}
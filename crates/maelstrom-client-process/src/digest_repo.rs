@@ -123,7 +123,7 @@ async fn digest_repository_simple_add_get() {
 
     let foo_path = tmp_dir.path().join("foo.tar");
     fs.write(&foo_path, "foo").await.unwrap();
-    let (mtime, digest) = crate::calculate_digest(&foo_path).await.unwrap();
+    let (mtime, digest) = crate::hasher::calculate_digest(&foo_path).await.unwrap();
     repo.add(foo_path.clone(), mtime, digest.clone())
         .await
         .unwrap();
@@ -139,7 +139,7 @@ async fn digest_repository_simple_add_get_after_modify() {
 
     let foo_path = tmp_dir.path().join("foo.tar");
     fs.write(&foo_path, "foo").await.unwrap();
-    let (mtime, digest) = crate::calculate_digest(&foo_path).await.unwrap();
+    let (mtime, digest) = crate::hasher::calculate_digest(&foo_path).await.unwrap();
     repo.add(foo_path.clone(), mtime, digest.clone())
         .await
         .unwrap();
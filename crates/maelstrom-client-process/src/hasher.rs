@@ -0,0 +1,149 @@
+//! Hashing of artifacts on a pool of threads.
+
+use anyhow::Result;
+use maelstrom_base::Sha256Digest;
+use maelstrom_util::{config::common::HashingThreads, io::Sha256Stream};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::{sync::Semaphore, task};
+
+fn calculate_digest_sync(path: &Path) -> Result<(SystemTime, Sha256Digest)> {
+    let mut f = fs::File::open(path)?;
+    let mut hasher = Sha256Stream::new(io::sink());
+    io::copy(&mut f, &mut hasher)?;
+    let mtime = f.metadata()?.modified()?;
+
+    Ok((mtime, hasher.finalize().1))
+}
+
+/// Compute the digest of a file, along with its modification time, without blocking the runtime.
+#[cfg(test)]
+pub async fn calculate_digest(path: &Path) -> Result<(SystemTime, Sha256Digest)> {
+    let path = path.to_owned();
+    task::spawn_blocking(move || calculate_digest_sync(&path)).await?
+}
+
+/// Hashes artifacts on tokio's blocking thread pool, limiting how many are hashed at once.
+pub struct ArtifactHasher {
+    permits: Arc<Semaphore>,
+}
+
+impl ArtifactHasher {
+    pub fn new(threads: HashingThreads) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(threads.into_inner().into())),
+        }
+    }
+
+    /// Compute the digest of a file, along with its modification time. If too many other files are
+    /// being hashed, this waits for one of them to finish first.
+    pub async fn hash(&self, path: PathBuf) -> Result<(SystemTime, Sha256Digest)> {
+        self.run(move || calculate_digest_sync(&path)).await
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let _permit = self.permits.acquire().await?;
+        task::spawn_blocking(f).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Condvar, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    fn hasher(threads: u16) -> ArtifactHasher {
+        ArtifactHasher::new(HashingThreads::try_from(threads).unwrap())
+    }
+
+    #[tokio::test]
+    async fn many_artifacts_hashed_correctly() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let paths = Vec::from_iter((0..100).map(|i| {
+            let path = temp_dir.path().join(format!("artifact-{i}"));
+            fs::write(&path, format!("contents of artifact {i}").repeat(i + 1)).unwrap();
+            path
+        }));
+
+        let hasher = hasher(8);
+        let results = future::try_join_all(paths.iter().map(|path| hasher.hash(path.clone())))
+            .await
+            .unwrap();
+
+        for (path, (mtime, digest)) in paths.iter().zip(results) {
+            let (expected_mtime, expected_digest) = calculate_digest_sync(path).unwrap();
+            assert_eq!(mtime, expected_mtime);
+            assert_eq!(digest, expected_digest);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_artifact_is_an_error() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        hasher(1)
+            .hash(temp_dir.path().join("missing"))
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn artifacts_hashed_concurrently() {
+        // Every job waits until all of them have started, so they can only all see each other if
+        // they run at the same time. The wait has a timeout so that the test fails instead of
+        // hanging if they don't.
+        const THREADS: u16 = 4;
+        let hasher = hasher(THREADS);
+        let started = Arc::new((Mutex::new(0), Condvar::new()));
+        let jobs = (0..THREADS).map(|_| {
+            let started = started.clone();
+            hasher.run(move || {
+                let (count, condvar) = &*started;
+                let mut count = count.lock().unwrap();
+                *count += 1;
+                condvar.notify_all();
+                let (count, _) = condvar
+                    .wait_timeout_while(count, Duration::from_secs(30), |count| *count < THREADS)
+                    .unwrap();
+                Ok(*count == THREADS)
+            })
+        });
+        let all_saw_each_other = future::try_join_all(jobs).await.unwrap();
+        assert_eq!(all_saw_each_other, vec![true; THREADS.into()]);
+    }
+
+    #[tokio::test]
+    async fn concurrency_is_bounded() {
+        const THREADS: u16 = 2;
+        let hasher = hasher(THREADS);
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let jobs = (0..10).map(|_| {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            hasher.run(move || {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now_running, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            })
+        });
+        future::try_join_all(jobs).await.unwrap();
+        assert_eq!(max_running.load(Ordering::SeqCst), usize::from(THREADS));
+    }
+}
@@ -1,6 +1,7 @@
 mod artifact_pusher;
 mod client;
 mod digest_repo;
+mod hasher;
 mod router;
 mod rpc;
 mod stream_wrapper;
@@ -10,25 +11,14 @@ pub use maelstrom_worker::clone_into_pid_and_user_namespace;
 use anyhow::Result;
 use client::Client;
 use futures::stream::StreamExt as _;
-use maelstrom_base::Sha256Digest;
 use maelstrom_client_base::proto::client_process_server::ClientProcessServer;
-use maelstrom_util::{async_fs, io::Sha256Stream, log::LoggerFactory};
+use maelstrom_util::log::LoggerFactory;
 use rpc::Handler;
-use std::{error, os::unix::net::UnixStream as StdUnixStream, path::Path, time::SystemTime};
+use std::{error, os::unix::net::UnixStream as StdUnixStream};
 use stream_wrapper::StreamWrapper;
 use tokio::net::UnixStream as TokioUnixStream;
 use tonic::transport::Server;
 
-async fn calculate_digest(path: &Path) -> Result<(SystemTime, Sha256Digest)> {
-    let fs = async_fs::Fs::new();
-    let mut f = fs.open_file(path).await?;
-    let mut hasher = Sha256Stream::new(tokio::io::sink());
-    tokio::io::copy(&mut f, &mut hasher).await?;
-    let mtime = f.metadata().await?.modified()?;
-
-    Ok((mtime, hasher.finalize().1))
-}
-
 type TokioError<T> = Result<T, Box<dyn error::Error + Send + Sync>>;
 
 #[tokio::main]
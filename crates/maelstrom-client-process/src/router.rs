@@ -2,8 +2,9 @@ use crate::artifact_pusher;
 use anyhow::{anyhow, Result};
 use maelstrom_base::{
     proto::{BrokerToClient, BrokerToWorker, ClientToBroker, WorkerToBroker},
-    stats::{JobState, JobStateCounts},
-    ClientId, ClientJobId, JobId, JobOutcomeResult, JobSpec, Sha256Digest,
+    stats::{CachedArtifact, JobState, JobStateCounts, WorkerDistribution},
+    ClientId, ClientJobId, JobId, JobOutcomeResult, JobOutputStream, JobSpec, Sha256Digest,
+    WorkerCapabilities, WorkerCapabilitiesMap, WorkerId,
 };
 use maelstrom_util::{config::common::Slots, ext::OptionExt as _, fs::Fs, sync};
 use std::{
@@ -21,10 +22,35 @@ use tokio::{
 pub trait Deps {
     type JobHandle;
     fn job_done(&self, handle: Self::JobHandle, cjid: ClientJobId, result: JobOutcomeResult);
+    fn job_running(&self, handle: &mut Self::JobHandle, cjid: ClientJobId);
+    fn job_output(
+        &self,
+        handle: &mut Self::JobHandle,
+        cjid: ClientJobId,
+        stream: JobOutputStream,
+        output: Vec<u8>,
+    );
 
     type JobStateCountsHandle;
     fn job_state_counts(&self, handle: Self::JobStateCountsHandle, counts: JobStateCounts);
 
+    type WorkerDistributionHandle;
+    fn worker_distribution(
+        &self,
+        handle: Self::WorkerDistributionHandle,
+        distribution: WorkerDistribution,
+    );
+
+    type WorkerCapabilitiesHandle;
+    fn worker_capabilities(
+        &self,
+        handle: Self::WorkerCapabilitiesHandle,
+        capabilities: WorkerCapabilitiesMap,
+    );
+
+    type ArtifactsHandle;
+    fn artifacts(&self, handle: Self::ArtifactsHandle, artifacts: Vec<CachedArtifact>);
+
     type AllJobsCompleteHandle;
     fn all_jobs_complete(&self, handle: Self::AllJobsCompleteHandle);
 
@@ -42,7 +68,14 @@ pub enum Message<DepsT: Deps> {
     AddArtifact(PathBuf, Sha256Digest),
     RunJob(JobSpec, DepsT::JobHandle),
     GetJobStateCounts(DepsT::JobStateCountsHandle),
+    GetWorkerDistribution(DepsT::WorkerDistributionHandle),
+    GetWorkerCapabilities(DepsT::WorkerCapabilitiesHandle),
+    GetArtifacts(DepsT::ArtifactsHandle),
     NotifyWhenAllJobsComplete(DepsT::AllJobsCompleteHandle),
+    CancelAllJobs,
+    CancelJob(ClientJobId),
+    /// The consumer of a job's streamed output has taken this many more bytes of it.
+    AckJobOutput(ClientJobId, u32),
 
     // Only in non-standalone mode.
     Broker(BrokerToClient),
@@ -56,32 +89,67 @@ struct Router<DepsT: Deps> {
     deps: DepsT,
     standalone: bool,
     slots: Slots,
+    local_worker_capabilities: WorkerCapabilities,
     artifacts: HashMap<Sha256Digest, PathBuf>,
     next_client_job_id: u32,
     job_handles: HashMap<ClientJobId, DepsT::JobHandle>,
     job_state_counts_handles: VecDeque<DepsT::JobStateCountsHandle>,
+    worker_distribution_handles: VecDeque<DepsT::WorkerDistributionHandle>,
+    worker_capabilities_handles: VecDeque<DepsT::WorkerCapabilitiesHandle>,
+    artifacts_handles: VecDeque<DepsT::ArtifactsHandle>,
     all_jobs_complete_handles: Vec<DepsT::AllJobsCompleteHandle>,
     counts: JobStateCounts,
 }
 
 impl<DepsT: Deps> Router<DepsT> {
-    fn new(deps: DepsT, standalone: bool, slots: Slots) -> Self {
+    fn new(
+        deps: DepsT,
+        standalone: bool,
+        slots: Slots,
+        local_worker_capabilities: WorkerCapabilities,
+    ) -> Self {
         Self {
             deps,
             standalone,
             slots,
+            local_worker_capabilities,
             artifacts: Default::default(),
             next_client_job_id: Default::default(),
             job_handles: Default::default(),
             job_state_counts_handles: Default::default(),
+            worker_distribution_handles: Default::default(),
+            worker_capabilities_handles: Default::default(),
+            artifacts_handles: Default::default(),
             all_jobs_complete_handles: Default::default(),
             counts: Default::default(),
         }
     }
 
     fn receive_job_response(&mut self, cjid: ClientJobId, result: JobOutcomeResult) {
-        let handle = self.job_handles.remove(&cjid).unwrap();
+        // The handle will be missing if the job was canceled after its response was sent.
+        let Some(handle) = self.job_handles.remove(&cjid) else {
+            return;
+        };
         self.deps.job_done(handle, cjid, result);
+        self.possibly_notify_all_jobs_complete();
+    }
+
+    fn receive_job_running(&mut self, cjid: ClientJobId) {
+        // As with output, the handle will only be missing if the job was canceled.
+        if let Some(handle) = self.job_handles.get_mut(&cjid) {
+            self.deps.job_running(handle, cjid);
+        }
+    }
+
+    fn receive_job_output(&mut self, cjid: ClientJobId, stream: JobOutputStream, output: Vec<u8>) {
+        // Output can't arrive after the job's response, so the handle will only be missing if the
+        // job was canceled.
+        if let Some(handle) = self.job_handles.get_mut(&cjid) {
+            self.deps.job_output(handle, cjid, stream, output);
+        }
+    }
+
+    fn possibly_notify_all_jobs_complete(&mut self) {
         if self.job_handles.is_empty() {
             for handle in self.all_jobs_complete_handles.drain(..) {
                 self.deps.all_jobs_complete(handle);
@@ -89,6 +157,79 @@ impl<DepsT: Deps> Router<DepsT> {
         }
     }
 
+    fn cancel_all_jobs(&mut self) {
+        // Dropping the handles tells whoever is waiting on the jobs that they were canceled.
+        let mut cjids: Vec<_> = self.job_handles.drain().map(|(cjid, _)| cjid).collect();
+        if self.standalone {
+            // We sort the jobs so the local worker cancels them in the order they were added.
+            cjids.sort();
+            for cjid in cjids {
+                self.deps.send_message_to_local_worker(
+                    maelstrom_worker::dispatcher::Message::Broker(BrokerToWorker::CancelJob(
+                        JobId {
+                            cid: ClientId::from(0),
+                            cjid,
+                        },
+                    )),
+                );
+            }
+            self.counts[JobState::Pending] = 0;
+            self.counts[JobState::Running] = 0;
+        } else {
+            self.deps
+                .send_message_to_broker(ClientToBroker::CancelAllJobs);
+        }
+        self.possibly_notify_all_jobs_complete();
+    }
+
+    fn cancel_job(&mut self, cjid: ClientJobId) {
+        // The handle will be missing if the job has already completed. Otherwise, dropping it tells
+        // whoever is waiting on the job that it was canceled.
+        if self.job_handles.remove(&cjid).is_none() {
+            return;
+        }
+        if self.standalone {
+            self.deps
+                .send_message_to_local_worker(maelstrom_worker::dispatcher::Message::Broker(
+                    BrokerToWorker::CancelJob(JobId {
+                        cid: ClientId::from(0),
+                        cjid,
+                    }),
+                ));
+            if self.counts[JobState::Pending] > 0 {
+                self.counts[JobState::Pending] -= 1;
+            } else {
+                self.counts[JobState::Running] -= 1;
+            }
+        } else {
+            self.deps
+                .send_message_to_broker(ClientToBroker::CancelJob(cjid));
+        }
+        self.possibly_notify_all_jobs_complete();
+    }
+
+    fn ack_job_output(&mut self, cjid: ClientJobId, bytes: u32) {
+        // Once the job has completed or been canceled, nothing is waiting on the ack.
+        if !self.job_handles.contains_key(&cjid) {
+            return;
+        }
+        if self.standalone {
+            self.deps
+                .send_message_to_local_worker(maelstrom_worker::dispatcher::Message::Broker(
+                    BrokerToWorker::JobOutputAck(
+                        JobId {
+                            cid: ClientId::from(0),
+                            cjid,
+                        },
+                        bytes,
+                    ),
+                ));
+        } else {
+            self.deps
+                .send_message_to_broker(ClientToBroker::JobOutputAck(cjid, bytes));
+        }
+    }
+
     fn receive_message(&mut self, message: Message<DepsT>) {
         match message {
             Message::AddArtifact(path, digest) => {
@@ -112,7 +253,7 @@ impl<DepsT: Deps> Router<DepsT> {
                                 cid: ClientId::from(0),
                                 cjid,
                             },
-                            spec,
+                            Box::new(spec),
                         )),
                     );
                 } else {
@@ -130,6 +271,48 @@ impl<DepsT: Deps> Router<DepsT> {
                         .send_message_to_broker(ClientToBroker::JobStateCountsRequest);
                 }
             }
+            Message::GetWorkerDistribution(handle) => {
+                if self.standalone {
+                    // All jobs are run on the local worker, which we report as worker 0.
+                    assert!(self.worker_distribution_handles.is_empty());
+                    let completed = self.counts[JobState::Complete];
+                    let distribution = (completed > 0)
+                        .then_some((WorkerId::from(0), completed))
+                        .into_iter()
+                        .collect();
+                    self.deps.worker_distribution(handle, distribution);
+                } else {
+                    self.worker_distribution_handles.push_back(handle);
+                    self.deps
+                        .send_message_to_broker(ClientToBroker::WorkerDistributionRequest);
+                }
+            }
+            Message::GetWorkerCapabilities(handle) => {
+                if self.standalone {
+                    // As with the distribution, the local worker is reported as worker 0.
+                    assert!(self.worker_capabilities_handles.is_empty());
+                    let capabilities = HashMap::from([(
+                        WorkerId::from(0),
+                        self.local_worker_capabilities.clone(),
+                    )]);
+                    self.deps.worker_capabilities(handle, capabilities);
+                } else {
+                    self.worker_capabilities_handles.push_back(handle);
+                    self.deps
+                        .send_message_to_broker(ClientToBroker::WorkerCapabilitiesRequest);
+                }
+            }
+            Message::GetArtifacts(handle) => {
+                if self.standalone {
+                    // There is no broker, and so no broker cache to report on.
+                    assert!(self.artifacts_handles.is_empty());
+                    self.deps.artifacts(handle, vec![]);
+                } else {
+                    self.artifacts_handles.push_back(handle);
+                    self.deps
+                        .send_message_to_broker(ClientToBroker::ArtifactsRequest);
+                }
+            }
             Message::NotifyWhenAllJobsComplete(handle) => {
                 if self.job_handles.is_empty() {
                     assert!(self.all_jobs_complete_handles.is_empty());
@@ -138,10 +321,21 @@ impl<DepsT: Deps> Router<DepsT> {
                     self.all_jobs_complete_handles.push(handle);
                 }
             }
+            Message::CancelAllJobs => self.cancel_all_jobs(),
+            Message::CancelJob(cjid) => self.cancel_job(cjid),
+            Message::AckJobOutput(cjid, bytes) => self.ack_job_output(cjid, bytes),
             Message::Broker(BrokerToClient::JobResponse(cjid, result)) => {
                 assert!(!self.standalone);
                 self.receive_job_response(cjid, result);
             }
+            Message::Broker(BrokerToClient::JobRunning(cjid)) => {
+                assert!(!self.standalone);
+                self.receive_job_running(cjid);
+            }
+            Message::Broker(BrokerToClient::JobOutput(cjid, stream, output)) => {
+                assert!(!self.standalone);
+                self.receive_job_output(cjid, stream, output);
+            }
             Message::Broker(BrokerToClient::TransferArtifact(digest)) => {
                 assert!(!self.standalone);
                 let path = self.artifacts.get(&digest).unwrap_or_else(|| {
@@ -157,8 +351,35 @@ impl<DepsT: Deps> Router<DepsT> {
                 self.deps
                     .job_state_counts(self.job_state_counts_handles.pop_front().unwrap(), counts);
             }
-            Message::LocalWorker(WorkerToBroker(jid, result)) => {
+            Message::Broker(BrokerToClient::WorkerDistributionResponse(distribution)) => {
+                assert!(!self.standalone);
+                self.deps.worker_distribution(
+                    self.worker_distribution_handles.pop_front().unwrap(),
+                    distribution,
+                );
+            }
+            Message::Broker(BrokerToClient::WorkerCapabilitiesResponse(capabilities)) => {
+                assert!(!self.standalone);
+                self.deps.worker_capabilities(
+                    self.worker_capabilities_handles.pop_front().unwrap(),
+                    capabilities,
+                );
+            }
+            Message::Broker(BrokerToClient::ArtifactsResponse(artifacts)) => {
+                assert!(!self.standalone);
+                self.deps
+                    .artifacts(self.artifacts_handles.pop_front().unwrap(), artifacts);
+            }
+            Message::Broker(BrokerToClient::TooManyRequests) => {
+                // The broker will get to our requests eventually, so there's nothing to do.
+                assert!(!self.standalone);
+            }
+            Message::LocalWorker(WorkerToBroker::JobResponse(jid, result)) => {
                 assert!(self.standalone);
+                if !self.job_handles.contains_key(&jid.cjid) {
+                    // The job was canceled, and has already been taken out of the counts.
+                    return;
+                }
                 if self.counts[JobState::Pending] > 0 {
                     self.counts[JobState::Pending] -= 1;
                 } else {
@@ -167,6 +388,14 @@ impl<DepsT: Deps> Router<DepsT> {
                 self.counts[JobState::Complete] += 1;
                 self.receive_job_response(jid.cjid, result);
             }
+            Message::LocalWorker(WorkerToBroker::JobRunning(jid)) => {
+                assert!(self.standalone);
+                self.receive_job_running(jid.cjid);
+            }
+            Message::LocalWorker(WorkerToBroker::JobOutput(jid, stream, output)) => {
+                assert!(self.standalone);
+                self.receive_job_output(jid.cjid, stream, output);
+            }
             Message::LocalWorkerStartArtifactFetch(digest, path) => {
                 assert!(self.standalone);
                 let response = maelstrom_worker::dispatcher::Message::ArtifactFetchCompleted(
@@ -206,11 +435,57 @@ impl Adapter {
     }
 }
 
+/// Called with each chunk of a job's output as it arrives, for jobs that stream their output. Each
+/// chunk must be acknowledged with [`Message::AckJobOutput`] once it has been consumed, or the job
+/// will eventually stop being able to send more.
+pub type JobOutputHandler = Box<dyn FnMut(ClientJobId, JobOutputStream, Vec<u8>) + Send + Sync>;
+
+/// Called with the job's id once the job starts running.
+pub type JobRunningHandler = Box<dyn FnOnce(ClientJobId) + Send + Sync>;
+
+pub struct JobHandle {
+    done: oneshot::Sender<(ClientJobId, JobOutcomeResult)>,
+    running: Option<JobRunningHandler>,
+    output: Option<JobOutputHandler>,
+}
+
+impl JobHandle {
+    pub fn new(
+        done: oneshot::Sender<(ClientJobId, JobOutcomeResult)>,
+        running: Option<JobRunningHandler>,
+        output: Option<JobOutputHandler>,
+    ) -> Self {
+        Self {
+            done,
+            running,
+            output,
+        }
+    }
+}
+
 impl Deps for Adapter {
-    type JobHandle = oneshot::Sender<(ClientJobId, JobOutcomeResult)>;
+    type JobHandle = JobHandle;
 
     fn job_done(&self, handle: Self::JobHandle, cjid: ClientJobId, result: JobOutcomeResult) {
-        handle.send((cjid, result)).ok();
+        handle.done.send((cjid, result)).ok();
+    }
+
+    fn job_running(&self, handle: &mut Self::JobHandle, cjid: ClientJobId) {
+        if let Some(running_handler) = handle.running.take() {
+            running_handler(cjid);
+        }
+    }
+
+    fn job_output(
+        &self,
+        handle: &mut Self::JobHandle,
+        cjid: ClientJobId,
+        stream: JobOutputStream,
+        output: Vec<u8>,
+    ) {
+        if let Some(output_handler) = &mut handle.output {
+            output_handler(cjid, stream, output);
+        }
     }
 
     type JobStateCountsHandle = oneshot::Sender<JobStateCounts>;
@@ -219,6 +494,32 @@ impl Deps for Adapter {
         handle.send(counts).ok();
     }
 
+    type WorkerDistributionHandle = oneshot::Sender<WorkerDistribution>;
+
+    fn worker_distribution(
+        &self,
+        handle: Self::WorkerDistributionHandle,
+        distribution: WorkerDistribution,
+    ) {
+        handle.send(distribution).ok();
+    }
+
+    type WorkerCapabilitiesHandle = oneshot::Sender<WorkerCapabilitiesMap>;
+
+    fn worker_capabilities(
+        &self,
+        handle: Self::WorkerCapabilitiesHandle,
+        capabilities: WorkerCapabilitiesMap,
+    ) {
+        handle.send(capabilities).ok();
+    }
+
+    type ArtifactsHandle = oneshot::Sender<Vec<CachedArtifact>>;
+
+    fn artifacts(&self, handle: Self::ArtifactsHandle, artifacts: Vec<CachedArtifact>) {
+        handle.send(artifacts).ok();
+    }
+
     type AllJobsCompleteHandle = oneshot::Sender<()>;
     fn all_jobs_complete(&self, handle: Self::AllJobsCompleteHandle) {
         handle.send(()).ok();
@@ -252,17 +553,19 @@ pub fn channel() -> (Sender, Receiver) {
     mpsc::unbounded_channel()
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn start_task(
     join_set: &mut JoinSet<Result<()>>,
     standalone: bool,
     slots: Slots,
+    local_worker_capabilities: WorkerCapabilities,
     receiver: Receiver,
     broker_sender: UnboundedSender<ClientToBroker>,
     artifact_pusher_sender: artifact_pusher::Sender,
     local_worker_sender: maelstrom_worker::DispatcherSender,
 ) {
     let adapter = Adapter::new(broker_sender, artifact_pusher_sender, local_worker_sender);
-    let mut router = Router::new(adapter, standalone, slots);
+    let mut router = Router::new(adapter, standalone, slots, local_worker_capabilities);
     join_set.spawn(sync::channel_reader(receiver, move |msg| {
         router.receive_message(msg)
     }));
@@ -1,10 +1,13 @@
 use crate::client::Client;
 use anyhow::Result;
+use maelstrom_base::{ClientJobId, JobOutputStream};
 use maelstrom_client_base::{
-    proto::{self, client_process_server::ClientProcess},
+    proto::{self, add_job_streaming_output_response, client_process_server::ClientProcess},
     IntoProtoBuf, IntoResult, TryFromProtoBuf,
 };
-use std::{path::PathBuf, result, sync::Arc};
+use std::{path::PathBuf, pin::Pin, result, sync::Arc};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::UnboundedReceiverStream, Stream, StreamExt as _};
 use tonic::{Code, Request, Response, Status};
 
 type TonicResult<T> = result::Result<T, Status>;
@@ -35,6 +38,20 @@ impl<T> ResultExt<T> for Result<T> {
     }
 }
 
+/// The status returned for a job that was canceled before it completed. The client uses this to
+/// tell cancellation apart from errors.
+fn job_canceled_status() -> Status {
+    Status::cancelled("job canceled")
+}
+
+fn add_job_streaming_output_response(
+    response: add_job_streaming_output_response::Response,
+) -> TonicResult<proto::AddJobStreamingOutputResponse> {
+    Ok(proto::AddJobStreamingOutputResponse {
+        response: Some(response),
+    })
+}
+
 #[allow(clippy::unit_arg)]
 #[tonic::async_trait]
 impl ClientProcess for Handler {
@@ -50,6 +67,9 @@ impl ClientProcess for Handler {
                     TryFromProtoBuf::try_from_proto_buf(request.cache_size)?,
                     TryFromProtoBuf::try_from_proto_buf(request.inline_limit)?,
                     TryFromProtoBuf::try_from_proto_buf(request.slots)?,
+                    TryFromProtoBuf::try_from_proto_buf(request.hashing_threads)?,
+                    TryFromProtoBuf::try_from_proto_buf(request.local_worker_idle_timeout)?,
+                    request.canonicalize_layer_order,
                 )
                 .await
                 .map(IntoProtoBuf::into_proto_buf)
@@ -115,16 +135,99 @@ impl ClientProcess for Handler {
         &self,
         request: Request<proto::AddJobRequest>,
     ) -> TonicResponse<proto::AddJobResponse> {
+        let res = async {
+            let spec = request.into_inner().into_result()?;
+            let spec = TryFromProtoBuf::try_from_proto_buf(spec)?;
+            self.client.run_job(spec, None, None).await
+        }
+        .await;
+        match res {
+            Ok(Some((cjid, res))) => Ok(Response::new(proto::AddJobResponse {
+                client_job_id: cjid.into_proto_buf(),
+                result: Some(res.into_proto_buf()),
+            })),
+            Ok(None) => Err(job_canceled_status()),
+            Err(e) => Err(Status::new(Code::Unknown, format!("{e:?}"))),
+        }
+    }
+
+    type AddJobStreamingOutputStream =
+        Pin<Box<dyn Stream<Item = TonicResult<proto::AddJobStreamingOutputResponse>> + Send>>;
+
+    async fn add_job_streaming_output(
+        &self,
+        request: Request<proto::AddJobRequest>,
+    ) -> TonicResponse<Self::AddJobStreamingOutputStream> {
         async {
             let spec = request.into_inner().into_result()?;
             let spec = TryFromProtoBuf::try_from_proto_buf(spec)?;
-            self.client
-                .run_job(spec)
-                .await
-                .map(|(cjid, res)| proto::AddJobResponse {
-                    client_job_id: cjid.into_proto_buf(),
-                    result: Some(res.into_proto_buf()),
-                })
+            // Each chunk of output is sent along with the ack to send once tonic has taken it off
+            // of the stream. tonic only does that as fast as the client reads the response, so
+            // this is what keeps the output flowing no faster than the client can consume it.
+            let (sender, receiver) = mpsc::unbounded_channel::<(
+                TonicResult<proto::AddJobStreamingOutputResponse>,
+                Option<(ClientJobId, u32)>,
+            )>();
+
+            // The router drops the output handler, and with it this sender, when the job
+            // completes. The job's result is then the last thing sent on the stream.
+            let output_sender = sender.clone();
+            let output_handler =
+                move |cjid: ClientJobId, stream: JobOutputStream, output: Vec<u8>| {
+                    let ack = (cjid, output.len().try_into().unwrap());
+                    let _ = output_sender.send((
+                        add_job_streaming_output_response(
+                            add_job_streaming_output_response::Response::Output(proto::JobOutput {
+                                stream: stream.into_proto_buf(),
+                                output,
+                            }),
+                        ),
+                        Some(ack),
+                    ));
+                };
+
+            let running_sender = sender.clone();
+            let running_handler = move |cjid: ClientJobId| {
+                let _ = running_sender.send((
+                    add_job_streaming_output_response(
+                        add_job_streaming_output_response::Response::Running(cjid.into_proto_buf()),
+                    ),
+                    None,
+                ));
+            };
+
+            let client = self.client.clone();
+            let ack_client = self.client.clone();
+            tokio::spawn(async move {
+                let response = match client
+                    .run_job(
+                        spec,
+                        Some(Box::new(running_handler)),
+                        Some(Box::new(output_handler)),
+                    )
+                    .await
+                {
+                    Ok(Some((cjid, res))) => add_job_streaming_output_response(
+                        add_job_streaming_output_response::Response::Done(proto::AddJobResponse {
+                            client_job_id: cjid.into_proto_buf(),
+                            result: Some(res.into_proto_buf()),
+                        }),
+                    ),
+                    Ok(None) => Err(job_canceled_status()),
+                    Err(e) => Err(Status::new(Code::Unknown, format!("{e:?}"))),
+                };
+                let _ = sender.send((response, None));
+            });
+
+            Ok(Box::pin(
+                UnboundedReceiverStream::new(receiver).map(move |(response, ack)| {
+                    if let Some((cjid, bytes)) = ack {
+                        // If the client has gone away, so has the job.
+                        let _ = ack_client.ack_job_output(cjid, bytes);
+                    }
+                    response
+                }),
+            ) as Self::AddJobStreamingOutputStream)
         }
         .await
         .map_to_tonic()
@@ -141,6 +244,27 @@ impl ClientProcess for Handler {
             .map_to_tonic()
     }
 
+    async fn cancel_job(
+        &self,
+        request: Request<proto::CancelJobRequest>,
+    ) -> TonicResponse<proto::Void> {
+        async {
+            let cjid = TryFromProtoBuf::try_from_proto_buf(request.into_inner().client_job_id)?;
+            self.client.cancel_job(cjid).await
+        }
+        .await
+        .map(IntoProtoBuf::into_proto_buf)
+        .map_to_tonic()
+    }
+
+    async fn cancel_all_jobs(&self, _request: Request<proto::Void>) -> TonicResponse<proto::Void> {
+        self.client
+            .cancel_all_jobs()
+            .await
+            .map(IntoProtoBuf::into_proto_buf)
+            .map_to_tonic()
+    }
+
     async fn get_job_state_counts(
         &self,
         _request: Request<proto::Void>,
@@ -154,6 +278,45 @@ impl ClientProcess for Handler {
             .map_to_tonic()
     }
 
+    async fn get_worker_distribution(
+        &self,
+        _request: Request<proto::Void>,
+    ) -> TonicResponse<proto::GetWorkerDistributionResponse> {
+        self.client
+            .get_worker_distribution()
+            .await
+            .map(|distribution| proto::GetWorkerDistributionResponse {
+                distribution: distribution.into_proto_buf(),
+            })
+            .map_to_tonic()
+    }
+
+    async fn get_worker_capabilities(
+        &self,
+        _request: Request<proto::Void>,
+    ) -> TonicResponse<proto::GetWorkerCapabilitiesResponse> {
+        self.client
+            .get_worker_capabilities()
+            .await
+            .map(|capabilities| proto::GetWorkerCapabilitiesResponse {
+                capabilities: capabilities.into_proto_buf(),
+            })
+            .map_to_tonic()
+    }
+
+    async fn list_artifacts(
+        &self,
+        _request: Request<proto::Void>,
+    ) -> TonicResponse<proto::ListArtifactsResponse> {
+        self.client
+            .list_artifacts()
+            .await
+            .map(|artifacts| proto::ListArtifactsResponse {
+                artifacts: artifacts.into_proto_buf(),
+            })
+            .map_to_tonic()
+    }
+
     async fn get_artifact_upload_progress(
         &self,
         _request: Request<proto::Void>,
@@ -2,7 +2,9 @@ pub use maelstrom_client_base::{spec, ArtifactUploadProgress, MANIFEST_DIR};
 
 use anyhow::{anyhow, bail, Context as _, Result};
 use maelstrom_base::{
-    stats::JobStateCounts, ArtifactType, ClientJobId, JobOutcomeResult, JobSpec, Sha256Digest,
+    stats::{CachedArtifact, JobStateCounts, WorkerDistribution},
+    ArtifactType, ClientJobId, JobOutcomeResult, JobOutputStream, JobSpec, Sha256Digest,
+    WorkerCapabilitiesMap,
 };
 use maelstrom_client_base::{
     proto::{self, client_process_client::ClientProcessClient},
@@ -10,21 +12,26 @@ use maelstrom_client_base::{
 };
 use maelstrom_container::ContainerImage;
 use maelstrom_util::{
-    config::common::{BrokerAddr, CacheSize, InlineLimit, LogLevel, Slots},
+    config::common::{
+        BrokerAddr, CacheSize, DispatcherThreads, HashingThreads, InlineLimit, LogLevel, Slots,
+    },
     log::LoggerFactory,
 };
 use spec::Layer;
 use std::os::linux::net::SocketAddrExt as _;
 use std::{
     future::Future,
-    io::{BufRead as _, BufReader},
+    io::{self, BufRead as _, BufReader, Read},
     os::unix::net::{SocketAddr, UnixStream},
-    path::Path,
+    path::{Path, PathBuf},
     pin::Pin,
     process,
     process::{Command, Stdio},
+    sync::Mutex,
     thread,
+    time::{Duration, Instant},
 };
+use tempfile::TempDir;
 use xdg::BaseDirectories;
 
 type BoxedFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
@@ -37,16 +44,57 @@ type RequestFn = Box<
 type RequestReceiver = tokio::sync::mpsc::UnboundedReceiver<RequestFn>;
 type RequestSender = tokio::sync::mpsc::UnboundedSender<RequestFn>;
 
+/// How long dropping a [`Client`] waits for the background process to exit before killing it.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often we check whether the dispatcher or background process has finished while shutting
+/// down.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 type TonicResult<T> = std::result::Result<T, tonic::Status>;
 type TonicResponse<T> = TonicResult<tonic::Response<T>>;
 
-#[tokio::main]
-async fn run_dispatcher(std_sock: UnixStream, mut requester: RequestReceiver) -> Result<()> {
+/// Build the runtime that the dispatcher runs on, with the given number of worker threads.
+fn dispatcher_runtime_builder(threads: DispatcherThreads) -> tokio::runtime::Builder {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder
+        .worker_threads(threads.into_inner().into())
+        .enable_all();
+    builder
+}
+
+fn run_dispatcher(
+    std_sock: UnixStream,
+    requester: RequestReceiver,
+    threads: DispatcherThreads,
+    keep_alive_interval: Option<Duration>,
+) -> Result<()> {
+    dispatcher_runtime_builder(threads)
+        .build()?
+        .block_on(dispatcher_main(std_sock, requester, keep_alive_interval))
+}
+
+/// Connect to the client process over `std_sock` and send it the requests read from `requester`.
+///
+/// If `keep_alive_interval` is given, an HTTP/2 ping is sent that often, even when no requests are
+/// outstanding. If the client process doesn't answer one, the connection is considered dead and
+/// any outstanding requests fail, instead of waiting forever.
+async fn dispatcher_main(
+    std_sock: UnixStream,
+    mut requester: RequestReceiver,
+    keep_alive_interval: Option<Duration>,
+) -> Result<()> {
     std_sock.set_nonblocking(true)?;
     let sock = tokio::net::UnixStream::from_std(std_sock.try_clone()?)?;
     let mut closure =
         Some(move || async move { std::result::Result::<_, tower::BoxError>::Ok(sock) });
-    let channel = tonic::transport::Endpoint::try_from("http://[::]")?
+    let mut endpoint = tonic::transport::Endpoint::try_from("http://[::]")?;
+    if let Some(interval) = keep_alive_interval {
+        endpoint = endpoint
+            .http2_keep_alive_interval(interval)
+            .keep_alive_while_idle(true);
+    }
+    let channel = endpoint
         .connect_with_connector(tower::service_fn(move |_| {
             (closure.take().expect("unexpected reconnect"))()
         }))
@@ -70,9 +118,24 @@ fn print_error(label: &str, res: Result<()>) {
 struct ClientBgHandle(maelstrom_linux::Pid);
 
 impl ClientBgHandle {
-    fn wait(&mut self) -> Result<()> {
+    /// Wait for the process to exit until `deadline`. If it hasn't exited by then, kill it and
+    /// return an error.
+    fn wait_until(&mut self, deadline: Instant) -> Result<()> {
+        loop {
+            let status = maelstrom_linux::waitpid_nohang(self.0)
+                .map_err(|e| anyhow!("waitpid failed: {e}"))?;
+            if status.is_some() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+        maelstrom_linux::kill(self.0, maelstrom_linux::Signal::KILL)
+            .map_err(|e| anyhow!("kill failed: {e}"))?;
         maelstrom_linux::waitpid(self.0).map_err(|e| anyhow!("waitpid failed: {e}"))?;
-        Ok(())
+        bail!("client process didn't exit in time and was killed")
     }
 }
 
@@ -130,22 +193,15 @@ impl ClientBgProcess {
         self.sock.take().unwrap()
     }
 
-    fn wait(&mut self) -> Result<()> {
-        self.handle.wait()
+    fn wait_until(&mut self, deadline: Instant) -> Result<()> {
+        self.handle.wait_until(deadline)
     }
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
         slog::debug!(self.log, "dropping Client");
-        drop(self.requester.take());
-        slog::debug!(self.log, "Client::drop: waiting for dispatcher");
-        print_error(
-            "dispatcher",
-            self.dispatcher_handle.take().unwrap().join().unwrap(),
-        );
-        slog::debug!(self.log, "Client::drop: waiting for child process");
-        self.process_handle.wait().unwrap();
+        print_error("shutdown", self.shutdown_inner(DEFAULT_SHUTDOWN_TIMEOUT));
     }
 }
 
@@ -153,6 +209,14 @@ pub struct Client {
     requester: Option<RequestSender>,
     process_handle: ClientBgProcess,
     dispatcher_handle: Option<thread::JoinHandle<Result<()>>>,
+    /// How long to wait for the response to a control request, like canceling a job or getting
+    /// stats, before canceling it. Requests that do work which can legitimately take a long time,
+    /// like adding artifacts, layers, container images, and jobs, aren't limited.
+    request_timeout: Option<Duration>,
+    cache_dir: PathBuf,
+    /// Where the contents given to [`Client::add_artifact_from_reader`] are written. It is created
+    /// the first time it's needed, and removed when the client is dropped.
+    reader_artifacts: Mutex<Option<TempDir>>,
     log: slog::Logger,
 }
 
@@ -165,6 +229,36 @@ fn map_tonic_error(error: tonic::Status) -> anyhow::Error {
     }
 }
 
+/// Whether an error returned by a [`Client`] method might go away if the request is tried again.
+/// This is the case for I/O and transport errors talking to the client process. Errors returned by
+/// the client process itself, or from using a client whose connection has shut down, aren't.
+pub fn is_transient_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<io::Error>()
+            || cause.is::<tonic::transport::Error>()
+            || cause
+                .downcast_ref::<tonic::Status>()
+                .is_some_and(|status| status.code() == tonic::Code::Unavailable)
+    })
+}
+
+/// Call a job's `handler` with the job's result, or with the error that kept us from getting it.
+/// If the job was canceled, the handler is dropped without being called.
+fn call_job_handler(
+    handler: impl FnOnce(Result<(ClientJobId, JobOutcomeResult)>) + Send + 'static,
+    result: Result<(ClientJobId, JobOutcomeResult)>,
+) {
+    let result = match result {
+        Ok(result) => Ok(result),
+        Err(err) => match err.downcast::<tonic::Status>() {
+            Ok(status) if status.code() == tonic::Code::Cancelled => return,
+            Ok(status) => Err(map_tonic_error(status)),
+            Err(err) => Err(err),
+        },
+    };
+    tokio::task::spawn_blocking(move || handler(result));
+}
+
 fn flatten_rpc_result<ProtRetT>(res: TonicResponse<ProtRetT>) -> Result<ProtRetT::Output>
 where
     ProtRetT: IntoResult,
@@ -172,6 +266,51 @@ where
     res.map_err(map_tonic_error)?.into_inner().into_result()
 }
 
+/// Hand a request to the dispatcher. The returned channel receives the response.
+///
+/// If `timeout` is given and the client process hasn't answered in that long, the request is
+/// canceled and the channel receives an error instead.
+fn send_request<BuilderT, FutureT, ProtRetT>(
+    requester: &RequestSender,
+    timeout: Option<Duration>,
+    builder: BuilderT,
+) -> Result<std::sync::mpsc::Receiver<Result<ProtRetT::Output>>>
+where
+    BuilderT: FnOnce(ClientProcessClient<tonic::transport::Channel>) -> FutureT,
+    BuilderT: Send + Sync + 'static,
+    FutureT: Future<Output = std::result::Result<tonic::Response<ProtRetT>, tonic::Status>> + Send,
+    ProtRetT: IntoResult,
+    ProtRetT::Output: Send + 'static,
+{
+    let (send, recv) = std::sync::mpsc::channel();
+    requester
+        .send(Box::new(move |client| {
+            Box::pin(async move {
+                let res = match timeout {
+                    // Dropping the RPC's future when the timeout fires resets its HTTP/2 stream,
+                    // which cancels the request in the client process.
+                    Some(timeout) => match tokio::time::timeout(timeout, builder(client)).await {
+                        Ok(res) => flatten_rpc_result(res),
+                        Err(_) => Err(anyhow!(
+                            "no RPC response from client process after {:.3}s",
+                            timeout.as_secs_f64()
+                        )),
+                    },
+                    None => flatten_rpc_result(builder(client).await),
+                };
+                let _ = send.send(res);
+            })
+        }))
+        .with_context(|| "sending RPC request to client process")?;
+    Ok(recv)
+}
+
+/// Wait for the response to a request sent with [`send_request`].
+fn receive_response<T>(recv: std::sync::mpsc::Receiver<Result<T>>) -> Result<T> {
+    recv.recv()
+        .with_context(|| "receiving RPC response from client process")?
+}
+
 impl Client {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -182,16 +321,27 @@ impl Client {
         cache_size: CacheSize,
         inline_limit: InlineLimit,
         slots: Slots,
+        hashing_threads: HashingThreads,
+        dispatcher_threads: DispatcherThreads,
+        local_worker_idle_timeout: Option<Duration>,
+        canonicalize_layer_order: bool,
+        keep_alive_interval: Option<Duration>,
+        request_timeout: Option<Duration>,
         log: slog::Logger,
     ) -> Result<Self> {
         let (send, recv) = tokio::sync::mpsc::unbounded_channel();
 
         let sock = process_handle.take_socket();
-        let dispatcher_handle = thread::spawn(move || run_dispatcher(sock, recv));
+        let dispatcher_handle = thread::spawn(move || {
+            run_dispatcher(sock, recv, dispatcher_threads, keep_alive_interval)
+        });
         let s = Self {
             requester: Some(send),
+            request_timeout,
             process_handle,
             dispatcher_handle: Some(dispatcher_handle),
+            cache_dir: cache_dir.as_ref().to_owned(),
+            reader_artifacts: Mutex::new(None),
             log,
         };
         slog::debug!(s.log, "finding maelstrom container dir");
@@ -207,6 +357,12 @@ impl Client {
             "cache_size" => ?cache_size,
             "inline_limit" => ?inline_limit,
             "slots" => ?slots,
+            "hashing_threads" => ?hashing_threads,
+            "dispatcher_threads" => ?dispatcher_threads,
+            "local_worker_idle_timeout" => ?local_worker_idle_timeout,
+            "canonicalize_layer_order" => canonicalize_layer_order,
+            "keep_alive_interval" => ?keep_alive_interval,
+            "request_timeout" => ?request_timeout,
         );
         let msg = proto::StartRequest {
             broker_addr: broker_addr.into_proto_buf(),
@@ -216,6 +372,9 @@ impl Client {
             cache_size: cache_size.into_proto_buf(),
             inline_limit: inline_limit.into_proto_buf(),
             slots: slots.into_proto_buf(),
+            hashing_threads: hashing_threads.into_proto_buf(),
+            local_worker_idle_timeout: local_worker_idle_timeout.into_proto_buf(),
+            canonicalize_layer_order,
         };
         s.send_sync(|mut client| async move { client.start(msg).await })?;
         slog::debug!(s.log, "client completed start");
@@ -223,8 +382,37 @@ impl Client {
         Ok(s)
     }
 
+    /// Shut down the client, waiting up to `timeout` for the dispatcher and the background
+    /// process to finish. If the background process is still running after `timeout`, it is
+    /// killed and an error is returned.
+    pub fn shutdown(mut self, timeout: Duration) -> Result<()> {
+        self.shutdown_inner(timeout)
+    }
+
+    fn shutdown_inner(&mut self, timeout: Duration) -> Result<()> {
+        let Some(dispatcher_handle) = self.dispatcher_handle.take() else {
+            return Ok(());
+        };
+        let deadline = Instant::now() + timeout;
+        drop(self.requester.take());
+
+        slog::debug!(self.log, "Client::shutdown: waiting for dispatcher");
+        while !dispatcher_handle.is_finished() && Instant::now() < deadline {
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+        if dispatcher_handle.is_finished() {
+            print_error("dispatcher", dispatcher_handle.join().unwrap());
+        } else {
+            slog::warn!(self.log, "dispatcher didn't finish in time");
+        }
+
+        slog::debug!(self.log, "Client::shutdown: waiting for child process");
+        self.process_handle.wait_until(deadline)
+    }
+
     fn send_async<BuilderT, FutureT, ProtRetT>(
         &self,
+        timeout: Option<Duration>,
         builder: BuilderT,
     ) -> Result<std::sync::mpsc::Receiver<Result<ProtRetT::Output>>>
     where
@@ -235,17 +423,7 @@ impl Client {
         ProtRetT: IntoResult,
         ProtRetT::Output: Send + 'static,
     {
-        let (send, recv) = std::sync::mpsc::channel();
-        self.requester
-            .as_ref()
-            .unwrap()
-            .send(Box::new(move |client| {
-                Box::pin(async move {
-                    let _ = send.send(flatten_rpc_result(builder(client).await));
-                })
-            }))
-            .with_context(|| "sending RPC request to client process")?;
-        Ok(recv)
+        send_request(self.requester.as_ref().unwrap(), timeout, builder)
     }
 
     fn send_sync<BuilderT, FutureT, ProtRetT>(&self, builder: BuilderT) -> Result<ProtRetT::Output>
@@ -257,9 +435,24 @@ impl Client {
         ProtRetT: IntoResult,
         ProtRetT::Output: Send + 'static,
     {
-        self.send_async(builder)?
-            .recv()
-            .with_context(|| "receiving RPC response from client process")?
+        receive_response(self.send_async(None, builder)?)
+    }
+
+    /// Like [`Self::send_sync`], but for short control requests. If the response doesn't come
+    /// within the request timeout, the request is canceled and an error is returned.
+    fn send_control<BuilderT, FutureT, ProtRetT>(
+        &self,
+        builder: BuilderT,
+    ) -> Result<ProtRetT::Output>
+    where
+        BuilderT: FnOnce(ClientProcessClient<tonic::transport::Channel>) -> FutureT,
+        BuilderT: Send + Sync + 'static,
+        FutureT:
+            Future<Output = std::result::Result<tonic::Response<ProtRetT>, tonic::Status>> + Send,
+        ProtRetT: IntoResult,
+        ProtRetT::Output: Send + 'static,
+    {
+        receive_response(self.send_async(self.request_timeout, builder)?)
     }
 
     pub fn add_artifact(&self, path: &Path) -> Result<Sha256Digest> {
@@ -274,6 +467,28 @@ impl Client {
         Ok(digest.try_into()?)
     }
 
+    /// Add an artifact with the contents read from `reader`. The contents are written to a file in
+    /// the cache directory, which is kept until the client is dropped.
+    pub fn add_artifact_from_reader(&self, mut reader: impl Read) -> Result<Sha256Digest> {
+        let path = {
+            let mut dir = self.reader_artifacts.lock().unwrap();
+            if dir.is_none() {
+                *dir = Some(
+                    tempfile::Builder::new()
+                        .prefix("reader-artifacts")
+                        .tempdir_in(&self.cache_dir)
+                        .with_context(|| "creating directory for artifacts read from readers")?,
+                );
+            }
+            let (mut file, path) = tempfile::NamedTempFile::new_in(dir.as_ref().unwrap())?
+                .keep()
+                .with_context(|| "creating file for artifact read from reader")?;
+            io::copy(&mut reader, &mut file).with_context(|| "reading artifact contents")?;
+            path
+        };
+        self.add_artifact(&path)
+    }
+
     pub fn add_layer(&self, layer: Layer) -> Result<(Sha256Digest, ArtifactType)> {
         slog::debug!(self.log, "client.add_layer"; "layer" => ?layer);
         let msg = proto::AddLayerRequest {
@@ -300,10 +515,13 @@ impl Client {
         TryFromProtoBuf::try_from_proto_buf(img)
     }
 
+    /// Add a job. Once the job completes, `handler` is called with its result. If we can't get
+    /// the result because of an error talking to the client process, `handler` is called with the
+    /// error instead.
     pub fn add_job(
         &self,
         spec: JobSpec,
-        handler: impl FnOnce(ClientJobId, JobOutcomeResult) + Send + Sync + 'static,
+        handler: impl FnOnce(Result<(ClientJobId, JobOutcomeResult)>) + Send + Sync + 'static,
     ) -> Result<()> {
         let msg = proto::AddJobRequest {
             spec: Some(spec.clone().into_proto_buf()),
@@ -324,33 +542,282 @@ impl Client {
                             TryFromProtoBuf::try_from_proto_buf(result)?,
                         ))
                     };
-                    if let Ok((cjid, result)) = inner.await {
-                        tokio::task::spawn_blocking(move || handler(cjid, result));
-                    }
+                    call_job_handler(handler, inner.await);
                 })
             }))?;
         Ok(())
     }
 
+    /// Like [`Self::add_job`], but the job's stdout and stderr are passed to `sink` in chunks as
+    /// the job produces them, instead of being collected into the job's result. This is meant for
+    /// jobs whose output is too large to hold in memory. The `stdout` and `stderr` in the result
+    /// passed to `handler` will be [`maelstrom_base::JobOutputResult::None`].
+    pub fn add_job_streaming_output(
+        &self,
+        mut spec: JobSpec,
+        sink: impl FnMut(JobOutputStream, &[u8]) + Send + Sync + 'static,
+        handler: impl FnOnce(Result<(ClientJobId, JobOutcomeResult)>) + Send + Sync + 'static,
+    ) -> Result<()> {
+        spec.stream_output = true;
+        self.add_job_with_events(spec, |_| {}, sink, handler)
+    }
+
+    /// Like [`Self::add_job`], but `running` is called with the job's id once the job starts
+    /// running on a worker. The id can be passed to [`Self::cancel_job`].
+    pub fn add_job_with_running_handler(
+        &self,
+        spec: JobSpec,
+        running: impl FnOnce(ClientJobId) + Send + Sync + 'static,
+        handler: impl FnOnce(Result<(ClientJobId, JobOutcomeResult)>) + Send + Sync + 'static,
+    ) -> Result<()> {
+        self.add_job_with_events(spec, running, |_, _| {}, handler)
+    }
+
+    /// Add a job using the streaming RPC, which tells us when the job starts running, and sends
+    /// the job's output as it is produced if [`JobSpec::stream_output`] is set.
+    fn add_job_with_events(
+        &self,
+        spec: JobSpec,
+        running: impl FnOnce(ClientJobId) + Send + Sync + 'static,
+        mut sink: impl FnMut(JobOutputStream, &[u8]) + Send + Sync + 'static,
+        handler: impl FnOnce(Result<(ClientJobId, JobOutcomeResult)>) + Send + Sync + 'static,
+    ) -> Result<()> {
+        let msg = proto::AddJobRequest {
+            spec: Some(spec.clone().into_proto_buf()),
+        };
+        self.requester
+            .as_ref()
+            .unwrap()
+            .send(Box::new(move |mut client| {
+                Box::pin(async move {
+                    let inner = async move {
+                        let mut running = Some(running);
+                        let mut stream = client.add_job_streaming_output(msg).await?.into_inner();
+                        while let Some(message) = stream.message().await? {
+                            match message.response {
+                                Some(
+                                    proto::add_job_streaming_output_response::Response::Running(
+                                        cjid,
+                                    ),
+                                ) => {
+                                    let cjid = TryFromProtoBuf::try_from_proto_buf(cjid)?;
+                                    if let Some(running) = running.take() {
+                                        tokio::task::block_in_place(|| running(cjid));
+                                    }
+                                }
+                                Some(
+                                    proto::add_job_streaming_output_response::Response::Output(
+                                        output,
+                                    ),
+                                ) => {
+                                    let stream =
+                                        TryFromProtoBuf::try_from_proto_buf(output.stream)?;
+                                    tokio::task::block_in_place(|| sink(stream, &output.output));
+                                }
+                                Some(proto::add_job_streaming_output_response::Response::Done(
+                                    res,
+                                )) => {
+                                    let result: proto::JobOutcomeResult = res
+                                        .result
+                                        .ok_or(anyhow!("malformed AddJobResponse"))
+                                        .with_context(|| format!("adding job {spec:#?}"))?;
+                                    return Result::<_, anyhow::Error>::Ok((
+                                        TryFromProtoBuf::try_from_proto_buf(res.client_job_id)?,
+                                        TryFromProtoBuf::try_from_proto_buf(result)?,
+                                    ));
+                                }
+                                None => bail!("malformed AddJobStreamingOutputResponse"),
+                            }
+                        }
+                        bail!("job output stream ended without a result")
+                    };
+                    call_job_handler(handler, inner.await);
+                })
+            }))?;
+        Ok(())
+    }
+
+    /// Cancel a job that has been added and hasn't completed yet. The job's handler won't be
+    /// called. This does nothing if the job has already completed.
+    pub fn cancel_job(&self, cjid: ClientJobId) -> Result<()> {
+        let msg = proto::CancelJobRequest {
+            client_job_id: cjid.into_proto_buf(),
+        };
+        self.send_control(move |mut client| async move { client.cancel_job(msg).await })
+            .with_context(|| format!("canceling job {cjid}"))
+    }
+
     pub fn wait_for_outstanding_jobs(&self) -> Result<()> {
         self.send_sync(move |mut client| async move {
             client.wait_for_outstanding_jobs(proto::Void {}).await
         })
-        .with_context(|| "waiting for outstanding jobs")?;
-        Ok(())
+        .with_context(|| "waiting for outstanding jobs")
+    }
+
+    /// Cancel all of the jobs that have been added and haven't completed yet, including ones
+    /// still waiting on artifacts. The handlers for the canceled jobs won't be called. This is
+    /// done in one request to the broker, so no job can slip through and start running while the
+    /// others are being canceled. The client stays connected and can keep adding jobs.
+    pub fn cancel_all(&self) -> Result<()> {
+        self.send_control(
+            move |mut client| async move { client.cancel_all_jobs(proto::Void {}).await },
+        )
+        .with_context(|| "canceling all jobs")
     }
 
     pub fn get_job_state_counts(&self) -> Result<JobStateCounts> {
-        self.send_sync(move |mut client| async move {
+        self.send_control(move |mut client| async move {
             let res = client.get_job_state_counts(proto::Void {}).await?;
             Ok(res.map(|v| TryFromProtoBuf::try_from_proto_buf(v.into_result()?)))
         })
     }
 
+    /// Get how many of this client's jobs have been completed by each worker.
+    pub fn get_worker_distribution(&self) -> Result<WorkerDistribution> {
+        self.send_control(move |mut client| async move {
+            let res = client.get_worker_distribution(proto::Void {}).await?;
+            Ok(res.map(|v| TryFromProtoBuf::try_from_proto_buf(v.into_result()?)))
+        })
+    }
+
+    /// Get the capabilities of each worker. In standalone mode, this is just the local worker.
+    pub fn get_worker_capabilities(&self) -> Result<WorkerCapabilitiesMap> {
+        self.send_control(move |mut client| async move {
+            let res = client.get_worker_capabilities(proto::Void {}).await?;
+            Ok(res.map(|v| TryFromProtoBuf::try_from_proto_buf(v.into_result()?)))
+        })
+    }
+
+    /// List the artifacts in the broker's cache, along with their sizes and refcounts. This is
+    /// meant for debugging the cache. In standalone mode, there is no broker, so this is empty.
+    pub fn list_artifacts(&self) -> Result<Vec<CachedArtifact>> {
+        self.send_control(move |mut client| async move {
+            let res = client.list_artifacts(proto::Void {}).await?;
+            Ok(res.map(|v| TryFromProtoBuf::try_from_proto_buf(v.into_result()?)))
+        })
+    }
+
     pub fn get_artifact_upload_progress(&self) -> Result<Vec<ArtifactUploadProgress>> {
-        self.send_sync(move |mut client| async move {
+        self.send_control(move |mut client| async move {
             let res = client.get_artifact_upload_progress(proto::Void {}).await?;
             Ok(res.map(|v| TryFromProtoBuf::try_from_proto_buf(v.into_result()?)))
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    };
+
+    fn started_worker_threads(threads: u16) -> usize {
+        let started = Arc::new(AtomicUsize::new(0));
+        let started_clone = started.clone();
+        let runtime = dispatcher_runtime_builder(threads.try_into().unwrap())
+            .on_thread_start(move || {
+                started_clone.fetch_add(1, Ordering::SeqCst);
+            })
+            .build()
+            .unwrap();
+        // Worker threads are spawned when the runtime is built, but they may not have run their
+        // start hook yet.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while started.load(Ordering::SeqCst) < threads.into() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(runtime);
+        started.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn dispatcher_runtime_uses_configured_worker_threads() {
+        assert_eq!(started_worker_threads(1), 1);
+        assert_eq!(started_worker_threads(3), 3);
+    }
+
+    #[test]
+    fn request_to_stalled_client_process_times_out() {
+        // Nothing ever reads from or writes to the other end of the socket, so the client process
+        // looks alive but never answers.
+        let (sock, _stalled) = UnixStream::pair().unwrap();
+        let (send, recv) = tokio::sync::mpsc::unbounded_channel();
+        thread::spawn(move || {
+            run_dispatcher(
+                sock,
+                recv,
+                1u16.try_into().unwrap(),
+                Some(Duration::from_secs(60)),
+            )
+        });
+
+        let start = Instant::now();
+        let response = send_request(
+            &send,
+            Some(Duration::from_millis(100)),
+            |mut client| async move { client.get_job_state_counts(proto::Void {}).await },
+        )
+        .unwrap();
+        let err = receive_response(response).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no RPC response from client process after 0.100s"
+        );
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn timed_out_request_is_canceled() {
+        struct SetOnDrop(Arc<AtomicBool>);
+
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let (sock, _stalled) = UnixStream::pair().unwrap();
+        let (send, recv) = tokio::sync::mpsc::unbounded_channel();
+        thread::spawn(move || run_dispatcher(sock, recv, 1u16.try_into().unwrap(), None));
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let guard = SetOnDrop(dropped.clone());
+        let response = send_request(
+            &send,
+            Some(Duration::from_millis(100)),
+            |mut client| async move {
+                let _guard = guard;
+                client.get_job_state_counts(proto::Void {}).await
+            },
+        )
+        .unwrap();
+        receive_response(response).unwrap_err();
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn stuck_background_process_killed_after_timeout() {
+        let child = Command::new("sleep").arg("1000").spawn().unwrap();
+        let mut handle = ClientBgHandle(child.into());
+        let start = Instant::now();
+        let err = handle
+            .wait_until(start + Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "client process didn't exit in time and was killed"
+        );
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn exited_background_process_not_killed() {
+        let child = Command::new("true").spawn().unwrap();
+        let mut handle = ClientBgHandle(child.into());
+        handle
+            .wait_until(Instant::now() + Duration::from_secs(10))
+            .unwrap();
+    }
+}
@@ -57,9 +57,15 @@ impl ClientFixture {
             None, /* broker_addr */
             &project_dir,
             &cache_dir,
-            "1mb".parse().unwrap(), /* cache_size */
-            "1mb".parse().unwrap(), /* inline_limit */
-            2u16.try_into().unwrap(),
+            "1mb".parse().unwrap(),   /* cache_size */
+            "1mb".parse().unwrap(),   /* inline_limit */
+            2u16.try_into().unwrap(), /* slots */
+            2u16.try_into().unwrap(), /* hashing_threads */
+            2u16.try_into().unwrap(), /* dispatcher_threads */
+            None,                     /* local_worker_idle_timeout */
+            false,                    /* canonicalize_layer_order */
+            None,                     /* keep_alive_interval */
+            None,                     /* request_timeout */
             log.clone(),
         )
         .unwrap();
@@ -123,10 +129,15 @@ impl ClientFixture {
             user: UserId::new(0),
             group: GroupId::new(0),
             timeout: None,
+            enable_init_process: false,
+            cpus: 0,
+            memory: 0,
+            stream_output: false,
+            priority: 0,
         };
         let (send, recv) = std::sync::mpsc::channel();
         self.client
-            .add_job(spec, move |_, outcome| send.send(outcome).unwrap())
+            .add_job(spec, move |res| send.send(res.unwrap().1).unwrap())
             .unwrap();
         let outcome = recv.recv().unwrap();
         let output = assert_matches!(
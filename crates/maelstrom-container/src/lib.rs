@@ -1,12 +1,14 @@
 pub use oci_spec::image::{Arch, Os};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context as _, Result};
 use anyhow_trace::anyhow_trace;
 use async_compression::tokio::bufread::GzipDecoder;
 use core::task::Poll;
 use futures::stream::TryStreamExt as _;
 use maelstrom_util::async_fs::{self as fs, Fs};
-use oci_spec::image::{Descriptor, ImageIndex, ImageManifest, Platform};
+use oci_spec::image::{
+    Descriptor, ImageIndex, ImageManifest, MediaType, Platform, ANNOTATION_REF_NAME,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::future::Future;
@@ -421,6 +423,136 @@ pub async fn download_image(
     })
 }
 
+/// Image names starting with this prefix refer to an OCI image layout directory on the local
+/// file system, instead of to an image in a registry. The rest of the name is the path to the
+/// directory, relative to the project directory.
+pub const OCI_LAYOUT_PREFIX: &str = "oci:";
+
+fn oci_layout_blob_path(layout_dir: &Path, digest: &str) -> Result<PathBuf> {
+    let (algorithm, encoded) = digest
+        .split_once(':')
+        .ok_or_else(|| anyhow!("malformed digest {digest:?}"))?;
+    Ok(layout_dir.join("blobs").join(algorithm).join(encoded))
+}
+
+#[anyhow_trace]
+async fn read_oci_layout_blob<T: DeserializeOwned>(
+    fs: &Fs,
+    layout_dir: &Path,
+    digest: &str,
+) -> Result<T> {
+    let contents = fs.read(oci_layout_blob_path(layout_dir, digest)?).await?;
+    let value = serde_json::from_slice(&contents)?;
+    Ok(value)
+}
+
+/// Find the digest of the image manifest to use in an OCI image layout.
+///
+/// If a manifest in the layout's index is annotated with `tag` as its reference name, that one is
+/// used. Otherwise, the manifest for the current platform is used, or the only manifest if there
+/// is just one. Nested indexes, like those written by `docker buildx`, are followed.
+#[anyhow_trace]
+pub async fn resolve_oci_layout_manifest(fs: &Fs, layout_dir: &Path, tag: &str) -> Result<String> {
+    let mut index: ImageIndex =
+        serde_json::from_slice(&fs.read(layout_dir.join("index.json")).await?)?;
+    let mut tag = Some(tag);
+    loop {
+        let manifests = index.manifests();
+        let current_platform = Platform::default();
+        let descriptor = tag
+            .and_then(|tag| {
+                manifests.iter().find(|des| {
+                    des.annotations()
+                        .as_ref()
+                        .and_then(|annotations| annotations.get(ANNOTATION_REF_NAME))
+                        .is_some_and(|ref_name| ref_name == tag)
+                })
+            })
+            .or_else(|| {
+                manifests
+                    .iter()
+                    .find(|des| des.platform().as_ref() == Some(&current_platform))
+            })
+            .or_else(|| (manifests.len() == 1).then(|| &manifests[0]))
+            .ok_or_else(|| {
+                anyhow!(
+                    "couldn't find an image manifest in OCI layout {}",
+                    layout_dir.display()
+                )
+            })?;
+        if descriptor.media_type() != &MediaType::ImageIndex {
+            return Ok(descriptor.digest().clone());
+        }
+        index = read_oci_layout_blob(fs, layout_dir, descriptor.digest()).await?;
+        // Reference names only apply to the top-level index.
+        tag = None;
+    }
+}
+
+#[anyhow_trace]
+async fn copy_oci_layout_layer(
+    fs: &Fs,
+    layout_dir: &Path,
+    layer: &Descriptor,
+    prog: impl ProgressTracker,
+    mut out: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let blob = fs
+        .open_file(oci_layout_blob_path(layout_dir, layer.digest())?)
+        .await?;
+    let blob = tokio::io::BufReader::new(ProgressTrackerStream::new(prog, blob));
+    match layer.media_type() {
+        MediaType::ImageLayer | MediaType::ImageLayerNonDistributable => {
+            let mut blob = blob;
+            tokio::io::copy(&mut blob, &mut out).await?;
+        }
+        MediaType::ImageLayerGzip | MediaType::ImageLayerNonDistributableGzip => {
+            tokio::io::copy(&mut GzipDecoder::new(blob), &mut out).await?;
+        }
+        MediaType::Other(other) if other.ends_with(".tar.gzip") => {
+            tokio::io::copy(&mut GzipDecoder::new(blob), &mut out).await?;
+        }
+        other => bail!("unsupported layer media type {other}"),
+    }
+    Ok(())
+}
+
+/// Copy the image with the given manifest digest out of an OCI image layout, decompressing its
+/// layers into `layer_dir`.
+#[anyhow_trace]
+pub async fn copy_oci_layout_image(
+    fs: &Fs,
+    name: &str,
+    layout_dir: &Path,
+    manifest_digest: &str,
+    layer_dir: impl AsRef<Path>,
+    prog: impl ProgressTracker,
+) -> Result<ContainerImage> {
+    let image: ImageManifest = read_oci_layout_blob(fs, layout_dir, manifest_digest).await?;
+    let config: oci_spec::image::ImageConfiguration =
+        read_oci_layout_blob(fs, layout_dir, image.config().digest()).await?;
+
+    let total_size: i64 = image.layers().iter().map(|l| l.size()).sum();
+    prog.set_length(total_size as u64);
+
+    let mut layers = vec![];
+    for (i, layer) in image.layers().iter().enumerate() {
+        let path = layer_dir.as_ref().join(format!("layer_{i}.tar"));
+        let mut file = fs.create_file(&path).await?;
+        copy_oci_layout_layer(fs, layout_dir, layer, prog.clone(), &mut file).await?;
+        file.flush().await?;
+        layers.push(path);
+    }
+
+    Ok(ContainerImage {
+        version: ContainerImageVersion::default(),
+        name: name.into(),
+        digest: manifest_digest.into(),
+        config: config.into(),
+        layers,
+    })
+}
+
 #[derive(Copy, Clone, Default, Debug, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
 #[repr(u32)]
 pub enum LockedContainerImageTagsVersion {
@@ -575,16 +707,30 @@ impl<ContainerImageDepotOpsT: ContainerImageDepotOps> ContainerImageDepot<Contai
         digest: &str,
         prog: impl ProgressTracker,
     ) -> Result<ContainerImage> {
+        self.populate_cache_dir(digest, |output_dir| async move {
+            self.ops
+                .download_image(name, digest, &output_dir, prog)
+                .await
+        })
+        .await
+    }
+
+    #[anyhow_trace]
+    async fn populate_cache_dir<FutureT>(
+        &self,
+        digest: &str,
+        populate: impl FnOnce(PathBuf) -> FutureT,
+    ) -> Result<ContainerImage>
+    where
+        FutureT: Future<Output = Result<ContainerImage>>,
+    {
         let output_dir = self.cache_dir.join(digest);
         if output_dir.exists() {
             self.fs.remove_dir_all(&output_dir).await?;
         }
         self.fs.create_dir(&output_dir).await?;
 
-        let img = self
-            .ops
-            .download_image(name, digest, &output_dir, prog)
-            .await?;
+        let img = populate(output_dir.clone()).await?;
         self.fs
             .write(
                 output_dir.join("config.json"),
@@ -624,6 +770,8 @@ impl<ContainerImageDepotOpsT: ContainerImageDepotOps> ContainerImageDepot<Contai
         })
     }
 
+    /// Get the container image with the given name and tag. The tag may instead be a digest of the
+    /// form `sha256:<hex>`, in which case that exact image is used.
     #[anyhow_trace]
     pub async fn get_container_image(
         &self,
@@ -638,10 +786,60 @@ impl<ContainerImageDepotOpsT: ContainerImageDepotOps> ContainerImageDepot<Contai
             return Ok(img.clone());
         }
 
-        let mut tags = self.lock_tags().await?;
-        let digest = self
-            .get_image_digest(&mut tags.locked_tags, name, tag)
-            .await?;
+        let img = if let Some(layout_dir) = name.strip_prefix(OCI_LAYOUT_PREFIX) {
+            self.get_oci_layout_image(name, &self.project_dir.join(layout_dir), tag, prog)
+                .await
+                .with_context(|| format!("loading image from OCI layout {layout_dir}"))?
+        } else {
+            self.get_registry_image(name, tag, prog).await?
+        };
+
+        self.cache.lock().await.insert(cache_key, img.clone());
+        Ok(img)
+    }
+
+    /// Images from an OCI layout are cached by manifest digest, just like images from a registry.
+    /// Since the layout is already on the local file system, resolving the manifest is cheap, so
+    /// there is no need to record it in the tags lock file.
+    #[anyhow_trace]
+    async fn get_oci_layout_image(
+        &self,
+        name: &str,
+        layout_dir: &Path,
+        tag: &str,
+        prog: impl ProgressTracker,
+    ) -> Result<ContainerImage> {
+        let digest = resolve_oci_layout_manifest(&self.fs, layout_dir, tag).await?;
+        self.with_cache_lock(&digest, async {
+            Ok(if let Some(img) = self.get_cached_image(&digest).await {
+                img
+            } else {
+                self.populate_cache_dir(&digest, |output_dir| {
+                    copy_oci_layout_image(&self.fs, name, layout_dir, &digest, output_dir, prog)
+                })
+                .await?
+            })
+        })
+        .await
+    }
+
+    #[anyhow_trace]
+    async fn get_registry_image(
+        &self,
+        name: &str,
+        tag_or_digest: &str,
+        prog: impl ProgressTracker,
+    ) -> Result<ContainerImage> {
+        // A digest already pins the image, so there is nothing to resolve or lock.
+        let (digest, tags) = if tag_or_digest.starts_with("sha256:") {
+            (tag_or_digest.to_owned(), None)
+        } else {
+            let mut tags = self.lock_tags().await?;
+            let digest = self
+                .get_image_digest(&mut tags.locked_tags, name, tag_or_digest)
+                .await?;
+            (digest, Some(tags))
+        };
 
         let img = self
             .with_cache_lock(&digest, async {
@@ -652,9 +850,9 @@ impl<ContainerImageDepotOpsT: ContainerImageDepotOps> ContainerImageDepot<Contai
                 })
             })
             .await?;
-        tags.write().await?;
-
-        self.cache.lock().await.insert(cache_key, img.clone());
+        if let Some(tags) = tags {
+            tags.write().await?;
+        }
         Ok(img)
     }
 }
@@ -971,3 +1169,226 @@ async fn container_image_depot_update_image_but_nothing_to_do() {
         vec!["sha256:abcdef", "sha256:ghijk"]
     );
 }
+
+#[cfg(test)]
+async fn write_oci_layout_blob(fs: &Fs, layout_dir: &Path, name: &str, contents: &[u8]) -> String {
+    let digest = format!("sha256:{name}");
+    let path = oci_layout_blob_path(layout_dir, &digest).unwrap();
+    fs.create_dir_all(path.parent().unwrap()).await.unwrap();
+    fs.write(path, contents).await.unwrap();
+    digest
+}
+
+#[cfg(test)]
+async fn write_oci_layout(fs: &Fs, layout_dir: &Path) -> String {
+    use async_compression::tokio::write::GzipEncoder;
+
+    let mut encoder = GzipEncoder::new(vec![]);
+    encoder.write_all(b"second layer").await.unwrap();
+    encoder.shutdown().await.unwrap();
+    let gzip_layer = encoder.into_inner();
+
+    let plain_digest = write_oci_layout_blob(fs, layout_dir, "plain", b"first layer").await;
+    let gzip_digest = write_oci_layout_blob(fs, layout_dir, "gzip", &gzip_layer).await;
+    let config_digest = write_oci_layout_blob(
+        fs,
+        layout_dir,
+        "config",
+        serde_json::json!({
+            "architecture": "amd64",
+            "os": "linux",
+            "config": {
+                "Env": ["FOO=foo", "BAR=bar"],
+                "WorkingDir": "/work",
+            },
+            "rootfs": { "type": "layers", "diff_ids": [] },
+            "history": [],
+        })
+        .to_string()
+        .as_bytes(),
+    )
+    .await;
+    let manifest_digest = write_oci_layout_blob(
+        fs,
+        layout_dir,
+        "manifest",
+        serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": config_digest,
+                "size": 0,
+            },
+            "layers": [
+                {
+                    "mediaType": "application/vnd.oci.image.layer.v1.tar",
+                    "digest": plain_digest,
+                    "size": 11,
+                },
+                {
+                    "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+                    "digest": gzip_digest,
+                    "size": gzip_layer.len(),
+                },
+            ],
+        })
+        .to_string()
+        .as_bytes(),
+    )
+    .await;
+    fs.write(
+        layout_dir.join("oci-layout"),
+        r#"{"imageLayoutVersion": "1.0.0"}"#,
+    )
+    .await
+    .unwrap();
+    fs.write(
+        layout_dir.join("index.json"),
+        serde_json::json!({
+            "schemaVersion": 2,
+            "manifests": [
+                {
+                    "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                    "digest": manifest_digest,
+                    "size": 0,
+                    "annotations": { ANNOTATION_REF_NAME: "v1" },
+                },
+            ],
+        })
+        .to_string(),
+    )
+    .await
+    .unwrap();
+    manifest_digest
+}
+
+#[tokio::test]
+async fn container_image_depot_oci_layout() {
+    let fs = Fs::new();
+    let project_dir = tempfile::tempdir().unwrap();
+    let image_dir = tempfile::tempdir().unwrap();
+    let manifest_digest = write_oci_layout(&fs, &project_dir.path().join("layout")).await;
+
+    let depot = ContainerImageDepot::new_with(
+        project_dir.path(),
+        image_dir.path(),
+        PanicContainerImageDepotOps,
+    )
+    .unwrap();
+    let img = depot
+        .get_container_image("oci:layout", "latest", NullProgressTracker)
+        .await
+        .unwrap();
+
+    assert_eq!(img.name, "oci:layout");
+    assert_eq!(img.digest, manifest_digest);
+    assert_eq!(img.env(), Some(&vec!["FOO=foo".into(), "BAR=bar".into()]));
+    assert_eq!(img.working_dir(), Some(&"/work".into()));
+    assert_eq!(img.layers.len(), 2);
+    assert_eq!(fs.read(&img.layers[0]).await.unwrap(), b"first layer");
+    assert_eq!(fs.read(&img.layers[1]).await.unwrap(), b"second layer");
+
+    // The image is cached by manifest digest, and no tags are locked.
+    assert_eq!(
+        sorted_dir_listing(&fs, image_dir.path()).await,
+        vec![manifest_digest.clone()]
+    );
+    assert!(!fs.exists(project_dir.path().join(TAG_FILE_NAME)).await);
+
+    // A new depot finds the image in the cache even if the layout's layers are gone.
+    fs.remove_file(project_dir.path().join("layout/blobs/sha256/plain"))
+        .await
+        .unwrap();
+    let depot = ContainerImageDepot::new_with(
+        project_dir.path(),
+        image_dir.path(),
+        PanicContainerImageDepotOps,
+    )
+    .unwrap();
+    let img2 = depot
+        .get_container_image("oci:layout", "v1", NullProgressTracker)
+        .await
+        .unwrap();
+    assert_eq!(img, img2);
+}
+
+#[tokio::test]
+async fn container_image_depot_oci_layout_missing() {
+    let project_dir = tempfile::tempdir().unwrap();
+    let image_dir = tempfile::tempdir().unwrap();
+
+    let depot = ContainerImageDepot::new_with(
+        project_dir.path(),
+        image_dir.path(),
+        PanicContainerImageDepotOps,
+    )
+    .unwrap();
+    let err = depot
+        .get_container_image("oci:missing", "latest", NullProgressTracker)
+        .await
+        .unwrap_err();
+    assert!(
+        err.chain()
+            .any(|err| err.to_string() == "loading image from OCI layout missing"),
+        "{err:?}"
+    );
+}
+
+#[tokio::test]
+async fn container_image_depot_digest_not_affected_by_tag() {
+    let fs = Fs::new();
+    let project_dir = tempfile::tempdir().unwrap();
+    let image_dir = tempfile::tempdir().unwrap();
+
+    // Resolving a digest doesn't need to resolve a tag, and isn't recorded in the lock file.
+    let depot = ContainerImageDepot::new_with(
+        project_dir.path(),
+        image_dir.path(),
+        FakeContainerImageDepotOps(maplit::hashmap! {}),
+    )
+    .unwrap();
+    let pinned = depot
+        .get_container_image("foo", "sha256:abcdef", NullProgressTracker)
+        .await
+        .unwrap();
+    assert_eq!(pinned.digest, "sha256:abcdef");
+    assert!(!fs.exists(project_dir.path().join(TAG_FILE_NAME)).await);
+    drop(depot);
+
+    // Moving the tag somewhere else doesn't change what the digest refers to.
+    let depot = ContainerImageDepot::new_with(
+        project_dir.path(),
+        image_dir.path(),
+        FakeContainerImageDepotOps(maplit::hashmap! {
+            "foo-latest".into() => "sha256:ghijk".into(),
+        }),
+    )
+    .unwrap();
+    let latest = depot
+        .get_container_image("foo", "latest", NullProgressTracker)
+        .await
+        .unwrap();
+    assert_eq!(latest.digest, "sha256:ghijk");
+    let pinned_again = depot
+        .get_container_image("foo", "sha256:abcdef", NullProgressTracker)
+        .await
+        .unwrap();
+    assert_eq!(pinned_again, pinned);
+
+    assert_eq!(
+        fs.read_to_string(project_dir.path().join(TAG_FILE_NAME))
+            .await
+            .unwrap(),
+        "\
+            version = 0\n\
+            \n\
+            [foo]\n\
+            latest = \"sha256:ghijk\"\n\
+        "
+    );
+    assert_eq!(
+        sorted_dir_listing(&fs, image_dir.path()).await,
+        vec!["sha256:abcdef", "sha256:ghijk"]
+    );
+}
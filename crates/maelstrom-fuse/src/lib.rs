@@ -5,7 +5,7 @@ pub mod fuser;
 
 use anyhow::Result;
 pub use fuser::{FileAttr, FileType};
-use fuser::{MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry};
+use fuser::{MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyXattr};
 use futures::stream::{Stream, StreamExt};
 use maelstrom_linux::{self as linux, Errno};
 use maelstrom_util::r#async::await_and_every_sec;
@@ -196,6 +196,12 @@ impl ErrorResponse for ReplyData {
     }
 }
 
+impl ErrorResponse for ReplyXattr {
+    fn error(self, e: i32) -> impl Future<Output = ()> {
+        ReplyXattr::error(self, e)
+    }
+}
+
 trait Response {
     type Reply: ErrorResponse;
     fn send(self, reply: Self::Reply) -> impl Future<Output = ()>;
@@ -344,6 +350,40 @@ impl<FileSystemT: FuseFileSystem + Send + Sync + 'static> fuser::Filesystem
             drop(permit);
         });
     }
+
+    async fn getxattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        let handler = self.handler.clone();
+        let permit = self.sem.clone().acquire_owned().await.unwrap();
+        let request = req.into();
+        let name = name.to_owned();
+        tokio::task::spawn(async move {
+            handle_resp(handler.get_xattr(request, ino, &name, size).await, reply).await;
+            drop(permit);
+        });
+    }
+
+    async fn listxattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        let handler = self.handler.clone();
+        let permit = self.sem.clone().acquire_owned().await.unwrap();
+        let request = req.into();
+        tokio::task::spawn(async move {
+            handle_resp(handler.list_xattr(request, ino, size).await, reply).await;
+            drop(permit);
+        });
+    }
 }
 
 /// Passed to all the [`FuseFileSystem`] request functions and contains information about who is
@@ -441,6 +481,40 @@ impl Response for ReadLinkResponse {
     }
 }
 
+/// Response from a [`FuseFileSystem::get_xattr`] or [`FuseFileSystem::list_xattr`] request
+#[derive(Debug)]
+pub enum XattrResponse {
+    /// The size of the data, sent when the request was made with a size of 0
+    Size(u32),
+    Data(Vec<u8>),
+}
+
+impl XattrResponse {
+    /// Build the right response for a request with the given `size`, returning `ERANGE` if the
+    /// data doesn't fit.
+    pub fn new(data: Vec<u8>, size: u32) -> ErrnoResult<Self> {
+        let len = u32::try_from(data.len()).map_err(|_| Errno::E2BIG)?;
+        if size == 0 {
+            Ok(Self::Size(len))
+        } else if len > size {
+            Err(Errno::ERANGE)
+        } else {
+            Ok(Self::Data(data))
+        }
+    }
+}
+
+impl Response for XattrResponse {
+    type Reply = ReplyXattr;
+
+    async fn send(self, reply: ReplyXattr) {
+        match self {
+            Self::Size(size) => reply.size(size).await,
+            Self::Data(data) => reply.data(&data).await,
+        }
+    }
+}
+
 /// Directory entry, used in [`FuseFileSystem::read_dir`] request
 pub struct DirEntry {
     pub ino: u64,
@@ -535,6 +609,25 @@ pub trait FuseFileSystem {
         async move { Err(Errno::ENOSYS) }
     }
 
+    fn get_xattr(
+        &self,
+        _req: Request,
+        _ino: u64,
+        _name: &OsStr,
+        _size: u32,
+    ) -> impl Future<Output = ErrnoResult<XattrResponse>> + Send {
+        async move { Err(Errno::ENOSYS) }
+    }
+
+    fn list_xattr(
+        &self,
+        _req: Request,
+        _ino: u64,
+        _size: u32,
+    ) -> impl Future<Output = ErrnoResult<XattrResponse>> + Send {
+        async move { Err(Errno::ENOSYS) }
+    }
+
     /*
     fn readdirplus(
         &mut self,
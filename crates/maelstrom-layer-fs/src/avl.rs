@@ -4,10 +4,13 @@ use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, FromInto};
 use std::borrow::BorrowMut;
 use std::cmp::{self, Ordering};
+use std::collections::HashSet;
+use std::future::Future;
 use std::marker::PhantomData;
 use std::num::NonZeroU64;
+use std::pin::Pin;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct AvlPtr(NonZeroU64);
 
 impl AvlPtr {
@@ -99,6 +102,30 @@ pub trait AvlStorage {
     async fn flush(&mut self) -> Result<()>;
 }
 
+/// A problem with the structure of an AVL tree found by [`AvlTree::check`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AvlFault<KeyT> {
+    /// A pointer doesn't point to a node that can be read.
+    BadPointer(AvlPtr),
+    /// A pointer to a node was found more than once, so the tree has a cycle or shared node.
+    RepeatedPointer(AvlPtr),
+    /// The node with the given key is on the wrong side of one of its ancestors.
+    OutOfOrder(KeyT),
+    /// The stored height for the node with the given key doesn't match its subtrees.
+    WrongHeight(KeyT),
+    /// The subtrees of the node with the given key differ in height by more than one.
+    Unbalanced(KeyT),
+}
+
+/// The result of [`AvlTree::check`].
+pub struct AvlCheck<KeyT, ValueT> {
+    /// All the entries which could be read, in order.
+    pub entries: Vec<(KeyT, ValueT)>,
+    pub faults: Vec<AvlFault<KeyT>>,
+}
+
+type KeyBounds<KeyT> = (Option<KeyT>, Option<KeyT>);
+
 /// An async error capable AVL tree backed something implementing AvlStorage trait. It is intended
 /// that the storage trait store entries on disk somehow.
 pub struct AvlTree<StorageT> {
@@ -403,6 +430,88 @@ where
         self.storage.flush().await
     }
 
+    /// Walk the whole tree checking that it is well-formed. Before any pointer is followed it is
+    /// passed to `valid_ptr`, which should return false if it points outside of the storage.
+    pub async fn check(
+        &mut self,
+        valid_ptr: impl Fn(AvlPtr) -> bool,
+    ) -> Result<AvlCheck<StorageT::Key, StorageT::Value>>
+    where
+        StorageT::Key: Clone,
+    {
+        let mut check = AvlCheck {
+            entries: vec![],
+            faults: vec![],
+        };
+        let root = self.storage.root().await?;
+        self.check_node(
+            root,
+            (None, None),
+            &valid_ptr,
+            &mut HashSet::new(),
+            &mut check,
+        )
+        .await?;
+        Ok(check)
+    }
+
+    /// Check the subtree rooted at `ptr`, returning its actual height.
+    fn check_node<'a>(
+        &'a mut self,
+        ptr: Option<AvlPtr>,
+        bounds: KeyBounds<StorageT::Key>,
+        valid_ptr: &'a dyn Fn(AvlPtr) -> bool,
+        seen: &'a mut HashSet<AvlPtr>,
+        check: &'a mut AvlCheck<StorageT::Key, StorageT::Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<u32>> + 'a>>
+    where
+        StorageT::Key: Clone,
+    {
+        Box::pin(async move {
+            let Some(ptr) = ptr else {
+                return Ok(0);
+            };
+            if !seen.insert(ptr) {
+                check.faults.push(AvlFault::RepeatedPointer(ptr));
+                return Ok(0);
+            }
+            if !valid_ptr(ptr) {
+                check.faults.push(AvlFault::BadPointer(ptr));
+                return Ok(0);
+            }
+            let Ok(node) = self.storage.look_up(ptr).await else {
+                check.faults.push(AvlFault::BadPointer(ptr));
+                return Ok(0);
+            };
+
+            let (lower, upper) = bounds;
+            if lower.as_ref().is_some_and(|l| &node.key <= l)
+                || upper.as_ref().is_some_and(|u| &node.key >= u)
+            {
+                check.faults.push(AvlFault::OutOfOrder(node.key.clone()));
+            }
+
+            let left_bounds = (lower, Some(node.key.clone()));
+            let left_height = self
+                .check_node(node.left, left_bounds, valid_ptr, seen, check)
+                .await?;
+            check.entries.push((node.key.clone(), node.value));
+            let right_bounds = (Some(node.key.clone()), upper);
+            let right_height = self
+                .check_node(node.right, right_bounds, valid_ptr, seen, check)
+                .await?;
+
+            let height = 1 + cmp::max(left_height, right_height);
+            if node.height != height {
+                check.faults.push(AvlFault::WrongHeight(node.key.clone()));
+            }
+            if left_height.abs_diff(right_height) > 1 {
+                check.faults.push(AvlFault::Unbalanced(node.key));
+            }
+            Ok(height)
+        })
+    }
+
     #[cfg(test)]
     async fn height_of(&mut self, node_ptr: Option<AvlPtr>) -> Result<usize> {
         let Some(node_ptr) = node_ptr else {
@@ -697,4 +806,54 @@ mod tests {
         assert_eq!(tree.get(&1).await.unwrap(), Some(7));
         assert_eq!(tree.get(&2).await.unwrap(), None);
     }
+    #[tokio::test]
+    async fn check_finds_faults() {
+        let mut tree = AvlTree::new(MemoryStorage::default());
+        for v in 0..10 {
+            tree.insert_if_not_exists(v, v)
+                .await
+                .unwrap()
+                .assert_is_true();
+        }
+        let check = tree.check(|_| true).await.unwrap();
+        assert_eq!(check.entries, (0..10).map(|v| (v, v)).collect::<Vec<_>>());
+        assert_eq!(check.faults, vec![]);
+
+        // Swap the keys of the root and its left child.
+        let root = tree.storage.root.unwrap();
+        let left = tree.storage.values[root.as_u64() as usize - 1]
+            .left
+            .unwrap();
+        let root_key = tree.storage.values[root.as_u64() as usize - 1].key;
+        let left_key = tree.storage.values[left.as_u64() as usize - 1].key;
+        tree.storage.values[root.as_u64() as usize - 1].key = left_key;
+        tree.storage.values[left.as_u64() as usize - 1].key = root_key;
+        let check = tree.check(|_| true).await.unwrap();
+        assert!(
+            check.faults.contains(&AvlFault::OutOfOrder(root_key)),
+            "{:?}",
+            check.faults
+        );
+
+        // Make the root's right child point back at the root.
+        tree.storage.values[root.as_u64() as usize - 1].key = root_key;
+        tree.storage.values[left.as_u64() as usize - 1].key = left_key;
+        let right = tree.storage.values[root.as_u64() as usize - 1]
+            .right
+            .unwrap();
+        tree.storage.values[right.as_u64() as usize - 1].left = Some(root);
+        let check = tree.check(|_| true).await.unwrap();
+        assert!(
+            check.faults.contains(&AvlFault::RepeatedPointer(root)),
+            "{:?}",
+            check.faults
+        );
+
+        let check = tree.check(|ptr| ptr != left).await.unwrap();
+        assert!(
+            check.faults.contains(&AvlFault::BadPointer(left)),
+            "{:?}",
+            check.faults
+        );
+    }
 }
@@ -2,7 +2,7 @@ use crate::dir::{DirectoryDataReader, DirectoryDataWriter, OrderedDirectoryStrea
 use crate::file::FileMetadataWriter;
 use crate::ty::{
     DirectoryEntryData, DirectoryEntryFileData, FileAttributes, FileData, FileId, FileType,
-    LayerId, LayerSuper,
+    FileXattrs, LayerId, LayerSuper, SparseExtent,
 };
 use crate::LayerFs;
 use anyhow::bail;
@@ -14,14 +14,29 @@ use maelstrom_base::{
     manifest::{ManifestEntryData, Mode, UnixTimestamp},
     Sha256Digest, Utf8Component, Utf8Path,
 };
-use maelstrom_util::{async_fs::Fs, ext::BoolExt as _, manifest::AsyncManifestReader};
+use maelstrom_util::{
+    async_fs::{self, Fs},
+    ext::BoolExt as _,
+    manifest::AsyncManifestReader,
+};
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::HashSet;
+use std::io::SeekFrom;
 use std::path::Path;
 use std::pin::Pin;
-use tokio::io::{AsyncRead, AsyncSeek};
+use tokio::io::{
+    AsyncRead, AsyncReadExt as _, AsyncSeek, AsyncSeekExt as _, AsyncWriteExt as _, BufWriter,
+};
 use tokio_tar::{Archive, EntryType};
 
+/// Regular files in tars at least this big are checked for runs of zeros, which are then stored as
+/// holes.
+const SPARSE_MIN_SIZE: u64 = 1024 * 1024;
+
+/// The granularity at which we look for holes in files.
+const SPARSE_BLOCK_SIZE: u64 = 4096;
+
 struct DirectoryDataWriterCache<'fs> {
     data_fs: &'fs Fs,
     cache: LruCache<FileId, DirectoryDataWriter>,
@@ -60,12 +75,76 @@ impl<'fs> DirectoryDataWriterCache<'fs> {
     }
 }
 
+/// Writes the data of sparse files to a layer's `sparse_data.bin`. Only the extents containing data
+/// are written, one after another.
+struct SparseDataWriter {
+    file: BufWriter<async_fs::File>,
+    len: u64,
+}
+
+#[anyhow_trace]
+impl SparseDataWriter {
+    async fn new(data_fs: &Fs, path: &Path) -> Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(data_fs.create_file(path).await?),
+            len: 0,
+        })
+    }
+
+    /// Read `length` bytes of file data, writing out the parts of it which contain something other
+    /// than zeros. Returns the extents written.
+    async fn write_extents(
+        &mut self,
+        mut data: impl AsyncRead + Unpin,
+        length: u64,
+    ) -> Result<Vec<SparseExtent>> {
+        let mut extents: Vec<SparseExtent> = vec![];
+        let mut block = vec![0; SPARSE_BLOCK_SIZE as usize];
+        let mut offset = 0;
+        while offset < length {
+            let block_len = std::cmp::min(SPARSE_BLOCK_SIZE, length - offset);
+            let block = &mut block[..block_len as usize];
+            data.read_exact(block).await?;
+            if block.iter().any(|b| *b != 0) {
+                match extents.last_mut() {
+                    Some(last) if last.offset + last.length == offset => last.length += block_len,
+                    _ => extents.push(SparseExtent {
+                        offset,
+                        length: block_len,
+                        data_offset: self.len,
+                    }),
+                }
+                self.file.write_all(block).await?;
+                self.len += block_len;
+            }
+            offset += block_len;
+        }
+        Ok(extents)
+    }
+
+    /// Throw away everything written after the first `len` bytes.
+    async fn truncate(&mut self, len: u64) -> Result<()> {
+        self.file.flush().await?;
+        let file = self.file.get_mut();
+        file.set_len(len).await?;
+        file.seek(SeekFrom::Start(len)).await?;
+        self.len = len;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.file.flush().await?;
+        Ok(())
+    }
+}
+
 /// Creates a LayerFS bottom layer using a manifest or a tar file as input.
 pub struct BottomLayerBuilder<'fs> {
     layer_fs: LayerFs,
     file_writer: FileMetadataWriter,
     time: UnixTimestamp,
     dir_writer_cache: DirectoryDataWriterCache<'fs>,
+    sparse_data: Option<SparseDataWriter>,
 }
 
 #[anyhow_trace]
@@ -102,6 +181,7 @@ impl<'fs> BottomLayerBuilder<'fs> {
                     mtime: time,
                 },
                 FileData::Empty,
+                FileXattrs::new(),
             )
             .await?;
         assert_eq!(root, FileId::root(LayerId::BOTTOM));
@@ -113,9 +193,18 @@ impl<'fs> BottomLayerBuilder<'fs> {
             file_writer,
             time,
             dir_writer_cache: DirectoryDataWriterCache::new(data_fs),
+            sparse_data: None,
         })
     }
 
+    async fn sparse_data_writer(&mut self) -> Result<&mut SparseDataWriter> {
+        if self.sparse_data.is_none() {
+            let path = self.layer_fs.sparse_data_path(LayerId::BOTTOM).await?;
+            self.sparse_data = Some(SparseDataWriter::new(&self.layer_fs.data_fs, &path).await?);
+        }
+        Ok(self.sparse_data.as_mut().unwrap())
+    }
+
     async fn look_up(&mut self, dir_id: FileId, name: &str) -> Result<Option<FileId>> {
         let dir_reader = self
             .dir_writer_cache
@@ -164,7 +253,7 @@ impl<'fs> BottomLayerBuilder<'fs> {
                             mode: Mode(0o777),
                             mtime: self.time,
                         };
-                        self.add_dir(dir_id, comp, attrs).await?
+                        self.add_dir(dir_id, comp, attrs, FileXattrs::new()).await?
                     }
                 }
             }
@@ -177,10 +266,11 @@ impl<'fs> BottomLayerBuilder<'fs> {
         parent: FileId,
         name: &str,
         attrs: FileAttributes,
+        xattrs: FileXattrs,
     ) -> Result<FileId> {
         let file_id = self
             .file_writer
-            .insert_file(FileType::Directory, attrs, FileData::Empty)
+            .insert_file(FileType::Directory, attrs, FileData::Empty, xattrs)
             .await?;
         self.add_link(parent, name, file_id, FileType::Directory)
             .await?
@@ -234,10 +324,22 @@ impl<'fs> BottomLayerBuilder<'fs> {
         path: &Utf8Path,
         attrs: FileAttributes,
         data: FileData,
+    ) -> Result<FileId> {
+        self.add_file_path_with_xattrs(path, attrs, data, FileXattrs::new())
+            .await
+    }
+
+    /// Like [`Self::add_file_path`] but also records the given extended attributes for the file.
+    pub async fn add_file_path_with_xattrs(
+        &mut self,
+        path: &Utf8Path,
+        attrs: FileAttributes,
+        data: FileData,
+        xattrs: FileXattrs,
     ) -> Result<FileId> {
         let file_id = self
             .file_writer
-            .insert_file(FileType::RegularFile, attrs, data)
+            .insert_file(FileType::RegularFile, attrs, data, xattrs)
             .await?;
 
         let parent_id = if let Some(parent) = path.parent() {
@@ -280,6 +382,22 @@ impl<'fs> BottomLayerBuilder<'fs> {
     /// Add a directory at the given path in the new layer. Creates any intermediate directories
     /// that don't exist.
     pub async fn add_dir_path(&mut self, path: &Utf8Path, attrs: FileAttributes) -> Result<FileId> {
+        self.add_dir_path_with_xattrs(path, attrs, FileXattrs::new())
+            .await
+    }
+
+    /// Like [`Self::add_dir_path`] but also records the given extended attributes for the
+    /// directory.
+    ///
+    /// Extended attributes are stored in the file-table which can't be rewritten, so if the
+    /// directory already exists (e.g. it was created as an intermediate directory) only its
+    /// attributes are updated.
+    pub async fn add_dir_path_with_xattrs(
+        &mut self,
+        path: &Utf8Path,
+        attrs: FileAttributes,
+        xattrs: FileXattrs,
+    ) -> Result<FileId> {
         let parent_id = if let Some(parent) = path.parent() {
             self.ensure_path(parent).await?
         } else {
@@ -290,7 +408,7 @@ impl<'fs> BottomLayerBuilder<'fs> {
             self.set_attr(existing, attrs).await?;
             Ok(existing)
         } else {
-            self.add_dir(parent_id, name, attrs).await
+            self.add_dir(parent_id, name, attrs, xattrs).await
         }
     }
 
@@ -312,7 +430,8 @@ impl<'fs> BottomLayerBuilder<'fs> {
                 mode: Mode(0o777),
                 mtime: self.time,
             };
-            self.add_dir(parent_id, name, attrs).await?;
+            self.add_dir(parent_id, name, attrs, FileXattrs::new())
+                .await?;
         }
 
         self.set_opaque_dir(parent_id, name, true).await?;
@@ -334,7 +453,12 @@ impl<'fs> BottomLayerBuilder<'fs> {
         };
         let file_id = self
             .file_writer
-            .insert_file(FileType::Symlink, attrs, FileData::Inline(target.into()))
+            .insert_file(
+                FileType::Symlink,
+                attrs,
+                FileData::Inline(target.into()),
+                FileXattrs::new(),
+            )
             .await?;
 
         let parent_id = if let Some(parent) = path.parent() {
@@ -396,14 +520,23 @@ impl<'fs> BottomLayerBuilder<'fs> {
         tar_stream: impl AsyncRead + Unpin,
     ) -> Result<()> {
         let mut ar = Archive::new(tar_stream);
-        let mut entries = ar.entries()?;
+        let mut entries = ar.entries_raw()?;
+        let mut extended = TarExtendedHeaders::default();
         while let Some(entry) = entries.next().await {
-            let entry = entry?;
+            let mut entry = entry?;
+            if extended.read_from(&mut entry).await? {
+                continue;
+            }
+            let TarExtendedHeaders {
+                path: entry_path,
+                link_name,
+                xattrs,
+            } = std::mem::take(&mut extended);
             let header = entry.header();
-            let entry_path = entry.path()?;
-            let utf8_path: &Utf8Path = entry_path
-                .to_str()
-                .ok_or(anyhow!("non-UTF8 path in tar"))?
+            let entry_path = entry_path.unwrap_or_else(|| entry.path_bytes().into_owned());
+            let link_name = link_name.or_else(|| header.link_name_bytes().map(Cow::into_owned));
+            let utf8_path: &Utf8Path = std::str::from_utf8(&entry_path)
+                .map_err(|_| anyhow!("non-UTF8 path in tar"))?
                 .as_ref();
             let path = Utf8Path::new("/").join(utf8_path);
             match header.entry_type() {
@@ -422,46 +555,52 @@ impl<'fs> BottomLayerBuilder<'fs> {
                         continue;
                     }
 
-                    self.add_file_path(
-                        &path,
-                        FileAttributes {
-                            size: header.size()?,
-                            mode: Mode(header.mode()?),
-                            mtime: UnixTimestamp(header.mtime()?.try_into()?),
-                        },
-                        FileData::Digest {
-                            digest: digest.clone(),
-                            offset: entry.raw_file_position(),
-                            length: header.entry_size()?,
-                        },
-                    )
-                    .await?;
+                    let attrs = FileAttributes {
+                        size: header.size()?,
+                        mode: Mode(header.mode()?),
+                        mtime: UnixTimestamp(header.mtime()?.try_into()?),
+                    };
+                    let offset = entry.raw_file_position();
+                    let length = header.entry_size()?;
+                    let mut data = FileData::Digest {
+                        digest: digest.clone(),
+                        offset,
+                        length,
+                    };
+                    if length >= SPARSE_MIN_SIZE {
+                        let sparse_data = self.sparse_data_writer().await?;
+                        let start = sparse_data.len;
+                        let extents = sparse_data.write_extents(&mut entry, length).await?;
+                        if extents.iter().map(|e| e.length).sum::<u64>() < length {
+                            data = FileData::Sparse { length, extents };
+                        } else {
+                            // There are no holes, so read the data from the tar like usual.
+                            sparse_data.truncate(start).await?;
+                        }
+                    }
+                    self.add_file_path_with_xattrs(&path, attrs, data, xattrs)
+                        .await?;
                 }
                 EntryType::Directory => {
-                    self.add_dir_path(
+                    self.add_dir_path_with_xattrs(
                         &path,
                         FileAttributes {
                             size: header.size()?,
                             mode: Mode(header.mode()?),
                             mtime: UnixTimestamp(header.mtime()?.try_into()?),
                         },
+                        xattrs,
                     )
                     .await?;
                 }
                 EntryType::Symlink => {
-                    self.add_symlink_path(
-                        &path,
-                        header.link_name_bytes().expect("empty symlink in tar"),
-                    )
-                    .await?;
+                    self.add_symlink_path(&path, link_name.expect("empty symlink in tar"))
+                        .await?;
                 }
                 EntryType::Link => {
                     self.add_link_path(
                         &path,
-                        std::str::from_utf8(
-                            &header.link_name_bytes().expect("empty symlink in tar"),
-                        )?
-                        .into(),
+                        std::str::from_utf8(&link_name.expect("empty symlink in tar"))?.into(),
                     )
                     .await?;
                 }
@@ -471,6 +610,9 @@ impl<'fs> BottomLayerBuilder<'fs> {
             }
         }
         self.file_writer.flush().await?;
+        if let Some(sparse_data) = &mut self.sparse_data {
+            sparse_data.flush().await?;
+        }
 
         Ok(())
     }
@@ -527,11 +669,71 @@ impl<'fs> BottomLayerBuilder<'fs> {
     pub async fn finish(mut self) -> Result<LayerFs> {
         self.file_writer.flush().await?;
         self.dir_writer_cache.flush().await?;
+        if let Some(sparse_data) = &mut self.sparse_data {
+            sparse_data.flush().await?;
+        }
 
         Ok(self.layer_fs)
     }
 }
 
+/// Extended headers in a tar archive which apply to the entry that follows them.
+///
+/// We read these ourselves from the raw entries rather than relying on
+/// [`tokio_tar::Archive::entries`], since it drops them if the underlying reader isn't ready while
+/// they are being read.
+#[derive(Default)]
+struct TarExtendedHeaders {
+    path: Option<Vec<u8>>,
+    link_name: Option<Vec<u8>>,
+    xattrs: FileXattrs,
+}
+
+#[anyhow_trace]
+impl TarExtendedHeaders {
+    /// If the given entry is an extended header, record what it contains and return true.
+    async fn read_from(
+        &mut self,
+        entry: &mut tokio_tar::Entry<impl AsyncRead + Unpin>,
+    ) -> Result<bool> {
+        match entry.header().entry_type() {
+            EntryType::XHeader => {
+                if let Some(extensions) = entry.pax_extensions().await? {
+                    for extension in extensions {
+                        let extension = extension?;
+                        let value = extension.value_bytes().to_vec();
+                        match extension.key()? {
+                            "path" => self.path = Some(value),
+                            "linkpath" => self.link_name = Some(value),
+                            key => {
+                                // This is how GNU tar and most image tooling store xattrs.
+                                if let Some(name) = key.strip_prefix("SCHILY.xattr.") {
+                                    self.xattrs.insert(name.into(), value);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            EntryType::GNULongName => self.path = Some(read_tar_long_name(entry).await?),
+            EntryType::GNULongLink => self.link_name = Some(read_tar_long_name(entry).await?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+async fn read_tar_long_name(
+    entry: &mut tokio_tar::Entry<impl AsyncRead + Unpin>,
+) -> Result<Vec<u8>> {
+    let mut name = vec![];
+    entry.read_to_end(&mut name).await?;
+    while name.last() == Some(&0) {
+        name.pop();
+    }
+    Ok(name)
+}
+
 /// Walks the `right_fs` and yields together with it any matching entries from `left_fs`
 pub struct DoubleFsWalk<'fs> {
     streams: Vec<(Option<WalkStream>, WalkStream)>,
@@ -776,6 +978,23 @@ impl<'fs> UpperLayerBuilder<'fs> {
             .hard_link(other_attribute_table, upper_attribute_table)
             .await?;
 
+        let other_sparse_data = other
+            .sparse_data_path(other.layer_super().await?.layer_id)
+            .await?;
+        if self.upper.data_fs.exists(&other_sparse_data).await {
+            let upper_sparse_data = self
+                .upper
+                .sparse_data_path(self.upper.layer_super().await?.layer_id)
+                .await?;
+            if self.upper.data_fs.exists(&upper_sparse_data).await {
+                self.upper.data_fs.remove_file(&upper_sparse_data).await?;
+            }
+            self.upper
+                .data_fs
+                .hard_link(other_sparse_data, upper_sparse_data)
+                .await?;
+        }
+
         Ok(())
     }
 
@@ -783,8 +1002,8 @@ impl<'fs> UpperLayerBuilder<'fs> {
     ///
     /// This function only supports being called once per builder.
     ///
-    /// The file-table and attribute-table from the given bottom layer are hardlinked into the new
-    /// layer.
+    /// The file-table, attribute-table, and sparse data from the given bottom layer are hardlinked
+    /// into the new layer.
     ///
     /// The file-system from the given bottom layer is walked together with the file-system we are
     /// being stacked on top of. Anywhere they overlap we merge the directory contents together. If
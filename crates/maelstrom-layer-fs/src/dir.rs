@@ -1,24 +1,26 @@
-use crate::avl::{AvlNode, AvlPtr, AvlStorage, AvlTree, FlatAvlPtrOption};
+use crate::avl::{AvlCheck, AvlNode, AvlPtr, AvlStorage, AvlTree, FlatAvlPtrOption};
 use crate::ty::{
     decode_with_rich_error, encode_with_rich_error, DirectoryEntryData, DirectoryOffset, FileId,
     FileType, LayerFsVersion,
 };
 use crate::LayerFs;
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context as _, Result};
 use anyhow_trace::anyhow_trace;
-use maelstrom_util::async_fs::{File, Fs};
+use maelstrom_util::async_fs::{File, Fs, GetPath};
 use maelstrom_util::ext::BoolExt as _;
 use maelstrom_util::io::BufferedStream;
+use memmap2::Mmap;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, FromInto};
-use std::borrow::BorrowMut;
-use std::io::SeekFrom;
+use std::io::{self, Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use tokio::io::{AsyncSeekExt as _, AsyncWriteExt as _};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt as _, AsyncWrite, AsyncWriteExt as _, ReadBuf};
 
 /// Reads data from a LayerFS directory contents file (`<offset>.dir_data.bin`)
 pub struct DirectoryDataReader {
-    stream: BufferedStream<File>,
+    stream: DirectoryDataStream,
     entry_begin: u64,
     length: u64,
 }
@@ -26,16 +28,55 @@ pub struct DirectoryDataReader {
 const CHUNK_SIZE: usize = 512;
 const CACHE_SIZE: usize = 64;
 
+/// Directory contents files at least this big are memory-mapped rather than read through a
+/// [`BufferedStream`]. Past this size the buffered stream's cache is too small to hold much of the
+/// tree, so look-ups end up doing a read for most nodes they visit.
+const MMAP_THRESHOLD: u64 = 1024 * 1024;
+
 #[anyhow_trace]
 impl DirectoryDataReader {
+    /// Open the directory's contents, memory-mapping them if the file is large.
     pub async fn new(layer_fs: &LayerFs, file_id: FileId) -> Result<Self> {
+        let (file, length) = Self::open(layer_fs, file_id).await?;
+        if length >= MMAP_THRESHOLD {
+            Self::from_mapped(file, length).await
+        } else {
+            Self::from_buffered(file, length).await
+        }
+    }
+
+    /// Open the directory's contents, always reading them through a [`BufferedStream`].
+    pub async fn new_buffered(layer_fs: &LayerFs, file_id: FileId) -> Result<Self> {
+        let (file, length) = Self::open(layer_fs, file_id).await?;
+        Self::from_buffered(file, length).await
+    }
+
+    /// Open the directory's contents, always memory-mapping them.
+    pub async fn new_mapped(layer_fs: &LayerFs, file_id: FileId) -> Result<Self> {
+        let (file, length) = Self::open(layer_fs, file_id).await?;
+        Self::from_mapped(file, length).await
+    }
+
+    async fn open(layer_fs: &LayerFs, file_id: FileId) -> Result<(File, u64)> {
         let file = layer_fs
             .data_fs
             .open_file(layer_fs.dir_data_path(file_id).await?)
             .await?;
         let length = file.metadata().await?.len();
-        let mut stream =
-            BufferedStream::new(CHUNK_SIZE, CACHE_SIZE.try_into().unwrap(), file).await?;
+        Ok((file, length))
+    }
+
+    async fn from_buffered(file: File, length: u64) -> Result<Self> {
+        let stream = BufferedStream::new(CHUNK_SIZE, CACHE_SIZE.try_into().unwrap(), file).await?;
+        Self::from_stream(DirectoryDataStream::Buffered(Box::new(stream)), length).await
+    }
+
+    async fn from_mapped(file: File, length: u64) -> Result<Self> {
+        let stream = MappedFile::new(file).await?;
+        Self::from_stream(DirectoryDataStream::Mapped(stream), length).await
+    }
+
+    async fn from_stream(mut stream: DirectoryDataStream, length: u64) -> Result<Self> {
         let _header: DirectoryEntryStorageHeader = decode_with_rich_error(&mut stream).await?;
         let entry_begin = stream.stream_position().await?;
         Ok(Self {
@@ -78,6 +119,14 @@ impl DirectoryDataReader {
         }))
     }
 
+    /// Check that the AVL tree making up the directory is well-formed.
+    pub async fn check(&mut self) -> Result<AvlCheck<String, DirectoryEntryData>> {
+        let (entry_begin, length) = (self.entry_begin, self.length);
+        let mut tree = AvlTree::new(DirectoryEntryStorage::new(&mut self.stream));
+        tree.check(|ptr| (entry_begin..length).contains(&ptr.as_u64()))
+            .await
+    }
+
     pub async fn into_ordered_stream(self) -> Result<OrderedDirectoryStream> {
         Ok(Box::pin(
             AvlTree::new(DirectoryEntryStorage::new(self.stream))
@@ -87,6 +136,144 @@ impl DirectoryDataReader {
     }
 }
 
+/// The stream a [`DirectoryDataReader`] reads the directory contents file through.
+enum DirectoryDataStream {
+    Buffered(Box<BufferedStream<File>>),
+    Mapped(MappedFile),
+}
+
+impl GetPath for DirectoryDataStream {
+    fn path(&self) -> &Path {
+        match self {
+            Self::Buffered(s) => s.path(),
+            Self::Mapped(s) => s.path(),
+        }
+    }
+}
+
+impl AsyncRead for DirectoryDataStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Buffered(s) => Pin::new(s).poll_read(cx, buf),
+            Self::Mapped(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for DirectoryDataStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Buffered(s) => Pin::new(s).poll_write(cx, buf),
+            Self::Mapped(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Buffered(s) => Pin::new(s).poll_flush(cx),
+            Self::Mapped(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Buffered(s) => Pin::new(s).poll_shutdown(cx),
+            Self::Mapped(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+impl AsyncSeek for DirectoryDataStream {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match self.get_mut() {
+            Self::Buffered(s) => Pin::new(s).start_seek(position),
+            Self::Mapped(s) => Pin::new(s).start_seek(position),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match self.get_mut() {
+            Self::Buffered(s) => Pin::new(s).poll_complete(cx),
+            Self::Mapped(s) => Pin::new(s).poll_complete(cx),
+        }
+    }
+}
+
+/// A read-only memory-mapping of a file that can be read from and seeked like the file itself.
+struct MappedFile {
+    cursor: Cursor<Mmap>,
+    path: PathBuf,
+}
+
+impl MappedFile {
+    async fn new(file: File) -> Result<Self> {
+        let path = file.path().to_owned();
+        let file = file.into_std().await;
+        // SAFETY: The data files for a layer aren't modified once the layer has been built, which
+        // is the only time they are read.
+        let map = unsafe { Mmap::map(&file) }.with_context(|| format!("mmap({path:?})"))?;
+        Ok(Self {
+            cursor: Cursor::new(map),
+            path,
+        })
+    }
+}
+
+impl GetPath for MappedFile {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsyncRead for MappedFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().cursor).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MappedFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "memory-mapped directory data is read-only",
+        )))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for MappedFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.get_mut().cursor).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.get_mut().cursor).poll_complete(cx)
+    }
+}
+
 #[serde_as]
 #[derive(Copy, Clone, Default, Debug, Deserialize, Serialize)]
 pub struct DirectoryEntryStorageHeader {
@@ -95,12 +282,12 @@ pub struct DirectoryEntryStorageHeader {
     pub root: Option<AvlPtr>,
 }
 
-struct DirectoryEntryStorage<FileT> {
-    stream: FileT,
+struct DirectoryEntryStorage<StreamT> {
+    stream: StreamT,
 }
 
-impl<FileT> DirectoryEntryStorage<FileT> {
-    fn new(stream: FileT) -> Self {
+impl<StreamT> DirectoryEntryStorage<StreamT> {
+    fn new(stream: StreamT) -> Self {
         Self { stream }
     }
 }
@@ -108,62 +295,52 @@ impl<FileT> DirectoryEntryStorage<FileT> {
 type DirectoryEntry = AvlNode<String, DirectoryEntryData>;
 
 #[anyhow_trace]
-impl<FileT: BorrowMut<BufferedStream<File>> + Send> AvlStorage for DirectoryEntryStorage<FileT> {
+impl<StreamT> AvlStorage for DirectoryEntryStorage<StreamT>
+where
+    StreamT: AsyncRead + AsyncWrite + AsyncSeek + GetPath + Unpin + Send,
+{
     type Key = String;
     type Value = DirectoryEntryData;
 
     async fn root(&mut self) -> Result<Option<AvlPtr>> {
-        self.stream.borrow_mut().seek(SeekFrom::Start(0)).await?;
-        let header: DirectoryEntryStorageHeader =
-            decode_with_rich_error(self.stream.borrow_mut()).await?;
+        self.stream.seek(SeekFrom::Start(0)).await?;
+        let header: DirectoryEntryStorageHeader = decode_with_rich_error(&mut self.stream).await?;
         Ok(header.root)
     }
 
     async fn set_root(&mut self, root: AvlPtr) -> Result<()> {
-        self.stream.borrow_mut().seek(SeekFrom::Start(0)).await?;
+        self.stream.seek(SeekFrom::Start(0)).await?;
         let header = DirectoryEntryStorageHeader {
             root: Some(root),
             ..Default::default()
         };
-        encode_with_rich_error(self.stream.borrow_mut(), &header).await?;
+        encode_with_rich_error(&mut self.stream, &header).await?;
         Ok(())
     }
 
     async fn look_up(&mut self, key: AvlPtr) -> Result<DirectoryEntry> {
-        self.stream
-            .borrow_mut()
-            .seek(SeekFrom::Start(key.as_u64()))
-            .await?;
-        decode_with_rich_error(self.stream.borrow_mut()).await
+        self.stream.seek(SeekFrom::Start(key.as_u64())).await?;
+        decode_with_rich_error(&mut self.stream).await
     }
 
     async fn update(&mut self, key: AvlPtr, value: DirectoryEntry) -> Result<()> {
-        self.stream
-            .borrow_mut()
-            .seek(SeekFrom::Start(key.as_u64()))
-            .await?;
+        self.stream.seek(SeekFrom::Start(key.as_u64())).await?;
 
         #[cfg(debug_assertions)]
         let old_len = {
             use tokio::io::AsyncReadExt as _;
-            let old_len = self.stream.borrow_mut().read_u64().await?;
-            self.stream
-                .borrow_mut()
-                .seek(SeekFrom::Start(key.as_u64()))
-                .await?;
+            let old_len = self.stream.read_u64().await?;
+            self.stream.seek(SeekFrom::Start(key.as_u64())).await?;
             old_len
         };
 
-        encode_with_rich_error(self.stream.borrow_mut(), &value).await?;
+        encode_with_rich_error(&mut self.stream, &value).await?;
 
         #[cfg(debug_assertions)]
         {
             use tokio::io::AsyncReadExt as _;
-            self.stream
-                .borrow_mut()
-                .seek(SeekFrom::Start(key.as_u64()))
-                .await?;
-            let new_len = self.stream.borrow_mut().read_u64().await?;
+            self.stream.seek(SeekFrom::Start(key.as_u64())).await?;
+            let new_len = self.stream.read_u64().await?;
             assert_eq!(old_len, new_len);
         }
 
@@ -171,14 +348,14 @@ impl<FileT: BorrowMut<BufferedStream<File>> + Send> AvlStorage for DirectoryEntr
     }
 
     async fn insert(&mut self, node: DirectoryEntry) -> Result<AvlPtr> {
-        self.stream.borrow_mut().seek(SeekFrom::End(0)).await?;
-        let new_ptr = self.stream.borrow_mut().stream_position().await?;
-        encode_with_rich_error(self.stream.borrow_mut(), &node).await?;
+        self.stream.seek(SeekFrom::End(0)).await?;
+        let new_ptr = self.stream.stream_position().await?;
+        encode_with_rich_error(&mut self.stream, &node).await?;
         Ok(AvlPtr::new(new_ptr).unwrap())
     }
 
     async fn flush(&mut self) -> Result<()> {
-        self.stream.borrow_mut().flush().await?;
+        self.stream.flush().await?;
         Ok(())
     }
 }
@@ -1,6 +1,6 @@
 use crate::ty::{
     decode_with_rich_error, encode_with_rich_error, AttributesId, FileAttributes, FileData, FileId,
-    FileTableEntry, FileType, LayerFsVersion, LayerId,
+    FileTableEntry, FileType, FileXattrs, LayerFsVersion, LayerId,
 };
 use crate::LayerFs;
 use anyhow::Result;
@@ -37,7 +37,8 @@ impl FileMetadataReader {
                 .await?,
         )
         .await?;
-        let _header: FileTableHeader = decode_with_rich_error(&mut file_table).await?;
+        let header: FileTableHeader = decode_with_rich_error(&mut file_table).await?;
+        header.version.check()?;
         let file_table_start = file_table.stream_position().await?;
 
         let mut attr_table = BufferedStream::new(
@@ -49,7 +50,8 @@ impl FileMetadataReader {
                 .await?,
         )
         .await?;
-        let _header: AttributesTableHeader = decode_with_rich_error(&mut attr_table).await?;
+        let header: AttributesTableHeader = decode_with_rich_error(&mut attr_table).await?;
+        header.version.check()?;
         let attr_table_start = attr_table.stream_position().await?;
         Ok(Self {
             file_table,
@@ -88,6 +90,17 @@ impl FileMetadataReader {
 
         Ok((entry.kind, entry.data))
     }
+
+    pub async fn get_xattrs(&mut self, id: FileId) -> Result<FileXattrs> {
+        assert_eq!(id.layer(), self.layer_id);
+
+        self.file_table
+            .seek(SeekFrom::Start(self.file_table_start + id.offset_u64() - 1))
+            .await?;
+        let entry: FileTableEntry = decode_with_rich_error(&mut self.file_table).await?;
+
+        Ok(entry.xattrs)
+    }
 }
 
 #[derive(Copy, Clone, Default, Debug, Deserialize, Serialize)]
@@ -151,6 +164,7 @@ impl FileMetadataWriter {
         kind: FileType,
         attrs: FileAttributes,
         data: FileData,
+        xattrs: FileXattrs,
     ) -> Result<FileId> {
         let attr_id = AttributesId::try_from(
             self.attr_table.stream_position().await? - self.attr_table_start + 1,
@@ -162,6 +176,7 @@ impl FileMetadataWriter {
             kind,
             data,
             attr_id,
+            xattrs,
         };
 
         let offset =
@@ -7,10 +7,23 @@ async fn main() -> Result<()> {
     let mut args = env::args();
     args.next();
     let path = args.next().ok_or(anyhow!("expected path to fs layer"))?;
-    let offset: u32 = args
+    let offset = args
         .next()
-        .ok_or(anyhow!("expected file offset"))?
-        .parse()?;
+        .ok_or(anyhow!("expected file offset or \"verify\""))?;
+
+    if offset == "verify" {
+        let cache_path = args.next().ok_or(anyhow!("expected path to cache"))?;
+        let layer_fs = LayerFs::from_path(path.as_ref(), cache_path.as_ref())?;
+        let problems = layer_fs.verify().await?;
+        for problem in &problems {
+            println!("{problem}");
+        }
+        if !problems.is_empty() {
+            return Err(anyhow!("found {} problem(s)", problems.len()));
+        }
+        return Ok(());
+    }
+    let offset: u32 = offset.parse()?;
 
     let layer_fs = LayerFs::from_path(path.as_ref(), "/dev/null".as_ref())?;
     let layer_id = layer_fs.layer_id().await?;
@@ -9,15 +9,22 @@
 //! # Disk Layout
 //! A layer is stored on disk as a directory of files. Each directory contains the following files
 //! - `super.bin` contains information about the layer including any layers it is stacked on top of
-//! - `file_table.bin` contains a listing of all the files in the layer
+//! - `file_table.bin` contains a listing of all the files in the layer, along with any extended
+//!   attributes they have
 //! - `attributes_table.bin` contains the attributes for all the files in the layer
 //! - `<offset>.dir_data.bin` contains directory contents for the directory found at `<offset>` in
-//!    the file table.
+//!   the file table.
+//! - `sparse_data.bin` contains the data of any sparse files in the layer. It only exists if there
+//!   are some.
 //!
-//! Notice that none of the above bullets mention containing file-data. Most file-data is instead
-//! read from files outside of the layer. This what the `cache_dir` in [`LayerFs::from_path`] is
-//! used for. However, if a file contains a small amount of data it can actually be stored in the
-//! attributes as "inline data" to avoid the overhead of reading from another file.
+//! Other than for sparse files, none of the above bullets mention containing file-data. Most
+//! file-data is instead read from files outside of the layer. This what the `cache_dir` in
+//! [`LayerFs::from_path`] is used for. However, if a file contains a small amount of data it can
+//! actually be stored in the attributes as "inline data" to avoid the overhead of reading from
+//! another file.
+//!
+//! Large files from tars which contain runs of zeros are stored as sparse files. Only the parts
+//! which contain data are copied into `sparse_data.bin`, and the rest reads as zeros.
 //!
 //! # The Stacking
 //! When a layer is stacked on top of other layers, the directory entries for that layer may point
@@ -102,28 +109,35 @@ mod builder;
 mod dir;
 mod file;
 mod ty;
+mod verify;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use anyhow_trace::anyhow_trace;
+pub use avl::{AvlFault, AvlPtr};
 pub use builder::*;
 pub use dir::DirectoryDataReader;
 pub use file::FileMetadataReader;
 use futures::stream::StreamExt as _;
 use lru::LruCache;
-use maelstrom_base::Sha256Digest;
+use maelstrom_base::{Sha256Digest, Utf8Component, Utf8Path, Utf8PathBuf};
 use maelstrom_fuse::{
     AttrResponse, EntryResponse, ErrnoResult, FileAttr, FuseFileSystem, ReadLinkResponse,
-    ReadResponse, Request,
+    ReadResponse, Request, XattrResponse,
 };
 use maelstrom_linux::Errno;
 use maelstrom_util::async_fs::Fs;
 use std::ffi::OsStr;
+use std::io;
+use std::os::unix::fs::FileExt as _;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
-pub use ty::{FileAttributes, FileData, FileId, FileType, LayerId, LayerSuper};
+pub use ty::{
+    FileAttributes, FileData, FileId, FileType, FileXattrs, LayerId, LayerSuper, SparseExtent,
+};
+pub use verify::VerifyProblem;
 
 const TTL: Duration = Duration::from_secs(1); // 1 second
                                               //
@@ -255,6 +269,10 @@ impl LayerFs {
         Ok(self.data_path(layer_id).await?.join("attributes_table.bin"))
     }
 
+    async fn sparse_data_path(&self, layer_id: LayerId) -> Result<PathBuf> {
+        Ok(self.data_path(layer_id).await?.join("sparse_data.bin"))
+    }
+
     async fn layer_super(&self) -> Result<LayerSuper> {
         Ok(self.layer_super.read(&self.data_fs).await?.clone())
     }
@@ -267,6 +285,89 @@ impl LayerFs {
         self.cache_path.join(digest.to_string())
     }
 
+    /// Find the file at `path`, looking up each component in the directory before it. Symlinks
+    /// aren't followed. If some component doesn't exist, the error is an [`io::Error`] of kind
+    /// [`io::ErrorKind::NotFound`].
+    async fn look_up_path(&self, path: &Utf8Path) -> Result<(FileId, FileType)> {
+        let mut file_id = self.root().await?;
+        let mut kind = FileType::Directory;
+        let mut looked_up = Utf8PathBuf::from("/");
+        for component in path.components() {
+            let name = match component {
+                Utf8Component::RootDir | Utf8Component::CurDir => continue,
+                Utf8Component::Normal(name) => name,
+                Utf8Component::ParentDir | Utf8Component::Prefix(_) => {
+                    bail!("{path}: only normal path components are supported")
+                }
+            };
+            if kind != FileType::Directory {
+                bail!("{looked_up}: not a directory");
+            }
+            looked_up.push(name);
+            let entry = DirectoryDataReader::new(self, file_id)
+                .await?
+                .look_up_entry(name)
+                .await?
+                .and_then(|entry| entry.into_file_data())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{looked_up}: no such file or directory"),
+                    )
+                })?;
+            file_id = entry.file_id;
+            kind = entry.kind;
+        }
+        Ok((file_id, kind))
+    }
+
+    /// Read the entire contents of the regular file at `path`, without mounting the file-system.
+    /// If any component of `path` doesn't exist, the error is an [`io::Error`] of kind
+    /// [`io::ErrorKind::NotFound`].
+    pub async fn read_file(&self, path: &Utf8Path) -> Result<Vec<u8>> {
+        let (file_id, kind) = self.look_up_path(path).await?;
+        if kind != FileType::RegularFile {
+            bail!("{path}: not a regular file");
+        }
+        let (_, data) = FileMetadataReader::new(self, file_id.layer())
+            .await?
+            .get_data(file_id)
+            .await?;
+        let (data_path, length, extents) = match data {
+            FileData::Empty => return Ok(vec![]),
+            FileData::Inline(inline) => return Ok(inline),
+            FileData::Digest {
+                digest,
+                offset,
+                length,
+            } => (
+                self.cache_entry(&digest),
+                length,
+                vec![SparseExtent {
+                    offset: 0,
+                    length,
+                    data_offset: offset,
+                }],
+            ),
+            FileData::Sparse { length, extents } => (
+                self.sparse_data_path(file_id.layer()).await?,
+                length,
+                extents,
+            ),
+        };
+        let file = self.data_fs.open_file(data_path).await?.into_std().await;
+        tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+            let mut contents = vec![0; length as usize];
+            for extent in extents {
+                let start = extent.offset as usize;
+                let end = start + extent.length as usize;
+                file.read_exact_at(&mut contents[start..end], extent.data_offset)?;
+            }
+            Ok(contents)
+        })
+        .await?
+    }
+
     /// Mount the file-system in a child process. It can then be accessed via a path in `/proc`.
     /// See [`maelstrom_fuse::fuse_mount_namespace`] for more details.
     pub async fn mount(
@@ -298,6 +399,7 @@ pub struct ReaderCache {
     dir_readers: LruCache<PathBuf, Arc<Mutex<DirectoryDataReader>>>,
     file_readers: LruCache<PathBuf, Arc<Mutex<FileMetadataReader>>>,
     data_files: LruCache<Sha256Digest, Arc<std::fs::File>>,
+    sparse_data_files: LruCache<PathBuf, Arc<std::fs::File>>,
 }
 
 impl Default for ReaderCache {
@@ -312,6 +414,7 @@ impl ReaderCache {
             dir_readers: LruCache::new(300.try_into().unwrap()),
             file_readers: LruCache::new(300.try_into().unwrap()),
             data_files: LruCache::new(200.try_into().unwrap()),
+            sparse_data_files: LruCache::new(100.try_into().unwrap()),
         }
     }
 
@@ -366,6 +469,22 @@ impl ReaderCache {
             Ok(file)
         }
     }
+
+    async fn sparse_data_file(
+        &mut self,
+        layer_fs: &LayerFs,
+        layer_id: LayerId,
+    ) -> Result<Arc<std::fs::File>> {
+        let path = layer_fs.sparse_data_path(layer_id).await?;
+        if let Some(file) = self.sparse_data_files.get(&path) {
+            Ok(file.clone())
+        } else {
+            let file = layer_fs.data_fs.open_file(&path).await?;
+            let file = Arc::new(file.into_std().await);
+            self.sparse_data_files.push(path, file.clone());
+            Ok(file)
+        }
+    }
 }
 
 struct LayerFsFuseAdapter {
@@ -384,6 +503,22 @@ impl LayerFsFuseAdapter {
     }
 }
 
+impl LayerFsFuseAdapter {
+    async fn get_xattrs(&self, ino: u64) -> ErrnoResult<FileXattrs> {
+        let file = to_einval(self.log.clone(), FileId::try_from(ino))?;
+        let reader = to_eio(
+            self.log.clone(),
+            self.cache
+                .lock()
+                .await
+                .files(&self.layer_fs, file.layer())
+                .await,
+        )?;
+        let xattrs = to_eio(self.log.clone(), reader.lock().await.get_xattrs(file).await)?;
+        Ok(xattrs)
+    }
+}
+
 impl FuseFileSystem for LayerFsFuseAdapter {
     async fn look_up(&self, req: Request, parent: u64, name: &OsStr) -> ErrnoResult<EntryResponse> {
         let name = to_einval(self.log.clone(), name.to_str().ok_or("invalid name"))?;
@@ -416,13 +551,15 @@ impl FuseFileSystem for LayerFsFuseAdapter {
                 .files(&self.layer_fs, file.layer())
                 .await,
         )?;
-        let (kind, attrs) = to_eio(self.log.clone(), reader.lock().await.get_attr(file).await)?;
+        let mut reader = reader.lock().await;
+        let (kind, attrs) = to_eio(self.log.clone(), reader.get_attr(file).await)?;
+        let (_, data) = to_eio(self.log.clone(), reader.get_data(file).await)?;
         Ok(AttrResponse {
             ttl: TTL,
             attr: FileAttr {
                 ino,
                 size: attrs.size,
-                blocks: 0,
+                blocks: data.allocated_size().div_ceil(512),
                 atime: attrs.mtime.into(),
                 mtime: attrs.mtime.into(),
                 ctime: attrs.mtime.into(),
@@ -502,6 +639,59 @@ impl FuseFileSystem for LayerFsFuseAdapter {
                     length: read_length as usize,
                 })
             }
+            FileData::Sparse {
+                length: file_length,
+                extents,
+            } => {
+                let read_start = to_einval::<u64, _>(self.log.clone(), offset.try_into())?;
+                if read_start > file_length {
+                    return Err(Errno::EINVAL);
+                }
+                let read_end = std::cmp::min(read_start + size as u64, file_length);
+
+                let data_file = to_eio(
+                    self.log.clone(),
+                    self.cache
+                        .lock()
+                        .await
+                        .sparse_data_file(&self.layer_fs, file.layer())
+                        .await,
+                )?;
+
+                // If the read is entirely within one extent we can splice straight from the file,
+                // otherwise we have to fill in the holes.
+                if let Some(extent) = extents
+                    .iter()
+                    .find(|e| e.offset <= read_start && read_end <= e.offset + e.length)
+                {
+                    return Ok(ReadResponse::Splice {
+                        file: data_file,
+                        offset: extent.data_offset + (read_start - extent.offset),
+                        length: (read_end - read_start) as usize,
+                    });
+                }
+
+                let data = tokio::task::spawn_blocking(move || {
+                    let mut data = vec![0; (read_end - read_start) as usize];
+                    for extent in extents {
+                        let start = std::cmp::max(extent.offset, read_start);
+                        let end = std::cmp::min(extent.offset + extent.length, read_end);
+                        if start < end {
+                            data_file.read_exact_at(
+                                &mut data
+                                    [(start - read_start) as usize..(end - read_start) as usize],
+                                extent.data_offset + (start - extent.offset),
+                            )?;
+                        }
+                    }
+                    std::io::Result::Ok(data)
+                })
+                .await
+                .map_err(|_| Errno::EIO)?;
+                Ok(ReadResponse::Buffer {
+                    data: to_eio(self.log.clone(), data)?,
+                })
+            }
         }
     }
 
@@ -568,14 +758,39 @@ impl FuseFileSystem for LayerFsFuseAdapter {
             FileData::Inline(inline) => Ok(ReadLinkResponse {
                 data: inline.to_vec(),
             }),
-            FileData::Digest { .. } => Err(Errno::EIO),
+            FileData::Digest { .. } | FileData::Sparse { .. } => Err(Errno::EIO),
+        }
+    }
+
+    async fn get_xattr(
+        &self,
+        _req: Request,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+    ) -> ErrnoResult<XattrResponse> {
+        let name = name.to_str().ok_or(Errno::ENODATA)?;
+        let mut xattrs = self.get_xattrs(ino).await?;
+        let value = xattrs.remove(name).ok_or(Errno::ENODATA)?;
+        XattrResponse::new(value, size)
+    }
+
+    async fn list_xattr(&self, _req: Request, ino: u64, size: u32) -> ErrnoResult<XattrResponse> {
+        let xattrs = self.get_xattrs(ino).await?;
+        let mut names = vec![];
+        for name in xattrs.keys() {
+            names.extend(name.as_bytes());
+            names.push(0);
         }
+        XattrResponse::new(names, size)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::avl::AvlCheck;
+    use crate::ty::{DirectoryEntryData, DirectoryOffset};
     use maelstrom_base::manifest::UnixTimestamp;
     use maelstrom_base::{
         manifest::{ManifestEntry, ManifestEntryData, ManifestEntryMetadata, Mode},
@@ -1048,7 +1263,8 @@ mod tests {
                         let size = match &data {
                             ty::FileData::Empty => 0,
                             ty::FileData::Inline(d) => d.len() as u64,
-                            ty::FileData::Digest { length, .. } => *length,
+                            ty::FileData::Digest { length, .. }
+                            | ty::FileData::Sparse { length, .. } => *length,
                         };
                         let metadata = ManifestEntryMetadata {
                             size,
@@ -1072,6 +1288,7 @@ mod tests {
                                         assert_eq!(offset, 0);
                                         Some(digest)
                                     }
+                                    ty::FileData::Sparse { .. } => panic!(),
                                 };
                                 builder
                                     .write_entry(&ManifestEntry {
@@ -1521,6 +1738,492 @@ mod tests {
         .await
     }
 
+    #[tokio::test]
+    async fn layer_from_tar_with_sparse_file() {
+        let mut fix = Fixture::new().await;
+
+        let mut contents = vec![0; 4 * 1024 * 1024];
+        contents[..5].copy_from_slice(b"hello");
+        contents[3 * 1024 * 1024..3 * 1024 * 1024 + 5].copy_from_slice(b"world");
+        let layer_fs = fix
+            .build_bottom_layer_from_tar(vec![
+                BuildEntry::reg("Disk", contents.clone()),
+                BuildEntry::reg("Small", vec![0; 1024]),
+            ])
+            .await;
+        let sparse_data = layer_fs.top_layer_path.join("sparse_data.bin");
+
+        let mount_handle = fix.mount(layer_fs).await;
+        let mount_path = mount_handle.mount_path();
+
+        let attrs = fix.fs.metadata(mount_path.join("Disk")).await.unwrap();
+        assert_eq!(attrs.len(), contents.len() as u64);
+        assert!(attrs.blocks() * 512 <= 2 * 4096, "{}", attrs.blocks());
+        assert_eq!(
+            fix.fs.read(mount_path.join("Disk")).await.unwrap(),
+            contents
+        );
+
+        // Only the two blocks with data in them are stored.
+        assert_eq!(fix.fs.metadata(sparse_data).await.unwrap().len(), 2 * 4096);
+
+        // Small files aren't checked for holes.
+        let attrs = fix.fs.metadata(mount_path.join("Small")).await.unwrap();
+        assert_eq!(attrs.blocks(), 2);
+        assert_eq!(
+            fix.fs.read(mount_path.join("Small")).await.unwrap(),
+            vec![0; 1024]
+        );
+
+        mount_handle.umount_and_join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_healthy_layers() {
+        let mut fix = Fixture::new().await;
+
+        let mut contents = vec![0; 4 * 1024 * 1024];
+        contents[..5].copy_from_slice(b"hello");
+        let layer_fs1 = fix
+            .build_bottom_layer_from_tar(vec![
+                BuildEntry::reg("Foo", "hello world"),
+                BuildEntry::dir("Bar"),
+                BuildEntry::reg("Bar/Baz", "goodbye"),
+                BuildEntry::reg("Bar/Disk", contents),
+                BuildEntry::sym("Bar/Qux", "../Foo"),
+            ])
+            .await;
+        assert_eq!(layer_fs1.verify().await.unwrap(), vec![]);
+
+        let layer_fs2 = fix
+            .build_bottom_layer(
+                (0..100)
+                    .map(|i| BuildEntry::reg(format!("Bar/File{i}"), "a"))
+                    .collect(),
+            )
+            .await;
+        assert_eq!(layer_fs2.verify().await.unwrap(), vec![]);
+
+        let layer_fs = fix.build_upper_layer(&layer_fs1, &layer_fs2).await;
+        assert_eq!(layer_fs.verify().await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn verify_truncated_file_table() {
+        let mut fix = Fixture::new().await;
+
+        let layer_fs = fix
+            .build_bottom_layer(vec![
+                BuildEntry::reg("Foo", "hello world"),
+                BuildEntry::dir("Bar"),
+                BuildEntry::reg("Bar/Baz", "goodbye"),
+            ])
+            .await;
+
+        // "Bar/Baz" is the last entry in the file table.
+        let file_table = layer_fs.file_table_path(LayerId::BOTTOM).await.unwrap();
+        let len = fix.fs.metadata(&file_table).await.unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&file_table)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let problems = layer_fs.verify().await.unwrap();
+        assert!(
+            matches!(
+                &problems[..],
+                [VerifyProblem::BadFileId { path, .. }] if path == "/Bar/Baz"
+            ),
+            "{problems:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_corrupt_directory_tree() {
+        let mut fix = Fixture::new().await;
+
+        let layer_fs = fix
+            .build_bottom_layer(vec![
+                BuildEntry::reg("Foo", "hello world"),
+                BuildEntry::dir("Bar"),
+                BuildEntry::reg("Bar/Baz", "goodbye"),
+            ])
+            .await;
+
+        // Point the root of the tree past the end of the file.
+        let mut header = vec![];
+        ty::encode(
+            &mut header,
+            &dir::DirectoryEntryStorageHeader {
+                version: ty::LayerFsVersion::default(),
+                root: Some(AvlPtr::new(0xffff).unwrap()),
+            },
+        )
+        .await
+        .unwrap();
+        let dir_data = layer_fs
+            .dir_data_path(FileId::root(LayerId::BOTTOM))
+            .await
+            .unwrap();
+        std::os::unix::fs::FileExt::write_at(
+            &std::fs::OpenOptions::new()
+                .write(true)
+                .open(dir_data)
+                .unwrap(),
+            &header,
+            0,
+        )
+        .unwrap();
+
+        let problems = layer_fs.verify().await.unwrap();
+        assert_eq!(
+            problems,
+            vec![VerifyProblem::BadDirectoryTree {
+                path: "/".into(),
+                fault: AvlFault::BadPointer(AvlPtr::new(0xffff).unwrap()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn read_file_rejects_old_version() {
+        let mut fix = Fixture::new().await;
+
+        let layer_fs = fix
+            .build_bottom_layer(vec![BuildEntry::reg("Foo", "hello world")])
+            .await;
+        assert_eq!(
+            layer_fs.read_file("Foo".into()).await.unwrap(),
+            b"hello world"
+        );
+
+        // Rewrite the file table's header as if it were written before the format changed.
+        let mut header = vec![];
+        ty::encode(
+            &mut header,
+            &file::FileTableHeader {
+                version: ty::LayerFsVersion::V0,
+            },
+        )
+        .await
+        .unwrap();
+        let file_table = layer_fs.file_table_path(LayerId::BOTTOM).await.unwrap();
+        std::os::unix::fs::FileExt::write_at(
+            &std::fs::OpenOptions::new()
+                .write(true)
+                .open(file_table)
+                .unwrap(),
+            &header,
+            0,
+        )
+        .unwrap();
+
+        layer_fs.read_file("Foo".into()).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn read_file_from_bottom_layer() {
+        let mut fix = Fixture::new().await;
+
+        let digest = fix.add_to_cache(b"hello world").await;
+        let mut contents = vec![0; 4 * 1024 * 1024];
+        contents[..5].copy_from_slice(b"hello");
+        contents[3 * 1024 * 1024..][..5].copy_from_slice(b"world");
+
+        let layer_fs1 = fix
+            .build_bottom_layer(vec![
+                BuildEntry::reg("Foo", "hello world"),
+                BuildEntry::reg_digest("Bar", digest, 6, 5),
+                BuildEntry::dir("Baz"),
+                BuildEntry::reg_empty("Baz/Empty"),
+            ])
+            .await;
+        assert_eq!(
+            layer_fs1.read_file("/Foo".into()).await.unwrap(),
+            b"hello world"
+        );
+        assert_eq!(layer_fs1.read_file("Bar".into()).await.unwrap(), b"world");
+        assert_eq!(layer_fs1.read_file("Baz/Empty".into()).await.unwrap(), b"");
+
+        let layer_fs2 = fix
+            .build_bottom_layer_from_tar(vec![
+                BuildEntry::dir("Qux"),
+                BuildEntry::reg("Qux/Disk", contents.clone()),
+            ])
+            .await;
+        assert_eq!(
+            layer_fs2.read_file("/Qux/Disk".into()).await.unwrap(),
+            contents
+        );
+    }
+
+    #[tokio::test]
+    async fn read_file_from_upper_layer() {
+        let mut fix = Fixture::new().await;
+
+        let layer_fs1 = fix
+            .build_bottom_layer(vec![
+                BuildEntry::reg("Foo", "hello"),
+                BuildEntry::reg("Bar", "goodbye"),
+                BuildEntry::reg("Baz", "farewell"),
+            ])
+            .await;
+        let layer_fs2 = fix
+            .build_bottom_layer(vec![
+                BuildEntry::reg("Foo", "world"),
+                BuildEntry::whiteout("Baz"),
+            ])
+            .await;
+        let layer_fs = fix.build_upper_layer(&layer_fs1, &layer_fs2).await;
+
+        assert_eq!(layer_fs.read_file("Foo".into()).await.unwrap(), b"world");
+        assert_eq!(layer_fs.read_file("Bar".into()).await.unwrap(), b"goodbye");
+        let err = layer_fs.read_file("Baz".into()).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn read_file_errors() {
+        let mut fix = Fixture::new().await;
+
+        let layer_fs = fix
+            .build_bottom_layer(vec![
+                BuildEntry::reg("Foo", "hello world"),
+                BuildEntry::dir("Bar"),
+            ])
+            .await;
+
+        for path in ["Qux", "Bar/Qux", "Qux/Foo"] {
+            let err = layer_fs.read_file(path.into()).await.unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<io::Error>().unwrap().kind(),
+                io::ErrorKind::NotFound,
+                "{path}: {err}"
+            );
+        }
+        assert_eq!(
+            layer_fs
+                .read_file("Foo/Qux".into())
+                .await
+                .unwrap_err()
+                .root_cause()
+                .to_string(),
+            "/Foo: not a directory"
+        );
+        assert_eq!(
+            layer_fs
+                .read_file("Bar".into())
+                .await
+                .unwrap_err()
+                .root_cause()
+                .to_string(),
+            "Bar: not a regular file"
+        );
+        assert_eq!(
+            layer_fs
+                .read_file("Bar/../Foo".into())
+                .await
+                .unwrap_err()
+                .root_cause()
+                .to_string(),
+            "Bar/../Foo: only normal path components are supported"
+        );
+    }
+
+    #[tokio::test]
+    async fn upper_layer_with_sparse_file() {
+        let mut fix = Fixture::new().await;
+
+        let mut contents = vec![0; 2 * 1024 * 1024];
+        contents[1024 * 1024..1024 * 1024 + 5].copy_from_slice(b"hello");
+        let lower = fix
+            .build_bottom_layer_from_tar(vec![BuildEntry::reg("Foo", b"foo".to_vec())])
+            .await;
+        let upper = fix
+            .build_bottom_layer_from_tar(vec![BuildEntry::reg("Disk", contents.clone())])
+            .await;
+        let layer_fs = fix.build_upper_layer(&lower, &upper).await;
+
+        assert_eq!(layer_fs.read_file("/Disk".into()).await.unwrap(), contents);
+
+        let mount_handle = fix.mount(layer_fs).await;
+        let mount_path = mount_handle.mount_path();
+        assert_eq!(
+            fix.fs.read(mount_path.join("Disk")).await.unwrap(),
+            contents
+        );
+        assert_eq!(fix.fs.read(mount_path.join("Foo")).await.unwrap(), b"foo");
+
+        mount_handle.umount_and_join().await.unwrap();
+    }
+
+    fn pax_record(key: &str, value: &str) -> Vec<u8> {
+        // The length prefix includes itself, so keep growing it until it is stable.
+        let body = format!(" {key}={value}\n");
+        let mut len = body.len();
+        while format!("{len}").len() + body.len() != len {
+            len = format!("{len}").len() + body.len();
+        }
+        format!("{len}{body}").into_bytes()
+    }
+
+    #[tokio::test]
+    async fn layer_from_tar_with_xattrs() {
+        let mut fix = Fixture::new().await;
+
+        let tar_path = fix.cache_dir.join("temp.tar");
+        let f = fix.fs.create_file(&tar_path).await.unwrap();
+        let mut ar = tokio_tar::Builder::new(f.into_inner());
+        for (path, type_) in [
+            ("Foo", tokio_tar::EntryType::Regular),
+            ("Bar", tokio_tar::EntryType::Directory),
+        ] {
+            let mut pax = pax_record("SCHILY.xattr.user.color", "blue");
+            pax.extend(pax_record("SCHILY.xattr.security.label", path));
+            let mut header = tokio_tar::Header::new_ustar();
+            header.set_entry_type(tokio_tar::EntryType::XHeader);
+            header.set_size(pax.len() as u64);
+            ar.append_data(&mut header, format!("PaxHeaders/{path}"), &pax[..])
+                .await
+                .unwrap();
+
+            let mut header = tokio_tar::Header::new_ustar();
+            header.set_entry_type(type_);
+            header.set_size(0);
+            header.set_mode(0o555);
+            ar.append_data(&mut header, path, tokio::io::empty())
+                .await
+                .unwrap();
+        }
+        let mut header = tokio_tar::Header::new_ustar();
+        header.set_size(0);
+        header.set_mode(0o555);
+        ar.append_data(&mut header, "Baz", tokio::io::empty())
+            .await
+            .unwrap();
+        ar.finish().await.unwrap();
+
+        let digest = calc_digest(&fix.fs, &tar_path).await;
+        let final_path = fix.cache_dir.join(digest.to_string());
+        fix.fs.rename(tar_path, &final_path).await.unwrap();
+
+        let data_dir = fix.new_data_dir().await;
+        let mut builder = fix.bottom_layer_builder(&data_dir).await;
+        builder
+            .add_from_tar(digest, fix.fs.open_file(final_path).await.unwrap())
+            .await
+            .unwrap();
+        let layer_fs = builder.finish().await.unwrap();
+
+        let mut dir_reader = DirectoryDataReader::new(&layer_fs, layer_fs.root().await.unwrap())
+            .await
+            .unwrap();
+        let mut file_reader = FileMetadataReader::new(&layer_fs, LayerId::BOTTOM)
+            .await
+            .unwrap();
+        for path in ["Foo", "Bar"] {
+            let file_id = dir_reader.look_up(path).await.unwrap().unwrap();
+            let xattrs = file_reader.get_xattrs(file_id).await.unwrap();
+            assert_eq!(
+                xattrs,
+                FileXattrs::from([
+                    ("security.label".into(), path.as_bytes().to_vec()),
+                    ("user.color".into(), b"blue".to_vec()),
+                ])
+            );
+        }
+        let file_id = dir_reader.look_up("Baz").await.unwrap().unwrap();
+        assert_eq!(
+            file_reader.get_xattrs(file_id).await.unwrap(),
+            FileXattrs::new()
+        );
+    }
+
+    async fn open_directory(
+        layer_fs: &LayerFs,
+        file_id: FileId,
+        mapped: bool,
+    ) -> DirectoryDataReader {
+        if mapped {
+            DirectoryDataReader::new_mapped(layer_fs, file_id)
+                .await
+                .unwrap()
+        } else {
+            DirectoryDataReader::new_buffered(layer_fs, file_id)
+                .await
+                .unwrap()
+        }
+    }
+
+    /// Read a directory every way a [`DirectoryDataReader`] can: looking up each of the given
+    /// names, checking the tree, then iterating in file order and in name order.
+    async fn read_whole_directory(
+        layer_fs: &LayerFs,
+        file_id: FileId,
+        mapped: bool,
+        names: &[String],
+    ) -> (
+        Vec<Option<DirectoryEntryData>>,
+        AvlCheck<String, DirectoryEntryData>,
+        Vec<(u64, String, DirectoryEntryData)>,
+        Vec<(String, DirectoryEntryData)>,
+    ) {
+        let mut reader = open_directory(layer_fs, file_id, mapped).await;
+        let mut looked_up = vec![];
+        for name in names {
+            looked_up.push(reader.look_up_entry(name).await.unwrap());
+        }
+        let check = reader.check().await.unwrap();
+        let unordered = reader
+            .into_stream(DirectoryOffset::try_from(0).unwrap())
+            .await
+            .unwrap()
+            .map(|e| {
+                let (offset, entry) = e.unwrap();
+                (offset, entry.key, entry.value)
+            })
+            .collect()
+            .await;
+        let ordered = open_directory(layer_fs, file_id, mapped)
+            .await
+            .into_ordered_stream()
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        (looked_up, check, unordered, ordered)
+    }
+
+    #[tokio::test]
+    async fn buffered_and_mapped_directory_readers_agree() {
+        let mut fix = Fixture::new().await;
+
+        let mut names: Vec<_> = (0..2000).map(|i| format!("File{i}")).collect();
+        let layer_fs = fix
+            .build_bottom_layer(names.iter().map(|n| BuildEntry::reg(n, "a")).collect())
+            .await;
+        names.extend(["Missing".into(), "".into(), "File2000".into()]);
+
+        let root = layer_fs.root().await.unwrap();
+        let buffered = read_whole_directory(&layer_fs, root, false, &names).await;
+        let mapped = read_whole_directory(&layer_fs, root, true, &names).await;
+
+        assert_eq!(buffered.0.iter().filter(|e| e.is_some()).count(), 2000);
+        assert_eq!(buffered.1.faults, vec![]);
+        assert_eq!(buffered.2.len(), 2000);
+        assert_eq!(buffered.0, mapped.0);
+        assert_eq!(buffered.1.entries, mapped.1.entries);
+        assert_eq!(buffered.1.faults, mapped.1.faults);
+        assert_eq!(buffered.2, mapped.2);
+        assert_eq!(buffered.3, mapped.3);
+    }
+
     async fn two_layer_test(lower: Vec<&str>, upper: Vec<&str>, expected: Vec<Expect>) {
         let mut fix = Fixture::new().await;
 
@@ -1,4 +1,4 @@
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Context as _, Result};
 use derive_more::{From, Into};
 use maelstrom_base::{
     manifest::{Mode, UnixTimestamp},
@@ -10,7 +10,7 @@ use maelstrom_linux::Errno;
 use maelstrom_util::async_fs::{Fs, GetPath};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::num::{NonZeroU32, NonZeroU64};
 use std::path::Path;
 use std::path::PathBuf;
@@ -122,14 +122,14 @@ impl TryFrom<i64> for DirectoryOffset {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub struct DirectoryEntryFileData {
     pub file_id: FileId,
     pub kind: FileType,
     pub opaque_dir: bool,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, From)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, From)]
 pub enum DirectoryEntryData {
     Whiteout,
     FileData(DirectoryEntryFileData),
@@ -155,11 +155,27 @@ impl DirectoryEntryData {
     }
 }
 
-#[derive(Copy, Clone, Default, Debug, Deserialize_repr, Serialize_repr)]
+/// The version of the on-disk format. Layers written with a different version can't be read.
+#[derive(Copy, Clone, Default, Debug, Deserialize_repr, Eq, PartialEq, Serialize_repr)]
 #[repr(u32)]
 pub enum LayerFsVersion {
-    #[default]
     V0 = 0,
+    /// File table entries include the file's extended attributes.
+    #[default]
+    V1 = 1,
+}
+
+impl LayerFsVersion {
+    /// Return an error if this isn't the version this crate writes.
+    pub fn check(self) -> Result<()> {
+        if self != Self::default() {
+            bail!(
+                "layer has LayerFS version {self:?}, but only {:?} is supported",
+                Self::default()
+            );
+        }
+        Ok(())
+    }
 }
 
 #[derive(Copy, Clone, Debug, Deserialize, Serialize)]
@@ -197,14 +213,45 @@ pub enum FileData {
         offset: u64,
         length: u64,
     },
+    /// A file of `length` bytes where only the given extents contain data. Everything else is a
+    /// hole and reads as zeros. The extents' data is stored in the layer's `sparse_data.bin`.
+    Sparse {
+        length: u64,
+        extents: Vec<SparseExtent>,
+    },
 }
 
+impl FileData {
+    /// The number of bytes of the file which are backed by actual data.
+    pub fn allocated_size(&self) -> u64 {
+        match self {
+            Self::Empty => 0,
+            Self::Inline(data) => data.len() as u64,
+            Self::Digest { length, .. } => *length,
+            Self::Sparse { extents, .. } => extents.iter().map(|e| e.length).sum(),
+        }
+    }
+}
+
+/// A range of a sparse file which contains data. The `offset` is relative to the start of the file,
+/// and the `data_offset` is where the data is found in the layer's `sparse_data.bin`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SparseExtent {
+    pub offset: u64,
+    pub length: u64,
+    pub data_offset: u64,
+}
+
+/// Extended attributes for a file, keyed by their full name (e.g. `user.foo`).
+pub type FileXattrs = BTreeMap<String, Vec<u8>>;
+
 /// What is stored in the file-table about each file.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct FileTableEntry {
     pub kind: FileType,
     pub data: FileData,
     pub attr_id: AttributesId,
+    pub xattrs: FileXattrs,
 }
 
 pub async fn decode<T: DeserializeOwned>(mut stream: impl AsyncRead + Unpin) -> Result<T> {
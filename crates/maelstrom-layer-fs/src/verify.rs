@@ -0,0 +1,228 @@
+use crate::avl::AvlFault;
+use crate::dir::DirectoryDataReader;
+use crate::file::FileMetadataReader;
+use crate::ty::{DirectoryEntryData, FileData, FileId, FileType, LayerId};
+use crate::LayerFs;
+use anyhow::Result;
+use anyhow_trace::anyhow_trace;
+use maelstrom_base::{Sha256Digest, Utf8PathBuf};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::fmt;
+
+/// A problem found by [`LayerFs::verify`]. Each problem contains the path in the file-system
+/// where it was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyProblem {
+    /// The directory's data couldn't be read at all.
+    UnreadableDirectory {
+        path: Utf8PathBuf,
+        file_id: FileId,
+        error: String,
+    },
+    /// The AVL tree making up the directory isn't well-formed.
+    BadDirectoryTree {
+        path: Utf8PathBuf,
+        fault: AvlFault<String>,
+    },
+    /// The `FileId` doesn't refer to a readable entry in a file-table.
+    BadFileId {
+        path: Utf8PathBuf,
+        file_id: FileId,
+        error: String,
+    },
+    /// The blob containing the file's data isn't in the cache.
+    MissingData {
+        path: Utf8PathBuf,
+        digest: Sha256Digest,
+    },
+    /// The file's data extends past the end of the blob containing it.
+    DataOutOfBounds {
+        path: Utf8PathBuf,
+        digest: Sha256Digest,
+        end: u64,
+        blob_length: u64,
+    },
+    /// The sparse file's data extends past the end of its layer's `sparse_data.bin`. If that file
+    /// is missing, its length is zero.
+    SparseDataOutOfBounds {
+        path: Utf8PathBuf,
+        end: u64,
+        data_length: u64,
+    },
+}
+
+impl fmt::Display for VerifyProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnreadableDirectory {
+                path,
+                file_id,
+                error,
+            } => write!(f, "{path}: unreadable directory {file_id:?}: {error}"),
+            Self::BadDirectoryTree { path, fault } => {
+                write!(f, "{path}: malformed directory: {fault:?}")
+            }
+            Self::BadFileId {
+                path,
+                file_id,
+                error,
+            } => write!(f, "{path}: bad file id {file_id:?}: {error}"),
+            Self::MissingData { path, digest } => write!(f, "{path}: missing data {digest}"),
+            Self::DataOutOfBounds {
+                path,
+                digest,
+                end,
+                blob_length,
+            } => write!(
+                f,
+                "{path}: data ends at {end} past the end of {digest} ({blob_length} bytes)"
+            ),
+            Self::SparseDataOutOfBounds {
+                path,
+                end,
+                data_length,
+            } => write!(
+                f,
+                "{path}: sparse data ends at {end} past the end of sparse_data.bin \
+                ({data_length} bytes)"
+            ),
+        }
+    }
+}
+
+#[anyhow_trace]
+impl LayerFs {
+    /// Walk every directory and file in the file-system checking that all the on-disk data
+    /// structures are intact. Returns the problems found, which is empty for a healthy layer.
+    ///
+    /// An error is only returned when the layer itself can't be opened.
+    pub async fn verify(&self) -> Result<Vec<VerifyProblem>> {
+        let mut problems = vec![];
+        let mut file_readers: HashMap<LayerId, FileMetadataReader> = HashMap::new();
+        let mut seen_dirs = HashSet::new();
+        let mut dirs = vec![(Utf8PathBuf::from("/"), self.root().await?)];
+
+        while let Some((path, dir_id)) = dirs.pop() {
+            if !seen_dirs.insert(dir_id) {
+                continue;
+            }
+            let check = match DirectoryDataReader::new(self, dir_id).await {
+                Ok(mut reader) => reader.check().await,
+                Err(err) => Err(err),
+            };
+            let check = match check {
+                Ok(check) => check,
+                Err(err) => {
+                    problems.push(VerifyProblem::UnreadableDirectory {
+                        path,
+                        file_id: dir_id,
+                        error: format!("{err:#}"),
+                    });
+                    continue;
+                }
+            };
+            problems.extend(check.faults.into_iter().map(|fault| {
+                VerifyProblem::BadDirectoryTree {
+                    path: path.clone(),
+                    fault,
+                }
+            }));
+
+            for (name, entry) in check.entries {
+                let DirectoryEntryData::FileData(entry) = entry else {
+                    continue;
+                };
+                let path = path.join(name);
+                let file_id = entry.file_id;
+
+                let reader = match file_readers.entry(file_id.layer()) {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => {
+                        match FileMetadataReader::new(self, file_id.layer()).await {
+                            Ok(reader) => e.insert(reader),
+                            Err(err) => {
+                                problems.push(VerifyProblem::BadFileId {
+                                    path,
+                                    file_id,
+                                    error: format!("{err:#}"),
+                                });
+                                continue;
+                            }
+                        }
+                    }
+                };
+                let data = match reader.get_attr(file_id).await {
+                    Ok(_) => reader.get_data(file_id).await,
+                    Err(err) => Err(err),
+                };
+                let data = match data {
+                    Ok((_, data)) => data,
+                    Err(err) => {
+                        problems.push(VerifyProblem::BadFileId {
+                            path,
+                            file_id,
+                            error: format!("{err:#}"),
+                        });
+                        continue;
+                    }
+                };
+
+                if let Some(problem) = self.verify_data(&path, file_id.layer(), data).await {
+                    problems.push(problem);
+                }
+                if entry.kind == FileType::Directory {
+                    dirs.push((path, file_id));
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    async fn verify_data(
+        &self,
+        path: &Utf8PathBuf,
+        layer_id: LayerId,
+        data: FileData,
+    ) -> Option<VerifyProblem> {
+        let (digest, end) = match data {
+            FileData::Empty | FileData::Inline(_) => return None,
+            FileData::Digest {
+                digest,
+                offset,
+                length,
+            } => (digest, offset + length),
+            FileData::Sparse { extents, .. } => {
+                let end = extents
+                    .iter()
+                    .map(|e| e.data_offset + e.length)
+                    .max()
+                    .unwrap_or(0);
+                let data_length = match self.sparse_data_path(layer_id).await {
+                    Ok(data_path) => match self.data_fs.metadata(data_path).await {
+                        Ok(metadata) => metadata.len(),
+                        Err(_) => 0,
+                    },
+                    Err(_) => 0,
+                };
+                return (end > data_length).then(|| VerifyProblem::SparseDataOutOfBounds {
+                    path: path.clone(),
+                    end,
+                    data_length,
+                });
+            }
+        };
+        let Ok(metadata) = self.data_fs.metadata(self.cache_entry(&digest)).await else {
+            return Some(VerifyProblem::MissingData {
+                path: path.clone(),
+                digest,
+            });
+        };
+        (end > metadata.len()).then(|| VerifyProblem::DataOutOfBounds {
+            path: path.clone(),
+            digest,
+            end,
+            blob_length: metadata.len(),
+        })
+    }
+}
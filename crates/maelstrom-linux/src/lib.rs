@@ -158,6 +158,7 @@ impl Errno {
     pub const EPIPE: Self = Self(libc::EPIPE);
     pub const EDOM: Self = Self(libc::EDOM);
     pub const ERANGE: Self = Self(libc::ERANGE);
+    pub const ENODATA: Self = Self(libc::ENODATA);
     pub const EWOULDBLOCK: Self = Self::EAGAIN;
 }
 
@@ -427,7 +428,53 @@ impl PollFd {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default, Into)]
+/// Resource usage of a process, as returned by [`waitid`].
+#[repr(transparent)]
+pub struct Rusage(libc::rusage);
+
+impl Rusage {
+    fn duration_from_timeval(timeval: libc::timeval) -> Duration {
+        Duration::new(
+            timeval.tv_sec.try_into().unwrap(),
+            (timeval.tv_usec * 1000).try_into().unwrap(),
+        )
+    }
+
+    pub fn user_time(&self) -> Duration {
+        Self::duration_from_timeval(self.0.ru_utime)
+    }
+
+    pub fn system_time(&self) -> Duration {
+        Self::duration_from_timeval(self.0.ru_stime)
+    }
+
+    pub fn voluntary_context_switches(&self) -> u64 {
+        self.0.ru_nvcsw.try_into().unwrap()
+    }
+
+    pub fn involuntary_context_switches(&self) -> u64 {
+        self.0.ru_nivcsw.try_into().unwrap()
+    }
+}
+
+#[repr(transparent)]
+pub struct SigSet(libc::sigset_t);
+
+impl SigSet {
+    pub fn empty() -> Self {
+        let mut set = unsafe { mem::zeroed() };
+        unsafe { libc::sigemptyset(&mut set) };
+        Self(set)
+    }
+
+    pub fn full() -> Self {
+        let mut set = unsafe { mem::zeroed() };
+        unsafe { libc::sigfillset(&mut set) };
+        Self(set)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, Into, PartialEq)]
 pub struct Signal(c_int);
 
 impl Signal {
@@ -712,6 +759,15 @@ pub fn setsid() -> Result<(), Errno> {
     Errno::result(unsafe { libc::setsid() }).map(drop)
 }
 
+pub fn sigprocmask_setmask(set: &SigSet) -> Result<(), Errno> {
+    Errno::result(unsafe { libc::sigprocmask(libc::SIG_SETMASK, &set.0, ptr::null_mut()) })
+        .map(drop)
+}
+
+pub fn sigwaitinfo(set: &SigSet) -> Result<Signal, Errno> {
+    Errno::result(unsafe { libc::sigwaitinfo(&set.0, ptr::null_mut()) }).map(Signal)
+}
+
 pub fn socket(
     domain: SocketDomain,
     type_: SocketType,
@@ -727,6 +783,10 @@ pub fn umount2(path: &CStr, flags: UmountFlags) -> Result<(), Errno> {
     Errno::result(unsafe { libc::umount2(path_ptr, flags.0) }).map(drop)
 }
 
+pub fn unshare(flags: CloneFlags) -> Result<(), Errno> {
+    Errno::result(unsafe { libc::unshare(flags.0) }).map(drop)
+}
+
 pub fn _exit(status: ExitCode) -> ! {
     unsafe { libc::_exit(status.0) };
 }
@@ -766,6 +826,22 @@ pub fn wait() -> Result<WaitResult, Errno> {
     })
 }
 
+/// Like [`wait`], but return `Ok(None)` instead of blocking if there are children, but none of
+/// them have terminated yet.
+pub fn wait_nohang() -> Result<Option<WaitResult>, Errno> {
+    let inner = |status: &mut c_int| {
+        let status_ptr = status as *mut c_int;
+        unsafe { libc::waitpid(-1, status_ptr, libc::WNOHANG) }
+    };
+    let mut status = 0;
+    Errno::result(inner(&mut status)).map(|pid| {
+        (pid != 0).then(|| WaitResult {
+            pid: Pid(pid),
+            status: extract_wait_status(status),
+        })
+    })
+}
+
 pub fn waitpid(pid: Pid) -> Result<WaitStatus, Errno> {
     let inner = |status: &mut c_int| {
         let status_ptr = status as *mut c_int;
@@ -776,16 +852,42 @@ pub fn waitpid(pid: Pid) -> Result<WaitStatus, Errno> {
     Errno::result(inner(&mut status)).map(|_| extract_wait_status(status))
 }
 
-pub fn waitid(pidfd: Fd) -> Result<WaitStatus, Errno> {
-    let inner = |siginfo: &mut siginfo_t| {
+/// Like [`waitpid`], but return `Ok(None)` instead of blocking if `pid` hasn't terminated yet.
+pub fn waitpid_nohang(pid: Pid) -> Result<Option<WaitStatus>, Errno> {
+    let inner = |status: &mut c_int| {
+        let status_ptr = status as *mut c_int;
+        unsafe { libc::waitpid(pid.0, status_ptr, libc::WNOHANG) }
+    };
+    let mut status = 0;
+    Errno::result(inner(&mut status)).map(|pid| (pid != 0).then(|| extract_wait_status(status)))
+}
+
+/// Wait for the process referred to by `pidfd` to terminate. Along with how it terminated, this
+/// returns the resources used by the process and all of the descendants it waited for.
+pub fn waitid(pidfd: Fd) -> Result<(WaitStatus, Rusage), Errno> {
+    // The libc wrapper doesn't let us pass in a rusage, so we have to make the system call
+    // ourselves.
+    let inner = |siginfo: &mut siginfo_t, rusage: &mut libc::rusage| {
         let idtype = libc::P_PIDFD as idtype_t;
         let id = pidfd.0 as id_t;
         let siginfo_ptr = siginfo as *mut siginfo_t;
         let options = libc::WEXITED;
-        unsafe { libc::waitid(idtype, id, siginfo_ptr, options) }
+        let rusage_ptr = rusage as *mut libc::rusage;
+        unsafe {
+            libc::syscall(
+                libc::SYS_waitid,
+                idtype,
+                id,
+                siginfo_ptr,
+                options,
+                rusage_ptr,
+            )
+        }
     };
     let mut siginfo = unsafe { mem::zeroed() };
-    Errno::result(inner(&mut siginfo)).map(|_| extract_wait_status_from_siginfo(siginfo))
+    let mut rusage = unsafe { mem::zeroed() };
+    Errno::result(inner(&mut siginfo, &mut rusage))
+        .map(|_| (extract_wait_status_from_siginfo(siginfo), Rusage(rusage)))
 }
 
 pub fn write(fd: Fd, buf: &[u8]) -> Result<usize, Errno> {
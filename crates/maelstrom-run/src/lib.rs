@@ -1 +1,5 @@
+pub mod progress;
+pub mod schedule;
 pub mod spec;
+pub mod status;
+pub mod summary;
@@ -1,23 +1,41 @@
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+use clap::Args;
+use indicatif::ProgressDrawTarget;
 use maelstrom_base::{
     ClientJobId, JobCompleted, JobEffects, JobError, JobOutcome, JobOutcomeResult, JobOutputResult,
-    JobStatus,
+    JobSpec, JobStatus,
 };
 use maelstrom_client::{
-    spec::{std_env_lookup, ImageConfig},
+    spec::{split_image_reference, std_env_lookup, Image, ImageConfig},
     Client, ClientBgProcess,
 };
 use maelstrom_macro::Config;
-use maelstrom_run::spec::job_spec_iter_from_reader;
+use maelstrom_run::{
+    progress::JobProgress,
+    schedule::{JobIndex, JobScheduler},
+    spec::{
+        job_spec_iter_from_files, job_spec_iter_from_reader, job_spec_iter_from_toml_reader,
+        validate_job_specs_from_files, validate_job_specs_from_reader,
+        validate_job_specs_from_toml_reader, ImageUses, JobSpecFormat, NamedJobSpec,
+    },
+    status::StatusStyle,
+    summary::JobSummary,
+};
 use maelstrom_util::{
-    config::common::{BrokerAddr, CacheSize, InlineLimit, LogLevel, Slots},
+    config::common::{
+        BrokerAddr, CacheRoot, CacheSize, ColorMode, DispatcherThreads, HashingThreads,
+        InlineLimit, LogLevel, Slots,
+    },
     fs::Fs,
-    process::{ExitCode, ExitCodeAccumulator},
+    process::ExitCode,
 };
 use std::{
-    io::{self, Read, Write as _},
+    env,
+    io::{self, IsTerminal as _, Write as _},
     path::PathBuf,
-    sync::Arc,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
 };
 use xdg::BaseDirectories;
 
@@ -36,6 +54,33 @@ pub struct Config {
     #[config(short = 'l', value_name = "LEVEL", default = r#""info""#)]
     pub log_level: LogLevel,
 
+    /// Show a progress bar with how many jobs have been submitted, completed, and failed. The bar
+    /// is only shown when stderr is a terminal.
+    #[config(flag)]
+    pub progress: bool,
+
+    /// When to color the status and error lines printed about jobs: "auto", "always", or "never".
+    /// With "auto", they are colored if stderr is a terminal.
+    #[config(value_name = "WHEN", default = r#""auto""#)]
+    pub color: ColorMode,
+
+    /// The image to use for jobs that don't have an `image` field of their own.
+    #[config(option, value_name = "IMAGE", default = r#""no default image""#)]
+    pub default_image: Option<String>,
+
+    /// The parts of the default image that jobs use, as a comma-separated list of "layers",
+    /// "environment", and "working_directory".
+    #[config(value_name = "USES", default = "ImageUses::default()")]
+    pub default_image_use: ImageUses,
+
+    /// The directory to use for the cache. This is where the local worker keeps its artifacts, and
+    /// where layers are built before they are uploaded.
+    #[config(
+        value_name = "PATH",
+        default = "|bd: &BaseDirectories| bd.get_cache_home().into_os_string().into_string().unwrap()"
+    )]
+    pub cache_dir: CacheRoot,
+
     /// The target amount of disk space to use for the cache. This bound won't be followed
     /// strictly, so it's best to be conservative. SI and binary suffixes are supported.
     #[config(
@@ -53,6 +98,39 @@ pub struct Config {
     /// The number of job slots available.
     #[config(short = 'S', value_name = "N", default = "Slots::default()")]
     pub slots: Slots,
+
+    /// Shut the local worker down once it has had no jobs for this many seconds, releasing its
+    /// cache. A new local worker is started when the next job arrives.
+    #[config(option, value_name = "SECONDS", default = r#""never""#)]
+    pub local_worker_idle_timeout: Option<u32>,
+
+    /// The maximum number of artifacts to hash at once.
+    #[config(
+        value_name = "N",
+        default = "HashingThreads::default()",
+        next_help_heading = "Client Options"
+    )]
+    pub hashing_threads: HashingThreads,
+
+    /// The number of worker threads the client uses to send requests to the client process.
+    #[config(value_name = "N", default = "DispatcherThreads::default()")]
+    pub dispatcher_threads: DispatcherThreads,
+
+    /// Put the layers of each job in a canonical order before submitting it, moving layers around
+    /// only when they don't share any paths. Jobs whose layers differ only in order then share
+    /// cached file systems on the workers.
+    #[config(flag)]
+    pub canonicalize_layer_order: bool,
+
+    /// Ping the client process over its connection this often, in seconds, so that a connection
+    /// that has silently died is noticed instead of leaving requests waiting forever.
+    #[config(option, value_name = "SECONDS", default = r#""never""#)]
+    pub client_keep_alive_interval: Option<u32>,
+
+    /// Cancel a control request to the client process, like canceling jobs or getting stats, if no
+    /// response arrives in this many seconds. Adding artifacts, layers, and jobs isn't limited.
+    #[config(option, value_name = "SECONDS", default = r#""never""#)]
+    pub client_request_timeout: Option<u32>,
 }
 
 fn print_effects(
@@ -61,7 +139,9 @@ fn print_effects(
         stdout,
         stderr,
         duration: _,
+        resource_usage: _,
     }: JobEffects,
+    style: &StatusStyle,
 ) -> Result<()> {
     match stdout {
         JobOutputResult::None => {}
@@ -71,7 +151,12 @@ fn print_effects(
         JobOutputResult::Truncated { first, truncated } => {
             io::stdout().lock().write_all(&first)?;
             io::stdout().lock().flush()?;
-            eprintln!("job {cjid}: stdout truncated, {truncated} bytes lost");
+            eprintln!(
+                "{}",
+                style.warning(format!(
+                    "job {cjid}: stdout truncated, {truncated} bytes lost"
+                ))
+            );
         }
     }
     match stderr {
@@ -81,62 +166,286 @@ fn print_effects(
         }
         JobOutputResult::Truncated { first, truncated } => {
             io::stderr().lock().write_all(&first)?;
-            eprintln!("job {cjid}: stderr truncated, {truncated} bytes lost");
+            eprintln!(
+                "{}",
+                style.warning(format!(
+                    "job {cjid}: stderr truncated, {truncated} bytes lost"
+                ))
+            );
         }
     }
     Ok(())
 }
 
-fn visitor(cjid: ClientJobId, result: JobOutcomeResult, accum: Arc<ExitCodeAccumulator>) {
+/// Report the outcome of a job, or the error that kept us from getting it, returning whether the
+/// job succeeded. Exiting with one of `allowed_exit_codes` counts as success.
+fn visitor(
+    result: Result<(ClientJobId, JobOutcomeResult)>,
+    allowed_exit_codes: &[u8],
+    summary: Arc<JobSummary>,
+    style: &StatusStyle,
+) -> bool {
+    let (cjid, result) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            summary.add_errored();
+            eprintln!("{}", style.warning(format!("job error: {err:#}")));
+            return false;
+        }
+    };
+    let succeeded = summary.add_outcome(&result, allowed_exit_codes);
     match result {
         Ok(JobOutcome::Completed(JobCompleted { status, effects })) => {
-            print_effects(cjid, effects).ok();
+            print_effects(cjid, effects, style).ok();
             match status {
-                JobStatus::Exited(0) => {}
+                JobStatus::Exited(code) if code == 0 || allowed_exit_codes.contains(&code) => {}
                 JobStatus::Exited(code) => {
                     io::stdout().lock().flush().ok();
-                    eprintln!("job {cjid}: exited with code {code}");
-                    accum.add(ExitCode::from(code));
+                    eprintln!(
+                        "{}",
+                        style.failure(format!("job {cjid}: exited with code {code}"))
+                    );
                 }
                 JobStatus::Signaled(signum) => {
                     io::stdout().lock().flush().ok();
-                    eprintln!("job {cjid}: killed by signal {signum}");
-                    accum.add(ExitCode::FAILURE);
+                    eprintln!(
+                        "{}",
+                        style.failure(format!("job {cjid}: killed by signal {signum}"))
+                    );
                 }
-            };
+            }
         }
         Ok(JobOutcome::TimedOut(effects)) => {
-            print_effects(cjid, effects).ok();
+            print_effects(cjid, effects, style).ok();
             io::stdout().lock().flush().ok();
-            eprintln!("job {cjid}: timed out");
-            accum.add(ExitCode::FAILURE);
+            eprintln!("{}", style.failure(format!("job {cjid}: timed out")));
         }
         Err(JobError::Execution(err)) => {
-            eprintln!("job {cjid}: execution error: {err}");
-            accum.add(ExitCode::FAILURE);
+            eprintln!(
+                "{}",
+                style.warning(format!("job {cjid}: execution error: {err}"))
+            );
         }
         Err(JobError::System(err)) => {
-            eprintln!("job {cjid}: system error: {err}");
-            accum.add(ExitCode::FAILURE);
+            eprintln!(
+                "{}",
+                style.warning(format!("job {cjid}: system error: {err}"))
+            );
         }
     }
+    succeeded
+}
+
+/// What [`submit_jobs`] is told about.
+enum Event {
+    /// A job was read from the input.
+    JobRead(Box<NamedJobSpec>),
+    /// All of the input has been read successfully.
+    EndOfInput,
+    /// Reading the input failed. Nothing more will be submitted.
+    InputFailed,
+    /// A submitted job finished, successfully or not.
+    JobFinished(JobIndex, bool),
 }
 
-fn cache_dir() -> PathBuf {
-    BaseDirectories::with_prefix("maelstrom/run")
-        .expect("failed to find cache dir")
-        .get_cache_file("")
+/// Submit jobs as they're read, until all of them have been read and the submitted ones have
+/// finished. A job without dependencies is submitted as soon as it's read. A job with dependencies
+/// is held back until all of them have succeeded, which means we have to hear back about each job
+/// before we know what else to submit.
+fn submit_jobs(
+    client: &Client,
+    events: mpsc::Receiver<Event>,
+    sender: mpsc::Sender<Event>,
+    summary: &Arc<JobSummary>,
+    style: &Arc<StatusStyle>,
+    progress: &Option<Arc<JobProgress>>,
+) -> Result<()> {
+    let report_skipped = |skipped: Vec<String>| {
+        for skipped in skipped {
+            let report = || {
+                eprintln!(
+                    "{}",
+                    style.warning(format!("{skipped}: skipped because a dependency failed"))
+                )
+            };
+            match progress {
+                Some(progress) => {
+                    progress.suspend(report);
+                    progress.job_skipped();
+                }
+                None => report(),
+            }
+            summary.add_skipped();
+        }
+    };
+
+    let mut scheduler = JobScheduler::<(JobSpec, Vec<u8>)>::new();
+    let mut running = 0;
+    let mut end_of_input = false;
+    loop {
+        for (index, (job_spec, allowed_exit_codes)) in scheduler.take_ready() {
+            let summary_clone = summary.clone();
+            let style_clone = style.clone();
+            let progress_clone = progress.clone();
+            let sender_clone = sender.clone();
+            client.add_job(job_spec, move |result| {
+                let succeeded = match &progress_clone {
+                    Some(progress) => {
+                        let succeeded = progress.suspend(|| {
+                            visitor(result, &allowed_exit_codes, summary_clone, &style_clone)
+                        });
+                        progress.job_finished(succeeded);
+                        succeeded
+                    }
+                    None => visitor(result, &allowed_exit_codes, summary_clone, &style_clone),
+                };
+                sender_clone.send(Event::JobFinished(index, succeeded)).ok();
+            })?;
+            if let Some(progress) = progress {
+                progress.job_submitted();
+            }
+            running += 1;
+        }
+        if end_of_input && running == 0 {
+            return Ok(());
+        }
+        match events.recv()? {
+            Event::JobRead(js) => {
+                js.spec
+                    .validate()
+                    .with_context(|| format!("invalid job for {}", js.spec.program))?;
+                if let Some(progress) = progress {
+                    progress.job_added();
+                }
+                report_skipped(scheduler.add(
+                    js.name,
+                    js.depends_on,
+                    (js.spec, js.allowed_exit_codes),
+                )?);
+            }
+            Event::EndOfInput => {
+                scheduler.check_complete()?;
+                end_of_input = true;
+            }
+            // The caller reports the error.
+            Event::InputFailed => return Ok(()),
+            Event::JobFinished(index, succeeded) => {
+                running -= 1;
+                report_skipped(scheduler.job_finished(index, succeeded));
+            }
+        }
+    }
+}
+
+#[derive(Args)]
+struct ExtraCommandLineOptions {
+    #[arg(
+        value_name = "FILE",
+        help = "Read job specifications from the given files, in order, instead of from standard \
+            input."
+    )]
+    files: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        help = "The format the job specifications are written in: \"json\" or \"toml\". If this \
+            isn't given, files ending in \".toml\" are read as TOML, and everything else, \
+            including standard input, is read as JSON."
+    )]
+    format: Option<JobSpecFormat>,
+
+    #[arg(
+        long,
+        help = "Check the job specifications and report every problem found, without running any \
+            jobs."
+    )]
+    validate_only: bool,
+}
+
+/// Check the job specifications in `files`, or on standard input if there are none, printing every
+/// problem found.
+fn validate_only(
+    files: Vec<PathBuf>,
+    format: Option<JobSpecFormat>,
+    default_image: Option<Image>,
+    style: &StatusStyle,
+) -> Result<ExitCode> {
+    // Images aren't downloaded when only validating, so act as if each image has everything a job
+    // could use from it.
+    let image_lookup = |_: &str| {
+        Ok(ImageConfig {
+            layers: vec![PathBuf::from("image-layer.tar")],
+            environment: Some(vec![]),
+            working_directory: Some("/".into()),
+        })
+    };
+    let problems = if !files.is_empty() {
+        validate_job_specs_from_files(files, format, std_env_lookup, image_lookup, default_image)
+    } else if format == Some(JobSpecFormat::Toml) {
+        validate_job_specs_from_toml_reader(
+            io::stdin().lock(),
+            std_env_lookup,
+            image_lookup,
+            default_image,
+        )
+    } else {
+        validate_job_specs_from_reader(
+            io::stdin().lock(),
+            std_env_lookup,
+            image_lookup,
+            default_image,
+        )
+    };
+    for problem in &problems {
+        eprintln!("{}", style.failure(format!("{problem:#}")));
+    }
+    Ok(match problems.len() {
+        0 => ExitCode::SUCCESS,
+        1 => {
+            eprintln!("found 1 problem");
+            ExitCode::FAILURE
+        }
+        n => {
+            eprintln!("found {n} problems");
+            ExitCode::FAILURE
+        }
+    })
 }
 
 fn main() -> Result<ExitCode> {
-    let config = Config::new("maelstrom/run", "MAELSTROM_RUN")?;
+    let (config, extra_options): (_, ExtraCommandLineOptions) =
+        Config::new_with_extra_from_args("maelstrom/run", "MAELSTROM_RUN", env::args())?;
+
+    let default_image = config.default_image.map(|name| Image {
+        name,
+        use_: config.default_image_use.into(),
+    });
+
+    if extra_options.validate_only {
+        let style = StatusStyle::new(config.color.enabled(io::stderr().is_terminal()));
+        return validate_only(
+            extra_options.files,
+            extra_options.format,
+            default_image,
+            &style,
+        );
+    }
 
     let bg_proc = ClientBgProcess::new_from_fork(config.log_level)?;
 
     maelstrom_util::log::run_with_logger(config.log_level, |log| {
         let fs = Fs::new();
-        let accum = Arc::new(ExitCodeAccumulator::default());
-        let cache_dir = cache_dir();
+        let summary = Arc::new(JobSummary::default());
+        let style = Arc::new(StatusStyle::new(
+            config.color.enabled(io::stderr().is_terminal()),
+        ));
+        // We don't know how many jobs there are until we've read them all, so the bar grows as
+        // they're read.
+        let progress = config
+            .progress
+            .then(|| Arc::new(JobProgress::new(0, ProgressDrawTarget::stderr())));
+        let cache_dir = config.cache_dir.into_inner();
         fs.create_dir_all(&cache_dir)?;
         let client = Client::new(
             bg_proc,
@@ -146,11 +455,22 @@ fn main() -> Result<ExitCode> {
             config.cache_size,
             config.inline_limit,
             config.slots,
+            config.hashing_threads,
+            config.dispatcher_threads,
+            config
+                .local_worker_idle_timeout
+                .map(|secs| Duration::from_secs(secs.into())),
+            config.canonicalize_layer_order,
+            config
+                .client_keep_alive_interval
+                .map(|secs| Duration::from_secs(secs.into())),
+            config
+                .client_request_timeout
+                .map(|secs| Duration::from_secs(secs.into())),
             log,
         )?;
-        let reader: Box<dyn Read> = Box::new(io::stdin().lock());
         let image_lookup = |image: &str| {
-            let (image, version) = image.split_once(':').unwrap_or((image, "latest"));
+            let (image, version) = split_image_reference(image);
             let image = client.get_container_image(image, version)?;
             Ok(ImageConfig {
                 layers: image.layers.clone(),
@@ -158,19 +478,75 @@ fn main() -> Result<ExitCode> {
                 working_directory: image.working_dir().map(From::from),
             })
         };
-        let job_specs = job_spec_iter_from_reader(
-            reader,
-            |layer| client.add_layer(layer),
-            std_env_lookup,
-            image_lookup,
-        );
-        for job_spec in job_specs {
-            let accum_clone = accum.clone();
-            client.add_job(job_spec?, move |cjid, result| {
-                visitor(cjid, result, accum_clone)
-            })?;
-        }
+        let layer_mapper = |layer| client.add_layer(layer);
+        let job_specs: Box<dyn Iterator<Item = _>> = if !extra_options.files.is_empty() {
+            Box::new(job_spec_iter_from_files(
+                extra_options.files,
+                extra_options.format,
+                layer_mapper,
+                std_env_lookup,
+                image_lookup,
+                default_image,
+            ))
+        } else if extra_options.format == Some(JobSpecFormat::Toml) {
+            Box::new(job_spec_iter_from_toml_reader(
+                io::stdin().lock(),
+                layer_mapper,
+                std_env_lookup,
+                image_lookup,
+                default_image,
+            ))
+        } else {
+            Box::new(job_spec_iter_from_reader(
+                io::stdin().lock(),
+                |reader| client.add_artifact_from_reader(reader),
+                layer_mapper,
+                std_env_lookup,
+                image_lookup,
+                default_image,
+            ))
+        };
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            let submitter = scope.spawn(|| {
+                submit_jobs(
+                    &client,
+                    receiver,
+                    sender.clone(),
+                    &summary,
+                    &style,
+                    &progress,
+                )
+            });
+            let mut read_result = Ok(());
+            for job_spec in job_specs {
+                match job_spec {
+                    Ok(job_spec) => {
+                        // The submitter only stops listening if it has failed.
+                        if sender.send(Event::JobRead(Box::new(job_spec))).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        read_result = Err(err);
+                        break;
+                    }
+                }
+            }
+            let end = match read_result {
+                Ok(()) => Event::EndOfInput,
+                Err(_) => Event::InputFailed,
+            };
+            sender.send(end).ok();
+            let submit_result = submitter.join().unwrap();
+            read_result.and(submit_result)
+        })?;
         client.wait_for_outstanding_jobs()?;
-        Ok(accum.get())
+        if let Some(progress) = &progress {
+            progress.finish();
+        }
+        io::stdout().lock().flush()?;
+        eprintln!("{}", summary.counts());
+        Ok(summary.exit_code())
     })
 }
@@ -0,0 +1,130 @@
+//! A progress bar for interactive use, showing how many jobs have been submitted, completed, and
+//! failed.
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::sync::Mutex;
+
+/// How many jobs have reached each point so far.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProgressCounts {
+    /// Jobs that have been submitted to the client.
+    pub submitted: u64,
+    /// Jobs that are done, whether they ran or were skipped.
+    pub completed: u64,
+    /// Completed jobs that didn't succeed, including skipped ones.
+    pub failed: u64,
+}
+
+/// Tracks jobs as they are submitted and finish, and draws a bar for them. This is shared with the
+/// job callbacks, so it can be used from multiple threads.
+pub struct JobProgress {
+    bar: ProgressBar,
+    counts: Mutex<ProgressCounts>,
+}
+
+impl JobProgress {
+    /// Create a bar for `total` jobs, drawn to `draw_target`. Nothing is drawn if the target isn't
+    /// a terminal.
+    pub fn new(total: u64, draw_target: ProgressDrawTarget) -> Self {
+        let bar = ProgressBar::with_draw_target(Some(total), draw_target).with_style(
+            ProgressStyle::with_template("{wide_bar:.green} {pos}/{len} {msg}")
+                .unwrap()
+                .progress_chars("##-"),
+        );
+        let progress = Self {
+            bar,
+            counts: Mutex::new(ProgressCounts::default()),
+        };
+        progress.update(&ProgressCounts::default());
+        progress
+    }
+
+    fn update(&self, counts: &ProgressCounts) {
+        self.bar.set_position(counts.completed);
+        self.bar.set_message(format!(
+            "{} submitted, {} completed, {} failed",
+            counts.submitted, counts.completed, counts.failed
+        ));
+    }
+
+    /// Count one more job toward the total, for when the total isn't known up front.
+    pub fn job_added(&self) {
+        self.bar.inc_length(1);
+    }
+
+    pub fn job_submitted(&self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.submitted += 1;
+        self.update(&counts);
+    }
+
+    pub fn job_finished(&self, succeeded: bool) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.completed += 1;
+        if !succeeded {
+            counts.failed += 1;
+        }
+        self.update(&counts);
+    }
+
+    /// Record a job that won't be run because one of its dependencies didn't succeed.
+    pub fn job_skipped(&self) {
+        self.job_finished(false);
+    }
+
+    /// Run `f` with the bar hidden, so that anything it prints isn't mixed up with the bar.
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.bar.suspend(f)
+    }
+
+    pub fn counts(&self) -> ProgressCounts {
+        *self.counts.lock().unwrap()
+    }
+
+    /// Remove the bar once all jobs are done.
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn completed_reaches_total() {
+        let progress = JobProgress::new(4, ProgressDrawTarget::hidden());
+        for _ in 0..3 {
+            progress.job_submitted();
+        }
+        progress.job_finished(true);
+        progress.job_finished(false);
+        progress.job_skipped();
+        progress.job_submitted();
+        progress.job_finished(true);
+        assert_eq!(
+            progress.counts(),
+            ProgressCounts {
+                submitted: 4,
+                completed: 4,
+                failed: 2,
+            }
+        );
+        assert_eq!(progress.bar.position(), 4);
+        assert_eq!(progress.bar.length(), Some(4));
+        assert_eq!(progress.bar.message(), "4 submitted, 4 completed, 2 failed");
+        progress.finish();
+        assert!(progress.bar.is_finished());
+    }
+
+    #[test]
+    fn total_grows_as_jobs_are_added() {
+        let progress = JobProgress::new(0, ProgressDrawTarget::hidden());
+        progress.job_added();
+        progress.job_submitted();
+        progress.job_added();
+        assert_eq!(progress.bar.length(), Some(2));
+        progress.job_finished(true);
+        assert_eq!(progress.bar.position(), 1);
+    }
+}
@@ -0,0 +1,486 @@
+//! Ordering of jobs that depend on other jobs.
+//!
+//! Jobs may be given a `name`, and may list the names of other jobs in `depends_on`. Jobs are added
+//! one at a time, as they're read. A job without dependencies is ready as soon as it's added. A job
+//! with dependencies is held back until every job it depends on has completed successfully, which
+//! may include jobs that haven't been added yet. If one of its dependencies fails, the job is
+//! skipped, as is everything that depends on it.
+
+use anyhow::{bail, Result};
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+};
+
+/// The position of a job in the order it was given to [`JobScheduler::add`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct JobIndex(usize);
+
+#[derive(Debug)]
+enum JobState<T> {
+    Waiting(T),
+    Running,
+    Succeeded,
+    /// The job failed or was skipped.
+    Failed,
+}
+
+#[derive(Debug)]
+struct ScheduledJob<T> {
+    name: Option<String>,
+    state: JobState<T>,
+    dependencies: Vec<JobIndex>,
+    dependents: Vec<JobIndex>,
+    unfinished_dependencies: usize,
+}
+
+/// Hands out jobs in an order that respects their dependencies.
+#[derive(Debug)]
+pub struct JobScheduler<T> {
+    jobs: Vec<ScheduledJob<T>>,
+    indices: HashMap<String, JobIndex>,
+    /// Jobs waiting on names that no job added so far has, in the order they were added.
+    unresolved: Vec<(String, JobIndex)>,
+    ready: VecDeque<JobIndex>,
+}
+
+impl<T> Default for JobScheduler<T> {
+    fn default() -> Self {
+        Self {
+            jobs: vec![],
+            indices: HashMap::new(),
+            unresolved: vec![],
+            ready: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> JobScheduler<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a job. This fails if another job already has the same name. If one of the job's
+    /// dependencies has already failed, the job is skipped right away, and so are any jobs that
+    /// were waiting on it. The descriptions of the skipped jobs are returned.
+    pub fn add(
+        &mut self,
+        name: Option<String>,
+        depends_on: Vec<String>,
+        job: T,
+    ) -> Result<Vec<String>> {
+        let index = JobIndex(self.jobs.len());
+        if let Some(name) = &name {
+            if self.indices.contains_key(name) {
+                bail!("more than one job is named `{name}`");
+            }
+        }
+
+        // A job that is going to be skipped mustn't wait on anything, or it would be made ready
+        // once its other dependencies finished.
+        let failed_dependency = depends_on.iter().any(|dependency| {
+            self.indices
+                .get(dependency)
+                .is_some_and(|index| matches!(self.jobs[index.0].state, JobState::Failed))
+        });
+
+        let mut dependencies = vec![];
+        let mut unfinished_dependencies = 0;
+        for dependency in depends_on {
+            match self.indices.get(&dependency) {
+                Some(&dependency_index) => {
+                    dependencies.push(dependency_index);
+                    let dependency_job = &mut self.jobs[dependency_index.0];
+                    if !failed_dependency
+                        && matches!(
+                            dependency_job.state,
+                            JobState::Waiting(_) | JobState::Running
+                        )
+                    {
+                        dependency_job.dependents.push(index);
+                        unfinished_dependencies += 1;
+                    }
+                }
+                None if !failed_dependency => {
+                    self.unresolved.push((dependency, index));
+                    unfinished_dependencies += 1;
+                }
+                None => {}
+            }
+        }
+
+        self.jobs.push(ScheduledJob {
+            name: name.clone(),
+            state: JobState::Waiting(job),
+            dependencies,
+            dependents: vec![],
+            unfinished_dependencies,
+        });
+
+        // Hook up the jobs that were added before this one and were waiting for its name.
+        if let Some(name) = name {
+            let mut dependents = vec![];
+            self.unresolved.retain(|(dependency, dependent)| {
+                *dependency != name || {
+                    dependents.push(*dependent);
+                    false
+                }
+            });
+            for dependent in &dependents {
+                self.jobs[dependent.0].dependencies.push(index);
+            }
+            self.jobs[index.0].dependents = dependents;
+            self.indices.insert(name, index);
+        }
+
+        if failed_dependency {
+            let mut skipped = vec![index];
+            skipped.extend(self.skip_dependents(index));
+            skipped.sort();
+            self.jobs[index.0].state = JobState::Failed;
+            return Ok(self.describe_all(skipped));
+        }
+        if unfinished_dependencies == 0 {
+            self.ready.push_back(index);
+        }
+        Ok(vec![])
+    }
+
+    /// Check that the dependencies of the jobs added so far can all be met, once all of the jobs
+    /// have been added. This fails if a job depends on a name that no job has, or if the
+    /// dependencies have a cycle.
+    pub fn check_complete(&self) -> Result<()> {
+        if let Some((dependency, index)) = self.unresolved.first() {
+            bail!(
+                "{} depends on unknown job `{dependency}`",
+                self.describe(*index)
+            );
+        }
+        let dependencies = Vec::from_iter(self.jobs.iter().map(|job| job.dependencies.clone()));
+        if let Some(cycle) = find_cycle(&dependencies) {
+            let cycle = Vec::from_iter(cycle.into_iter().map(|index| self.describe(index)));
+            bail!("dependency cycle: {}", cycle.join(" -> "));
+        }
+        Ok(())
+    }
+
+    /// Take all of the jobs whose dependencies have completed successfully, in the order they
+    /// became ready. Each job is only returned once.
+    pub fn take_ready(&mut self) -> Vec<(JobIndex, T)> {
+        Vec::from_iter(self.ready.drain(..).map(|index| {
+            let job = &mut self.jobs[index.0];
+            match mem::replace(&mut job.state, JobState::Running) {
+                JobState::Waiting(job) => (index, job),
+                _ => panic!("job {index:?} handed out more than once"),
+            }
+        }))
+    }
+
+    /// Record that a job returned from [`Self::take_ready`] has finished. If it succeeded, any jobs
+    /// that were only waiting on it become ready. If it failed, every job that depends on it,
+    /// directly or not, is skipped. The descriptions of the skipped jobs are returned.
+    pub fn job_finished(&mut self, index: JobIndex, succeeded: bool) -> Vec<String> {
+        let job = &mut self.jobs[index.0];
+        assert!(matches!(job.state, JobState::Running));
+
+        if succeeded {
+            job.state = JobState::Succeeded;
+            for dependent in job.dependents.clone() {
+                let dependent_job = &mut self.jobs[dependent.0];
+                dependent_job.unfinished_dependencies -= 1;
+                if dependent_job.unfinished_dependencies == 0
+                    && matches!(dependent_job.state, JobState::Waiting(_))
+                {
+                    self.ready.push_back(dependent);
+                }
+            }
+            return vec![];
+        }
+
+        job.state = JobState::Failed;
+        let mut skipped = self.skip_dependents(index);
+        skipped.sort();
+        self.describe_all(skipped)
+    }
+
+    /// Skip every job still waiting that depends on `index`, directly or not, returning them.
+    fn skip_dependents(&mut self, index: JobIndex) -> Vec<JobIndex> {
+        let mut skipped = vec![];
+        let mut to_skip = self.jobs[index.0].dependents.clone();
+        while let Some(dependent) = to_skip.pop() {
+            let dependent_job = &mut self.jobs[dependent.0];
+            if let JobState::Waiting(_) = dependent_job.state {
+                dependent_job.state = JobState::Failed;
+                skipped.push(dependent);
+                to_skip.extend(dependent_job.dependents.iter().copied());
+            }
+        }
+        skipped
+    }
+
+    fn describe(&self, index: JobIndex) -> String {
+        describe(&self.jobs[index.0].name, index)
+    }
+
+    fn describe_all(&self, indices: Vec<JobIndex>) -> Vec<String> {
+        Vec::from_iter(indices.into_iter().map(|index| self.describe(index)))
+    }
+}
+
+fn describe(name: &Option<String>, index: JobIndex) -> String {
+    match name {
+        Some(name) => format!("job `{name}`"),
+        None => format!("job #{}", index.0 + 1),
+    }
+}
+
+/// Find a cycle in the dependency graph, if there is one. The returned path starts and ends with
+/// the same job.
+fn find_cycle(dependencies: &[Vec<JobIndex>]) -> Option<Vec<JobIndex>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        Unvisited,
+        InProgress,
+        Visited,
+    }
+
+    let mut marks = vec![Mark::Unvisited; dependencies.len()];
+    for start in 0..dependencies.len() {
+        if marks[start] != Mark::Unvisited {
+            continue;
+        }
+        // Each entry in the stack is a job and how many of its dependencies have been explored.
+        let mut stack = vec![(start, 0)];
+        marks[start] = Mark::InProgress;
+        while let Some((index, explored)) = stack.last_mut() {
+            let index = *index;
+            let Some(JobIndex(next)) = dependencies[index].get(*explored).copied() else {
+                marks[index] = Mark::Visited;
+                stack.pop();
+                continue;
+            };
+            *explored += 1;
+            match marks[next] {
+                Mark::Unvisited => {
+                    marks[next] = Mark::InProgress;
+                    stack.push((next, 0));
+                }
+                Mark::InProgress => {
+                    let position = stack.iter().position(|(i, _)| *i == next).unwrap();
+                    let mut cycle = Vec::from_iter(stack[position..].iter().map(|(i, _)| *i));
+                    cycle.push(next);
+                    // We walked from dependents to dependencies, so reverse the path to show the
+                    // order in which the jobs would have to run.
+                    cycle.reverse();
+                    return Some(Vec::from_iter(cycle.into_iter().map(JobIndex)));
+                }
+                Mark::Visited => {}
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maelstrom_test::string;
+
+    fn job(
+        name: Option<&str>,
+        depends_on: &[&str],
+        job: &'static str,
+    ) -> (Option<String>, Vec<String>, &'static str) {
+        (
+            name.map(String::from),
+            Vec::from_iter(depends_on.iter().map(|d| d.to_string())),
+            job,
+        )
+    }
+
+    /// Add all of `jobs`, as if they were all read before any of them ran.
+    fn schedule(
+        jobs: impl IntoIterator<Item = (Option<String>, Vec<String>, &'static str)>,
+    ) -> Result<JobScheduler<&'static str>> {
+        let mut scheduler = JobScheduler::new();
+        for (name, depends_on, job) in jobs {
+            assert_eq!(scheduler.add(name, depends_on, job)?, Vec::<String>::new());
+        }
+        scheduler.check_complete()?;
+        Ok(scheduler)
+    }
+
+    fn add(
+        scheduler: &mut JobScheduler<&'static str>,
+        job: (Option<String>, Vec<String>, &'static str),
+    ) -> Vec<String> {
+        let (name, depends_on, job) = job;
+        scheduler.add(name, depends_on, job).unwrap()
+    }
+
+    fn take_ready(scheduler: &mut JobScheduler<&'static str>) -> Vec<&'static str> {
+        Vec::from_iter(scheduler.take_ready().into_iter().map(|(_, job)| job))
+    }
+
+    fn finish(scheduler: &mut JobScheduler<&'static str>, index: usize, succeeded: bool) {
+        assert_eq!(
+            scheduler.job_finished(JobIndex(index), succeeded),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn no_dependencies_all_ready_in_order() {
+        let mut scheduler = schedule([
+            job(None, &[], "a"),
+            job(Some("b"), &[], "b"),
+            job(None, &[], "c"),
+        ])
+        .unwrap();
+        assert_eq!(take_ready(&mut scheduler), vec!["a", "b", "c"]);
+        assert_eq!(take_ready(&mut scheduler), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn dependent_submitted_after_dependency_succeeds() {
+        let mut scheduler =
+            schedule([job(Some("b"), &["a"], "b"), job(Some("a"), &[], "a")]).unwrap();
+        assert_eq!(take_ready(&mut scheduler), vec!["a"]);
+        assert_eq!(take_ready(&mut scheduler), Vec::<&str>::new());
+        finish(&mut scheduler, 1, true);
+        assert_eq!(take_ready(&mut scheduler), vec!["b"]);
+        finish(&mut scheduler, 0, true);
+        assert_eq!(take_ready(&mut scheduler), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn dependent_waits_for_all_dependencies() {
+        let mut scheduler = schedule([
+            job(Some("a"), &[], "a"),
+            job(Some("b"), &[], "b"),
+            job(Some("c"), &["a", "b"], "c"),
+        ])
+        .unwrap();
+        assert_eq!(take_ready(&mut scheduler), vec!["a", "b"]);
+        finish(&mut scheduler, 0, true);
+        assert_eq!(take_ready(&mut scheduler), Vec::<&str>::new());
+        finish(&mut scheduler, 1, true);
+        assert_eq!(take_ready(&mut scheduler), vec!["c"]);
+    }
+
+    #[test]
+    fn failed_dependency_skips_dependents_transitively() {
+        let mut scheduler = schedule([
+            job(Some("a"), &[], "a"),
+            job(Some("b"), &["a"], "b"),
+            job(None, &["b"], "c"),
+            job(Some("d"), &[], "d"),
+        ])
+        .unwrap();
+        assert_eq!(take_ready(&mut scheduler), vec!["a", "d"]);
+        assert_eq!(
+            scheduler.job_finished(JobIndex(0), false),
+            vec![string!("job `b`"), string!("job #3")]
+        );
+        finish(&mut scheduler, 3, true);
+        assert_eq!(take_ready(&mut scheduler), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn duplicate_name_rejected() {
+        let err = schedule([job(Some("a"), &[], "a"), job(Some("a"), &[], "b")]).unwrap_err();
+        assert_eq!(err.to_string(), "more than one job is named `a`");
+    }
+
+    #[test]
+    fn unknown_dependency_rejected() {
+        let err = schedule([job(None, &["a"], "a")]).unwrap_err();
+        assert_eq!(err.to_string(), "job #1 depends on unknown job `a`");
+    }
+
+    #[test]
+    fn cycle_rejected() {
+        let err = schedule([
+            job(Some("a"), &["c"], "a"),
+            job(Some("b"), &["a"], "b"),
+            job(Some("c"), &["b"], "c"),
+            job(Some("d"), &[], "d"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "dependency cycle: job `a` -> job `b` -> job `c` -> job `a`"
+        );
+    }
+
+    #[test]
+    fn self_dependency_rejected() {
+        let err = schedule([job(Some("a"), &["a"], "a")]).unwrap_err();
+        assert_eq!(err.to_string(), "dependency cycle: job `a` -> job `a`");
+    }
+
+    #[test]
+    fn job_ready_as_soon_as_added() {
+        let mut scheduler = JobScheduler::new();
+        add(&mut scheduler, job(None, &[], "a"));
+        assert_eq!(take_ready(&mut scheduler), vec!["a"]);
+        add(&mut scheduler, job(Some("b"), &[], "b"));
+        assert_eq!(take_ready(&mut scheduler), vec!["b"]);
+    }
+
+    #[test]
+    fn dependent_added_after_dependency_succeeded_is_ready() {
+        let mut scheduler = JobScheduler::new();
+        add(&mut scheduler, job(Some("a"), &[], "a"));
+        assert_eq!(take_ready(&mut scheduler), vec!["a"]);
+        finish(&mut scheduler, 0, true);
+        add(&mut scheduler, job(None, &["a"], "b"));
+        assert_eq!(take_ready(&mut scheduler), vec!["b"]);
+    }
+
+    #[test]
+    fn dependent_added_after_dependency_failed_is_skipped() {
+        let mut scheduler = JobScheduler::new();
+        add(&mut scheduler, job(Some("a"), &[], "a"));
+        assert_eq!(take_ready(&mut scheduler), vec!["a"]);
+        finish(&mut scheduler, 0, false);
+        add(&mut scheduler, job(None, &["c"], "d"));
+        assert_eq!(
+            add(&mut scheduler, job(Some("c"), &["a"], "c")),
+            vec![string!("job #2"), string!("job `c`")]
+        );
+        assert_eq!(take_ready(&mut scheduler), Vec::<&str>::new());
+        scheduler.check_complete().unwrap();
+    }
+
+    #[test]
+    fn dependent_added_after_one_dependency_failed_is_not_made_ready() {
+        let mut scheduler = JobScheduler::new();
+        add(&mut scheduler, job(Some("a"), &[], "a"));
+        add(&mut scheduler, job(Some("b"), &[], "b"));
+        assert_eq!(take_ready(&mut scheduler), vec!["a", "b"]);
+        finish(&mut scheduler, 0, false);
+        assert_eq!(
+            add(&mut scheduler, job(Some("c"), &["a", "b", "d"], "c")),
+            vec![string!("job `c`")]
+        );
+        finish(&mut scheduler, 1, true);
+        add(&mut scheduler, job(Some("d"), &[], "d"));
+        assert_eq!(take_ready(&mut scheduler), vec!["d"]);
+        finish(&mut scheduler, 3, true);
+        assert_eq!(take_ready(&mut scheduler), Vec::<&str>::new());
+        scheduler.check_complete().unwrap();
+    }
+
+    #[test]
+    fn dependent_waits_for_dependency_added_later() {
+        let mut scheduler = JobScheduler::new();
+        add(&mut scheduler, job(None, &["a"], "b"));
+        add(&mut scheduler, job(None, &[], "c"));
+        assert_eq!(take_ready(&mut scheduler), vec!["c"]);
+        add(&mut scheduler, job(Some("a"), &[], "a"));
+        assert_eq!(take_ready(&mut scheduler), vec!["a"]);
+        finish(&mut scheduler, 2, true);
+        assert_eq!(take_ready(&mut scheduler), vec!["b"]);
+    }
+}
@@ -1,69 +1,646 @@
-use anyhow::{anyhow, Error, Result};
+use crate::schedule::JobScheduler;
+use anyhow::{anyhow, bail, Context as _, Error, Result};
 use maelstrom_base::{
-    ArtifactType, EnumSet, GroupId, JobDevice, JobDeviceListDeserialize, JobMount, JobSpec,
-    NonEmpty, Sha256Digest, Timeout, UserId, Utf8PathBuf,
+    ArtifactType, EnumSet, GroupId, JobDevice, JobDeviceListDeserialize, JobMount, JobMountFsType,
+    JobSpec, NonEmpty, Sha256Digest, Timeout, UserId, Utf8Path, Utf8PathBuf,
 };
 use maelstrom_client::spec::{
     incompatible, substitute, Image, ImageConfig, ImageOption, ImageUse, Layer, PossiblyImage,
 };
-use serde::{de, Deserialize, Deserializer};
-use std::{collections::BTreeMap, io::Read};
+use maelstrom_util::config::common::StringError;
+use serde::{de, de::DeserializeOwned, Deserialize, Deserializer};
+use serde_json::value::RawValue;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    iter, mem,
+    path::{Path, PathBuf},
+    result,
+    str::FromStr,
+};
+
+/// A [`JobSpec`] along with the name given to the job, if any, the names of the jobs it depends
+/// on, and the non-zero exit codes that still count as the job succeeding.
+#[derive(Debug, Eq, PartialEq)]
+pub struct NamedJobSpec {
+    pub name: Option<String>,
+    pub depends_on: Vec<String>,
+    pub allowed_exit_codes: Vec<u8>,
+    pub spec: JobSpec,
+}
+
+/// The parts of the default image that jobs use, written like "layers,environment". The parts are
+/// named as in an `image` field's `use`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub struct ImageUses(EnumSet<ImageUse>);
+
+impl Default for ImageUses {
+    fn default() -> Self {
+        Self(ImageUse::Layers | ImageUse::Environment)
+    }
+}
+
+impl From<ImageUses> for EnumSet<ImageUse> {
+    fn from(uses: ImageUses) -> Self {
+        uses.0
+    }
+}
+
+impl FromStr for ImageUses {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|use_| !use_.is_empty())
+            .map(|use_| match use_ {
+                "layers" => Ok(ImageUse::Layers),
+                "environment" => Ok(ImageUse::Environment),
+                "working_directory" => Ok(ImageUse::WorkingDirectory),
+                _ => Err(StringError(format!("invalid image use {use_:?}"))),
+            })
+            .collect::<result::Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl TryFrom<String> for ImageUses {
+    type Error = StringError;
+    fn try_from(s: String) -> result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for ImageUses {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let uses = Vec::from_iter(self.0.iter().map(|use_| match use_ {
+            ImageUse::Layers => "layers",
+            ImageUse::Environment => "environment",
+            ImageUse::WorkingDirectory => "working_directory",
+        }));
+        f.write_str(&uses.join(","))
+    }
+}
+
+/// The format job specifications are written in. In JSON, they are a series of objects, one after
+/// another. In TOML, they are an array of tables named `jobs`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum JobSpecFormat {
+    #[default]
+    Json,
+    Toml,
+}
+
+impl JobSpecFormat {
+    /// Guess the format of a file from its extension. Anything other than ".toml" is taken to be
+    /// JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension == "toml" => Self::Toml,
+            _ => Self::Json,
+        }
+    }
+}
+
+impl FromStr for JobSpecFormat {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            _ => Err(StringError(format!(
+                "invalid job specification format {s:?}"
+            ))),
+        }
+    }
+}
 
 struct JobSpecIterator<InnerT, LayerMapperT, EnvLookupT, ImageLookupT> {
     inner: InnerT,
     layer_mapper: LayerMapperT,
     env_lookup: EnvLookupT,
     image_lookup: ImageLookupT,
+    default_image: Option<Image>,
 }
 
 impl<InnerT, LayerMapperT, EnvLookupT, ImageLookupT> Iterator
     for JobSpecIterator<InnerT, LayerMapperT, EnvLookupT, ImageLookupT>
 where
-    InnerT: Iterator<Item = serde_json::Result<Job>>,
+    InnerT: Iterator<Item = Result<Job>>,
     LayerMapperT: Fn(Layer) -> Result<(Sha256Digest, ArtifactType)>,
     EnvLookupT: Fn(&str) -> Result<Option<String>>,
     ImageLookupT: FnMut(&str) -> Result<ImageConfig>,
 {
-    type Item = Result<JobSpec>;
+    type Item = Result<NamedJobSpec>;
 
     fn next(&mut self) -> Option<Self::Item> {
         match self.inner.next() {
             None => None,
-            Some(Err(err)) => Some(Err(Error::new(err))),
-            Some(Ok(job)) => Some(job.into_job_spec(
+            Some(Err(err)) => Some(Err(err)),
+            Some(Ok(job)) => Some(job.into_named_job_spec(
                 &self.layer_mapper,
                 &self.env_lookup,
                 &mut self.image_lookup,
+                self.default_image.as_ref(),
             )),
         }
     }
 }
 
+/// A [`BufRead`]er wrapper that keeps track of how many bytes have been consumed from it.
+struct CountingReader<ReaderT> {
+    inner: ReaderT,
+    offset: u64,
+}
+
+impl<ReaderT> CountingReader<ReaderT> {
+    fn new(inner: ReaderT) -> Self {
+        Self { inner, offset: 0 }
+    }
+}
+
+impl<ReaderT: Read> Read for CountingReader<ReaderT> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        self.offset += size as u64;
+        Ok(size)
+    }
+}
+
+impl<ReaderT: BufRead> BufRead for CountingReader<ReaderT> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.offset += amt as u64;
+    }
+}
+
+/// Reads jobs from a stream that may also contain the contents of layers. A layer's contents are
+/// given by a line of the form "@layer NAME SIZE", followed by SIZE bytes of a tar file. Jobs after
+/// it in the stream can then use it with a `{ "stdin": "NAME" }` layer.
+struct ReaderJobIterator<ReaderT, ArtifactAdderT> {
+    reader: CountingReader<ReaderT>,
+    artifact_adder: ArtifactAdderT,
+    layers: HashMap<String, Sha256Digest>,
+    values_read: usize,
+    done: bool,
+}
+
+impl<ReaderT, ArtifactAdderT> ReaderJobIterator<ReaderT, ArtifactAdderT>
+where
+    ReaderT: BufRead,
+    ArtifactAdderT: FnMut(&mut dyn Read) -> Result<Sha256Digest>,
+{
+    /// Skip any whitespace and return the next byte without consuming it, or `None` at the end of
+    /// the stream.
+    fn peek_past_whitespace(&mut self) -> Result<Option<u8>> {
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            let whitespace = buf
+                .iter()
+                .take_while(|b| matches!(b, b' ' | b'\n' | b'\t' | b'\r'))
+                .count();
+            let next = buf.get(whitespace).copied();
+            self.reader.consume(whitespace);
+            if next.is_some() {
+                return Ok(next);
+            }
+        }
+    }
+
+    fn read_layer(&mut self) -> Result<()> {
+        let mut header = String::new();
+        self.reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        let (name, size) = header
+            .strip_prefix("@layer ")
+            .and_then(|rest| rest.rsplit_once(' '))
+            .ok_or_else(|| anyhow!("expected \"@layer NAME SIZE\", got {header:?}"))?;
+        let size: u64 = size
+            .parse()
+            .with_context(|| format!("parsing size of layer {name:?}"))?;
+        let mut contents = (&mut self.reader).take(size);
+        let digest = (self.artifact_adder)(&mut contents)
+            .with_context(|| format!("adding layer {name:?}"))?;
+        if contents.limit() != 0 {
+            bail!(
+                "layer {name:?} ended after {} of {size} bytes",
+                size - contents.limit()
+            );
+        }
+        self.layers.insert(name.into(), digest);
+        Ok(())
+    }
+
+    /// Read any layers up to the next JSON value, then read and return that value. Errors reading
+    /// the value say which job it was and the offset in the stream that it started at, since the
+    /// line and column given by [`serde_json`] are relative to the start of the job.
+    fn next_value<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
+        loop {
+            match self.peek_past_whitespace()? {
+                None => return Ok(None),
+                Some(b'@') => self.read_layer()?,
+                Some(_) => {
+                    self.values_read += 1;
+                    let offset = self.reader.offset;
+                    return Ok(Some(
+                        T::deserialize(&mut serde_json::Deserializer::from_reader(
+                            &mut self.reader,
+                        ))
+                        .with_context(|| format!("job #{} at byte {offset}", self.values_read))?,
+                    ));
+                }
+            }
+        }
+    }
+
+    fn next_job(&mut self) -> Result<Option<Job>> {
+        let Some(mut job) = self.next_value::<Job>()? else {
+            return Ok(None);
+        };
+        job.resolve_stdin_layers(&self.layers)?;
+        Ok(Some(job))
+    }
+}
+
+impl<ReaderT, ArtifactAdderT> Iterator for ReaderJobIterator<ReaderT, ArtifactAdderT>
+where
+    ReaderT: BufRead,
+    ArtifactAdderT: FnMut(&mut dyn Read) -> Result<Sha256Digest>,
+{
+    type Item = Result<Job>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.next_job().transpose();
+        // We can't tell where the next job starts after an error.
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
+}
+
+/// Read the jobs from `reader`. Besides jobs, the stream may contain the contents of layers, which
+/// are added as artifacts with `artifact_adder`. See [`ReaderJobIterator`] for the format.
+///
+/// Jobs that don't have an `image` field use `default_image`, if there is one.
 pub fn job_spec_iter_from_reader(
+    reader: impl BufRead,
+    artifact_adder: impl FnMut(&mut dyn Read) -> Result<Sha256Digest>,
+    layer_mapper: impl Fn(Layer) -> Result<(Sha256Digest, ArtifactType)>,
+    env_lookup: impl Fn(&str) -> Result<Option<String>>,
+    image_lookup: impl FnMut(&str) -> Result<ImageConfig>,
+    default_image: Option<Image>,
+) -> impl Iterator<Item = Result<NamedJobSpec>> {
+    let inner = ReaderJobIterator {
+        reader: CountingReader::new(reader),
+        artifact_adder,
+        layers: HashMap::new(),
+        values_read: 0,
+        done: false,
+    };
+    JobSpecIterator {
+        inner,
+        layer_mapper,
+        env_lookup,
+        image_lookup,
+        default_image,
+    }
+}
+
+/// Read the jobs from a TOML document. See [`TomlJobs`] for the format. The whole document has to
+/// be read and parsed before any of the jobs can be returned.
+fn toml_job_iter(reader: impl Read) -> Box<dyn Iterator<Item = Result<Job>>> {
+    match read_toml_jobs(reader) {
+        Err(err) => Box::new(iter::once(Err(err))),
+        Ok(jobs) => Box::new(
+            (1..)
+                .zip(jobs)
+                .map(|(index, job)| job.with_context(|| format!("job #{index}"))),
+        ),
+    }
+}
+
+/// Like [`job_spec_iter_from_reader`], but for jobs written in TOML. Layers can't be read from the
+/// stream in this format.
+pub fn job_spec_iter_from_toml_reader(
     reader: impl Read,
     layer_mapper: impl Fn(Layer) -> Result<(Sha256Digest, ArtifactType)>,
     env_lookup: impl Fn(&str) -> Result<Option<String>>,
     image_lookup: impl FnMut(&str) -> Result<ImageConfig>,
-) -> impl Iterator<Item = Result<JobSpec>> {
-    let inner = serde_json::Deserializer::from_reader(reader).into_iter::<Job>();
+    default_image: Option<Image>,
+) -> impl Iterator<Item = Result<NamedJobSpec>> {
+    JobSpecIterator {
+        inner: toml_job_iter(reader),
+        layer_mapper,
+        env_lookup,
+        image_lookup,
+        default_image,
+    }
+}
+
+/// Read the jobs from each of the given files in turn, as if they had all been concatenated
+/// together. Errors reading or parsing a file say which file it was.
+///
+/// Each file is read in `format`, or if that's `None`, in the format its extension suggests.
+///
+/// Relative paths of tar layers are resolved against the directory containing the file the job
+/// came from, so that a spec file can refer to tar files next to it regardless of the current
+/// working directory.
+pub fn job_spec_iter_from_files(
+    paths: impl IntoIterator<Item = PathBuf>,
+    format: Option<JobSpecFormat>,
+    layer_mapper: impl Fn(Layer) -> Result<(Sha256Digest, ArtifactType)>,
+    env_lookup: impl Fn(&str) -> Result<Option<String>>,
+    image_lookup: impl FnMut(&str) -> Result<ImageConfig>,
+    default_image: Option<Image>,
+) -> impl Iterator<Item = Result<NamedJobSpec>> {
+    let inner = paths
+        .into_iter()
+        .flat_map(move |path| -> Box<dyn Iterator<Item = Result<Job>>> {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(err) => {
+                    return Box::new(iter::once(
+                        Err(err).with_context(|| format!("opening {}", path.display())),
+                    ))
+                }
+            };
+            let base = path
+                .parent()
+                .map(|dir| Utf8PathBuf::try_from(dir.to_owned()).ok());
+            let jobs: Box<dyn Iterator<Item = Result<Job>>> =
+                match format.unwrap_or_else(|| JobSpecFormat::from_path(&path)) {
+                    JobSpecFormat::Json => Box::new(
+                        serde_json::Deserializer::from_reader(BufReader::new(file))
+                            .into_iter::<Job>()
+                            .map(|job| Ok(job?)),
+                    ),
+                    JobSpecFormat::Toml => toml_job_iter(file),
+                };
+            Box::new(jobs.map(move |job| {
+                let mut job = job.with_context(|| format!("parsing {}", path.display()))?;
+                match &base {
+                    None => {}
+                    Some(Some(base)) => job.resolve_relative_tar_paths(base),
+                    Some(None) => {
+                        bail!("directory of {} isn't valid UTF-8", path.display())
+                    }
+                }
+                Ok(job)
+            }))
+        });
     JobSpecIterator {
         inner,
         layer_mapper,
         env_lookup,
         image_lookup,
+        default_image,
+    }
+}
+
+/// Collects the problems found in job specifications without running them. Layers aren't built, so
+/// every layer is mapped to the same placeholder digest.
+struct JobValidator<EnvLookupT, ImageLookupT> {
+    env_lookup: EnvLookupT,
+    image_lookup: ImageLookupT,
+    default_image: Option<Image>,
+    /// The names and dependencies of every job, used to check dependencies once all of the jobs
+    /// have been read. Jobs that couldn't be parsed have no name or dependencies.
+    dependencies: Vec<(Option<String>, Vec<String>, ())>,
+    errors: Vec<Error>,
+}
+
+impl<EnvLookupT, ImageLookupT> JobValidator<EnvLookupT, ImageLookupT>
+where
+    EnvLookupT: Fn(&str) -> Result<Option<String>>,
+    ImageLookupT: FnMut(&str) -> Result<ImageConfig>,
+{
+    fn new(
+        env_lookup: EnvLookupT,
+        image_lookup: ImageLookupT,
+        default_image: Option<Image>,
+    ) -> Self {
+        Self {
+            env_lookup,
+            image_lookup,
+            default_image,
+            dependencies: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// Check the `index`th job in `source`, which is an error if the job couldn't be parsed. Its
+    /// stdin layers are resolved against `stdin_layers` if it came from a stream that can contain
+    /// them.
+    fn check(
+        &mut self,
+        source: &str,
+        index: usize,
+        job: Result<Job>,
+        stdin_layers: Option<&HashMap<String, Sha256Digest>>,
+    ) {
+        let dependencies_len = self.dependencies.len();
+        let result = self.check_job(job, stdin_layers);
+        if self.dependencies.len() == dependencies_len {
+            self.dependencies.push((None, vec![], ()));
+        }
+        if let Err(err) = result {
+            self.errors
+                .push(err.context(format!("job {index} in {source}")));
+        }
+    }
+
+    /// Check a job given as the raw text of a JSON value. The job is parsed from the raw text,
+    /// since the order of its fields matters.
+    fn check_json(
+        &mut self,
+        source: &str,
+        index: usize,
+        value: &RawValue,
+        stdin_layers: Option<&HashMap<String, Sha256Digest>>,
+    ) {
+        let job = serde_json::from_str(value.get()).map_err(Error::new);
+        self.check(source, index, job, stdin_layers);
+    }
+
+    /// Check every job in a TOML document. A syntax error means none of them can be checked.
+    fn check_toml(&mut self, source: &str, reader: impl Read) {
+        match read_toml_jobs(reader) {
+            Ok(jobs) => {
+                for (index, job) in (1..).zip(jobs) {
+                    self.check(source, index, job, None);
+                }
+            }
+            Err(err) => self.errors.push(err.context(format!("parsing {source}"))),
+        }
+    }
+
+    fn check_job(
+        &mut self,
+        job: Result<Job>,
+        stdin_layers: Option<&HashMap<String, Sha256Digest>>,
+    ) -> Result<()> {
+        let mut job = job?;
+        self.dependencies
+            .push((job.name.clone(), job.depends_on.clone(), ()));
+        if let Some(stdin_layers) = stdin_layers {
+            job.resolve_stdin_layers(stdin_layers)?;
+        }
+        let spec = job.into_job_spec(
+            |_| Ok((Sha256Digest::from(0u64), ArtifactType::Tar)),
+            &self.env_lookup,
+            &mut self.image_lookup,
+            self.default_image.as_ref(),
+        )?;
+        spec.validate()
+            .with_context(|| format!("invalid job for {}", spec.program))
     }
+
+    fn finish(mut self) -> Vec<Error> {
+        let mut scheduler = JobScheduler::new();
+        let result = self
+            .dependencies
+            .into_iter()
+            .try_for_each(|(name, depends_on, ())| scheduler.add(name, depends_on, ()).map(drop))
+            .and_then(|()| scheduler.check_complete());
+        if let Err(err) = result {
+            self.errors.push(err);
+        }
+        self.errors
+    }
+}
+
+/// Check every job read from `reader` without running any of them, returning all of the problems
+/// found instead of stopping at the first. The stream has the same format as for
+/// [`job_spec_iter_from_reader`], but the contents of layers in it are skipped.
+///
+/// After a JSON syntax error, we can't tell where the next job starts, so nothing after it is
+/// checked.
+pub fn validate_job_specs_from_reader(
+    reader: impl BufRead,
+    env_lookup: impl Fn(&str) -> Result<Option<String>>,
+    image_lookup: impl FnMut(&str) -> Result<ImageConfig>,
+    default_image: Option<Image>,
+) -> Vec<Error> {
+    let mut validator = JobValidator::new(env_lookup, image_lookup, default_image);
+    let mut jobs = ReaderJobIterator {
+        reader: CountingReader::new(reader),
+        artifact_adder: |contents: &mut dyn Read| {
+            io::copy(contents, &mut io::sink())?;
+            Ok(Sha256Digest::from(0u64))
+        },
+        layers: HashMap::new(),
+        values_read: 0,
+        done: false,
+    };
+    for index in 1.. {
+        match jobs.next_value::<Box<RawValue>>() {
+            Ok(None) => break,
+            Ok(Some(value)) => {
+                validator.check_json("standard input", index, &value, Some(&jobs.layers));
+            }
+            Err(err) => {
+                validator.errors.push(err.context("reading standard input"));
+                break;
+            }
+        }
+    }
+    validator.finish()
+}
+
+/// Like [`validate_job_specs_from_reader`], but for jobs written in TOML, as read by
+/// [`job_spec_iter_from_toml_reader`].
+pub fn validate_job_specs_from_toml_reader(
+    reader: impl Read,
+    env_lookup: impl Fn(&str) -> Result<Option<String>>,
+    image_lookup: impl FnMut(&str) -> Result<ImageConfig>,
+    default_image: Option<Image>,
+) -> Vec<Error> {
+    let mut validator = JobValidator::new(env_lookup, image_lookup, default_image);
+    validator.check_toml("standard input", reader);
+    validator.finish()
+}
+
+/// Like [`validate_job_specs_from_reader`], but for the jobs in each of the given files, as read by
+/// [`job_spec_iter_from_files`]. A syntax error in one file doesn't stop the others from being
+/// checked.
+pub fn validate_job_specs_from_files(
+    paths: impl IntoIterator<Item = PathBuf>,
+    format: Option<JobSpecFormat>,
+    env_lookup: impl Fn(&str) -> Result<Option<String>>,
+    image_lookup: impl FnMut(&str) -> Result<ImageConfig>,
+    default_image: Option<Image>,
+) -> Vec<Error> {
+    let mut validator = JobValidator::new(env_lookup, image_lookup, default_image);
+    for path in paths {
+        let source = path.display().to_string();
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                validator
+                    .errors
+                    .push(Error::new(err).context(format!("opening {source}")));
+                continue;
+            }
+        };
+        if format.unwrap_or_else(|| JobSpecFormat::from_path(&path)) == JobSpecFormat::Toml {
+            validator.check_toml(&source, file);
+            continue;
+        }
+        let values = serde_json::Deserializer::from_reader(BufReader::new(file))
+            .into_iter::<Box<RawValue>>();
+        for (index, value) in (1..).zip(values) {
+            match value {
+                Ok(value) => validator.check_json(&source, index, &value, None),
+                Err(err) => {
+                    validator
+                        .errors
+                        .push(Error::new(err).context(format!("parsing {source}")));
+                    break;
+                }
+            }
+        }
+    }
+    validator.finish()
+}
+
+/// A layer in a job. Besides the layers the client builds, a job read from a stream can use a tar
+/// file whose contents came earlier in the same stream.
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[serde(untagged, deny_unknown_fields)]
+enum JobLayer {
+    Client(Layer),
+    Stdin {
+        stdin: String,
+    },
+    /// A `Stdin` layer whose contents have been added as an artifact.
+    #[serde(skip)]
+    Artifact(Sha256Digest),
 }
 
 #[derive(Debug, Eq, PartialEq)]
 struct Job {
+    name: Option<String>,
+    depends_on: Vec<String>,
     program: Utf8PathBuf,
     arguments: Option<Vec<String>>,
     environment: Option<PossiblyImage<BTreeMap<String, String>>>,
     added_environment: BTreeMap<String, String>,
-    layers: PossiblyImage<NonEmpty<Layer>>,
-    added_layers: Vec<Layer>,
+    layers: Option<PossiblyImage<NonEmpty<JobLayer>>>,
+    added_layers: Vec<JobLayer>,
     devices: Option<EnumSet<JobDeviceListDeserialize>>,
     mounts: Option<Vec<JobMount>>,
+    scratch_dir: Option<Utf8PathBuf>,
     enable_loopback: Option<bool>,
     enable_writable_file_system: Option<bool>,
     working_directory: Option<PossiblyImage<Utf8PathBuf>>,
@@ -71,20 +648,28 @@ struct Job {
     group: Option<GroupId>,
     image: Option<String>,
     timeout: Option<u32>,
+    enable_init_process: Option<bool>,
+    cpus: Option<u32>,
+    memory: Option<u64>,
+    priority: Option<i8>,
+    allowed_exit_codes: Option<Vec<u8>>,
 }
 
 impl Job {
     #[cfg(test)]
     fn new(program: Utf8PathBuf, layers: NonEmpty<Layer>) -> Self {
         Job {
+            name: None,
+            depends_on: Default::default(),
             program,
-            layers: PossiblyImage::Explicit(layers),
+            layers: Some(PossiblyImage::Explicit(layers.map(JobLayer::Client))),
             added_layers: Default::default(),
             arguments: None,
             environment: None,
             added_environment: Default::default(),
             devices: None,
             mounts: None,
+            scratch_dir: None,
             enable_loopback: None,
             enable_writable_file_system: None,
             working_directory: None,
@@ -92,15 +677,131 @@ impl Job {
             group: None,
             image: None,
             timeout: None,
+            enable_init_process: None,
+            cpus: None,
+            memory: None,
+            priority: None,
+            allowed_exit_codes: None,
+        }
+    }
+
+    /// The layers given in the job itself, as opposed to those that come from an image.
+    fn explicit_layers_mut(&mut self) -> impl Iterator<Item = &mut JobLayer> {
+        let explicit_layers = match &mut self.layers {
+            Some(PossiblyImage::Explicit(layers)) => Some(layers.iter_mut()),
+            Some(PossiblyImage::Image) | None => None,
+        };
+        explicit_layers
+            .into_iter()
+            .flatten()
+            .chain(&mut self.added_layers)
+    }
+
+    /// Make the paths of tar layers that are relative be relative to `base` instead.
+    fn resolve_relative_tar_paths(&mut self, base: &Utf8Path) {
+        for layer in self.explicit_layers_mut() {
+            if let JobLayer::Client(Layer::Tar { path }) = layer {
+                if path.is_relative() {
+                    *path = base.join(&*path);
+                }
+            }
+        }
+    }
+
+    /// Replace the layers that refer to contents read from the stream with the artifacts they were
+    /// added as.
+    fn resolve_stdin_layers(&mut self, artifacts: &HashMap<String, Sha256Digest>) -> Result<()> {
+        for layer in self.explicit_layers_mut() {
+            if let JobLayer::Stdin { stdin } = layer {
+                let digest = artifacts
+                    .get(stdin)
+                    .ok_or_else(|| anyhow!("no layer named {stdin:?} has been read"))?;
+                *layer = JobLayer::Artifact(digest.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Use `default_image` as the job's image. Fields the default image is used for can't also be
+    /// set in the job, just as if it had been given in the job's `image` field.
+    fn use_default_image(&mut self, default_image: &Image) -> Result<()> {
+        self.image = Some(default_image.name.clone());
+        for use_ in default_image.use_ {
+            match use_ {
+                ImageUse::WorkingDirectory => {
+                    if self.working_directory.is_some() {
+                        bail!(concat!(
+                            "field `working_directory` cannot be set if the default image has a ",
+                            "`use` of `working_directory`"
+                        ));
+                    }
+                    self.working_directory = Some(PossiblyImage::Image);
+                }
+                ImageUse::Layers => {
+                    if self.layers.is_some() {
+                        bail!(concat!(
+                            "field `layers` cannot be set if the default image has a `use` of ",
+                            "`layers` (try `added_layers` instead)"
+                        ));
+                    }
+                    self.layers = Some(PossiblyImage::Image);
+                }
+                ImageUse::Environment => {
+                    if self.environment.is_some() {
+                        bail!(concat!(
+                            "field `environment` cannot be set if the default image has a `use` ",
+                            "of `environment` (try `added_environment` instead)"
+                        ));
+                    }
+                    self.environment = Some(PossiblyImage::Image);
+                }
+            }
         }
+        Ok(())
+    }
+
+    fn into_named_job_spec(
+        mut self,
+        layer_mapper: impl Fn(Layer) -> Result<(Sha256Digest, ArtifactType)>,
+        env_lookup: impl Fn(&str) -> Result<Option<String>>,
+        image_lookup: impl FnMut(&str) -> Result<ImageConfig>,
+        default_image: Option<&Image>,
+    ) -> Result<NamedJobSpec> {
+        let name = self.name.take();
+        let depends_on = mem::take(&mut self.depends_on);
+        let allowed_exit_codes = self.allowed_exit_codes.take().unwrap_or_default();
+        Ok(NamedJobSpec {
+            name,
+            depends_on,
+            allowed_exit_codes,
+            spec: self.into_job_spec(layer_mapper, env_lookup, image_lookup, default_image)?,
+        })
     }
 
+    /// Turn the job into a [`JobSpec`]. If the job doesn't have an `image` field, `default_image`
+    /// is used in its place before any image is looked up.
     fn into_job_spec(
-        self,
+        mut self,
         layer_mapper: impl Fn(Layer) -> Result<(Sha256Digest, ArtifactType)>,
         env_lookup: impl Fn(&str) -> Result<Option<String>>,
         image_lookup: impl FnMut(&str) -> Result<ImageConfig>,
+        default_image: Option<&Image>,
     ) -> Result<JobSpec> {
+        if let (None, Some(default_image)) = (&self.image, default_image) {
+            self.use_default_image(default_image)?;
+        }
+        // Without an `image` field, these couldn't be checked until the default image was known.
+        if !self.added_layers.is_empty() && !matches!(self.layers, Some(PossiblyImage::Image)) {
+            bail!("field `added_layers` cannot be set without an image with a `use` of `layers`");
+        }
+        if !self.added_environment.is_empty()
+            && !matches!(self.environment, Some(PossiblyImage::Image))
+        {
+            bail!(concat!(
+                "field `added_environment` cannot be set without an image with a `use` of ",
+                "`environment`"
+            ));
+        }
         let image = ImageOption::new(&self.image, image_lookup)?;
         let mut environment = match self.environment {
             None => BTreeMap::default(),
@@ -132,17 +833,32 @@ impl Job {
         environment.extend(added_environment);
         let environment = Vec::from_iter(environment.into_iter().map(|(k, v)| k + "=" + &v));
         let mut layers = match self.layers {
-            PossiblyImage::Explicit(layers) => layers,
-            PossiblyImage::Image => NonEmpty::from_vec(image.layers()?.collect())
-                .ok_or_else(|| anyhow!("image {} has no layers to use", image.name()))?,
+            None => bail!("missing field `layers`"),
+            Some(PossiblyImage::Explicit(layers)) => layers,
+            Some(PossiblyImage::Image) => {
+                NonEmpty::from_vec(image.layers()?.map(JobLayer::Client).collect())
+                    .ok_or_else(|| anyhow!("image {} has no layers to use", image.name()))?
+            }
         };
         layers.extend(self.added_layers);
-        let layers = layers.try_map(layer_mapper)?;
+        let layers = layers.try_map(|layer| match layer {
+            JobLayer::Client(layer) => layer_mapper(layer),
+            JobLayer::Artifact(digest) => Ok((digest, ArtifactType::Tar)),
+            JobLayer::Stdin { stdin } => Err(anyhow!(
+                "layer {stdin:?} can only be used by jobs read from standard input"
+            )),
+        })?;
         let working_directory = match self.working_directory {
             None => Utf8PathBuf::from("/"),
             Some(PossiblyImage::Explicit(working_directory)) => working_directory,
             Some(PossiblyImage::Image) => image.working_directory()?,
         };
+        // A scratch directory is just a tmpfs mounted after the other mounts.
+        let mut mounts = self.mounts.unwrap_or_default();
+        mounts.extend(self.scratch_dir.map(|mount_point| JobMount {
+            fs_type: JobMountFsType::Tmp,
+            mount_point,
+        }));
         Ok(JobSpec {
             program: self.program,
             arguments: self.arguments.unwrap_or_default(),
@@ -154,13 +870,18 @@ impl Job {
                 .into_iter()
                 .map(JobDevice::from)
                 .collect(),
-            mounts: self.mounts.unwrap_or_default(),
+            mounts,
             enable_loopback: self.enable_loopback.unwrap_or_default(),
             enable_writable_file_system: self.enable_writable_file_system.unwrap_or_default(),
             working_directory,
             user: self.user.unwrap_or(UserId::from(0)),
             group: self.group.unwrap_or(GroupId::from(0)),
             timeout: self.timeout.and_then(Timeout::new),
+            enable_init_process: self.enable_init_process.unwrap_or_default(),
+            cpus: self.cpus.unwrap_or_default(),
+            memory: self.memory.unwrap_or_default(),
+            stream_output: false,
+            priority: self.priority.unwrap_or_default(),
         })
     }
 }
@@ -168,6 +889,8 @@ impl Job {
 #[derive(Deserialize)]
 #[serde(field_identifier, rename_all = "snake_case")]
 enum JobField {
+    Name,
+    DependsOn,
     Program,
     Arguments,
     Environment,
@@ -176,6 +899,7 @@ enum JobField {
     AddedLayers,
     Devices,
     Mounts,
+    ScratchDir,
     EnableLoopback,
     EnableWritableFileSystem,
     WorkingDirectory,
@@ -183,12 +907,20 @@ enum JobField {
     Group,
     Image,
     Timeout,
+    EnableInitProcess,
+    Cpus,
+    Memory,
+    Priority,
+    AllowedExitCodes,
 }
 
 struct JobVisitor;
 
+/// Check that `var` comes from an image, so that it can be added to. If no `image` field has been
+/// seen yet, the check is left for when the job's default image is known.
 fn must_be_image<T, E>(
     var: &Option<PossiblyImage<T>>,
+    image: &Option<String>,
     if_none: &str,
     if_explicit: &str,
 ) -> std::result::Result<(), E>
@@ -196,6 +928,7 @@ where
     E: de::Error,
 {
     match var {
+        None if image.is_none() => Ok(()),
         None => Err(E::custom(format_args!("{}", if_none))),
         Some(PossiblyImage::Explicit(_)) => Err(E::custom(format_args!("{}", if_explicit))),
         Some(PossiblyImage::Image) => Ok(()),
@@ -213,6 +946,8 @@ impl<'de> de::Visitor<'de> for JobVisitor {
     where
         A: de::MapAccess<'de>,
     {
+        let mut name = None;
+        let mut depends_on = None;
         let mut program = None;
         let mut arguments = None;
         let mut environment = None;
@@ -221,6 +956,7 @@ impl<'de> de::Visitor<'de> for JobVisitor {
         let mut added_layers = None;
         let mut devices = None;
         let mut mounts = None;
+        let mut scratch_dir = None;
         let mut enable_loopback = None;
         let mut enable_writable_file_system = None;
         let mut working_directory = None;
@@ -228,8 +964,19 @@ impl<'de> de::Visitor<'de> for JobVisitor {
         let mut group = None;
         let mut image = None;
         let mut timeout = None;
+        let mut enable_init_process = None;
+        let mut cpus = None;
+        let mut memory = None;
+        let mut priority = None;
+        let mut allowed_exit_codes = None;
         while let Some(key) = map.next_key()? {
             match key {
+                JobField::Name => {
+                    name = Some(map.next_value()?);
+                }
+                JobField::DependsOn => {
+                    depends_on = Some(map.next_value()?);
+                }
                 JobField::Program => {
                     program = Some(map.next_value()?);
                 }
@@ -244,11 +991,16 @@ impl<'de> de::Visitor<'de> for JobVisitor {
                             "`environment` is also set (try `added_environment` instead)"
                         ),
                     )?;
+                    incompatible(
+                        &added_environment,
+                        "field `added_environment` set before `image` with a `use` of `environment`",
+                    )?;
                     environment = Some(PossiblyImage::Explicit(map.next_value()?));
                 }
                 JobField::AddedEnvironment => {
                     must_be_image(
                         &environment,
+                        &image,
                         "field `added_environment` set before `image` with a `use` of `environment`",
                         "field `added_environment` cannot be set with `environment` field",
                     )?;
@@ -262,6 +1014,10 @@ impl<'de> de::Visitor<'de> for JobVisitor {
                             "`layers` is also set (try `added_layers` instead)"
                         ),
                     )?;
+                    incompatible(
+                        &added_layers,
+                        "field `added_layers` set before `image` with a `use` of `layers`",
+                    )?;
                     layers = Some(PossiblyImage::Explicit(
                         NonEmpty::from_vec(map.next_value()?).ok_or_else(|| {
                             de::Error::custom(format_args!("field `layers` cannot be empty"))
@@ -271,6 +1027,7 @@ impl<'de> de::Visitor<'de> for JobVisitor {
                 JobField::AddedLayers => {
                     must_be_image(
                         &layers,
+                        &image,
                         "field `added_layers` set before `image` with a `use` of `layers`",
                         "field `added_layers` cannot be set with `layer` field",
                     )?;
@@ -282,6 +1039,9 @@ impl<'de> de::Visitor<'de> for JobVisitor {
                 JobField::Mounts => {
                     mounts = Some(map.next_value()?);
                 }
+                JobField::ScratchDir => {
+                    scratch_dir = Some(map.next_value()?);
+                }
                 JobField::EnableLoopback => {
                     enable_loopback = Some(map.next_value()?);
                 }
@@ -307,7 +1067,30 @@ impl<'de> de::Visitor<'de> for JobVisitor {
                 JobField::Timeout => {
                     timeout = Some(map.next_value()?);
                 }
+                JobField::EnableInitProcess => {
+                    enable_init_process = Some(map.next_value()?);
+                }
+                JobField::Cpus => {
+                    cpus = Some(map.next_value()?);
+                }
+                JobField::Memory => {
+                    memory = Some(map.next_value()?);
+                }
+                JobField::Priority => {
+                    priority = Some(map.next_value()?);
+                }
+                JobField::AllowedExitCodes => {
+                    allowed_exit_codes = Some(map.next_value()?);
+                }
                 JobField::Image => {
+                    incompatible(
+                        &added_layers,
+                        "field `added_layers` set before `image` with a `use` of `layers`",
+                    )?;
+                    incompatible(
+                        &added_environment,
+                        "field `added_environment` set before `image` with a `use` of `environment`",
+                    )?;
                     let i = map.next_value::<Image>()?;
                     image = Some(i.name);
                     for use_ in i.use_ {
@@ -338,15 +1121,22 @@ impl<'de> de::Visitor<'de> for JobVisitor {
                 }
             }
         }
+        // A job without an `image` field may get its layers from the default image.
+        if image.is_some() && layers.is_none() {
+            return Err(de::Error::missing_field("layers"));
+        }
         Ok(Job {
+            name,
+            depends_on: depends_on.unwrap_or_default(),
             program: program.ok_or_else(|| de::Error::missing_field("program"))?,
             arguments,
             environment,
             added_environment: added_environment.unwrap_or_default(),
-            layers: layers.ok_or_else(|| de::Error::missing_field("layers"))?,
+            layers,
             added_layers: added_layers.unwrap_or_default(),
             devices,
             mounts,
+            scratch_dir,
             enable_loopback,
             enable_writable_file_system,
             working_directory,
@@ -354,6 +1144,11 @@ impl<'de> de::Visitor<'de> for JobVisitor {
             group,
             image,
             timeout,
+            enable_init_process,
+            cpus,
+            memory,
+            priority,
+            allowed_exit_codes,
         })
     }
 }
@@ -367,12 +1162,84 @@ impl<'de> de::Deserialize<'de> for Job {
     }
 }
 
+/// The fields of a job read from TOML, in the order they were written. Whether some fields conflict
+/// depends on which came first, and [`toml::Table`] doesn't keep the order, so the fields are held
+/// like this until they can be given to [`JobVisitor`].
+struct TomlJob(Vec<(String, toml::Value)>);
+
+impl TomlJob {
+    fn into_job(self) -> Result<Job> {
+        Job::deserialize(de::value::MapDeserializer::<_, toml::de::Error>::new(
+            self.0.into_iter(),
+        ))
+        // The error can't say where in the document the problem is, so there's nothing to show but
+        // the message.
+        .map_err(|err| anyhow!("{}", err.message()))
+    }
+}
+
+struct TomlJobVisitor;
+
+impl<'de> de::Visitor<'de> for TomlJobVisitor {
+    type Value = TomlJob;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "Job")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut fields = vec![];
+        while let Some(field) = map.next_entry()? {
+            fields.push(field);
+        }
+        Ok(TomlJob(fields))
+    }
+}
+
+impl<'de> de::Deserialize<'de> for TomlJob {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(TomlJobVisitor)
+    }
+}
+
+/// A TOML document of jobs. The jobs are written as an array of tables:
+///
+/// ```toml
+/// [[jobs]]
+/// program = "/bin/echo"
+/// arguments = ["hello"]
+/// layers = [{ tar = "echo.tar" }]
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlJobs {
+    #[serde(default)]
+    jobs: Vec<TomlJob>,
+}
+
+/// Read and parse a TOML document of jobs. A syntax error anywhere in the document is returned on
+/// its own. Otherwise, a problem with one job is returned in its place.
+fn read_toml_jobs(mut reader: impl Read) -> Result<Vec<Result<Job>>> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+    let TomlJobs { jobs } = toml::from_str(&contents)?;
+    Ok(Vec::from_iter(jobs.into_iter().map(TomlJob::into_job)))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use anyhow::Error;
     use assert_matches::assert_matches;
-    use maelstrom_base::{enum_set, nonempty, JobMountFsType};
+    use maelstrom_base::{enum_set, nonempty};
     use maelstrom_test::{digest, path_buf_vec, string, string_vec, tar_layer, utf8_path_buf};
+    use std::io;
 
     fn layer_mapper(layer: Layer) -> Result<(Sha256Digest, ArtifactType)> {
         assert_matches!(layer, Layer::Tar { path } => {
@@ -411,32 +1278,77 @@ mod test {
     fn minimum_into_job_spec() {
         assert_eq!(
             Job::new(utf8_path_buf!("program"), nonempty![tar_layer!("1")])
-                .into_job_spec(layer_mapper, env, images)
+                .into_job_spec(layer_mapper, env, images, None)
                 .unwrap(),
             JobSpec::new("program", nonempty![(digest!(1), ArtifactType::Tar)]),
         );
     }
 
     #[test]
-    fn most_into_job_spec() {
+    fn name_and_depends_on_into_named_job_spec() {
         assert_eq!(
-            Job {
-                arguments: Some(string_vec!["arg1", "arg2"]),
-                environment: Some(PossiblyImage::Explicit(BTreeMap::from([
-                    (string!("FOO"), string!("foo")),
-                    (string!("BAR"), string!("bar")),
-                ]))),
-                devices: Some(enum_set! {JobDeviceListDeserialize::Null}),
-                mounts: Some(vec![JobMount {
-                    fs_type: JobMountFsType::Tmp,
-                    mount_point: utf8_path_buf!("/tmp"),
-                }]),
+            parse_job(
+                r#"{
+                    "name": "b",
+                    "depends_on": [ "a" ],
+                    "program": "/bin/sh",
+                    "layers": [ { "tar": "1" } ]
+                }"#,
+            )
+            .unwrap()
+            .into_named_job_spec(layer_mapper, env, images, None)
+            .unwrap(),
+            NamedJobSpec {
+                name: Some(string!("b")),
+                depends_on: string_vec!["a"],
+                allowed_exit_codes: vec![],
+                spec: JobSpec::new("/bin/sh", nonempty![(digest!(1), ArtifactType::Tar)]),
+            },
+        );
+    }
+
+    #[test]
+    fn allowed_exit_codes_into_named_job_spec() {
+        assert_eq!(
+            parse_job(
+                r#"{
+                    "program": "/bin/sh",
+                    "layers": [ { "tar": "1" } ],
+                    "allowed_exit_codes": [ 1, 2 ]
+                }"#,
+            )
+            .unwrap()
+            .into_named_job_spec(layer_mapper, env, images, None)
+            .unwrap(),
+            NamedJobSpec {
+                name: None,
+                depends_on: vec![],
+                allowed_exit_codes: vec![1, 2],
+                spec: JobSpec::new("/bin/sh", nonempty![(digest!(1), ArtifactType::Tar)]),
+            },
+        );
+    }
+
+    #[test]
+    fn most_into_job_spec() {
+        assert_eq!(
+            Job {
+                arguments: Some(string_vec!["arg1", "arg2"]),
+                environment: Some(PossiblyImage::Explicit(BTreeMap::from([
+                    (string!("FOO"), string!("foo")),
+                    (string!("BAR"), string!("bar")),
+                ]))),
+                devices: Some(enum_set! {JobDeviceListDeserialize::Null}),
+                mounts: Some(vec![JobMount {
+                    fs_type: JobMountFsType::Tmp,
+                    mount_point: utf8_path_buf!("/tmp"),
+                }]),
                 working_directory: Some(PossiblyImage::Explicit("/working-directory".into())),
                 user: Some(UserId::from(101)),
                 group: Some(GroupId::from(202)),
                 ..Job::new(utf8_path_buf!("program"), nonempty![tar_layer!("1")])
             }
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new("program", nonempty![(digest!(1), ArtifactType::Tar)])
                 .arguments(["arg1", "arg2"])
@@ -459,7 +1371,7 @@ mod test {
                 enable_loopback: Some(true),
                 ..Job::new(utf8_path_buf!("program"), nonempty![tar_layer!("1")])
             }
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new("program", nonempty![(digest!(1), ArtifactType::Tar)])
                 .enable_loopback(true),
@@ -473,7 +1385,7 @@ mod test {
                 enable_writable_file_system: Some(true),
                 ..Job::new(utf8_path_buf!("program"), nonempty![tar_layer!("1")])
             }
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new("program", nonempty![(digest!(1), ArtifactType::Tar)])
                 .enable_writable_file_system(true),
@@ -510,7 +1422,7 @@ mod test {
                 }"#
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -534,12 +1446,28 @@ mod test {
 
     #[test]
     fn missing_layers() {
-        assert_error(
+        assert_anyhow_error(
             parse_job(
                 r#"{
                     "program": "/bin/sh"
                 }"#,
             )
+            .unwrap()
+            .into_job_spec(layer_mapper, env, images, None)
+            .unwrap_err(),
+            "missing field `layers`",
+        );
+    }
+
+    #[test]
+    fn missing_layers_with_image() {
+        assert_error(
+            parse_job(
+                r#"{
+                    "program": "/bin/sh",
+                    "image": { "name": "image1", "use": [ "environment" ] }
+                }"#,
+            )
             .unwrap_err(),
             "missing field `layers`",
         );
@@ -572,7 +1500,7 @@ mod test {
                 }"#
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -597,7 +1525,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap_err(),
             "image empty has no layers to use",
         );
@@ -653,7 +1581,7 @@ mod test {
                 }"#
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -668,15 +1596,17 @@ mod test {
 
     #[test]
     fn added_layers_only() {
-        assert_error(
+        assert_anyhow_error(
             parse_job(
                 r#"{
                     "program": "/bin/sh",
                     "added_layers": [ { "tar": "1" } ]
                 }"#,
             )
+            .unwrap()
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap_err(),
-            "field `added_layers` set before `image` with a `use` of `layers`",
+            "field `added_layers` cannot be set without an image with a `use` of `layers`",
         );
     }
 
@@ -716,7 +1646,7 @@ mod test {
             parse_job(
                 r#"{
                     "program": "/bin/sh",
-                    "added_layers": [ "3" ],
+                    "added_layers": [ { "tar": "3" } ],
                     "image": { "name": "image1", "use": [ "layers" ] }
                 }"#,
             )
@@ -736,7 +1666,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -757,7 +1687,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -778,7 +1708,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -799,7 +1729,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -820,7 +1750,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -841,7 +1771,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -895,7 +1825,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -921,7 +1851,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -937,7 +1867,7 @@ mod test {
 
     #[test]
     fn added_environment_without_environment_from_image() {
-        assert_error(
+        assert_anyhow_error(
             parse_job(
                 r#"{
                     "program": "/bin/sh",
@@ -945,8 +1875,11 @@ mod test {
                     "added_environment": { "FOO": "foo", "BAR": "bar" }
                 }"#,
             )
+            .unwrap()
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap_err(),
-            "field `added_environment` set before `image` with a `use` of `environment`",
+            "field `added_environment` cannot be set without an image with a `use` of \
+                `environment`",
         )
     }
 
@@ -1009,7 +1942,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -1032,7 +1965,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -1056,7 +1989,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -1077,7 +2010,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -1098,7 +2031,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -1122,7 +2055,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -1181,7 +2114,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -1202,7 +2135,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -1223,7 +2156,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -1244,7 +2177,7 @@ mod test {
                 }"#,
             )
             .unwrap()
-            .into_job_spec(layer_mapper, env, images)
+            .into_job_spec(layer_mapper, env, images, None)
             .unwrap(),
             JobSpec::new(
                 string!("/bin/sh"),
@@ -1253,4 +2186,867 @@ mod test {
             .timeout(Timeout::new(0)),
         )
     }
+
+    #[test]
+    fn enable_init_process() {
+        assert_eq!(
+            parse_job(
+                r#"{
+                    "program": "/bin/sh",
+                    "layers": [ { "tar": "1" } ],
+                    "enable_init_process": true
+                }"#,
+            )
+            .unwrap()
+            .into_job_spec(layer_mapper, env, images, None)
+            .unwrap(),
+            JobSpec::new(
+                string!("/bin/sh"),
+                nonempty![(digest!(1), ArtifactType::Tar)]
+            )
+            .enable_init_process(true),
+        )
+    }
+
+    #[test]
+    fn cpus_and_memory() {
+        assert_eq!(
+            parse_job(
+                r#"{
+                    "program": "/bin/sh",
+                    "layers": [ { "tar": "1" } ],
+                    "cpus": 2,
+                    "memory": 1073741824
+                }"#,
+            )
+            .unwrap()
+            .into_job_spec(layer_mapper, env, images, None)
+            .unwrap(),
+            JobSpec::new(
+                string!("/bin/sh"),
+                nonempty![(digest!(1), ArtifactType::Tar)]
+            )
+            .cpus(2)
+            .memory(1073741824),
+        )
+    }
+
+    #[test]
+    fn priority() {
+        assert_eq!(
+            parse_job(
+                r#"{
+                    "program": "/bin/sh",
+                    "layers": [ { "tar": "1" } ],
+                    "priority": -3
+                }"#,
+            )
+            .unwrap()
+            .into_job_spec(layer_mapper, env, images, None)
+            .unwrap(),
+            JobSpec::new(
+                string!("/bin/sh"),
+                nonempty![(digest!(1), ArtifactType::Tar)]
+            )
+            .priority(-3),
+        )
+    }
+
+    #[test]
+    fn scratch_dir_added_to_mounts() {
+        assert_eq!(
+            parse_job(
+                r#"{
+                    "program": "/bin/sh",
+                    "layers": [ { "tar": "1" } ],
+                    "mounts": [ { "fs_type": "proc", "mount_point": "/proc" } ],
+                    "scratch_dir": "/tmp"
+                }"#,
+            )
+            .unwrap()
+            .into_job_spec(layer_mapper, env, images, None)
+            .unwrap(),
+            JobSpec::new(
+                string!("/bin/sh"),
+                nonempty![(digest!(1), ArtifactType::Tar)]
+            )
+            .mounts([
+                JobMount {
+                    fs_type: JobMountFsType::Proc,
+                    mount_point: utf8_path_buf!("/proc"),
+                },
+                JobMount {
+                    fs_type: JobMountFsType::Tmp,
+                    mount_point: utf8_path_buf!("/tmp"),
+                },
+            ]),
+        )
+    }
+
+    fn job_spec_iter_from_temp_files(
+        contents: &[&str],
+    ) -> (tempfile::TempDir, Vec<PathBuf>, Vec<Result<NamedJobSpec>>) {
+        let files = Vec::from_iter(
+            contents
+                .iter()
+                .enumerate()
+                .map(|(i, contents)| (format!("spec{i}.json"), *contents)),
+        );
+        job_spec_iter_from_named_temp_files(&files, None)
+    }
+
+    fn job_spec_iter_from_named_temp_files(
+        files: &[(String, &str)],
+        format: Option<JobSpecFormat>,
+    ) -> (tempfile::TempDir, Vec<PathBuf>, Vec<Result<NamedJobSpec>>) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let paths = Vec::from_iter(files.iter().map(|(name, contents)| {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        }));
+        // Relative tar paths are resolved against the directory of the spec file, so strip it back
+        // off before mapping the layer.
+        let dir = Utf8PathBuf::try_from(temp_dir.path().to_owned()).unwrap();
+        let layer_mapper = move |layer| match layer {
+            Layer::Tar { path } => layer_mapper(Layer::Tar {
+                path: path.strip_prefix(&dir).unwrap().to_owned(),
+            }),
+            layer => layer_mapper(layer),
+        };
+        let job_specs = Vec::from_iter(job_spec_iter_from_files(
+            paths.clone(),
+            format,
+            layer_mapper,
+            env,
+            images,
+            None,
+        ));
+        (temp_dir, paths, job_specs)
+    }
+
+    #[test]
+    fn multiple_files_run_in_order() {
+        let (_temp_dir, _paths, job_specs) = job_spec_iter_from_temp_files(&[
+            r#"
+                { "program": "/bin/a", "layers": [ { "tar": "1" } ] }
+                { "program": "/bin/b", "layers": [ { "tar": "1" } ] }
+            "#,
+            r#"
+                { "program": "/bin/c", "layers": [ { "tar": "2" } ] }
+            "#,
+        ]);
+        assert_eq!(
+            Vec::from_iter(job_specs.into_iter().map(|job_spec| job_spec.unwrap().spec)),
+            vec![
+                JobSpec::new(
+                    string!("/bin/a"),
+                    nonempty![(digest!(1), ArtifactType::Tar)]
+                ),
+                JobSpec::new(
+                    string!("/bin/b"),
+                    nonempty![(digest!(1), ArtifactType::Tar)]
+                ),
+                JobSpec::new(
+                    string!("/bin/c"),
+                    nonempty![(digest!(2), ArtifactType::Tar)]
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn multiple_files_parse_error_names_file() {
+        let (_temp_dir, paths, mut job_specs) = job_spec_iter_from_temp_files(&[
+            r#"{ "program": "/bin/a", "layers": [ { "tar": "1" } ] }"#,
+            r#"{ "program": "/bin/b", "layers": [ { "tar": "1" } ], "bad": 1 }"#,
+        ]);
+        assert_eq!(job_specs.len(), 2);
+        let err = job_specs.pop().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), format!("parsing {}", paths[1].display()));
+        job_specs.pop().unwrap().unwrap();
+    }
+
+    #[test]
+    fn multiple_files_missing_file_names_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("missing.json");
+        let err = job_spec_iter_from_files([path.clone()], None, layer_mapper, env, images, None)
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.to_string(), format!("opening {}", path.display()));
+    }
+
+    #[test]
+    fn relative_tar_paths_resolved_against_spec_file() {
+        // The spec file and the tar file live in a directory other than the current working
+        // directory, which is the package's directory when running tests.
+        let temp_dir = tempfile::tempdir().unwrap();
+        let spec_dir = temp_dir.path().join("specs");
+        std::fs::create_dir_all(spec_dir.join("fixtures")).unwrap();
+        std::fs::write(spec_dir.join("fixtures/x.tar"), "").unwrap();
+        let spec_path = spec_dir.join("spec.json");
+        std::fs::write(
+            &spec_path,
+            r#"
+                {
+                    "program": "/bin/a",
+                    "layers": [ { "tar": "fixtures/x.tar" }, { "tar": "/absolute.tar" } ]
+                }
+                {
+                    "program": "/bin/b",
+                    "image": { "name": "image1", "use": [ "layers" ] },
+                    "added_layers": [ { "tar": "fixtures/x.tar" } ]
+                }
+            "#,
+        )
+        .unwrap();
+        assert!(!std::path::Path::new("fixtures/x.tar").exists());
+
+        let tar_path = Utf8PathBuf::try_from(spec_dir.join("fixtures/x.tar")).unwrap();
+        let layer_mapper = |layer| {
+            assert_matches!(layer, Layer::Tar { path } => {
+                if path == tar_path {
+                    assert!(path.is_file());
+                    Ok((digest!(1), ArtifactType::Tar))
+                } else if path == "/absolute.tar" {
+                    Ok((digest!(2), ArtifactType::Tar))
+                } else {
+                    // Layers that come from an image are left alone.
+                    layer_mapper(Layer::Tar { path })
+                }
+            })
+        };
+        let job_specs = Vec::from_iter(
+            job_spec_iter_from_files([spec_path], None, layer_mapper, env, images, None)
+                .map(|job_spec| job_spec.unwrap().spec),
+        );
+        assert_eq!(
+            job_specs,
+            vec![
+                JobSpec::new(
+                    string!("/bin/a"),
+                    nonempty![
+                        (digest!(1), ArtifactType::Tar),
+                        (digest!(2), ArtifactType::Tar)
+                    ]
+                ),
+                JobSpec::new(
+                    string!("/bin/b"),
+                    nonempty![
+                        (digest!(42), ArtifactType::Tar),
+                        (digest!(43), ArtifactType::Tar),
+                        (digest!(1), ArtifactType::Tar)
+                    ]
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn toml_and_json_files_run_in_order() {
+        let (_temp_dir, _paths, job_specs) = job_spec_iter_from_named_temp_files(
+            &[
+                (
+                    "a.toml".into(),
+                    r#"
+                    # Comments are allowed.
+                    [[jobs]]
+                    program = "/bin/a"
+                    layers = [{ tar = "1" }]
+
+                    [[jobs]]
+                    name = "b"
+                    program = "/bin/b"
+                    arguments = ["x"]
+                    layers = [{ tar = "1" }]
+                    timeout = 10
+                "#,
+                ),
+                (
+                    "b.json".into(),
+                    r#"{ "depends_on": [ "b" ], "program": "/bin/c", "layers": [ { "tar": "2" } ] }"#,
+                ),
+            ],
+            None,
+        );
+        assert_eq!(
+            Vec::from_iter(job_specs.into_iter().map(Result::unwrap)),
+            vec![
+                NamedJobSpec {
+                    name: None,
+                    depends_on: vec![],
+                    allowed_exit_codes: vec![],
+                    spec: JobSpec::new(
+                        string!("/bin/a"),
+                        nonempty![(digest!(1), ArtifactType::Tar)]
+                    ),
+                },
+                NamedJobSpec {
+                    name: Some("b".into()),
+                    depends_on: vec![],
+                    allowed_exit_codes: vec![],
+                    spec: JobSpec::new(
+                        string!("/bin/b"),
+                        nonempty![(digest!(1), ArtifactType::Tar)]
+                    )
+                    .arguments(["x"])
+                    .timeout(Timeout::new(10)),
+                },
+                NamedJobSpec {
+                    name: None,
+                    depends_on: vec!["b".into()],
+                    allowed_exit_codes: vec![],
+                    spec: JobSpec::new(
+                        string!("/bin/c"),
+                        nonempty![(digest!(2), ArtifactType::Tar)]
+                    ),
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn toml_errors_name_file_and_job() {
+        let (_temp_dir, paths, job_specs) = job_spec_iter_from_named_temp_files(
+            &[
+                (
+                    "a.toml".into(),
+                    r#"
+                    [[jobs]]
+                    program = "/bin/a"
+                    layers = [{ tar = "1" }]
+
+                    [[jobs]]
+                    program = "/bin/b"
+                    added_layers = [{ tar = "1" }]
+                    image = { name = "image1", use = ["layers"] }
+                "#,
+                ),
+                ("b.toml".into(), "[[jobs]\n"),
+            ],
+            None,
+        );
+        let errors = Vec::from_iter(
+            job_specs
+                .into_iter()
+                .map(|job_spec| job_spec.map(|_| ()).map_err(|err| format!("{err:#}"))),
+        );
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0], Ok(()));
+        assert_eq!(
+            errors[1],
+            Err(format!(
+                "parsing {}: job #2: field `added_layers` set before `image` with a `use` of \
+                    `layers`",
+                paths[0].display()
+            )),
+        );
+        let err = errors[2].clone().unwrap_err();
+        assert!(
+            err.starts_with(&format!(
+                "parsing {}: TOML parse error at line 1",
+                paths[1].display()
+            )),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn format_overrides_file_extension() {
+        let (_temp_dir, _paths, job_specs) = job_spec_iter_from_named_temp_files(
+            &[(
+                "jobs.txt".into(),
+                "[[jobs]]\nprogram = \"/bin/a\"\nlayers = [{ tar = \"1\" }]\n",
+            )],
+            Some(JobSpecFormat::Toml),
+        );
+        assert_eq!(
+            Vec::from_iter(job_specs.into_iter().map(|job_spec| job_spec.unwrap().spec)),
+            vec![JobSpec::new(
+                string!("/bin/a"),
+                nonempty![(digest!(1), ArtifactType::Tar)]
+            )],
+        );
+    }
+
+    #[test]
+    fn job_spec_format_from_path_and_str() {
+        assert_eq!(
+            JobSpecFormat::from_path(Path::new("dir/jobs.toml")),
+            JobSpecFormat::Toml
+        );
+        assert_eq!(
+            JobSpecFormat::from_path(Path::new("dir.toml/jobs.json")),
+            JobSpecFormat::Json
+        );
+        assert_eq!(
+            JobSpecFormat::from_path(Path::new("jobs")),
+            JobSpecFormat::Json
+        );
+        assert_eq!(
+            "toml".parse::<JobSpecFormat>().unwrap(),
+            JobSpecFormat::Toml
+        );
+        assert_eq!(
+            "yaml".parse::<JobSpecFormat>().unwrap_err().to_string(),
+            r#"invalid job specification format "yaml""#,
+        );
+    }
+
+    fn no_artifacts(_: &mut dyn Read) -> Result<Sha256Digest> {
+        panic!("no layers should be read")
+    }
+
+    #[test]
+    fn relative_tar_paths_from_reader_left_alone() {
+        let job_specs = Vec::from_iter(
+            job_spec_iter_from_reader(
+                r#"{ "program": "/bin/a", "layers": [ { "tar": "1" } ] }"#.as_bytes(),
+                no_artifacts,
+                layer_mapper,
+                env,
+                images,
+                None,
+            )
+            .map(|job_spec| job_spec.unwrap().spec),
+        );
+        assert_eq!(
+            job_specs,
+            vec![JobSpec::new(
+                string!("/bin/a"),
+                nonempty![(digest!(1), ArtifactType::Tar)]
+            )],
+        );
+    }
+
+    #[test]
+    fn layers_read_from_stream() {
+        let mut added = vec![];
+        let artifact_adder = |reader: &mut dyn Read| {
+            let mut contents = vec![];
+            reader.read_to_end(&mut contents)?;
+            added.push(contents);
+            Ok(digest!(added.len() as u64 + 100))
+        };
+        let stream = concat!(
+            "@layer fixture 5\n",
+            "hello",
+            r#"{ "program": "/bin/a", "layers": [ { "stdin": "fixture" } ] }"#,
+            "\n@layer other fixture 3\n",
+            "bye\n",
+            r#"
+                {
+                    "program": "/bin/b",
+                    "layers": [
+                        { "tar": "1" },
+                        { "stdin": "fixture" },
+                        { "stdin": "other fixture" }
+                    ]
+                }
+            "#,
+        );
+        let job_specs = Vec::from_iter(
+            job_spec_iter_from_reader(
+                stream.as_bytes(),
+                artifact_adder,
+                layer_mapper,
+                env,
+                images,
+                None,
+            )
+            .map(|job_spec| job_spec.unwrap().spec),
+        );
+        assert_eq!(
+            job_specs,
+            vec![
+                JobSpec::new(
+                    string!("/bin/a"),
+                    nonempty![(digest!(101), ArtifactType::Tar)]
+                ),
+                JobSpec::new(
+                    string!("/bin/b"),
+                    nonempty![
+                        (digest!(1), ArtifactType::Tar),
+                        (digest!(101), ArtifactType::Tar),
+                        (digest!(102), ArtifactType::Tar)
+                    ]
+                ),
+            ],
+        );
+        assert_eq!(added, vec![b"hello".to_vec(), b"bye".to_vec()]);
+    }
+
+    #[test]
+    fn layer_read_from_stream_must_come_first() {
+        let mut job_specs = job_spec_iter_from_reader(
+            r#"{ "program": "/bin/a", "layers": [ { "stdin": "fixture" } ] }"#.as_bytes(),
+            no_artifacts,
+            layer_mapper,
+            env,
+            images,
+            None,
+        );
+        assert_anyhow_error(
+            job_specs.next().unwrap().unwrap_err(),
+            r#"no layer named "fixture" has been read"#,
+        );
+        assert!(job_specs.next().is_none());
+    }
+
+    #[test]
+    fn layer_read_from_stream_cut_short() {
+        let mut job_specs = job_spec_iter_from_reader(
+            "@layer fixture 10\nhello".as_bytes(),
+            |reader: &mut dyn Read| {
+                io::copy(reader, &mut io::sink())?;
+                Ok(digest!(1))
+            },
+            layer_mapper,
+            env,
+            images,
+            None,
+        );
+        assert_anyhow_error(
+            job_specs.next().unwrap().unwrap_err(),
+            r#"layer "fixture" ended after 5 of 10 bytes"#,
+        );
+    }
+
+    #[test]
+    fn syntax_error_from_stream_says_which_job() {
+        let stream = concat!(
+            r#"{ "program": "/bin/a", "layers": [ { "tar": "1" } ] }"#,
+            "\n@layer fixture 5\nhello",
+            r#"{ "program": "/bin/b", "layers": [ { "stdin": "fixture" } ] }"#,
+            "\n",
+            r#"{ "program": "/bin/c", "layers": [ { "tar": "1" } }"#,
+        );
+        let mut job_specs = job_spec_iter_from_reader(
+            stream.as_bytes(),
+            |reader: &mut dyn Read| {
+                io::copy(reader, &mut io::sink())?;
+                Ok(digest!(1))
+            },
+            layer_mapper,
+            env,
+            images,
+            None,
+        );
+        job_specs.next().unwrap().unwrap();
+        job_specs.next().unwrap().unwrap();
+        assert_eq!(
+            format!("{:#}", job_specs.next().unwrap().unwrap_err()),
+            "job #3 at byte 138: expected `,` or `]` at line 1 column 51",
+        );
+        assert!(job_specs.next().is_none());
+    }
+
+    #[test]
+    fn layer_read_from_stream_in_file() {
+        let (_temp_dir, _paths, mut job_specs) = job_spec_iter_from_temp_files(&[
+            r#"{ "program": "/bin/a", "layers": [ { "stdin": "fixture" } ] }"#,
+        ]);
+        assert_anyhow_error(
+            job_specs.pop().unwrap().unwrap_err(),
+            r#"layer "fixture" can only be used by jobs read from standard input"#,
+        );
+    }
+
+    fn default_image(use_: EnumSet<ImageUse>) -> Image {
+        Image {
+            name: string!("image1"),
+            use_,
+        }
+    }
+
+    #[test]
+    fn default_image_used_without_image() {
+        assert_eq!(
+            parse_job(
+                r#"{
+                    "program": "/bin/sh",
+                    "added_layers": [ { "tar": "1" } ],
+                    "added_environment": { "BAR": "bar" }
+                }"#,
+            )
+            .unwrap()
+            .into_job_spec(
+                layer_mapper,
+                env,
+                images,
+                Some(&default_image(ImageUse::Layers | ImageUse::Environment)),
+            )
+            .unwrap(),
+            JobSpec::new(
+                string!("/bin/sh"),
+                nonempty![
+                    (digest!(42), ArtifactType::Tar),
+                    (digest!(43), ArtifactType::Tar),
+                    (digest!(1), ArtifactType::Tar)
+                ]
+            )
+            .environment(["BAR=bar", "BAZ=image-baz", "FOO=image-foo"]),
+        );
+    }
+
+    #[test]
+    fn default_image_not_used_with_image() {
+        assert_eq!(
+            parse_job(
+                r#"{
+                    "program": "/bin/sh",
+                    "layers": [ { "tar": "1" } ],
+                    "image": { "name": "image1", "use": [ "working_directory" ] }
+                }"#,
+            )
+            .unwrap()
+            .into_job_spec(
+                layer_mapper,
+                env,
+                images,
+                Some(&Image {
+                    name: string!("bogus"),
+                    use_: ImageUse::Layers | ImageUse::Environment,
+                }),
+            )
+            .unwrap(),
+            JobSpec::new(
+                string!("/bin/sh"),
+                nonempty![(digest!(1), ArtifactType::Tar)]
+            )
+            .working_directory("/foo"),
+        );
+    }
+
+    #[test]
+    fn default_image_with_layers_and_layers() {
+        assert_anyhow_error(
+            parse_job(
+                r#"{
+                    "program": "/bin/sh",
+                    "layers": [ { "tar": "1" } ]
+                }"#,
+            )
+            .unwrap()
+            .into_job_spec(
+                layer_mapper,
+                env,
+                images,
+                Some(&default_image(ImageUse::Layers.into())),
+            )
+            .unwrap_err(),
+            "field `layers` cannot be set if the default image has a `use` of `layers` (try \
+                `added_layers` instead)",
+        );
+    }
+
+    #[test]
+    fn default_image_without_environment_and_added_environment() {
+        assert_anyhow_error(
+            parse_job(
+                r#"{
+                    "program": "/bin/sh",
+                    "added_environment": { "BAR": "bar" }
+                }"#,
+            )
+            .unwrap()
+            .into_job_spec(
+                layer_mapper,
+                env,
+                images,
+                Some(&default_image(ImageUse::Layers.into())),
+            )
+            .unwrap_err(),
+            "field `added_environment` cannot be set without an image with a `use` of \
+                `environment`",
+        );
+    }
+
+    #[test]
+    fn image_uses_from_str() {
+        assert_eq!(
+            "layers, working_directory,".parse::<ImageUses>().unwrap(),
+            ImageUses(ImageUse::Layers | ImageUse::WorkingDirectory),
+        );
+        assert_eq!(
+            "layers,bogus".parse::<ImageUses>().unwrap_err().to_string(),
+            r#"invalid image use "bogus""#,
+        );
+        assert_eq!(ImageUses::default().to_string(), "layers,environment");
+    }
+
+    fn validation_problems(problems: Vec<Error>) -> Vec<String> {
+        Vec::from_iter(problems.iter().map(|problem| format!("{problem:#}")))
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let stream = concat!(
+            r#"{ "name": "a", "program": "/bin/a", "layers": [ { "tar": "1" } ] }"#,
+            r#"{ "program": "/bin/b", "layers": [ { "tar": "1" } ], "bogus": 1 }"#,
+            r#"{
+                "program": "/bin/c",
+                "image": { "name": "image1", "use": [ "layers" ] },
+                "layers": [ { "tar": "1" } ]
+            }"#,
+            r#"{
+                "program": "/bin/d",
+                "layers": [ { "symlinks": [ { "link": "/a", "target": "/b", "extra": 1 } ] } ]
+            }"#,
+            r#"{ "depends_on": [ "missing" ], "program": "/bin/e", "layers": [ { "tar": "1" } ] }"#,
+            "@layer fixture 5\nhello",
+            r#"{ "program": "/bin/f", "layers": [ { "stdin": "fixture" }, { "stdin": "other" } ] }"#,
+            r#"{
+                "program": "/bin/g",
+                "layers": [ { "tar": "1" } ],
+                "working_directory": "tmp"
+            }"#,
+            r#"{ "program": "/bin/h", "layers": [ { "tar": "1" } ] }"#,
+        );
+        assert_eq!(
+            validation_problems(validate_job_specs_from_reader(
+                stream.as_bytes(),
+                env,
+                images,
+                None
+            )),
+            vec![
+                "job 2 in standard input: unknown field `bogus`, expected one of `name`, \
+                    `depends_on`, `program`, `arguments`, `environment`, `added_environment`, \
+                    `layers`, `added_layers`, `devices`, `mounts`, `scratch_dir`, \
+                    `enable_loopback`, `enable_writable_file_system`, `working_directory`, `user`, \
+                    `group`, `image`, `timeout`, `enable_init_process`, `cpus`, `memory`, `priority`, \
+                    `allowed_exit_codes` at line 1 \
+                    column 60",
+                "job 3 in standard input: field `layers` cannot be set if `image` with a `use` of \
+                    `layers` is also set (try `added_layers` instead) at line 4 column 24",
+                "job 4 in standard input: data did not match any variant of untagged enum JobLayer \
+                    at line 3 column 94",
+                "job 6 in standard input: no layer named \"other\" has been read",
+                "job 7 in standard input: invalid job for /bin/g: working directory \"tmp\" \
+                    must be an absolute path",
+                "job #5 depends on unknown job `missing`",
+            ],
+        );
+    }
+
+    #[test]
+    fn validate_keeps_field_order() {
+        let stream = concat!(
+            r#"{
+                "program": "/bin/a",
+                "image": { "name": "image1", "use": [ "layers" ] },
+                "added_layers": [ { "tar": "1" } ]
+            }"#,
+            r#"{
+                "program": "/bin/b",
+                "added_layers": [ { "tar": "1" } ],
+                "image": { "name": "image1", "use": [ "layers" ] }
+            }"#,
+        );
+        assert_eq!(
+            validation_problems(validate_job_specs_from_reader(
+                stream.as_bytes(),
+                env,
+                images,
+                None
+            )),
+            vec![
+                "job 2 in standard input: field `added_layers` set before `image` with a `use` of \
+                    `layers` at line 4 column 23",
+            ],
+        );
+    }
+
+    #[test]
+    fn validate_files_after_syntax_error() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let paths = Vec::from_iter(
+            [
+                concat!(
+                    r#"{ "program": "/bin/a", "layers": [] }"#,
+                    r#"{ "program": "/bin/b", "#,
+                    r#"{ "program": "/bin/c", "layers": [] }"#,
+                ),
+                r#"{ "program": "/bin/d", "layers": [ { "stdin": "fixture" } ] }"#,
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(i, contents)| {
+                let path = temp_dir.path().join(format!("spec{i}.json"));
+                std::fs::write(&path, contents).unwrap();
+                path
+            }),
+        );
+        let spec0 = paths[0].display();
+        let spec1 = paths[1].display();
+        assert_eq!(
+            validation_problems(validate_job_specs_from_files(
+                paths.clone(),
+                None,
+                env,
+                images,
+                None
+            )),
+            vec![
+                format!("job 1 in {spec0}: field `layers` cannot be empty at line 1 column 37"),
+                format!("parsing {spec0}: key must be a string at line 1 column 61"),
+                format!(
+                    "job 1 in {spec1}: layer \"fixture\" can only be used by jobs read from \
+                        standard input"
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn validate_toml_reports_every_problem() {
+        let document = r#"
+            [[jobs]]
+            name = "a"
+            program = "/bin/a"
+            layers = [{ tar = "1" }]
+
+            [[jobs]]
+            program = "/bin/b"
+            layers = [{ tar = "1" }]
+            bogus = 1
+
+            [[jobs]]
+            program = "/bin/c"
+            image = { name = "image1", use = ["layers"] }
+            added_layers = [{ tar = "1" }]
+
+            [[jobs]]
+            program = "/bin/d"
+            added_layers = [{ tar = "1" }]
+            image = { name = "image1", use = ["layers"] }
+
+            [[jobs]]
+            depends_on = ["missing"]
+            program = "/bin/e"
+            layers = [{ tar = "1" }]
+        "#;
+        assert_eq!(
+            validation_problems(validate_job_specs_from_toml_reader(
+                document.as_bytes(),
+                env,
+                images,
+                None
+            )),
+            vec![
+                "job 2 in standard input: unknown field `bogus`, expected one of `name`, \
+                    `depends_on`, `program`, `arguments`, `environment`, `added_environment`, \
+                    `layers`, `added_layers`, `devices`, `mounts`, `scratch_dir`, \
+                    `enable_loopback`, `enable_writable_file_system`, `working_directory`, `user`, \
+                    `group`, `image`, `timeout`, `enable_init_process`, `cpus`, `memory`, `priority`, \
+                    `allowed_exit_codes`",
+                "job 4 in standard input: field `added_layers` set before `image` with a `use` of \
+                    `layers`",
+                "job #5 depends on unknown job `missing`",
+            ],
+        );
+    }
 }
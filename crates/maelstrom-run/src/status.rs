@@ -0,0 +1,58 @@
+//! Styling of the lines printed to stderr about how jobs ended.
+
+use console::{Style, StyledObject};
+
+/// Styles the status and error lines printed about jobs. Whether they are colored is decided once,
+/// up front, instead of by looking at the terminal each time.
+pub struct StatusStyle {
+    failure: Style,
+    warning: Style,
+}
+
+impl StatusStyle {
+    pub fn new(color: bool) -> Self {
+        Self {
+            failure: Style::new().red().force_styling(color),
+            warning: Style::new().yellow().force_styling(color),
+        }
+    }
+
+    /// Style a message about a job that ran but didn't succeed.
+    pub fn failure<D>(&self, msg: D) -> StyledObject<D> {
+        self.failure.apply_to(msg)
+    }
+
+    /// Style a message about a job that couldn't be run, or whose output was cut short.
+    pub fn warning<D>(&self, msg: D) -> StyledObject<D> {
+        self.warning.apply_to(msg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maelstrom_util::config::common::ColorMode;
+
+    #[test]
+    fn colored_only_when_forced_or_on_a_tty() {
+        for (mode, is_terminal, colored) in [
+            (ColorMode::Auto, true, true),
+            (ColorMode::Auto, false, false),
+            (ColorMode::Always, false, true),
+            (ColorMode::Always, true, true),
+            (ColorMode::Never, true, false),
+            (ColorMode::Never, false, false),
+        ] {
+            let style = StatusStyle::new(mode.enabled(is_terminal));
+            let failure = style.failure("exited with code 1").to_string();
+            let warning = style.warning("timed out").to_string();
+            if colored {
+                assert_eq!(failure, "\x1b[31mexited with code 1\x1b[0m");
+                assert_eq!(warning, "\x1b[33mtimed out\x1b[0m");
+            } else {
+                assert_eq!(failure, "exited with code 1");
+                assert_eq!(warning, "timed out");
+            }
+        }
+    }
+}
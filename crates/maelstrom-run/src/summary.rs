@@ -0,0 +1,216 @@
+//! Tallying of job outcomes, for the summary printed once all jobs are done.
+
+use maelstrom_base::{JobCompleted, JobError, JobOutcome, JobOutcomeResult, JobStatus};
+use maelstrom_util::process::{ExitCode, ExitCodeAccumulator};
+use std::{fmt, sync::Mutex};
+
+/// How many jobs ended in each way.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct JobCounts {
+    /// Jobs that exited with a code of 0.
+    pub succeeded: u64,
+    /// Jobs that exited with a non-zero code, or were killed by a signal.
+    pub failed: u64,
+    /// Jobs that ran past their timeout.
+    pub timed_out: u64,
+    /// Jobs that couldn't be run because of an execution or system error.
+    pub errored: u64,
+    /// Jobs that weren't run because one of their dependencies didn't succeed.
+    pub skipped: u64,
+}
+
+impl JobCounts {
+    pub fn total(&self) -> u64 {
+        self.succeeded + self.failed + self.timed_out + self.errored + self.skipped
+    }
+}
+
+impl fmt::Display for JobCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} jobs: {} succeeded, {} failed, {} timed out, {} errored, {} skipped",
+            self.total(),
+            self.succeeded,
+            self.failed,
+            self.timed_out,
+            self.errored,
+            self.skipped,
+        )
+    }
+}
+
+/// Collects the outcomes of jobs as they come in. This is shared between the job callbacks, so it
+/// can be used from multiple threads.
+#[derive(Default)]
+pub struct JobSummary {
+    counts: Mutex<JobCounts>,
+    exit_code: ExitCodeAccumulator,
+}
+
+impl JobSummary {
+    /// Record the outcome of a job, returning whether it succeeded. A job that exited with one of
+    /// `allowed_exit_codes` succeeded, just as if it had exited with a code of 0.
+    ///
+    /// The exit code is that of the first job that didn't succeed: the job's own exit code if it
+    /// exited with a non-zero code, or a generic failure otherwise.
+    pub fn add_outcome(&self, result: &JobOutcomeResult, allowed_exit_codes: &[u8]) -> bool {
+        let mut counts = self.counts.lock().unwrap();
+        match result {
+            Ok(JobOutcome::Completed(JobCompleted { status, .. })) => match status {
+                JobStatus::Exited(code) if *code == 0 || allowed_exit_codes.contains(code) => {
+                    counts.succeeded += 1;
+                    return true;
+                }
+                JobStatus::Exited(code) => {
+                    counts.failed += 1;
+                    self.exit_code.add(ExitCode::from(*code));
+                }
+                JobStatus::Signaled(_) => {
+                    counts.failed += 1;
+                    self.exit_code.add(ExitCode::FAILURE);
+                }
+            },
+            Ok(JobOutcome::TimedOut(_)) => {
+                counts.timed_out += 1;
+                self.exit_code.add(ExitCode::FAILURE);
+            }
+            Err(JobError::Execution(_) | JobError::System(_)) => {
+                counts.errored += 1;
+                self.exit_code.add(ExitCode::FAILURE);
+            }
+        }
+        false
+    }
+
+    /// Record that we couldn't get a job's result because of an error talking to the client.
+    pub fn add_errored(&self) {
+        self.counts.lock().unwrap().errored += 1;
+        self.exit_code.add(ExitCode::FAILURE);
+    }
+
+    /// Record that a job was skipped because one of its dependencies didn't succeed.
+    pub fn add_skipped(&self) {
+        self.counts.lock().unwrap().skipped += 1;
+        self.exit_code.add(ExitCode::FAILURE);
+    }
+
+    pub fn counts(&self) -> JobCounts {
+        *self.counts.lock().unwrap()
+    }
+
+    pub fn exit_code(&self) -> ExitCode {
+        self.exit_code.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maelstrom_base::{JobEffects, JobOutputResult};
+    use std::time::Duration;
+
+    fn effects() -> JobEffects {
+        JobEffects {
+            stdout: JobOutputResult::None,
+            stderr: JobOutputResult::None,
+            duration: Duration::from_secs(1),
+            resource_usage: Default::default(),
+        }
+    }
+
+    fn completed(status: JobStatus) -> JobOutcomeResult {
+        Ok(JobOutcome::Completed(JobCompleted {
+            status,
+            effects: effects(),
+        }))
+    }
+
+    #[test]
+    fn no_jobs() {
+        let summary = JobSummary::default();
+        assert_eq!(summary.counts(), JobCounts::default());
+        assert_eq!(summary.exit_code(), ExitCode::SUCCESS);
+        assert_eq!(
+            summary.counts().to_string(),
+            "0 jobs: 0 succeeded, 0 failed, 0 timed out, 0 errored, 0 skipped"
+        );
+    }
+
+    #[test]
+    fn all_succeeded() {
+        let summary = JobSummary::default();
+        assert!(summary.add_outcome(&completed(JobStatus::Exited(0)), &[]));
+        assert!(summary.add_outcome(&completed(JobStatus::Exited(0)), &[]));
+        assert_eq!(
+            summary.counts(),
+            JobCounts {
+                succeeded: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(summary.exit_code(), ExitCode::SUCCESS);
+    }
+
+    #[test]
+    fn mixed_outcomes() {
+        let summary = JobSummary::default();
+        assert!(summary.add_outcome(&completed(JobStatus::Exited(0)), &[]));
+        assert!(!summary.add_outcome(&Err(JobError::System(String::from("oops"))), &[]));
+        assert!(!summary.add_outcome(&completed(JobStatus::Exited(3)), &[]));
+        assert!(!summary.add_outcome(&completed(JobStatus::Signaled(9)), &[]));
+        assert!(!summary.add_outcome(&Ok(JobOutcome::TimedOut(effects())), &[]));
+        assert!(!summary.add_outcome(&Err(JobError::Execution(String::from("no such file"))), &[]));
+        assert!(summary.add_outcome(&completed(JobStatus::Exited(0)), &[]));
+        summary.add_skipped();
+        assert_eq!(
+            summary.counts(),
+            JobCounts {
+                succeeded: 2,
+                failed: 2,
+                timed_out: 1,
+                errored: 2,
+                skipped: 1,
+            }
+        );
+        assert_eq!(
+            summary.counts().to_string(),
+            "8 jobs: 2 succeeded, 2 failed, 1 timed out, 2 errored, 1 skipped"
+        );
+        // The system error came first, so it determines the exit code.
+        assert_eq!(summary.exit_code(), ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn exit_code_from_first_failed_job() {
+        let summary = JobSummary::default();
+        assert!(summary.add_outcome(&completed(JobStatus::Exited(0)), &[]));
+        assert!(!summary.add_outcome(&completed(JobStatus::Exited(3)), &[]));
+        assert!(!summary.add_outcome(&Err(JobError::System(String::from("oops"))), &[]));
+        assert_eq!(summary.exit_code(), ExitCode::from(3));
+    }
+
+    #[test]
+    fn allowed_exit_code_succeeds() {
+        let summary = JobSummary::default();
+        assert!(summary.add_outcome(&completed(JobStatus::Exited(2)), &[2]));
+        assert!(!summary.add_outcome(&completed(JobStatus::Signaled(2)), &[2]));
+        assert!(!summary.add_outcome(&completed(JobStatus::Exited(3)), &[2]));
+        assert_eq!(
+            summary.counts(),
+            JobCounts {
+                succeeded: 1,
+                failed: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn only_allowed_exit_codes_exits_successfully() {
+        let summary = JobSummary::default();
+        assert!(summary.add_outcome(&completed(JobStatus::Exited(2)), &[2]));
+        assert!(summary.add_outcome(&completed(JobStatus::Exited(0)), &[2]));
+        assert_eq!(summary.exit_code(), ExitCode::SUCCESS);
+    }
+}
@@ -78,6 +78,7 @@ macro_rules! outcome {
                     stdout: maelstrom_base::JobOutputResult::None,
                     stderr: maelstrom_base::JobOutputResult::None,
                     duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
                 }
             }
         ))
@@ -90,6 +91,7 @@ macro_rules! outcome {
                     stdout: maelstrom_base::JobOutputResult::None,
                     stderr: maelstrom_base::JobOutputResult::None,
                     duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
                 }
             }
         ))
@@ -102,6 +104,7 @@ macro_rules! outcome {
                     stdout: maelstrom_base::JobOutputResult::None,
                     stderr: maelstrom_base::JobOutputResult::None,
                     duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
                 }
             }
         ))
@@ -114,6 +117,7 @@ macro_rules! outcome {
                     stdout: maelstrom_base::JobOutputResult::None,
                     stderr: maelstrom_base::JobOutputResult::None,
                     duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
                 }
             }
         ))
@@ -249,6 +253,8 @@ macro_rules! glob_layer {
                 prepend_prefix: $prepend_prefix,
                 canonicalize: $canonicalize,
                 follow_symlinks: $follow_symlinks,
+                respect_gitignore: false,
+                preserve_mtimes: false,
             },
         }
     };
@@ -315,6 +321,8 @@ macro_rules! paths_layer {
                 prepend_prefix: $prepend_prefix,
                 canonicalize: $canonicalize,
                 follow_symlinks: $follow_symlinks,
+                respect_gitignore: false,
+                preserve_mtimes: false,
             },
         }
     };
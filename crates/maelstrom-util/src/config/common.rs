@@ -36,10 +36,43 @@ impl BrokerAddr {
     }
 }
 
+impl BrokerAddr {
+    /// The URL scheme for a plain TCP connection to the broker. An address without a scheme uses
+    /// this one.
+    const SCHEME: &'static str = "maelstrom";
+
+    /// The URL scheme reserved for TLS connections to the broker, which aren't supported yet.
+    const TLS_SCHEME: &'static str = "maelstroms";
+}
+
 impl FromStr for BrokerAddr {
     type Err = io::Error;
 
+    /// Parse a `host:port` socket address, optionally preceded by a scheme, like
+    /// `maelstrom://host:port`. The host may be a name, which is resolved.
     fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = match value.split_once("://") {
+            None => value,
+            Some((Self::SCHEME, rest)) => rest,
+            Some((Self::TLS_SCHEME, _)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "TLS connections to the broker (`{}://`) aren't supported yet",
+                        Self::TLS_SCHEME
+                    ),
+                ));
+            }
+            Some((scheme, _)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "unknown broker address scheme `{scheme}`, expected `{}`",
+                        Self::SCHEME
+                    ),
+                ));
+            }
+        };
         let addrs: Vec<SocketAddr> = value.to_socket_addrs()?.collect();
         // It's not clear how we could end up with an empty iterator. We'll assume that's
         // impossible until proven wrong.
@@ -219,6 +252,32 @@ impl LogLevel {
     }
 }
 
+/// Whether output written to a terminal should be colored.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, EnumString, Eq, PartialEq, Serialize, ValueEnum,
+)]
+#[clap(rename_all = "kebab_case")]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum ColorMode {
+    /// Color output only if it's going to a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    /// Return whether output should be colored, given whether it's going to a terminal.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorMode::Auto => is_terminal,
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Deserialize, Eq, PartialEq)]
 #[serde(transparent)]
 pub struct InlineLimit(#[serde(with = "bytesize_serde")] ByteSize);
@@ -351,6 +410,178 @@ impl Display for SlotsFromStrError {
 
 impl error::Error for SlotsFromStrError {}
 
+/// The number of artifacts the client will hash at once.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(try_from = "u16")]
+pub struct HashingThreads(u16);
+
+impl HashingThreads {
+    pub fn inner(&self) -> &u16 {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> u16 {
+        self.0
+    }
+}
+
+impl TryFrom<u16> for HashingThreads {
+    type Error = String;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        if value < 1 {
+            Err("value must be at least 1".to_string())
+        } else if value > 1000 {
+            Err("value must be less than 1000".to_string())
+        } else {
+            Ok(HashingThreads(value))
+        }
+    }
+}
+
+impl TryFrom<usize> for HashingThreads {
+    type Error = String;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        if value < 1 {
+            Err("value must be at least 1".to_string())
+        } else if value > 1000 {
+            Err("value must be less than 1000".to_string())
+        } else {
+            Ok(HashingThreads(value.try_into().unwrap()))
+        }
+    }
+}
+
+impl Debug for HashingThreads {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl Default for HashingThreads {
+    fn default() -> Self {
+        Self::try_from(num_cpus::get()).unwrap()
+    }
+}
+
+impl Display for HashingThreads {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for HashingThreads {
+    type Err = HashingThreadsFromStrError;
+    fn from_str(threads: &str) -> result::Result<Self, Self::Err> {
+        let threads = u16::from_str(threads).map_err(HashingThreadsFromStrError::Parse)?;
+        Self::try_from(threads).map_err(HashingThreadsFromStrError::Bounds)
+    }
+}
+
+#[derive(Debug)]
+pub enum HashingThreadsFromStrError {
+    Parse(ParseIntError),
+    Bounds(String),
+}
+
+impl Display for HashingThreadsFromStrError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(inner) => Display::fmt(inner, f),
+            Self::Bounds(inner) => write!(f, "{inner}"),
+        }
+    }
+}
+
+impl error::Error for HashingThreadsFromStrError {}
+
+/// The number of worker threads in the client's dispatcher runtime.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(try_from = "u16")]
+pub struct DispatcherThreads(u16);
+
+impl DispatcherThreads {
+    pub fn inner(&self) -> &u16 {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> u16 {
+        self.0
+    }
+}
+
+impl TryFrom<u16> for DispatcherThreads {
+    type Error = String;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        if value < 1 {
+            Err("value must be at least 1".to_string())
+        } else if value > 1000 {
+            Err("value must be less than 1000".to_string())
+        } else {
+            Ok(DispatcherThreads(value))
+        }
+    }
+}
+
+impl TryFrom<usize> for DispatcherThreads {
+    type Error = String;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        if value < 1 {
+            Err("value must be at least 1".to_string())
+        } else if value > 1000 {
+            Err("value must be less than 1000".to_string())
+        } else {
+            Ok(DispatcherThreads(value.try_into().unwrap()))
+        }
+    }
+}
+
+impl Debug for DispatcherThreads {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl Default for DispatcherThreads {
+    fn default() -> Self {
+        Self::try_from(num_cpus::get()).unwrap()
+    }
+}
+
+impl Display for DispatcherThreads {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for DispatcherThreads {
+    type Err = DispatcherThreadsFromStrError;
+    fn from_str(threads: &str) -> result::Result<Self, Self::Err> {
+        let threads = u16::from_str(threads).map_err(DispatcherThreadsFromStrError::Parse)?;
+        Self::try_from(threads).map_err(DispatcherThreadsFromStrError::Bounds)
+    }
+}
+
+#[derive(Debug)]
+pub enum DispatcherThreadsFromStrError {
+    Parse(ParseIntError),
+    Bounds(String),
+}
+
+impl Display for DispatcherThreadsFromStrError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(inner) => Display::fmt(inner, f),
+            Self::Bounds(inner) => write!(f, "{inner}"),
+        }
+    }
+}
+
+impl error::Error for DispatcherThreadsFromStrError {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -380,6 +611,55 @@ mod tests {
         );
     }
 
+    #[test]
+    fn broker_addr_from_str_with_scheme() {
+        assert_eq!(
+            BrokerAddr::from_str("maelstrom://127.0.0.1:1234").unwrap(),
+            BrokerAddr::new(LOCALHOST4),
+        );
+        assert_eq!(
+            BrokerAddr::from_str("maelstrom://[::1]:1234").unwrap(),
+            BrokerAddr::new(LOCALHOST6),
+        );
+    }
+
+    #[test]
+    fn broker_addr_from_str_with_tls_scheme() {
+        let err = BrokerAddr::from_str("maelstroms://127.0.0.1:1234").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(
+            err.to_string(),
+            "TLS connections to the broker (`maelstroms://`) aren't supported yet"
+        );
+    }
+
+    #[test]
+    fn broker_addr_from_str_with_unknown_scheme() {
+        let err = BrokerAddr::from_str("http://127.0.0.1:1234").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "unknown broker address scheme `http`, expected `maelstrom`"
+        );
+    }
+
+    #[test]
+    fn broker_addr_deserialize_from_string_with_scheme() {
+        assert_de_tokens(
+            &BrokerAddr::new(LOCALHOST4).readable(),
+            &[Token::String("maelstrom://127.0.0.1:1234")],
+        );
+    }
+
+    #[test]
+    fn color_mode_enabled() {
+        assert!(ColorMode::Auto.enabled(true));
+        assert!(!ColorMode::Auto.enabled(false));
+        assert!(ColorMode::Always.enabled(false));
+        assert!(!ColorMode::Never.enabled(true));
+        assert_eq!("always".parse::<ColorMode>().unwrap(), ColorMode::Always);
+    }
+
     #[test]
     fn broker_addr_into_string() {
         assert_eq!(String::from(BrokerAddr::new(LOCALHOST4)), "127.0.0.1:1234");
@@ -296,6 +296,14 @@ impl Fs {
         std::fs::write(path, contents).with_context(|| format!("write(\"{}\")", path.display()))
     }
 
+    pub fn read_if_exists<P: AsRef<Path>>(&self, path: P) -> Result<Option<Vec<u8>>> {
+        match fs_trampoline!(std::fs::read, path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) if is_not_found_err(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
     pub fn read_to_string<P: AsRef<Path>>(&self, path: P) -> Result<String> {
         fs_trampoline!(std::fs::read_to_string, path)
     }
@@ -197,13 +197,18 @@ pub struct ManifestBuilder<'cb, WriteT> {
     fs: Fs,
     writer: AsyncManifestWriter<WriteT>,
     follow_symlinks: bool,
+    mtime: Option<UnixTimestamp>,
     data_upload: Box<dyn DataUpload + 'cb>,
 }
 
 impl<'cb, WriteT: AsyncWrite + Unpin> ManifestBuilder<'cb, WriteT> {
+    /// If `mtime` is given, every entry is given it as its modification time, instead of the
+    /// modification time of the file it was made from. This makes the manifest only depend on the
+    /// contents of the files.
     pub async fn new(
         writer: WriteT,
         follow_symlinks: bool,
+        mtime: Option<UnixTimestamp>,
         data_upload: impl DataUpload + 'cb,
     ) -> io::Result<Self> {
         Ok(Self {
@@ -211,6 +216,7 @@ impl<'cb, WriteT: AsyncWrite + Unpin> ManifestBuilder<'cb, WriteT> {
             writer: AsyncManifestWriter::new(writer).await?,
             data_upload: Box::new(data_upload),
             follow_symlinks,
+            mtime,
         })
     }
 
@@ -220,9 +226,13 @@ impl<'cb, WriteT: AsyncWrite + Unpin> ManifestBuilder<'cb, WriteT> {
         path: impl AsRef<Path>,
         data: ManifestEntryData,
     ) -> Result<()> {
+        let mut metadata = convert_metadata(meta);
+        if let Some(mtime) = self.mtime {
+            metadata.mtime = mtime;
+        }
         let entry = ManifestEntry {
             path: to_utf8_path(path),
-            metadata: convert_metadata(meta),
+            metadata,
             data,
         };
         self.writer.write_entry(&entry).await?;
@@ -311,7 +321,7 @@ mod tests {
         ) -> Pin<Box<dyn Future<Output = ()> + 'a>>,
     {
         let mut buffer = vec![];
-        let mut builder = ManifestBuilder::new(&mut buffer, follow_symlinks, TestDataUpload)
+        let mut builder = ManifestBuilder::new(&mut buffer, follow_symlinks, None, TestDataUpload)
             .await
             .unwrap();
 
@@ -33,6 +33,18 @@ impl From<u8> for ExitCode {
     }
 }
 
+/// The numeric value the process would exit with. [`ExitCode::FAILURE`] is 1, like it is for
+/// [`std::process::ExitCode::FAILURE`] on Unix.
+impl From<ExitCode> for u8 {
+    fn from(val: ExitCode) -> Self {
+        match val.0 {
+            ExitCodeInner::Success => 0,
+            ExitCodeInner::Failure => 1,
+            ExitCodeInner::U8(val) => val.get(),
+        }
+    }
+}
+
 impl Termination for ExitCode {
     fn report(self) -> process::ExitCode {
         self.into()
@@ -107,6 +119,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_exit_code_to_u8() {
+        assert_eq!(u8::from(ExitCode::SUCCESS), 0);
+        assert_eq!(u8::from(ExitCode::FAILURE), 1);
+        for i in 0u8..255u8 {
+            assert_eq!(u8::from(ExitCode::from(i)), i);
+        }
+    }
+
     #[test]
     fn test_exit_code_properties() {
         is_clone::<ExitCode>();
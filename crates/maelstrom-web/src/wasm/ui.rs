@@ -224,6 +224,8 @@ impl<RpcConnectionT: ClientConnection> UiHandler<RpcConnectionT> {
             if let Some(msg) = rpc.try_recv()? {
                 match msg {
                     BrokerToClient::StatisticsResponse(stats) => self.stats = Some(stats),
+                    // Our request will be answered once the broker stops throttling us.
+                    BrokerToClient::TooManyRequests => {}
                     r => bail!("unexpected response: {r:?}"),
                 }
             }
@@ -8,9 +8,9 @@
 
 use core::{ffi::CStr, fmt::Write as _, result};
 use maelstrom_linux::{
-    self as linux, CloseRangeFirst, CloseRangeFlags, CloseRangeLast, Errno, Fd, FileMode,
-    MountFlags, NetlinkSocketAddr, OpenFlags, SocketDomain, SocketProtocol, SocketType,
-    UmountFlags,
+    self as linux, CloneArgs, CloneFlags, CloseRangeFirst, CloseRangeFlags, CloseRangeLast, Errno,
+    Fd, FileMode, MountFlags, NetlinkSocketAddr, OpenFlags, SigSet, Signal, SocketDomain,
+    SocketProtocol, SocketType, UmountFlags, WaitStatus,
 };
 
 struct SliceFmt<'a> {
@@ -63,6 +63,11 @@ pub enum Syscall<'a> {
     Execve(&'a CStr, &'a [Option<&'a u8>], &'a [Option<&'a u8>]),
     FuseMountUsingSavedFd(&'a CStr, &'a CStr, MountFlags, u32, linux::Uid, linux::Gid),
     SendMsgSavedFd(&'a [u8]),
+    Write(Fd, &'a [u8]),
+    Unshare(CloneFlags),
+    /// Fork. The child carries on with the rest of the script, while the parent stays behind and
+    /// becomes the job's init process. See [`run_init`].
+    ForkInit,
 }
 
 impl<'a> Syscall<'a> {
@@ -84,6 +89,7 @@ impl<'a> Syscall<'a> {
                     *saved_fd = fd.into_fd();
                 }),
             Syscall::WriteUsingSavedFd(buf) => linux::write(*saved_fd, buf).map(drop),
+            Syscall::Write(fd, buf) => linux::write(*fd, buf).map(drop),
             Syscall::SetSid => linux::setsid(),
             Syscall::Dup2(from, to) => linux::dup2(*from, *to).map(drop),
             Syscall::CloseRange(first, last, flags) => linux::close_range(*first, *last, *flags),
@@ -94,6 +100,7 @@ impl<'a> Syscall<'a> {
             Syscall::Mkdir(path, mode) => linux::mkdir(path, *mode),
             Syscall::PivotRoot(new_root, put_old) => linux::pivot_root(new_root, put_old),
             Syscall::Umount2(path, flags) => linux::umount2(path, *flags),
+            Syscall::Unshare(flags) => linux::unshare(*flags),
             Syscall::Execve(program, arguments, environment) => {
                 linux::execve(program, arguments, environment)
             }
@@ -117,6 +124,56 @@ impl<'a> Syscall<'a> {
                 assert_eq!(count, buffer.len());
                 Ok(())
             }
+            Syscall::ForkInit => {
+                // Block signals before forking so that none can be delivered to the init process
+                // before it is ready to forward them. The child unblocks them again.
+                linux::sigprocmask_setmask(&SigSet::full())?;
+                let mut clone_args = CloneArgs::default().exit_signal(Signal::CHLD);
+                match linux::clone3(&mut clone_args)? {
+                    None => linux::sigprocmask_setmask(&SigSet::empty()),
+                    Some(child) => run_init(child),
+                }
+            }
+        }
+    }
+}
+
+/// Be the init process for the job's PID namespace, with `child` being the job's program.
+///
+/// All signals are blocked, which means that we receive them even though we are init, and they are
+/// waited for synchronously. `SIGCHLD` causes us to reap every child that has terminated, whether
+/// it's `child` or an orphan that was reparented to us. Every other signal is forwarded to `child`.
+///
+/// Once `child` terminates, we exit with its exit code, or with 128 plus the signal number if it
+/// was killed by a signal. The kernel then kills everything left in the PID namespace.
+fn run_init(child: linux::Pid) -> ! {
+    // We don't need any of the file descriptors we inherited. In particular, we need to close our
+    // copy of the socket used to report errors, since the worker waits for every copy of it to be
+    // closed before it considers the job started.
+    let _ = linux::close_range(
+        CloseRangeFirst::Fd(Fd::STDIN),
+        CloseRangeLast::Max,
+        CloseRangeFlags::default(),
+    );
+    let signals = SigSet::full();
+    loop {
+        match linux::sigwaitinfo(&signals) {
+            Ok(Signal::CHLD) => {
+                while let Ok(Some(linux::WaitResult { pid, status })) = linux::wait_nohang() {
+                    if pid == child {
+                        linux::_exit(match status {
+                            WaitStatus::Exited(code) => code,
+                            WaitStatus::Signaled(signal) => {
+                                linux::ExitCode::from_u8(128 + signal.as_u8())
+                            }
+                        });
+                    }
+                }
+            }
+            Ok(signal) => {
+                let _ = linux::kill(child, signal);
+            }
+            Err(_) => {}
         }
     }
 }
@@ -136,6 +136,15 @@ impl Key {
     }
 }
 
+/// How many releases of other artifacts each use of an artifact, after the first, puts off its
+/// removal by. This keeps frequently-used artifacts, like the layers of common base images, in the
+/// cache longer without pinning them there.
+const PRIORITY_PER_USE: u64 = 4;
+
+/// The most uses that count towards an artifact's priority, so that even the most frequently used
+/// artifacts are eventually removed once they stop being used.
+const MAX_PRIORITY_USES: u64 = 8;
+
 /// An entry for a specific [Sha256Digest] in the [Cache]'s hash table. There is one of these for
 /// every subdirectory in the `sha256` subdirectory of the [Cache]'s root directory.
 enum Entry {
@@ -146,18 +155,21 @@ enum Entry {
 
     /// The artifact has been successfully downloaded and extracted, and the subdirectory is
     /// currently being used by at least one job. We reference count this state since there may be
-    /// multiple jobs using the same artifact.
+    /// multiple jobs using the same artifact. `uses` counts every job that has ever used the
+    /// artifact while it has been in the cache.
     InUse {
         bytes_used: u64,
         ref_count: NonZeroU32,
+        uses: u64,
     },
 
     /// The artifact has been successfully downloaded and extracted, but no jobs are
-    /// currently using it. The `priority` is provided by [Cache] and is used by the [Heap] to
-    /// determine which entry should be removed first when freeing up space.
+    /// currently using it. The `priority` is provided by [Cache], weighted by `uses`, and is used
+    /// by the [Heap] to determine which entry should be removed first when freeing up space.
     InHeap {
         bytes_used: u64,
         priority: u64,
+        uses: u64,
         heap_index: HeapIndex,
     },
 }
@@ -184,12 +196,13 @@ impl HeapDeps for Map {
     type Element = Key;
 
     fn is_element_less_than(&self, lhs: &Self::Element, rhs: &Self::Element) -> bool {
+        // Of two entries with the same priority, the less frequently used one is removed first.
         let lhs_priority = match self.get(lhs) {
-            Some(Entry::InHeap { priority, .. }) => *priority,
+            Some(Entry::InHeap { priority, uses, .. }) => (*priority, *uses),
             _ => panic!("Element should be in heap"),
         };
         let rhs_priority = match self.get(rhs) {
-            Some(Entry::InHeap { priority, .. }) => *priority,
+            Some(Entry::InHeap { priority, uses, .. }) => (*priority, *uses),
             _ => panic!("Element should be in heap"),
         };
         lhs_priority.cmp(&rhs_priority) == Ordering::Less
@@ -281,12 +294,16 @@ impl<FsT: Fs> Cache<FsT> {
                         jobs.push(jid);
                         GetArtifact::Wait
                     }
-                    Entry::InUse { ref_count, .. } => {
+                    Entry::InUse {
+                        ref_count, uses, ..
+                    } => {
                         *ref_count = ref_count.checked_add(1).unwrap();
+                        *uses = uses.checked_add(1).unwrap();
                         GetArtifact::Success(cache_path)
                     }
                     Entry::InHeap {
                         bytes_used,
+                        uses,
                         heap_index,
                         ..
                     } => {
@@ -294,6 +311,7 @@ impl<FsT: Fs> Cache<FsT> {
                         *entry = Entry::InUse {
                             ref_count: NonZeroU32::new(1).unwrap(),
                             bytes_used: *bytes_used,
+                            uses: uses.checked_add(1).unwrap(),
                         };
                         self.heap.remove(&mut self.entries, heap_index);
                         GetArtifact::Success(cache_path)
@@ -339,6 +357,7 @@ impl<FsT: Fs> Cache<FsT> {
         *entry = Entry::InUse {
             bytes_used,
             ref_count: NonZeroU32::new(ref_count).unwrap(),
+            uses: ref_count.into(),
         };
         self.bytes_used = self.bytes_used.checked_add(bytes_used).unwrap();
         debug!(self.log, "cache added artifact";
@@ -353,6 +372,14 @@ impl<FsT: Fs> Cache<FsT> {
         (Self::cache_path(&self.root, &key), jobs)
     }
 
+    /// Return whether any artifacts are still being downloaded, extracted, or built. Completions
+    /// for these are still going to be delivered to the cache.
+    pub fn has_pending_entries(&self) -> bool {
+        self.entries
+            .values()
+            .any(|entry| matches!(entry, Entry::DownloadingAndExtracting(_)))
+    }
+
     /// Notify the cache that a reference to an artifact is no longer needed.
     pub fn decrement_ref_count(&mut self, kind: EntryKind, digest: &Sha256Digest) {
         let key = Key::new(kind, digest.clone());
@@ -363,6 +390,7 @@ impl<FsT: Fs> Cache<FsT> {
         let Entry::InUse {
             bytes_used,
             ref_count,
+            uses,
         } = entry
         else {
             panic!("Got decrement_ref_count with existing zero reference count");
@@ -372,7 +400,10 @@ impl<FsT: Fs> Cache<FsT> {
             None => {
                 *entry = Entry::InHeap {
                     bytes_used: *bytes_used,
-                    priority: self.next_priority,
+                    priority: self.next_priority.saturating_add(
+                        (*uses).clamp(1, MAX_PRIORITY_USES).saturating_sub(1) * PRIORITY_PER_USE,
+                    ),
+                    uses: *uses,
                     heap_index: HeapIndex::default(),
                 };
                 self.heap.push(&mut self.entries, key.clone());
@@ -409,7 +440,8 @@ impl<FsT: Fs> Cache<FsT> {
     }
 
     /// Check to see if the cache is over its goal size, and if so, try to remove the least
-    /// recently used artifacts.
+    /// recently used artifacts, counting frequently-used artifacts as more recently used than
+    /// they were.
     fn possibly_remove_some(&mut self) {
         while self.bytes_used > self.bytes_used_target {
             let Some(key) = self.heap.pop(&mut self.entries) else {
@@ -640,6 +672,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn has_pending_entries_until_got_artifact() {
+        let mut fixture = Fixture::new_and_clear_messages(1000);
+        assert!(!fixture.cache.has_pending_entries());
+
+        fixture.get_artifact_ign(digest!(42), jid!(1));
+        fixture.get_artifact_ign(digest!(43), jid!(1));
+        assert!(fixture.cache.has_pending_entries());
+
+        fixture.got_artifact_success_ign(digest!(42), 100);
+        assert!(fixture.cache.has_pending_entries());
+
+        fixture
+            .cache
+            .got_artifact_failure(EntryKind::Blob, &digest!(43));
+        assert!(!fixture.cache.has_pending_entries());
+
+        fixture.decrement_ref_count_ign(digest!(42));
+        assert!(!fixture.cache.has_pending_entries());
+    }
+
     #[test]
     fn get_request_for_empty_larger_than_goal_ok_then_removes_on_decrement_ref_count() {
         let mut fixture = Fixture::new_and_clear_messages(1000);
@@ -743,6 +796,81 @@ mod tests {
         );
     }
 
+    #[test]
+    fn second_job_using_unused_artifact_does_not_refetch() {
+        let mut fixture = Fixture::new_and_clear_messages(1000);
+
+        fixture.get_artifact(
+            digest!(1),
+            jid!(1),
+            GetArtifact::Get(long_path!("/z/blob/sha256", 1)),
+        );
+        fixture.got_artifact_success_ign(digest!(1), 100);
+        fixture.decrement_ref_count(digest!(1), vec![]);
+
+        fixture.get_artifact(
+            digest!(1),
+            jid!(2),
+            GetArtifact::Success(long_path!("/z/blob/sha256", 1)),
+        );
+    }
+
+    #[test]
+    fn frequently_used_artifacts_are_removed_later() {
+        let mut fixture = Fixture::new_and_clear_messages(10);
+
+        // Artifact 1 is used by three jobs. Its last release is the third, but each use after the
+        // first puts its removal off by PRIORITY_PER_USE more releases.
+        fixture.get_artifact_ign(digest!(1), jid!(1));
+        fixture.got_artifact_success_ign(digest!(1), 4);
+        fixture.decrement_ref_count(digest!(1), vec![]);
+        for jid in [jid!(2), jid!(3)] {
+            fixture.get_artifact_ign(digest!(1), jid);
+            fixture.decrement_ref_count(digest!(1), vec![]);
+        }
+
+        fixture.get_artifact_ign(digest!(2), jid!(2));
+        fixture.got_artifact_success_ign(digest!(2), 4);
+        fixture.decrement_ref_count(digest!(2), vec![]);
+
+        // Artifact 1 was released before each of these artifacts, but they are removed first. The
+        // last of them ties with it on priority, and is removed first for being used less.
+        for n in 3..=2 + 2 * PRIORITY_PER_USE as u32 {
+            fixture.get_artifact_ign(digest!(n), jid!(n));
+            fixture.got_artifact_success(
+                digest!(n),
+                4,
+                (long_path!("/z/blob/sha256", n), vec![jid!(n)]),
+                vec![
+                    FileExists(short_path!("/z/removing", n - 2)),
+                    Rename(
+                        long_path!("/z/blob/sha256", n - 1),
+                        short_path!("/z/removing", n - 2),
+                    ),
+                    RemoveRecursively(short_path!("/z/removing", n - 2)),
+                ],
+            );
+            fixture.decrement_ref_count(digest!(n), vec![]);
+        }
+
+        // It isn't pinned, though. Once enough other artifacts have been used, it is removed.
+        let n = 3 + 2 * PRIORITY_PER_USE as u32;
+        fixture.get_artifact_ign(digest!(n), jid!(n));
+        fixture.got_artifact_success(
+            digest!(n),
+            4,
+            (long_path!("/z/blob/sha256", n), vec![jid!(n)]),
+            vec![
+                FileExists(short_path!("/z/removing", n - 2)),
+                Rename(
+                    long_path!("/z/blob/sha256", 1),
+                    short_path!("/z/removing", n - 2),
+                ),
+                RemoveRecursively(short_path!("/z/removing", n - 2)),
+            ],
+        );
+    }
+
     #[test]
     fn multiple_get_requests_for_empty() {
         let mut fixture = Fixture::new_and_clear_messages(1000);
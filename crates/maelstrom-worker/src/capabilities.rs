@@ -0,0 +1,120 @@
+//! Detect what the worker is able to provide to jobs.
+
+use maelstrom_base::{EnumSet, JobDevice, WorkerCapabilities};
+use std::{fs, path::Path};
+
+/// Detect the capabilities of the system rooted at `root`. This is normally `/`, but it can be
+/// pointed somewhere else for testing. Anything that can't be read is treated as unsupported.
+pub fn detect_capabilities(root: &Path) -> WorkerCapabilities {
+    let devices = EnumSet::<JobDevice>::all()
+        .iter()
+        .filter(|device| root.join("dev").join(device.name()).exists())
+        .collect();
+    let overlayfs = fs::read_to_string(root.join("proc/filesystems"))
+        .map(|filesystems| {
+            filesystems
+                .lines()
+                .any(|line| line.split_whitespace().last() == Some("overlay"))
+        })
+        .unwrap_or(false);
+    let cgroup_controllers = fs::read_to_string(root.join("sys/fs/cgroup/cgroup.controllers"))
+        .map(|controllers| controllers.split_whitespace().map(Into::into).collect())
+        .unwrap_or_default();
+    let cpus = fs::read_to_string(root.join("proc/cpuinfo"))
+        .map(|cpuinfo| {
+            cpuinfo
+                .lines()
+                .filter(|line| line.split(':').next().map(str::trim) == Some("processor"))
+                .count() as u32
+        })
+        .unwrap_or(0);
+    let memory = fs::read_to_string(root.join("proc/meminfo"))
+        .ok()
+        .and_then(|meminfo| {
+            let kib = meminfo
+                .lines()
+                .find_map(|line| line.strip_prefix("MemTotal:"))?
+                .trim()
+                .strip_suffix("kB")?
+                .trim()
+                .parse::<u64>()
+                .ok()?;
+            Some(kib * 1024)
+        })
+        .unwrap_or(0);
+    WorkerCapabilities {
+        devices,
+        overlayfs,
+        cgroup_controllers,
+        cpus,
+        memory,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maelstrom_base::enum_set;
+    use tempfile::TempDir;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn empty_root() {
+        let root = TempDir::new().unwrap();
+        assert_eq!(
+            detect_capabilities(root.path()),
+            WorkerCapabilities::default()
+        );
+    }
+
+    #[test]
+    fn mock_root() {
+        let root = TempDir::new().unwrap();
+        let root = root.path();
+        write(root, "dev/null", "");
+        write(root, "dev/zero", "");
+        write(root, "dev/fuse", "");
+        write(
+            root,
+            "proc/filesystems",
+            "nodev\tsysfs\nnodev\tproc\n\text4\nnodev\toverlay\n",
+        );
+        write(
+            root,
+            "sys/fs/cgroup/cgroup.controllers",
+            "cpu memory pids\n",
+        );
+        write(
+            root,
+            "proc/cpuinfo",
+            "processor\t: 0\nmodel name\t: Test CPU\n\nprocessor\t: 1\nmodel name\t: Test CPU\n",
+        );
+        write(
+            root,
+            "proc/meminfo",
+            "MemTotal:       16384 kB\nMemFree:         8192 kB\n",
+        );
+        assert_eq!(
+            detect_capabilities(root),
+            WorkerCapabilities {
+                devices: enum_set! {JobDevice::Fuse | JobDevice::Null | JobDevice::Zero},
+                overlayfs: true,
+                cgroup_controllers: vec!["cpu".into(), "memory".into(), "pids".into()],
+                cpus: 2,
+                memory: 16384 * 1024,
+            }
+        );
+    }
+
+    #[test]
+    fn no_overlayfs() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), "proc/filesystems", "nodev\tproc\n\text4\n");
+        assert!(!detect_capabilities(root.path()).overlayfs);
+    }
+}
@@ -1,9 +1,122 @@
+use bytesize::ByteSize;
 use maelstrom_macro::Config;
 use maelstrom_util::config::common::{
-    BrokerAddr, CacheRoot, CacheSize, InlineLimit, LogLevel, Slots,
+    BrokerAddr, CacheRoot, CacheSize, InlineLimit, LogLevel, Slots, StringError,
+};
+use serde::Deserialize;
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Debug, Formatter},
+    path::PathBuf,
+    result,
+    str::FromStr,
 };
 use xdg::BaseDirectories;
 
+/// The names of the environment variables that jobs may be given, written like "PATH,HOME".
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub struct EnvironmentAllowlist(BTreeSet<String>);
+
+impl EnvironmentAllowlist {
+    /// Return whether `var`, which looks like "NAME=VALUE", may be passed to a job.
+    pub fn allows(&self, var: &str) -> bool {
+        let name = var.split_once('=').map_or(var, |(name, _)| name);
+        self.0.contains(name)
+    }
+}
+
+impl FromStr for EnvironmentAllowlist {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                if name.contains('=') {
+                    Err(StringError(format!(
+                        "invalid environment variable name {name:?}"
+                    )))
+                } else {
+                    Ok(name.to_owned())
+                }
+            })
+            .collect::<result::Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl TryFrom<String> for EnvironmentAllowlist {
+    type Error = StringError;
+    fn try_from(s: String) -> result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// The most memory each job may use.
+#[derive(Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct JobMemoryLimit(#[serde(with = "bytesize_serde")] ByteSize);
+
+impl JobMemoryLimit {
+    pub fn as_bytes(self) -> u64 {
+        self.0 .0
+    }
+}
+
+impl From<ByteSize> for JobMemoryLimit {
+    fn from(bytes: ByteSize) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Debug for JobMemoryLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for JobMemoryLimit {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        Ok(Self(
+            <ByteSize as FromStr>::from_str(s).map_err(StringError)?,
+        ))
+    }
+}
+
+/// The most memory to use for holding the captured output of all running jobs together.
+#[derive(Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct OutputBufferLimit(#[serde(with = "bytesize_serde")] ByteSize);
+
+impl OutputBufferLimit {
+    pub fn as_bytes(self) -> u64 {
+        self.0 .0
+    }
+}
+
+impl From<ByteSize> for OutputBufferLimit {
+    fn from(bytes: ByteSize) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Debug for OutputBufferLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for OutputBufferLimit {
+    type Err = StringError;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        Ok(Self(
+            <ByteSize as FromStr>::from_str(s).map_err(StringError)?,
+        ))
+    }
+}
+
 #[derive(Config, Debug)]
 pub struct Config {
     /// Socket address of broker.
@@ -31,7 +144,81 @@ pub struct Config {
     #[config(short = 'i', value_name = "BYTES", default = "InlineLimit::default()")]
     pub inline_limit: InlineLimit,
 
+    /// The most memory to use for holding the captured stdout and stderr of all running jobs
+    /// together. A job's output isn't read until there's room to hold as much of it as the inline
+    /// limit allows, so the job blocks once it fills its pipes. SI and binary suffixes are
+    /// supported.
+    #[config(option, value_name = "BYTES", default = r#""no limit""#)]
+    pub output_buffer_limit: Option<OutputBufferLimit>,
+
     /// Minimum log level to output.
     #[config(short = 'l', value_name = "LEVEL", default = r#""info""#)]
     pub log_level: LogLevel,
+
+    /// A comma-separated list of the names of the environment variables that jobs may be given.
+    /// Any other variables in a job's environment are dropped before the job is started. An empty
+    /// list means jobs are given no environment variables at all.
+    #[config(option, value_name = "VARS", default = r#""all variables are passed""#)]
+    pub environment_allowlist: Option<EnvironmentAllowlist>,
+
+    /// A cgroup directory, in a mounted cgroup v2 hierarchy, to create each job's cgroup in. Limits
+    /// set on this cgroup then apply to all jobs together. By default, jobs are run in the
+    /// worker's own cgroup.
+    #[config(option, value_name = "PATH", default = r#""the worker's cgroup""#)]
+    pub cgroup_parent: Option<PathBuf>,
+
+    /// The most memory each job may use. A job that goes over this is killed, and fails with an
+    /// execution error. This requires a cgroup parent with the memory controller enabled for its
+    /// children. SI and binary suffixes are supported.
+    #[config(option, value_name = "BYTES", default = r#""no limit""#)]
+    pub job_memory_limit: Option<JobMemoryLimit>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_allowlist_allows_listed_names() {
+        let allowlist: EnvironmentAllowlist = "PATH, HOME,".parse().unwrap();
+        assert!(allowlist.allows("PATH=/bin"));
+        assert!(allowlist.allows("HOME="));
+        assert!(!allowlist.allows("PATHS=/bin"));
+        assert!(!allowlist.allows("SECRET=hunter2"));
+    }
+
+    #[test]
+    fn environment_allowlist_empty() {
+        let allowlist: EnvironmentAllowlist = "".parse().unwrap();
+        assert!(!allowlist.allows("PATH=/bin"));
+    }
+
+    #[test]
+    fn output_buffer_limit_from_str() {
+        assert_eq!(
+            "2MiB".parse::<OutputBufferLimit>().unwrap().as_bytes(),
+            2 * 1024 * 1024
+        );
+        assert!("lots".parse::<OutputBufferLimit>().is_err());
+    }
+
+    #[test]
+    fn job_memory_limit_from_str() {
+        assert_eq!(
+            "512 MB".parse::<JobMemoryLimit>().unwrap().as_bytes(),
+            512 * 1000 * 1000
+        );
+        assert!("lots".parse::<JobMemoryLimit>().is_err());
+    }
+
+    #[test]
+    fn environment_allowlist_invalid_name() {
+        assert_eq!(
+            "PATH,FOO=1"
+                .parse::<EnvironmentAllowlist>()
+                .unwrap_err()
+                .to_string(),
+            r#"invalid environment variable name "FOO=1""#
+        );
+    }
 }
@@ -7,7 +7,8 @@ use crate::cache::{self, GetArtifact};
 use anyhow::{Error, Result};
 use maelstrom_base::{
     proto::{BrokerToWorker, WorkerToBroker},
-    ArtifactType, JobCompleted, JobError, JobId, JobOutcome, JobResult, JobSpec, Sha256Digest,
+    ArtifactType, JobCompleted, JobError, JobId, JobOutcome, JobOutputStream, JobResult, JobSpec,
+    Sha256Digest,
 };
 use maelstrom_util::{config::common::Slots, ext::OptionExt as _};
 use std::{
@@ -38,6 +39,10 @@ pub trait Deps {
     /// completes.
     fn start_job(&mut self, jid: JobId, spec: JobSpec, path: PathBuf) -> Self::JobHandle;
 
+    /// Let a job that streams its output send `bytes` more bytes of it, because the client has
+    /// consumed that many. See [`maelstrom_base::JOB_OUTPUT_WINDOW`].
+    fn job_output_acked(&mut self, handle: &Self::JobHandle, bytes: u32);
+
     /// The timer handle should cancel an outstanding timer when it is dropped. It must be safe to
     /// drop this handle after the timer has completed. Dropping this handle may or may not result
     /// in no [`Message::JobTimer`] message. The dispatcher must be prepared to handle the case
@@ -104,6 +109,7 @@ pub trait Cache {
         bytes_used: u64,
     ) -> (PathBuf, Vec<JobId>);
     fn decrement_ref_count(&mut self, kind: cache::EntryKind, digest: &Sha256Digest);
+    fn has_pending_entries(&self) -> bool;
 }
 
 /// The standard implementation of [`Cache`] that just calls into [`cache::Cache`].
@@ -137,6 +143,10 @@ impl<FsT: cache::Fs> Cache for cache::Cache<FsT> {
     fn decrement_ref_count(&mut self, kind: cache::EntryKind, digest: &Sha256Digest) {
         self.decrement_ref_count(kind, digest)
     }
+
+    fn has_pending_entries(&self) -> bool {
+        self.has_pending_entries()
+    }
 }
 
 /// An input message for the dispatcher. These come from the broker, an executor, or an artifact
@@ -145,6 +155,9 @@ impl<FsT: cache::Fs> Cache for cache::Cache<FsT> {
 pub enum Message {
     Broker(BrokerToWorker),
     JobCompleted(JobId, JobResult<JobCompleted, String>),
+    /// A chunk of output from a job with `stream_output` set. It must come before the job's
+    /// [`Message::JobCompleted`].
+    JobOutput(JobId, JobOutputStream, Vec<u8>),
     JobTimer(JobId),
     ArtifactFetchCompleted(Sha256Digest, Result<u64>),
     BuiltBottomFsLayer(Sha256Digest, Result<u64>),
@@ -180,15 +193,29 @@ impl<
         }
     }
 
+    /// Return whether there are no jobs queued or running, and nothing outstanding that will send
+    /// a message back. An idle dispatcher can be dropped and replaced with a new one without any
+    /// messages being lost.
+    pub fn is_idle(&self) -> bool {
+        self.awaiting_layers.is_empty()
+            && self.available.is_empty()
+            && self.executing.is_empty()
+            && !self.cache.has_pending_entries()
+    }
+
     /// Process an incoming message. Messages come from the broker and from executors. See
     /// [Message] for more information.
     pub fn receive_message(&mut self, msg: Message) {
         match msg {
             Message::Broker(BrokerToWorker::EnqueueJob(jid, spec)) => {
-                self.receive_enqueue_job(jid, spec)
+                self.receive_enqueue_job(jid, *spec)
             }
             Message::Broker(BrokerToWorker::CancelJob(jid)) => self.receive_cancel_job(jid),
+            Message::Broker(BrokerToWorker::JobOutputAck(jid, bytes)) => {
+                self.receive_job_output_ack(jid, bytes)
+            }
             Message::JobCompleted(jid, result) => self.receive_job_completed(jid, result),
+            Message::JobOutput(jid, stream, output) => self.receive_job_output(jid, stream, output),
             Message::JobTimer(jid) => self.receive_job_timer(jid),
             Message::ArtifactFetchCompleted(digest, Err(err)) => {
                 self.receive_artifact_failure(digest, err)
@@ -251,7 +278,7 @@ enum ExecutingJobState<DepsT: Deps> {
     /// we're going to send a `JobOutcome::Completed` result to the broker, unless it times out or
     /// is canceled in the meantime.
     Nominal {
-        _job_handle: DepsT::JobHandle,
+        job_handle: DepsT::JobHandle,
         _timer_handle: Option<DepsT::TimerHandle>,
     },
 
@@ -400,9 +427,11 @@ impl<
             .timeout
             .map(|timeout| self.deps.start_timer(jid, Duration::from(timeout)));
         let job_handle = self.deps.start_job(jid, spec, path);
+        self.broker_sender
+            .send_message_to_broker(WorkerToBroker::JobRunning(jid));
         let executing_job = ExecutingJob {
             state: ExecutingJobState::Nominal {
-                _job_handle: job_handle,
+                job_handle,
                 _timer_handle: timer_handle,
             },
             cache_keys,
@@ -474,12 +503,19 @@ impl<
         match state {
             ExecutingJobState::Nominal { .. } => {
                 self.broker_sender
-                    .send_message_to_broker(WorkerToBroker(jid, result.map(JobOutcome::Completed)));
+                    .send_message_to_broker(WorkerToBroker::JobResponse(
+                        jid,
+                        result.map(JobOutcome::Completed),
+                    ));
             }
             ExecutingJobState::Canceled => {}
-            ExecutingJobState::TimedOut => self.broker_sender.send_message_to_broker(
-                WorkerToBroker(jid, result.map(|c| JobOutcome::TimedOut(c.effects))),
-            ),
+            ExecutingJobState::TimedOut => {
+                self.broker_sender
+                    .send_message_to_broker(WorkerToBroker::JobResponse(
+                        jid,
+                        result.map(|c| JobOutcome::TimedOut(c.effects)),
+                    ))
+            }
         }
 
         for cache::Key { kind, digest } in cache_keys {
@@ -488,6 +524,37 @@ impl<
         self.possibly_start_job();
     }
 
+    fn receive_job_output(&mut self, jid: JobId, stream: JobOutputStream, output: Vec<u8>) {
+        match self.executing.get(&jid) {
+            Some(ExecutingJob {
+                state: ExecutingJobState::Nominal { .. } | ExecutingJobState::TimedOut,
+                ..
+            }) => {
+                self.broker_sender
+                    .send_message_to_broker(WorkerToBroker::JobOutput(jid, stream, output));
+            }
+            // The broker doesn't want to hear any more about a canceled job.
+            Some(ExecutingJob {
+                state: ExecutingJobState::Canceled,
+                ..
+            }) => {}
+            None => panic!("missing entry for {jid:?}"),
+        }
+    }
+
+    fn receive_job_output_ack(&mut self, jid: JobId, bytes: u32) {
+        // Acks can trail the job: the client may not have consumed the last of the job's output
+        // until after the job completed. And once the job handle has been dropped, the job's
+        // output is no longer held back, so there's nothing to do for a canceled or timed-out job.
+        if let Some(ExecutingJob {
+            state: ExecutingJobState::Nominal { job_handle, .. },
+            ..
+        }) = self.executing.get(&jid)
+        {
+            self.deps.job_output_acked(job_handle, bytes);
+        }
+    }
+
     fn receive_job_timer(&mut self, jid: JobId) {
         let Some(&mut ExecutingJob {
             ref mut state,
@@ -518,10 +585,11 @@ impl<
             //
             // Otherwise, it means that there were previous errors for this entry, or it was
             // canceled, and there's nothing to do here.
-            self.broker_sender.send_message_to_broker(WorkerToBroker(
-                jid,
-                Err(JobError::System(format!("{msg} {digest}: {err:?}"))),
-            ));
+            self.broker_sender
+                .send_message_to_broker(WorkerToBroker::JobResponse(
+                    jid,
+                    Err(JobError::System(format!("{msg} {digest}: {err:?}"))),
+                ));
             for cache::Key { kind, digest } in entry.tracker.into_cache_keys() {
                 self.cache.decrement_ref_count(kind, &digest);
             }
@@ -694,6 +762,7 @@ mod tests {
         CacheGotArtifactFailure(cache::EntryKind, Sha256Digest),
         CacheDecrementRefCount(cache::EntryKind, Sha256Digest),
         JobHandleDropped(JobId),
+        JobOutputAcked(JobId, u32),
         StartTimer(JobId, Duration),
         TimerHandleDropped(JobId),
     }
@@ -724,6 +793,13 @@ mod tests {
             TestHandle(TestMessage::JobHandleDropped(jid), self.clone())
         }
 
+        fn job_output_acked(&mut self, handle: &Self::JobHandle, bytes: u32) {
+            let TestHandle(JobHandleDropped(jid), _) = handle else {
+                panic!("not a job handle");
+            };
+            self.borrow_mut().messages.push(JobOutputAcked(*jid, bytes));
+        }
+
         type TimerHandle = TestHandle;
 
         fn start_timer(&mut self, jid: JobId, duration: Duration) -> Self::TimerHandle {
@@ -836,6 +912,10 @@ mod tests {
                 .messages
                 .push(CacheDecrementRefCount(kind, digest.clone()))
         }
+
+        fn has_pending_entries(&self) -> bool {
+            false
+        }
     }
 
     struct Fixture {
@@ -932,10 +1012,11 @@ mod tests {
             (cache_key!(Blob, 1), GetArtifact::Success(path_buf!("/a"))),
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/a"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
         Broker(CancelJob(jid!(1))) => {
             JobHandleDropped(jid!(1)),
@@ -951,13 +1032,14 @@ mod tests {
             (cache_key!(BottomFsLayer, 42), GetArtifact::Success(path_buf!("/b"))),
             (cache_key!(UpperFsLayer, 42, 41), GetArtifact::Success(path_buf!("/a"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, [(41, Tar), (42, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(41), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(41), jid!(1)),
             CacheGetArtifact(Blob, digest!(42), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(42), jid!(1)),
             CacheGetArtifact(UpperFsLayer, upper_digest!(42, 41), jid!(1)),
-            StartJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]), path_buf!("/a"))
+            StartJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
         Broker(CancelJob(jid!(1))) => {
             JobHandleDropped(jid!(1)),
@@ -972,7 +1054,7 @@ mod tests {
             (cache_key!(Blob, 43), GetArtifact::Wait),
             (cache_key!(BottomFsLayer, 41), GetArtifact::Success(path_buf!("/a"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, [(41, Tar), (42, Tar), (43, Tar)]))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, [(41, Tar), (42, Tar), (43, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(41), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(41), jid!(1)),
             CacheGetArtifact(Blob, digest!(42), jid!(1)),
@@ -998,7 +1080,7 @@ mod tests {
             (cache_key!(UpperFsLayer, 42, 41), GetArtifact::Success(path_buf!("/b"))),
             (cache_key!(UpperFsLayer, 41, 42, 41), GetArtifact::Success(path_buf!("/a"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, [(41, Tar), (42, Tar), (41, Tar)]))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, [(41, Tar), (42, Tar), (41, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(41), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(41), jid!(1)),
             CacheGetArtifact(Blob, digest!(42), jid!(1)),
@@ -1006,7 +1088,8 @@ mod tests {
             CacheGetArtifact(UpperFsLayer, upper_digest!(42, 41), jid!(1)),
             CacheGetArtifact(UpperFsLayer, upper_digest!(41, 42, 41), jid!(1)),
             StartJob(jid!(1), spec!(1, [(41, Tar), (42, Tar), (41, Tar)]), path_buf!("/a")),
-            SendMessageToBroker(WorkerToBroker(jid!(1), Err(JobError::System(string!("se"))))),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(1), Err(JobError::System(string!("se"))))),
             CacheDecrementRefCount(Blob, digest!(41)),
             CacheDecrementRefCount(BottomFsLayer, digest!(41)),
             CacheDecrementRefCount(Blob, digest!(42)),
@@ -1028,19 +1111,20 @@ mod tests {
             (cache_key!(BottomFsLayer, 42), GetArtifact::Success(path_buf!("/b"))),
             (cache_key!(UpperFsLayer, 42, 41), GetArtifact::Success(path_buf!("/a"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, [(41, Tar), (42, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(42), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(42), jid!(1)),
             CacheGetArtifact(Blob, digest!(41), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(41), jid!(1)),
             CacheGetArtifact(UpperFsLayer, upper_digest!(42, 41), jid!(1)),
             StartJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
             CacheDecrementRefCount(Blob, digest!(41)),
             CacheDecrementRefCount(BottomFsLayer, digest!(41)),
             CacheDecrementRefCount(Blob, digest!(42)),
             CacheDecrementRefCount(BottomFsLayer, digest!(42)),
             CacheDecrementRefCount(UpperFsLayer, upper_digest!(42, 41)),
-            SendMessageToBroker(WorkerToBroker(jid!(1), Err(JobError::Execution(string!("ee"))))),
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(1), Err(JobError::Execution(string!("ee"))))),
         };
         Broker(CancelJob(jid!(1))) => {};
     }
@@ -1060,27 +1144,31 @@ mod tests {
             (cache_key!(BottomFsLayer, 4), GetArtifact::Success(path_buf!("/d"))),
             (cache_key!(BottomFsLayer, 5), GetArtifact::Success(path_buf!("/e"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
-        Broker(EnqueueJob(jid!(2), spec!(2, Tar))) => {
+        Broker(EnqueueJob(jid!(2), Box::new(spec!(2, Tar)))) => {
             CacheGetArtifact(Blob, digest!(2), jid!(2)),
             CacheGetArtifact(BottomFsLayer, digest!(2), jid!(2)),
             StartJob(jid!(2), spec!(2, Tar), path_buf!("/b")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(2))),
         };
-        Broker(EnqueueJob(jid!(3), spec!(3, Tar))) => {
+        Broker(EnqueueJob(jid!(3), Box::new(spec!(3, Tar)))) => {
             CacheGetArtifact(Blob, digest!(3), jid!(3)),
             CacheGetArtifact(BottomFsLayer, digest!(3), jid!(3)),
             StartJob(jid!(3), spec!(3, Tar), path_buf!("/c")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(3))),
         };
-        Broker(EnqueueJob(jid!(4), spec!(4, Tar))) => {
+        Broker(EnqueueJob(jid!(4), Box::new(spec!(4, Tar)))) => {
             CacheGetArtifact(Blob, digest!(4), jid!(4)),
             CacheGetArtifact(BottomFsLayer, digest!(4), jid!(4)),
             StartJob(jid!(4), spec!(4, Tar), path_buf!("/d")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(4))),
         };
-        Broker(EnqueueJob(jid!(5), spec!(5, Tar))) => {
+        Broker(EnqueueJob(jid!(5), Box::new(spec!(5, Tar)))) => {
             CacheGetArtifact(Blob, digest!(5), jid!(5)),
             CacheGetArtifact(BottomFsLayer, digest!(5), jid!(5)),
         };
@@ -1093,11 +1181,13 @@ mod tests {
                 stdout: JobOutputResult::None,
                 stderr: JobOutputResult::None,
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
             CacheDecrementRefCount(Blob, digest!(1)),
             CacheDecrementRefCount(BottomFsLayer, digest!(1)),
             StartJob(jid!(5), spec!(5, Tar), path_buf!("/e")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(5))),
         }
     }
 
@@ -1119,38 +1209,42 @@ mod tests {
             (cache_key!(BottomFsLayer, 3), GetArtifact::Success(path_buf!("/c"))),
             (cache_key!(BottomFsLayer, 4), GetArtifact::Success(path_buf!("/d"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
-        Broker(EnqueueJob(jid!(2), spec!(2, Tar))) => {
+        Broker(EnqueueJob(jid!(2), Box::new(spec!(2, Tar)))) => {
             CacheGetArtifact(Blob, digest!(2), jid!(2)),
             CacheGetArtifact(BottomFsLayer, digest!(2), jid!(2)),
         };
-        Broker(EnqueueJob(jid!(3), spec!(3, Tar))) => {
+        Broker(EnqueueJob(jid!(3), Box::new(spec!(3, Tar)))) => {
             CacheGetArtifact(Blob, digest!(3), jid!(3)),
             CacheGetArtifact(BottomFsLayer, digest!(3), jid!(3)),
         };
-        Broker(EnqueueJob(jid!(4), spec!(4, Tar))) => {
+        Broker(EnqueueJob(jid!(4), Box::new(spec!(4, Tar)))) => {
             CacheGetArtifact(Blob, digest!(4), jid!(4)),
             CacheGetArtifact(BottomFsLayer, digest!(4), jid!(4)),
         };
         PidStatus(pid!(1), JobStatus::Exited(0)) => {};
         JobStdout(jid!(1), Ok(JobOutputResult::None)) => {};
         JobStderr(jid!(1), Ok(JobOutputResult::None)) => {
-            SendMessageToBroker(WorkerToBroker(jid!(1), outcome!(1))),
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(1), outcome!(1))),
             CacheDecrementRefCount(Blob, digest!(1)),
             CacheDecrementRefCount(BottomFsLayer, digest!(1)),
             StartJob(jid!(2), spec!(2, Tar), path_buf!("/b")),
-            SendMessageToBroker(WorkerToBroker(jid!(2), Err(JobError::Execution(string!("ee"))))),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(2))),
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(2), Err(JobError::Execution(string!("ee"))))),
             CacheDecrementRefCount(Blob, digest!(2)),
             CacheDecrementRefCount(BottomFsLayer, digest!(2)),
             StartJob(jid!(3), spec!(3, Tar), path_buf!("/c")),
-            SendMessageToBroker(WorkerToBroker(jid!(3), Err(JobError::System(string!("se"))))),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(3))),
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(3), Err(JobError::System(string!("se"))))),
             CacheDecrementRefCount(Blob, digest!(3)),
             CacheDecrementRefCount(BottomFsLayer, digest!(3)),
             StartJob(jid!(4), spec!(4, Tar), path_buf!("/d")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(4))),
         };
     }
     */
@@ -1162,7 +1256,7 @@ mod tests {
             (cache_key!(BottomFsLayer, 41), GetArtifact::Success(path_buf!("/a"))),
             (cache_key!(Blob, 42), GetArtifact::Wait),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, [(41, Tar), (42, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(41), jid!(1)),
             CacheGetArtifact(Blob, digest!(42), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(41), jid!(1)),
@@ -1184,15 +1278,16 @@ mod tests {
             (cache_key!(BottomFsLayer, 43), GetArtifact::Success(path_buf!("/c"))),
             (cache_key!(UpperFsLayer, 42, 41), GetArtifact::Success(path_buf!("/a"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, [(41, Tar), (42, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(41), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(41), jid!(1)),
             CacheGetArtifact(Blob, digest!(42), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(42), jid!(1)),
             CacheGetArtifact(UpperFsLayer, upper_digest!(42, 41), jid!(1)),
-            StartJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]), path_buf!("/a"))
+            StartJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
-        Broker(EnqueueJob(jid!(2), spec!(2, [(43, Tar)]))) => {
+        Broker(EnqueueJob(jid!(2), Box::new(spec!(2, [(43, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(43), jid!(2)),
             CacheGetArtifact(BottomFsLayer, digest!(43), jid!(2)),
         };
@@ -1205,6 +1300,7 @@ mod tests {
                 stdout: JobOutputResult::None,
                 stderr: JobOutputResult::None,
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
             CacheDecrementRefCount(BottomFsLayer, digest!(41)),
@@ -1213,6 +1309,7 @@ mod tests {
             CacheDecrementRefCount(Blob, digest!(41)),
             CacheDecrementRefCount(Blob, digest!(42)),
             StartJob(jid!(2), spec!(2, [(43, Tar)]), path_buf!("/c")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(2))),
         };
     }
 
@@ -1232,17 +1329,19 @@ mod tests {
             (cache_key!(UpperFsLayer, 42, 41), GetArtifact::Success(path_buf!("/a"))),
             (cache_key!(UpperFsLayer, 41, 42, 41), GetArtifact::Success(path_buf!("/b"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar), path_buf!("/1")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
-        Broker(EnqueueJob(jid!(2), spec!(2, Tar))) => {
+        Broker(EnqueueJob(jid!(2), Box::new(spec!(2, Tar)))) => {
             CacheGetArtifact(Blob, digest!(2), jid!(2)),
             CacheGetArtifact(BottomFsLayer, digest!(2), jid!(2)),
             StartJob(jid!(2), spec!(2, Tar), path_buf!("/2")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(2))),
         };
-        Broker(EnqueueJob(jid!(3), spec!(3, [(41, Tar), (42, Tar), (41, Tar)]))) => {
+        Broker(EnqueueJob(jid!(3), Box::new(spec!(3, [(41, Tar), (42, Tar), (41, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(41), jid!(3)),
             CacheGetArtifact(BottomFsLayer, digest!(41), jid!(3)),
             CacheGetArtifact(Blob, digest!(42), jid!(3)),
@@ -1250,7 +1349,7 @@ mod tests {
             CacheGetArtifact(UpperFsLayer, upper_digest!(42, 41), jid!(3)),
             CacheGetArtifact(UpperFsLayer, upper_digest!(41, 42, 41), jid!(3)),
         };
-        Broker(EnqueueJob(jid!(4), spec!(4, Tar))) => {
+        Broker(EnqueueJob(jid!(4), Box::new(spec!(4, Tar)))) => {
             CacheGetArtifact(Blob, digest!(4), jid!(4)),
             CacheGetArtifact(BottomFsLayer, digest!(4), jid!(4)),
         };
@@ -1268,13 +1367,15 @@ mod tests {
                 stdout: JobOutputResult::None,
                 stderr: JobOutputResult::None,
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
-            SendMessageToBroker(WorkerToBroker(jid!(1), outcome!(1))),
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(1), outcome!(1))),
             CacheDecrementRefCount(Blob, digest!(1)),
             CacheDecrementRefCount(BottomFsLayer, digest!(1)),
             JobHandleDropped(jid!(1)),
             StartJob(jid!(4), spec!(4, Tar), path_buf!("/4")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(4))),
         };
     }
 
@@ -1290,10 +1391,11 @@ mod tests {
             (cache_key!(Blob, 1), GetArtifact::Success(path_buf!("/a"))),
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/a"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
         Broker(CancelJob(jid!(1))) => { JobHandleDropped(jid!(1)) };
         Broker(CancelJob(jid!(1))) => {};
@@ -1308,13 +1410,14 @@ mod tests {
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/1"))),
             (cache_key!(BottomFsLayer, 2), GetArtifact::Success(path_buf!("/2"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar).timeout(timeout!(1)))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar).timeout(timeout!(1))))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar).timeout(timeout!(1)), path_buf!("/1")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
             StartTimer(jid!(1), Duration::from_secs(1))
         };
-        Broker(EnqueueJob(jid!(2), spec!(2, Tar))) => {
+        Broker(EnqueueJob(jid!(2), Box::new(spec!(2, Tar)))) => {
             CacheGetArtifact(Blob, digest!(2), jid!(2)),
             CacheGetArtifact(BottomFsLayer, digest!(2), jid!(2)),
         };
@@ -1329,11 +1432,13 @@ mod tests {
                 stdout: JobOutputResult::None,
                 stderr: JobOutputResult::None,
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
             CacheDecrementRefCount(Blob, digest!(1)),
             CacheDecrementRefCount(BottomFsLayer, digest!(1)),
             StartJob(jid!(2), spec!(2, Tar), path_buf!("/2")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(2))),
         };
     }
 
@@ -1345,12 +1450,13 @@ mod tests {
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/a"))),
             (cache_key!(BottomFsLayer, 2), GetArtifact::Success(path_buf!("/b"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
-        Broker(EnqueueJob(jid!(2), spec!(2, Tar))) => {
+        Broker(EnqueueJob(jid!(2), Box::new(spec!(2, Tar)))) => {
             CacheGetArtifact(Blob, digest!(2), jid!(2)),
             CacheGetArtifact(BottomFsLayer, digest!(2), jid!(2)),
         };
@@ -1360,13 +1466,15 @@ mod tests {
                 stdout: JobOutputResult::None,
                 stderr: JobOutputResult::None,
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
-            SendMessageToBroker(WorkerToBroker(jid!(1), outcome!(1))),
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(1), outcome!(1))),
             CacheDecrementRefCount(Blob, digest!(1)),
             CacheDecrementRefCount(BottomFsLayer, digest!(1)),
             JobHandleDropped(jid!(1)),
             StartJob(jid!(2), spec!(2, Tar), path_buf!("/b")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(2))),
         };
     }
 
@@ -1378,22 +1486,24 @@ mod tests {
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/a"))),
             (cache_key!(BottomFsLayer, 2), GetArtifact::Success(path_buf!("/b"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
-        Broker(EnqueueJob(jid!(2), spec!(2, Tar))) => {
+        Broker(EnqueueJob(jid!(2), Box::new(spec!(2, Tar)))) => {
             CacheGetArtifact(Blob, digest!(2), jid!(2)),
             CacheGetArtifact(BottomFsLayer, digest!(2), jid!(2)),
         };
         Message::JobCompleted(jid!(1), Err(JobError::System(string!("job error")))) => {
-            SendMessageToBroker(WorkerToBroker(
+            SendMessageToBroker(WorkerToBroker::JobResponse(
                 jid!(1), Err(JobError::System(string!("job error"))))),
             CacheDecrementRefCount(Blob, digest!(1)),
             CacheDecrementRefCount(BottomFsLayer, digest!(1)),
             JobHandleDropped(jid!(1)),
             StartJob(jid!(2), spec!(2, Tar), path_buf!("/b")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(2))),
         };
     }
 
@@ -1406,13 +1516,14 @@ mod tests {
             (cache_key!(BottomFsLayer, 42), GetArtifact::Success(path_buf!("/b"))),
             (cache_key!(UpperFsLayer, 42, 41), GetArtifact::Success(path_buf!("/a"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, [(41, Tar), (42, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(41), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(41), jid!(1)),
             CacheGetArtifact(Blob, digest!(42), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(42), jid!(1)),
             CacheGetArtifact(UpperFsLayer, upper_digest!(42, 41), jid!(1)),
             StartJob(jid!(1), spec!(1, [(41, Tar), (42, Tar)]), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
         Broker(CancelJob(jid!(1))) => {
             JobHandleDropped(jid!(1)),
@@ -1423,6 +1534,7 @@ mod tests {
                 stdout: JobOutputResult::None,
                 stderr: JobOutputResult::None,
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
             CacheDecrementRefCount(Blob, digest!(41)),
@@ -1433,6 +1545,87 @@ mod tests {
         };
     }
 
+    script_test! {
+        receive_job_output_executing,
+        Fixture::new(1, [
+            (cache_key!(Blob, 1), GetArtifact::Success(path_buf!("/a"))),
+            (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/a"))),
+        ], [], []),
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
+            CacheGetArtifact(Blob, digest!(1), jid!(1)),
+            CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
+            StartJob(jid!(1), spec!(1, Tar), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
+        };
+        Message::JobOutput(jid!(1), JobOutputStream::Stdout, b"out".to_vec()) => {
+            SendMessageToBroker(WorkerToBroker::JobOutput(
+                jid!(1), JobOutputStream::Stdout, b"out".to_vec())),
+        };
+        Message::JobOutput(jid!(1), JobOutputStream::Stderr, b"err".to_vec()) => {
+            SendMessageToBroker(WorkerToBroker::JobOutput(
+                jid!(1), JobOutputStream::Stderr, b"err".to_vec())),
+        };
+    }
+
+    script_test! {
+        receive_job_output_canceled,
+        Fixture::new(1, [
+            (cache_key!(Blob, 1), GetArtifact::Success(path_buf!("/a"))),
+            (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/a"))),
+        ], [], []),
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
+            CacheGetArtifact(Blob, digest!(1), jid!(1)),
+            CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
+            StartJob(jid!(1), spec!(1, Tar), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
+        };
+        Broker(CancelJob(jid!(1))) => {
+            JobHandleDropped(jid!(1)),
+        };
+        Message::JobOutput(jid!(1), JobOutputStream::Stdout, b"out".to_vec()) => {};
+    }
+
+    script_test! {
+        receive_job_output_ack_executing,
+        Fixture::new(1, [
+            (cache_key!(Blob, 1), GetArtifact::Success(path_buf!("/a"))),
+            (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/a"))),
+        ], [], []),
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
+            CacheGetArtifact(Blob, digest!(1), jid!(1)),
+            CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
+            StartJob(jid!(1), spec!(1, Tar), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
+        };
+        Broker(JobOutputAck(jid!(1), 100)) => {
+            JobOutputAcked(jid!(1), 100),
+        };
+    }
+
+    script_test! {
+        receive_job_output_ack_canceled,
+        Fixture::new(1, [
+            (cache_key!(Blob, 1), GetArtifact::Success(path_buf!("/a"))),
+            (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/a"))),
+        ], [], []),
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))) => {
+            CacheGetArtifact(Blob, digest!(1), jid!(1)),
+            CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
+            StartJob(jid!(1), spec!(1, Tar), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
+        };
+        Broker(CancelJob(jid!(1))) => {
+            JobHandleDropped(jid!(1)),
+        };
+        Broker(JobOutputAck(jid!(1), 100)) => {};
+    }
+
+    script_test! {
+        receive_job_output_ack_unknown,
+        Fixture::new(1, [], [], []),
+        Broker(JobOutputAck(jid!(1), 100)) => {};
+    }
+
     #[test]
     #[should_panic(expected = "missing entry for JobId")]
     fn receive_job_completed_unknown() {
@@ -1445,6 +1638,7 @@ mod tests {
                     stdout: JobOutputResult::None,
                     stderr: JobOutputResult::None,
                     duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
                 },
             }),
         ));
@@ -1456,10 +1650,11 @@ mod tests {
             (cache_key!(Blob, 1), GetArtifact::Success(path_buf!("/a"))),
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/a"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar).timeout(timeout!(33)))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar).timeout(timeout!(33))))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar).timeout(timeout!(33)), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
             StartTimer(jid!(1), Duration::from_secs(33)),
         };
         Message::JobCompleted(jid!(1), Ok(base::JobCompleted {
@@ -1468,14 +1663,16 @@ mod tests {
                 stdout: JobOutputResult::None,
                 stderr: JobOutputResult::None,
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
-            SendMessageToBroker(WorkerToBroker(jid!(1), Ok(JobOutcome::Completed(base::JobCompleted {
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(1), Ok(JobOutcome::Completed(base::JobCompleted {
                 status: JobStatus::Exited(0),
                 effects: JobEffects {
                     stdout: JobOutputResult::None,
                     stderr: JobOutputResult::None,
                     duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
                 }
             })))),
             CacheDecrementRefCount(Blob, digest!(1)),
@@ -1491,10 +1688,11 @@ mod tests {
             (cache_key!(Blob, 1), GetArtifact::Success(path_buf!("/a"))),
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/a"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar).timeout(timeout!(33)))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar).timeout(timeout!(33))))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar).timeout(timeout!(33)), path_buf!("/a")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
             StartTimer(jid!(1), Duration::from_secs(33)),
         };
         Broker(CancelJob(jid!(1))) => {
@@ -1511,13 +1709,14 @@ mod tests {
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/1"))),
             (cache_key!(BottomFsLayer, 2), GetArtifact::Success(path_buf!("/2"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar).timeout(timeout!(1)))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar).timeout(timeout!(1))))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar).timeout(timeout!(1)), path_buf!("/1")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
             StartTimer(jid!(1), Duration::from_secs(1))
         };
-        Broker(EnqueueJob(jid!(2), spec!(2, Tar))) => {
+        Broker(EnqueueJob(jid!(2), Box::new(spec!(2, Tar)))) => {
             CacheGetArtifact(Blob, digest!(2), jid!(2)),
             CacheGetArtifact(BottomFsLayer, digest!(2), jid!(2)),
         };
@@ -1531,16 +1730,19 @@ mod tests {
                 stdout: JobOutputResult::Inline(boxed_u8!(b"stdout")),
                 stderr: JobOutputResult::Inline(boxed_u8!(b"stderr")),
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
             CacheDecrementRefCount(Blob, digest!(1)),
             CacheDecrementRefCount(BottomFsLayer, digest!(1)),
-            SendMessageToBroker(WorkerToBroker(jid!(1), Ok(JobOutcome::TimedOut(JobEffects {
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(1), Ok(JobOutcome::TimedOut(JobEffects {
                 stdout: JobOutputResult::Inline(boxed_u8!(b"stdout")),
                 stderr: JobOutputResult::Inline(boxed_u8!(b"stderr")),
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             })))),
             StartJob(jid!(2), spec!(2, Tar), path_buf!("/2")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(2))),
         };
     }
 
@@ -1552,13 +1754,14 @@ mod tests {
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/1"))),
             (cache_key!(BottomFsLayer, 2), GetArtifact::Success(path_buf!("/2"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar).timeout(timeout!(1)))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar).timeout(timeout!(1))))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar).timeout(timeout!(1)), path_buf!("/1")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
             StartTimer(jid!(1), Duration::from_secs(1))
         };
-        Broker(EnqueueJob(jid!(2), spec!(2, Tar))) => {
+        Broker(EnqueueJob(jid!(2), Box::new(spec!(2, Tar)))) => {
             CacheGetArtifact(Blob, digest!(2), jid!(2)),
             CacheGetArtifact(BottomFsLayer, digest!(2), jid!(2)),
         };
@@ -1568,21 +1771,24 @@ mod tests {
                 stdout: JobOutputResult::None,
                 stderr: JobOutputResult::None,
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
             CacheDecrementRefCount(Blob, digest!(1)),
             CacheDecrementRefCount(BottomFsLayer, digest!(1)),
             TimerHandleDropped(jid!(1)),
-            SendMessageToBroker(WorkerToBroker(jid!(1), Ok(JobOutcome::Completed(base::JobCompleted {
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(1), Ok(JobOutcome::Completed(base::JobCompleted {
                 status: JobStatus::Exited(0),
                 effects: JobEffects {
                     stdout: JobOutputResult::None,
                     stderr: JobOutputResult::None,
                     duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
                 }
             })))),
             JobHandleDropped(jid!(1)),
             StartJob(jid!(2), spec!(2, Tar), path_buf!("/2")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(2))),
         };
         JobTimer(jid!(1)) => {};
     }
@@ -1595,13 +1801,14 @@ mod tests {
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/1"))),
             (cache_key!(BottomFsLayer, 2), GetArtifact::Success(path_buf!("/2"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, Tar).timeout(timeout!(1)))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar).timeout(timeout!(1))))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             StartJob(jid!(1), spec!(1, Tar).timeout(timeout!(1)), path_buf!("/1")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
             StartTimer(jid!(1), Duration::from_secs(1))
         };
-        Broker(EnqueueJob(jid!(2), spec!(2, Tar))) => {
+        Broker(EnqueueJob(jid!(2), Box::new(spec!(2, Tar)))) => {
             CacheGetArtifact(Blob, digest!(2), jid!(2)),
             CacheGetArtifact(BottomFsLayer, digest!(2), jid!(2)),
         };
@@ -1616,11 +1823,13 @@ mod tests {
                 stdout: JobOutputResult::None,
                 stderr: JobOutputResult::None,
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
             CacheDecrementRefCount(Blob, digest!(1)),
             CacheDecrementRefCount(BottomFsLayer, digest!(1)),
             StartJob(jid!(2), spec!(2, Tar), path_buf!("/2")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(2))),
         };
     }
 
@@ -1640,7 +1849,7 @@ mod tests {
             (cache_key!(Blob, 42), vec![jid!(1)]),
             (cache_key!(Blob, 44), vec![jid!(1)]),
         ]),
-        Broker(EnqueueJob(jid!(1), spec!(1, [(41, Tar), (42, Tar), (43, Tar), (44, Tar)]))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, [(41, Tar), (42, Tar), (43, Tar), (44, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(41), jid!(1)),
             CacheGetArtifact(Blob, digest!(42), jid!(1)),
             CacheGetArtifact(Blob, digest!(43), jid!(1)),
@@ -1652,7 +1861,7 @@ mod tests {
         };
         ArtifactFetchCompleted(digest!(42), Err(anyhow!("foo"))) => {
             CacheGotArtifactFailure(Blob, digest!(42)),
-            SendMessageToBroker(WorkerToBroker(jid!(1), Err(JobError::System(
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(1), Err(JobError::System(
                 string!("Failed to download and extract layer artifact 000000000000000000000000000000000000000000000000000000000000002a: foo"))))),
             CacheDecrementRefCount(Blob, digest!(41))
         };
@@ -1687,10 +1896,10 @@ mod tests {
         );
         fixture
             .dispatcher
-            .receive_message(Broker(EnqueueJob(jid!(1), spec!(1, Tar))));
+            .receive_message(Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))));
         fixture
             .dispatcher
-            .receive_message(Broker(EnqueueJob(jid!(1), spec!(2, Tar))));
+            .receive_message(Broker(EnqueueJob(jid!(1), Box::new(spec!(2, Tar)))));
     }
 
     script_test! {
@@ -1700,11 +1909,12 @@ mod tests {
             (cache_key!(BottomFsLayer, 1), GetArtifact::Success(path_buf!("/1"))),
             (cache_key!(UpperFsLayer, 1, 1), GetArtifact::Success(path_buf!("/1"))),
         ], [], []),
-        Broker(EnqueueJob(jid!(1), spec!(1, [(1, Tar), (1, Tar)]))) => {
+        Broker(EnqueueJob(jid!(1), Box::new(spec!(1, [(1, Tar), (1, Tar)])))) => {
             CacheGetArtifact(Blob, digest!(1), jid!(1)),
             CacheGetArtifact(BottomFsLayer, digest!(1), jid!(1)),
             CacheGetArtifact(UpperFsLayer, upper_digest!(1, 1), jid!(1)),
             StartJob(jid!(1), spec!(1, [(1, Tar), (1, Tar)]), path_buf!("/1")),
+            SendMessageToBroker(WorkerToBroker::JobRunning(jid!(1))),
         };
         Message::JobCompleted(jid!(1), Ok(base::JobCompleted {
             status: JobStatus::Exited(0),
@@ -1712,20 +1922,61 @@ mod tests {
                 stdout: JobOutputResult::None,
                 stderr: JobOutputResult::None,
                 duration: std::time::Duration::from_secs(1),
+                resource_usage: Default::default(),
             }
         })) => {
             CacheDecrementRefCount(Blob, digest!(1)),
             CacheDecrementRefCount(BottomFsLayer, digest!(1)),
             CacheDecrementRefCount(UpperFsLayer, upper_digest!(1, 1)),
-            SendMessageToBroker(WorkerToBroker(jid!(1), Ok(JobOutcome::Completed(base::JobCompleted {
+            SendMessageToBroker(WorkerToBroker::JobResponse(jid!(1), Ok(JobOutcome::Completed(base::JobCompleted {
                 status: JobStatus::Exited(0),
                 effects: JobEffects {
                     stdout: JobOutputResult::None,
                     stderr: JobOutputResult::None,
                     duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
                 },
             })))),
             JobHandleDropped(jid!(1)),
         };
     }
+
+    #[test]
+    fn idle_until_job_completes() {
+        let mut fixture = Fixture::new(
+            1,
+            [
+                (cache_key!(Blob, 1), GetArtifact::Success(path_buf!("/a"))),
+                (
+                    cache_key!(BottomFsLayer, 1),
+                    GetArtifact::Success(path_buf!("/a")),
+                ),
+            ],
+            [],
+            [],
+        );
+        assert!(fixture.dispatcher.is_idle());
+        fixture
+            .dispatcher
+            .receive_message(Broker(EnqueueJob(jid!(1), Box::new(spec!(1, Tar)))));
+        assert!(!fixture.dispatcher.is_idle());
+        // A canceled job isn't done until it has actually terminated.
+        fixture
+            .dispatcher
+            .receive_message(Broker(CancelJob(jid!(1))));
+        assert!(!fixture.dispatcher.is_idle());
+        fixture.dispatcher.receive_message(Message::JobCompleted(
+            jid!(1),
+            Ok(base::JobCompleted {
+                status: JobStatus::Exited(0),
+                effects: JobEffects {
+                    stdout: JobOutputResult::None,
+                    stderr: JobOutputResult::None,
+                    duration: std::time::Duration::from_secs(1),
+                    resource_usage: Default::default(),
+                },
+            }),
+        ));
+        assert!(fixture.dispatcher.is_idle());
+    }
 }
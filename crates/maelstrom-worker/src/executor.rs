@@ -1,5 +1,6 @@
 //! Easily start and stop processes.
 
+use crate::config::{EnvironmentAllowlist, JobMemoryLimit, OutputBufferLimit};
 use anyhow::{anyhow, Error, Result};
 use bumpalo::{
     collections::{String as BumpString, Vec as BumpVec},
@@ -8,7 +9,8 @@ use bumpalo::{
 use futures::ready;
 use maelstrom_base::{
     EnumSet, GroupId, JobCompleted, JobDevice, JobEffects, JobError, JobMount, JobMountFsType,
-    JobOutputResult, JobResult, JobStatus, Timeout, UserId, Utf8PathBuf,
+    JobOutputResult, JobOutputStream, JobResourceUsage, JobResult, JobStatus, Timeout, UserId,
+    Utf8PathBuf,
 };
 use maelstrom_linux::{
     self as linux, CloneArgs, CloneFlags, CloseRangeFirst, CloseRangeFlags, CloseRangeLast, Errno,
@@ -17,6 +19,7 @@ use maelstrom_linux::{
 };
 use maelstrom_util::{
     config::common::InlineLimit,
+    ext::BoolExt as _,
     sync::EventReceiver,
     time::{Clock, ClockInstant as _},
 };
@@ -25,20 +28,30 @@ use netlink_packet_core::{NetlinkMessage, NLM_F_ACK, NLM_F_CREATE, NLM_F_EXCL, N
 use netlink_packet_route::{rtnl::constants::RTM_SETLINK, LinkMessage, RtnlMessage, IFF_UP};
 use std::os::unix::fs::MetadataExt;
 use std::{
+    collections::BTreeSet,
     ffi::{CStr, CString},
     fmt::Write as _,
     fs::File,
+    future::Future,
     io::Read as _,
     iter, mem,
-    os::{fd, unix::ffi::OsStrExt as _},
+    os::{
+        fd::{self, AsRawFd as _},
+        unix::ffi::OsStrExt as _,
+    },
     path::{Path, PathBuf},
     pin::Pin,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     task::{Context, Poll},
 };
 use tokio::{
     io::{self, unix::AsyncFd, AsyncRead, AsyncReadExt as _, Interest, ReadBuf},
     runtime, select,
-    sync::oneshot,
+    sync::{oneshot, Notify},
     task::JoinSet,
 };
 
@@ -64,6 +77,14 @@ pub struct JobSpec {
     pub user: UserId,
     pub group: GroupId,
     pub timeout: Option<Timeout>,
+    pub enable_init_process: bool,
+    /// The CPU cores the job asked for. With a cgroup parent, the job's cgroup is limited to this
+    /// much CPU time. Zero means no limit.
+    pub cpus: u32,
+    /// The bytes of memory the job asked for. With a cgroup parent, the job's cgroup is limited to
+    /// this much memory, or to the executor's job memory limit if that's lower. Zero means no limit.
+    pub memory: u64,
+    pub stream_output: bool,
 }
 
 impl JobSpec {
@@ -81,6 +102,11 @@ impl JobSpec {
             user,
             group,
             timeout,
+            enable_init_process,
+            cpus,
+            memory,
+            stream_output,
+            priority: _,
         } = spec;
         JobSpec {
             program,
@@ -94,6 +120,10 @@ impl JobSpec {
             user,
             group,
             timeout,
+            enable_init_process,
+            cpus,
+            memory,
+            stream_output,
         }
     }
 }
@@ -109,11 +139,42 @@ pub struct Executor<'clock, ClockT> {
     comma_upperdir_comma_workdir: String,
     netlink_socket_addr: NetlinkSocketAddr,
     netlink_message: Box<[u8]>,
+    environment_allowlist: Option<EnvironmentAllowlist>,
+    cgroup_parent: Option<PathBuf>,
+    next_cgroup: AtomicU64,
+    job_memory_limit: Option<JobMemoryLimit>,
+    output_buffer_budget: Option<Arc<OutputBufferBudget>>,
     clock: &'clock ClockT,
 }
 
 impl<'clock, ClockT> Executor<'clock, ClockT> {
-    pub fn new(mount_dir: PathBuf, tmpfs_dir: PathBuf, clock: &'clock ClockT) -> Result<Self> {
+    /// If `environment_allowlist` is given, only the variables it allows are passed from a job's
+    /// environment to the job.
+    ///
+    /// If `cgroup_parent` is given, each job is started in a new cgroup created in that cgroup
+    /// directory, instead of in our own cgroup. The job's cgroup is removed once the job exits.
+    ///
+    /// If `job_memory_limit` is given, each job's cgroup is limited to that much memory. This
+    /// requires `cgroup_parent`.
+    ///
+    /// A job's own CPU and memory requests are only enforced when `cgroup_parent` is given.
+    /// Without it, they're only used by the broker to decide where to run the job.
+    ///
+    /// If `output_buffer_limit` is given, the captured output of all jobs run by this executor
+    /// together won't take more than that much memory while it's being read.
+    pub fn new(
+        mount_dir: PathBuf,
+        tmpfs_dir: PathBuf,
+        environment_allowlist: Option<EnvironmentAllowlist>,
+        cgroup_parent: Option<PathBuf>,
+        job_memory_limit: Option<JobMemoryLimit>,
+        output_buffer_limit: Option<OutputBufferLimit>,
+        clock: &'clock ClockT,
+    ) -> Result<Self> {
+        if job_memory_limit.is_some() && cgroup_parent.is_none() {
+            return Err(anyhow!("a job memory limit requires a cgroup parent"));
+        }
+
         // Set up stdin to be a file that will always return EOF. We could do something similar
         // by opening /dev/null but then we would depend on /dev being mounted. The fewer
         // dependencies, the better.
@@ -177,6 +238,12 @@ impl<'clock, ClockT> Executor<'clock, ClockT> {
             comma_upperdir_comma_workdir,
             netlink_socket_addr,
             netlink_message: buffer,
+            environment_allowlist,
+            cgroup_parent,
+            next_cgroup: AtomicU64::new(0),
+            job_memory_limit,
+            output_buffer_budget: output_buffer_limit
+                .map(|limit| Arc::new(OutputBufferBudget::new(limit))),
             clock,
         })
     }
@@ -196,18 +263,32 @@ impl<'clock, ClockT: Clock> Executor<'clock, ClockT> {
     /// The `kill_event_receiver` is used to kill the child process. If the attached sender is ever
     /// closed, the child will be immediately killed with a SIGTERM.
     ///
+    /// If the spec has `stream_output` set, the job's stdout and stderr are handed to
+    /// `output_sink` in chunks as they're read, instead of being returned in the [`JobCompleted`].
+    /// The next chunk of a stream isn't read until the future returned for the previous one
+    /// completes, so the sink can hold the job back by not completing it. All of the output has
+    /// been handed over by the time this function returns.
+    ///
     /// This function should be run in a `spawn_blocking` context. Ideally, this function would be
     /// async, but that doesn't work because we rely on [`bumpalo::Bump`] as a fast arena
     /// allocator, and it's not `Sync`.
-    pub fn run_job(
+    pub fn run_job<SinkFutureT: Future<Output = ()> + Send>(
         &self,
         spec: &JobSpec,
         inline_limit: InlineLimit,
+        output_sink: impl Fn(JobOutputStream, Vec<u8>) -> SinkFutureT + Send + Sync + 'static,
         kill_event_receiver: EventReceiver,
         fuse_spawn: impl FnOnce(OwnedFd),
         runtime: runtime::Handle,
     ) -> JobResult<JobCompleted, Error> {
-        self.run_job_inner(spec, inline_limit, kill_event_receiver, fuse_spawn, runtime)
+        self.run_job_inner(
+            spec,
+            inline_limit,
+            output_sink,
+            kill_event_receiver,
+            fuse_spawn,
+            runtime,
+        )
     }
 }
 
@@ -220,10 +301,85 @@ impl<'clock, ClockT: Clock> Executor<'clock, ClockT> {
  *  FIGLET: private
  */
 
+/// A cgroup created for a single job. It is removed when dropped, which must only happen after the
+/// job has exited, since the kernel won't remove a cgroup that still has processes in it.
+struct JobCgroup {
+    path: PathBuf,
+    procs: File,
+    memory_limit: Option<u64>,
+}
+
+/// The period, in microseconds, over which a job's CPU limit is applied.
+const CPU_PERIOD_US: u64 = 100_000;
+
+impl JobCgroup {
+    /// Create the cgroup, limiting it to `memory_limit` bytes of memory and `cpus` cores' worth
+    /// of CPU time if they're given.
+    fn new(path: PathBuf, memory_limit: Option<u64>, cpus: Option<u32>) -> Result<Self> {
+        std::fs::create_dir(&path)?;
+        let configure = || -> Result<File> {
+            if let Some(cpus) = cpus {
+                let quota = u64::from(cpus) * CPU_PERIOD_US;
+                std::fs::write(path.join("cpu.max"), format!("{quota} {CPU_PERIOD_US}"))
+                    .map_err(|err| anyhow!("setting job CPU limit: {err}"))?;
+            }
+            if let Some(memory_limit) = memory_limit {
+                let set_limit = || -> io::Result<()> {
+                    std::fs::write(path.join("memory.max"), memory_limit.to_string())?;
+                    // Kill the whole job, not just one of its processes, when it goes over.
+                    std::fs::write(path.join("memory.oom.group"), "1")?;
+                    // Don't let the job get around the limit by swapping. Kernels without swap
+                    // accounting don't have this file, but they don't need it either.
+                    match std::fs::write(path.join("memory.swap.max"), "0") {
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                        res => res,
+                    }
+                };
+                set_limit().map_err(|err| anyhow!("setting job memory limit: {err}"))?;
+            }
+            Ok(File::options()
+                .write(true)
+                .open(path.join("cgroup.procs"))?)
+        };
+        let procs = configure().inspect_err(|_| {
+            let _ = std::fs::remove_dir(&path);
+        })?;
+        Ok(Self {
+            path,
+            procs,
+            memory_limit,
+        })
+    }
+
+    /// Whether the job was killed for going over its memory limit. Once it has exited, this is the
+    /// case if the kernel's OOM killer killed any of the cgroup's processes.
+    fn exceeded_memory_limit(&self) -> Result<bool> {
+        if self.memory_limit.is_none() {
+            return Ok(false);
+        }
+        let events = std::fs::read_to_string(self.path.join("memory.events"))?;
+        Ok(events
+            .lines()
+            .filter_map(|line| line.strip_prefix("oom_kill "))
+            .any(|count| count != "0"))
+    }
+
+    /// The cgroup's "cgroup.procs" file. Writing "0" to it moves the writer into the cgroup.
+    fn procs_fd(&self) -> Fd {
+        Fd::from_raw(self.procs.as_raw_fd())
+    }
+}
+
+impl Drop for JobCgroup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir(&self.path);
+    }
+}
+
 async fn wait_for_child(
     child_pidfd: OwnedFd,
     mut kill_event_receiver: EventReceiver,
-) -> Result<JobStatus> {
+) -> Result<(JobStatus, JobResourceUsage)> {
     let async_fd = AsyncFd::with_interest(child_pidfd, Interest::READABLE)?;
     let mut kill_event_received = false;
     loop {
@@ -238,10 +394,18 @@ async fn wait_for_child(
             },
         }
     }
-    Ok(match linux::waitid(async_fd.into_inner().as_fd())? {
+    let (status, rusage) = linux::waitid(async_fd.into_inner().as_fd())?;
+    let status = match status {
         WaitStatus::Exited(code) => JobStatus::Exited(code.as_u8()),
         WaitStatus::Signaled(signo) => JobStatus::Signaled(signo.as_u8()),
-    })
+    };
+    let resource_usage = JobResourceUsage {
+        user_time: rusage.user_time(),
+        system_time: rusage.system_time(),
+        voluntary_context_switches: rusage.voluntary_context_switches(),
+        involuntary_context_switches: rusage.involuntary_context_switches(),
+    };
+    Ok((status, resource_usage))
 }
 
 /// A wrapper for a raw, non-blocking fd that allows it to be read from async code.
@@ -269,16 +433,43 @@ impl AsyncRead for AsyncFile {
     }
 }
 
-/// Read all of the contents of `stream` and return the appropriate [`JobOutputResult`].
-async fn output_reader(fd: OwnedFd, inline_limit: InlineLimit) -> Result<JobOutputResult> {
+impl AsyncFile {
+    fn new(fd: OwnedFd) -> Result<Self> {
+        // Make the read side of the pipe non-blocking so that we can use it with Tokio.
+        linux::fcntl_setfl(fd.as_fd(), OpenFlags::NONBLOCK).map_err(Error::from)?;
+        Ok(AsyncFile(
+            AsyncFd::new(File::from(fd::OwnedFd::from(fd))).map_err(Error::from)?,
+        ))
+    }
+}
+
+/// The most output read at once, and so the most handed over at once when streaming a job's output.
+const OUTPUT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Read all of the contents of `stream` and return the appropriate [`JobOutputResult`]. If
+/// `buffer` is given, room is taken from it for each chunk of output that's kept.
+async fn output_reader(
+    fd: OwnedFd,
+    inline_limit: InlineLimit,
+    buffer: Option<&OutputBuffer>,
+) -> Result<JobOutputResult> {
     let mut buf = Vec::<u8>::new();
-    // Make the read side of the pipe non-blocking so that we can use it with Tokio.
-    linux::fcntl_setfl(fd.as_fd(), OpenFlags::NONBLOCK).map_err(Error::from)?;
-    let stream = AsyncFile(AsyncFd::new(File::from(fd::OwnedFd::from(fd))).map_err(Error::from)?);
-    let mut take = stream.take(inline_limit.as_bytes());
-    take.read_to_end(&mut buf).await?;
+    let mut stream = AsyncFile::new(fd)?;
+    let mut chunk = vec![0; OUTPUT_CHUNK_SIZE];
+    let limit = inline_limit.as_bytes();
+    while (buf.len() as u64) < limit {
+        let want = (limit - buf.len() as u64).min(chunk.len() as u64) as usize;
+        let len = stream.read(&mut chunk[..want]).await?;
+        if len == 0 {
+            break;
+        }
+        if let Some(buffer) = buffer {
+            buffer.take(len as u64).await;
+        }
+        buf.extend_from_slice(&chunk[..len]);
+    }
     let buf = buf.into_boxed_slice();
-    let truncated = io::copy(&mut take.into_inner(), &mut io::sink()).await?;
+    let truncated = io::copy(&mut stream, &mut io::sink()).await?;
     match truncated {
         0 if buf.is_empty() => Ok(JobOutputResult::None),
         0 => Ok(JobOutputResult::Inline(buf)),
@@ -289,13 +480,134 @@ async fn output_reader(fd: OwnedFd, inline_limit: InlineLimit) -> Result<JobOutp
     }
 }
 
-/// Task main for the output reader: Read the output and then call the callback.
-async fn output_reader_task_main(
+/// Read all of the contents of `stream`, handing them to `sink` in chunks as they're read. Each
+/// chunk's future is awaited before the next chunk is read. None of the output is kept, so the
+/// result is always [`JobOutputResult::None`].
+async fn output_streamer<SinkFutureT: Future<Output = ()>>(
+    fd: OwnedFd,
+    stream: JobOutputStream,
+    sink: &impl Fn(JobOutputStream, Vec<u8>) -> SinkFutureT,
+) -> Result<JobOutputResult> {
+    let mut file = AsyncFile::new(fd)?;
+    let mut buf = vec![0; OUTPUT_CHUNK_SIZE];
+    loop {
+        let len = file.read(&mut buf).await?;
+        if len == 0 {
+            return Ok(JobOutputResult::None);
+        }
+        sink(stream, buf[..len].to_vec()).await;
+    }
+}
+
+/// The memory available for holding the captured output of all of an executor's jobs.
+///
+/// Jobs take room for their output a chunk at a time, as it's read, and give it all back once
+/// their result has been handed over. A job waiting for more room keeps what it has, so if every
+/// job holding room were waiting for more, none of them could ever finish. To prevent that, the
+/// oldest job is always given room, even past the limit. At most that one job's output is over
+/// the limit at any time.
+struct OutputBufferBudget {
+    limit: u64,
+    state: Mutex<OutputBufferBudgetState>,
+    freed: Notify,
+    next_job: AtomicU64,
+}
+
+#[derive(Default)]
+struct OutputBufferBudgetState {
+    used: u64,
+    /// The jobs with an [`OutputBuffer`], oldest first.
+    jobs: BTreeSet<u64>,
+}
+
+impl OutputBufferBudget {
+    fn new(limit: OutputBufferLimit) -> Self {
+        Self {
+            limit: limit.as_bytes(),
+            state: Default::default(),
+            freed: Notify::new(),
+            next_job: AtomicU64::new(0),
+        }
+    }
+
+    /// Start holding a job's output. The job is younger than all jobs already holding output.
+    fn buffer(self: &Arc<Self>) -> OutputBuffer {
+        let job = self.next_job.fetch_add(1, Ordering::Relaxed);
+        self.state.lock().unwrap().jobs.insert(job);
+        OutputBuffer {
+            budget: self.clone(),
+            job,
+            held: AtomicU64::new(0),
+        }
+    }
+
+    #[cfg(test)]
+    fn reserved(&self) -> u64 {
+        self.state.lock().unwrap().used
+    }
+}
+
+/// One job's share of an [`OutputBufferBudget`]. All of the room the job took is given back when
+/// this is dropped.
+struct OutputBuffer {
+    budget: Arc<OutputBufferBudget>,
+    job: u64,
+    held: AtomicU64,
+}
+
+impl OutputBuffer {
+    /// Wait for room for `bytes` more bytes of the job's output.
+    async fn take(&self, bytes: u64) {
+        let budget = &self.budget;
+        loop {
+            // We have to start listening before we look, so we don't miss room being freed in
+            // between.
+            let freed = budget.freed.notified();
+            {
+                let mut state = budget.state.lock().unwrap();
+                let oldest = state.jobs.first() == Some(&self.job);
+                if oldest || state.used + bytes <= budget.limit {
+                    state.used += bytes;
+                    self.held.fetch_add(bytes, Ordering::Relaxed);
+                    return;
+                }
+            }
+            freed.await;
+        }
+    }
+}
+
+impl Drop for OutputBuffer {
+    fn drop(&mut self) {
+        let budget = &self.budget;
+        let mut state = budget.state.lock().unwrap();
+        state.used -= *self.held.get_mut();
+        state.jobs.remove(&self.job).assert_is_true();
+        drop(state);
+        // Even if we held no room, another job may have just become the oldest.
+        budget.freed.notify_waiters();
+    }
+}
+
+/// Task main for the output reader: Read the output and then call the callback. If `sink` is
+/// given, the output is streamed to it instead of being kept. Otherwise, room for the kept output
+/// is taken from `buffer`, if it's given.
+async fn output_reader_task_main<
+    SinkFutureT: Future<Output = ()>,
+    SinkT: Fn(JobOutputStream, Vec<u8>) -> SinkFutureT,
+>(
     fd: OwnedFd,
     inline_limit: InlineLimit,
+    stream: JobOutputStream,
+    sink: Option<Arc<SinkT>>,
+    buffer: Option<Arc<OutputBuffer>>,
     sender: oneshot::Sender<Result<JobOutputResult>>,
 ) {
-    let _ = sender.send(output_reader(fd, inline_limit).await);
+    let result = match sink {
+        None => output_reader(fd, inline_limit, buffer.as_deref()).await,
+        Some(sink) => output_streamer(fd, stream, &*sink).await,
+    };
+    let _ = sender.send(result);
 }
 
 struct ScriptBuilder<'a> {
@@ -333,15 +645,17 @@ fn bump_c_str_from_bytes<'bump>(bump: &'bump Bump, bytes: &[u8]) -> Result<&'bum
 }
 
 impl<'clock, ClockT: Clock> Executor<'clock, ClockT> {
-    fn run_job_inner(
+    fn run_job_inner<SinkFutureT: Future<Output = ()> + Send>(
         &self,
         spec: &JobSpec,
         inline_limit: InlineLimit,
+        output_sink: impl Fn(JobOutputStream, Vec<u8>) -> SinkFutureT + Send + Sync + 'static,
         kill_event_receiver: EventReceiver,
         fuse_spawn: impl FnOnce(OwnedFd),
         runtime: runtime::Handle,
     ) -> JobResult<JobCompleted, Error> {
         let mut fuse_spawn = Some(fuse_spawn);
+        let output_sink = spec.stream_output.then(|| Arc::new(output_sink));
 
         fn syserr<E>(err: E) -> JobError<Error>
         where
@@ -376,6 +690,39 @@ impl<'clock, ClockT: Clock> Executor<'clock, ClockT> {
         let bump = Bump::new();
         let mut builder = ScriptBuilder::new(&bump);
 
+        // If we were given a parent cgroup, the child moves itself into a new cgroup inside of it
+        // before doing anything else, and only then creates its cgroup namespace, so that the new
+        // cgroup is the root of the job's cgroup tree. Without a parent cgroup, the child gets its
+        // cgroup namespace when it is cloned.
+        // The job's own memory request lowers the executor-wide limit, never raises it.
+        let memory_limit = self
+            .job_memory_limit
+            .map(|limit| limit.as_bytes())
+            .into_iter()
+            .chain((spec.memory > 0).then_some(spec.memory))
+            .min();
+        let cgroup = self
+            .cgroup_parent
+            .as_ref()
+            .map(|cgroup_parent| {
+                let index = self.next_cgroup.fetch_add(1, Ordering::Relaxed);
+                JobCgroup::new(
+                    cgroup_parent.join(format!("job-{}-{index}", process::id())),
+                    memory_limit,
+                    (spec.cpus > 0).then_some(spec.cpus),
+                )
+            })
+            .transpose()
+            .map_err(syserr)?;
+        if let Some(cgroup) = &cgroup {
+            builder.push(Syscall::Write(cgroup.procs_fd(), b"0"), &|err| {
+                syserr(anyhow!("moving into job cgroup: {err}"))
+            });
+            builder.push(Syscall::Unshare(CloneFlags::NEWCGROUP), &|err| {
+                syserr(anyhow!("creating cgroup namespace: {err}"))
+            });
+        }
+
         if spec.enable_loopback {
             // In order to have a loopback network interface, we need to create a netlink socket and
             // configure things with the kernel. This creates the socket.
@@ -661,20 +1008,38 @@ impl<'clock, ClockT: Clock> Executor<'clock, ClockT> {
             });
         }
 
-        // Finally, do the exec.
-        let program = bump_c_str(&bump, spec.program.as_str()).map_err(syserr)?;
+        // If requested, leave an init process behind as pid 1 to reap zombies and forward
+        // signals, and do the exec in its child.
+        if spec.enable_init_process {
+            builder.push(Syscall::ForkInit, &|err| {
+                syserr(anyhow!("forking for init process: {err}"))
+            });
+        }
+
+        // Finally, do the exec. The program, arguments, and environment come from the job's spec,
+        // so one with a NUL byte in it is a problem with the job, not with us.
+        let program = bump_c_str(&bump, spec.program.as_str())
+            .map_err(|err| JobError::Execution(anyhow!("program {:?}: {err}", spec.program)))?;
         let mut arguments =
             BumpVec::with_capacity_in(spec.arguments.len().checked_add(2).unwrap(), &bump);
         arguments.push(Some(&program.to_bytes_with_nul()[0]));
         for argument in &spec.arguments {
-            let argument_cstr = bump_c_str(&bump, argument.as_str()).map_err(syserr)?;
+            let argument_cstr = bump_c_str(&bump, argument.as_str())
+                .map_err(|err| JobError::Execution(anyhow!("argument {argument:?}: {err}")))?;
             arguments.push(Some(&argument_cstr.to_bytes_with_nul()[0]));
         }
         arguments.push(None);
         let mut environment =
             BumpVec::with_capacity_in(spec.environment.len().checked_add(1).unwrap(), &bump);
-        for var in &spec.environment {
-            let var_cstr = bump_c_str(&bump, var.as_str()).map_err(syserr)?;
+        let allowed = |var: &&String| {
+            self.environment_allowlist
+                .as_ref()
+                .map_or(true, |allowlist| allowlist.allows(var))
+        };
+        for var in spec.environment.iter().filter(allowed) {
+            let var_cstr = bump_c_str(&bump, var.as_str()).map_err(|err| {
+                JobError::Execution(anyhow!("environment variable {var:?}: {err}"))
+            })?;
             environment.push(Some(&var_cstr.to_bytes_with_nul()[0]));
         }
         environment.push(None);
@@ -688,16 +1053,23 @@ impl<'clock, ClockT: Clock> Executor<'clock, ClockT> {
         );
 
         // We're finally ready to actually clone the child.
+        //
+        // Normally, the child shares our address space until it execs, which saves copying our
+        // page tables. An init process doesn't exec, and would be left running on a stack we free
+        // when this function returns, so it has to get its own copy of our address space instead.
+        let mut clone_flags = CloneFlags::NEWIPC
+            | CloneFlags::NEWNET
+            | CloneFlags::NEWNS
+            | CloneFlags::NEWPID
+            | CloneFlags::NEWUSER;
+        if cgroup.is_none() {
+            clone_flags = clone_flags | CloneFlags::NEWCGROUP;
+        }
+        if !spec.enable_init_process {
+            clone_flags = clone_flags | CloneFlags::VM;
+        }
         let mut clone_args = CloneArgs::default()
-            .flags(
-                CloneFlags::NEWCGROUP
-                    | CloneFlags::NEWIPC
-                    | CloneFlags::NEWNET
-                    | CloneFlags::NEWNS
-                    | CloneFlags::NEWPID
-                    | CloneFlags::NEWUSER
-                    | CloneFlags::VM,
-            )
+            .flags(clone_flags)
             .exit_signal(Signal::CHLD);
         let mut args = maelstrom_worker_child::ChildArgs {
             write_sock: write_sock.as_fd(),
@@ -732,7 +1104,19 @@ impl<'clock, ClockT: Clock> Executor<'clock, ClockT> {
         // An alternative would be to panic and send a plain JobStatus back.
         let (status_sender, status_receiver) = oneshot::channel();
         runtime.spawn(async move {
-            let _ = status_sender.send(wait_for_child(child_pidfd, kill_event_receiver).await);
+            let result = wait_for_child(child_pidfd, kill_event_receiver)
+                .await
+                .and_then(|(status, resource_usage)| {
+                    let exceeded_memory_limit = match &cgroup {
+                        Some(cgroup) => cgroup.exceeded_memory_limit()?,
+                        None => false,
+                    };
+                    Ok((status, resource_usage, exceeded_memory_limit))
+                });
+            // Remove the job's cgroup before reporting that the job is done, so that anyone who
+            // hears about it can count on the cgroup being gone.
+            drop(cgroup);
+            let _ = status_sender.send(result);
         });
 
         // Read (in a blocking manner) from the exec result pipe. The child will write to the pipe if
@@ -781,20 +1165,48 @@ impl<'clock, ClockT: Clock> Executor<'clock, ClockT> {
 
         let start = self.clock.now();
 
-        // Spawn independent tasks to consume stdout and stderr. We want to do this in parallel so
-        // that we don't cause a deadlock on one while we're reading the other one.
+        // Spawn a task to consume stdout and stderr. We want to read them concurrently so that we
+        // don't cause a deadlock on one while we're reading the other one.
+        //
+        // If there is an output buffer budget, and we're capturing the output instead of
+        // streaming it, room for each chunk is taken from it as the chunk is read. Until there's
+        // room, the job will block once it fills its pipes. The room is given back once we've
+        // returned the output.
         //
-        // We put these in a JoinSet so that they will be canceled if we return early.
+        // We put the task in a JoinSet so that it will be canceled if we return early.
         //
-        // We have to drop our own copies of the write side of the pipe. If we didn't, these tasks
+        // We have to drop our own copies of the write side of the pipe. If we didn't, the task
         // would never complete.
+        let output_buffer = self
+            .output_buffer_budget
+            .as_ref()
+            .filter(|_| output_sink.is_none())
+            .map(|budget| Arc::new(budget.buffer()));
         let mut joinset = JoinSet::new();
         joinset.spawn_on(
-            output_reader_task_main(stdout_read_fd, inline_limit, stdout_sender),
-            &runtime,
-        );
-        joinset.spawn_on(
-            output_reader_task_main(stderr_read_fd, inline_limit, stderr_sender),
+            {
+                let output_buffer = output_buffer.clone();
+                async move {
+                    tokio::join!(
+                        output_reader_task_main(
+                            stdout_read_fd,
+                            inline_limit,
+                            JobOutputStream::Stdout,
+                            output_sink.clone(),
+                            output_buffer.clone(),
+                            stdout_sender,
+                        ),
+                        output_reader_task_main(
+                            stderr_read_fd,
+                            inline_limit,
+                            JobOutputStream::Stderr,
+                            output_sink,
+                            output_buffer,
+                            stderr_sender,
+                        ),
+                    );
+                }
+            },
             &runtime,
         );
         drop(stdout_write_fd);
@@ -811,14 +1223,24 @@ impl<'clock, ClockT: Clock> Executor<'clock, ClockT> {
                 })
                 .map_err(syserr)
         }
-        Ok(JobCompleted {
-            status: read_from_receiver(status_receiver)?,
+        let (status, resource_usage, exceeded_memory_limit) = read_from_receiver(status_receiver)?;
+        if exceeded_memory_limit {
+            let limit = memory_limit.unwrap();
+            return Err(JobError::Execution(anyhow!(
+                "job was killed for exceeding its memory limit of {limit} bytes"
+            )));
+        }
+        let completed = JobCompleted {
+            status,
             effects: JobEffects {
                 stdout: read_from_receiver(stdout_receiver)?,
                 stderr: read_from_receiver(stderr_receiver)?,
                 duration: start.elapsed(),
+                resource_usage,
             },
-        })
+        };
+        drop(output_buffer);
+        Ok(completed)
     }
 }
 
@@ -837,7 +1259,12 @@ mod tests {
     use bytesize::ByteSize;
     use maelstrom_base::{nonempty, ArtifactType, JobStatus};
     use maelstrom_test::{boxed_u8, digest, utf8_path_buf};
-    use maelstrom_util::{async_fs, log::test_logger, sync, time::TickingClock};
+    use maelstrom_util::{
+        async_fs,
+        log::test_logger,
+        sync,
+        time::{SystemMonotonicClock, TickingClock},
+    };
     use std::sync::Arc;
     use tempfile::TempDir;
     use tokio::sync::Mutex;
@@ -904,6 +1331,8 @@ mod tests {
         expected_stdout: JobOutputResult,
         expected_stderr: JobOutputResult,
         expected_duration: std::time::Duration,
+        environment_allowlist: Option<EnvironmentAllowlist>,
+        cgroup_parent: Option<PathBuf>,
         mount: TarMount,
         clock: TickingClock,
     }
@@ -917,6 +1346,8 @@ mod tests {
                 expected_stdout: JobOutputResult::None,
                 expected_stderr: JobOutputResult::None,
                 expected_duration: std::time::Duration::from_secs(1),
+                environment_allowlist: None,
+                cgroup_parent: None,
                 mount,
                 clock: TickingClock::new(),
             }
@@ -948,6 +1379,16 @@ mod tests {
             self
         }
 
+        fn environment_allowlist(mut self, environment_allowlist: &str) -> Self {
+            self.environment_allowlist = Some(environment_allowlist.parse().unwrap());
+            self
+        }
+
+        fn cgroup_parent(mut self, cgroup_parent: &Path) -> Self {
+            self.cgroup_parent = Some(cgroup_parent.to_owned());
+            self
+        }
+
         async fn run(self) {
             let JobCompleted {
                 status,
@@ -956,18 +1397,24 @@ mod tests {
                         stdout,
                         stderr,
                         duration,
+                        resource_usage: _,
                     },
             } = tokio::task::spawn_blocking(move || {
                 let (_kill_event_sender, kill_event_receiver) = sync::event();
                 Executor::new(
                     tempfile::tempdir().unwrap().into_path(),
                     tempfile::tempdir().unwrap().into_path(),
+                    self.environment_allowlist,
+                    self.cgroup_parent,
+                    None,
+                    None,
                     &self.clock,
                 )
                 .unwrap()
                 .run_job(
                     &self.spec,
                     self.inline_limit,
+                    |_, _| async {},
                     kill_event_receiver,
                     |fd| self.mount.spawn(fd),
                     runtime::Handle::current(),
@@ -1133,6 +1580,226 @@ mod tests {
             .await;
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn environment_allowlist() {
+        Test::from_spec(
+            bash_spec("echo -n ${FOO-unset} - ${SECRET-unset}")
+                .environment(["FOO=3", "SECRET=hunter2"]),
+        )
+        .await
+        .environment_allowlist("FOO,BAR")
+        .expected_stdout(JobOutputResult::Inline(boxed_u8!(b"3 - unset")))
+        .run()
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn environment_with_nul_byte() {
+        let spec = bash_spec("echo -n $FOO").environment(["FOO=a\0b"]);
+        assert_matches!(
+            run_spec(spec).await,
+            Err(JobError::Execution(err)) if err.to_string().starts_with("environment variable")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn argument_with_nul_byte() {
+        let spec = test_spec("/bin/echo").arguments(["a\0b"]);
+        assert_matches!(
+            run_spec(spec).await,
+            Err(JobError::Execution(err)) if err.to_string().starts_with("argument")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn empty_environment_allowlist() {
+        Test::from_spec(bash_spec("echo -n ${FOO-unset}").environment(["FOO=3"]))
+            .await
+            .environment_allowlist("")
+            .expected_stdout(JobOutputResult::Inline(boxed_u8!(b"unset")))
+            .run()
+            .await;
+    }
+
+    /// A new, empty cgroup inside of our own cgroup in the cgroup v2 hierarchy. It is removed when
+    /// dropped.
+    struct TestCgroup(PathBuf);
+
+    impl TestCgroup {
+        fn new() -> Self {
+            let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap();
+            let mount_point = mountinfo
+                .lines()
+                .find_map(|line| {
+                    let (mount, fs) = line.split_once(" - ").unwrap();
+                    fs.starts_with("cgroup2 ")
+                        .then(|| mount.split(' ').nth(4).unwrap().to_owned())
+                })
+                .unwrap();
+            let cgroups = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+            let our_cgroup = cgroups
+                .lines()
+                .find_map(|line| line.strip_prefix("0::"))
+                .unwrap();
+            let path = Path::new(&mount_point)
+                .join(our_cgroup.trim_start_matches('/'))
+                .join(format!("maelstrom-test-{}", process::id()));
+            std::fs::create_dir(&path).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestCgroup {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir(&self.0);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cgroup_parent() {
+        let cgroup_parent = TestCgroup::new();
+        Test::from_spec(python_spec(concat!(
+            "import time\n",
+            "end = time.time() + 0.2\n",
+            "while time.time() < end: pass\n",
+        )))
+        .await
+        .cgroup_parent(cgroup_parent.path())
+        .run()
+        .await;
+
+        // The job's CPU time was charged to a cgroup under the parent, which was removed once the
+        // job was done.
+        let cpu_stat = std::fs::read_to_string(cgroup_parent.path().join("cpu.stat")).unwrap();
+        let usage_usec: u64 = cpu_stat
+            .lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(usage_usec > 0, "{usage_usec}");
+        let children = Vec::from_iter(
+            std::fs::read_dir(cgroup_parent.path())
+                .unwrap()
+                .map(Result::unwrap)
+                .filter(|entry| entry.file_type().unwrap().is_dir()),
+        );
+        assert!(children.is_empty(), "{children:?}");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn job_memory_limit() {
+        let cgroup_parent = TestCgroup::new();
+        // We may not have the memory controller, like when we're in a container that hasn't been
+        // delegated it. There's nothing to test then.
+        if std::fs::write(
+            cgroup_parent.path().join("cgroup.subtree_control"),
+            "+memory",
+        )
+        .is_err()
+        {
+            return;
+        }
+        let executor = Executor::new(
+            tempfile::tempdir().unwrap().into_path(),
+            tempfile::tempdir().unwrap().into_path(),
+            None,
+            Some(cgroup_parent.path().to_owned()),
+            Some(ByteSize::mib(32).into()),
+            None,
+            &SystemMonotonicClock,
+        )
+        .unwrap();
+        let mount = TarMount::new().await;
+        let run = |script| {
+            let spec = JobSpec::from_spec(python_spec(script));
+            let (_kill_event_sender, kill_event_receiver) = sync::event();
+            executor.run_job(
+                &spec,
+                ByteSize::b(0).into(),
+                |_, _| async {},
+                kill_event_receiver,
+                |fd| mount.spawn(fd),
+                runtime::Handle::current(),
+            )
+        };
+
+        let result = tokio::task::block_in_place(|| run("data = b'x' * (1024 * 1024)"));
+        assert_matches!(
+            result,
+            Ok(JobCompleted {
+                status: JobStatus::Exited(0),
+                ..
+            })
+        );
+
+        let result = tokio::task::block_in_place(|| run("data = b'x' * (256 * 1024 * 1024)"));
+        assert_matches!(
+            result,
+            Err(JobError::Execution(err)) if err.to_string().contains("memory limit")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn job_memory_request() {
+        let cgroup_parent = TestCgroup::new();
+        if std::fs::write(
+            cgroup_parent.path().join("cgroup.subtree_control"),
+            "+memory",
+        )
+        .is_err()
+        {
+            return;
+        }
+        let executor = Executor::new(
+            tempfile::tempdir().unwrap().into_path(),
+            tempfile::tempdir().unwrap().into_path(),
+            None,
+            Some(cgroup_parent.path().to_owned()),
+            None,
+            None,
+            &SystemMonotonicClock,
+        )
+        .unwrap();
+        let mount = TarMount::new().await;
+        let mut spec = python_spec("data = b'x' * (256 * 1024 * 1024)");
+        spec.memory = ByteSize::mib(32).as_u64();
+        let spec = JobSpec::from_spec(spec);
+        let (_kill_event_sender, kill_event_receiver) = sync::event();
+        let result = tokio::task::block_in_place(|| {
+            executor.run_job(
+                &spec,
+                ByteSize::b(0).into(),
+                |_, _| async {},
+                kill_event_receiver,
+                |fd| mount.spawn(fd),
+                runtime::Handle::current(),
+            )
+        });
+        assert_matches!(
+            result,
+            Err(JobError::Execution(err)) if err.to_string().contains("memory limit of 33554432 bytes")
+        );
+    }
+
+    #[test]
+    fn job_memory_limit_requires_cgroup_parent() {
+        let result = Executor::new(
+            tempfile::tempdir().unwrap().into_path(),
+            tempfile::tempdir().unwrap().into_path(),
+            None,
+            None,
+            Some(ByteSize::mib(32).into()),
+            None,
+            &SystemMonotonicClock,
+        );
+        assert!(result.is_err());
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn stdin_empty() {
         Test::from_spec(test_spec("/bin/cat")).await.run().await;
@@ -1159,6 +1826,98 @@ mod tests {
         .await;
     }
 
+    fn proc_mount() -> JobMount {
+        JobMount {
+            fs_type: JobMountFsType::Proc,
+            mount_point: utf8_path_buf!("/proc"),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn init_process_reaps_orphans() {
+        // Fork some children that each fork a grandchild and then exit right away. The
+        // grandchildren are orphaned and reparented to pid 1. Without an init process, pid 1 would
+        // be the job, which never waits for them, so they'd be left as zombies.
+        Test::from_spec(
+            python_spec(concat!(
+                "import os, time;\n",
+                "print('pid:', os.getpid(), 'ppid:', os.getppid());\n",
+                "for _ in range(5):\n",
+                "    pid = os.fork()\n",
+                "    if pid == 0:\n",
+                "        if os.fork() != 0:\n",
+                "            os._exit(0)\n",
+                "        os._exit(0)\n",
+                "    os.waitpid(pid, 0)\n",
+                "def zombies():\n",
+                "    count = 0\n",
+                "    for entry in os.listdir('/proc'):\n",
+                "        if entry.isdigit():\n",
+                "            try:\n",
+                "                stat = open('/proc/%s/stat' % entry).read()\n",
+                "            except (IOError, OSError):\n",
+                "                continue\n",
+                "            count += stat.rsplit(')', 1)[1].split()[0] == 'Z'\n",
+                "    return count\n",
+                "for _ in range(500):\n",
+                "    if zombies() == 0:\n",
+                "        break\n",
+                "    time.sleep(0.01)\n",
+                "print('zombies:', zombies());\n",
+            ))
+            .mounts([proc_mount()])
+            .enable_init_process(true),
+        )
+        .await
+        .expected_stdout(JobOutputResult::Inline(boxed_u8!(
+            b"pid: 2 ppid: 1\nzombies: 0\n"
+        )))
+        .run()
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn init_process_forwards_signals() {
+        // Signals sent to pid 1 should end up at the job.
+        Test::from_spec(
+            python_spec(concat!(
+                "import os, signal, sys;\n",
+                "def handler(signum, frame):\n",
+                "    print('got', signum == signal.SIGUSR1)\n",
+                "    sys.exit(0)\n",
+                "signal.signal(signal.SIGUSR1, handler)\n",
+                "os.kill(1, signal.SIGUSR1)\n",
+                "while True:\n",
+                "    signal.pause()\n",
+            ))
+            .enable_init_process(true),
+        )
+        .await
+        .expected_stdout(JobOutputResult::Inline(boxed_u8!(b"got True\n")))
+        .run()
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn init_process_exits_with_job_exit_code() {
+        Test::from_spec(bash_spec("exit 3").enable_init_process(true))
+            .await
+            .expected_status(JobStatus::Exited(3))
+            .run()
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn init_process_exit_code_for_signaled_job() {
+        // The init process can't kill itself with a signal, so it reports the job being killed by
+        // signal 6 the way a shell would.
+        Test::from_spec(python_spec("import os; os.abort()").enable_init_process(true))
+            .await
+            .expected_status(JobStatus::Exited(128 + 6))
+            .run()
+            .await;
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn no_loopback() {
         Test::from_spec(
@@ -1564,30 +2323,311 @@ mod tests {
             .await;
     }
 
-    async fn assert_execution_error(spec: maelstrom_base::JobSpec) {
+    async fn run_spec(spec: maelstrom_base::JobSpec) -> JobResult<JobCompleted, Error> {
+        run_spec_with_output_sink(spec, |_, _| async {}).await
+    }
+
+    async fn run_spec_with_output_sink<SinkFutureT: Future<Output = ()> + Send>(
+        spec: maelstrom_base::JobSpec,
+        output_sink: impl Fn(JobOutputStream, Vec<u8>) -> SinkFutureT + Send + Sync + 'static,
+    ) -> JobResult<JobCompleted, Error> {
         let clock = TickingClock::new();
         let mount = TarMount::new().await;
         let spec = JobSpec::from_spec(spec);
         let (_kill_event_sender, kill_event_receiver) = sync::event();
-        assert_matches!(
+        tokio::task::spawn_blocking(move || {
+            Executor::new(
+                tempfile::tempdir().unwrap().into_path(),
+                tempfile::tempdir().unwrap().into_path(),
+                None,
+                None,
+                None,
+                None,
+                &clock,
+            )
+            .unwrap()
+            .run_job(
+                &spec,
+                ByteSize::b(0).into(),
+                output_sink,
+                kill_event_receiver,
+                |fd| mount.spawn(fd),
+                runtime::Handle::current(),
+            )
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn assert_execution_error(spec: maelstrom_base::JobSpec) {
+        assert_matches!(run_spec(spec).await, Err(JobError::Execution(_)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stream_output_teed_to_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let stdout_path = temp_dir.path().join("stdout");
+        let stdout_file = std::sync::Mutex::new(File::create(&stdout_path).unwrap());
+        let stderr = Arc::new(std::sync::Mutex::new(vec![]));
+        let stderr_clone = stderr.clone();
+        let JobCompleted { status, effects } = run_spec_with_output_sink(
+            python_spec(concat!(
+                "import sys;",
+                "sys.stdout.buffer.write(b'a' * 10000000);",
+                "sys.stdout.flush();",
+                "sys.stderr.write('b' * 10)",
+            ))
+            .stream_output(true),
+            move |stream, output| {
+                match stream {
+                    JobOutputStream::Stdout => {
+                        std::io::Write::write_all(&mut *stdout_file.lock().unwrap(), &output)
+                            .unwrap()
+                    }
+                    JobOutputStream::Stderr => stderr_clone.lock().unwrap().extend(output),
+                }
+                async {}
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, JobStatus::Exited(0));
+        assert_eq!(effects.stdout, JobOutputResult::None);
+        assert_eq!(effects.stderr, JobOutputResult::None);
+        assert_eq!(std::fs::metadata(&stdout_path).unwrap().len(), 10_000_000);
+        assert_eq!(*stderr.lock().unwrap(), b"b".repeat(10));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stream_output_held_back_by_sink() {
+        // The sink doesn't let any chunk through until the gate is opened, so only the first chunk
+        // should be read until then.
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let chunks = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let bytes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let job = tokio::spawn({
+            let gate = gate.clone();
+            let chunks = chunks.clone();
+            let bytes = bytes.clone();
+            run_spec_with_output_sink(
+                python_spec(concat!(
+                    "import sys;",
+                    "sys.stdout.buffer.write(b'a' * 10000000);",
+                    "sys.stdout.flush()",
+                ))
+                .stream_output(true),
+                move |_, output| {
+                    chunks.fetch_add(1, Ordering::SeqCst);
+                    bytes.fetch_add(output.len(), Ordering::SeqCst);
+                    let gate = gate.clone();
+                    async move { gate.acquire().await.unwrap().forget() }
+                },
+            )
+        });
+        // Starting the job can take a while, so wait for the first chunk before checking that no
+        // more come.
+        while chunks.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        assert_eq!(chunks.load(Ordering::SeqCst), 1);
+
+        gate.add_permits(tokio::sync::Semaphore::MAX_PERMITS / 2);
+        let JobCompleted { status, .. } = job.await.unwrap().unwrap();
+        assert_eq!(status, JobStatus::Exited(0));
+        assert_eq!(bytes.load(Ordering::SeqCst), 10_000_000);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stream_output_delivered_before_job_exits() {
+        // The job never exits on its own. The sink kills it when it sees the first output, so the
+        // job only completes if its output is streamed while it's still running.
+        let mount = TarMount::new().await;
+        let spec = JobSpec::from_spec(
+            python_spec(concat!(
+                "import sys, time;",
+                "sys.stdout.write('a');",
+                "sys.stdout.flush();",
+                "time.sleep(1000)",
+            ))
+            .stream_output(true),
+        );
+        let (kill_event_sender, kill_event_receiver) = sync::event();
+        let kill_event_sender = std::sync::Mutex::new(Some(kill_event_sender));
+        let stdout = Arc::new(std::sync::Mutex::new(vec![]));
+        let stdout_clone = stdout.clone();
+        let JobCompleted { status, .. } = tokio::task::block_in_place(|| {
+            Executor::new(
+                tempfile::tempdir().unwrap().into_path(),
+                tempfile::tempdir().unwrap().into_path(),
+                None,
+                None,
+                None,
+                None,
+                &SystemMonotonicClock,
+            )
+            .unwrap()
+            .run_job(
+                &spec,
+                ByteSize::b(0).into(),
+                move |stream, output| {
+                    assert_eq!(stream, JobOutputStream::Stdout);
+                    stdout_clone.lock().unwrap().extend(output);
+                    kill_event_sender.lock().unwrap().take();
+                    async {}
+                },
+                kill_event_receiver,
+                |fd| mount.spawn(fd),
+                runtime::Handle::current(),
+            )
+        })
+        .unwrap();
+        assert_eq!(status, JobStatus::Signaled(9));
+        assert_eq!(*stdout.lock().unwrap(), b"a");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn output_buffer_limit_bounds_concurrent_output() {
+        let executor = Arc::new(
+            Executor::new(
+                tempfile::tempdir().unwrap().into_path(),
+                tempfile::tempdir().unwrap().into_path(),
+                None,
+                None,
+                None,
+                Some(ByteSize::b(40000).into()),
+                &SystemMonotonicClock,
+            )
+            .unwrap(),
+        );
+        let mount = Arc::new(TarMount::new().await);
+        let spec = Arc::new(JobSpec::from_spec(python_spec(concat!(
+            "import sys;",
+            "sys.stdout.write('a' * 100000);",
+            "sys.stdout.flush();",
+            "sys.stderr.write('b' * 100000)",
+        ))));
+
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let monitor = tokio::task::spawn({
+            let executor = executor.clone();
+            let done = done.clone();
+            async move {
+                let mut peak = 0;
+                while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                    let budget = executor.output_buffer_budget.as_ref().unwrap();
+                    peak = peak.max(budget.reserved());
+                    tokio::task::yield_now().await;
+                }
+                peak
+            }
+        });
+
+        let jobs = Vec::from_iter((0..8).map(|_| {
+            let executor = executor.clone();
+            let mount = mount.clone();
+            let spec = spec.clone();
             tokio::task::spawn_blocking(move || {
-                Executor::new(
-                    tempfile::tempdir().unwrap().into_path(),
-                    tempfile::tempdir().unwrap().into_path(),
-                    &clock,
-                )
-                .unwrap()
-                .run_job(
+                let (_kill_event_sender, kill_event_receiver) = sync::event();
+                executor.run_job(
                     &spec,
-                    ByteSize::b(0).into(),
+                    ByteSize::b(10000).into(),
+                    |_, _| async {},
                     kill_event_receiver,
                     |fd| mount.spawn(fd),
                     runtime::Handle::current(),
                 )
             })
-            .await
-            .unwrap(),
-            Err(JobError::Execution(_))
+        }));
+        for job in jobs {
+            let JobCompleted { status, effects } = job.await.unwrap().unwrap();
+            assert_eq!(status, JobStatus::Exited(0));
+            assert_eq!(
+                effects.stdout,
+                JobOutputResult::Truncated {
+                    first: b"a".repeat(10000).into_boxed_slice(),
+                    truncated: 90000,
+                }
+            );
+            assert_eq!(
+                effects.stderr,
+                JobOutputResult::Truncated {
+                    first: b"b".repeat(10000).into_boxed_slice(),
+                    truncated: 90000,
+                }
+            );
+        }
+        done.store(true, std::sync::atomic::Ordering::SeqCst);
+
+        // Only the oldest job can go over the limit, and by no more than its own output.
+        let peak = monitor.await.unwrap();
+        assert!(peak > 0);
+        assert!(peak <= 40000 + 20000, "{peak}");
+        assert_eq!(
+            executor.output_buffer_budget.as_ref().unwrap().reserved(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn output_buffer_room_taken_as_needed() {
+        use futures::FutureExt as _;
+
+        let budget = Arc::new(OutputBufferBudget::new(ByteSize::b(100).into()));
+        let oldest = budget.buffer();
+        let younger = budget.buffer();
+
+        // Room is taken as output is read, not all up front.
+        oldest.take(60).now_or_never().unwrap();
+        younger.take(30).now_or_never().unwrap();
+        assert_eq!(budget.reserved(), 90);
+
+        // A younger job waits for room, but the oldest job never does.
+        let mut waiting = Box::pin(younger.take(30));
+        assert!((&mut waiting).now_or_never().is_none());
+        oldest.take(50).now_or_never().unwrap();
+        assert_eq!(budget.reserved(), 140);
+
+        // Once the oldest job is done, its room is given back.
+        drop(oldest);
+        waiting.await;
+        assert_eq!(budget.reserved(), 60);
+        drop(younger);
+        assert_eq!(budget.reserved(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resource_usage_of_compute_bound_job() {
+        let JobCompleted { status, effects } = run_spec(python_spec(concat!(
+            "import time\n",
+            "end = time.time() + 0.2\n",
+            "while time.time() < end: pass\n",
+        )))
+        .await
+        .unwrap();
+        assert_eq!(status, JobStatus::Exited(0));
+        let usage = effects.resource_usage;
+        assert!(usage.user_time > std::time::Duration::ZERO, "{usage:?}");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resource_usage_includes_reaped_children() {
+        // The job waits for its child, so the child's CPU time should be included.
+        let JobCompleted { effects, .. } = run_spec(python_spec(concat!(
+            "import os, time;\n",
+            "pid = os.fork()\n",
+            "if pid == 0:\n",
+            "    end = time.time() + 0.2\n",
+            "    while time.time() < end: pass\n",
+            "    os._exit(0)\n",
+            "os.waitpid(pid, 0)\n",
+        )))
+        .await
+        .unwrap();
+        let usage = effects.resource_usage;
+        assert!(
+            usage.user_time + usage.system_time >= std::time::Duration::from_millis(100),
+            "{usage:?}"
         );
     }
 
@@ -1600,4 +2640,12 @@ mod tests {
     async fn bad_working_directory_is_an_execution_error() {
         assert_execution_error(test_spec("/bin/cat").working_directory("/dev/null")).await;
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn execution_error_with_init_process() {
+        assert_execution_error(
+            test_spec("a_program_that_does_not_exist").enable_init_process(true),
+        )
+        .await;
+    }
 }
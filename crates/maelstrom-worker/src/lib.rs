@@ -1,6 +1,7 @@
 //! Code for the worker binary.
 
 pub mod cache;
+pub mod capabilities;
 pub mod config;
 pub mod dispatcher;
 mod executor;
@@ -9,14 +10,14 @@ mod layer_fs;
 
 use anyhow::{Context as _, Result};
 use cache::{Cache, StdFs};
-use config::Config;
+use config::{Config, EnvironmentAllowlist, JobMemoryLimit, OutputBufferLimit};
 use dispatcher::{Deps, Dispatcher, Message};
 use executor::Executor;
 use lru::LruCache;
 use maelstrom_base::{
     manifest::ManifestEntryData,
     proto::{Hello, WorkerToBroker},
-    ArtifactType, JobError, JobId, JobSpec, Sha256Digest,
+    ArtifactType, JobError, JobId, JobSpec, Sha256Digest, JOB_OUTPUT_WINDOW,
 };
 use maelstrom_linux::{
     self as linux, CloneArgs, CloneFlags, PollEvents, PollFd, Signal, WaitStatus,
@@ -43,7 +44,10 @@ use tokio::{
     io::BufReader,
     net::TcpStream,
     signal::unix::{self, SignalKind},
-    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Semaphore,
+    },
     task::{self, JoinHandle, JoinSet},
     time,
 };
@@ -165,6 +169,10 @@ impl DispatcherAdapter {
         mount_dir: PathBuf,
         tmpfs_dir: PathBuf,
         blob_cache_dir: PathBuf,
+        environment_allowlist: Option<EnvironmentAllowlist>,
+        cgroup_parent: Option<PathBuf>,
+        job_memory_limit: Option<JobMemoryLimit>,
+        output_buffer_limit: Option<OutputBufferLimit>,
     ) -> Result<Self> {
         let fs = Fs::new();
         fs.create_dir_all(&mount_dir)?;
@@ -174,6 +182,10 @@ impl DispatcherAdapter {
             executor: Arc::new(Executor::new(
                 mount_dir,
                 tmpfs_dir.clone(),
+                environment_allowlist,
+                cgroup_parent,
+                job_memory_limit,
+                output_buffer_limit,
                 &SystemMonotonicClock,
             )?),
             blob_cache_dir,
@@ -196,6 +208,7 @@ impl DispatcherAdapter {
         spec: JobSpec,
         layer_fs_path: PathBuf,
         kill_event_receiver: EventReceiver,
+        output_window: Arc<Semaphore>,
     ) -> Result<()> {
         let log = self
             .log
@@ -217,6 +230,24 @@ impl DispatcherAdapter {
         let spec = executor::JobSpec::from_spec(spec);
         let inline_limit = self.inline_limit;
         let dispatcher_sender = self.dispatcher_sender.clone();
+        let output_sender = self.dispatcher_sender.clone();
+        let output_sink = move |stream, output: Vec<u8>| {
+            let output_window = output_window.clone();
+            let output_sender = output_sender.clone();
+            async move {
+                // The window is closed once the job handle is dropped. After that, the job has
+                // been killed, so there's little left to read, and nothing to hold back.
+                if let Ok(permit) = output_window
+                    .acquire_many(output.len().try_into().unwrap())
+                    .await
+                {
+                    permit.forget();
+                }
+                output_sender
+                    .send(Message::JobOutput(jid, stream, output))
+                    .ok();
+            }
+        };
         let runtime = tokio::runtime::Handle::current();
         task::spawn_blocking(move || {
             dispatcher_sender
@@ -226,6 +257,7 @@ impl DispatcherAdapter {
                         .run_job(
                             &spec,
                             inline_limit,
+                            output_sink,
                             kill_event_receiver,
                             fuse_spawn,
                             runtime,
@@ -238,6 +270,19 @@ impl DispatcherAdapter {
     }
 }
 
+/// Kills the job when dropped. It also holds the window of the job's streamed output that the
+/// client hasn't acknowledged yet. See [`JOB_OUTPUT_WINDOW`].
+pub struct JobHandle {
+    _kill_event_sender: EventSender,
+    output_window: Arc<Semaphore>,
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        self.output_window.close();
+    }
+}
+
 pub struct TimerHandle(JoinHandle<()>);
 
 impl Drop for TimerHandle {
@@ -247,17 +292,34 @@ impl Drop for TimerHandle {
 }
 
 impl Deps for DispatcherAdapter {
-    type JobHandle = EventSender;
+    type JobHandle = JobHandle;
 
     fn start_job(&mut self, jid: JobId, spec: JobSpec, layer_fs_path: PathBuf) -> Self::JobHandle {
         let (kill_event_sender, kill_event_receiver) = sync::event();
-        if let Err(e) = self.start_job_inner(jid, spec, layer_fs_path, kill_event_receiver) {
+        let output_window = Arc::new(Semaphore::new(JOB_OUTPUT_WINDOW as usize));
+        if let Err(e) = self.start_job_inner(
+            jid,
+            spec,
+            layer_fs_path,
+            kill_event_receiver,
+            output_window.clone(),
+        ) {
             let _ = self.dispatcher_sender.send(Message::JobCompleted(
                 jid,
                 Err(JobError::System(e.to_string())),
             ));
         }
-        kill_event_sender
+        JobHandle {
+            _kill_event_sender: kill_event_sender,
+            output_window,
+        }
+    }
+
+    fn job_output_acked(&mut self, handle: &Self::JobHandle, bytes: u32) {
+        // Never let the window grow past its original size, even if we're sent bogus acks.
+        let window = &handle.output_window;
+        let room = (JOB_OUTPUT_WINDOW as usize).saturating_sub(window.available_permits());
+        window.add_permits((bytes as usize).min(room));
     }
 
     type TimerHandle = TimerHandle;
@@ -409,6 +471,10 @@ async fn dispatcher_main(
         mount_dir,
         tmpfs_dir,
         blob_cache_dir,
+        config.environment_allowlist,
+        config.cgroup_parent,
+        config.job_memory_limit,
+        config.output_buffer_limit,
     ) {
         Err(err) => {
             error!(log, "could not start executor"; "err" => ?err);
@@ -455,6 +521,7 @@ pub async fn main_inner(config: Config, log: Logger) -> Result<()> {
         &mut write_stream,
         Hello::Worker {
             slots: (*config.slots.inner()).into(),
+            capabilities: capabilities::detect_capabilities(Path::new("/")),
         },
     )
     .await